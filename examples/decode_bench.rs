@@ -0,0 +1,334 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! Rough decode throughput measurement.
+//!
+//! ```sh
+//! cargo run --release --features all --example decode_bench
+//! ```
+
+// 各コーデックの計測はその機能がある時だけ作られるので、一部の機能では
+// 使われない関数が残る
+#![cfg_attr(not(feature = "all"), allow(dead_code, unused_variables))]
+
+use compression::prelude::*;
+use std::cmp;
+use std::time::Instant;
+
+fn sample(len: usize) -> Vec<u8> {
+    let words: [&[u8]; 8] = [
+        b"compression ",
+        b"deflate ",
+        b"huffman ",
+        b"lzss ",
+        b"window ",
+        b"symbol ",
+        b"\n",
+        b"0123456789 ",
+    ];
+    let mut seed = 0x2545_F491_u32;
+    let mut ret = Vec::with_capacity(len + 16);
+    while ret.len() < len {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        if seed & 0x30 == 0 {
+            ret.push(seed as u8);
+        } else {
+            ret.extend_from_slice(words[(seed >> 8) as usize & 7]);
+        }
+    }
+    ret.truncate(len);
+    ret
+}
+
+/// Noise with geometrically skewed byte frequencies, so that a dynamic
+/// Huffman block gets codes of up to 15 bits and few matches.
+fn skewed(len: usize) -> Vec<u8> {
+    let mut seed = 0x9E37_79B9_u32;
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let class = cmp::min(seed.trailing_zeros(), 15);
+            (class << 4 | seed >> 28) as u8
+        })
+        .collect()
+}
+
+/// An 8-bit image of flat rectangles, so most references are byte runs
+/// or copies of the row above.
+fn bitmap(len: usize) -> Vec<u8> {
+    const WIDTH: usize = 1024;
+    let mut seed = 0x1234_5678_u32;
+    let mut ret = vec![0; WIDTH];
+    while ret.len() < len {
+        let row = ret.len() - WIDTH;
+        ret.extend_from_within(row..row + WIDTH);
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        if seed & 7 == 0 {
+            let start = ret.len() - WIDTH + (seed >> 8) as usize % WIDTH;
+            let end = cmp::min(start + (seed >> 20) as usize, ret.len());
+            ret[start..end].fill(seed as u8);
+        }
+    }
+    ret.truncate(len);
+    ret
+}
+
+/// Log lines that differ in a few fields.
+fn logs(len: usize) -> Vec<u8> {
+    let mut seed = 0xDEAD_BEEF_u32;
+    let mut ret = Vec::with_capacity(len + 128);
+    let mut t = 0;
+    while ret.len() < len {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        t += seed as usize % 1000;
+        let level = ["INFO", "INFO", "INFO", "WARN"][seed as usize >> 30];
+        let line = format!(
+            "2024-05-01T12:{:02}:{:02}.{:03}Z {} server.request: handled \
+             request id={} path=/api/v1/items status=200 bytes={}\n",
+            t / 60_000 % 60,
+            t / 1000 % 60,
+            t % 1000,
+            level,
+            seed >> 12,
+            seed % 4096
+        );
+        ret.extend_from_slice(line.as_bytes());
+    }
+    ret.truncate(len);
+    ret
+}
+
+fn bench<F>(name: &str, data: &[u8], encoded: &[u8], mut decode: F)
+where
+    F: FnMut(&[u8]) -> Vec<u8>,
+{
+    const ROUNDS: usize = 10;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert_eq!(decode(encoded).len(), data.len());
+    }
+    let elapsed = start.elapsed();
+    let mb = (data.len() * ROUNDS) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:>14}: {:>8.2} MiB/s ({} -> {} bytes)",
+        name,
+        mb / elapsed.as_secs_f64(),
+        encoded.len(),
+        data.len()
+    );
+}
+
+/// Decodes through a cloning byte iterator.
+fn by_iter<D: Decoder<Input = u8, Output = u8>>(
+    encoded: &[u8],
+    mut decoder: D,
+) -> Vec<u8>
+where
+    CompressionError: From<D::Error>,
+    D::Error: std::fmt::Debug,
+{
+    encoded
+        .iter()
+        .cloned()
+        .decode(&mut decoder)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+
+/// Decodes many small streams, each with a new decoder.
+fn bench_tiny<F>(name: &str, encoded: &[Vec<u8>], mut decode: F)
+where
+    F: FnMut(&[u8]) -> Vec<u8>,
+{
+    const ROUNDS: usize = 3;
+    let mut len = 0;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        len = encoded.iter().map(|e| decode(e).len()).sum::<usize>();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:>14}: {:>8.2} us/stream ({} streams -> {} bytes)",
+        name,
+        elapsed.as_secs_f64() * 1e6 / (encoded.len() * ROUNDS) as f64,
+        encoded.len(),
+        len
+    );
+}
+
+fn main() {
+    let data = sample(4 << 20);
+
+    #[cfg(feature = "deflate")]
+    {
+        let encoded = data
+            .iter()
+            .cloned()
+            .encode(&mut Inflater::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        bench("deflate", &data, &encoded, |e| by_iter(e, Deflater::new()));
+        bench("deflate/slice", &data, &encoded, |e| {
+            Deflater::new().decode_slice(e).unwrap().0
+        });
+    }
+
+    #[cfg(feature = "deflate")]
+    {
+        // 16 から 255 バイトの小さな入力。ほとんどが固定ハフマンのブロックになる
+        let mut pos = 0;
+        let encoded = (0..10_000)
+            .map(|i| {
+                let len = 16 + i * 37 % 240;
+                pos += len;
+                data[pos - len..pos]
+                    .iter()
+                    .cloned()
+                    .encode(&mut Inflater::new(), Action::Finish)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        bench_tiny("deflate/tiny", &encoded, |e| {
+            Deflater::new().decode_slice(e).unwrap().0
+        });
+    }
+
+    #[cfg(feature = "deflate")]
+    {
+        let data = skewed(4 << 20);
+        let encoded = data
+            .iter()
+            .cloned()
+            .encode(&mut Inflater::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        bench("deflate/deep", &data, &encoded, |e| {
+            Deflater::new().decode_slice(e).unwrap().0
+        });
+    }
+
+    #[cfg(feature = "zlib")]
+    {
+        let encoded = data
+            .iter()
+            .cloned()
+            .encode(&mut ZlibEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        bench("zlib", &data, &encoded, |e| by_iter(e, ZlibDecoder::new()));
+        bench("zlib/slice", &data, &encoded, |e| {
+            ZlibDecoder::new().decode_slice(e).unwrap().0
+        });
+    }
+
+    #[cfg(feature = "bzip2")]
+    {
+        let encoded = data
+            .iter()
+            .cloned()
+            .encode(&mut BZip2Encoder::new(9), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        bench("bzip2", &data, &encoded, |e| {
+            by_iter(e, BZip2Decoder::new())
+        });
+        bench("bzip2/slice", &data, &encoded, |e| {
+            BZip2Decoder::new().decode_slice(e).unwrap().0
+        });
+    }
+
+    #[cfg(feature = "lzhuf")]
+    {
+        let encoded = data
+            .iter()
+            .cloned()
+            .encode(&mut LzhufEncoder::new(&LzhufMethod::Lh7), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        bench("lzhuf", &data, &encoded, |e| {
+            by_iter(e, LzhufDecoder::new(&LzhufMethod::Lh7))
+        });
+        bench("lzhuf/slice", &data, &encoded, |e| {
+            LzhufDecoder::new(&LzhufMethod::Lh7)
+                .decode_slice(e)
+                .unwrap()
+                .0
+        });
+    }
+
+    #[cfg(feature = "lzhuf")]
+    {
+        // 小さなファイルを多く含む書庫。各ブロックで符号表を作り直す
+        let mut pos = 0;
+        let encoded = (0..10_000)
+            .map(|i| {
+                let len = 16 + i * 37 % 240;
+                pos += len;
+                data[pos - len..pos]
+                    .iter()
+                    .cloned()
+                    .encode(
+                        &mut LzhufEncoder::new(&LzhufMethod::Lh7),
+                        Action::Finish,
+                    )
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        bench_tiny("lzhuf/tiny", &encoded, |e| {
+            LzhufDecoder::new(&LzhufMethod::Lh7)
+                .decode_slice(e)
+                .unwrap()
+                .0
+        });
+    }
+
+    #[cfg(any(feature = "deflate", feature = "lzhuf"))]
+    for &(name, ref data) in
+        &[("bitmap", bitmap(4 << 20)), ("logs", logs(4 << 20))]
+    {
+        #[cfg(feature = "deflate")]
+        {
+            let encoded = data
+                .iter()
+                .cloned()
+                .encode(&mut Inflater::new(), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            bench(&format!("deflate/{}", name), data, &encoded, |e| {
+                Deflater::new().decode_slice(e).unwrap().0
+            });
+        }
+        #[cfg(feature = "lzhuf")]
+        {
+            let encoded = data
+                .iter()
+                .cloned()
+                .encode(
+                    &mut LzhufEncoder::new(&LzhufMethod::Lh7),
+                    Action::Finish,
+                )
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            bench(&format!("lzhuf/{}", name), data, &encoded, |e| {
+                LzhufDecoder::new(&LzhufMethod::Lh7)
+                    .decode_slice(e)
+                    .unwrap()
+                    .0
+            });
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! Rough encode throughput measurement.
+//!
+//! ```sh
+//! cargo run --release --features all --example encode_bench
+//! ```
+
+// 各コーデックの計測はその機能がある時だけ作られるので、一部の機能では
+// 使われない関数が残る
+#![cfg_attr(not(feature = "all"), allow(dead_code, unused_variables))]
+
+#[cfg(feature = "deflate")]
+use compression::lzss::encoder::ParseMode;
+use compression::prelude::*;
+use std::time::Instant;
+
+fn sample(len: usize) -> Vec<u8> {
+    let words: [&[u8]; 8] = [
+        b"compression ",
+        b"deflate ",
+        b"huffman ",
+        b"lzss ",
+        b"window ",
+        b"symbol ",
+        b"\n",
+        b"0123456789 ",
+    ];
+    let mut seed = 0x2545_F491_u32;
+    let mut ret = Vec::with_capacity(len + 16);
+    while ret.len() < len {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        if seed & 0x30 == 0 {
+            ret.push(seed as u8);
+        } else {
+            ret.extend_from_slice(words[(seed >> 8) as usize & 7]);
+        }
+    }
+    ret.truncate(len);
+    ret
+}
+
+/// Scanlines of a 24-bit image after PNG's Sub filter: flat areas come
+/// out as runs of 0, noisy ones as small values.
+fn scanlines(width: usize, height: usize) -> Vec<u8> {
+    let mut seed = 0x2545_F491_u32;
+    let mut ret = Vec::with_capacity((width * 3 + 1) * height);
+    for y in 0..height {
+        ret.push(1);
+        let mut prev = [0_u8; 3];
+        for x in 0..width {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let pixel = if (x / 128 + y / 96) % 2 == 0 {
+                [200, 40, 90]
+            } else {
+                [
+                    (x / 4 + y / 3) as u8 ^ (seed & 3) as u8,
+                    (y / 2) as u8 ^ (seed >> 8 & 3) as u8,
+                    (seed >> 16 & 7) as u8,
+                ]
+            };
+            for (p, &c) in prev.iter_mut().zip(&pixel) {
+                ret.push(c.wrapping_sub(*p));
+                *p = c;
+            }
+        }
+    }
+    ret
+}
+
+fn bench<E, F>(name: &str, data: &[u8], mut encoder: F)
+where
+    E: Encoder<In = u8, Out = u8>,
+    CompressionError: From<E::Error>,
+    E::Error: std::fmt::Debug,
+    F: FnMut() -> E,
+{
+    const ROUNDS: usize = 3;
+    let mut len = 0;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        len = data
+            .iter()
+            .cloned()
+            .encode(&mut encoder(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+            .len();
+    }
+    let elapsed = start.elapsed();
+    let mb = (data.len() * ROUNDS) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:>8}: {:>8.2} MiB/s ({} -> {} bytes)",
+        name,
+        mb / elapsed.as_secs_f64(),
+        data.len(),
+        len
+    );
+}
+
+/// Encodes `data` straight from the slice, with `compress`.
+fn bench_slice<F>(name: &str, data: &[u8], mut compress: F)
+where
+    F: FnMut(&[u8]) -> Result<Vec<u8>, CompressionError>,
+{
+    const ROUNDS: usize = 3;
+    let mut len = 0;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        len = compress(data).unwrap().len();
+    }
+    let elapsed = start.elapsed();
+    let mb = (data.len() * ROUNDS) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:>8}: {:>8.2} MiB/s ({} -> {} bytes)",
+        name,
+        mb / elapsed.as_secs_f64(),
+        data.len(),
+        len
+    );
+}
+
+/// Encodes many small payloads, each with a new encoder.
+fn bench_tiny<E, F>(name: &str, payloads: &[&[u8]], mut encoder: F)
+where
+    E: Encoder<In = u8, Out = u8>,
+    CompressionError: From<E::Error>,
+    E::Error: std::fmt::Debug,
+    F: FnMut() -> E,
+{
+    const ROUNDS: usize = 3;
+    let mut len = 0;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        len = 0;
+        for p in payloads {
+            len += p
+                .iter()
+                .cloned()
+                .encode(&mut encoder(), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .len();
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:>8}: {:>8.2} us/payload ({} payloads -> {} bytes)",
+        name,
+        elapsed.as_secs_f64() * 1e6 / (payloads.len() * ROUNDS) as f64,
+        payloads.len(),
+        len
+    );
+}
+
+/// Slices `data` into payloads of 16 to 255 bytes.
+fn tiny_payloads(data: &[u8], count: usize) -> Vec<&[u8]> {
+    let mut pos = 0;
+    (0..count)
+        .map(|i| {
+            let len = 16 + i * 37 % 240;
+            pos += len;
+            &data[pos - len..pos]
+        })
+        .collect()
+}
+
+fn main() {
+    let data = sample(4 << 20);
+    let payloads = tiny_payloads(&data, 10_000);
+    #[cfg(feature = "deflate")]
+    let image = scanlines(1024, 1024);
+
+    #[cfg(feature = "deflate")]
+    bench("deflate", &data, Inflater::new);
+
+    #[cfg(feature = "deflate")]
+    bench("deflate/fast", &data, || {
+        let mut encoder = Inflater::new();
+        encoder.set_parse_mode(ParseMode::Fast);
+        encoder
+    });
+
+    #[cfg(feature = "deflate")]
+    bench_slice("deflate/slice", &data, |data| {
+        Inflater::new().compress_to_vec(data, Action::Finish)
+    });
+
+    #[cfg(feature = "deflate")]
+    bench_slice("deflate/fast/slice", &data, |data| {
+        let mut encoder = Inflater::new();
+        encoder.set_parse_mode(ParseMode::Fast);
+        encoder.compress_to_vec(data, Action::Finish)
+    });
+
+    #[cfg(feature = "deflate")]
+    bench_slice("png", &image, |data| {
+        Inflater::new().compress_to_vec(data, Action::Finish)
+    });
+
+    #[cfg(feature = "deflate")]
+    bench_slice("png/rle", &image, |data| {
+        let mut encoder = Inflater::new();
+        encoder.set_parse_mode(ParseMode::Rle);
+        encoder.compress_to_vec(data, Action::Finish)
+    });
+
+    #[cfg(feature = "deflate")]
+    bench_tiny("deflate/tiny", &payloads, Inflater::new);
+
+    #[cfg(feature = "gzip")]
+    bench("gzip", &data, GZipEncoder::new);
+
+    #[cfg(feature = "bzip2")]
+    bench("bzip2", &data, || BZip2Encoder::new(9));
+
+    #[cfg(feature = "lzhuf")]
+    bench("lzhuf", &data, || LzhufEncoder::new(&LzhufMethod::Lh7));
+
+    #[cfg(feature = "lzhuf")]
+    bench_slice("lzhuf/slice", &data, |data| {
+        LzhufEncoder::new(&LzhufMethod::Lh7)
+            .compress_to_vec(data, Action::Finish)
+    });
+}
@@ -0,0 +1,115 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! Extracts an LHA archive into a directory. Members whose paths are
+//! absolute or have `..` components are refused, unless `--sanitize` is
+//! given to extract them under their sanitized paths instead.
+//!
+//! ```sh
+//! cargo run --features lzhuf --example lha_extract [ARCHIVE DIR [--sanitize]]
+//! ```
+//!
+//! Without arguments the example extracts an archive of its own, with a
+//! member that tries to climb out of the directory, into a temporary
+//! directory and checks the result.
+
+#[cfg(all(feature = "lzhuf", feature = "std"))]
+fn main() {
+    use compression::prelude::*;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Extracts `archive` under `dir` and returns the paths written and
+    /// the names of the members refused or of unsupported methods.
+    fn extract(
+        archive: &[u8],
+        dir: &Path,
+        sanitize: bool,
+    ) -> Result<(Vec<PathBuf>, Vec<String>), CompressionError> {
+        let mut written = Vec::new();
+        let mut refused = Vec::new();
+        // 項目ごとに窓を作り直さない
+        let mut decoder = LzhufDecoder::new(&LzhufMethod::Lh0);
+        for entry in LhaReader::new(archive) {
+            let entry = entry?;
+            let path = entry.path().to_string_lossy();
+            let sanitized = entry.sanitized_path();
+            if (!entry.path().is_safe() && !sanitize)
+                || sanitized.components().is_empty()
+            {
+                refused.push(path);
+                continue;
+            }
+            let target = dir.join(sanitized.to_path_buf());
+            if entry.is_dir() {
+                fs::create_dir_all(&target).unwrap();
+            } else {
+                let data = match entry.decompress_with(&mut decoder) {
+                    Ok(data) => data,
+                    Err(e) if e.kind() == ErrorKind::Unsupported => {
+                        refused.push(format!("{} ({})", path, e));
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).unwrap();
+                }
+                fs::write(&target, data).unwrap();
+            }
+            written.push(target);
+        }
+        Ok((written, refused))
+    }
+
+    let args = env::args().collect::<Vec<_>>();
+    let (archive, dir, sanitize) = match &args[..] {
+        [_, archive, dir] => (archive, dir, false),
+        [_, archive, dir, flag] if flag == "--sanitize" => (archive, dir, true),
+        [_] => {
+            let dir = env::temp_dir().join("lha_extract_example");
+            let _ = fs::remove_dir_all(&dir);
+            let mut writer = LhaWriter::new(Vec::new());
+            let mtime = LhaTimestamp::Unix(820_000_000);
+            writer
+                .add(b"docs/hello.txt", b"Hello, LHA!", mtime)
+                .unwrap();
+            writer.add(b"../../evil.txt", b"escaped", mtime).unwrap();
+            let archive = writer.finish().unwrap();
+
+            let (written, refused) = extract(&archive, &dir, false).unwrap();
+            assert_eq!(written, vec![dir.join("docs").join("hello.txt")]);
+            assert_eq!(refused, vec!["../../evil.txt".to_string()]);
+            assert_eq!(fs::read(&written[0]).unwrap(), b"Hello, LHA!");
+            println!("extracted {:?}, refused {:?}", written, refused);
+
+            let (written, _) = extract(&archive, &dir, true).unwrap();
+            assert_eq!(written[1], dir.join("evil.txt"));
+            println!("with --sanitize, extracted {:?}", written);
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        _ => {
+            eprintln!("usage: lha_extract [ARCHIVE DIR [--sanitize]]");
+            return;
+        }
+    };
+
+    let archive = fs::read(archive).unwrap();
+    let (written, refused) =
+        extract(&archive, Path::new(dir), sanitize).unwrap();
+    for path in &written {
+        println!("{}", path.display());
+    }
+    for name in &refused {
+        eprintln!("refused {}", name);
+    }
+}
+
+#[cfg(not(all(feature = "lzhuf", feature = "std")))]
+fn main() {}
@@ -0,0 +1,95 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! Raw LZSS codes with a 4 KiB window and 2 byte minimum matches, as a
+//! custom container format would use them.
+//!
+//! ```sh
+//! cargo run --example lzss_custom
+//! ```
+
+#[cfg(feature = "lzss")]
+fn main() {
+    use compression::lzss::decoder::LzssDecoder;
+    use compression::lzss::encoder::LzssEncoder;
+    use compression::lzss::LzssCode;
+    use compression::prelude::*;
+    use std::cmp::Ordering;
+
+    const WINDOW_SIZE: usize = 0x1000;
+    const MIN_MATCH: usize = 2;
+    const MAX_MATCH: usize = 18;
+
+    // 長い一致を、同じ長さなら近い一致を選ぶ
+    fn comparison(lhs: LzssCode, rhs: LzssCode) -> Ordering {
+        match (lhs, rhs) {
+            (
+                LzssCode::Reference {
+                    len: llen,
+                    pos: lpos,
+                },
+                LzssCode::Reference {
+                    len: rlen,
+                    pos: rpos,
+                },
+            ) => rlen.cmp(&llen).then(lpos.cmp(&rpos)),
+            (LzssCode::Symbol(_), LzssCode::Symbol(_)) => Ordering::Equal,
+            (_, LzssCode::Symbol(_)) => Ordering::Less,
+            (LzssCode::Symbol(_), _) => Ordering::Greater,
+        }
+    }
+
+    let data =
+        b"ab ab abc abcd abcde, to be or not to be, that is the question"
+            .iter()
+            .cycle()
+            .take(10_000)
+            .cloned()
+            .collect::<Vec<u8>>();
+
+    let codes = data
+        .iter()
+        .cloned()
+        .encode(
+            &mut LzssEncoder::with_params(
+                WINDOW_SIZE,
+                MIN_MATCH,
+                MAX_MATCH,
+                comparison,
+            ),
+            Action::Finish,
+        )
+        .collect::<Result<Vec<LzssCode>, _>>()
+        .unwrap();
+
+    let mut references = 0;
+    for code in &codes {
+        if let LzssCode::Reference { len, pos } = *code {
+            assert!((MIN_MATCH..=MAX_MATCH).contains(&len));
+            assert!(pos < WINDOW_SIZE);
+            references += 1;
+        }
+    }
+
+    let decoded = codes
+        .iter()
+        .cloned()
+        .decode(&mut LzssDecoder::new(WINDOW_SIZE))
+        .collect::<Result<Vec<u8>, _>>()
+        .unwrap();
+    assert_eq!(decoded, data);
+
+    println!(
+        "{} bytes -> {} codes ({} references)",
+        data.len(),
+        codes.len(),
+        references
+    );
+}
+
+#[cfg(not(feature = "lzss"))]
+fn main() {}
@@ -0,0 +1,138 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! A `MatchFinder` that only remembers the last position each 3 byte
+//! prefix was seen at, plugged into `LzssEncoder` in place of the
+//! built-in hash chains.
+//!
+//! ```sh
+//! cargo run --features lzss --example match_finder
+//! ```
+
+#[cfg(feature = "lzss")]
+fn main() {
+    use compression::lzss::decoder::LzssDecoder;
+    use compression::lzss::encoder::LzssEncoder;
+    use compression::lzss::finder::{MatchFinder, Window};
+    use compression::lzss::LzssCode;
+    use compression::prelude::*;
+    use std::cmp::Ordering;
+    use std::collections::{HashMap, VecDeque};
+
+    const WINDOW_SIZE: usize = 0x1000;
+    const MIN_MATCH: usize = 3;
+    const MAX_MATCH: usize = 18;
+
+    // 各位置について、同じ 3 バイトが最後に現れた位置だけを覚えておく
+    #[derive(Default)]
+    struct LastOccurrence {
+        last: HashMap<[u8; MIN_MATCH], usize>,
+        // 窓の中の位置と、その前に同じ 3 バイトが現れた位置
+        prev: VecDeque<(usize, Option<usize>)>,
+    }
+
+    impl MatchFinder for LastOccurrence {
+        fn insert(&mut self, window: &Window<'_>, pos: usize) {
+            // 探す位置より後ろも入っていることがあるので、ここで前の位置を引く
+            let mut key = [0; MIN_MATCH];
+            for (i, k) in key.iter_mut().enumerate() {
+                *k = window.get(pos.wrapping_add(i)).unwrap();
+            }
+            self.prev.push_back((pos, self.last.insert(key, pos)));
+            let held = window.end().wrapping_sub(window.start());
+            while let Some(&(p, _)) = self.prev.front() {
+                if window.end().wrapping_sub(p) <= held {
+                    break;
+                }
+                self.prev.pop_front();
+            }
+        }
+
+        fn find(
+            &mut self,
+            _window: &Window<'_>,
+            pos: usize,
+            max_len: usize,
+        ) -> Option<(usize, usize)> {
+            let first = self.prev.front()?.0;
+            let (_, prev) = *self.prev.get(pos.wrapping_sub(first))?;
+            // 長さは比べずに上限を答え、符号器に一致する分へ縮めさせる
+            Some((max_len, pos.wrapping_sub(prev?)))
+        }
+    }
+
+    // 長い一致を、同じ長さなら近い一致を選ぶ
+    fn comparison(lhs: LzssCode, rhs: LzssCode) -> Ordering {
+        match (lhs, rhs) {
+            (
+                LzssCode::Reference {
+                    len: llen,
+                    pos: lpos,
+                },
+                LzssCode::Reference {
+                    len: rlen,
+                    pos: rpos,
+                },
+            ) => rlen.cmp(&llen).then(lpos.cmp(&rpos)),
+            (LzssCode::Symbol(_), LzssCode::Symbol(_)) => Ordering::Equal,
+            (_, LzssCode::Symbol(_)) => Ordering::Less,
+            (LzssCode::Symbol(_), _) => Ordering::Greater,
+        }
+    }
+
+    let data = (0..500)
+        .map(|i| format!("line {}: to be or not to be\n", i % 37))
+        .flat_map(String::into_bytes)
+        .collect::<Vec<u8>>();
+
+    for (name, codes) in [
+        (
+            "last occurrence",
+            data.iter()
+                .cloned()
+                .encode(
+                    &mut LzssEncoder::with_match_finder(
+                        LastOccurrence::default(),
+                        WINDOW_SIZE,
+                        MIN_MATCH,
+                        MAX_MATCH,
+                    ),
+                    Action::Finish,
+                )
+                .collect::<Result<Vec<LzssCode>, _>>()
+                .unwrap(),
+        ),
+        (
+            "hash chains",
+            data.iter()
+                .cloned()
+                .encode(
+                    &mut LzssEncoder::with_params(
+                        WINDOW_SIZE,
+                        MIN_MATCH,
+                        MAX_MATCH,
+                        comparison,
+                    ),
+                    Action::Finish,
+                )
+                .collect::<Result<Vec<LzssCode>, _>>()
+                .unwrap(),
+        ),
+    ] {
+        let decoded = codes
+            .iter()
+            .cloned()
+            .decode(&mut LzssDecoder::new(WINDOW_SIZE))
+            .collect::<Result<Vec<u8>, _>>()
+            .unwrap();
+        assert_eq!(decoded, data);
+        println!("{}: {} bytes -> {} codes", name, data.len(), codes.len());
+    }
+}
+
+#[cfg(not(feature = "lzss"))]
+fn main() {}
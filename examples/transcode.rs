@@ -0,0 +1,174 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! Converts a member of an LHA archive to a gzip member at the `LzssCode`
+//! level, so the data is never held decoded in full: the codes of the
+//! lzhuf stream go straight into the gzip encoder, which only keeps its
+//! 32 KiB window. The references gzip cannot hold, farther than 32 KiB in
+//! -lh7-, of 2 bytes in -lzs- or into the window -lh1- to -lh3- and the
+//! LArc methods start with, are written as the bytes they stand for.
+//!
+//! ```sh
+//! cargo run --features lzhuf,gzip --example transcode [IN.lzh NAME OUT.gz]
+//! ```
+//!
+//! Without arguments the example converts data of its own with each method
+//! and checks the results.
+
+#[cfg(all(feature = "lzhuf", feature = "gzip"))]
+fn main() {
+    use compression::prelude::*;
+    use std::collections::VecDeque;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{BufWriter, Write};
+
+    // deflate の窓
+    const GZIP_WINDOW: usize = 0x8000;
+
+    /// Passes on the codes of `method` that gzip can hold, and replaces the
+    /// other references with their bytes.
+    fn gzip_codes<I: Iterator<Item = LzssCode>>(
+        codes: I,
+        method: LzhufMethod,
+    ) -> impl Iterator<Item = LzssCode> {
+        let mut window =
+            method.initial_window().into_iter().collect::<VecDeque<_>>();
+        let mut written = 0;
+        codes.flat_map(move |code| {
+            let bytes = match code {
+                LzssCode::Symbol(s) => vec![s],
+                LzssCode::Reference { len, pos } => (0..len)
+                    .map(|_| {
+                        let b = window[window.len() - pos - 1];
+                        window.push_back(b);
+                        b
+                    })
+                    .collect(),
+            };
+            if let LzssCode::Symbol(s) = code {
+                window.push_back(s);
+            }
+            while window.len() > 0x10000 {
+                window.pop_front();
+            }
+            let codes = match code {
+                LzssCode::Reference { len, pos }
+                    if len < 3 || pos >= GZIP_WINDOW || pos >= written =>
+                {
+                    bytes.iter().map(|&b| LzssCode::Symbol(b)).collect()
+                }
+                _ => vec![code],
+            };
+            written += bytes.len();
+            codes
+        })
+    }
+
+    fn transcode<W: Write>(
+        payload: &[u8],
+        method: LzhufMethod,
+        size: usize,
+        output: &mut W,
+    ) -> Result<usize, CompressionError> {
+        let mut decoder = LzhufCodeDecoder::with_original_size(&method, size);
+        let mut err = None;
+        let mut len = 0;
+        let codes = payload
+            .iter()
+            .cloned()
+            .decode(&mut decoder)
+            .scan(&mut err, |err, x| x.map_err(|e| **err = Some(e)).ok());
+        for b in gzip_codes(codes, method)
+            .encode(&mut GZipCodeEncoder::new(), Action::Finish)
+        {
+            output.write_all(&[b?]).unwrap();
+            len += 1;
+        }
+        // 入力の誤りで符号列が途中で終わった場合
+        err.map_or(Ok(len), Err)
+    }
+
+    fn gunzip(gzip: &[u8]) -> Vec<u8> {
+        gzip.iter()
+            .cloned()
+            .decode(&mut GZipDecoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    let args = env::args().collect::<Vec<_>>();
+    if let [_, input, name, output] = &args[..] {
+        let archive = fs::read(input).unwrap();
+        let entry = LhaReader::new(&archive)
+            .map(Result::unwrap)
+            .find(|e| e.header.name == name.as_bytes())
+            .expect("no such member");
+        let method = entry.header.method().expect("unsupported method");
+        let mut output = BufWriter::new(File::create(output).unwrap());
+        let len = transcode(
+            entry.compressed(),
+            method,
+            entry.header.original_size as usize,
+            &mut output,
+        )
+        .unwrap();
+        output.flush().unwrap();
+        println!("wrote {} bytes", len);
+        return;
+    }
+
+    // 64 KiB の窓の -lh7- だけが届く、40000 バイト前の繰り返しを含める
+    let mut data = (0..1000)
+        .map(|i| format!("{} bottles of beer on the wall\n", i % 99 + 1))
+        .flat_map(String::into_bytes)
+        .collect::<Vec<u8>>();
+    let mut seed = 0x2545_F491_u32;
+    data.extend((0..40_000).map(|_| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as u8
+    }));
+    data.extend_from_within(10_000..60_000);
+
+    let lh1 = include_bytes!("../data/sample3.lh1");
+    let sample3 = include_bytes!("../data/sample3.ref");
+    let mut gzip = Vec::new();
+    transcode(lh1, LzhufMethod::Lh1, sample3.len(), &mut gzip).unwrap();
+    assert_eq!(gunzip(&gzip), &sample3[..]);
+    println!(
+        "{} bytes: -lh1- {} bytes -> gzip {} bytes",
+        sample3.len(),
+        lh1.len(),
+        gzip.len()
+    );
+
+    for &method in &[
+        LzhufMethod::Lh4,
+        LzhufMethod::Lh5,
+        LzhufMethod::Lh6,
+        LzhufMethod::Lh7,
+    ] {
+        let lzh = LzhufEncoder::new(&method)
+            .compress_to_vec(&data, Action::Finish)
+            .unwrap();
+        let mut gzip = Vec::new();
+        transcode(&lzh, method, data.len(), &mut gzip).unwrap();
+        assert_eq!(gunzip(&gzip), data);
+        println!(
+            "{} bytes: {} {} bytes -> gzip {} bytes",
+            data.len(),
+            method,
+            lzh.len(),
+            gzip.len()
+        );
+    }
+}
+
+#[cfg(not(all(feature = "lzhuf", feature = "gzip")))]
+fn main() {}
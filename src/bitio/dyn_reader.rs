@@ -0,0 +1,344 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+use crate::bitio::direction::Direction;
+use crate::bitio::reader::BitRead;
+use crate::bitio::small_bit_vec::SmallBitVec;
+use crate::core::cmp;
+use crate::core::mem::size_of;
+use crate::core::ops::{BitOrAssign, Shl, Shr};
+use crate::error::CompressionError;
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use num_traits::cast::{cast, NumCast};
+use num_traits::sign::Unsigned;
+
+/// Object-safe form of `BitRead`.
+///
+/// Every `BitRead` implements it, and a boxed `DynBitRead` implements
+/// `BitRead` again, so a decoder can run over a reader chosen at runtime.
+/// Bits pass through as `u64` and the input as
+/// `&mut dyn Iterator<Item = u8>`.
+pub trait DynBitRead {
+    type Direction: Direction;
+
+    fn dyn_peek_bits(
+        &mut self,
+        len: usize,
+        iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<SmallBitVec<u64>, &'static str>;
+
+    fn dyn_skip_bits(
+        &mut self,
+        len: usize,
+        iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<usize, &'static str>;
+
+    fn dyn_read_bits(
+        &mut self,
+        len: usize,
+        iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<SmallBitVec<u64>, &'static str>;
+
+    fn dyn_read_bytes(
+        &mut self,
+        buf: &mut [u8],
+        iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<usize, &'static str>;
+
+    fn dyn_read_unary(
+        &mut self,
+        max: usize,
+        iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<usize, CompressionError>;
+
+    fn dyn_skip_to_next_byte(&mut self) -> usize;
+
+    fn dyn_bit_position(&self) -> usize;
+}
+
+impl<B: BitRead> DynBitRead for B {
+    type Direction = B::Direction;
+
+    fn dyn_peek_bits(
+        &mut self,
+        len: usize,
+        mut iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<SmallBitVec<u64>, &'static str> {
+        self.peek_bits(len, &mut iter)
+    }
+
+    fn dyn_skip_bits(
+        &mut self,
+        len: usize,
+        mut iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<usize, &'static str> {
+        self.skip_bits(len, &mut iter)
+    }
+
+    fn dyn_read_bits(
+        &mut self,
+        len: usize,
+        mut iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<SmallBitVec<u64>, &'static str> {
+        self.read_bits(len, &mut iter)
+    }
+
+    fn dyn_read_bytes(
+        &mut self,
+        buf: &mut [u8],
+        mut iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<usize, &'static str> {
+        self.read_bytes(buf, &mut iter)
+    }
+
+    fn dyn_read_unary(
+        &mut self,
+        max: usize,
+        mut iter: &mut dyn Iterator<Item = u8>,
+    ) -> Result<usize, CompressionError> {
+        self.read_unary(max, &mut iter)
+    }
+
+    fn dyn_skip_to_next_byte(&mut self) -> usize {
+        self.skip_to_next_byte()
+    }
+
+    fn dyn_bit_position(&self) -> usize {
+        self.bit_position()
+    }
+}
+
+impl<'a, D: Direction> BitRead for Box<dyn DynBitRead<Direction = D> + 'a> {
+    type Direction = D;
+
+    fn peek_bits<T, R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        iter: &mut R,
+    ) -> Result<SmallBitVec<T>, &'static str>
+    where
+        T: Unsigned
+            + BitOrAssign
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + From<u8>
+            + NumCast,
+    {
+        if len > size_of::<T>() << 3 {
+            return Err("len is too long");
+        }
+        let b = (**self).dyn_peek_bits(len, iter)?;
+        Ok(SmallBitVec::new(cast(*b.data_ref()).unwrap(), b.len()))
+    }
+
+    fn skip_bits<R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        (**self).dyn_skip_bits(len, iter)
+    }
+
+    fn read_bits<T, R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        iter: &mut R,
+    ) -> Result<SmallBitVec<T>, &'static str>
+    where
+        T: Unsigned
+            + BitOrAssign
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + From<u8>
+            + NumCast,
+    {
+        if len > size_of::<T>() << 3 {
+            return Err("len is too long");
+        }
+        let b = (**self).dyn_read_bits(len, iter)?;
+        Ok(SmallBitVec::new(cast(*b.data_ref()).unwrap(), b.len()))
+    }
+
+    fn read_bytes<R: Iterator<Item = u8>>(
+        &mut self,
+        buf: &mut [u8],
+        iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        (**self).dyn_read_bytes(buf, iter)
+    }
+
+    fn read_bits_into<T, R: Iterator<Item = u8>>(
+        &mut self,
+        width: usize,
+        out: &mut [T],
+        iter: &mut R,
+    ) -> Result<usize, &'static str>
+    where
+        T: NumCast,
+    {
+        if width > cmp::min(56, size_of::<T>() << 3) {
+            return Err("len is too long");
+        }
+        // 仮想呼び出しでは一括読み込みできないのでフィールドごとに読む
+        for (i, d) in out.iter_mut().enumerate() {
+            let b = (**self).dyn_peek_bits(width, iter)?;
+            if b.len() < width {
+                return Ok(i);
+            }
+            let _ = (**self).dyn_skip_bits(width, iter)?;
+            *d = cast(*b.data_ref()).unwrap();
+        }
+        Ok(out.len())
+    }
+
+    fn read_unary<R: Iterator<Item = u8>>(
+        &mut self,
+        max: usize,
+        iter: &mut R,
+    ) -> Result<usize, CompressionError> {
+        (**self).dyn_read_unary(max, iter)
+    }
+
+    fn skip_to_next_byte(&mut self) -> usize {
+        (**self).dyn_skip_to_next_byte()
+    }
+
+    fn bit_position(&self) -> usize {
+        (**self).dyn_bit_position()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitio::direction::left::Left;
+    use crate::bitio::reader::BitReader;
+    use crate::bitio::slice_reader::SliceBitReader;
+    use crate::core::iter;
+
+    const DATA: [u8; 5] = [0b1100_1010, 0b0101_0011, 0xFF, 0x00, 0x5A];
+
+    fn dyn_checker(
+        mut reader: Box<dyn DynBitRead<Direction = Left> + '_>,
+        mut iter: &mut dyn Iterator<Item = u8>,
+    ) {
+        assert_eq!(
+            reader.read_bits::<u8, _>(3, &mut iter),
+            Ok(SmallBitVec::new(0b110, 3))
+        );
+        assert_eq!(reader.read_unary(8, &mut iter), Ok(0));
+        let mut out = [0_u8; 3];
+        assert_eq!(reader.read_bits_into(4, &mut out, &mut iter), Ok(3));
+        assert_eq!(out, [0b1010, 0b0101, 0b0011]);
+        assert_eq!(reader.skip_to_next_byte(), 0);
+        let mut buf = [0; 4];
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter), Ok(3));
+        assert_eq!(buf, [0xFF, 0x00, 0x5A, 0]);
+        assert_eq!(reader.bit_position(), 40);
+        assert_eq!(
+            reader.read_bits::<u8, _>(1, &mut iter),
+            Ok(SmallBitVec::new(0, 0))
+        );
+    }
+
+    #[test]
+    fn dynbitreader_read() {
+        dyn_checker(
+            Box::new(BitReader::<Left>::new()),
+            &mut DATA.iter().cloned(),
+        );
+        dyn_checker(
+            Box::new(SliceBitReader::<Left>::new(&DATA)),
+            &mut iter::empty(),
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn dynbitreader_gzip() {
+        use crate::action::Action;
+        use crate::bitio::direction::right::Right;
+        use crate::gzip::decoder::{GZipDecoder, GZipDecoderBase};
+        use crate::gzip::encoder::GZipEncoder;
+        use crate::traits::decoder::{BitDecoder, DecodeExt};
+        use crate::traits::encoder::EncodeExt;
+        #[cfg(not(feature = "std"))]
+        use alloc::{vec, vec::Vec};
+
+        let input =
+            (0..0x2000).map(|i| (i * i % 253) as u8).collect::<Vec<_>>();
+        let encoded = input
+            .encode(&mut GZipEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let expected = encoded
+            .iter()
+            .cloned()
+            .decode(&mut GZipDecoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let readers: Vec<Box<dyn DynBitRead<Direction = Right>>> = vec![
+            Box::new(BitReader::<Right>::new()),
+            Box::new(SliceBitReader::<Right>::new(&encoded)),
+        ];
+        for (i, reader) in readers.into_iter().enumerate() {
+            let mut decoder =
+                BitDecoder::with_service(GZipDecoderBase::new(), reader);
+            let input = if i == 0 { &encoded[..] } else { &[] };
+            let decoded = input
+                .iter()
+                .cloned()
+                .decode(&mut decoder)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[cfg(feature = "lzhuf")]
+    #[test]
+    fn dynbitreader_lzhuf() {
+        use crate::action::Action;
+        use crate::lzhuf::decoder::{LzhufDecoder, LzhufDecoderBase};
+        use crate::lzhuf::encoder::LzhufEncoder;
+        use crate::lzhuf::LzhufMethod;
+        use crate::traits::decoder::{BitDecoder, DecodeExt};
+        use crate::traits::encoder::EncodeExt;
+        #[cfg(not(feature = "std"))]
+        use alloc::vec::Vec;
+
+        let input =
+            (0..0x2000).map(|i| (i * i % 253) as u8).collect::<Vec<_>>();
+        let method = LzhufMethod::Lh7;
+        let encoded = input
+            .encode(&mut LzhufEncoder::new(&method), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let expected = encoded
+            .iter()
+            .cloned()
+            .decode(&mut LzhufDecoder::new(&method))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let reader: Box<dyn DynBitRead<Direction = Left>> =
+            Box::new(BitReader::<Left>::new());
+        let mut decoder = BitDecoder::with_service(
+            LzhufDecoderBase::new(method, None),
+            reader,
+        );
+        let decoded = encoded
+            .iter()
+            .cloned()
+            .decode(&mut decoder)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(decoded, expected);
+    }
+}
@@ -6,6 +6,25 @@
 //! <http://mozilla.org/MPL/2.0/>.
 
 pub(crate) mod direction;
+#[cfg(feature = "alloc")]
+pub(crate) mod dyn_reader;
 pub(crate) mod reader;
+pub(crate) mod slice_reader;
 pub(crate) mod small_bit_vec;
 pub(crate) mod writer;
+
+pub use self::direction::left::Left;
+pub use self::direction::right::Right;
+pub use self::direction::Direction;
+#[cfg(feature = "alloc")]
+pub use self::dyn_reader::DynBitRead;
+#[cfg(feature = "alloc")]
+pub use self::reader::Mark;
+pub use self::reader::{BitRead, BitReader};
+pub use self::slice_reader::SliceBitReader;
+pub use self::small_bit_vec::SmallBitVec;
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
+pub use self::small_bit_vec::SmallBitVecReverse;
+#[cfg(feature = "alloc")]
+pub use self::writer::BitWriter;
+pub use self::writer::{BitWrite, SliceBitWriter};
@@ -7,214 +7,606 @@
 
 use crate::bitio::direction::Direction;
 use crate::bitio::small_bit_vec::SmallBitVec;
-use crate::cbuffer::CircularBuffer;
 use crate::core::cmp;
+#[cfg(feature = "alloc")]
+use crate::core::iter;
 use crate::core::iter::Iterator;
 use crate::core::marker::PhantomData;
 use crate::core::mem::size_of;
 use crate::core::ops::{BitOrAssign, Shl, Shr};
-#[cfg(not(feature = "std"))]
-use alloc::borrow::ToOwned;
-#[cfg(not(feature = "std"))]
-use alloc::string::String;
-#[cfg(not(feature = "std"))]
-#[allow(unused_imports)]
-use alloc::{vec, vec::Vec};
+use crate::error::CompressionError;
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::vec_deque::VecDeque;
+use num_traits::cast::{cast, NumCast};
 use num_traits::sign::Unsigned;
+#[cfg(feature = "std")]
+use std::collections::vec_deque::VecDeque;
 
-pub(crate) trait BitRead
+pub trait BitRead
 where
     Self::Direction: Direction,
 {
     type Direction;
 
-    fn peek_bits<T: Unsigned, R: Iterator<Item = u8>>(
+    /// Returns up to `len` bits without consuming them. Fewer bits are
+    /// returned when the input ends first.
+    fn peek_bits<T, R: Iterator<Item = u8>>(
         &mut self,
         len: usize,
         iter: &mut R,
-    ) -> Result<SmallBitVec<T>, String>
+    ) -> Result<SmallBitVec<T>, &'static str>
     where
-        T: BitOrAssign
+        T: Unsigned
+            + BitOrAssign
             + Shl<usize, Output = T>
             + Shr<usize, Output = T>
-            + From<u8>;
+            + From<u8>
+            + NumCast;
 
+    /// Discards up to `len` bits and returns the number actually skipped.
     fn skip_bits<R: Iterator<Item = u8>>(
         &mut self,
         len: usize,
         iter: &mut R,
-    ) -> Result<usize, String>;
-    fn read_bits<T: Unsigned, R: Iterator<Item = u8>>(
+    ) -> Result<usize, &'static str>;
+
+    fn read_bits<T, R: Iterator<Item = u8>>(
         &mut self,
         len: usize,
         iter: &mut R,
-    ) -> Result<SmallBitVec<T>, String>
+    ) -> Result<SmallBitVec<T>, &'static str>
     where
-        T: BitOrAssign
+        T: Unsigned
+            + BitOrAssign
             + Shl<usize, Output = T>
             + Shr<usize, Output = T>
-            + From<u8>;
+            + From<u8>
+            + NumCast,
+    {
+        let r = self.peek_bits::<T, R>(len, iter);
+        if let Ok(ref l) = r {
+            let _ = self.skip_bits::<_>(l.len(), iter)?;
+        }
+        r
+    }
+
+    /// Reads whole bytes into `buf` and returns the number of bytes read.
+    fn read_bytes<R: Iterator<Item = u8>>(
+        &mut self,
+        buf: &mut [u8],
+        iter: &mut R,
+    ) -> Result<usize, &'static str>;
+
+    /// Fills `out` with `width`-bit fields and returns the number filled.
+    /// Stops early, leaving the partial field unconsumed, when the input
+    /// ends.
+    fn read_bits_into<T, R: Iterator<Item = u8>>(
+        &mut self,
+        width: usize,
+        out: &mut [T],
+        iter: &mut R,
+    ) -> Result<usize, &'static str>
+    where
+        T: NumCast;
+
+    /// Counts the 1-bits in front of the next 0-bit and consumes both.
+    ///
+    /// Fails with `DataError` when the run is longer than `max`.
+    fn read_unary<R: Iterator<Item = u8>>(
+        &mut self,
+        max: usize,
+        iter: &mut R,
+    ) -> Result<usize, CompressionError>;
 
     fn skip_to_next_byte(&mut self) -> usize;
+
+    /// Returns the number of bits consumed so far.
+    fn bit_position(&self) -> usize;
+}
+
+impl<B: BitRead + ?Sized> BitRead for &mut B {
+    type Direction = B::Direction;
+
+    #[inline]
+    fn peek_bits<T, R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        iter: &mut R,
+    ) -> Result<SmallBitVec<T>, &'static str>
+    where
+        T: Unsigned
+            + BitOrAssign
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + From<u8>
+            + NumCast,
+    {
+        (**self).peek_bits(len, iter)
+    }
+
+    #[inline]
+    fn skip_bits<R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        (**self).skip_bits(len, iter)
+    }
+
+    #[inline]
+    fn read_bits<T, R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        iter: &mut R,
+    ) -> Result<SmallBitVec<T>, &'static str>
+    where
+        T: Unsigned
+            + BitOrAssign
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + From<u8>
+            + NumCast,
+    {
+        (**self).read_bits(len, iter)
+    }
+
+    #[inline]
+    fn read_bytes<R: Iterator<Item = u8>>(
+        &mut self,
+        buf: &mut [u8],
+        iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        (**self).read_bytes(buf, iter)
+    }
+
+    #[inline]
+    fn read_bits_into<T, R: Iterator<Item = u8>>(
+        &mut self,
+        width: usize,
+        out: &mut [T],
+        iter: &mut R,
+    ) -> Result<usize, &'static str>
+    where
+        T: NumCast,
+    {
+        (**self).read_bits_into(width, out, iter)
+    }
+
+    #[inline]
+    fn read_unary<R: Iterator<Item = u8>>(
+        &mut self,
+        max: usize,
+        iter: &mut R,
+    ) -> Result<usize, CompressionError> {
+        (**self).read_unary(max, iter)
+    }
+
+    #[inline]
+    fn skip_to_next_byte(&mut self) -> usize {
+        (**self).skip_to_next_byte()
+    }
+
+    #[inline]
+    fn bit_position(&self) -> usize {
+        (**self).bit_position()
+    }
 }
 
+const ACC_BITS: usize = size_of::<u64>() << 3;
+
+/// Bytes held in the accumulator and spill on top of the rewind window.
+#[cfg(feature = "alloc")]
+const LOOKAHEAD: usize = (ACC_BITS >> 3) + 2;
+
+/// A position returned by [`BitReader::checkpoint`].
+#[cfg(feature = "alloc")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mark(usize);
+
+/// Bit reader backed by a 64-bit accumulator.
+///
+/// The accumulator is refilled a byte at a time until it holds more than
+/// 56 bits, so reads of up to 56 bits are a mask and a shift. `spill`
+/// holds one extra byte for peeks that do not fit in the accumulator.
+/// `fetched` counts every byte taken from the input, so the logical
+/// position can be recovered by subtracting what is still buffered.
+///
+/// With a rewind window, the last fetched bytes are kept in `history` and
+/// `rewind` moves them to `replay`, which is read before the input. The
+/// rewind window needs the `alloc` feature.
 #[derive(Clone, Debug)]
-pub(crate) struct BitReader<D: Direction> {
-    buf: u8,
-    counter: usize,
-    cbuf: CircularBuffer<u8>,
-    pos: usize,
+pub struct BitReader<D: Direction> {
+    acc: u64,
+    bits: usize,
+    spill: Option<u8>,
+    fetched: usize,
+    #[cfg(feature = "alloc")]
+    window: usize,
+    #[cfg(feature = "alloc")]
+    history: VecDeque<u8>,
+    #[cfg(feature = "alloc")]
+    replay: VecDeque<u8>,
     phantom: PhantomData<fn() -> D>,
 }
 
 impl<D: Direction> BitRead for BitReader<D> {
     type Direction = D;
 
-    fn peek_bits<T: Unsigned, R: Iterator<Item = u8>>(
+    fn peek_bits<T, R: Iterator<Item = u8>>(
         &mut self,
         len: usize,
         iter: &mut R,
-    ) -> Result<SmallBitVec<T>, String>
+    ) -> Result<SmallBitVec<T>, &'static str>
     where
-        T: BitOrAssign
+        T: Unsigned
+            + BitOrAssign
             + Shl<usize, Output = T>
             + Shr<usize, Output = T>
-            + From<u8>,
+            + From<u8>
+            + NumCast,
     {
-        let firstlen = cmp::min(len, self.counter);
-        let needlen = (len - firstlen + 7) >> 3;
-
-        if needlen > 0 {
-            // バッファに読み込む
-            if needlen > self.pos {
-                if needlen + self.pos > self.buffer_cap() {
-                    return Err("len is too long".to_owned());
-                }
-                let rbuf = iter.take(needlen - self.pos).collect::<Vec<u8>>();
-                self.cbuf.append(&rbuf);
-                self.pos += rbuf.len();
-            }
-            let mut ret = Self::conv_u8_to_t(self.buf);
-            let mut count = self.counter;
-            for i in (0..cmp::min(self.pos, needlen))
-                .map(|x| Self::conv_u8_to_t(self.cbuf[self.pos - x - 1]))
-            {
-                ret |= D::backward(i, count);
-                count += size_of::<u8>() << 3;
-            }
-            let retlen = cmp::min(count, len);
-            Ok(if retlen != 0 {
-                SmallBitVec::new(
-                    D::convert(ret, size_of::<T>() << 3, retlen),
-                    retlen,
-                )
-            } else {
-                SmallBitVec::new(T::zero(), 0)
-            })
-        } else {
-            Ok(SmallBitVec::new(
-                D::convert(T::from(self.buf), size_of::<u8>() << 3, firstlen),
-                firstlen,
-            ))
+        if len > cmp::min(ACC_BITS, size_of::<T>() << 3) {
+            return Err("len is too long");
+        }
+        if self.bits < len {
+            self.refill(iter);
+        }
+        if self.bits >= len {
+            return Ok(SmallBitVec::new(
+                Self::conv_u64_to_t(self.peek_acc(len)),
+                len,
+            ));
+        }
+        if self.bits < ACC_BITS - 7 {
+            // 入力の終端
+            let len = self.bits;
+            return Ok(SmallBitVec::new(
+                Self::conv_u64_to_t(self.peek_acc(len)),
+                len,
+            ));
+        }
+
+        // アキュムレータに収まらない分は spill から読む
+        if self.spill.is_none() {
+            self.spill = self.fetch(iter);
         }
+        let head = self.bits;
+        let head_data = self.peek_acc(head);
+        let (data, len) = match self.spill {
+            Some(b) => {
+                let rest = len - head;
+                let b: u64 = b.into();
+                let data = if D::is_reverse() {
+                    head_data | ((b & ((1 << rest) - 1)) << head)
+                } else {
+                    (head_data << rest) | (b >> (8 - rest))
+                };
+                (data, len)
+            }
+            None => (head_data, head),
+        };
+        Ok(SmallBitVec::new(Self::conv_u64_to_t(data), len))
     }
 
     fn skip_bits<R: Iterator<Item = u8>>(
         &mut self,
         len: usize,
         iter: &mut R,
-    ) -> Result<usize, String> {
-        let firstlen = cmp::min(len, self.counter);
-        let midlen = (len - firstlen) >> 3;
-        let mut lastlen = (len - firstlen) & 0x07;
-
-        if lastlen > 0 || midlen > 0 {
-            // バッファに読み込む
-            let needlen = midlen + if lastlen > 0 { 1 } else { 0 };
-            if needlen > self.pos {
-                if needlen + self.pos > self.buffer_cap() {
-                    return Err("len is too long".to_owned());
+    ) -> Result<usize, &'static str> {
+        if len <= self.bits {
+            self.consume(len);
+            return Ok(len);
+        }
+
+        let mut skipped = self.bits;
+        self.acc = 0;
+        self.bits = 0;
+
+        // 残りはバイト単位で入力から直接読み飛ばす
+        let mut rest = len - skipped;
+        if rest >= 8 && self.spill.take().is_some() {
+            skipped += 8;
+            rest -= 8;
+        }
+        let count = if self.is_direct() {
+            let count = iter.take(rest >> 3).count();
+            self.fetched += count;
+            count
+        } else {
+            (0..rest >> 3)
+                .take_while(|_| self.fetch(iter).is_some())
+                .count()
+        };
+        skipped += count << 3;
+        if count < rest >> 3 {
+            return Ok(skipped);
+        }
+        rest &= 0x07;
+
+        if rest > 0 {
+            self.refill(iter);
+            let tail = cmp::min(rest, self.bits);
+            self.consume(tail);
+            skipped += tail;
+        }
+        Ok(skipped)
+    }
+
+    fn read_bytes<R: Iterator<Item = u8>>(
+        &mut self,
+        buf: &mut [u8],
+        iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        let mut len = 0;
+        if self.bits & 0x07 != 0 {
+            for d in buf.iter_mut() {
+                let b = self.read_bits::<u8, _>(8, iter)?;
+                if b.len() < 8 {
+                    break;
                 }
-                let rbuf = iter.take(needlen - self.pos).collect::<Vec<u8>>();
-                self.cbuf.append(&rbuf);
-                self.pos += rbuf.len();
+                *d = b.data();
+                len += 1;
             }
-            if midlen == self.pos {
-                lastlen = 0;
+            return Ok(len);
+        }
+
+        // バイト境界にあるのでビット操作は不要
+        while len < buf.len() && self.bits > 0 {
+            buf[len] = self.peek_acc(8) as u8;
+            self.consume(8);
+            len += 1;
+        }
+        if len < buf.len() {
+            if let Some(b) = self.spill.take() {
+                buf[len] = b;
+                len += 1;
             }
-            let readlen = cmp::min(self.pos, needlen);
-            self.pos -= readlen;
-            if lastlen > 0 {
-                self.buf = D::forward(self.cbuf[self.pos], lastlen);
-                self.counter = (size_of::<u8>() << 3) - lastlen;
-            } else {
-                self.buf = 0;
-                self.counter = 0;
+        }
+        for d in buf[len..].iter_mut() {
+            match self.fetch(iter) {
+                Some(s) => *d = s,
+                None => break,
             }
-            Ok(firstlen + cmp::min((midlen << 3) + lastlen, readlen << 3))
-        } else {
-            self.buf = D::forward(self.buf, firstlen);
-            self.counter -= firstlen;
-            Ok(firstlen)
+            len += 1;
         }
+        Ok(len)
     }
 
-    fn read_bits<T: Unsigned, R: Iterator<Item = u8>>(
+    fn read_bits_into<T, R: Iterator<Item = u8>>(
         &mut self,
-        len: usize,
+        width: usize,
+        out: &mut [T],
         iter: &mut R,
-    ) -> Result<SmallBitVec<T>, String>
+    ) -> Result<usize, &'static str>
     where
-        T: BitOrAssign
-            + Shl<usize, Output = T>
-            + Shr<usize, Output = T>
-            + From<u8>,
+        T: NumCast,
     {
-        let r = self.peek_bits::<T, R>(len, iter);
-        if let Ok(ref l) = r {
-            let _ = self.skip_bits::<_>(l.len(), iter)?;
+        // 1 回の refill で 56 ビット以上埋まるので、その範囲なら
+        // フィールドごとではなく数フィールドごとの refill で済む
+        if width > cmp::min(ACC_BITS - 8, size_of::<T>() << 3) {
+            return Err("len is too long");
+        }
+        for (i, d) in out.iter_mut().enumerate() {
+            if self.bits < width {
+                self.refill(iter);
+                if self.bits < width {
+                    return Ok(i);
+                }
+            }
+            *d = Self::conv_u64_to_t(self.peek_acc(width));
+            self.consume(width);
+        }
+        Ok(out.len())
+    }
+
+    fn read_unary<R: Iterator<Item = u8>>(
+        &mut self,
+        max: usize,
+        iter: &mut R,
+    ) -> Result<usize, CompressionError> {
+        let mut count = 0;
+        loop {
+            if self.bits == 0 {
+                self.refill(iter);
+                if self.bits == 0 {
+                    return Err(CompressionError::UnexpectedEof);
+                }
+            }
+            let ones = cmp::min(
+                if D::is_reverse() {
+                    self.acc.trailing_ones()
+                } else {
+                    self.acc.leading_ones()
+                } as usize,
+                self.bits,
+            );
+            if count + ones > max {
+                return Err(CompressionError::DataError);
+            }
+            if ones < self.bits {
+                self.consume(ones + 1);
+                return Ok(count + ones);
+            }
+            count += ones;
+            self.consume(ones);
         }
-        r
     }
 
     fn skip_to_next_byte(&mut self) -> usize {
-        let len = self.counter;
-        self.buf = 0;
-        self.counter = 0;
+        let len = self.bits & 0x07;
+        self.consume(len);
         len
     }
-}
 
-const DEFAULT_BUF_SIZE: usize = 8; // u64まで対応可能
+    /// Bytes that have been read ahead but not consumed are not counted.
+    #[inline]
+    fn bit_position(&self) -> usize {
+        let buffered = self.bits + if self.spill.is_some() { 8 } else { 0 };
+        (self.fetched << 3) - buffered
+    }
+}
 
 impl<D: Direction> BitReader<D> {
     #[inline]
-    pub(crate) fn new() -> Self {
-        Self::with_capacity(DEFAULT_BUF_SIZE)
+    pub fn new() -> Self {
+        Self {
+            acc: 0,
+            bits: 0,
+            spill: None,
+            fetched: 0,
+            #[cfg(feature = "alloc")]
+            window: 0,
+            #[cfg(feature = "alloc")]
+            history: VecDeque::new(),
+            #[cfg(feature = "alloc")]
+            replay: VecDeque::new(),
+            phantom: PhantomData,
+        }
     }
 
+    /// Creates a reader that can `rewind` up to `window` consumed bytes.
+    #[cfg(feature = "alloc")]
     #[inline]
-    pub(crate) fn with_capacity(cap: usize) -> Self {
+    pub fn with_rewind_window(window: usize) -> Self {
         Self {
-            buf: 0,
-            counter: 0,
-            cbuf: CircularBuffer::<u8>::new(cap),
-            pos: 0,
-            phantom: PhantomData,
+            window,
+            ..Self::new()
+        }
+    }
+
+    /// Returns a mark for the current position.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn checkpoint(&self) -> Mark {
+        Mark(self.bit_position())
+    }
+
+    /// Moves back to `mark` so that the bits after it are read again.
+    ///
+    /// Fails if `mark` lies ahead of the current position or further back
+    /// than the rewind window.
+    #[cfg(feature = "alloc")]
+    pub fn rewind(&mut self, mark: Mark) -> Result<(), &'static str> {
+        if mark.0 > self.bit_position() {
+            return Err("mark is ahead of the current position");
+        }
+        let start = mark.0 >> 3;
+        let back = self.fetched - start;
+        if back > self.history.len() {
+            return Err("mark is out of the rewind window");
         }
+        for _ in 0..back {
+            let b = self.history.pop_back().unwrap();
+            self.replay.push_front(b);
+        }
+        self.acc = 0;
+        self.bits = 0;
+        self.spill = None;
+        self.fetched = start;
+
+        let rest = mark.0 & 0x07;
+        if rest > 0 {
+            self.refill(&mut iter::empty());
+            self.consume(rest);
+        }
+        Ok(())
     }
 
+    /// Returns the number of input bytes consumed since construction. A
+    /// partially consumed byte counts as consumed.
     #[inline]
-    pub(crate) fn buffer_cap(&self) -> usize {
-        self.cbuf.cap() - self.pos
+    pub fn bytes_consumed(&self) -> usize {
+        (self.bit_position() + 7) >> 3
     }
 
+    /// Returns `true` when bytes are taken from the input untracked.
+    #[cfg(feature = "alloc")]
     #[inline]
-    fn conv_u8_to_t<T: Unsigned>(value: u8) -> T
-    where
-        T: Shl<usize, Output = T> + Shr<usize, Output = T> + From<u8>,
-    {
-        D::convert(T::from(value), size_of::<u8>() << 3, size_of::<T>() << 3)
+    fn is_direct(&self) -> bool {
+        self.window == 0 && self.replay.is_empty()
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    #[inline]
+    fn is_direct(&self) -> bool {
+        true
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    #[inline]
+    fn fetch<R: Iterator<Item = u8>>(&mut self, iter: &mut R) -> Option<u8> {
+        let ret = iter.next();
+        if ret.is_some() {
+            self.fetched += 1;
+        }
+        ret
+    }
+
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn fetch<R: Iterator<Item = u8>>(&mut self, iter: &mut R) -> Option<u8> {
+        let ret = self.replay.pop_front().or_else(|| iter.next());
+        if let Some(b) = ret {
+            self.fetched += 1;
+            if self.window > 0 {
+                if self.history.len() == self.window + LOOKAHEAD {
+                    let _ = self.history.pop_front();
+                }
+                self.history.push_back(b);
+            }
+        }
+        ret
+    }
+
+    #[inline]
+    fn refill<R: Iterator<Item = u8>>(&mut self, iter: &mut R) {
+        while self.bits <= ACC_BITS - 8 {
+            let b = match self.spill.take() {
+                Some(b) => b,
+                None => match self.fetch(iter) {
+                    Some(b) => b,
+                    None => break,
+                },
+            };
+            let b: u64 = b.into();
+            if D::is_reverse() {
+                self.acc |= b << self.bits;
+            } else {
+                self.acc |= b << (ACC_BITS - 8 - self.bits);
+            }
+            self.bits += 8;
+        }
+    }
+
+    /// Returns the next `len` bits of the accumulator, right aligned.
+    #[inline]
+    fn peek_acc(&self, len: usize) -> u64 {
+        if len == 0 {
+            0
+        } else if D::is_reverse() {
+            self.acc & (u64::MAX >> (ACC_BITS - len))
+        } else {
+            self.acc >> (ACC_BITS - len)
+        }
+    }
+
+    #[inline]
+    fn consume(&mut self, len: usize) {
+        debug_assert!(len <= self.bits);
+        self.acc = if len >= ACC_BITS {
+            0
+        } else if D::is_reverse() {
+            self.acc >> len
+        } else {
+            self.acc << len
+        };
+        self.bits -= len;
+    }
+
+    #[inline]
+    fn conv_u64_to_t<T: NumCast>(value: u64) -> T {
+        debug_assert!(value >> ((size_of::<T>() << 3) - 1) <= 1);
+        cast(value).unwrap()
     }
 }
 
@@ -228,11 +620,16 @@ impl<D: Direction> Default for BitReader<D> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "alloc")]
     use crate::action::Action;
     use crate::bitio::direction::left::Left;
     use crate::bitio::direction::right::Right;
+    #[cfg(feature = "alloc")]
     use crate::bitio::writer::{BitWriteExt, BitWriter};
+    #[cfg(all(feature = "alloc", not(feature = "std")))]
+    use alloc::{vec, vec::Vec};
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_read() {
         let cursor = vec![0b1100_1100];
@@ -258,6 +655,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_readmulti() {
         let cursor = vec![243, 221, 190, 200];
@@ -279,6 +677,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitreader_read() {
         let cursor = vec![0b1100_1100];
@@ -304,6 +703,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitreader_multi() {
         let cursor = vec![0xCF, 0x6F, 0x87, 0xEC];
@@ -325,6 +725,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_peek() {
         let mut writer = BitWriter::<Left>::new();
@@ -372,6 +773,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_peek_big() {
         let mut writer = BitWriter::<Left>::new();
@@ -410,6 +812,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_zeros() {
         let mut writer = BitWriter::<Left>::new();
@@ -456,6 +859,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_skip() {
         let mut writer = BitWriter::<Left>::new();
@@ -491,6 +895,7 @@ mod tests {
         assert_eq!(reader.skip_to_next_byte(), 2);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitreader_skip_big() {
         let mut writer = BitWriter::<Left>::new();
@@ -518,4 +923,496 @@ mod tests {
             Some(SmallBitVec::new(200, 8))
         );
     }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn rightbitreader_peek() {
+        let cursor = vec![0b1100_1100, 0b1010_0101];
+        let mut iter = cursor.into_iter();
+
+        let mut reader = BitReader::<Right>::new();
+
+        assert_eq!(
+            reader.peek_bits::<u32, _>(3, &mut iter).ok(),
+            Some(SmallBitVec::new(0b100, 3))
+        );
+        assert_eq!(
+            reader.peek_bits::<u32, _>(3, &mut iter).ok(),
+            Some(SmallBitVec::new(0b100, 3))
+        );
+        assert_eq!(reader.skip_bits::<_>(3, &mut iter).ok(), Some(3));
+        assert_eq!(
+            reader.peek_bits::<u32, _>(9, &mut iter).ok(),
+            Some(SmallBitVec::new(0b0_1011_1001, 9))
+        );
+        assert_eq!(
+            reader.read_bits::<u32, _>(9, &mut iter).ok(),
+            Some(SmallBitVec::new(0b0_1011_1001, 9))
+        );
+        assert_eq!(
+            reader.read_bits::<u32, _>(4, &mut iter).ok(),
+            Some(SmallBitVec::new(0b1010, 4))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_peek_eof() {
+        let mut left = BitReader::<Left>::new();
+        let mut iter = vec![0b1100_1100].into_iter();
+        assert_eq!(left.skip_bits::<_>(3, &mut iter).ok(), Some(3));
+        assert_eq!(
+            left.peek_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0b0_1100, 5))
+        );
+        assert_eq!(
+            left.peek_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0b0_1100, 5))
+        );
+        assert_eq!(left.skip_bits::<_>(12, &mut iter).ok(), Some(5));
+        assert_eq!(
+            left.peek_bits::<u32, _>(1, &mut iter).ok(),
+            Some(SmallBitVec::new(0, 0))
+        );
+
+        let mut right = BitReader::<Right>::new();
+        let mut iter = vec![0b1100_1100].into_iter();
+        assert_eq!(right.skip_bits::<_>(3, &mut iter).ok(), Some(3));
+        assert_eq!(
+            right.peek_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0b1_1001, 5))
+        );
+        assert_eq!(
+            right.read_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0b1_1001, 5))
+        );
+        assert_eq!(
+            right.read_bits::<u32, _>(1, &mut iter).ok(),
+            Some(SmallBitVec::new(0, 0))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_peek_long_after_partial() {
+        let cursor = vec![0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+        let mut iter = cursor.into_iter();
+
+        let mut reader = BitReader::<Left>::new();
+
+        assert_eq!(
+            reader.peek_bits::<u32, _>(24, &mut iter).ok(),
+            Some(SmallBitVec::new(0x12_3456, 24))
+        );
+        assert_eq!(reader.skip_bits::<_>(4, &mut iter).ok(), Some(4));
+        assert_eq!(
+            reader.peek_bits::<u64, _>(44, &mut iter).ok(),
+            Some(SmallBitVec::new(0x234_5678_9ABC, 44))
+        );
+        assert_eq!(
+            reader.read_bits::<u64, _>(60, &mut iter).ok(),
+            Some(SmallBitVec::new(0x234_5678_9ABC_DEF0, 60))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_skip_long() {
+        let cursor = (0..0x4000_u32).map(|x| x as u8).collect::<Vec<_>>();
+
+        let mut left = BitReader::<Left>::new();
+        let mut iter = cursor.clone().into_iter();
+        assert_eq!(
+            left.peek_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0x000, 12))
+        );
+        assert_eq!(left.skip_bits::<_>(3, &mut iter).ok(), Some(3));
+        assert_eq!(
+            left.skip_bits::<_>((0x1000 << 3) - 3 + 4, &mut iter).ok(),
+            Some((0x1000 << 3) - 3 + 4)
+        );
+        assert_eq!(
+            left.read_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0x001, 12))
+        );
+        assert_eq!(
+            left.skip_bits::<_>(0x10000 << 3, &mut iter).ok(),
+            Some((0x4000 - 0x1002) << 3)
+        );
+        assert_eq!(
+            left.read_bits::<u32, _>(1, &mut iter).ok(),
+            Some(SmallBitVec::new(0, 0))
+        );
+
+        let mut right = BitReader::<Right>::new();
+        let mut iter = cursor.into_iter();
+        assert_eq!(
+            right.peek_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0x100, 12))
+        );
+        assert_eq!(right.skip_bits::<_>(3, &mut iter).ok(), Some(3));
+        assert_eq!(
+            right.skip_bits::<_>((0x1000 << 3) - 3 + 4, &mut iter).ok(),
+            Some((0x1000 << 3) - 3 + 4)
+        );
+        assert_eq!(
+            right.read_bits::<u32, _>(12, &mut iter).ok(),
+            Some(SmallBitVec::new(0x010, 12))
+        );
+        assert_eq!(
+            right.skip_bits::<_>(0x10000 << 3, &mut iter).ok(),
+            Some((0x4000 - 0x1002) << 3)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_read_bytes() {
+        let cursor = vec![0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+
+        let mut reader = BitReader::<Left>::new();
+        let mut iter = cursor.clone().into_iter();
+        let mut buf = [0; 3];
+        assert_eq!(
+            reader.peek_bits::<u32, _>(16, &mut iter).ok(),
+            Some(SmallBitVec::new(0x1234, 16))
+        );
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter).ok(), Some(3));
+        assert_eq!(buf, [0x12, 0x34, 0x56]);
+        assert_eq!(
+            reader.read_bits::<u32, _>(4, &mut iter).ok(),
+            Some(SmallBitVec::new(0x7, 4))
+        );
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter).ok(), Some(3));
+        assert_eq!(buf, [0x89, 0xAB, 0xCD]);
+        assert_eq!(reader.skip_to_next_byte(), 4);
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter).ok(), Some(1));
+        assert_eq!(buf[0], 0xF0);
+
+        let mut reader = BitReader::<Right>::new();
+        let mut iter = cursor.into_iter();
+        assert_eq!(
+            reader.read_bits::<u32, _>(4, &mut iter).ok(),
+            Some(SmallBitVec::new(0x2, 4))
+        );
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter).ok(), Some(3));
+        assert_eq!(buf, [0x41, 0x63, 0x85]);
+        assert_eq!(reader.skip_to_next_byte(), 4);
+        let mut buf = [0; 8];
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter).ok(), Some(4));
+        assert_eq!(buf[..4], [0x9A, 0xBC, 0xDE, 0xF0]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_peek_spill() {
+        let cursor = vec![
+            0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x0F, 0xED, 0xCB,
+        ];
+
+        let mut left = BitReader::<Left>::new();
+        let mut iter = cursor.clone().into_iter();
+        assert_eq!(left.skip_bits::<_>(4, &mut iter).ok(), Some(4));
+        assert_eq!(
+            left.peek_bits::<u64, _>(64, &mut iter).ok(),
+            Some(SmallBitVec::new(0x2345_6789_ABCD_EF00, 64))
+        );
+        assert_eq!(
+            left.read_bits::<u64, _>(62, &mut iter).ok(),
+            Some(SmallBitVec::new(0x08D1_59E2_6AF3_7BC0, 62))
+        );
+        assert_eq!(
+            left.read_bits::<u32, _>(26, &mut iter).ok(),
+            Some(SmallBitVec::new(0x0F_EDCB, 22))
+        );
+
+        let mut right = BitReader::<Right>::new();
+        let mut iter = cursor.into_iter();
+        assert_eq!(right.skip_bits::<_>(4, &mut iter).ok(), Some(4));
+        assert_eq!(
+            right.peek_bits::<u64, _>(64, &mut iter).ok(),
+            Some(SmallBitVec::new(0xFF0D_EBC9_A785_6341, 64))
+        );
+        assert_eq!(
+            right.read_bits::<u64, _>(64, &mut iter).ok(),
+            Some(SmallBitVec::new(0xFF0D_EBC9_A785_6341, 64))
+        );
+        assert_eq!(
+            right.read_bits::<u32, _>(20, &mut iter).ok(),
+            Some(SmallBitVec::new(0xCBED0, 20))
+        );
+        assert!(right.peek_bits::<u64, _>(65, &mut iter).is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_position() {
+        let mut iter = (0..32_u8).collect::<Vec<_>>().into_iter();
+        let mut reader = BitReader::<Right>::new();
+
+        assert_eq!(reader.bytes_consumed(), 0);
+        let _ = reader.read_bits::<u8, _>(3, &mut iter);
+        assert_eq!(reader.bit_position(), 3);
+        assert_eq!(reader.bytes_consumed(), 1);
+        let _ = reader.peek_bits::<u64, _>(64, &mut iter);
+        assert_eq!(reader.bit_position(), 3);
+        assert_eq!(reader.skip_to_next_byte(), 5);
+        assert_eq!(reader.bit_position(), 8);
+        let _ = reader.skip_bits(100, &mut iter);
+        assert_eq!(reader.bit_position(), 108);
+        let mut buf = [0; 4];
+        let _ = reader.skip_to_next_byte();
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter), Ok(4));
+        assert_eq!(buf, [14, 15, 16, 17]);
+        assert_eq!(reader.bytes_consumed(), 18);
+        let _ = reader.skip_bits(1000, &mut iter);
+        assert_eq!(reader.bytes_consumed(), 32);
+    }
+
+    const WIDE: [u8; 16] = [
+        0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x0F, 0xED, 0xCB, 0xA9,
+        0x87, 0x65, 0x43, 0x21,
+    ];
+
+    #[test]
+    fn leftbitreader_read_wide() {
+        let mut iter = WIDE.iter().cloned();
+        let mut reader = BitReader::<Left>::new();
+
+        assert_eq!(
+            reader.read_bits::<u8, _>(3, &mut iter),
+            Ok(SmallBitVec::new(0b000, 3))
+        );
+        assert_eq!(
+            reader.read_bits::<u32, _>(17, &mut iter),
+            Ok(SmallBitVec::new(0x1_2345, 17))
+        );
+        assert_eq!(
+            reader.read_bits::<u32, _>(24, &mut iter),
+            Ok(SmallBitVec::new(0x67_89AB, 24))
+        );
+        assert_eq!(
+            reader.read_bits::<usize, _>(32, &mut iter),
+            Ok(SmallBitVec::new(0xCDEF_00FE, 32))
+        );
+        assert_eq!(
+            reader.read_bits::<u64, _>(48, &mut iter),
+            Ok(SmallBitVec::new(0xDCBA_9876_5432, 48))
+        );
+        assert_eq!(reader.bit_position(), 124);
+    }
+
+    #[test]
+    fn rightbitreader_read_wide() {
+        let mut iter = WIDE.iter().cloned();
+        let mut reader = BitReader::<Right>::new();
+
+        assert_eq!(
+            reader.read_bits::<u8, _>(3, &mut iter),
+            Ok(SmallBitVec::new(0b010, 3))
+        );
+        assert_eq!(
+            reader.read_bits::<u32, _>(17, &mut iter),
+            Ok(SmallBitVec::new(0xC682, 17))
+        );
+        assert_eq!(
+            reader.read_bits::<u32, _>(24, &mut iter),
+            Ok(SmallBitVec::new(0xC9_A785, 24))
+        );
+        assert_eq!(
+            reader.read_bits::<usize, _>(32, &mut iter),
+            Ok(SmallBitVec::new(0xD0FF_0DEB, 32))
+        );
+        assert_eq!(
+            reader.read_bits::<u64, _>(48, &mut iter),
+            Ok(SmallBitVec::new(0x1436_587A_9CBE, 48))
+        );
+        assert_eq!(reader.bit_position(), 124);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_rewind() {
+        let mut iter = (0..64_u8).collect::<Vec<_>>().into_iter();
+        let mut reader = BitReader::<Left>::with_rewind_window(16);
+
+        let _ = reader.read_bits::<u8, _>(5, &mut iter);
+        let mark = reader.checkpoint();
+        let first = reader.read_bits::<u32, _>(20, &mut iter);
+        let _ = reader.skip_bits(60, &mut iter);
+        assert_eq!(reader.rewind(mark), Ok(()));
+        assert_eq!(reader.bit_position(), 5);
+        assert_eq!(reader.read_bits::<u32, _>(20, &mut iter), first);
+
+        // 巻き戻した後も続きは正しく読める
+        let _ = reader.skip_bits(7, &mut iter);
+        let mut buf = [0; 3];
+        assert_eq!(reader.read_bytes(&mut buf, &mut iter), Ok(3));
+        assert_eq!(buf, [4, 5, 6]);
+        assert_eq!(reader.rewind(mark), Ok(()));
+        let _ = reader.skip_bits(3, &mut iter);
+        assert_eq!(
+            reader.read_bits::<u64, _>(64, &mut iter),
+            Ok(SmallBitVec::new(0x0102_0304_0506_0708, 64))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn rightbitreader_rewind() {
+        let mut iter = vec![0b1011_0110, 0b0101_1100].into_iter();
+        let mut reader = BitReader::<Right>::with_rewind_window(1);
+
+        let _ = reader.read_bits::<u8, _>(3, &mut iter);
+        let mark = reader.checkpoint();
+        assert_eq!(
+            reader.read_bits::<u8, _>(8, &mut iter),
+            Ok(SmallBitVec::new(0b1001_0110, 8))
+        );
+        assert_eq!(reader.rewind(mark), Ok(()));
+        assert_eq!(
+            reader.read_bits::<u16, _>(13, &mut iter),
+            Ok(SmallBitVec::new(0b0_1011_1001_0110, 13))
+        );
+        assert_eq!(reader.bytes_consumed(), 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_rewind_out_of_window() {
+        let mut iter = (0..64_u8).collect::<Vec<_>>().into_iter();
+        let mut reader = BitReader::<Left>::with_rewind_window(4);
+
+        let mark = reader.checkpoint();
+        let _ = reader.skip_bits(8 * 20, &mut iter);
+        let later = reader.checkpoint();
+        assert!(reader.rewind(mark).is_err());
+        assert_eq!(reader.bit_position(), 8 * 20);
+
+        let _ = reader.skip_bits(8 * 4, &mut iter);
+        assert_eq!(reader.rewind(later), Ok(()));
+        let _ = reader.skip_bits(8, &mut iter);
+        assert!(reader.rewind(Mark(8 * 30)).is_err());
+        assert_eq!(
+            reader.read_bits::<u8, _>(8, &mut iter),
+            Ok(SmallBitVec::new(21, 8))
+        );
+
+        let mut reader = BitReader::<Left>::new();
+        let mark = reader.checkpoint();
+        let _ = reader.read_bits::<u8, _>(1, &mut iter);
+        assert!(reader.rewind(mark).is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn leftbitreader_read_unary() {
+        let mut iter = vec![0b1110_1011, 0xFF, 0xFF, 0b1100_0000].into_iter();
+        let mut reader = BitReader::<Left>::new();
+
+        assert_eq!(reader.read_unary(8, &mut iter), Ok(3));
+        assert_eq!(reader.read_unary(8, &mut iter), Ok(1));
+        assert_eq!(reader.read_unary(100, &mut iter), Ok(20));
+        for _ in 0..5 {
+            assert_eq!(reader.read_unary(0, &mut iter), Ok(0));
+        }
+        assert_eq!(
+            reader.read_unary(100, &mut iter),
+            Err(CompressionError::UnexpectedEof)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn rightbitreader_read_unary() {
+        let mut iter = vec![0b1101_0111, 0xFF, 0xFF, 0b0000_0011].into_iter();
+        let mut reader = BitReader::<Right>::new();
+
+        assert_eq!(reader.read_unary(8, &mut iter), Ok(3));
+        assert_eq!(reader.read_unary(8, &mut iter), Ok(1));
+        assert_eq!(reader.read_unary(100, &mut iter), Ok(20));
+        assert_eq!(reader.read_unary(0, &mut iter), Ok(0));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_read_unary_too_long() {
+        let mut iter = vec![0xFF; 32].into_iter();
+        let mut reader = BitReader::<Left>::new();
+        assert_eq!(
+            reader.read_unary(100, &mut iter),
+            Err(CompressionError::DataError)
+        );
+
+        let mut iter = vec![0xFF, 0xFE].into_iter();
+        let mut reader = BitReader::<Left>::new();
+        assert_eq!(
+            reader.read_unary(14, &mut iter),
+            Err(CompressionError::DataError)
+        );
+
+        let mut iter = vec![0xFF, 0xFE].into_iter();
+        let mut reader = BitReader::<Left>::new();
+        assert_eq!(reader.read_unary(15, &mut iter), Ok(15));
+    }
+
+    #[cfg(feature = "alloc")]
+    fn read_bits_into_checker<D: Direction>() {
+        let data = (0..64_u32)
+            .map(|x| (x.wrapping_mul(0x9E37_79B9) >> 13) as u8)
+            .collect::<Vec<_>>();
+        for &width in &[1, 3, 7, 8, 13, 31, 56] {
+            let count = (data.len() << 3) / width;
+            let mut expected = Vec::new();
+            let mut iter = data.iter().cloned();
+            let mut reader = BitReader::<D>::new();
+            for _ in 0..count {
+                expected.push(
+                    reader
+                        .read_bits::<u64, _>(width, &mut iter)
+                        .unwrap()
+                        .data(),
+                );
+            }
+
+            let mut out = vec![0_u64; count];
+            let mut iter = data.iter().cloned();
+            let mut reader = BitReader::<D>::new();
+            assert_eq!(
+                reader.read_bits_into(width, &mut out, &mut iter),
+                Ok(count)
+            );
+            assert_eq!(out, expected);
+            assert_eq!(reader.bit_position(), count * width);
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn leftbitreader_read_bits_into() {
+        read_bits_into_checker::<Left>();
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn rightbitreader_read_bits_into() {
+        read_bits_into_checker::<Right>();
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitreader_read_bits_into_eof() {
+        let mut iter = vec![0b1011_0111, 0b0100_0000].into_iter();
+        let mut reader = BitReader::<Left>::new();
+        let mut out = [0_u8; 8];
+
+        assert_eq!(reader.read_bits_into(3, &mut out, &mut iter), Ok(5));
+        assert_eq!(&out[..5], &[0b101, 0b101, 0b110, 0b100, 0b000]);
+        assert_eq!(reader.bit_position(), 15);
+        assert_eq!(
+            reader.read_bits::<u8, _>(3, &mut iter).ok(),
+            Some(SmallBitVec::new(0, 1))
+        );
+        assert!(reader.read_bits_into(9, &mut out, &mut iter).is_err());
+    }
 }
@@ -0,0 +1,395 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+use crate::bitio::direction::Direction;
+use crate::bitio::reader::BitRead;
+use crate::bitio::small_bit_vec::SmallBitVec;
+use crate::core::cmp;
+use crate::core::convert::TryInto;
+use crate::core::marker::PhantomData;
+use crate::core::mem::size_of;
+use crate::core::ops::{BitOrAssign, Shl, Shr};
+use crate::error::CompressionError;
+use num_traits::cast::{cast, NumCast};
+use num_traits::sign::Unsigned;
+
+const ACC_BITS: usize = size_of::<u64>() << 3;
+
+/// Bit reader over a borrowed slice.
+///
+/// Works like `BitReader`, but while 8 bytes remain the accumulator is
+/// refilled with a single 8-byte load, and byte-aligned `read_bytes` copy
+/// straight from the slice. The `iter` argument of the `BitRead` methods
+/// is not used.
+///
+/// The bits loaded past `bits` are the real upcoming input, so the next
+/// refill ORs the same values over them.
+#[derive(Clone, Debug)]
+pub struct SliceBitReader<'a, D: Direction> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u64,
+    bits: usize,
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<D: Direction> BitRead for SliceBitReader<'_, D> {
+    type Direction = D;
+
+    fn peek_bits<T, R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        _iter: &mut R,
+    ) -> Result<SmallBitVec<T>, &'static str>
+    where
+        T: Unsigned
+            + BitOrAssign
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + From<u8>
+            + NumCast,
+    {
+        if len > cmp::min(ACC_BITS, size_of::<T>() << 3) {
+            return Err("len is too long");
+        }
+        if self.bits < len {
+            self.refill();
+        }
+        if self.bits >= len {
+            return Ok(SmallBitVec::new(
+                Self::conv_u64_to_t(self.peek_acc(len)),
+                len,
+            ));
+        }
+
+        // 入力が残っていればアキュムレータは 56 ビット以上埋まっているので、
+        // 足りない分は次の 1 バイトから読める
+        let head = self.bits;
+        let head_data = self.peek_acc(head);
+        let (data, len) = match self.data.get(self.pos) {
+            Some(&b) => {
+                let rest = len - head;
+                let b: u64 = b.into();
+                let data = if D::is_reverse() {
+                    head_data | ((b & ((1 << rest) - 1)) << head)
+                } else {
+                    (head_data << rest) | (b >> (8 - rest))
+                };
+                (data, len)
+            }
+            None => (head_data, head),
+        };
+        Ok(SmallBitVec::new(Self::conv_u64_to_t(data), len))
+    }
+
+    fn skip_bits<R: Iterator<Item = u8>>(
+        &mut self,
+        len: usize,
+        _iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        if len <= self.bits {
+            self.consume(len);
+            return Ok(len);
+        }
+
+        let mut skipped = self.bits;
+        self.acc = 0;
+        self.bits = 0;
+
+        let rest = len - skipped;
+        let count = cmp::min(rest >> 3, self.data.len() - self.pos);
+        self.pos += count;
+        skipped += count << 3;
+        if count < rest >> 3 {
+            return Ok(skipped);
+        }
+
+        let rest = rest & 0x07;
+        if rest > 0 {
+            self.refill();
+            let tail = cmp::min(rest, self.bits);
+            self.consume(tail);
+            skipped += tail;
+        }
+        Ok(skipped)
+    }
+
+    fn read_bytes<R: Iterator<Item = u8>>(
+        &mut self,
+        buf: &mut [u8],
+        iter: &mut R,
+    ) -> Result<usize, &'static str> {
+        let mut len = 0;
+        if self.bits & 0x07 != 0 {
+            for d in buf.iter_mut() {
+                let b = self.read_bits::<u8, _>(8, iter)?;
+                if b.len() < 8 {
+                    break;
+                }
+                *d = b.data();
+                len += 1;
+            }
+            return Ok(len);
+        }
+
+        while len < buf.len() && self.bits > 0 {
+            buf[len] = self.peek_acc(8) as u8;
+            self.consume(8);
+            len += 1;
+        }
+        if len < buf.len() {
+            // 先読みした分は捨ててスライスから直接コピーする
+            self.acc = 0;
+            let count = cmp::min(buf.len() - len, self.data.len() - self.pos);
+            buf[len..len + count]
+                .copy_from_slice(&self.data[self.pos..self.pos + count]);
+            self.pos += count;
+            len += count;
+        }
+        Ok(len)
+    }
+
+    fn read_bits_into<T, R: Iterator<Item = u8>>(
+        &mut self,
+        width: usize,
+        out: &mut [T],
+        _iter: &mut R,
+    ) -> Result<usize, &'static str>
+    where
+        T: NumCast,
+    {
+        if width > cmp::min(ACC_BITS - 8, size_of::<T>() << 3) {
+            return Err("len is too long");
+        }
+        for (i, d) in out.iter_mut().enumerate() {
+            if self.bits < width {
+                self.refill();
+                if self.bits < width {
+                    return Ok(i);
+                }
+            }
+            *d = Self::conv_u64_to_t(self.peek_acc(width));
+            self.consume(width);
+        }
+        Ok(out.len())
+    }
+
+    fn read_unary<R: Iterator<Item = u8>>(
+        &mut self,
+        max: usize,
+        _iter: &mut R,
+    ) -> Result<usize, CompressionError> {
+        let mut count = 0;
+        loop {
+            if self.bits == 0 {
+                self.refill();
+                if self.bits == 0 {
+                    return Err(CompressionError::UnexpectedEof);
+                }
+            }
+            let ones = cmp::min(
+                if D::is_reverse() {
+                    self.acc.trailing_ones()
+                } else {
+                    self.acc.leading_ones()
+                } as usize,
+                self.bits,
+            );
+            if count + ones > max {
+                return Err(CompressionError::DataError);
+            }
+            if ones < self.bits {
+                self.consume(ones + 1);
+                return Ok(count + ones);
+            }
+            count += ones;
+            self.consume(ones);
+        }
+    }
+
+    fn skip_to_next_byte(&mut self) -> usize {
+        let len = self.bits & 0x07;
+        self.consume(len);
+        len
+    }
+
+    #[inline]
+    fn bit_position(&self) -> usize {
+        (self.pos << 3) - self.bits
+    }
+}
+
+impl<'a, D: Direction> SliceBitReader<'a, D> {
+    #[inline]
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            acc: 0,
+            bits: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the number of bytes consumed from the slice. A partially
+    /// consumed byte counts as consumed.
+    #[inline]
+    pub fn bytes_consumed(&self) -> usize {
+        (self.bit_position() + 7) >> 3
+    }
+
+    #[inline]
+    fn refill(&mut self) {
+        if self.bits > ACC_BITS - 8 {
+            return;
+        }
+        if let Some(word) = self.data.get(self.pos..self.pos + 8) {
+            let word: [u8; 8] = word.try_into().unwrap();
+            if D::is_reverse() {
+                self.acc |= u64::from_le_bytes(word) << self.bits;
+            } else {
+                self.acc |= u64::from_be_bytes(word) >> self.bits;
+            }
+            self.pos += (ACC_BITS - 1 - self.bits) >> 3;
+            self.bits |= ACC_BITS - 8;
+            return;
+        }
+        while self.bits <= ACC_BITS - 8 && self.pos < self.data.len() {
+            let b: u64 = self.data[self.pos].into();
+            if D::is_reverse() {
+                self.acc |= b << self.bits;
+            } else {
+                self.acc |= b << (ACC_BITS - 8 - self.bits);
+            }
+            self.pos += 1;
+            self.bits += 8;
+        }
+    }
+
+    /// Returns the next `len` bits of the accumulator, right aligned.
+    #[inline]
+    fn peek_acc(&self, len: usize) -> u64 {
+        if len == 0 {
+            0
+        } else if D::is_reverse() {
+            self.acc & (u64::MAX >> (ACC_BITS - len))
+        } else {
+            self.acc >> (ACC_BITS - len)
+        }
+    }
+
+    #[inline]
+    fn consume(&mut self, len: usize) {
+        debug_assert!(len <= self.bits);
+        self.acc = if len >= ACC_BITS {
+            0
+        } else if D::is_reverse() {
+            self.acc >> len
+        } else {
+            self.acc << len
+        };
+        self.bits -= len;
+    }
+
+    #[inline]
+    fn conv_u64_to_t<T: NumCast>(value: u64) -> T {
+        debug_assert!(value >> ((size_of::<T>() << 3) - 1) <= 1);
+        cast(value).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitio::direction::left::Left;
+    use crate::bitio::direction::right::Right;
+    use crate::bitio::reader::BitReader;
+    use crate::core::iter;
+    use rand::{rng, RngExt};
+
+    fn slicereader_checker<D: Direction>() {
+        let mut data = [0_u8; 0x400];
+        for d in data.iter_mut() {
+            *d = rng().random();
+        }
+        let mut iter = data.iter().cloned();
+        let mut expected = BitReader::<D>::new();
+        let mut reader = SliceBitReader::<D>::new(&data);
+        let mut empty = iter::empty();
+
+        while expected.bit_position() < data.len() << 3 {
+            let len = rng().random_range(0..65);
+            match rng().random_range(0..5) {
+                0 => assert_eq!(
+                    reader.peek_bits::<u64, _>(len, &mut empty),
+                    expected.peek_bits::<u64, _>(len, &mut iter)
+                ),
+                1 => assert_eq!(
+                    reader.read_bits::<u64, _>(len, &mut empty),
+                    expected.read_bits::<u64, _>(len, &mut iter)
+                ),
+                2 => assert_eq!(
+                    reader.skip_bits(len * 3, &mut empty),
+                    expected.skip_bits(len * 3, &mut iter)
+                ),
+                3 => {
+                    let mut buf = [0; 16];
+                    let mut expected_buf = [0; 16];
+                    assert_eq!(
+                        reader.read_bytes(&mut buf[..len >> 2], &mut empty),
+                        expected.read_bytes(
+                            &mut expected_buf[..len >> 2],
+                            &mut iter
+                        )
+                    );
+                    assert_eq!(buf, expected_buf);
+                }
+                _ => assert_eq!(
+                    reader.skip_to_next_byte(),
+                    expected.skip_to_next_byte()
+                ),
+            }
+            assert_eq!(reader.bit_position(), expected.bit_position());
+            assert_eq!(reader.bytes_consumed(), expected.bytes_consumed());
+        }
+        assert_eq!(
+            reader.peek_bits::<u64, _>(8, &mut empty),
+            Ok(SmallBitVec::new(0, 0))
+        );
+    }
+
+    #[test]
+    fn leftslicereader_matches_bitreader() {
+        for _ in 0..100 {
+            slicereader_checker::<Left>();
+        }
+    }
+
+    #[test]
+    fn rightslicereader_matches_bitreader() {
+        for _ in 0..100 {
+            slicereader_checker::<Right>();
+        }
+    }
+
+    #[test]
+    fn slicereader_read_unary() {
+        let data = [0b1110_1011, 0xFF, 0xFF, 0b1100_0000];
+        let mut reader = SliceBitReader::<Left>::new(&data);
+        let mut empty = iter::empty();
+
+        assert_eq!(reader.read_unary(8, &mut empty), Ok(3));
+        assert_eq!(reader.read_unary(8, &mut empty), Ok(1));
+        assert_eq!(reader.read_unary(100, &mut empty), Ok(20));
+        let mut out = [0_u8; 8];
+        assert_eq!(reader.read_bits_into(1, &mut out, &mut empty), Ok(5));
+        assert_eq!(
+            reader.read_unary(100, &mut empty),
+            Err(CompressionError::UnexpectedEof)
+        );
+    }
+}
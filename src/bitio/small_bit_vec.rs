@@ -8,7 +8,7 @@
 use crate::core::mem::size_of;
 
 #[derive(Clone, Debug, Eq)]
-pub(crate) struct SmallBitVec<T = u32> {
+pub struct SmallBitVec<T = u32> {
     data: T,
     len: usize,
 }
@@ -23,7 +23,7 @@ where
 }
 
 impl<T> SmallBitVec<T> {
-    pub(crate) fn new(data: T, len: usize) -> Self {
+    pub fn new(data: T, len: usize) -> Self {
         debug_assert!(
             (size_of::<T>() * 8) >= len,
             "len is greater than bit capacity"
@@ -32,56 +32,60 @@ impl<T> SmallBitVec<T> {
     }
 
     #[inline]
-    pub(crate) fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.len
     }
 
     #[inline]
-    pub(crate) fn is_empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         self.len == 0
     }
 
     #[inline]
-    pub(crate) fn data_ref(&self) -> &T {
+    pub fn data_ref(&self) -> &T {
         &self.data
     }
 }
 
 impl<T: Copy> SmallBitVec<T> {
     #[inline]
-    #[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
-    pub(crate) fn data(&self) -> T {
+    pub fn data(&self) -> T {
         self.data
     }
 }
 
-pub(crate) trait SmallBitVecReverse {
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
+/// Reverses the order of the bits of a `SmallBitVec`.
+pub trait SmallBitVecReverse {
     fn reverse(&self) -> Self;
 }
 
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
 impl SmallBitVecReverse for SmallBitVec<u8> {
     fn reverse(&self) -> Self {
         let mut x = self.data;
         x = (x & 0x55) << 1 | (x & 0xAA) >> 1;
         x = (x & 0x33) << 2 | (x & 0xCC) >> 2;
-        x = x << 4 | x >> 4;
+        x = x.rotate_right(4);
         x >>= 8 - self.len;
         Self::new(x, self.len)
     }
 }
 
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
 impl SmallBitVecReverse for SmallBitVec<u16> {
     fn reverse(&self) -> Self {
         let mut x = self.data;
         x = (x & 0x5555) << 1 | (x & 0xAAAA) >> 1;
         x = (x & 0x3333) << 2 | (x & 0xCCCC) >> 2;
         x = (x & 0x0F0F) << 4 | (x & 0xF0F0) >> 4;
-        x = x << 8 | x >> 8;
+        x = x.rotate_right(8);
         x >>= 16 - self.len;
         Self::new(x, self.len)
     }
 }
 
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
 impl SmallBitVecReverse for SmallBitVec<u32> {
     fn reverse(&self) -> Self {
         let mut x = self.data;
@@ -89,12 +93,13 @@ impl SmallBitVecReverse for SmallBitVec<u32> {
         x = (x & 0x3333_3333) << 2 | (x & 0xCCCC_CCCC) >> 2;
         x = (x & 0x0F0F_0F0F) << 4 | (x & 0xF0F0_F0F0) >> 4;
         x = (x & 0x00FF_00FF) << 8 | (x & 0xFF00_FF00) >> 8;
-        x = x << 16 | x >> 16;
+        x = x.rotate_right(16);
         x >>= 32 - self.len;
         Self::new(x, self.len)
     }
 }
 
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
 impl SmallBitVecReverse for SmallBitVec<u64> {
     fn reverse(&self) -> Self {
         let mut x = self.data;
@@ -103,8 +108,8 @@ impl SmallBitVecReverse for SmallBitVec<u64> {
         x = (x & 0x0F0F_0F0F_0F0F_0F0F) << 4 | (x & 0xF0F0_F0F0_F0F0_F0F0) >> 4;
         x = (x & 0x00FF_00FF_00FF_00FF) << 8 | (x & 0xFF00_FF00_FF00_FF00) >> 8;
         x = (x & 0x0000_FFFF_0000_FFFF) << 16
-            | (x & 0xFFFF_FFFF_0000_0000) >> 16;
-        x = x << 32 | x >> 32;
+            | (x & 0xFFFF_0000_FFFF_0000) >> 16;
+        x = x.rotate_right(32);
         x >>= 64 - self.len;
         Self::new(x, self.len)
     }
@@ -116,7 +121,10 @@ impl<T: Default> Default for SmallBitVec<T> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(
+    test,
+    any(feature = "bzip2", feature = "deflate", feature = "lzhuf")
+))]
 mod tests {
     use super::*;
 
@@ -151,4 +159,12 @@ mod tests {
             SmallBitVec::<u64>::new(0x0001_F1C6, 17)
         );
     }
+
+    #[test]
+    fn smallbitvec_u64_reverse_48() {
+        assert_eq!(
+            SmallBitVec::<u64>::new(0x1234_5678_9ABC, 48).reverse(),
+            SmallBitVec::<u64>::new(0x3D59_1E6A_2C48, 48)
+        );
+    }
 }
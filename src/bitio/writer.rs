@@ -5,19 +5,27 @@
 //! version 2.0 (the "License"). You can obtain a copy of the License at
 //! <http://mozilla.org/MPL/2.0/>.
 
+#[cfg(all(test, feature = "alloc"))]
 use crate::action::Action;
 use crate::bitio::direction::Direction;
 use crate::bitio::small_bit_vec::SmallBitVec;
+#[cfg(all(test, feature = "alloc"))]
 use crate::core::borrow::BorrowMut;
+use crate::core::cmp;
 use crate::core::marker::PhantomData;
 use crate::core::mem::size_of;
+#[cfg(all(test, feature = "alloc"))]
 use crate::core::ops::{BitOr, Shl, Shr};
-#[cfg(not(feature = "std"))]
-#[allow(unused_imports)]
-use alloc::vec;
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::collections::vec_deque::VecDeque;
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::vec::Vec;
 use num_traits::sign::Unsigned;
 use num_traits::{cast, NumCast};
+#[cfg(feature = "std")]
+use std::collections::vec_deque::VecDeque;
 
+#[cfg(all(test, feature = "alloc"))]
 pub(crate) trait BitWriteExt<T, I>
 where
     T: Copy
@@ -36,6 +44,7 @@ where
     ) -> BitIterator<T, D, I, W>;
 }
 
+#[cfg(all(test, feature = "alloc"))]
 impl<T, I> BitWriteExt<T, I::IntoIter> for I
 where
     T: Copy
@@ -60,6 +69,7 @@ where
     }
 }
 
+#[cfg(all(test, feature = "alloc"))]
 pub(crate) struct BitIterator<T, D, I, W>
 where
     T: Copy
@@ -76,12 +86,11 @@ where
     writer: W,
     inner: I,
     action: Action,
-    buf: T,
-    buflen: usize,
     finished: bool,
     phantom: PhantomData<fn() -> D>,
 }
 
+#[cfg(all(test, feature = "alloc"))]
 impl<T, D, I, W> BitIterator<T, D, I, W>
 where
     T: Copy
@@ -100,14 +109,13 @@ where
             writer,
             inner,
             action,
-            buf: T::zero(),
-            buflen: 0,
             finished: false,
             phantom: PhantomData,
         }
     }
 }
 
+#[cfg(all(test, feature = "alloc"))]
 impl<T, D, I, W> Iterator for BitIterator<T, D, I, W>
 where
     T: Copy
@@ -124,8 +132,11 @@ where
     type Item = u8;
 
     fn next(&mut self) -> Option<u8> {
-        while self.buflen == 0 {
-            let s = match self.inner.next() {
+        loop {
+            if let Some(b) = self.writer.borrow_mut().pop_byte() {
+                return Some(b);
+            }
+            match self.inner.next() {
                 Some(ref s) => self.writer.borrow_mut().write_bits(s),
                 None => {
                     if self.finished {
@@ -135,109 +146,302 @@ where
                         || Action::Finish == self.action
                     {
                         self.finished = true;
-                        match self.writer.borrow_mut().flush::<T>() {
-                            Some((x, y)) if y != 0 => (x, y),
-                            _ => return None,
+                        let _ = self.writer.borrow_mut().pad_to_byte(false);
+                        if !self.writer.borrow_mut().has_byte() {
+                            return None;
                         }
                     } else {
                         return None;
                     }
                 }
-            };
-            self.buf = s.0;
-            self.buflen = s.1;
+            }
         }
+    }
+}
 
-        let ret = cast::<T, u8>(D::convert(
-            self.buf,
-            size_of::<T>() << 3,
-            size_of::<u8>() << 3,
-        ))
-        .unwrap();
+const ACC_BITS: usize = 64;
 
-        self.buf = D::forward(self.buf, size_of::<u8>() << 3);
-        self.buflen -= 1;
-        Some(ret)
+pub trait BitWrite<T> {
+    /// Appends `data`.
+    fn write_bits(&mut self, data: &SmallBitVec<T>);
+
+    /// Appends `n` bits of `!terminator` followed by one `terminator` bit.
+    fn write_unary(&mut self, n: usize, terminator: bool)
+    where
+        T: Unsigned + NumCast,
+    {
+        let width = size_of::<T>() << 3;
+        let mut rest = n;
+        while rest > 0 {
+            let len = cmp::min(rest, width);
+            let fill = if terminator {
+                0
+            } else {
+                u64::MAX >> (64 - len)
+            };
+            self.write_bits(&SmallBitVec::new(cast(fill).unwrap(), len));
+            rest -= len;
+        }
+        self.write_bits(&SmallBitVec::new(
+            if terminator { T::one() } else { T::zero() },
+            1,
+        ));
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Clone> BitWrite<T> for Vec<SmallBitVec<T>> {
+    fn write_bits(&mut self, data: &SmallBitVec<T>) {
+        self.push(data.clone());
     }
 }
 
+/// Collects bits in a 64-bit accumulator; completed bytes are taken out
+/// with `pop_byte`. Whole bytes move to `overflow` when a write would not
+/// fit, so any amount can be written between two `pop_byte` calls.
+#[cfg(feature = "alloc")]
 #[derive(Clone, Debug)]
-pub(crate) struct BitWriter<D: Direction> {
-    buf: u8,
-    counter: usize,
+pub struct BitWriter<D: Direction> {
+    acc: u64,
+    bits: usize,
+    total: usize,
+    overflow: VecDeque<u8>,
     phantom: PhantomData<fn() -> D>,
 }
 
+#[cfg(feature = "alloc")]
 impl<D: Direction> Default for BitWriter<D> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "alloc")]
+impl<D: Direction, T: Copy + Unsigned + NumCast> BitWrite<T> for BitWriter<D> {
+    /// Appends `data` to the accumulator. Codes of up to 57 bits are
+    /// accepted.
+    #[inline]
+    fn write_bits(&mut self, data: &SmallBitVec<T>) {
+        let len = data.len();
+        if len == 0 {
+            return;
+        }
+        let value = cast::<T, u64>(*data.data_ref()).unwrap()
+            & (u64::MAX >> (ACC_BITS - len));
+        self.write_u64(value, len);
+    }
+
+    /// Writes the run up to 56 bits at a time.
+    fn write_unary(&mut self, n: usize, terminator: bool) {
+        const CHUNK: usize = ACC_BITS - 8;
+        let fill = if terminator { 0 } else { u64::MAX };
+        let mut rest = n;
+        while rest >= CHUNK {
+            self.write_u64(fill >> (ACC_BITS - CHUNK), CHUNK);
+            rest -= CHUNK;
+        }
+        // 残りと終端ビットをまとめて書く
+        let t: u64 = terminator.into();
+        let value = if rest == 0 {
+            t
+        } else if D::is_reverse() {
+            (fill >> (ACC_BITS - rest)) | (t << rest)
+        } else {
+            (fill >> (ACC_BITS - rest)) << 1 | t
+        };
+        self.write_u64(value, rest + 1);
+    }
+}
+
+#[cfg(feature = "alloc")]
 impl<D: Direction> BitWriter<D> {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self {
-            buf: 0,
-            counter: 0,
+            acc: 0,
+            bits: 0,
+            total: 0,
+            overflow: VecDeque::new(),
             phantom: PhantomData,
         }
     }
 
-    pub(crate) fn write_bits<T>(&mut self, data: &SmallBitVec<T>) -> (T, usize)
-    where
-        T: Copy
-            + BitOr<Output = T>
-            + From<u8>
-            + Shl<usize, Output = T>
-            + Shr<usize, Output = T>
-            + Unsigned
-            + NumCast,
-    {
-        if data.is_empty() {
-            return (T::zero(), 0);
+    #[inline]
+    fn write_u64(&mut self, value: u64, len: usize) {
+        if self.bits + len > ACC_BITS {
+            while let Some(b) = self.take_acc_byte() {
+                self.overflow.push_back(b);
+            }
+        }
+        debug_assert!(self.bits + len <= ACC_BITS);
+        if D::is_reverse() {
+            self.acc |= value << self.bits;
+        } else {
+            self.acc |= value << (ACC_BITS - self.bits - len);
         }
+        self.bits += len;
+        self.total += len;
+    }
 
-        let len = data.len();
-        let data = D::convert(*data.data_ref(), len, size_of::<T>() << 3);
-        let clen = len + self.counter;
+    /// Returns the number of bits written so far, padding included.
+    #[inline]
+    pub fn bit_len(&self) -> usize {
+        self.total
+    }
 
-        let wdata = D::convert::<T>(
-            From::from(self.buf),
-            size_of::<u8>() << 3,
-            size_of::<T>() << 3,
-        ) | D::backward(data, self.counter);
+    /// Returns `true` if at least one whole byte is pending.
+    #[inline]
+    pub fn has_byte(&self) -> bool {
+        self.bits >= 8 || !self.overflow.is_empty()
+    }
 
-        let wlen = clen >> 3;
+    /// Takes the next whole byte out of the writer.
+    #[inline]
+    pub fn pop_byte(&mut self) -> Option<u8> {
+        match self.overflow.pop_front() {
+            Some(b) => Some(b),
+            None => self.take_acc_byte(),
+        }
+    }
 
-        self.buf = cast::<T, u8>(D::convert(
-            if wlen == 0 {
-                wdata
+    #[inline]
+    fn take_acc_byte(&mut self) -> Option<u8> {
+        if self.bits < 8 {
+            return None;
+        }
+        let ret = if D::is_reverse() {
+            let ret = self.acc as u8;
+            self.acc >>= 8;
+            ret
+        } else {
+            let ret = (self.acc >> (ACC_BITS - 8)) as u8;
+            self.acc <<= 8;
+            ret
+        };
+        self.bits -= 8;
+        Some(ret)
+    }
+
+    /// Completes the pending partial byte with `fill` bits and returns the
+    /// number of bits added. Does nothing when already byte aligned.
+    pub fn pad_to_byte(&mut self, fill: bool) -> usize {
+        let len = self.bit_len().wrapping_neg() & 0x07;
+        if len > 0 {
+            let value = if fill { 0xFF } else { 0 };
+            self.write_u64(value >> (8 - len), len);
+        }
+        len
+    }
+}
+
+/// Bit writer into a caller-provided slice; needs no allocation.
+///
+/// Whole bytes are stored as soon as they are complete. Once a byte does
+/// not fit, the writer is marked as overflowed and drops every later
+/// write, and `finish` returns an error.
+#[derive(Debug)]
+pub struct SliceBitWriter<'a, D: Direction> {
+    buf: &'a mut [u8],
+    pos: usize,
+    acc: u64,
+    bits: usize,
+    overflowed: bool,
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<D: Direction, T: Copy + Unsigned + NumCast> BitWrite<T>
+    for SliceBitWriter<'_, D>
+{
+    /// Appends `data`. Codes of up to 57 bits are accepted.
+    #[inline]
+    fn write_bits(&mut self, data: &SmallBitVec<T>) {
+        let len = data.len();
+        if len == 0 {
+            return;
+        }
+        let value = cast::<T, u64>(*data.data_ref()).unwrap()
+            & (u64::MAX >> (ACC_BITS - len));
+        self.write_u64(value, len);
+    }
+}
+
+impl<'a, D: Direction> SliceBitWriter<'a, D> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            acc: 0,
+            bits: 0,
+            overflowed: false,
+            phantom: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn write_u64(&mut self, value: u64, len: usize) {
+        if self.overflowed {
+            return;
+        }
+        // 書き込みのたびに吐き出すので、ここでは 8 ビット未満しか残っていない
+        debug_assert!(self.bits + len <= ACC_BITS);
+        if D::is_reverse() {
+            self.acc |= value << self.bits;
+        } else {
+            self.acc |= value << (ACC_BITS - self.bits - len);
+        }
+        self.bits += len;
+        while self.bits >= 8 {
+            let b = if D::is_reverse() {
+                let b = self.acc as u8;
+                self.acc >>= 8;
+                b
             } else {
-                D::forward(data, (wlen << 3) - self.counter)
-            },
-            size_of::<T>() << 3,
-            size_of::<u8>() << 3,
-        ))
-        .unwrap();
-        self.counter = clen - (wlen << 3);
-        (wdata, wlen)
+                let b = (self.acc >> (ACC_BITS - 8)) as u8;
+                self.acc <<= 8;
+                b
+            };
+            self.bits -= 8;
+            match self.buf.get_mut(self.pos) {
+                Some(d) => *d = b,
+                None => {
+                    self.overflowed = true;
+                    return;
+                }
+            }
+            self.pos += 1;
+        }
     }
 
-    pub(crate) fn flush<T>(&mut self) -> Option<(T, usize)>
-    where
-        T: Copy
-            + BitOr<Output = T>
-            + From<u8>
-            + Shl<usize, Output = T>
-            + Shr<usize, Output = T>
-            + Unsigned
-            + NumCast,
-    {
-        if self.counter > 0 {
-            let c = (size_of::<u8>() << 3) - self.counter;
-            Some(self.write_bits::<T>(&SmallBitVec::new(T::zero(), c)))
+    /// Returns the number of bits written so far, padding included.
+    #[inline]
+    pub fn bit_len(&self) -> usize {
+        (self.pos << 3) + self.bits
+    }
+
+    /// Returns `true` once a write has run past the end of the slice.
+    #[inline]
+    pub fn is_overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// Completes the pending partial byte with `fill` bits and returns the
+    /// number of bits added. Does nothing when already byte aligned.
+    pub fn pad_to_byte(&mut self, fill: bool) -> usize {
+        let len = self.bits.wrapping_neg() & 0x07;
+        if len > 0 {
+            let value = if fill { 0xFF } else { 0 };
+            self.write_u64(value >> (8 - len), len);
+        }
+        len
+    }
+
+    /// Pads the last byte with 0-bits and returns the number of bytes
+    /// written, or an error if the output did not fit in the slice.
+    pub fn finish(mut self) -> Result<usize, &'static str> {
+        let _ = self.pad_to_byte(false);
+        if self.overflowed {
+            Err("buffer is too small")
         } else {
-            None
+            Ok(self.pos)
         }
     }
 }
@@ -247,9 +451,16 @@ mod tests {
     use super::*;
     use crate::bitio::direction::left::Left;
     use crate::bitio::direction::right::Right;
-    #[cfg(not(feature = "std"))]
-    use alloc::vec::Vec;
-
+    #[cfg(feature = "alloc")]
+    use crate::bitio::reader::{BitRead, BitReader};
+    #[cfg(feature = "alloc")]
+    use crate::core::iter;
+    #[cfg(all(feature = "alloc", not(feature = "std")))]
+    use alloc::{vec, vec::Vec};
+    #[cfg(feature = "alloc")]
+    use rand::{rng, RngExt};
+
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitwriter_write() {
         let mut writer = BitWriter::<Left>::new();
@@ -265,6 +476,7 @@ mod tests {
         assert_eq!(ret, vec![0b1100_1100_u8]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitwriter_write_big() {
         let mut writer = BitWriter::<Left>::new();
@@ -278,6 +490,7 @@ mod tests {
         assert_eq!(ret, vec![243, 221, 190, 200]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitwriter_write_pad() {
         let mut writer = BitWriter::<Left>::new();
@@ -289,12 +502,13 @@ mod tests {
         .to_bytes(&mut writer, Action::Run)
         .collect::<Vec<_>>();
         assert_eq!(ret.len(), 0);
-        let ret = vec![SmallBitVec::<u32>::default(); 0]
+        let ret = Vec::<SmallBitVec<u32>>::new()
             .to_bytes(&mut writer, Action::Flush)
             .collect::<Vec<_>>();
         assert_eq!(ret, vec![204]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitwriter_write_1bit() {
         let mut writer = BitWriter::<Left>::new();
@@ -304,6 +518,7 @@ mod tests {
         assert_eq!(ret, vec![128]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn leftbitwriter_zero() {
         let mut writer = BitWriter::<Left>::new();
@@ -321,6 +536,25 @@ mod tests {
         assert_eq!(ret, vec![0, 0, 0, 0]);
     }
 
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn leftbitwriter_write_32() {
+        let mut writer = BitWriter::<Left>::new();
+        let ret = vec![
+            SmallBitVec::new(0b101_u32, 3),
+            SmallBitVec::new(0xDEAD_BEEF, 32),
+            SmallBitVec::new(0x1234_5678, 32),
+            SmallBitVec::new(0b1, 1),
+        ]
+        .to_bytes(&mut writer, Action::Flush)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            ret,
+            vec![0xBB, 0xD5, 0xB7, 0xDD, 0xE2, 0x46, 0x8A, 0xCF, 0x10]
+        );
+    }
+
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write() {
         let mut writer = BitWriter::<Right>::new();
@@ -336,6 +570,25 @@ mod tests {
         assert_eq!(ret, vec![0b0001_1101]);
     }
 
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn rightbitwriter_write_32() {
+        let mut writer = BitWriter::<Right>::new();
+        let ret = vec![
+            SmallBitVec::new(0b101_u32, 3),
+            SmallBitVec::new(0xDEAD_BEEF, 32),
+            SmallBitVec::new(0x1234_5678, 32),
+            SmallBitVec::new(0b1, 1),
+        ]
+        .to_bytes(&mut writer, Action::Flush)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            ret,
+            vec![0x7D, 0xF7, 0x6D, 0xF5, 0xC6, 0xB3, 0xA2, 0x91, 0x08]
+        );
+    }
+
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write_big() {
         let mut writer = BitWriter::<Right>::new();
@@ -349,6 +602,7 @@ mod tests {
         assert_eq!(ret, vec![0xCF, 0x6F, 0x87, 0xEC]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write_pad() {
         let mut writer = BitWriter::<Right>::new();
@@ -360,12 +614,13 @@ mod tests {
         .to_bytes(&mut writer, Action::Run)
         .collect::<Vec<_>>();
         assert_eq!(ret.len(), 0);
-        let ret = vec![SmallBitVec::<u32>::default(); 0]
+        let ret = Vec::<SmallBitVec<u32>>::new()
             .to_bytes(&mut writer, Action::Flush)
             .collect::<Vec<_>>();
         assert_eq!(ret, vec![0b0001_1101]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write_pad_8() {
         let mut writer = BitWriter::<Right>::new();
@@ -377,12 +632,13 @@ mod tests {
         .to_bytes(&mut writer, Action::Run)
         .collect::<Vec<_>>();
         assert_eq!(ret.len(), 0);
-        let ret = vec![SmallBitVec::<u8>::default(); 0]
+        let ret = Vec::<SmallBitVec<u8>>::new()
             .to_bytes(&mut writer, Action::Flush)
             .collect::<Vec<_>>();
         assert_eq!(ret, vec![0b0001_1101]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write_pad_16() {
         let mut writer = BitWriter::<Right>::new();
@@ -394,12 +650,13 @@ mod tests {
         .to_bytes(&mut writer, Action::Run)
         .collect::<Vec<_>>();
         assert_eq!(ret.len(), 0);
-        let ret = vec![SmallBitVec::<u16>::default(); 0]
+        let ret = Vec::<SmallBitVec<u16>>::new()
             .to_bytes(&mut writer, Action::Flush)
             .collect::<Vec<_>>();
         assert_eq!(ret, vec![0b0001_1101]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write_pad_64() {
         let mut writer = BitWriter::<Right>::new();
@@ -411,12 +668,13 @@ mod tests {
         .to_bytes(&mut writer, Action::Run)
         .collect::<Vec<_>>();
         assert_eq!(ret.len(), 0);
-        let ret = vec![SmallBitVec::<u64>::default(); 0]
+        let ret = Vec::<SmallBitVec<u64>>::new()
             .to_bytes(&mut writer, Action::Flush)
             .collect::<Vec<_>>();
         assert_eq!(ret, vec![0b0001_1101]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_write_1bit() {
         let mut writer = BitWriter::<Right>::new();
@@ -426,6 +684,7 @@ mod tests {
         assert_eq!(ret, vec![1]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn rightbitwriter_zero() {
         let mut writer = BitWriter::<Right>::new();
@@ -442,4 +701,183 @@ mod tests {
         .collect::<Vec<_>>();
         assert_eq!(ret, vec![0, 0, 0, 0]);
     }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitwriter_pad_to_byte() {
+        let mut writer = BitWriter::<Left>::new();
+        assert_eq!(writer.pad_to_byte(true), 0);
+        writer.write_bits(&SmallBitVec::new(0b101_u8, 3));
+        assert_eq!(writer.bit_len(), 3);
+        assert_eq!(writer.pad_to_byte(true), 5);
+        assert_eq!(writer.pad_to_byte(true), 0);
+        assert_eq!(writer.bit_len(), 8);
+        writer.write_bits(&SmallBitVec::new(0b1_u8, 1));
+        assert_eq!(writer.pad_to_byte(false), 7);
+        assert_eq!(writer.bit_len(), 16);
+        assert_eq!(writer.pop_byte(), Some(0b1011_1111));
+        assert_eq!(writer.pop_byte(), Some(0b1000_0000));
+        assert_eq!(writer.pop_byte(), None);
+
+        let mut writer = BitWriter::<Right>::new();
+        writer.write_bits(&SmallBitVec::new(0b101_u8, 3));
+        assert_eq!(writer.pad_to_byte(true), 5);
+        assert_eq!(writer.pop_byte(), Some(0b1111_1101));
+        assert_eq!(writer.bit_len(), 8);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn leftbitwriter_write_wide() {
+        let mut writer = BitWriter::<Left>::new();
+        let ret = vec![
+            SmallBitVec::new(0b000_u64, 3),
+            SmallBitVec::new(0x1_2345, 17),
+            SmallBitVec::new(0x67_89AB, 24),
+            SmallBitVec::new(0xCDEF_00FE, 32),
+            SmallBitVec::new(0xDCBA_9876_5432, 48),
+        ]
+        .to_bytes(&mut writer, Action::Flush)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            ret,
+            vec![
+                0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x0F, 0xED,
+                0xCB, 0xA9, 0x87, 0x65, 0x43, 0x20,
+            ]
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn rightbitwriter_write_wide() {
+        let mut writer = BitWriter::<Right>::new();
+        let ret = vec![
+            SmallBitVec::new(0b010_u64, 3),
+            SmallBitVec::new(0xC682, 17),
+            SmallBitVec::new(0xC9_A785, 24),
+            SmallBitVec::new(0xD0FF_0DEB, 32),
+            SmallBitVec::new(0x1436_587A_9CBE, 48),
+        ]
+        .to_bytes(&mut writer, Action::Flush)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            ret,
+            vec![
+                0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x0F, 0xED,
+                0xCB, 0xA9, 0x87, 0x65, 0x43, 0x01,
+            ]
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    fn check_unary<D: Direction>(prefix: usize, runs: &[usize]) {
+        let mut writer = BitWriter::<D>::new();
+        writer.write_bits(&SmallBitVec::new(0_u8, prefix));
+        for &n in runs {
+            BitWrite::<u8>::write_unary(&mut writer, n, false);
+        }
+        let _ = writer.pad_to_byte(false);
+        let bytes = iter::from_fn(|| writer.pop_byte()).collect::<Vec<_>>();
+
+        let mut iter = bytes.into_iter();
+        let mut reader = BitReader::<D>::new();
+        let _ = reader.skip_bits(prefix, &mut iter);
+        for &n in runs {
+            assert_eq!(reader.read_unary(n, &mut iter), Ok(n));
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitwriter_unary_roundtrip() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let prefix = rng.random_range(0..8);
+            let runs = (0..rng.random_range(1..8))
+                .map(|_| rng.random_range(0..4000))
+                .collect::<Vec<_>>();
+            check_unary::<Left>(prefix, &runs);
+            check_unary::<Right>(prefix, &runs);
+        }
+        check_unary::<Left>(0, &[0, 55, 56, 57, 63, 64, 65, 0]);
+        check_unary::<Right>(7, &[0, 55, 56, 57, 63, 64, 65, 0]);
+    }
+
+    #[test]
+    fn slicebitwriter_overflow() {
+        let mut buf = [0_u8; 2];
+        let mut writer = SliceBitWriter::<Left>::new(&mut buf);
+        writer.write_bits(&SmallBitVec::new(0b1010_u8, 4));
+        writer.write_bits(&SmallBitVec::new(0xABC_u16, 12));
+        assert_eq!(writer.bit_len(), 16);
+        assert_eq!(writer.finish(), Ok(2));
+        assert_eq!(buf, [0xAA, 0xBC]);
+
+        // 最後の 1 バイトがパディングで溢れる
+        let mut buf = [0_u8; 1];
+        let mut writer = SliceBitWriter::<Right>::new(&mut buf);
+        writer.write_bits(&SmallBitVec::new(0x1FF_u16, 9));
+        assert!(!writer.is_overflowed());
+        assert_eq!(writer.finish(), Err("buffer is too small"));
+        assert_eq!(buf, [0xFF]);
+
+        // 溢れた後の書き込みは捨てられる
+        let mut buf = [0_u8; 4];
+        let mut writer = SliceBitWriter::<Left>::new(&mut buf);
+        for _ in 0..10 {
+            writer.write_bits(&SmallBitVec::new(u64::MAX >> 7, 57));
+        }
+        BitWrite::<u64>::write_unary(&mut writer, 1000, false);
+        assert!(writer.is_overflowed());
+        assert_eq!(writer.finish(), Err("buffer is too small"));
+        assert_eq!(buf, [0xFF; 4]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn slicebitwriter_matches_bitwriter() {
+        let mut rng = rng();
+        let mut writer = BitWriter::<Right>::new();
+        let mut buf = [0_u8; 0x400];
+        let mut slice_writer = SliceBitWriter::<Right>::new(&mut buf);
+        while writer.bit_len() < 0x1000 {
+            let len = rng.random_range(1..58);
+            let data = SmallBitVec::new(rng.random::<u64>() >> (64 - len), len);
+            writer.write_bits(&data);
+            slice_writer.write_bits(&data);
+            assert_eq!(slice_writer.bit_len(), writer.bit_len());
+        }
+        let _ = writer.pad_to_byte(false);
+        let expected = iter::from_fn(|| writer.pop_byte()).collect::<Vec<_>>();
+        let len = slice_writer.finish().unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn bitwriter_unary_terminator() {
+        let mut writer = BitWriter::<Left>::new();
+        BitWrite::<u8>::write_unary(&mut writer, 3, true);
+        BitWrite::<u8>::write_unary(&mut writer, 2, false);
+        let _ = writer.pad_to_byte(false);
+        assert_eq!(writer.pop_byte(), Some(0b0001_1100));
+
+        let mut writer = BitWriter::<Right>::new();
+        BitWrite::<u8>::write_unary(&mut writer, 3, true);
+        BitWrite::<u8>::write_unary(&mut writer, 2, false);
+        let _ = writer.pad_to_byte(false);
+        assert_eq!(writer.pop_byte(), Some(0b0011_1000));
+
+        let mut ret = Vec::<SmallBitVec<u16>>::new();
+        ret.write_unary(20, false);
+        assert_eq!(
+            ret,
+            vec![
+                SmallBitVec::new(0xFFFF, 16),
+                SmallBitVec::new(0xF, 4),
+                SmallBitVec::new(0, 1),
+            ]
+        );
+    }
 }
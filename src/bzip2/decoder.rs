@@ -6,18 +6,17 @@
 //! <http://mozilla.org/MPL/2.0/>.
 
 use crate::bitio::direction::left::Left;
-use crate::bitio::reader::{BitRead, BitReader};
-use crate::bitset::BitArray;
+use crate::bitio::reader::BitRead;
 use crate::bzip2::error::BZip2Error;
 use crate::bzip2::mtf::MtfPositionDecoder;
-use crate::bzip2::{HEADER_h, BZ_G_SIZE, HEADER_0, HEADER_B, HEADER_Z};
+use crate::bzip2::{
+    HEADER_h, BLOCK_MAGIC, BZ_G_SIZE, END_MAGIC, HEADER_0, HEADER_B, HEADER_Z,
+};
 use crate::core::hash::{BuildHasher, Hasher};
 use crate::crc32::{BuiltinDigest, IEEE_NORMAL};
 use crate::huffman::decoder::HuffmanDecoder;
 use crate::traits::decoder::{BitDecodeService, BitDecoderImpl, Decoder};
 #[cfg(not(feature = "std"))]
-use alloc::string::String;
-#[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
@@ -141,22 +140,37 @@ impl BZip2DecoderBase {
     fn read_u8<R: BitRead, I: Iterator<Item = u8>>(
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<u8, String> {
+    ) -> Result<u8, &'static str> {
         reader.read_bits(8, iter).map(|x| x.data())
     }
 
     fn read_u32<R: BitRead, I: Iterator<Item = u8>>(
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<u32, String> {
-        reader.read_bits(32, iter).map(|x| x.data())
+    ) -> Result<u32, &'static str> {
+        let b = reader.read_bits(32, iter)?;
+        if b.len() < 32 {
+            return Err("unexpected eof");
+        }
+        Ok(b.data())
+    }
+
+    fn read_u48<R: BitRead, I: Iterator<Item = u8>>(
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<u64, &'static str> {
+        let b = reader.read_bits(48, iter)?;
+        if b.len() < 48 {
+            return Err("unexpected eof");
+        }
+        Ok(b.data())
     }
 
     fn check_u8<R: BitRead, I: Iterator<Item = u8>>(
         reader: &mut R,
         iter: &mut I,
         value: u8,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, &'static str> {
         Self::read_u8(reader, iter).map(|x| x == value)
     }
 
@@ -181,7 +195,7 @@ impl BZip2DecoderBase {
                 self.block_size_100k = {
                     let b = Self::read_u8(reader, iter)
                         .map_err(|_| BZip2Error::UnexpectedEof)?;
-                    if b < 1 + HEADER_0 || b > 9 + HEADER_0 {
+                    if !(1 + HEADER_0..=9 + HEADER_0).contains(&b) {
                         return Err(magic_err);
                     }
                     usize::from(b - HEADER_0)
@@ -195,30 +209,15 @@ impl BZip2DecoderBase {
                 if data_block_crc != self.block_crc {
                     return Err(BZip2Error::DataError);
                 }
-                self.combined_crc = ((self.combined_crc << 1)
-                    | (self.combined_crc >> 31))
-                    ^ self.block_crc;
+                self.combined_crc =
+                    self.combined_crc.rotate_left(1) ^ self.block_crc;
                 self.block_crc_digest = IEEE_NORMAL.build_hasher();
             }
 
-            let block_head_byte = Self::read_u8(reader, iter)
+            let block_magic = Self::read_u48(reader, iter)
                 .map_err(|_| BZip2Error::UnexpectedEof)?;
 
-            if block_head_byte == 0x31 {
-                let _ = Self::check_u8(reader, iter, 0x41)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x59)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x26)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x53)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x59)
-                    .map_err(|_| BZip2Error::DataError)?;
+            if block_magic == BLOCK_MAGIC {
                 self.block_no += 1;
                 debug!("    [{}: huff+mtf ", self.block_no);
 
@@ -241,31 +240,31 @@ impl BZip2DecoderBase {
 
                 /*--- Receive the mapping table ---*/
                 let seq2unseq = {
-                    let mut in_use16 = BitArray::new(16);
-                    for i in 0..16 {
-                        in_use16.set(
-                            i,
-                            reader
-                                .read_bits::<u8, _>(1, iter)
-                                .map_err(|_| BZip2Error::UnexpectedEof)?
-                                .data()
-                                == 1,
-                        );
+                    let mut in_use16 = [0_u8; 16];
+                    if reader
+                        .read_bits_into(1, &mut in_use16, iter)
+                        .map_err(|_| BZip2Error::UnexpectedEof)?
+                        < 16
+                    {
+                        return Err(BZip2Error::UnexpectedEof);
                     }
 
                     let mut ret = Vec::with_capacity(256);
+                    let mut in_use = [0_u8; 16];
                     for (i, _) in
-                        in_use16.iter().enumerate().filter(|&(_, x)| x)
+                        in_use16.iter().enumerate().filter(|&(_, &x)| x == 1)
                     {
-                        for j in 0..16 {
-                            if reader
-                                .read_bits::<u8, _>(1, iter)
-                                .map_err(|_| BZip2Error::UnexpectedEof)?
-                                .data()
-                                == 1
-                            {
-                                ret.push(i * 16 + j)
-                            }
+                        if reader
+                            .read_bits_into(1, &mut in_use, iter)
+                            .map_err(|_| BZip2Error::UnexpectedEof)?
+                            < 16
+                        {
+                            return Err(BZip2Error::UnexpectedEof);
+                        }
+                        for (j, _) in
+                            in_use.iter().enumerate().filter(|&(_, &x)| x == 1)
+                        {
+                            ret.push(i * 16 + j)
                         }
                     }
                     ret
@@ -282,7 +281,7 @@ impl BZip2DecoderBase {
                     .read_bits(3, iter)
                     .map_err(|_| BZip2Error::UnexpectedEof)?
                     .data();
-                if n_groups < 2 || n_groups > 6 {
+                if !(2..=6).contains(&n_groups) {
                     return Err(BZip2Error::DataError);
                 }
                 let n_selectors = reader
@@ -298,18 +297,7 @@ impl BZip2DecoderBase {
                     let mut selector_mtf_dec =
                         MtfPositionDecoder::new(n_groups);
                     for _ in 0..n_selectors {
-                        let mut j = 0;
-                        while reader
-                            .read_bits::<u8, _>(1, iter)
-                            .map_err(|_| BZip2Error::UnexpectedEof)?
-                            .data()
-                            != 0
-                        {
-                            j += 1;
-                            if j >= n_groups {
-                                return Err(BZip2Error::DataError);
-                            }
-                        }
+                        let j = reader.read_unary(n_groups - 1, iter)?;
                         /*--- Undo the MTF values for the selectors. ---*/
                         selector.push(selector_mtf_dec.pop(j));
                     }
@@ -329,7 +317,7 @@ impl BZip2DecoderBase {
                             .data()
                             != 0
                         {
-                            if curr < 1 || curr > 20 {
+                            if !(1..=20).contains(&curr) {
                                 return Err(BZip2Error::DataError);
                             }
                             if reader
@@ -351,7 +339,8 @@ impl BZip2DecoderBase {
                 let mut code = Vec::with_capacity(n_groups);
                 for l in &len {
                     code.push(
-                        HuffmanDecoder::<Left>::new(l, 12)
+                        // 参照実装に合わせて不完全な符号も受け付ける
+                        HuffmanDecoder::<Left>::new_allow_incomplete(l, 12)
                             .map_err(|_| BZip2Error::DataError)?,
                     );
                 }
@@ -385,7 +374,7 @@ impl BZip2DecoderBase {
                         let next_sym = code[selector[group_no - 1]]
                             .dec(reader, iter)
                             .map_err(|_| BZip2Error::DataError)?
-                            .ok_or_else(|| BZip2Error::DataError)?;
+                            .ok_or(BZip2Error::DataError)?;
 
                         if es > 0
                             && next_sym != Self::RUN_A
@@ -483,21 +472,7 @@ impl BZip2DecoderBase {
                 self.result_wrote_count = 0;
 
                 return Ok(true);
-            } else if block_head_byte == 0x17 {
-                let _ = Self::check_u8(reader, iter, 0x72)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x45)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x38)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x50)
-                    .map_err(|_| BZip2Error::DataError)?;
-
-                let _ = Self::check_u8(reader, iter, 0x90)
-                    .map_err(|_| BZip2Error::DataError)?;
+            } else if block_magic == END_MAGIC {
                 let stored_combind_crc = Self::read_u32(reader, iter)
                     .map_err(|_| BZip2Error::UnexpectedEof)?;
                 debug!(
@@ -547,11 +522,15 @@ impl BitDecodeService for BZip2DecoderBase {
     type Error = BZip2Error;
     type Output = u8;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<u8>, Self::Error> {
+    ) -> Result<Option<u8>, Self::Error>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
         if self.result_count == self.result_wrote_count {
             if self.n_block_used == self.tt.len()
                 && !self.init_block(reader, iter)?
@@ -591,6 +570,18 @@ impl BZip2Decoder {
             inner: BitDecoderImpl::<BZip2DecoderBase>::new(),
         }
     }
+
+    /// Decodes a stream held in memory, reading the slice directly instead
+    /// of through a byte iterator. Returns the decoded data and the number
+    /// of input bytes the stream occupies.
+    ///
+    /// The decoder must not have been given any input through `next`.
+    pub fn decode_slice(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(Vec<u8>, usize), BZip2Error> {
+        self.inner.decode_slice(input)
+    }
 }
 
 impl Default for BZip2Decoder {
@@ -7,16 +7,16 @@
 
 use crate::action::Action;
 use crate::bitio::direction::left::Left;
-use crate::bitio::direction::Direction;
 use crate::bitio::small_bit_vec::SmallBitVec;
-use crate::bitio::writer::BitWriter;
+use crate::bitio::writer::{BitWrite, BitWriter};
 use crate::bitset::BitArray;
 use crate::bzip2::mtf::MtfPosition;
-use crate::bzip2::{HEADER_h, BZ_G_SIZE, HEADER_0, HEADER_B, HEADER_Z};
+use crate::bzip2::{
+    HEADER_h, BLOCK_MAGIC, BZ_G_SIZE, END_MAGIC, HEADER_0, HEADER_B, HEADER_Z,
+};
 use crate::core::cmp;
 use crate::core::fmt;
 use crate::core::hash::{BuildHasher, Hasher};
-use crate::core::u8;
 use crate::crc32::{BuiltinDigest, IEEE_NORMAL};
 use crate::error::CompressionError;
 use crate::huffman::cano_huff_table::make_tab_with_fn;
@@ -43,8 +43,6 @@ pub struct BZip2Encoder {
     queue: VecDeque<SmallBitVec<u32>>,
 
     finished: bool,
-    bitbuf: u32,
-    bitbuflen: usize,
     bit_finished: bool,
 }
 
@@ -56,7 +54,7 @@ impl Default for BZip2Encoder {
 
 impl BZip2Encoder {
     pub fn new(level: usize) -> Self {
-        if level < 1 || level > 9 {
+        if !(1..=9).contains(&level) {
             panic!("invalid level");
         }
 
@@ -65,8 +63,6 @@ impl BZip2Encoder {
             writer: BitWriter::new(),
             queue: VecDeque::new(),
             finished: false,
-            bitbuf: 0,
-            bitbuflen: 0,
             bit_finished: false,
         }
     }
@@ -122,8 +118,11 @@ impl Encoder for BZip2Encoder {
         iter: &mut I,
         action: Action,
     ) -> Option<Result<u8, CompressionError>> {
-        while self.bitbuflen == 0 {
-            let s = match self.next_bits(iter, action) {
+        loop {
+            if let Some(b) = self.writer.pop_byte() {
+                return Some(Ok(b));
+            }
+            match self.next_bits(iter, action) {
                 Some(Err(e)) => return Some(Err(e)),
                 Some(Ok(ref s)) => self.writer.write_bits(s),
                 None => {
@@ -134,9 +133,9 @@ impl Encoder for BZip2Encoder {
                         match action {
                             Action::Finish | Action::Flush => {
                                 self.bit_finished = true;
-                                match self.writer.flush::<u32>() {
-                                    Some((x, y)) if y != 0 => (x, y),
-                                    _ => return None,
+                                let _ = self.writer.pad_to_byte(false);
+                                if !self.writer.has_byte() {
+                                    return None;
                                 }
                             }
                             _ => {
@@ -145,16 +144,8 @@ impl Encoder for BZip2Encoder {
                         }
                     }
                 }
-            };
-            self.bitbuf = s.0;
-            self.bitbuflen = s.1;
+            }
         }
-
-        let ret = Left::convert(self.bitbuf, 32, 8) as u8;
-
-        self.bitbuf = Left::forward(self.bitbuf, 8);
-        self.bitbuflen -= 1;
-        Some(Ok(ret))
     }
 }
 
@@ -221,6 +212,11 @@ impl EncoderInner {
         self.write(queue, SmallBitVec::new(val, 32));
     }
 
+    fn write_u48(&mut self, queue: &mut VecDeque<SmallBitVec<u32>>, val: u64) {
+        self.write_u16(queue, (val >> 32) as u16);
+        self.write_u32(queue, val as u32);
+    }
+
     fn write_block(
         &mut self,
         is_final: bool,
@@ -234,8 +230,7 @@ impl EncoderInner {
         let nblock = self.block_buf.len();
         let block_crc = self.block_crc.finish() as u32;
 
-        self.combined_crc =
-            ((self.combined_crc << 1) | (self.combined_crc >> 31)) ^ block_crc;
+        self.combined_crc = self.combined_crc.rotate_left(1) ^ block_crc;
 
         debug!(
             "    block {}: crc = 0x{:08X}, combined CRC = 0x{:08X}, size = {}",
@@ -251,12 +246,7 @@ impl EncoderInner {
         }
 
         if nblock > 0 {
-            self.write_u8(queue, 0x31);
-            self.write_u8(queue, 0x41);
-            self.write_u8(queue, 0x59);
-            self.write_u8(queue, 0x26);
-            self.write_u8(queue, 0x53);
-            self.write_u8(queue, 0x59);
+            self.write_u48(queue, BLOCK_MAGIC);
 
             /*-- Now the block's CRC, so it is in a known place. --*/
             self.write_u32(queue, block_crc);
@@ -277,12 +267,7 @@ impl EncoderInner {
         }
         /*-- If this is the last block, add the stream trailer. --*/
         if is_final {
-            self.write_u8(queue, 0x17);
-            self.write_u8(queue, 0x72);
-            self.write_u8(queue, 0x45);
-            self.write_u8(queue, 0x38);
-            self.write_u8(queue, 0x50);
-            self.write_u8(queue, 0x90);
+            self.write_u48(queue, END_MAGIC);
             let comcrc = self.combined_crc;
             self.write_u32(queue, comcrc);
             debug!("    final combined CRC = 0x{:08X}   ", self.combined_crc);
@@ -321,7 +306,7 @@ impl EncoderInner {
         let mut mtf_freq = vec![0; in_use_count + 2];
         let mut mtf_count = 0;
 
-        for (i, &s) in bwt(&self.block_buf, usize::from(u8::max_value()))
+        for (i, &s) in bwt(&self.block_buf, usize::from(u8::MAX))
             .iter()
             .enumerate()
         {
@@ -1,8 +1,8 @@
 use crate::core::fmt;
-use crate::error::CompressionError;
+use crate::error::{fmt_offset, CompressionError, ErrorKind, WithOffset};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum BZip2Error {
+pub enum BZip2ErrorKind {
     DataError,
     DataErrorMagicFirst,
     DataErrorMagic,
@@ -10,9 +10,105 @@ pub enum BZip2Error {
     Unexpected,
 }
 
+/// Error of the bzip2 decoder.
+///
+/// Like `CompressionError`, it records the input position of the error
+/// and equality only compares the kind.
+#[derive(Debug, Clone, Copy)]
+pub struct BZip2Error {
+    kind: BZip2ErrorKind,
+    bit_offset: Option<usize>,
+}
+
+#[allow(non_upper_case_globals)]
+impl BZip2Error {
+    pub const DataError: Self = Self::new(BZip2ErrorKind::DataError);
+    pub const DataErrorMagicFirst: Self =
+        Self::new(BZip2ErrorKind::DataErrorMagicFirst);
+    pub const DataErrorMagic: Self = Self::new(BZip2ErrorKind::DataErrorMagic);
+    pub const UnexpectedEof: Self = Self::new(BZip2ErrorKind::UnexpectedEof);
+    pub const Unexpected: Self = Self::new(BZip2ErrorKind::Unexpected);
+}
+
+impl BZip2Error {
+    #[inline]
+    pub const fn new(kind: BZip2ErrorKind) -> Self {
+        Self {
+            kind,
+            bit_offset: None,
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self) -> BZip2ErrorKind {
+        self.kind
+    }
+
+    /// Returns the input position of the error in bits, if known.
+    #[inline]
+    pub fn bit_offset(&self) -> Option<usize> {
+        self.bit_offset
+    }
+
+    fn description_in(&self) -> &str {
+        match self.kind {
+            BZip2ErrorKind::DataError => "data integrity (CRC) error in data",
+            BZip2ErrorKind::DataErrorMagicFirst => {
+                "bad magic number (file not created by bzip2)"
+            }
+            BZip2ErrorKind::DataErrorMagic => {
+                "trailing garbage after EOF ignored"
+            }
+            BZip2ErrorKind::UnexpectedEof => "file ends unexpectedly",
+            BZip2ErrorKind::Unexpected => "unexpected error",
+        }
+    }
+}
+
+impl PartialEq for BZip2Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for BZip2Error {}
+
+impl From<BZip2ErrorKind> for BZip2Error {
+    fn from(kind: BZip2ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl From<CompressionError> for BZip2Error {
+    fn from(error: CompressionError) -> Self {
+        let kind = match error.kind() {
+            ErrorKind::DataError
+            | ErrorKind::ChecksumMismatch
+            | ErrorKind::HeaderChecksumMismatch => BZip2ErrorKind::DataError,
+            ErrorKind::UnexpectedEof => BZip2ErrorKind::UnexpectedEof,
+            ErrorKind::Unexpected
+            | ErrorKind::BufferTooSmall
+            | ErrorKind::Unsupported => BZip2ErrorKind::Unexpected,
+        };
+        Self {
+            kind,
+            bit_offset: error.bit_offset(),
+        }
+    }
+}
+
+impl WithOffset for BZip2Error {
+    #[inline]
+    fn with_offset(mut self, bit_offset: usize) -> Self {
+        self.bit_offset = self.bit_offset.or(Some(bit_offset));
+        self
+    }
+}
+
 impl fmt::Display for BZip2Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.description_in())
+        write!(f, "{}", self.description_in())?;
+        fmt_offset(f, self.bit_offset)
     }
 }
 
@@ -22,31 +118,21 @@ impl ::std::error::Error for BZip2Error {
         self.description_in()
     }
 
-    fn cause(&self) -> Option<&dyn (::std::error::Error)> {
+    fn cause(&self) -> Option<&dyn ::std::error::Error> {
         None
     }
 }
 
-impl BZip2Error {
-    fn description_in(&self) -> &str {
-        match *self {
-            BZip2Error::DataError => "data integrity (CRC) error in data",
-            BZip2Error::DataErrorMagicFirst => {
-                "bad magic number (file not created by bzip2)"
-            }
-            BZip2Error::DataErrorMagic => "trailing garbage after EOF ignored",
-            BZip2Error::UnexpectedEof => "file ends unexpectedly",
-            BZip2Error::Unexpected => "unexpected error",
-        }
-    }
-}
-
 impl From<BZip2Error> for CompressionError {
     fn from(error: BZip2Error) -> Self {
-        match error {
-            BZip2Error::UnexpectedEof => CompressionError::UnexpectedEof,
-            BZip2Error::Unexpected => CompressionError::Unexpected,
-            _ => CompressionError::DataError,
+        let kind = match error.kind {
+            BZip2ErrorKind::UnexpectedEof => ErrorKind::UnexpectedEof,
+            BZip2ErrorKind::Unexpected => ErrorKind::Unexpected,
+            _ => ErrorKind::DataError,
+        };
+        match error.bit_offset {
+            Some(o) => Self::new(kind).with_offset(o),
+            None => Self::new(kind),
         }
     }
 }
@@ -17,6 +17,9 @@ const HEADER_Z: u8 = 0x5a;
 const HEADER_h: u8 = 0x68;
 const HEADER_0: u8 = 0x30;
 
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+
 const BZ_G_SIZE: usize = 50;
 
 #[cfg(test)]
@@ -24,6 +27,9 @@ mod tests {
     use crate::action::Action;
     use crate::bzip2::decoder::BZip2Decoder;
     use crate::bzip2::encoder::BZip2Encoder;
+    use crate::bzip2::error::BZip2Error;
+    use crate::crc32::crc32;
+    use crate::error::CompressionError;
     use crate::traits::decoder::DecodeExt;
     use crate::traits::encoder::EncodeExt;
     #[cfg(not(feature = "std"))]
@@ -32,7 +38,6 @@ mod tests {
     #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
     use log::debug;
-    use simple_logger;
 
     fn setup() {
         let _ = simple_logger::init();
@@ -79,6 +84,10 @@ mod tests {
             debug!("{}", e);
         }
         assert!(ret2 == Ok(expected.to_vec()), "invalid unzip");
+        assert_eq!(
+            BZip2Decoder::new().decode_slice(actual),
+            Ok((expected.to_vec(), actual.len()))
+        );
     }
 
     #[test]
@@ -170,4 +179,44 @@ mod tests {
         }
         assert_eq!(decompressed, Ok(data));
     }
+
+    #[test]
+    fn test_error_offset() {
+        let mut input = b"a\n"
+            .to_vec()
+            .encode(&mut BZip2Encoder::new(9), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // ブロックの CRC を壊す
+        input[10] ^= 0xFF;
+        let err = input
+            .iter()
+            .cloned()
+            .decode(&mut BZip2Decoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        // CRC はブロックを読み終えた位置で照合される
+        assert_eq!(err, BZip2Error::DataError);
+        assert_eq!(err.bit_offset(), Some(228));
+        assert_eq!(CompressionError::from(err).bit_offset(), Some(228));
+    }
+
+    #[test]
+    fn test_golden_digests() {
+        // 符号表の構築が決定的で、出力が環境によらず同じであることを確かめる
+        let cases: [(&[u8], usize, u32); 4] = [
+            (include_bytes!("../../data/sample1.ref"), 32352, 0xf633_44ea),
+            (include_bytes!("../../data/sample2.ref"), 72618, 0xb966_1c10),
+            (include_bytes!("../../data/sample3.ref"), 234, 0xf494_725e),
+            (include_bytes!("../../data/sample4.ref"), 40488, 0x8a55_b30d),
+        ];
+        for &(input, len, digest) in &cases {
+            let encoded = input
+                .to_vec()
+                .encode(&mut BZip2Encoder::new(9), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!((encoded.len(), crc32(&encoded)), (len, digest));
+        }
+    }
 }
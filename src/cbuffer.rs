@@ -4,43 +4,56 @@
 //! This Source Code is subject to the terms of the Mozilla Public License
 //! version 2.0 (the "License"). You can obtain a copy of the License at
 //! <http://mozilla.org/MPL/2.0/>.
+#![cfg(feature = "lzss")]
 
+use crate::core::cmp;
 use crate::core::iter;
+use crate::core::marker::PhantomData;
 use crate::core::ops::{Index, IndexMut};
-use crate::core::{ptr, usize};
+use crate::core::ptr;
 #[cfg(not(feature = "std"))]
 use alloc::boxed::Box;
 #[cfg(not(feature = "std"))]
 use alloc::vec;
 
 #[derive(Clone, Hash, Debug)]
-pub(crate) struct CircularBuffer<T> {
-    data: Box<[T]>, // want to use RawVec but that is unstable
+pub(crate) struct CircularBuffer<T, S = Box<[T]>> {
+    data: S, // want to use RawVec but that is unstable
     pos: usize,
     is_first: bool,
+    phantom: PhantomData<T>,
 }
 
 impl<T: Default + Clone> CircularBuffer<T> {
     pub(crate) fn new(cap: usize) -> Self {
+        Self::with_storage(vec![T::default(); cap].into_boxed_slice())
+    }
+}
+
+impl<T, S: AsRef<[T]> + AsMut<[T]>> CircularBuffer<T, S> {
+    /// Uses all of `data` as the buffer, which starts out empty.
+    pub(crate) fn with_storage(data: S) -> Self {
         Self {
-            data: vec![T::default(); cap].into_boxed_slice(),
+            data,
             pos: 0,
             is_first: true,
+            phantom: PhantomData,
         }
     }
-}
 
-impl<T> CircularBuffer<T> {
     pub(crate) fn append(&mut self, data: &[T]) {
-        let len = self.data.len() - self.pos;
+        let len = self.data.as_ref().len() - self.pos;
         let count = data.len();
+        if count == 0 {
+            return;
+        }
 
         unsafe {
             let daddr = data.get_unchecked(0);
             if len >= data.len() {
                 ptr::copy_nonoverlapping(
                     daddr,
-                    self.data.get_unchecked_mut(self.pos),
+                    self.data.as_mut().get_unchecked_mut(self.pos),
                     count,
                 );
                 self.pos = if len != data.len() {
@@ -52,12 +65,12 @@ impl<T> CircularBuffer<T> {
             } else {
                 ptr::copy_nonoverlapping(
                     daddr,
-                    self.data.get_unchecked_mut(self.pos),
+                    self.data.as_mut().get_unchecked_mut(self.pos),
                     len,
                 );
                 ptr::copy_nonoverlapping(
                     data.get_unchecked(len),
-                    self.data.get_unchecked_mut(0),
+                    self.data.as_mut().get_unchecked_mut(0),
                     count - len,
                 );
                 self.pos = data.len() - len;
@@ -66,25 +79,32 @@ impl<T> CircularBuffer<T> {
         }
     }
 
+    /// Empties the buffer, keeping its storage.
+    #[cfg(any(feature = "deflate", feature = "lzhuf"))]
+    pub(crate) fn clear(&mut self) {
+        self.pos = 0;
+        self.is_first = true;
+    }
+
     #[inline]
     pub(crate) fn len(&self) -> usize {
         if self.is_first {
             self.pos
         } else {
-            self.data.len()
+            self.data.as_ref().len()
         }
     }
 
     #[inline]
     pub(crate) fn cap(&self) -> usize {
-        self.data.len()
+        self.data.as_ref().len()
     }
 
     #[cfg(any(feature = "lzss", test))]
     pub(crate) fn push(&mut self, data: T) {
-        self.data[self.pos] = data;
+        self.data.as_mut()[self.pos] = data;
         self.pos += 1;
-        if self.pos >= self.data.len() {
+        if self.pos >= self.data.as_ref().len() {
             self.pos = 0;
             self.is_first = false;
         }
@@ -104,27 +124,67 @@ impl<T> CircularBuffer<T> {
     fn inner_idx(&self, idx: usize) -> usize {
         debug_assert!(idx < self.len());
         if self.pos < idx + 1 {
-            self.pos + self.data.len() - idx - 1
+            self.pos + self.data.as_ref().len() - idx - 1
         } else {
             self.pos - idx - 1
         }
     }
 }
 
-impl<T> Index<usize> for CircularBuffer<T> {
+impl<T: Copy, S: AsRef<[T]> + AsMut<[T]>> CircularBuffer<T, S> {
+    /// Pushes `len` items copied from `pos` items back, where `pos == 0` is
+    /// the last item pushed. The source may overlap the items being pushed,
+    /// in which case it repeats with period `pos + 1`, as an LZ77 reference
+    /// does.
+    pub(crate) fn copy_back(&mut self, pos: usize, len: usize) {
+        debug_assert!(pos < self.len());
+        let dist = pos + 1;
+        let w = self.pos;
+        if w < dist || len > self.data.as_ref().len() - w {
+            // 複写元か複写先がバッファの端をまたぐ
+            for _ in 0..len {
+                let d = self[pos];
+                self.push(d);
+            }
+            return;
+        }
+        let s = w - dist;
+        if dist == 1 {
+            let d = self.data.as_ref()[s];
+            self.data.as_mut()[w..w + len].fill(d);
+        } else if dist >= len {
+            self.data.as_mut().copy_within(s..s + len, w);
+        } else {
+            // 重なる場合は複写済みの分も複写元に含めて倍々に広げる
+            let mut done = 0;
+            while done < len {
+                let n = cmp::min(len - done, dist + done);
+                self.data.as_mut().copy_within(s..s + n, w + done);
+                done += n;
+            }
+        }
+        self.pos = w + len;
+        if self.pos == self.data.as_ref().len() {
+            self.pos = 0;
+            self.is_first = false;
+        }
+    }
+}
+
+impl<T, S: AsRef<[T]> + AsMut<[T]>> Index<usize> for CircularBuffer<T, S> {
     type Output = T;
 
     #[inline]
     fn index(&self, idx: usize) -> &T {
-        &self.data[self.inner_idx(idx)]
+        &self.data.as_ref()[self.inner_idx(idx)]
     }
 }
 
-impl<T> IndexMut<usize> for CircularBuffer<T> {
+impl<T, S: AsRef<[T]> + AsMut<[T]>> IndexMut<usize> for CircularBuffer<T, S> {
     #[inline]
     fn index_mut(&mut self, idx: usize) -> &mut T {
         let idx = self.inner_idx(idx);
-        &mut self.data[idx]
+        &mut self.data.as_mut()[idx]
     }
 }
 
@@ -218,4 +278,44 @@ mod tests {
             assert_eq!(i, 16 - d);
         }
     }
+
+    fn copy_back_naive(buf: &mut CircularBuffer<u32>, pos: usize, len: usize) {
+        for _ in 0..len {
+            let d = buf[pos];
+            buf.push(d);
+        }
+    }
+
+    #[test]
+    fn copy_back() {
+        // 埋める, 倍々, 重ならない複写, 端をまたぐ場合を含める
+        let cases = [
+            (0, 5),
+            (0, 12),
+            (1, 7),
+            (2, 11),
+            (3, 4),
+            (6, 3),
+            (9, 9),
+            (14, 2),
+            (15, 13),
+        ];
+        for filled in 1..40 {
+            for &(pos, len) in cases.iter().filter(|c| c.0 < filled) {
+                let mut fast = CircularBuffer::new(16);
+                for d in 0..filled as u32 {
+                    fast.push(d);
+                }
+                let mut slow = fast.clone();
+                fast.copy_back(pos, len);
+                copy_back_naive(&mut slow, pos, len);
+                assert_eq!(fast.get_raw_pos(), slow.get_raw_pos());
+                assert_eq!(fast.len(), slow.len());
+                assert_eq!(
+                    fast.into_iter().collect::<Vec<_>>(),
+                    slow.into_iter().collect::<Vec<_>>()
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,111 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+static CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u16;
+        let mut j = 0;
+        while j < 8 {
+            value = if value & 1 == 1 {
+                (value >> 1) ^ 0xA001
+            } else {
+                value >> 1
+            };
+            j += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-16/ARC, the CRC-16 of LHA archives: the reversed polynomial
+/// 0xA001, starting from 0 without inversion.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::checksum::Crc16;
+///
+/// let mut crc = Crc16::new();
+/// crc.update(b"1234");
+/// crc.update(b"56789");
+/// assert_eq!(crc.finish(), 0xBB3D);
+/// assert_eq!(Crc16::checksum(b"123456789"), 0xBB3D);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Crc16 {
+    value: u16,
+}
+
+impl Crc16 {
+    pub const fn new() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Returns the CRC of `data`.
+    pub fn checksum(data: &[u8]) -> u16 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finish()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.value = data.iter().fold(self.value, |crc, &b| {
+            CRC16_TABLE[usize::from(crc as u8 ^ b)] ^ (crc >> 8)
+        });
+    }
+
+    /// Returns the CRC of the data so far. More data can still be added.
+    pub fn finish(&self) -> u16 {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_answers() {
+        for &(data, crc) in &[
+            (&b""[..], 0x0000),
+            (b"A", 0x30C0),
+            (b"abc", 0x9738),
+            (b"123456789", 0xBB3D),
+            (b"The quick brown fox jumps over the lazy dog", 0xFCDF),
+        ] {
+            assert_eq!(Crc16::checksum(data), crc);
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut data = [0_u8; 256];
+        for (i, d) in data.iter_mut().enumerate() {
+            *d = i as u8;
+        }
+        let whole = Crc16::checksum(&data);
+        for i in 0..data.len() {
+            let mut crc = Crc16::new();
+            crc.update(&data[..i]);
+            crc.update(&data[i..]);
+            assert_eq!(crc.finish(), whole);
+        }
+    }
+
+    #[test]
+    fn test_archive_member() {
+        // 最初の -lh0- の項目の CRC はヘッダの 31 バイト目から
+        let archive = include_bytes!("../data/level0.lzh");
+        let stored = u16::from_le_bytes([archive[31], archive[32]]);
+        assert_eq!(&archive[2..7], b"-lh0-");
+        assert_eq!(Crc16::checksum(&archive[33..72]), stored);
+    }
+}
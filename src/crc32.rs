@@ -4,7 +4,7 @@
 //! This Source Code is subject to the terms of the Mozilla Public License
 //! version 2.0 (the "License"). You can obtain a copy of the License at
 //! <http://mozilla.org/MPL/2.0/>.
-#![cfg(any(feature = "bzip2", feature = "gzip"))]
+#![cfg(any(feature = "bzip2", feature = "gzip", test))]
 
 use crate::core::borrow::Borrow;
 use crate::core::fmt;
@@ -14,7 +14,7 @@ use lazy_static::lazy_static;
 #[cfg(any(feature = "gzip", test))]
 lazy_static! {
     pub(crate) static ref IEEE_REVERSE_TABLE: [u32; 256] =
-        { make_table_reverse(0xEDB8_8320) };
+        make_table_reverse(0xEDB8_8320);
     pub(crate) static ref IEEE_REVERSE: DigestBuilder<&'static [u32; 256]> = {
         DigestBuilder {
             table: &*IEEE_REVERSE_TABLE,
@@ -24,10 +24,10 @@ lazy_static! {
     };
 }
 
-#[cfg(any(feature = "bzip2"))]
+#[cfg(feature = "bzip2")]
 lazy_static! {
     pub(crate) static ref IEEE_NORMAL_TABLE: [u32; 256] =
-        { make_table_normal(0x04C1_1DB7) };
+        make_table_normal(0x04C1_1DB7);
     pub(crate) static ref IEEE_NORMAL: DigestBuilder<&'static [u32; 256]> = {
         DigestBuilder {
             table: &*IEEE_NORMAL_TABLE,
@@ -54,7 +54,7 @@ fn make_table_reverse(poly: u32) -> [u32; 256] {
     table
 }
 
-#[cfg(any(feature = "bzip2"))]
+#[cfg(feature = "bzip2")]
 fn make_table_normal(poly: u32) -> [u32; 256] {
     let mut table = [0u32; 256];
     for (i, item) in table.iter_mut().enumerate() {
@@ -77,7 +77,7 @@ fn update_reverse(value: u32, table: &[u32; 256], byte: u8) -> u32 {
     table[((value as u8) ^ byte) as usize] ^ (value >> 8)
 }
 
-#[cfg(any(feature = "bzip2"))]
+#[cfg(feature = "bzip2")]
 #[inline]
 fn update_normal(value: u32, table: &[u32; 256], byte: u8) -> u32 {
     table[(((value >> 24) as u8) ^ byte) as usize] ^ (value << 8)
@@ -85,7 +85,7 @@ fn update_normal(value: u32, table: &[u32; 256], byte: u8) -> u32 {
 
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum PolynomialRepresentation {
-    #[cfg(any(feature = "bzip2"))]
+    #[cfg(feature = "bzip2")]
     Normal,
     #[cfg(any(feature = "gzip", test))]
     Reverse,
@@ -137,7 +137,7 @@ impl<T: Borrow<[u32; 256]>> Hasher for Digest<T> {
     #[inline]
     fn write_u8(&mut self, i: u8) {
         self.value = match self.poly_repr {
-            #[cfg(any(feature = "bzip2"))]
+            #[cfg(feature = "bzip2")]
             PolynomialRepresentation::Normal => {
                 update_normal(self.value, self.table.borrow(), i)
             }
@@ -149,8 +149,18 @@ impl<T: Borrow<[u32; 256]>> Hasher for Digest<T> {
     }
 }
 
+#[cfg(any(feature = "bzip2", feature = "gzip"))]
 pub(crate) type BuiltinDigest = Digest<&'static [u32; 256]>;
 
+/// Returns the CRC-32 of `data`, for checking encoder outputs against
+/// known values in tests.
+#[cfg(all(test, any(feature = "bzip2", feature = "gzip", feature = "lzhuf")))]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = IEEE_REVERSE.build_hasher();
+    hasher.write(data);
+    hasher.finish() as u32
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
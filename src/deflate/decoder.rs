@@ -7,9 +7,8 @@
 
 use crate::bitio::direction::right::Right;
 use crate::bitio::reader::{BitRead, BitReader};
-use crate::deflate::{
-    fix_offset_table, fix_symbol_table, gen_len_tab, gen_off_tab, CodeTable,
-};
+use crate::core::cmp;
+use crate::deflate::{fixed_dec, FIX_OFFSET, FIX_SYMBOL, LEN_TAB, OFF_TAB};
 use crate::error::CompressionError;
 use crate::huffman::decoder::HuffmanDecoder;
 use crate::lzss::decoder::LzssDecoder;
@@ -18,6 +17,8 @@ use crate::traits::decoder::{
     BitDecodeService, BitDecoder, BitDecoderImpl, DecodeIterator, Decoder,
 };
 #[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
@@ -26,6 +27,7 @@ use alloc::vec::Vec;
 #[derive(Debug)]
 enum DeflateHuffmanDecoder {
     HuffmanDecoder(HuffmanDecoder<Right>, bool),
+    Fixed(&'static [(u16, u8)], bool),
     NoComp(u32),
 }
 
@@ -36,29 +38,27 @@ impl DeflateHuffmanDecoder {
         iter: &mut I,
     ) -> Result<Option<u16>, CompressionError> {
         match *self {
+            DeflateHuffmanDecoder::HuffmanDecoder(_, true)
+            | DeflateHuffmanDecoder::Fixed(_, true) => Ok(None),
             DeflateHuffmanDecoder::HuffmanDecoder(ref mut rhd, ref mut end) => {
-                if *end {
-                    Ok(None)
-                } else {
-                    rhd.dec(reader, iter)
-                        .map_err(|_| CompressionError::DataError)
-                        .and_then(|x| match x {
-                            Some(256) => {
-                                *end = true;
-                                Ok(None)
-                            }
-                            None => Err(CompressionError::UnexpectedEof),
-                            x => Ok(x),
-                        })
-                }
+                let x = rhd
+                    .dec(reader, iter)
+                    .map_err(|_| CompressionError::DataError)?;
+                Self::check_end(x, end)
+            }
+            DeflateHuffmanDecoder::Fixed(lookup, ref mut end) => {
+                let x = fixed_dec(lookup, reader, iter)
+                    .map_err(|_| CompressionError::DataError)?;
+                Self::check_end(x, end)
             }
             DeflateHuffmanDecoder::NoComp(ref mut block_size) => {
                 if *block_size > 0 {
                     *block_size -= 1;
-                    reader
-                        .read_bits::<u16, _>(8, iter)
-                        .map(|x| Some(x.data()))
-                        .map_err(|_| CompressionError::UnexpectedEof)
+                    let mut buf = [0; 1];
+                    match reader.read_bytes(&mut buf, iter) {
+                        Ok(1) => Ok(Some(u16::from(buf[0]))),
+                        _ => Err(CompressionError::UnexpectedEof),
+                    }
                 } else {
                     Ok(None)
                 }
@@ -66,9 +66,24 @@ impl DeflateHuffmanDecoder {
         }
     }
 
+    fn check_end(
+        x: Option<u16>,
+        end: &mut bool,
+    ) -> Result<Option<u16>, CompressionError> {
+        match x {
+            Some(256) => {
+                *end = true;
+                Ok(None)
+            }
+            None => Err(CompressionError::UnexpectedEof),
+            x => Ok(x),
+        }
+    }
+
     pub(crate) fn end(&self) -> bool {
         match *self {
-            DeflateHuffmanDecoder::HuffmanDecoder(_, end) => end,
+            DeflateHuffmanDecoder::HuffmanDecoder(_, end)
+            | DeflateHuffmanDecoder::Fixed(_, end) => end,
             DeflateHuffmanDecoder::NoComp(block_size) => block_size == 0,
         }
     }
@@ -79,8 +94,8 @@ struct DeflaterInner {
     symbol_decoder: Option<DeflateHuffmanDecoder>,
     offset_decoder: Option<DeflateHuffmanDecoder>,
     is_final: bool,
-    len_tab: CodeTable,
-    offset_tab: CodeTable,
+    // Deflate64 の長さ符号 285 と距離符号 30, 31 を読む
+    deflate64: bool,
 }
 
 impl DeflaterInner {
@@ -91,9 +106,42 @@ impl DeflaterInner {
             symbol_decoder: None,
             offset_decoder: None,
             is_final: false,
-            len_tab: gen_len_tab(),
-            offset_tab: gen_off_tab(),
+            deflate64: false,
+        }
+    }
+
+    fn deflate64() -> Self {
+        Self {
+            deflate64: true,
+            ..Self::new()
+        }
+    }
+
+    /// Reads up to `buf.len()` bytes of the stored block being decoded
+    /// straight from the input, and returns how many were read: 0 when no
+    /// stored block has bytes left.
+    fn read_stored<R: BitRead, I: Iterator<Item = u8>>(
+        &mut self,
+        buf: &mut [u8],
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<usize, CompressionError> {
+        let block_size = match self.symbol_decoder {
+            Some(DeflateHuffmanDecoder::NoComp(ref mut n)) if *n > 0 => n,
+            _ => return Ok(0),
+        };
+        let len = cmp::min(*block_size as usize, buf.len());
+        // LEN と NLEN の後なので境界にあるが、バイト単位で読めるよう揃える
+        let _ = reader.skip_to_next_byte();
+        if reader
+            .read_bytes(&mut buf[..len], iter)
+            .map_err(|_| CompressionError::UnexpectedEof)?
+            < len
+        {
+            return Err(CompressionError::UnexpectedEof);
         }
+        *block_size -= len as u32;
+        Ok(len)
     }
 
     fn dec_len_tree<R: BitRead, I: Iterator<Item = u8>>(
@@ -105,70 +153,122 @@ impl DeflaterInner {
         let len_index = [
             16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
         ];
+        let hclen = hclen as usize;
+        let mut lens = [0_u8; 19];
+        if reader
+            .read_bits_into(3, &mut lens[..hclen], iter)
+            .map_err(|_| CompressionError::UnexpectedEof)?
+            < hclen
+        {
+            return Err(CompressionError::UnexpectedEof);
+        }
         let mut len_list = vec![0; 19];
-        for &i in len_index.iter().take(hclen as usize) {
-            len_list[i] = *reader
-                .read_bits(3, iter)
-                .map_err(|_| CompressionError::UnexpectedEof)?
-                .data_ref();
+        for (&i, &l) in len_index.iter().zip(&lens[..hclen]) {
+            len_list[i] = l;
         }
-        Ok(DeflateHuffmanDecoder::HuffmanDecoder(
-            HuffmanDecoder::new(&len_list, Self::SEARCH_TAB_LEN)
-                .map_err(|_| CompressionError::DataError)?,
-            false,
-        ))
+        // 符号長の符号は、記号が 1 つだけでも完全でなければならない
+        if len_list.iter().filter(|&&l| l != 0).count() < 2 {
+            return Err(Self::invalid("invalid code lengths set"));
+        }
+        Self::build_tree(&len_list, false, "invalid code lengths set")
     }
 
-    fn dec_huff_tree<R: BitRead, I: Iterator<Item = u8>>(
+    /// Reads `len` code lengths, those of the literal/length code and of
+    /// the distance code in one sequence; repeats may run from one code
+    /// into the other (RFC 1951 3.2.7).
+    fn dec_lens<R: BitRead, I: Iterator<Item = u8>>(
         &mut self,
         len_decoder: &mut DeflateHuffmanDecoder,
         len: usize,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<DeflateHuffmanDecoder, CompressionError> {
+    ) -> Result<Vec<u8>, CompressionError> {
         let mut ll = Vec::new();
         while ll.len() < len {
             match len_decoder.dec(reader, iter)? {
                 None => return Err(CompressionError::UnexpectedEof),
                 Some(16) => {
-                    let last = ll.iter().last().map_or_else(
-                        || Err(CompressionError::UnexpectedEof),
-                        |&l| Ok(l),
-                    )?;
-                    for _ in 0..(reader
-                        .read_bits::<u8, _>(2, iter)
-                        .map_err(|_| CompressionError::UnexpectedEof)?
-                        .data()
-                        + 3)
-                    {
-                        ll.push(last);
-                    }
+                    // 繰り返す前の符号長がない
+                    let last = *ll.last().ok_or_else(|| {
+                        Self::invalid("invalid bit length repeat")
+                    })?;
+                    let count = Self::read_exact(2, reader, iter)? as usize + 3;
+                    ll.resize(ll.len() + count, last);
                 }
                 Some(17) => {
-                    for _ in 0..(3 + reader
-                        .read_bits::<u8, _>(3, iter)
-                        .map_err(|_| CompressionError::UnexpectedEof)?
-                        .data())
-                    {
-                        ll.push(0);
-                    }
+                    let count = 3 + Self::read_exact(3, reader, iter)? as usize;
+                    ll.resize(ll.len() + count, 0);
                 }
                 Some(18) => {
-                    for _ in 0..(11
-                        + reader
-                            .read_bits::<u8, _>(7, iter)
-                            .map_err(|_| CompressionError::UnexpectedEof)?
-                            .data())
-                    {
-                        ll.push(0);
-                    }
+                    let count =
+                        11 + Self::read_exact(7, reader, iter)? as usize;
+                    ll.resize(ll.len() + count, 0);
                 }
                 Some(n) => ll.push(n as u8),
             }
         }
+        // 繰り返しが符号長の数を越えた
+        if ll.len() > len {
+            return Err(Self::invalid("invalid bit length repeat"));
+        }
+        Ok(ll)
+    }
+
+    fn read_ext<R: BitRead, I: Iterator<Item = u8>>(
+        extbits: usize,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<u16, CompressionError> {
+        if extbits == 0 {
+            return Ok(0);
+        }
+        Ok(Self::read_exact(extbits, reader, iter)? as u16)
+    }
+
+    /// Reads `len` bits, which the input must have all of.
+    fn read_exact<R: BitRead, I: Iterator<Item = u8>>(
+        len: usize,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<u32, CompressionError> {
+        let bits = reader
+            .read_bits::<u32, _>(len, iter)
+            .map_err(|_| CompressionError::UnexpectedEof)?;
+        if bits.len() < len {
+            return Err(CompressionError::UnexpectedEof);
+        }
+        Ok(bits.data())
+    }
+
+    fn invalid(reason: &'static str) -> CompressionError {
+        CompressionError::DataError.with_reason(reason)
+    }
+
+    /// Gives the reason to a `DataError` of the codes, and passes other
+    /// errors, such as the end of the input, as they are.
+    fn invalid_code(
+        e: CompressionError,
+        reason: &'static str,
+    ) -> CompressionError {
+        if e == CompressionError::DataError {
+            e.with_reason(reason)
+        } else {
+            e
+        }
+    }
+
+    fn build_tree(
+        lens: &[u8],
+        allow_incomplete: bool,
+        reason: &'static str,
+    ) -> Result<DeflateHuffmanDecoder, CompressionError> {
+        let decoder = if allow_incomplete {
+            HuffmanDecoder::new_allow_incomplete(lens, Self::SEARCH_TAB_LEN)
+        } else {
+            HuffmanDecoder::new(lens, Self::SEARCH_TAB_LEN)
+        };
         Ok(DeflateHuffmanDecoder::HuffmanDecoder(
-            HuffmanDecoder::new(&ll, Self::SEARCH_TAB_LEN)
-                .map_err(|_| CompressionError::DataError)?,
+            decoder.map_err(|_| Self::invalid(reason))?,
             false,
         ))
     }
@@ -178,29 +278,20 @@ impl DeflaterInner {
         reader: &mut R,
         iter: &mut I,
     ) -> Result<(), CompressionError> {
-        self.is_final = reader
-            .read_bits::<u8, _>(1, iter)
-            .map_err(|_| CompressionError::UnexpectedEof)?
-            .data()
-            == 1;
-        match reader
-            .read_bits::<u8, _>(2, iter)
-            .map_err(|_| CompressionError::UnexpectedEof)?
-            .data()
-        {
+        self.is_final = Self::read_exact(1, reader, iter)? == 1;
+        match Self::read_exact(2, reader, iter)? {
             // 無圧縮
             0 => {
                 let _ = reader.skip_to_next_byte();
-                let block_len = reader
-                    .read_bits(16, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data();
-                let block_len_checksum = reader
-                    .read_bits::<u32, _>(16, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data();
+                let block_len = Self::read_exact(16, reader, iter)?;
+                let block_len_checksum = Self::read_exact(16, reader, iter)?;
+                // NLEN は LEN の 1 の補数
                 if (block_len ^ block_len_checksum) != 0xFFFF {
-                    return Err(CompressionError::DataError);
+                    return Err(Self::invalid("invalid stored block lengths")
+                        .with_checksums(
+                            block_len_checksum,
+                            !block_len & 0xFFFF,
+                        ));
                 }
                 self.symbol_decoder =
                     Some(DeflateHuffmanDecoder::NoComp(block_len));
@@ -208,61 +299,59 @@ impl DeflaterInner {
             }
             // 固定ハフマン
             1 => {
-                self.symbol_decoder =
-                    Some(DeflateHuffmanDecoder::HuffmanDecoder(
-                        HuffmanDecoder::new(
-                            &fix_symbol_table(),
-                            Self::SEARCH_TAB_LEN,
-                        )
-                        .map_err(|_| CompressionError::DataError)?,
-                        false,
-                    ));
-                self.offset_decoder =
-                    Some(DeflateHuffmanDecoder::HuffmanDecoder(
-                        HuffmanDecoder::new(
-                            fix_offset_table(),
-                            Self::SEARCH_TAB_LEN,
-                        )
-                        .map_err(|_| CompressionError::DataError)?,
-                        false,
-                    ));
+                self.symbol_decoder = Some(DeflateHuffmanDecoder::Fixed(
+                    FIX_SYMBOL.lookup(),
+                    false,
+                ));
+                self.offset_decoder = Some(DeflateHuffmanDecoder::Fixed(
+                    FIX_OFFSET.lookup(),
+                    false,
+                ));
             }
             // カスタムハフマン
             2 => {
                 // リテラル/長さ符号の個数
-                let hlit = reader
-                    .read_bits::<u16, _>(5, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data()
-                    + 257;
+                let hlit = Self::read_exact(5, reader, iter)? as u16 + 257;
                 // 距離符号の個数
-                let hdist = reader
-                    .read_bits::<u16, _>(5, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data()
-                    + 1;
+                let hdist = Self::read_exact(5, reader, iter)? as u16 + 1;
                 // 長さ符号の個数
-                let hclen = reader
-                    .read_bits::<u32, _>(4, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data()
-                    + 4;
+                let hclen = Self::read_exact(4, reader, iter)? + 4;
+                // 長さ符号 286, 287 は使われず、距離符号 30, 31 は
+                // Deflate64 だけが使う
+                let max_hdist = if self.deflate64 { 32 } else { 30 };
+                if hlit > 286 || hdist > max_hdist {
+                    return Err(Self::invalid(
+                        "too many length or distance symbols",
+                    ));
+                }
                 let mut lt = self.dec_len_tree(hclen, reader, iter)?;
-                self.symbol_decoder = Some(self.dec_huff_tree(
+                let hlit = usize::from(hlit);
+                let lens = self.dec_lens(
                     &mut lt,
-                    hlit as usize,
+                    hlit + usize::from(hdist),
                     reader,
                     iter,
+                )?;
+                if lens[256] == 0 {
+                    return Err(Self::invalid(
+                        "invalid code -- missing end-of-block",
+                    ));
+                }
+                self.symbol_decoder = Some(Self::build_tree(
+                    &lens[..hlit],
+                    false,
+                    "invalid literal/lengths set",
                 )?);
-                self.offset_decoder = Some(self.dec_huff_tree(
-                    &mut lt,
-                    hdist as usize,
-                    reader,
-                    iter,
+                // zlib と同様に、不完全な距離符号は長さ 1 の符号 1 つか、
+                // 距離を使わないブロックの符号がないものだけを受け付ける
+                let dist = &lens[hlit..];
+                self.offset_decoder = Some(Self::build_tree(
+                    dist,
+                    dist.iter().all(|&l| l == 0),
+                    "invalid distances set",
                 )?);
             }
-            // ありえない
-            _ => unreachable!(),
+            _ => return Err(Self::invalid("invalid block type")),
         }
         Ok(())
     }
@@ -273,11 +362,15 @@ impl BitDecodeService for DeflaterInner {
     type Error = CompressionError;
     type Output = LzssCode;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<LzssCode>, CompressionError> {
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
         loop {
             if self
                 .symbol_decoder
@@ -288,44 +381,66 @@ impl BitDecodeService for DeflaterInner {
                     return Ok(None);
                 }
                 self.init_block(reader, iter)?;
-            } else if let Some(sym) =
-                self.symbol_decoder.as_mut().unwrap().dec(reader, iter)?
+            } else if let Some(sym) = self
+                .symbol_decoder
+                .as_mut()
+                .unwrap()
+                .dec(reader, iter)
+                .map_err(|e| {
+                    Self::invalid_code(e, "invalid literal/length code")
+                })?
             {
                 if sym <= 255 {
                     return Ok(Some(LzssCode::Symbol(sym as u8)));
                 } else {
                     let len_index = (sym - 257) as usize;
-                    let extbits = (&self.len_tab).ext_bits(len_index);
-                    let len = (self.len_tab.convert_back(
-                        len_index,
-                        if extbits != 0 {
-                            reader
-                                .read_bits(extbits, iter)
-                                .map_err(|_| CompressionError::UnexpectedEof)?
-                                .data()
-                        } else {
-                            0
-                        },
-                    ) + 3) as usize;
+                    // 固定ハフマン符号の 286, 287 は使われない
+                    if len_index >= 29 {
+                        return Err(Self::invalid(
+                            "invalid literal/length code",
+                        ));
+                    }
+                    // Deflate64 の長さ符号 285 は 3 に 16 ビットを足す
+                    let (base, extbits) = if self.deflate64 && len_index == 28 {
+                        (0, 16)
+                    } else {
+                        (
+                            LEN_TAB.convert_back(len_index, 0),
+                            LEN_TAB.ext_bits(len_index),
+                        )
+                    };
+                    let len = usize::from(base)
+                        + usize::from(Self::read_ext(extbits, reader, iter)?)
+                        + 3;
                     let off_index = self
                         .offset_decoder
                         .as_mut()
                         .unwrap()
-                        .dec(reader, iter)?
-                        .ok_or_else(|| CompressionError::UnexpectedEof)?
+                        .dec(reader, iter)
+                        .map_err(|e| {
+                            Self::invalid_code(e, "invalid distance code")
+                        })?
+                        .ok_or(CompressionError::UnexpectedEof)?
                         as usize;
-                    let off_extbits = (&self.offset_tab).ext_bits(off_index);
-                    let pos = self.offset_tab.convert_back(
-                        off_index,
-                        if off_extbits != 0 {
-                            reader
-                                .read_bits(off_extbits, iter)
-                                .map_err(|_| CompressionError::UnexpectedEof)?
-                                .data()
-                        } else {
-                            0
-                        },
-                    ) as usize;
+                    // 距離符号 30, 31 は Deflate64 で 32769 以上を表す
+                    let (base, off_extbits) = match off_index {
+                        30 | 31 if self.deflate64 => {
+                            (((off_index - 28) as u16) << 14, 14)
+                        }
+                        30 | 31 => {
+                            return Err(Self::invalid("invalid distance code"))
+                        }
+                        _ => (
+                            OFF_TAB.convert_back(off_index, 0),
+                            OFF_TAB.ext_bits(off_index),
+                        ),
+                    };
+                    let pos = usize::from(base)
+                        + usize::from(Self::read_ext(
+                            off_extbits,
+                            reader,
+                            iter,
+                        )?);
                     return Ok(Some(LzssCode::Reference { len, pos }));
                 }
             }
@@ -333,10 +448,15 @@ impl BitDecodeService for DeflaterInner {
     }
 }
 
+// 無圧縮ブロックを一度に読む大きさで、窓より小さい
+const STORED_CHUNK_SIZE: usize = 0x1000;
+
 #[derive(Debug)]
-pub(crate) struct DeflaterBase {
+pub(crate) struct DeflaterBase<B = Box<[u8]>> {
     inner: DeflaterInner,
-    lzss_decoder: LzssDecoder,
+    lzss_decoder: LzssDecoder<B>,
+    // 入力を受け取り始めたら、辞書はもう置けない
+    started: bool,
 }
 
 impl Default for DeflaterBase {
@@ -352,40 +472,90 @@ impl DeflaterBase {
         Self {
             lzss_decoder: LzssDecoder::new(Self::MAX_BLOCK_SIZE),
             inner: DeflaterInner::new(),
+            started: false,
         }
     }
 
     pub(crate) fn with_dict(dict: &[u8]) -> Self {
+        let mut ret = Self::new();
+        ret.lzss_decoder.set_dictionary(dict);
+        ret
+    }
+
+    // 距離は 65536 まで届くが、窓は元から 64 KiB ある
+    fn deflate64() -> Self {
         Self {
-            lzss_decoder: LzssDecoder::with_dict(Self::MAX_BLOCK_SIZE, dict),
+            inner: DeflaterInner::deflate64(),
+            ..Self::new()
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> DeflaterBase<B> {
+    // 距離は 32768 までなので、それだけあればよい
+    const WINDOW_SIZE: usize = 0x8000;
+
+    pub(crate) fn with_buffer(buf: B) -> Result<Self, CompressionError> {
+        Ok(Self {
+            lzss_decoder: LzssDecoder::with_buffer(buf, Self::WINDOW_SIZE)?,
             inner: DeflaterInner::new(),
+            started: false,
+        })
+    }
+
+    fn set_dictionary(&mut self, dict: &[u8]) -> Result<(), CompressionError> {
+        if self.started {
+            return Err(CompressionError::Unsupported);
         }
+        self.lzss_decoder.set_dictionary(dict);
+        Ok(())
     }
 }
 
-impl BitDecodeService for DeflaterBase {
+impl<B: AsRef<[u8]> + AsMut<[u8]>> BitDecodeService for DeflaterBase<B> {
     type Direction = Right;
     type Error = CompressionError;
     type Output = u8;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<u8>, Self::Error> {
+    ) -> Result<Option<u8>, Self::Error>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        self.started = true;
+        // 無圧縮ブロックは符号を介さず、まとめて窓に読み込む
+        if !self.lzss_decoder.is_pending() {
+            let mut chunk = [0_u8; STORED_CHUNK_SIZE];
+            let len = self.inner.read_stored(&mut chunk, reader, iter)?;
+            if len > 0 {
+                self.lzss_decoder.push_literals(&chunk[..len]);
+            }
+        }
         let mut bd = BitDecoder::<DeflaterInner, _, _>::with_service(
             &mut self.inner,
             reader,
         );
-        self.lzss_decoder
-            .next(&mut DecodeIterator::<I, _, _>::new(iter, &mut bd).flatten())
-            .transpose()
+        let mut err = None;
+        let ret = self.lzss_decoder.next(
+            &mut DecodeIterator::<I, _, _>::new(iter, &mut bd)
+                .scan(&mut err, |err, x| x.map_err(|e| **err = Some(e)).ok()),
+        );
+        match err {
+            Some(e) => Err(e),
+            None => ret.transpose(),
+        }
     }
 }
 
+/// Decoder of a raw deflate stream (RFC 1951), with no header or checksum.
+/// The zlib and gzip decoders read their streams with the same core.
 #[derive(Debug)]
-pub struct Deflater {
-    inner: BitDecoderImpl<DeflaterBase>,
+pub struct Deflater<B: AsRef<[u8]> + AsMut<[u8]> = Box<[u8]>> {
+    inner: BitDecoderImpl<DeflaterBase<B>>,
 }
 
 impl Deflater {
@@ -403,6 +573,76 @@ impl Deflater {
             ),
         }
     }
+
+    /// Creates a decoder of Deflate64, the enhanced deflate of ZIP method
+    /// 9. It differs from deflate in the 64 KiB window, the distance
+    /// codes 30 and 31, which reach 32769 and above with 14 extra bits,
+    /// and the length code 285, which stands for 3 to 65538 with 16 extra
+    /// bits instead of 258.
+    pub fn deflate64() -> Self {
+        Self {
+            inner: BitDecoderImpl::<DeflaterBase>::with_service(
+                DeflaterBase::deflate64(),
+                BitReader::new(),
+            ),
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Deflater<B> {
+    /// Creates a decoder that keeps the 32 KiB window in `buf` instead of
+    /// allocating it. Returns `BufferTooSmall` if `buf` is shorter than
+    /// 32768 bytes.
+    ///
+    /// With `decode_slice_into`, stored and fixed Huffman blocks then
+    /// decode without touching the heap. Dynamic Huffman blocks still
+    /// allocate the tables of their codes.
+    pub fn with_buffer(buf: B) -> Result<Self, CompressionError> {
+        Ok(Self {
+            inner: BitDecoderImpl::<DeflaterBase<B>>::with_service(
+                DeflaterBase::with_buffer(buf)?,
+                BitReader::new(),
+            ),
+        })
+    }
+
+    /// Puts `dict` in the window before the output, for a stream encoded
+    /// with the same bytes given to `Inflater::set_dictionary`. `dict` is
+    /// not part of the output.
+    ///
+    /// Returns `Unsupported` once decoding has begun.
+    pub fn set_dictionary(
+        &mut self,
+        dict: &[u8],
+    ) -> Result<(), CompressionError> {
+        self.inner.service_mut().set_dictionary(dict)
+    }
+
+    /// Decodes a stream held in memory, reading the slice directly instead
+    /// of through a byte iterator. Returns the decoded data and the number
+    /// of input bytes the stream occupies.
+    ///
+    /// The decoder must not have been given any input through `next`.
+    pub fn decode_slice(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(Vec<u8>, usize), CompressionError> {
+        self.inner.decode_slice(input)
+    }
+
+    /// Like `decode_slice`, but writes the decoded data into `output`
+    /// instead of a new `Vec`. Returns the number of bytes written and of
+    /// input bytes the stream occupies, or `BufferTooSmall` if the data
+    /// does not fit in `output`.
+    ///
+    /// The decoder must not have been given any input through `next`.
+    pub fn decode_slice_into(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize), CompressionError> {
+        self.inner.decode_slice_into(input, output)
+    }
 }
 
 impl Default for Deflater {
@@ -413,7 +653,56 @@ impl Default for Deflater {
     }
 }
 
-impl Decoder for Deflater {
+/// Deflate decoder that outputs the `LzssCode`s of the stream instead of
+/// the bytes they stand for.
+///
+/// A `Reference` has `len` between 3 and 258 and `pos` below 32768, or
+/// with `deflate64` `len` up to 65538 and `pos` below 65536. The codes are
+/// not checked against the output; an `LzssDecoder` of the window size
+/// rejects references before its start.
+#[derive(Debug)]
+pub struct DeflateCodeDecoder {
+    inner: BitDecoderImpl<DeflaterInner>,
+}
+
+impl DeflateCodeDecoder {
+    pub fn new() -> Self {
+        Self {
+            inner: BitDecoderImpl::<DeflaterInner>::from(DeflaterInner::new()),
+        }
+    }
+
+    /// Creates a decoder of the codes of a Deflate64 stream, as
+    /// `Deflater::deflate64` reads.
+    pub fn deflate64() -> Self {
+        Self {
+            inner: BitDecoderImpl::<DeflaterInner>::from(
+                DeflaterInner::deflate64(),
+            ),
+        }
+    }
+}
+
+impl Default for DeflateCodeDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for DeflateCodeDecoder {
+    type Input = u8;
+    type Output = LzssCode;
+    type Error = CompressionError;
+
+    fn next<I: Iterator<Item = Self::Input>>(
+        &mut self,
+        iter: &mut I,
+    ) -> Option<Result<Self::Output, Self::Error>> {
+        self.inner.next(iter)
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Decoder for Deflater<B> {
     type Input = u8;
     type Output = u8;
     type Error = CompressionError;
@@ -7,18 +7,15 @@
 
 use crate::action::Action;
 use crate::bitio::direction::right::Right;
-use crate::bitio::direction::Direction;
 use crate::bitio::small_bit_vec::SmallBitVec;
-use crate::bitio::writer::BitWriter;
+use crate::bitio::writer::{BitWrite, BitWriter};
 use crate::cbuffer::CircularBuffer;
 use crate::core::cmp::{self, Ordering};
-use crate::deflate::{
-    fix_offset_table, fix_symbol_table, gen_len_tab, gen_off_tab, CodeTable,
-};
+use crate::deflate::{FIX_OFFSET, FIX_SYMBOL, LEN_TAB, OFF_TAB};
 use crate::error::CompressionError;
-use crate::huffman::cano_huff_table::make_table;
 use crate::huffman::encoder::HuffmanEncoder;
-use crate::lzss::encoder::LzssEncoder;
+use crate::huffman::FrequencyCounter;
+use crate::lzss::encoder::{CodePrices, LzssEncoder, MatchParams, ParseMode};
 use crate::lzss::LzssCode;
 use crate::traits::encoder::Encoder;
 #[cfg(not(feature = "std"))]
@@ -60,17 +57,13 @@ enum DeflateLzssCode {
     },
 }
 
-impl DeflateLzssCode {
-    pub(crate) fn from_with_codetab(
-        source: &LzssCode,
-        len_tab: &CodeTable,
-        offset_tab: &CodeTable,
-    ) -> Self {
+impl From<&LzssCode> for DeflateLzssCode {
+    fn from(source: &LzssCode) -> Self {
         match *source {
             LzssCode::Symbol(s) => DeflateLzssCode::Symbol(s),
             LzssCode::Reference { len, pos } => {
-                let l = len_tab.convert(len as u16 - 3);
-                let o = offset_tab.convert(pos as u16);
+                let l = LEN_TAB.convert(len as u16 - 3);
+                let o = OFF_TAB.convert(pos as u16);
                 DeflateLzssCode::Reference {
                     len: u16::from(l.0) + 257,
                     len_sub: l.1,
@@ -82,6 +75,36 @@ impl DeflateLzssCode {
     }
 }
 
+impl DeflateLzssCode {
+    /// Returns the literal/length symbol and the distance symbol.
+    fn symbols(&self) -> (usize, Option<usize>) {
+        match *self {
+            DeflateLzssCode::Symbol(s) => (usize::from(s), None),
+            DeflateLzssCode::Reference { len, pos, .. } => {
+                (usize::from(len), Some(usize::from(pos)))
+            }
+        }
+    }
+}
+
+type DeflateCounter =
+    FrequencyCounter<fn(&DeflateLzssCode) -> (usize, Option<usize>)>;
+
+/// What blocks the deflate encoder writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockMode {
+    /// Each block is the smallest of stored, fixed Huffman and dynamic
+    /// Huffman. Blocks of less than 256 bytes are not tried as dynamic,
+    /// whose tables would take about as much as they save.
+    #[default]
+    Auto,
+    /// Fixed Huffman blocks only, as zlib's strategy `Z_FIXED`. The codes
+    /// are not counted, so each block takes the least time to finish.
+    Fixed,
+    /// Stored blocks only, with the input as it is.
+    Stored,
+}
+
 #[derive(Debug)]
 pub(crate) enum InflateBitVec {
     BitVec(SmallBitVec<u16>),
@@ -89,18 +112,92 @@ pub(crate) enum InflateBitVec {
     Flush,
 }
 
+/// Codes a block is written with.
+enum BlockCodes {
+    Fixed,
+    Custom(HuffmanEncoder<Right, u16>, HuffmanEncoder<Right, u16>),
+}
+
+impl BlockCodes {
+    fn symbol(&self, s: u16) -> Result<SmallBitVec<u16>, CompressionError> {
+        match *self {
+            BlockCodes::Fixed => Ok(FIX_SYMBOL.enc(s)),
+            BlockCodes::Custom(ref enc, _) => {
+                enc.enc(s).map_err(|_| CompressionError::Unexpected)
+            }
+        }
+    }
+
+    fn offset(&self, s: u16) -> Result<SmallBitVec<u16>, CompressionError> {
+        match *self {
+            BlockCodes::Fixed => Ok(FIX_OFFSET.enc(s)),
+            BlockCodes::Custom(_, ref enc) => {
+                enc.enc(s).map_err(|_| CompressionError::Unexpected)
+            }
+        }
+    }
+}
+
+/// Encoder of a raw deflate stream (RFC 1951), with no zlib or gzip
+/// header and no checksum, as ZIP members and HTTP's raw
+/// `Content-Encoding: deflate` hold it. `ZlibEncoder` and `GZipEncoder`
+/// wrap this encoder.
+///
+/// `Action::Flush` is zlib's `Z_SYNC_FLUSH`: it ends the block in progress
+/// and writes an empty stored block, so the output so far ends on a byte
+/// boundary and decodes in full. The encoding can go on after it, with the
+/// window kept.
 #[derive(Debug)]
 pub struct Inflater {
-    inner: InflaterInner,
+    codes: DeflateCodeEncoder,
     lzss: LzssEncoder<fn(LzssCode, LzssCode) -> Ordering>,
-    writer: BitWriter<Right>,
+    rsync: Option<Rsync>,
+}
 
-    queue: VecDeque<InflateBitVec>,
-    finished: bool,
+/// The rolling sum of `Inflater::set_rsyncable`, over the last
+/// `Rsync::WINDOW` input bytes, and the output of the segment encoded.
+#[derive(Debug)]
+struct Rsync {
+    window: CircularBuffer<u8>,
+    sum: u32,
+    // 前の区切りからのバイト数
+    since_cut: usize,
+    out: VecDeque<u8>,
+    ended: bool,
+}
 
-    bitbuf: u16,
-    bitbuflen: usize,
-    bit_finished: bool,
+impl Rsync {
+    // gzip の RSYNC_WIN
+    const WINDOW: usize = 0x2000;
+
+    fn new() -> Self {
+        Self {
+            window: CircularBuffer::new(Self::WINDOW),
+            sum: 0,
+            since_cut: 0,
+            out: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// Adds `b` to the sum, and returns whether the output is cut after it.
+    fn push(&mut self, b: u8) -> bool {
+        if self.window.len() == Self::WINDOW {
+            self.sum -= u32::from(self.window[Self::WINDOW - 1]);
+        }
+        self.window.push(b);
+        self.sum += u32::from(b);
+        self.since_cut += 1;
+        // 同じバイトの連続で区切り続けないように、窓の大きさは空ける
+        if self.since_cut >= Self::WINDOW
+            && self.sum as usize & (Self::WINDOW - 1) == 0
+        {
+            self.since_cut = 0;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl Default for Inflater {
@@ -112,54 +209,432 @@ impl Default for Inflater {
 impl Inflater {
     const LZSS_MIN_MATCH: usize = 3;
     const LZSS_MAX_MATCH: usize = 258;
-    const LZSS_LAZY_LEVEL: usize = 3;
+    // 最適解析が直前のブロックの符号長を使えるように、一度に渡す量を
+    // 解析の単位までにする
+    const SLICE_CHUNK: usize = 0x1000;
+    // zlib の圧縮レベル 9 と同じ探索の深さ
+    const OPTIMAL_PARAMS: MatchParams = MatchParams {
+        good_length: 32,
+        nice_length: 258,
+        max_lazy: 258,
+        max_chain: 4096,
+    };
 
     pub fn new() -> Self {
+        Self::with_window_bits(15)
+    }
+
+    /// Creates an encoder whose references reach back at most
+    /// `1 << window_bits` bytes, as zlib's `windowBits` sets, so a decoder
+    /// with a window of that size reads the stream. The match search keeps
+    /// a window of that size too. `new` is the same as 15, the 32 KiB
+    /// window of deflate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_bits` is not between 9 and 15.
+    pub fn with_window_bits(window_bits: u32) -> Self {
+        let codes = DeflateCodeEncoder::with_window_bits(window_bits);
         Self {
-            inner: InflaterInner::new(),
-            lzss: LzssEncoder::new(
-                lzss_comparison,
-                0x8000,
-                Self::LZSS_MAX_MATCH,
+            lzss: LzssEncoder::with_params(
+                codes.inner.window_size,
                 Self::LZSS_MIN_MATCH,
-                Self::LZSS_LAZY_LEVEL,
+                Self::LZSS_MAX_MATCH,
+                lzss_comparison,
             ),
-            writer: BitWriter::new(),
-            queue: VecDeque::new(),
-            finished: false,
-            bitbuf: 0,
-            bitbuflen: 0,
-            bit_finished: false,
+            codes,
+            rsync: None,
         }
     }
 
     pub fn with_dict(dict: &[u8]) -> Self {
+        let mut ret = Self::new();
+        ret.lzss.set_dictionary(dict);
+        ret.codes.inner.set_dictionary(dict);
+        ret
+    }
+
+    /// Puts `dict` before the input, so the first references can already
+    /// point into it. `dict` is not part of the output, and the decoder is
+    /// given the same bytes with `Deflater::set_dictionary`. Only the last
+    /// window size bytes of a longer `dict` can be referred to, and a
+    /// second call adds its bytes after those of the first.
+    ///
+    /// Returns `Unsupported` once encoding has begun.
+    pub fn set_dictionary(
+        &mut self,
+        dict: &[u8],
+    ) -> Result<(), CompressionError> {
+        self.codes.set_dictionary(dict)?;
+        self.lzss.set_dictionary(dict);
+        Ok(())
+    }
+
+    /// Ends the output as `Action::Flush` does and drops the window, as
+    /// zlib's `Z_FULL_FLUSH`: no reference after it reaches before it, so
+    /// the rest of the stream decodes on its own with a new `Deflater`.
+    /// Returns the bytes up to that point that are not output yet.
+    pub fn full_flush(&mut self) -> Result<Vec<u8>, CompressionError> {
+        let out = self.compress_to_vec(&[], Action::Flush)?;
+        self.forget_window();
+        Ok(out)
+    }
+
+    /// Drops the window after a flush, for the `full_flush` of the
+    /// encoders that wrap this one.
+    pub(crate) fn forget_window(&mut self) {
+        self.lzss.forget_window();
+        self.codes.inner.nocomp_buf.clear();
+    }
+
+    /// Makes the output friendly to rsync and other tools that look for
+    /// runs of bytes two files share, as gzip's `--rsyncable` does. The
+    /// encoder keeps the sum of the last 8 KiB of input, and where its low
+    /// 13 bits are zero, at least 8 KiB after the previous cut, it does a
+    /// `full_flush`. The cuts depend only on the input near them, so a
+    /// change in the input changes the output only up to the next cut after
+    /// it. Each cut costs the window and 5 bytes of empty stored block.
+    ///
+    /// Set it before encoding begins, or between calls that end with
+    /// `Action::Flush`.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.rsync = if rsyncable { Some(Rsync::new()) } else { None };
+    }
+
+    /// Sets how the input is split into codes. `ParseMode::Optimal` is the
+    /// slowest and smallest setting: it searches matches as deeply as zlib
+    /// level 9, and prices the codes by the Huffman code lengths of the
+    /// previous block. `ParseMode::Fast` is the fastest that searches the
+    /// window, in the place of zlib level 1, and `ParseMode::Rle` is zlib's
+    /// strategy `Z_RLE`.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.lzss.set_parse_mode(mode);
+        match mode {
+            ParseMode::Lazy | ParseMode::Fast | ParseMode::Rle => {
+                self.lzss.set_match_params(MatchParams::default());
+                self.codes.inner.optimal = false;
+            }
+            ParseMode::Optimal => {
+                self.lzss.set_match_params(Self::OPTIMAL_PARAMS);
+                self.lzss.set_prices(self.codes.inner.fixed_prices());
+                self.codes.inner.optimal = true;
+            }
+        }
+    }
+
+    /// Sets the blocks written, from the next block on. `BlockMode::Fixed`
+    /// skips counting the codes and writes each block with the fixed
+    /// Huffman codes.
+    pub fn set_block_mode(&mut self, mode: BlockMode) {
+        self.codes.set_block_mode(mode);
+    }
+
+    /// Creates an encoder with `set_level(level)`. `new` is the same as
+    /// level 6.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is more than 9.
+    pub fn with_level(level: u32) -> Self {
+        let mut ret = Self::new();
+        ret.set_level(level);
+        ret
+    }
+
+    /// Sets the parse mode, the match search and the blocks by a level
+    /// from 0 to 9, as zlib does. The levels are:
+    ///
+    /// - 0: `BlockMode::Stored`. The input still goes through the match
+    ///   search of level 1, whose codes only rebuild the bytes.
+    /// - 1: `ParseMode::Fast`, several times faster than the default.
+    /// - 2, 3: `ParseMode::Lazy` without lazy matching, comparing up to 8
+    ///   and 32 candidates.
+    /// - 4 to 8: `ParseMode::Lazy` with the `MatchParams` of zlib's levels,
+    ///   but 32 candidates at 4 so it does not fall behind 3. 6 is the
+    ///   default of the encoder, `MatchParams::default()`.
+    /// - 9: `ParseMode::Optimal`.
+    ///
+    /// Levels from 1 on set `BlockMode::Auto`. Higher levels are slower
+    /// and, on most input, smaller.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is more than 9.
+    pub fn set_level(&mut self, level: u32) {
+        self.set_block_mode(if level == 0 {
+            BlockMode::Stored
+        } else {
+            BlockMode::Auto
+        });
+        // zlib の configuration_table の値
+        let (good_length, max_lazy, nice_length, max_chain) = match level {
+            0 | 1 => return self.set_parse_mode(ParseMode::Fast),
+            2 => (4, 0, 16, 8),
+            3 => (4, 0, 32, 32),
+            4 => (4, 4, 32, 32),
+            5 => (8, 16, 32, 32),
+            6 => (8, 16, 128, 128),
+            7 => (8, 32, 128, 256),
+            8 => (32, 128, 258, 1024),
+            9 => return self.set_parse_mode(ParseMode::Optimal),
+            _ => panic!("level out of range"),
+        };
+        self.set_parse_mode(ParseMode::Lazy);
+        self.lzss.set_match_params(MatchParams {
+            good_length,
+            nice_length,
+            max_lazy,
+            max_chain,
+        });
+    }
+
+    /// Encodes `input` with `action` and returns the bytes, the same as
+    /// collecting `input.iter().cloned().encode(self, action)`, but reading
+    /// the input straight from the slice.
+    pub fn compress_to_vec(
+        &mut self,
+        input: &[u8],
+        action: Action,
+    ) -> Result<Vec<u8>, CompressionError> {
+        let mut rsync = match self.rsync.take() {
+            Some(rsync) => rsync,
+            None => return self.compress_slice(input, action),
+        };
+        let mut out = Vec::new();
+        let mut rest = input;
+        let ret = loop {
+            match rest.iter().position(|&b| rsync.push(b)) {
+                Some(i) => {
+                    let (segment, next) = rest.split_at(i + 1);
+                    match self.compress_slice(segment, Action::Flush) {
+                        Ok(o) => out.extend(o),
+                        Err(e) => break Err(e),
+                    }
+                    self.forget_window();
+                    rest = next;
+                }
+                None => break self.compress_slice(rest, action),
+            }
+        };
+        self.rsync = Some(rsync);
+        out.extend(ret?);
+        Ok(out)
+    }
+
+    /// Encodes the segments of `set_rsyncable` from `iter`, each up to a
+    /// cut or `SLICE_CHUNK` bytes, and yields their output.
+    fn next_rsyncable<I: Iterator<Item = u8>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        loop {
+            let rsync = self.rsync.as_mut()?;
+            if let Some(b) = rsync.out.pop_front() {
+                return Some(Ok(b));
+            }
+            if rsync.ended {
+                rsync.ended = false;
+                return None;
+            }
+            let mut segment = Vec::with_capacity(Self::SLICE_CHUNK);
+            let mut cut = false;
+            while !cut && segment.len() < Self::SLICE_CHUNK {
+                match iter.next() {
+                    Some(b) => {
+                        segment.push(b);
+                        cut = rsync.push(b);
+                    }
+                    None => {
+                        rsync.ended = true;
+                        break;
+                    }
+                }
+            }
+            let ended = rsync.ended;
+            let segment_action = if cut {
+                Action::Flush
+            } else if ended {
+                action
+            } else {
+                Action::Run
+            };
+            let out = match self.compress_slice(&segment, segment_action) {
+                Ok(out) => out,
+                Err(e) => return Some(Err(e)),
+            };
+            if cut {
+                self.forget_window();
+            }
+            self.rsync.as_mut()?.out.extend(out);
+        }
+    }
+
+    fn compress_slice(
+        &mut self,
+        input: &[u8],
+        action: Action,
+    ) -> Result<Vec<u8>, CompressionError> {
+        let codes = &mut self.codes;
+        let lzss = &mut self.lzss;
+        let mut out = Vec::new();
+        let mut err = None;
+        let mut chunks = input.chunks(Self::SLICE_CHUNK);
+        let mut flushed = false;
+        loop {
+            if let Some(p) = codes.inner.prices.take() {
+                lzss.set_prices(p);
+            }
+            let chunk = match chunks.next() {
+                Some(chunk) => chunk,
+                None if action != Action::Run && !flushed => {
+                    lzss.flush();
+                    flushed = true;
+                    &[]
+                }
+                None => break,
+            };
+            lzss.encode_slice(chunk, &mut |code| {
+                if err.is_none() {
+                    err = codes.push_code(&code, &mut out).err();
+                }
+            });
+            if let Some(e) = err {
+                return Err(e);
+            }
+        }
+        codes.end(action, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl Encoder for Inflater {
+    type Error = CompressionError;
+    type In = u8;
+    type Out = u8;
+    fn next<I: Iterator<Item = u8>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        if self.rsync.is_some() {
+            return self.next_rsyncable(iter, action);
+        }
+        let lzss = &mut self.lzss;
+        self.codes.next_with(
+            |inner| {
+                if let Some(p) = inner.prices.take() {
+                    lzss.set_prices(p);
+                }
+                lzss.next(iter, action)
+            },
+            action,
+        )
+    }
+}
+
+/// Deflate encoder of a stream of `LzssCode`s, for codes that come from
+/// somewhere other than this crate's match finder, such as another
+/// format's code decoder.
+///
+/// It accepts the codes a deflate stream can hold: a `Reference` needs
+/// `len` between 3 and 258 and `pos` below 32768, or the window size of
+/// `with_window_bits`, and may not reach before the start of the output,
+/// or of the dictionary if one is given.
+/// Other codes stop the encoding with `CompressionError::DataError`.
+#[derive(Debug)]
+pub struct DeflateCodeEncoder {
+    inner: InflaterInner,
+    writer: BitWriter<Right>,
+
+    queue: VecDeque<InflateBitVec>,
+    finished: bool,
+
+    bit_finished: bool,
+    // 入力を受け取り始めたら、辞書はもう置けない
+    started: bool,
+}
+
+impl Default for DeflateCodeEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeflateCodeEncoder {
+    pub fn new() -> Self {
+        Self::with_inner(InflaterInner::new())
+    }
+
+    /// Creates an encoder that accepts references up to `1 << window_bits`
+    /// bytes back, as `Inflater::with_window_bits` does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_bits` is not between 9 and 15.
+    pub fn with_window_bits(window_bits: u32) -> Self {
+        assert!((9..=15).contains(&window_bits), "window bits out of range");
+        let mut inner = InflaterInner::new();
+        inner.window_size = 1 << window_bits;
+        Self::with_inner(inner)
+    }
+
+    /// Creates an encoder whose codes may refer to `dict`, as
+    /// `Inflater::with_dict` does.
+    pub fn with_dict(dict: &[u8]) -> Self {
+        Self::with_inner(InflaterInner::with_dict(dict))
+    }
+
+    /// Sets the blocks written, from the next block on, as
+    /// `Inflater::set_block_mode` does.
+    pub fn set_block_mode(&mut self, mode: BlockMode) {
+        self.inner.block_mode = mode;
+    }
+
+    /// Lets the codes refer to `dict`, as `Inflater::set_dictionary` does.
+    pub fn set_dictionary(
+        &mut self,
+        dict: &[u8],
+    ) -> Result<(), CompressionError> {
+        if self.started {
+            return Err(CompressionError::Unsupported);
+        }
+        self.inner.set_dictionary(dict);
+        Ok(())
+    }
+
+    /// Ends the output as `Inflater::full_flush` does. A `Reference` after
+    /// it may not reach before it.
+    pub fn full_flush(&mut self) -> Result<Vec<u8>, CompressionError> {
+        let mut out = Vec::new();
+        self.end(Action::Flush, &mut out)?;
+        self.inner.nocomp_buf.clear();
+        Ok(out)
+    }
+
+    fn with_inner(inner: InflaterInner) -> Self {
         Self {
-            inner: InflaterInner::with_dict(dict),
-            lzss: LzssEncoder::with_dict(
-                lzss_comparison,
-                0x8000,
-                Self::LZSS_MAX_MATCH,
-                Self::LZSS_MIN_MATCH,
-                Self::LZSS_LAZY_LEVEL,
-                dict,
-            ),
+            inner,
             writer: BitWriter::new(),
             queue: VecDeque::new(),
             finished: false,
-            bitbuf: 0,
-            bitbuflen: 0,
             bit_finished: false,
+            started: false,
         }
     }
 
-    fn next_bits<I: Iterator<Item = u8>>(
+    fn next_bits<F>(
         &mut self,
-        iter: &mut I,
+        code: &mut F,
         action: Action,
-    ) -> Option<Result<InflateBitVec, CompressionError>> {
+    ) -> Option<Result<InflateBitVec, CompressionError>>
+    where
+        F: FnMut(
+            &mut InflaterInner,
+        ) -> Option<Result<LzssCode, CompressionError>>,
+    {
         while self.queue.is_empty() {
-            match self.lzss.next(iter, action) {
+            match code(&mut self.inner) {
                 Some(Ok(ref s)) => {
                     if let Err(e) = self.inner.next(s, &mut self.queue) {
                         return Some(Err(e));
@@ -197,122 +672,260 @@ impl Inflater {
         }
         self.queue.pop_front().map(Ok)
     }
-}
 
-impl Encoder for Inflater {
-    type Error = CompressionError;
-    type In = u8;
-    type Out = u8;
-    fn next<I: Iterator<Item = u8>>(
+    /// Encodes `code` and appends the bytes completed so far to `out`.
+    fn push_code(
+        &mut self,
+        code: &LzssCode,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        self.started = true;
+        self.inner.next(code, &mut self.queue)?;
+        self.write_queue(out);
+        Ok(())
+    }
+
+    /// Ends the input given to `push_code` as `action` does, and appends
+    /// the rest of the bytes to `out`.
+    fn end(
         &mut self,
-        iter: &mut I,
         action: Action,
-    ) -> Option<Result<u8, CompressionError>> {
-        while self.bitbuflen == 0 {
-            let s = match self.next_bits(iter, action) {
+        out: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        self.started = true;
+        match action {
+            Action::Run => return Ok(()),
+            Action::Flush => self.inner.flush(&mut self.queue)?,
+            Action::Finish => self.inner.finish(&mut self.queue)?,
+        }
+        self.write_queue(out);
+        let _ = self.writer.pad_to_byte(false);
+        while let Some(b) = self.writer.pop_byte() {
+            out.push(b);
+        }
+        Ok(())
+    }
+
+    fn write_queue(&mut self, out: &mut Vec<u8>) {
+        for bits in self.queue.drain(..) {
+            match bits {
+                InflateBitVec::BitVec(ref s) => self.writer.write_bits(s),
+                InflateBitVec::Byte(s) => {
+                    while let Some(b) = self.writer.pop_byte() {
+                        out.push(b);
+                    }
+                    out.push(s);
+                }
+                InflateBitVec::Flush => {
+                    let _ = self.writer.pad_to_byte(false);
+                }
+            }
+        }
+        while let Some(b) = self.writer.pop_byte() {
+            out.push(b);
+        }
+    }
+
+    /// Encodes the codes `code` returns, which gets to look at the block
+    /// state first so `Inflater` can pass the prices on to its parser.
+    fn next_with<F>(
+        &mut self,
+        mut code: F,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>>
+    where
+        F: FnMut(
+            &mut InflaterInner,
+        ) -> Option<Result<LzssCode, CompressionError>>,
+    {
+        self.started = true;
+        loop {
+            if let Some(b) = self.writer.pop_byte() {
+                return Some(Ok(b));
+            }
+            if self.bit_finished {
+                self.bit_finished = false;
+                return None;
+            }
+            match self.next_bits(&mut code, action) {
                 Some(Err(e)) => return Some(Err(e)),
                 Some(Ok(InflateBitVec::BitVec(ref s))) => {
                     self.writer.write_bits(s)
                 }
                 Some(Ok(InflateBitVec::Byte(s))) => return Some(Ok(s)),
                 Some(Ok(InflateBitVec::Flush)) => {
-                    self.writer.flush::<u16>().unwrap_or_else(|| (0, 0))
+                    let _ = self.writer.pad_to_byte(false);
                 }
-                None => {
-                    if self.bit_finished {
-                        self.bit_finished = false;
-                        return None;
-                    } else {
-                        match action {
-                            Action::Finish | Action::Flush => {
-                                self.bit_finished = true;
-                                match self.writer.flush::<u16>() {
-                                    Some((x, y)) if y != 0 => (x, y),
-                                    _ => return None,
-                                }
-                            }
-                            _ => {
-                                return None;
-                            }
-                        }
+                None => match action {
+                    Action::Finish | Action::Flush => {
+                        // 残りのバイトを出してから None を返す
+                        self.bit_finished = true;
+                        let _ = self.writer.pad_to_byte(false);
                     }
-                }
-            };
-            self.bitbuf = s.0;
-            self.bitbuflen = s.1;
+                    Action::Run => return None,
+                },
+            }
         }
+    }
+}
 
-        let ret = Right::convert(self.bitbuf, 16, 8) as u8;
-
-        self.bitbuf = Right::forward(self.bitbuf, 8);
-        self.bitbuflen -= 1;
-        Some(Ok(ret))
+impl Encoder for DeflateCodeEncoder {
+    type Error = CompressionError;
+    type In = LzssCode;
+    type Out = u8;
+    fn next<I: Iterator<Item = LzssCode>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        self.next_with(
+            |inner| {
+                iter.next().map(|s| match s {
+                    LzssCode::Reference { len, pos }
+                        if !(Inflater::LZSS_MIN_MATCH
+                            ..=Inflater::LZSS_MAX_MATCH)
+                            .contains(&len)
+                            || pos >= inner.window_size
+                            || pos >= inner.nocomp_buf.len() =>
+                    {
+                        Err(CompressionError::DataError)
+                    }
+                    _ => Ok(s),
+                })
+            },
+            action,
+        )
     }
 }
 
 #[derive(Debug)]
 struct InflaterInner {
-    len_tab: CodeTable,
-    offset_tab: CodeTable,
-
     block_buf: Vec<DeflateLzssCode>,
     decompress_len: usize,
-    symbol_freq: Vec<usize>,
-    offset_freq: Vec<usize>,
+    freq: DeflateCounter,
     nocomp_buf: CircularBuffer<u8>,
     finished: bool,
+    // 最適解析のとき、直前のブロックの符号長から求めた符号の長さ
+    optimal: bool,
+    prices: Option<CodePrices>,
+    block_mode: BlockMode,
+    // ブロックの最初の符号のときの block_mode
+    block_mode_in_use: BlockMode,
+    // 最後に空の無圧縮ブロックを書いてから、符号を受け取っていない
+    synced: bool,
+    // 参照が届く距離
+    window_size: usize,
 }
 
 impl InflaterInner {
     const MAX_BLOCK_SIZE: usize = 0xFFFF;
     const SIZE_OF_SYMBOL_FREQ_BUF: usize = 257 + 29;
     const SIZE_OF_OFFSET_FREQ_BUF: usize = 30;
+    // これより短いブロックは、動的ハフマンの表を作らない
+    const MIN_DYNAMIC_BLOCK: usize = 256;
+    const WINDOW_SIZE: usize = 0x8000;
 
     fn init_block(&mut self) {
         self.block_buf = Vec::with_capacity(Self::MAX_BLOCK_SIZE);
-        self.symbol_freq = vec![0; Self::SIZE_OF_SYMBOL_FREQ_BUF];
-        self.offset_freq = vec![0; Self::SIZE_OF_OFFSET_FREQ_BUF];
-        self.symbol_freq[256] = 1;
+        self.decompress_len = 0;
+        self.freq.clear();
+        // ブロックの終端の記号
+        self.freq.add_symbol(256);
+    }
+
+    fn new_counter() -> DeflateCounter {
+        let mut freq = FrequencyCounter::new(
+            Self::SIZE_OF_SYMBOL_FREQ_BUF,
+            Self::SIZE_OF_OFFSET_FREQ_BUF,
+            DeflateLzssCode::symbols as fn(&_) -> _,
+        );
+        freq.add_symbol(256);
+        freq
     }
 
     pub(crate) fn new() -> Self {
-        let mut symbol_freq = vec![0; Self::SIZE_OF_SYMBOL_FREQ_BUF];
-        symbol_freq[256] = 1;
         Self {
-            len_tab: gen_len_tab(),
-            offset_tab: gen_off_tab(),
-            symbol_freq,
+            freq: Self::new_counter(),
             block_buf: Vec::with_capacity(Self::MAX_BLOCK_SIZE),
-            offset_freq: vec![0; Self::SIZE_OF_OFFSET_FREQ_BUF],
             decompress_len: 0,
             nocomp_buf: CircularBuffer::new(Self::MAX_BLOCK_SIZE),
             finished: false,
+            optimal: false,
+            prices: None,
+            block_mode: BlockMode::Auto,
+            block_mode_in_use: BlockMode::Auto,
+            synced: false,
+            window_size: Self::WINDOW_SIZE,
         }
     }
 
     pub(crate) fn with_dict(dict: &[u8]) -> Self {
-        let mut symbol_freq = vec![0; Self::SIZE_OF_SYMBOL_FREQ_BUF];
-        symbol_freq[256] = 1;
-        let mut nocomp_buf = CircularBuffer::new(Self::MAX_BLOCK_SIZE);
-        nocomp_buf.append(dict);
-        Self {
-            len_tab: gen_len_tab(),
-            offset_tab: gen_off_tab(),
-            symbol_freq,
-            block_buf: Vec::with_capacity(Self::MAX_BLOCK_SIZE),
-            offset_freq: vec![0; Self::SIZE_OF_OFFSET_FREQ_BUF],
-            decompress_len: 0,
-            nocomp_buf,
-            finished: false,
+        let mut ret = Self::new();
+        ret.set_dictionary(dict);
+        ret
+    }
+
+    fn set_dictionary(&mut self, dict: &[u8]) {
+        // 距離は窓の大きさまでなので、辞書の末尾だけを置く
+        let window = self.window_size;
+        self.nocomp_buf
+            .append(&dict[dict.len() - cmp::min(window, dict.len())..]);
+    }
+
+    /// Returns the cost of the codes under the code lengths `sym_enc_tab`
+    /// and `off_enc_tab`. Symbols without a length cost one bit more than
+    /// the longest code, or as much as in the fixed code if that is more.
+    fn code_prices(
+        &self,
+        sym_enc_tab: &[u8],
+        off_enc_tab: &[u8],
+    ) -> CodePrices {
+        fn lens(tab: &[u8], fixed: &[u8]) -> Vec<u32> {
+            let longest = tab.iter().max().map_or(0, |&l| cmp::min(l + 1, 15));
+            fixed
+                .iter()
+                .enumerate()
+                .map(|(s, &f)| match tab.get(s) {
+                    Some(&l) if l != 0 => u32::from(l),
+                    _ => u32::from(cmp::max(longest, f)),
+                })
+                .collect()
+        }
+        let sym = lens(sym_enc_tab, FIX_SYMBOL.lens());
+        let off = lens(off_enc_tab, FIX_OFFSET.lens());
+        let lengths = (0..=Inflater::LZSS_MAX_MATCH)
+            .map(|len| {
+                if len < Inflater::LZSS_MIN_MATCH {
+                    return 0;
+                }
+                let c = usize::from(LEN_TAB.convert(len as u16 - 3).0);
+                sym[257 + c] + LEN_TAB.ext_bits(c) as u32
+            })
+            .collect();
+        let positions = (0..0x8000)
+            .map(|pos| {
+                let c = usize::from(OFF_TAB.convert(pos).0);
+                off[c] + OFF_TAB.ext_bits(c) as u32
+            })
+            .collect();
+        CodePrices {
+            literals: sym[..256].to_vec(),
+            lengths,
+            positions,
         }
     }
 
+    /// Returns the cost of the codes in a fixed Huffman block.
+    fn fixed_prices(&self) -> CodePrices {
+        self.code_prices(FIX_SYMBOL.lens(), FIX_OFFSET.lens())
+    }
+
     fn enc_tab_to_freq(enc_tab: &[u8]) -> (Vec<(u8, u16)>, Vec<u16>) {
         let mut freq = vec![0; 19];
         let mut list = Vec::new();
         let mut old = 255;
         let mut len = 0;
-        for &d in enc_tab.iter().chain(vec![255_u8].iter()) {
+        for &d in enc_tab.iter().chain([255_u8].iter()) {
             if old != d {
                 if old == 0 {
                     if len >= 11 {
@@ -361,9 +974,11 @@ impl InflaterInner {
         (list, freq)
     }
 
-    fn conv_tab(list: &[(u8, u16)], enc_tab: &[u8]) -> Vec<SmallBitVec<u16>> {
+    fn conv_tab(
+        list: &[(u8, u16)],
+        enc: &HuffmanEncoder<Right, u16>,
+    ) -> Vec<SmallBitVec<u16>> {
         let mut ret = Vec::new();
-        let enc = HuffmanEncoder::<Right, _>::new(enc_tab);
         for &(s, e) in list.iter() {
             ret.push(enc.enc(s).unwrap());
             match s {
@@ -387,14 +1002,15 @@ impl InflaterInner {
             .zip(offfreq.iter())
             .map(|(&x, &y)| (x + y) as usize)
             .collect::<Vec<_>>();
-        let len_enc_tab = make_table(&lenfreq, 7);
+        let (len_enc, len_enc_tab) =
+            HuffmanEncoder::<Right, u16>::from_frequencies(&lenfreq, 7);
 
         let len_map = [
             3, 17, 15, 13, 11, 9, 7, 5, 4, 6, 8, 10, 12, 14, 16, 18, 0, 1, 2,
         ];
         // let len_map = [16, 17, 18, 0, 8, 7, 9, 6, 10,
         //                5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
-        let mut len_tab = vec![0; 19];
+        let mut len_tab = [0; 19];
         let mut len_count = 3;
 
         for (&d, &i) in len_enc_tab.iter().zip(len_map.iter()) {
@@ -406,31 +1022,30 @@ impl InflaterInner {
         let hlit = sym_enc_tab
             .iter()
             .enumerate()
-            .filter(|&(_, &s)| s != 0)
-            .last()
+            .rfind(|&(_, &s)| s != 0)
             .unwrap()
             .0
             - 256;
         let hdist = off_enc_tab
             .iter()
             .enumerate()
-            .filter(|&(_, &s)| s != 0)
-            .last()
-            .unwrap_or_else(|| (0, &0))
+            .rfind(|&(_, &s)| s != 0)
+            .unwrap_or((0, &0))
             .0;
         let hclen = len_count - 3;
 
-        let mut ret = Vec::new();
-        ret.push(SmallBitVec::new(2, 2)); // custom huffman signature
-        ret.push(SmallBitVec::new(hlit as u16, 5));
-        ret.push(SmallBitVec::new(hdist as u16, 5));
-        ret.push(SmallBitVec::new(hclen as u16, 4));
+        let mut ret = vec![
+            SmallBitVec::new(2, 2), // custom huffman signature
+            SmallBitVec::new(hlit as u16, 5),
+            SmallBitVec::new(hdist as u16, 5),
+            SmallBitVec::new(hclen as u16, 4),
+        ];
         for &d in len_tab.iter().take(len_count + 1) {
             ret.push(SmallBitVec::new(u16::from(d), 3));
         }
 
-        ret.append(&mut Self::conv_tab(&symlist, &len_enc_tab));
-        ret.append(&mut Self::conv_tab(&offlist, &len_enc_tab));
+        ret.append(&mut Self::conv_tab(&symlist, &len_enc));
+        ret.append(&mut Self::conv_tab(&offlist, &len_enc));
         ret
     }
 
@@ -449,32 +1064,71 @@ impl InflaterInner {
             1,
         )));
 
-        let sym_enc_tab = make_table(&self.symbol_freq, 15);
-        let off_enc_tab = make_table(&self.offset_freq, 15);
-
-        // カスタムハフマンを使用した時のビット数を計算
-        let custom_huffman_header =
-            Self::create_custom_huffman_table(&sym_enc_tab, &off_enc_tab);
-
-        let custom_haffman_size = self
-            .cals_comp_len(&sym_enc_tab, &off_enc_tab)
-            + custom_huffman_header
-                .iter()
-                .fold(0, |s, v| v.len() as u64 + s);
-
-        let fix_sym_enc_tab = fix_symbol_table();
-        let fix_off_enc_tab = fix_offset_table();
+        if self.block_buf.is_empty() {
+            self.block_mode_in_use = self.block_mode;
+        }
+        // 数えた頻度から、動的ハフマンの表とそのビット数を求める
+        let mode = self.block_mode_in_use;
+        let mut custom = if mode == BlockMode::Auto
+            && self.decompress_len >= Self::MIN_DYNAMIC_BLOCK
+        {
+            let (sym_enc, sym_enc_tab) =
+                HuffmanEncoder::<Right, u16>::from_frequencies(
+                    self.freq.symbol_freqs(),
+                    15,
+                );
+            let (off_enc, mut off_enc_tab) =
+                HuffmanEncoder::<Right, u16>::from_frequencies(
+                    self.freq.offset_freqs(),
+                    15,
+                );
+            if off_enc_tab.is_empty() {
+                // 距離符号を使わないブロックでも符号長は 1 つ書く必要がある
+                off_enc_tab.push(0);
+            }
+            if self.optimal {
+                // ブロックの表を作り直すたびに次の解析の値段も更新する
+                self.prices =
+                    Some(self.code_prices(&sym_enc_tab, &off_enc_tab));
+            }
 
-        // 固定ハフマンを使用した時のビット数を計算
-        let fixed_haffman_size =
-            self.cals_comp_len(&fix_sym_enc_tab, fix_off_enc_tab) + 2;
+            // カスタムハフマンを使用した時のビット数を計算
+            let header =
+                Self::create_custom_huffman_table(&sym_enc_tab, &off_enc_tab);
+            let size = self.cals_comp_len(&sym_enc_tab, &off_enc_tab)
+                + header.iter().fold(0, |s, v| v.len() as u64 + s);
+            Some((sym_enc, off_enc, header, size))
+        } else {
+            None
+        };
 
-        // 無圧縮時のビット数
-        let original_size = ((self.decompress_len as u64) << 3) + 2 + 16 + 16;
+        let stored = match mode {
+            BlockMode::Auto => {
+                // 固定ハフマンを使用した時のビット数を計算
+                let fixed_haffman_size = self
+                    .cals_comp_len(FIX_SYMBOL.lens(), FIX_OFFSET.lens())
+                    + 2;
+                // 無圧縮時のビット数
+                let original_size =
+                    ((self.decompress_len as u64) << 3) + 2 + 16 + 16;
+                let custom_haffman_size =
+                    custom.as_ref().map_or(u64::MAX, |c| c.3);
+                if original_size <= custom_haffman_size
+                    && original_size <= fixed_haffman_size
+                {
+                    true
+                } else {
+                    if fixed_haffman_size <= custom_haffman_size {
+                        custom = None;
+                    }
+                    false
+                }
+            }
+            BlockMode::Fixed => false,
+            BlockMode::Stored => true,
+        };
 
-        if original_size <= custom_haffman_size
-            && original_size <= fixed_haffman_size
-        {
+        if stored {
             // 無圧縮時
             queue.push_back(InflateBitVec::BitVec(SmallBitVec::new(0, 2)));
             queue.push_back(InflateBitVec::Flush);
@@ -491,32 +1145,22 @@ impl InflaterInner {
                 queue.push_back(InflateBitVec::Byte(d));
             }
         } else {
-            let (sym_enc, off_enc) = if fixed_haffman_size
-                <= custom_haffman_size
-            {
-                // 固定ハフマン使用
-                queue.push_back(InflateBitVec::BitVec(SmallBitVec::new(1, 2)));
-                (
-                    HuffmanEncoder::<Right, u16>::new(&fix_sym_enc_tab),
-                    HuffmanEncoder::<Right, u16>::new(fix_off_enc_tab),
-                )
-            } else {
+            let codes = if let Some((sym_enc, off_enc, header, _)) = custom {
                 // カスタムハフマン
-                for d in custom_huffman_header {
+                for d in header {
                     queue.push_back(InflateBitVec::BitVec(d));
                 }
-                (
-                    HuffmanEncoder::<Right, _>::new(&sym_enc_tab),
-                    HuffmanEncoder::<Right, _>::new(&off_enc_tab),
-                )
+                BlockCodes::Custom(sym_enc, off_enc)
+            } else {
+                // 固定ハフマン使用
+                queue.push_back(InflateBitVec::BitVec(SmallBitVec::new(1, 2)));
+                BlockCodes::Fixed
             };
             for b in &self.block_buf {
                 match *b {
                     DeflateLzssCode::Symbol(s) => {
                         queue.push_back(InflateBitVec::BitVec(
-                            sym_enc
-                                .enc(s)
-                                .map_err(|_| CompressionError::Unexpected)?,
+                            codes.symbol(u16::from(s))?,
                         ));
                     }
                     DeflateLzssCode::Reference {
@@ -526,23 +1170,17 @@ impl InflaterInner {
                         ref pos_sub,
                     } => {
                         queue.push_back(InflateBitVec::BitVec(
-                            sym_enc
-                                .enc(len)
-                                .map_err(|_| CompressionError::Unexpected)?,
+                            codes.symbol(len)?,
                         ));
                         queue.push_back(InflateBitVec::BitVec(len_sub.clone()));
                         queue.push_back(InflateBitVec::BitVec(
-                            off_enc
-                                .enc(pos)
-                                .map_err(|_| CompressionError::Unexpected)?,
+                            codes.offset(u16::from(pos))?,
                         ));
                         queue.push_back(InflateBitVec::BitVec(pos_sub.clone()));
                     }
                 }
             }
-            queue.push_back(InflateBitVec::BitVec(
-                sym_enc.enc(256).map_err(|_| CompressionError::Unexpected)?,
-            ));
+            queue.push_back(InflateBitVec::BitVec(codes.symbol(256)?));
         }
         self.init_block();
         Ok(())
@@ -552,12 +1190,12 @@ impl InflaterInner {
         sym_enc_tab
             .iter()
             .enumerate()
-            .zip(self.symbol_freq.iter())
+            .zip(self.freq.symbol_freqs())
             .map(|((i, &l), &f)| {
                 f as u64
                     * (u64::from(l)
                         + if i >= 257 {
-                            self.len_tab.ext_bits(i - 257) as u64
+                            LEN_TAB.ext_bits(i - 257) as u64
                         } else {
                             0
                         })
@@ -566,10 +1204,9 @@ impl InflaterInner {
             + off_enc_tab
                 .iter()
                 .enumerate()
-                .zip(self.offset_freq.iter())
+                .zip(self.freq.offset_freqs())
                 .map(|((i, &l), &f)| {
-                    f as u64
-                        * (u64::from(l) + self.offset_tab.ext_bits(i) as u64)
+                    f as u64 * (u64::from(l) + OFF_TAB.ext_bits(i) as u64)
                 })
                 .sum::<u64>()
     }
@@ -595,6 +1232,10 @@ impl InflaterInner {
         } else {
             self.decompress_len = new_len;
         }
+        if self.block_buf.is_empty() {
+            self.block_mode_in_use = self.block_mode;
+        }
+        self.synced = false;
 
         // lzss decode
         // 元のデータを使わずにlzssのデコードを行なっているので、
@@ -604,28 +1245,14 @@ impl InflaterInner {
                 self.nocomp_buf.push(s);
             }
             LzssCode::Reference { len, pos } => {
-                for _ in 0..len {
-                    let d = self.nocomp_buf[pos];
-                    self.nocomp_buf.push(d);
-                }
+                self.nocomp_buf.copy_back(pos, len);
             }
         }
 
-        let code = DeflateLzssCode::from_with_codetab(
-            buf,
-            &self.len_tab,
-            &self.offset_tab,
-        );
-        match code {
-            DeflateLzssCode::Symbol(s) => {
-                self.symbol_freq[s as usize] += 1;
-            }
-            DeflateLzssCode::Reference { len, pos, .. } => {
-                self.symbol_freq[len as usize] += 1;
-                self.offset_freq[pos as usize] += 1;
-            }
+        let code = DeflateLzssCode::from(buf);
+        if self.block_mode_in_use == BlockMode::Auto {
+            self.freq.add(&code);
         }
-
         self.block_buf.push(code);
         Ok(())
     }
@@ -634,11 +1261,19 @@ impl InflaterInner {
         &mut self,
         queue: &mut VecDeque<InflateBitVec>,
     ) -> Result<(), CompressionError> {
-        if !self.finished {
-            self.write_block(false, queue)
-        } else {
-            Ok(())
+        if self.finished || (self.synced && self.block_buf.is_empty()) {
+            return Ok(());
         }
+        if !self.block_buf.is_empty() {
+            self.write_block(false, queue)?;
+        }
+        self.synced = true;
+        // zlib の Z_SYNC_FLUSH と同じく、空の無圧縮ブロックでバイト境界に揃える
+        queue.push_back(InflateBitVec::BitVec(SmallBitVec::new(0, 3)));
+        queue.push_back(InflateBitVec::Flush);
+        queue.push_back(InflateBitVec::BitVec(SmallBitVec::new(0, 16)));
+        queue.push_back(InflateBitVec::BitVec(SmallBitVec::new(0xFFFF, 16)));
+        Ok(())
     }
 
     fn finish(
@@ -658,8 +1293,15 @@ mod tests {
     use super::*;
     use crate::action::Action;
     use crate::bitio::writer::BitWriteExt;
+    use crate::deflate::decoder::Deflater;
+    use crate::huffman::canonical::length_limited_lengths;
+    use crate::traits::decoder::DecodeExt;
     use crate::traits::encoder::EncodeExt;
     #[cfg(not(feature = "std"))]
+    use alloc::format;
+    #[cfg(not(feature = "std"))]
+    use alloc::string::String;
+    #[cfg(not(feature = "std"))]
     #[allow(unused_imports)]
     use alloc::vec;
 
@@ -768,7 +1410,7 @@ mod tests {
     #[test]
     fn test_arr3() {
         let mut encoder = Inflater::new();
-        let a = (144..256)
+        let a = (0..256)
             .map(|x| x as u8)
             .encode(&mut encoder, Action::Finish)
             .collect::<Result<Vec<_>, _>>();
@@ -781,16 +1423,14 @@ mod tests {
             // FILLER
             SmallBitVec::new(0, 5),
             // LEN
-            SmallBitVec::new(112, 16),
+            SmallBitVec::new(256, 16),
             // NLEN
-            SmallBitVec::new(65_423, 16),
+            SmallBitVec::new(65_279, 16),
         ];
 
         // DATA
         r.append(
-            &mut ((144..256)
-                .map(|x| SmallBitVec::new(x, 8))
-                .collect::<Vec<_>>()),
+            &mut ((0..256).map(|x| SmallBitVec::new(x, 8)).collect::<Vec<_>>()),
         );
 
         let b = r
@@ -805,238 +1445,31 @@ mod tests {
         let mut encoder = Inflater::new();
         let a = (144..256)
             .cycle()
-            .take(224)
+            .take(336)
             .map(|x| x as u8)
             .encode(&mut encoder, Action::Finish)
             .collect::<Result<Vec<_>, _>>();
 
-        let r = vec![
-            SmallBitVec::new(1_u32, 1),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(23, 5),
-            SmallBitVec::new(13, 5),
-            SmallBitVec::new(14, 4),
-            SmallBitVec::new(2, 3),
-            SmallBitVec::new(4, 3),
-            SmallBitVec::new(3, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(2, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(2, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(0, 3),
-            SmallBitVec::new(4, 3),
-            SmallBitVec::new(3, 3),
-            SmallBitVec::new(127, 7),
-            SmallBitVec::new(15, 4),
-            SmallBitVec::new(3, 3),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(1, 2),
-            SmallBitVec::new(3, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(0, 2),
-            SmallBitVec::new(3, 3),
-            SmallBitVec::new(11, 7),
-            SmallBitVec::new(2, 2),
-            SmallBitVec::new(3, 3),
-            SmallBitVec::new(2, 7),
-            SmallBitVec::new(7, 4),
-            SmallBitVec::new(28, 7),
-            SmallBitVec::new(92, 7),
-            SmallBitVec::new(60, 7),
-            SmallBitVec::new(124, 7),
-            SmallBitVec::new(2, 7),
-            SmallBitVec::new(66, 7),
-            SmallBitVec::new(34, 7),
-            SmallBitVec::new(98, 7),
-            SmallBitVec::new(18, 7),
-            SmallBitVec::new(82, 7),
-            SmallBitVec::new(50, 7),
-            SmallBitVec::new(114, 7),
-            SmallBitVec::new(10, 7),
-            SmallBitVec::new(74, 7),
-            SmallBitVec::new(42, 7),
-            SmallBitVec::new(106, 7),
-            SmallBitVec::new(0, 6),
-            SmallBitVec::new(26, 7),
-            SmallBitVec::new(90, 7),
-            SmallBitVec::new(58, 7),
-            SmallBitVec::new(122, 7),
-            SmallBitVec::new(6, 7),
-            SmallBitVec::new(70, 7),
-            SmallBitVec::new(38, 7),
-            SmallBitVec::new(102, 7),
-            SmallBitVec::new(22, 7),
-            SmallBitVec::new(86, 7),
-            SmallBitVec::new(54, 7),
-            SmallBitVec::new(118, 7),
-            SmallBitVec::new(14, 7),
-            SmallBitVec::new(32, 6),
-            SmallBitVec::new(78, 7),
-            SmallBitVec::new(46, 7),
-            SmallBitVec::new(110, 7),
-            SmallBitVec::new(30, 7),
-            SmallBitVec::new(94, 7),
-            SmallBitVec::new(62, 7),
-            SmallBitVec::new(126, 7),
-            SmallBitVec::new(1, 7),
-            SmallBitVec::new(65, 7),
-            SmallBitVec::new(33, 7),
-            SmallBitVec::new(97, 7),
-            SmallBitVec::new(16, 6),
-            SmallBitVec::new(17, 7),
-            SmallBitVec::new(81, 7),
-            SmallBitVec::new(49, 7),
-            SmallBitVec::new(113, 7),
-            SmallBitVec::new(9, 7),
-            SmallBitVec::new(73, 7),
-            SmallBitVec::new(41, 7),
-            SmallBitVec::new(105, 7),
-            SmallBitVec::new(25, 7),
-            SmallBitVec::new(89, 7),
-            SmallBitVec::new(48, 6),
-            SmallBitVec::new(57, 7),
-            SmallBitVec::new(121, 7),
-            SmallBitVec::new(5, 7),
-            SmallBitVec::new(69, 7),
-            SmallBitVec::new(37, 7),
-            SmallBitVec::new(8, 6),
-            SmallBitVec::new(40, 6),
-            SmallBitVec::new(24, 6),
-            SmallBitVec::new(101, 7),
-            SmallBitVec::new(21, 7),
-            SmallBitVec::new(56, 6),
-            SmallBitVec::new(85, 7),
-            SmallBitVec::new(53, 7),
-            SmallBitVec::new(117, 7),
-            SmallBitVec::new(13, 7),
-            SmallBitVec::new(77, 7),
-            SmallBitVec::new(45, 7),
-            SmallBitVec::new(109, 7),
-            SmallBitVec::new(29, 7),
-            SmallBitVec::new(93, 7),
-            SmallBitVec::new(61, 7),
-            SmallBitVec::new(125, 7),
-            SmallBitVec::new(3, 7),
-            SmallBitVec::new(67, 7),
-            SmallBitVec::new(35, 7),
-            SmallBitVec::new(99, 7),
-            SmallBitVec::new(19, 7),
-            SmallBitVec::new(83, 7),
-            SmallBitVec::new(51, 7),
-            SmallBitVec::new(115, 7),
-            SmallBitVec::new(11, 7),
-            SmallBitVec::new(75, 7),
-            SmallBitVec::new(4, 6),
-            SmallBitVec::new(43, 7),
-            SmallBitVec::new(107, 7),
-            SmallBitVec::new(27, 7),
-            SmallBitVec::new(91, 7),
-            SmallBitVec::new(59, 7),
-            SmallBitVec::new(123, 7),
-            SmallBitVec::new(7, 7),
-            SmallBitVec::new(71, 7),
-            SmallBitVec::new(39, 7),
-            SmallBitVec::new(103, 7),
-            SmallBitVec::new(23, 7),
-            SmallBitVec::new(87, 7),
-            SmallBitVec::new(55, 7),
-            SmallBitVec::new(119, 7),
-            SmallBitVec::new(15, 7),
-            SmallBitVec::new(79, 7),
-            SmallBitVec::new(47, 7),
-            SmallBitVec::new(111, 7),
-            SmallBitVec::new(31, 7),
-            SmallBitVec::new(36, 6),
-            SmallBitVec::new(20, 6),
-            SmallBitVec::new(95, 7),
-            SmallBitVec::new(52, 6),
-            SmallBitVec::new(63, 7),
-            SmallBitVec::new(12, 6),
-            SmallBitVec::new(127, 7),
-            SmallBitVec::new(13, 4),
-            SmallBitVec::new(0, 1),
-            SmallBitVec::new(15, 5),
-            SmallBitVec::new(44, 6),
+        // 動的ハフマンブロック (zlib で展開できることを確認済み)
+        let b = vec![
+            221, 205, 179, 1, 196, 0, 0, 0, 192, 253, 187, 183, 109, 219, 182,
+            109, 219, 70, 236, 61, 50, 193, 113, 184, 60, 190, 64, 40, 18, 75,
+            164, 50, 185, 66, 169, 82, 107, 180, 58, 189, 193, 104, 50, 91,
+            172, 54, 187, 195, 233, 114, 123, 188, 62, 127, 32, 24, 10, 71,
+            162, 177, 120, 34, 153, 74, 103, 178, 185, 124, 161, 88, 42, 87,
+            170, 181, 122, 163, 217, 106, 119, 186, 189, 254, 96, 56, 26, 79,
+            166, 179, 249, 98, 185, 90, 111, 182, 187, 253, 225, 120, 58, 95,
+            174, 183, 251, 227, 249, 122, 127, 190, 191, 63, 0, 66, 48, 130,
+            98, 56, 65, 82, 52, 219, 61, 6,
         ];
 
-        let b = r
-            .to_bytes(BitWriter::<Right>::new(), Action::Flush)
-            .collect::<Vec<_>>();
-
         assert_eq!(a, Ok(b));
     }
 
     #[test]
     fn test_defaltelzsscode() {
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 3, pos: 0 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 3, pos: 0 }),
             DeflateLzssCode::Reference {
                 len: 257,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1045,11 +1478,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 4, pos: 1 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 4, pos: 1 }),
             DeflateLzssCode::Reference {
                 len: 258,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1058,11 +1487,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 5, pos: 2 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 5, pos: 2 }),
             DeflateLzssCode::Reference {
                 len: 259,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1071,11 +1496,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 6, pos: 3 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 6, pos: 3 }),
             DeflateLzssCode::Reference {
                 len: 260,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1084,11 +1505,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 7, pos: 4 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 7, pos: 4 }),
             DeflateLzssCode::Reference {
                 len: 261,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1097,11 +1514,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 8, pos: 5 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 8, pos: 5 }),
             DeflateLzssCode::Reference {
                 len: 262,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1110,11 +1523,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 9, pos: 6 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 9, pos: 6 }),
             DeflateLzssCode::Reference {
                 len: 263,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1123,11 +1532,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 10, pos: 7 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 10, pos: 7 }),
             DeflateLzssCode::Reference {
                 len: 264,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1136,11 +1541,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 11, pos: 8 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 11, pos: 8 }),
             DeflateLzssCode::Reference {
                 len: 265,
                 len_sub: SmallBitVec::new(0, 1),
@@ -1149,11 +1550,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 12, pos: 9 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 12, pos: 9 }),
             DeflateLzssCode::Reference {
                 len: 265,
                 len_sub: SmallBitVec::new(1, 1),
@@ -1162,11 +1559,7 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference { len: 13, pos: 10 },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference { len: 13, pos: 10 }),
             DeflateLzssCode::Reference {
                 len: 266,
                 len_sub: SmallBitVec::new(0, 1),
@@ -1175,14 +1568,10 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference {
-                    len: 257,
-                    pos: 24_576,
-                },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference {
+                len: 257,
+                pos: 24_576,
+            }),
             DeflateLzssCode::Reference {
                 len: 284,
                 len_sub: SmallBitVec::new(30, 5),
@@ -1191,14 +1580,10 @@ mod tests {
             }
         );
         assert_eq!(
-            DeflateLzssCode::from_with_codetab(
-                &LzssCode::Reference {
-                    len: 258,
-                    pos: 0x7FFF,
-                },
-                &gen_len_tab(),
-                &gen_off_tab(),
-            ),
+            DeflateLzssCode::from(&LzssCode::Reference {
+                len: 258,
+                pos: 0x7FFF,
+            }),
             DeflateLzssCode::Reference {
                 len: 285,
                 len_sub: SmallBitVec::new(0, 0),
@@ -1207,4 +1592,625 @@ mod tests {
             }
         );
     }
+
+    /// Returns the Fibonacci numbers from 1, 1 up to `n` of them.
+    fn fibonacci(n: usize) -> Vec<usize> {
+        let mut ret = vec![1, 1];
+        while ret.len() < n {
+            let l = ret.len();
+            ret.push(ret[l - 1] + ret[l - 2]);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_overlong_frequencies() {
+        // フィボナッチ頻度の記号と距離は、制限がなければ 15 ビットを超える
+        let mut codes = Vec::new();
+        // 頻度 1 の終端記号があるので、リテラルの頻度は 1, 2, 3, 5, ...
+        for (i, &f) in fibonacci(21).iter().skip(1).enumerate() {
+            codes.extend((0..f).map(|_| LzssCode::Symbol(i as u8 * 11)));
+        }
+        for (c, &f) in fibonacci(19).iter().enumerate() {
+            // 距離符号 c の最小の距離
+            let dist = if c < 4 {
+                c + 1
+            } else {
+                let extra = c / 2 - 1;
+                (2 << extra) + 1 + (c & 1) * (1 << extra)
+            };
+            codes.extend((0..f).map(|_| LzssCode::Reference {
+                len: 3,
+                pos: dist - 1,
+            }));
+        }
+        let mut expected = Vec::new();
+        for c in &codes {
+            match *c {
+                LzssCode::Symbol(s) => expected.push(s),
+                LzssCode::Reference { len, pos } => {
+                    for _ in 0..len {
+                        expected.push(expected[expected.len() - pos - 1]);
+                    }
+                }
+            }
+        }
+
+        let mut inner = InflaterInner::new();
+        let mut queue = VecDeque::new();
+        for c in &codes {
+            inner.next(c, &mut queue).unwrap();
+        }
+        let unlimited =
+            |f: &[usize]| *length_limited_lengths(f, 64).iter().max().unwrap();
+        assert!(unlimited(inner.freq.symbol_freqs()) > 15);
+        assert!(unlimited(inner.freq.offset_freqs()) > 15);
+        inner.finish(&mut queue).unwrap();
+
+        let encoded = queue
+            .into_iter()
+            .map(|b| match b {
+                InflateBitVec::BitVec(b) => b,
+                _ => panic!("not a compressed block"),
+            })
+            .to_bytes(BitWriter::<Right>::new(), Action::Finish)
+            .collect::<Vec<_>>();
+        // BFINAL 1, BTYPE 2
+        assert_eq!(encoded[0] & 7, 5);
+        assert_eq!(
+            Deflater::new().decode_slice(&encoded),
+            Ok((expected, encoded.len()))
+        );
+    }
+
+    #[test]
+    fn test_optimal_prices() {
+        let mut inner = InflaterInner::new();
+        let mut queue = VecDeque::new();
+        inner.optimal = true;
+        for _ in 0..300 {
+            inner.next(&LzssCode::Symbol(b'a'), &mut queue).unwrap();
+        }
+        inner.next(&LzssCode::Symbol(b'b'), &mut queue).unwrap();
+        assert_eq!(inner.prices, None);
+
+        // 'a' が 1 ビット、'b' と終端が 2 ビットの表になる
+        inner.flush(&mut queue).unwrap();
+        let prices = inner.prices.take().unwrap();
+        assert_eq!((prices.literals[97], prices.literals[98]), (1, 2));
+        // 使われなかった記号は固定ハフマン符号の長さ
+        assert_eq!(prices.literals[99], 8);
+        assert_eq!(prices.lengths[3], 7);
+        assert_eq!(prices.lengths[258], 8);
+        assert_eq!(prices.positions[0], 5);
+        assert_eq!(prices.positions[0x7FFF], 5 + 13);
+
+        for _ in 0..300 {
+            inner.next(&LzssCode::Symbol(b'b'), &mut queue).unwrap();
+        }
+        inner.finish(&mut queue).unwrap();
+        let prices = inner.prices.take().unwrap();
+        assert_eq!(prices.literals[97], 8);
+    }
+
+    #[test]
+    fn test_flush() {
+        let source = (0..300)
+            .map(|i| format!("{} bottles of beer\n", i % 99 + 1))
+            .flat_map(String::into_bytes)
+            .collect::<Vec<u8>>();
+        // 一致の途中や、最小一致長に満たない先読みの中で区切っても、
+        // そこまでの入力はすべて出力に入る
+        for &cut in &[1, 2, 3, 5, 21, 22, 40, 61, 300, 2049, source.len()] {
+            for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast]
+            {
+                let mut encoder = Inflater::new();
+                encoder.set_parse_mode(mode);
+                let out = source[..cut]
+                    .iter()
+                    .cloned()
+                    .encode(&mut encoder, Action::Flush)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let decoded = out
+                    .iter()
+                    .cloned()
+                    .decode(&mut Deflater::new())
+                    .take_while(Result::is_ok)
+                    .collect::<Result<Vec<_>, _>>();
+                assert_eq!(decoded, Ok(source[..cut].to_vec()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_to_vec() {
+        // ブロックを跨ぐ長さで、最適解析が前のブロックの符号長を使う
+        let mut seed = 0x2545_F491_u32;
+        let source = (0..14000)
+            .flat_map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                ["the ", "quick ", "brown ", "fox ", "jumps\n", "over "]
+                    [(seed % 6) as usize]
+                    .bytes()
+            })
+            .collect::<Vec<u8>>();
+        for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast] {
+            for &action in &[Action::Run, Action::Flush, Action::Finish] {
+                let mut encoder = Inflater::new();
+                encoder.set_parse_mode(mode);
+                let expected = source
+                    .iter()
+                    .cloned()
+                    .encode(&mut encoder, action)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let mut encoder = Inflater::new();
+                encoder.set_parse_mode(mode);
+                let out = encoder.compress_to_vec(&source, action).unwrap();
+                assert_eq!(out, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rle() {
+        // Sub フィルタを掛けた走査線: 平らな領域は 0 の連続に、
+        // 揺らぎのある領域は小さな値の並びになる
+        let (width, height) = (256, 96);
+        let mut seed = 0x2545_F491_u32;
+        let mut source = Vec::new();
+        for y in 0..height {
+            source.push(1);
+            let mut prev = [0_u8; 3];
+            for x in 0..width {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let pixel = if (x / 64 + y / 32) % 2 == 0 {
+                    [200, 40, 90]
+                } else {
+                    [
+                        (x / 4 + y / 3) as u8 ^ (seed & 3) as u8,
+                        (y / 2) as u8 ^ (seed >> 8 & 3) as u8,
+                        (seed >> 16 & 7) as u8,
+                    ]
+                };
+                for (p, &c) in prev.iter_mut().zip(&pixel) {
+                    source.push(c.wrapping_sub(*p));
+                    *p = c;
+                }
+            }
+        }
+
+        let lazy = Inflater::new()
+            .compress_to_vec(&source, Action::Finish)
+            .unwrap();
+        let mut encoder = Inflater::new();
+        encoder.set_parse_mode(ParseMode::Rle);
+        let rle = encoder.compress_to_vec(&source, Action::Finish).unwrap();
+        let decoded = rle
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(source));
+        assert!(rle.len() * 100 <= lazy.len() * 105);
+    }
+
+    #[test]
+    fn test_levels() {
+        let corpus: [&[u8]; 3] = [
+            include_bytes!("../../data/sample1.ref"),
+            include_bytes!("../../data/sample2.ref"),
+            include_bytes!("../../data/sample4.ref"),
+        ];
+        for data in &corpus {
+            let sizes = (0..=9)
+                .map(|level| {
+                    let out = Inflater::with_level(level)
+                        .compress_to_vec(data, Action::Finish)
+                        .unwrap();
+                    assert_eq!(
+                        Deflater::new().decode_slice(&out),
+                        Ok((data.to_vec(), out.len()))
+                    );
+                    out.len()
+                })
+                .collect::<Vec<_>>();
+            assert!(sizes.windows(2).all(|w| w[1] <= w[0]), "{:?}", sizes);
+            let default = Inflater::new()
+                .compress_to_vec(data, Action::Finish)
+                .unwrap();
+            assert_eq!(default.len(), sizes[6]);
+        }
+    }
+
+    #[test]
+    fn test_level_0() {
+        let data = include_bytes!("../../data/sample1.ref");
+        let out = data
+            .iter()
+            .cloned()
+            .encode(&mut Inflater::with_level(0), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // 最後の短いブロックも含め、すべて無圧縮のブロック
+        let mut pos = 0;
+        let mut blocks = 0;
+        loop {
+            assert_eq!(out[pos] & 0b110, 0);
+            let is_final = out[pos] & 1 == 1;
+            let len = u16::from_le_bytes([out[pos + 1], out[pos + 2]]);
+            assert_eq!(u16::from_le_bytes([out[pos + 3], out[pos + 4]]), !len);
+            pos += 5 + usize::from(len);
+            blocks += 1;
+            if is_final {
+                break;
+            }
+        }
+        assert_eq!((pos, blocks), (out.len(), 2));
+        assert_eq!(
+            Deflater::new().decode_slice(&out),
+            Ok((data.to_vec(), pos))
+        );
+    }
+
+    #[test]
+    fn test_block_choice() {
+        use rand::distr::StandardUniform;
+        use rand::{rng, RngExt};
+
+        let size = |data: &[u8], mode: BlockMode| {
+            let mut encoder = Inflater::new();
+            encoder.set_block_mode(mode);
+            let out = encoder.compress_to_vec(data, Action::Finish).unwrap();
+            assert_eq!(Deflater::new().decode_slice(&out).unwrap().0, data);
+            out
+        };
+        // 1 KiB から 4 KiB の小さなファイルで、どの種類だけにするよりも
+        // 大きくならない
+        let corpus: [&[u8]; 3] = [
+            include_bytes!("../../data/sample1.ref"),
+            include_bytes!("../../data/sample2.ref"),
+            include_bytes!("../../data/sample4.ref"),
+        ];
+        for data in &corpus {
+            for (i, file) in data.chunks_exact(0x1000).step_by(5).enumerate() {
+                let file = &file[..0x400 + i * 0x100 % 0xC00];
+                let auto = size(file, BlockMode::Auto).len();
+                assert!(auto <= size(file, BlockMode::Fixed).len());
+                assert!(auto <= size(file, BlockMode::Stored).len());
+            }
+        }
+        // 乱数は無圧縮, 文章は動的ハフマン, 短い繰り返しは固定ハフマン
+        let random = rng()
+            .sample_iter(StandardUniform)
+            .take(0x800)
+            .collect::<Vec<u8>>();
+        let text = &corpus[1][..0x800];
+        let run = [b'a'; 0x400];
+        for &(data, btype) in &[(&random[..], 0), (text, 2), (&run[..], 1)] {
+            let out = size(data, BlockMode::Auto);
+            assert_eq!((out[0] >> 1) & 0b11, btype);
+        }
+    }
+
+    #[test]
+    fn test_rsyncable() {
+        // 文章の断片を並べた 10 MB と、その真ん中の 1 バイトだけ違うもの
+        let text = include_bytes!("../../data/sample2.ref");
+        let mut seed = 0x2545_F491_u32;
+        let mut a = Vec::with_capacity(10_000_000);
+        while a.len() < 10_000_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let start = seed as usize % (text.len() - 256);
+            a.extend_from_slice(
+                &text[start..start + 16 + (seed >> 25) as usize],
+            );
+        }
+        a.truncate(10_000_000);
+        let mut b = a.clone();
+        b[5_000_000] ^= 1;
+
+        let compress = |data: &[u8], rsyncable: bool| {
+            let mut encoder = Inflater::new();
+            encoder.set_rsyncable(rsyncable);
+            let out = encoder.compress_to_vec(data, Action::Finish).unwrap();
+            assert_eq!(Deflater::new().decode_slice(&out).unwrap().0, data);
+            out
+        };
+        let common = |x: &[u8], y: &[u8]| {
+            let prefix = x.iter().zip(y).take_while(|(p, q)| p == q).count();
+            let suffix = x
+                .iter()
+                .rev()
+                .zip(y.iter().rev())
+                .take_while(|(p, q)| p == q)
+                .count();
+            (prefix, suffix)
+        };
+        let (x, y) = (compress(&a, true), compress(&b, true));
+        let (prefix, suffix) = common(&x, &y);
+        // 違いは前後の区切りの間だけに収まる
+        assert!(prefix > x.len() * 45 / 100, "{} of {}", prefix, x.len());
+        assert!(suffix > x.len() * 45 / 100, "{} of {}", suffix, x.len());
+        assert!(
+            (prefix + suffix) * 100 > x.len().max(y.len()) * 99,
+            "{} + {} of {}",
+            prefix,
+            suffix,
+            x.len()
+        );
+        // 区切らなければ、違いのあとは揃わない
+        let plain = compress(&a, false);
+        let (_, suffix) = common(&plain, &compress(&b, false));
+        assert!(suffix < plain.len() / 100, "{}", suffix);
+
+        // 1 バイトずつ取る符号化も同じ所で区切る
+        let data = &a[..300_000];
+        let mut encoder = Inflater::new();
+        encoder.set_rsyncable(true);
+        let mut out = data
+            .iter()
+            .cloned()
+            .encode(&mut encoder, Action::Run)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        out.extend(encoder.compress_to_vec(&[], Action::Finish).unwrap());
+        assert_eq!(out, compress(data, true));
+    }
+
+    #[test]
+    fn test_window_bits() {
+        use crate::core::iter;
+        use crate::deflate::decoder::DeflateCodeDecoder;
+        use crate::lzss::decoder::LzssDecoder;
+
+        let data = &include_bytes!("../../data/sample2.ref")[..0x1_0000];
+        for bits in 9..=15 {
+            let window = 1 << bits;
+            for &level in &[1, 6, 9] {
+                let mut encoder = Inflater::with_window_bits(bits);
+                encoder.set_level(level);
+                let out =
+                    encoder.compress_to_vec(data, Action::Finish).unwrap();
+                let codes = out
+                    .iter()
+                    .cloned()
+                    .decode(&mut DeflateCodeDecoder::new())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                assert!(codes.iter().all(|c| match *c {
+                    LzssCode::Reference { pos, .. } => pos < window,
+                    LzssCode::Symbol(_) => true,
+                }));
+                // 窓の大きさだけの復号器で読める
+                let decoded = codes
+                    .into_iter()
+                    .decode(&mut LzssDecoder::new(window))
+                    .collect::<Result<Vec<_>, _>>();
+                assert_eq!(decoded, Ok(data.to_vec()), "{} {}", bits, level);
+            }
+        }
+
+        let mut encoder = DeflateCodeEncoder::with_window_bits(9);
+        let codes = (0..512)
+            .map(|i| LzssCode::Symbol(i as u8))
+            .chain(iter::once(LzssCode::Reference { len: 3, pos: 511 }));
+        assert!(codes
+            .encode(&mut encoder, Action::Finish)
+            .all(|b| b.is_ok()));
+        let mut encoder = DeflateCodeEncoder::with_window_bits(9);
+        let codes = (0..513)
+            .map(|i| LzssCode::Symbol(i as u8))
+            .chain(iter::once(LzssCode::Reference { len: 3, pos: 512 }));
+        assert_eq!(
+            codes
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>(),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_window_bits_out_of_range() {
+        let _ = Inflater::with_window_bits(8);
+    }
+
+    #[test]
+    fn test_small_blocks() {
+        let text = (0..20)
+            .map(|i| format!("{} bottles of beer on the wall\n", 99 - i))
+            .collect::<String>();
+        // 256 バイト未満のブロックは、動的ハフマンの表を作らずに固定ハフマン
+        for len in (1..256).step_by(17) {
+            let data = &text.as_bytes()[..len];
+            let out = Inflater::new().compress_to_vec(data, Action::Finish);
+            let out = out.unwrap();
+            assert_eq!(out[0] & 0b111, 0b011, "{} bytes", len);
+            assert_eq!(
+                Deflater::new().decode_slice(&out),
+                Ok((data.to_vec(), out.len()))
+            );
+        }
+        // zlib の Z_FIXED と同じ出力
+        let out =
+            Inflater::new().compress_to_vec(b"Hello, world!", Action::Finish);
+        assert_eq!(
+            out.unwrap(),
+            [
+                0xF3, 0x48, 0xCD, 0xC9, 0xC9, 0xD7, 0x51, 0x28, 0xCF, 0x2F,
+                0xCA, 0x49, 0x51, 0x04, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixed_mode() {
+        let data = include_bytes!("../../data/sample1.ref");
+        let mut encoder = Inflater::new();
+        encoder.set_block_mode(BlockMode::Fixed);
+        let fixed = encoder.compress_to_vec(data, Action::Finish).unwrap();
+        assert_eq!(fixed[0] & 0b110, 0b010);
+        assert_eq!(
+            Deflater::new().decode_slice(&fixed),
+            Ok((data.to_vec(), fixed.len()))
+        );
+        let auto = Inflater::new().compress_to_vec(data, Action::Finish);
+        assert!(auto.unwrap().len() < fixed.len());
+
+        // ブロックの途中で変えても、そのブロックは始めのモードで書く
+        let mut encoder = Inflater::new();
+        let mut out =
+            encoder.compress_to_vec(&data[..5000], Action::Run).unwrap();
+        encoder.set_block_mode(BlockMode::Fixed);
+        out.extend(
+            encoder.compress_to_vec(&data[5000..], Action::Run).unwrap(),
+        );
+        encoder.set_block_mode(BlockMode::Auto);
+        out.extend(encoder.compress_to_vec(data, Action::Finish).unwrap());
+        assert_eq!(
+            Deflater::new().decode_slice(&out),
+            Ok(([&data[..], &data[..]].concat(), out.len()))
+        );
+    }
+
+    #[test]
+    fn test_sync_flush() {
+        let messages: [&[u8]; 4] = [
+            b"Hello, world!",
+            b"Hello again, world!",
+            b"",
+            b"Bye, world!",
+        ];
+        let mut encoder = Inflater::new();
+        let mut iter_encoder = Inflater::new();
+        let mut stream = Vec::new();
+        let mut sent = Vec::new();
+        for m in &messages {
+            let out = encoder.compress_to_vec(m, Action::Flush).unwrap();
+            let iter_out = m
+                .iter()
+                .cloned()
+                .encode(&mut iter_encoder, Action::Flush)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(out, iter_out);
+            // 間に入力がなければ何も書かない
+            assert_eq!(out.is_empty(), m.is_empty());
+            if !m.is_empty() {
+                assert!(out.ends_with(&[0, 0, 0xFF, 0xFF]));
+            }
+            stream.extend(out);
+            sent.extend_from_slice(m);
+            // そこまでの出力だけで、そこまでの入力がすべて戻る
+            let decoded = stream
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .take_while(Result::is_ok)
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(sent.clone()));
+        }
+        stream.extend(encoder.compress_to_vec(&[], Action::Finish).unwrap());
+        assert_eq!(
+            Deflater::new().decode_slice(&stream),
+            Ok((sent, stream.len()))
+        );
+
+        // Python の zlib で Z_FIXED と Z_SYNC_FLUSH を使ったものと同じ
+        assert_eq!(
+            Inflater::new().compress_to_vec(messages[0], Action::Flush),
+            Ok(vec![
+                0xF2, 0x48, 0xCD, 0xC9, 0xC9, 0xD7, 0x51, 0x28, 0xCF, 0x2F,
+                0xCA, 0x49, 0x51, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF
+            ])
+        );
+        // zlib が 2 つ目の通信文を書いたもの
+        let zlib = [
+            0xF2, 0x48, 0xCD, 0xC9, 0xC9, 0xD7, 0x51, 0x28, 0xCF, 0x2F, 0xCA,
+            0x49, 0x51, 0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xF2, 0x00, 0x71,
+            0x14, 0x12, 0xD3, 0x13, 0x33, 0xF3, 0x60, 0x42, 0x00, 0x00, 0x00,
+            0x00, 0xFF, 0xFF,
+        ];
+        let decoded = zlib
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .take_while(Result::is_ok)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok([messages[0], messages[1]].concat()));
+    }
+
+    #[test]
+    fn test_full_flush() {
+        use crate::deflate::decoder::DeflateCodeDecoder;
+
+        let data = &include_bytes!("../../data/sample2.ref")[..20000];
+        for &mode in &[
+            ParseMode::Lazy,
+            ParseMode::Fast,
+            ParseMode::Optimal,
+            ParseMode::Rle,
+        ] {
+            let mut encoder = Inflater::new();
+            encoder.set_parse_mode(mode);
+            let mut stream =
+                encoder.compress_to_vec(data, Action::Run).unwrap();
+            stream.extend(encoder.full_flush().unwrap());
+            let cut = stream.len();
+            // 同じデータを続けても、区切りの前は指さない
+            stream
+                .extend(encoder.compress_to_vec(data, Action::Finish).unwrap());
+            let (decoded, _) = Deflater::new().decode_slice(&stream).unwrap();
+            assert_eq!(decoded, [data, data].concat());
+
+            let suffix = &stream[cut..];
+            assert_eq!(
+                Deflater::new().decode_slice(suffix),
+                Ok((data.to_vec(), suffix.len())),
+                "{:?}",
+                mode
+            );
+            let mut len = 0;
+            for code in suffix
+                .iter()
+                .cloned()
+                .decode(&mut DeflateCodeDecoder::new())
+            {
+                len += match code.unwrap() {
+                    LzssCode::Symbol(_) => 1,
+                    LzssCode::Reference { len: l, pos } => {
+                        assert!(pos < len, "{:?}", mode);
+                        l
+                    }
+                };
+            }
+            assert_eq!(len, data.len());
+        }
+
+        // 符号の列でも、区切りの前を指すものは受け付けない
+        let mut encoder = DeflateCodeEncoder::new();
+        let codes = [LzssCode::Symbol(b'a'), LzssCode::Symbol(b'b')];
+        let mut out = codes
+            .iter()
+            .cloned()
+            .encode(&mut encoder, Action::Run)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        out.extend(encoder.full_flush().unwrap());
+        let reference = [LzssCode::Reference { len: 3, pos: 1 }];
+        let ret = reference
+            .iter()
+            .cloned()
+            .encode(&mut encoder, Action::Finish)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(ret, Err(CompressionError::DataError));
+    }
 }
@@ -9,34 +9,137 @@
 pub(crate) mod decoder;
 pub(crate) mod encoder;
 
+use crate::bitio::reader::BitRead;
 use crate::bitio::small_bit_vec::SmallBitVec;
-use crate::core::u16;
-#[cfg(not(feature = "std"))]
-#[allow(unused_imports)]
-use alloc::vec;
-#[cfg(not(feature = "std"))]
-use alloc::vec::Vec;
-
-fn fix_symbol_table() -> Vec<u8> {
-    let mut r = vec![8; 144];
-    r.append(&mut vec![9; 112]);
-    r.append(&mut vec![7; 24]);
-    r.append(&mut vec![8; 8]);
-    r
+
+/// Code lengths of the fixed literal/length code (RFC 1951 3.2.6).
+const FIX_SYMBOL_LENS: [u8; 288] = {
+    let mut lens = [8; 288];
+    let mut i = 144;
+    while i < 280 {
+        lens[i] = if i < 256 { 9 } else { 7 };
+        i += 1;
+    }
+    lens
+};
+
+static FIX_SYMBOL: FixedCode<288, 512> = FixedCode::new(FIX_SYMBOL_LENS);
+static FIX_OFFSET: FixedCode<32, 32> = FixedCode::new([5; 32]);
+
+/// Canonical Huffman code of `N` symbols built at compile time, for the
+/// `Right` direction.
+#[derive(Debug)]
+struct FixedCode<const N: usize, const L: usize> {
+    lens: [u8; N],
+    // 書き込む順にビットを反転した符号
+    codes: [u16; N],
+    // 次の log2(L) ビットで引く (記号, 符号長)
+    lookup: [(u16, u8); L],
 }
 
-fn fix_offset_table() -> &'static [u8; 32] {
-    &[5; 32]
+impl<const N: usize, const L: usize> FixedCode<N, L> {
+    /// Builds the code of `lens`, which must be a complete code of at
+    /// most `log2(L)` bits.
+    const fn new(lens: [u8; N]) -> Self {
+        let bits = L.trailing_zeros() as usize;
+        let mut count = [0_u16; 16];
+        let mut s = 0;
+        while s < N {
+            count[lens[s] as usize] += 1;
+            s += 1;
+        }
+        let mut next = [0_u16; 16];
+        let mut len = 2;
+        while len < 16 {
+            next[len] = (next[len - 1] + count[len - 1]) << 1;
+            len += 1;
+        }
+
+        let mut codes = [0; N];
+        let mut lookup = [(0, 0); L];
+        let mut s = 0;
+        while s < N {
+            let len = lens[s] as usize;
+            let code = next[len].reverse_bits() >> (16 - len);
+            next[len] += 1;
+            codes[s] = code;
+            let mut j = 0;
+            while j < 1 << (bits - len) {
+                lookup[code as usize | j << len] = (s as u16, len as u8);
+                j += 1;
+            }
+            s += 1;
+        }
+        Self {
+            lens,
+            codes,
+            lookup,
+        }
+    }
+
+    fn lens(&self) -> &[u8] {
+        &self.lens
+    }
+
+    fn enc(&self, symbol: u16) -> SmallBitVec<u16> {
+        let s = usize::from(symbol);
+        SmallBitVec::new(self.codes[s], usize::from(self.lens[s]))
+    }
+
+    fn lookup(&self) -> &[(u16, u8)] {
+        &self.lookup
+    }
 }
 
+/// Decodes a symbol with the lookup table of a `FixedCode`, the same way
+/// `HuffmanDecoder::dec` does: `None` at the end of the input, including
+/// when it ends inside a code.
+fn fixed_dec<R: BitRead, I: Iterator<Item = u8>>(
+    lookup: &[(u16, u8)],
+    reader: &mut R,
+    iter: &mut I,
+) -> Result<Option<u16>, &'static str> {
+    let bits = lookup.len().trailing_zeros() as usize;
+    let c = reader.peek_bits::<usize, _>(bits, iter)?;
+    // 足りないビットは 0 として引いたので、符号がそこに掛かれば途中で尽きた
+    let (symbol, len) = lookup[*c.data_ref()];
+    let len = usize::from(len);
+    if len > c.len() || reader.skip_bits(len, iter)? < len {
+        return Ok(None);
+    }
+    Ok(Some(symbol))
+}
+
+/// Base values and extra bits of the length or distance codes, with the
+/// code of each of the `V` values below the last base.
 #[derive(Debug)]
-struct CodeTable {
-    codes: Vec<u8>,
-    offsets: Vec<u16>,
-    ext_bits: Vec<u8>,
+struct CodeTable<const N: usize, const V: usize> {
+    codes: [u8; V],
+    offsets: [u16; N],
+    ext_bits: [u8; N],
 }
 
-impl CodeTable {
+impl<const N: usize, const V: usize> CodeTable<N, V> {
+    /// Builds the table of the codes starting at `offsets`, the last of
+    /// which only bounds the one before it.
+    const fn new(offsets: [u16; N], ext_bits: [u8; N]) -> Self {
+        let mut codes = [0; V];
+        let mut j = 0;
+        let mut i = 0;
+        while i < V {
+            while offsets[j + 1] as usize <= i {
+                j += 1;
+            }
+            codes[i] = j as u8;
+            i += 1;
+        }
+        Self {
+            codes,
+            offsets,
+            ext_bits,
+        }
+    }
+
     fn convert(&self, value: u16) -> (u8, SmallBitVec<u16>) {
         let pos = self.codes[value as usize];
         (
@@ -57,98 +160,159 @@ impl CodeTable {
     }
 }
 
-fn gen_codes(len: usize, offsets: &[u16]) -> Vec<u8> {
-    let mut codes = Vec::with_capacity(len);
-    let mut j = 0;
-    for i in 0..(len as u16) {
-        while offsets[j as usize + 1] <= i {
-            j += 1;
-        }
-        codes.push(j);
+static LEN_TAB: CodeTable<30, 256> = {
+    let mut offsets = [0; 30];
+    let mut ext_bits = [0; 30];
+    let mut i = 0;
+    while i < 8 {
+        offsets[i] = i as u16;
+        i += 1;
     }
-    codes
-}
-
-fn gen_len_tab() -> CodeTable {
-    let mut offsets = Vec::with_capacity(30);
-    let mut ext_bits = Vec::with_capacity(29);
-    for i in 0..8 {
-        offsets.push(i);
-        ext_bits.push(0);
-    }
-
-    for i in 8..28 {
+    while i < 28 {
         let n = (i >> 2) - 1;
-        offsets.push(u16::from(i & 3 | 4) << n);
-        ext_bits.push(n);
+        offsets[i] = ((i & 3 | 4) << n) as u16;
+        ext_bits[i] = n as u8;
+        i += 1;
     }
+    offsets[28] = 255;
+    offsets[29] = u16::MAX;
+    CodeTable::new(offsets, ext_bits)
+};
 
-    // 28
-    offsets.push(255);
-    ext_bits.push(0);
-
-    // 29
-    offsets.push(u16::MAX);
-
-    let codes = gen_codes(256, &offsets);
-
-    CodeTable {
-        codes,
-        offsets,
-        ext_bits,
+static OFF_TAB: CodeTable<31, 0x8000> = {
+    let mut offsets = [0; 31];
+    let mut ext_bits = [0; 31];
+    let mut i = 0;
+    while i < 4 {
+        offsets[i] = i as u16;
+        i += 1;
     }
-}
-
-fn gen_off_tab() -> CodeTable {
-    let mut offsets = Vec::with_capacity(31);
-    let mut ext_bits = Vec::with_capacity(30);
-    for i in 0..4 {
-        offsets.push(i);
-        ext_bits.push(0);
-    }
-
-    for i in 4..30 {
+    while i < 30 {
         let n = (i >> 1) - 1;
-        offsets.push(u16::from(i & 1 | 2) << n);
-        ext_bits.push(n);
+        offsets[i] = ((i & 1 | 2) << n) as u16;
+        ext_bits[i] = n as u8;
+        i += 1;
     }
-
-    // 30
-    offsets.push(u16::MAX);
-
-    let codes = gen_codes(0x8000, &offsets);
-
-    CodeTable {
-        codes,
-        offsets,
-        ext_bits,
-    }
-}
+    offsets[30] = u16::MAX;
+    CodeTable::new(offsets, ext_bits)
+};
 
 #[cfg(test)]
 mod tests {
+    use super::{fixed_dec, FixedCode, FIX_OFFSET, FIX_SYMBOL};
     use crate::action::Action;
-    use crate::deflate::decoder::Deflater;
-    use crate::deflate::encoder::Inflater;
+    use crate::bitio::direction::right::Right;
+    use crate::bitio::reader::BitRead;
+    use crate::bitio::slice_reader::SliceBitReader;
+    use crate::core::iter;
+    use crate::deflate::decoder::{DeflateCodeDecoder, Deflater};
+    use crate::deflate::encoder::{BlockMode, DeflateCodeEncoder, Inflater};
+    use crate::error::{CompressionError, ErrorKind};
+    use crate::huffman::decoder::HuffmanDecoder;
+    use crate::huffman::encoder::HuffmanEncoder;
+    use crate::lzss::encoder::ParseMode;
+    use crate::lzss::LzssCode;
     use crate::traits::decoder::DecodeExt;
     use crate::traits::encoder::EncodeExt;
     #[cfg(not(feature = "std"))]
+    use alloc::format;
+    #[cfg(not(feature = "std"))]
+    use alloc::string::{String, ToString};
+    #[cfg(not(feature = "std"))]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
-    use rand::distributions::Standard;
-    use rand::{thread_rng, Rng};
+    use rand::distr::StandardUniform;
+    use rand::{rng, RngExt};
+
+    fn fixed_code_checker<const N: usize, const L: usize>(
+        code: &FixedCode<N, L>,
+    ) {
+        let encoder = HuffmanEncoder::<Right, u16>::new(code.lens());
+        for s in 0..N as u16 {
+            assert_eq!(code.enc(s), encoder.enc(s).unwrap());
+        }
+        let mut decoder =
+            HuffmanDecoder::<Right>::new(code.lens(), 12).unwrap();
+        for v in 0..L as u16 {
+            let data = v.to_le_bytes();
+            let mut expected = SliceBitReader::<Right>::new(&data);
+            let mut reader = SliceBitReader::<Right>::new(&data);
+            assert_eq!(
+                fixed_dec(code.lookup(), &mut reader, &mut iter::empty()),
+                Ok(decoder.dec(&mut expected, &mut iter::empty()).unwrap())
+            );
+            assert_eq!(reader.bit_position(), expected.bit_position());
+        }
+    }
+
+    #[test]
+    fn test_fixed_codes() {
+        fixed_code_checker(&FIX_SYMBOL);
+        fixed_code_checker(&FIX_OFFSET);
+    }
+
+    #[test]
+    fn test_unused_fixed_codes() {
+        // 固定ハフマンのブロックで長さ符号 286 と距離符号 30
+        for data in &[[27, 3], [3, 62]] {
+            assert_eq!(
+                Deflater::new().decode_slice(data).map_err(|e| e.kind()),
+                Err(ErrorKind::DataError)
+            );
+        }
+    }
+
+    #[test]
+    fn test_truncated_fixed_block() {
+        let mut encoder = Inflater::new();
+        encoder.set_block_mode(BlockMode::Fixed);
+        let data = b"aabbaabbaaabbbaaabbbaabbaabb".repeat(10);
+        let encoded = encoder.compress_to_vec(&data, Action::Finish).unwrap();
+        assert_eq!(encoded[0] & 0b110, 0b010);
+        // 足りないビットを 0 とすると、7 ビットの 0 の符号がブロックの終わりになる
+        for encoded in &[&[0x4b, 0x04, 0x00][..], &encoded] {
+            for len in 0..encoded.len() {
+                assert_eq!(
+                    Deflater::new()
+                        .decode_slice(&encoded[..len])
+                        .map_err(|e| e.kind()),
+                    Err(ErrorKind::UnexpectedEof),
+                    "{} of {} bytes",
+                    len,
+                    encoded.len()
+                );
+            }
+            assert!(Deflater::new().decode_slice(encoded).is_ok());
+        }
+    }
 
     fn check(testarray: &[u8]) {
         let encoded = testarray
             .to_vec()
             .encode(&mut Inflater::new(), Action::Finish)
-            .collect::<Result<Vec<_>, _>>();
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
         let decoded = encoded
-            .unwrap()
+            .iter()
+            .cloned()
             .decode(&mut Deflater::new())
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
 
         assert_eq!(testarray.to_vec(), decoded);
+        let mut window = vec![0; 0x8000];
+        let mut output = vec![0; decoded.len()];
+        assert_eq!(
+            Deflater::with_buffer(&mut window[..])
+                .and_then(|mut d| d.decode_slice_into(&encoded, &mut output)),
+            Ok((decoded.len(), encoded.len()))
+        );
+        assert_eq!(output, decoded);
+        assert_eq!(
+            Deflater::new().decode_slice(&encoded),
+            Ok((decoded, encoded.len()))
+        );
     }
 
     #[test]
@@ -189,34 +353,49 @@ mod tests {
 
     #[test]
     fn test_multiblocks() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks2() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks3() {
-        let rng = thread_rng();
+        let rng = rng();
 
         check(
             &(rng
-                .sample_iter(&Standard)
+                .sample_iter(StandardUniform)
                 .take(0xF_FFFF)
                 .collect::<Vec<_>>()),
         );
     }
 
     fn test_rand_with_len(len: usize) {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(len).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(len)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
@@ -233,4 +412,858 @@ mod tests {
     fn test_multiblocks5() {
         test_rand_with_len(0x10_0001);
     }
+
+    #[test]
+    fn test_incompressible() {
+        let data = rng()
+            .sample_iter(StandardUniform)
+            .take(16 * 0xFFFF)
+            .collect::<Vec<u8>>();
+        let encoded = Inflater::new().compress_to_vec(&data, Action::Finish);
+        let encoded = encoded.unwrap();
+        assert!(encoded.len() <= data.len() + data.len() / 1000);
+        // 無圧縮のブロックが、ヘッダの後で揃えて並ぶ。一致がブロックの境目を
+        // またぐと最後に短い端数が残り、それは固定ハフマンの方が小さくなりうる
+        let mut pos = 0;
+        let mut stored_len = 0;
+        loop {
+            let is_final = encoded[pos] & 1 == 1;
+            if encoded[pos] & 0b110 != 0 {
+                assert!(is_final);
+                assert!(data.len() - stored_len <= 258);
+                break;
+            }
+            let len = u16::from_le_bytes([encoded[pos + 1], encoded[pos + 2]]);
+            let nlen = u16::from_le_bytes([encoded[pos + 3], encoded[pos + 4]]);
+            assert_eq!(nlen, !len);
+            pos += 5 + usize::from(len);
+            stored_len += usize::from(len);
+            if is_final {
+                assert_eq!(pos, encoded.len());
+                break;
+            }
+        }
+        assert_eq!(
+            Deflater::new().decode_slice(&encoded),
+            Ok((data, encoded.len()))
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let encoded = b"aabbaabbaaabbbaaabbbaabbaabb"
+            .repeat(100)
+            .encode(&mut Inflater::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let decoded = encoded[..encoded.len() / 2]
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(decoded, Err(CompressionError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_stored_block() {
+        let encoded = [
+            0x01, 0x11, 0x00, 0xEE, 0xFF, 0x73, 0x74, 0x6F, 0x72, 0x65, 0x64,
+            0x20, 0x62, 0x6C, 0x6F, 0x63, 0x6B, 0x20, 0x64, 0x61, 0x74, 0x61,
+        ];
+        let decoded = encoded
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(b"stored block data".to_vec()));
+
+        let decoded = encoded[..12]
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Err(CompressionError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_large_stored_blocks() {
+        let data = rng()
+            .sample_iter(StandardUniform)
+            .take(0x2_8000)
+            .collect::<Vec<u8>>();
+        // 最長の無圧縮ブロックを並べ、最後のブロックはない
+        let mut encoded = Vec::new();
+        for chunk in data.chunks(0xFFFF) {
+            let len = chunk.len() as u16;
+            encoded.push(0x00);
+            encoded.extend_from_slice(&len.to_le_bytes());
+            encoded.extend_from_slice(&(!len).to_le_bytes());
+            encoded.extend_from_slice(chunk);
+        }
+        // 続くブロックは無圧縮ブロックの中身を参照する
+        let text = data[data.len() - 0x7000..].repeat(2);
+        let mut encoder = Inflater::new();
+        encoder.set_dictionary(&data).unwrap();
+        let tail = encoder.compress_to_vec(&text, Action::Finish).unwrap();
+        assert!(tail.len() < 0x1000);
+        encoded.extend_from_slice(&tail);
+        let mut expected = data.clone();
+        expected.extend_from_slice(&text);
+
+        assert_eq!(
+            encoded
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>(),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            Deflater::new().decode_slice(&encoded),
+            Ok((expected.clone(), encoded.len()))
+        );
+        let mut output = vec![0; expected.len()];
+        assert_eq!(
+            Deflater::with_buffer([0; 0x8000])
+                .unwrap()
+                .decode_slice_into(&encoded, &mut output),
+            Ok((expected.len(), encoded.len()))
+        );
+        assert_eq!(output, expected);
+
+        // ブロックの途中で入力が尽きる
+        assert_eq!(
+            encoded[..0x1_2345]
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>(),
+            Err(CompressionError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_empty_stored_blocks() {
+        // 同期フラッシュの空の無圧縮ブロックが続く
+        let mut encoded = [0x00, 0x00, 0x00, 0xFF, 0xFF].repeat(3);
+        encoded.extend(&[0x01, 0x03, 0x00, 0xFC, 0xFF, 0x61, 0x62, 0x63]);
+        assert_eq!(
+            encoded
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>(),
+            Ok(b"abc".to_vec())
+        );
+        assert_eq!(
+            Deflater::new().decode_slice(&encoded),
+            Ok((b"abc".to_vec(), encoded.len()))
+        );
+        assert_eq!(
+            Deflater::new().decode_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]),
+            Ok((Vec::new(), 5))
+        );
+    }
+
+    fn push_bits(bits: &mut Vec<bool>, value: u32, len: usize) {
+        bits.extend((0..len).map(|i| (value >> i) & 1 == 1));
+    }
+
+    /// Builds a final dynamic block from the literal/length code lengths
+    /// `lit`, the distance code lengths `dist` and the raw bits `data`.
+    fn dynamic_block(lit: &[u8], dist: &[u8], data: &[bool]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        push_bits(&mut bits, 1, 1);
+        push_bits(&mut bits, 2, 2);
+        push_bits(&mut bits, lit.len() as u32 - 257, 5);
+        push_bits(&mut bits, dist.len() as u32 - 1, 5);
+        // 符号長の符号は 0, 1, 2, 18 を 2 ビットずつ (00, 01, 10, 11)
+        push_bits(&mut bits, 14, 4);
+        for &l in &[0, 0, 2, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2] {
+            push_bits(&mut bits, l, 3);
+        }
+        let mut lens = lit.iter().chain(dist).peekable();
+        while let Some(&l) = lens.next() {
+            let mut zeros = 1;
+            while l == 0 && zeros < 138 && lens.peek() == Some(&&0) {
+                let _ = lens.next();
+                zeros += 1;
+            }
+            if zeros >= 11 {
+                bits.extend(&[true, true]);
+                push_bits(&mut bits, zeros - 11, 7);
+            } else {
+                for _ in 0..zeros {
+                    bits.extend(&[l & 2 != 0, l & 1 != 0]);
+                }
+            }
+        }
+        bits.extend(data);
+        bits.chunks(8)
+            .map(|c| c.iter().rev().fold(0, |a, &b| (a << 1) | b as u8))
+            .collect()
+    }
+
+    fn decode_dynamic(
+        lit: &[(usize, u8)],
+        dist: &[u8],
+    ) -> Result<Vec<u8>, CompressionError> {
+        let mut lens = vec![0; 257];
+        for &(s, l) in lit {
+            lens[s] = l;
+        }
+        // 'a' = 0, 256 = 1 の符号で "aa" と終端
+        dynamic_block(&lens, dist, &[false, false, true])
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    #[test]
+    fn test_bad_huffman_tables() {
+        assert_eq!(
+            decode_dynamic(&[(97, 1), (256, 1)], &[1]),
+            Ok(b"aa".to_vec())
+        );
+        // zlib と同様に、不完全な距離符号は長さ 1 の符号 1 つのものと、
+        // 符号がないものだけを受け付ける
+        assert_eq!(
+            decode_dynamic(&[(97, 1), (256, 1)], &[0]),
+            Ok(b"aa".to_vec())
+        );
+        let err = decode_dynamic(&[(97, 1), (256, 1)], &[1, 2]).unwrap_err();
+        assert_eq!(err.reason(), Some("invalid distances set"));
+        let err = decode_dynamic(&[(97, 1), (98, 1)], &[1]).unwrap_err();
+        assert_eq!(err.reason(), Some("invalid code -- missing end-of-block"));
+        assert_eq!(
+            decode_dynamic(&[(97, 1), (98, 1), (256, 1)], &[1]),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(
+            decode_dynamic(&[(97, 1), (256, 2)], &[1]),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(
+            decode_dynamic(&[(97, 1), (256, 1)], &[1, 1, 1]),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    #[test]
+    fn test_zlib_bad_streams() {
+        // zlib の infcover.c の壊れたストリームと、zlib の返すメッセージ
+        let bad: &[(&[u8], &str)] = &[
+            (
+                &[0x00, 0x00, 0x00, 0x00, 0x00],
+                "invalid stored block lengths",
+            ),
+            (&[0x06], "invalid block type"),
+            (&[0xFC, 0x00, 0x00], "too many length or distance symbols"),
+            (&[0x04, 0x00, 0xFE, 0xFF], "invalid code lengths set"),
+            (&[0x04, 0x00, 0x24, 0x49, 0x00], "invalid bit length repeat"),
+            (
+                &[0x04, 0x00, 0x24, 0xE9, 0xFF, 0xFF],
+                "invalid bit length repeat",
+            ),
+            (
+                &[0x04, 0x00, 0x24, 0xE9, 0xFF, 0x6D],
+                "invalid code -- missing end-of-block",
+            ),
+            (
+                &[
+                    0x04, 0x80, 0x49, 0x92, 0x24, 0x49, 0x92, 0x24, 0x71, 0xFF,
+                    0xFF, 0x93, 0x11, 0x00,
+                ],
+                "invalid literal/lengths set",
+            ),
+            (
+                &[
+                    0x04, 0x80, 0x49, 0x92, 0x24, 0x49, 0x92, 0x24, 0x0F, 0xB4,
+                    0xFF, 0xFF, 0xC3, 0x84,
+                ],
+                "invalid distances set",
+            ),
+            (
+                &[
+                    0x04, 0xC0, 0x81, 0x08, 0x00, 0x00, 0x00, 0x00, 0x20, 0x7F,
+                    0xEB, 0x0B, 0x00, 0x00,
+                ],
+                "invalid literal/length code",
+            ),
+            (&[0x02, 0x7E, 0xFF, 0xFF], "invalid distance code"),
+            (
+                &[
+                    0x0C, 0xC0, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x90, 0xFF,
+                    0x6B, 0x04, 0x00,
+                ],
+                "invalid distance too far back",
+            ),
+        ];
+        for &(input, reason) in bad {
+            let err = Deflater::new().decode_slice(input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::DataError, "{}", reason);
+            assert_eq!(err.reason(), Some(reason));
+            let decoded = input
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded.map_err(|e| e.reason()), Err(Some(reason)));
+        }
+        // 同じく正しいストリームと、zlib の出力の長さ
+        let good: &[(&[u8], usize)] = &[
+            // fixed
+            (&[0x03, 0x00], 0),
+            // stored
+            (&[0x01, 0x01, 0x00, 0xFE, 0xFF, 0x00], 1),
+            // pull 17
+            (
+                &[
+                    0x05, 0xC0, 0x21, 0x0D, 0x00, 0x00, 0x00, 0x80, 0xB0, 0xFE,
+                    0x6D, 0x2F, 0x91, 0x6C,
+                ],
+                0,
+            ),
+            // long code
+            (
+                &[
+                    0x05, 0xE0, 0x81, 0x91, 0x24, 0xCB, 0xB2, 0x2C, 0x49, 0xE2,
+                    0x0F, 0x2E, 0x8B, 0x9A, 0x47, 0x56, 0x9F, 0xFB, 0xFE, 0xEC,
+                    0xD2, 0xFF, 0x1F,
+                ],
+                0,
+            ),
+            // length extra
+            (
+                &[
+                    0xED, 0xC0, 0x01, 0x01, 0x00, 0x00, 0x00, 0x40, 0x20, 0xFF,
+                    0x57, 0x1B, 0x42, 0x2C, 0x4F,
+                ],
+                516,
+            ),
+            // long distance and extra
+            (
+                &[
+                    0xED, 0xCF, 0xC1, 0xB1, 0x2C, 0x47, 0x10, 0xC4, 0x30, 0xFA,
+                    0x6F, 0x35, 0x1D, 0x01, 0x82, 0x59, 0x3D, 0xFB, 0xBE, 0x2E,
+                    0x2A, 0xFC, 0x0F, 0x0C,
+                ],
+                518,
+            ),
+            // inflate_fast TYPE return
+            (&[0x02, 0x08, 0x20, 0x80, 0x00, 0x03, 0x00], 0),
+        ];
+        for &(input, len) in good {
+            let (decoded, _) = Deflater::new().decode_slice(input).unwrap();
+            assert_eq!(decoded, vec![0; len]);
+        }
+    }
+
+    #[test]
+    fn test_length_runs() {
+        // 0 の連続がリテラル/長さの符号長から距離の符号長へまたがる
+        let mut lit = vec![0; 258];
+        lit[97] = 1;
+        lit[256] = 1;
+        let mut dist = vec![0; 12];
+        dist.push(1);
+        let decode = |input: &[u8]| {
+            input
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let input = dynamic_block(&lit, &dist, &[false, false, true]);
+        assert_eq!(decode(&input), Ok(b"aa".to_vec()));
+        // HDIST を減らし、0 の連続が符号長の数を越える
+        let mut input = dynamic_block(&lit, &dist, &[false, false, true]);
+        input[1] = (input[1] & !0x1F) | 4;
+        assert_eq!(decode(&input), Err(CompressionError::DataError));
+    }
+
+    #[test]
+    fn test_error_offset() {
+        // 最終ブロック, 無圧縮, LEN と NLEN が一致しない
+        let input = [0x01, 0x00, 0x00, 0x00, 0x00, 0x61];
+        let err = input
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert_eq!(err, CompressionError::DataError);
+        assert_eq!(err.bit_offset(), Some(40));
+        assert_eq!(err.checksums(), Some((0, 0xFFFF)));
+        assert_eq!(
+            err.to_string(),
+            "data integrity error in data: invalid stored block lengths \
+             (expected 0x0000, computed 0xffff) at byte 5, bit 0"
+        );
+        assert_eq!(
+            Deflater::new()
+                .decode_slice(&input)
+                .unwrap_err()
+                .bit_offset(),
+            Some(40)
+        );
+    }
+
+    #[test]
+    fn test_distance_before_start() {
+        // 固定ハフマンの最終ブロックで、符号は上位ビットから書く
+        let block = |dict: &[u8], lit: Option<u8>, dist: u32| {
+            let mut bits = Vec::new();
+            push_bits(&mut bits, 1, 1);
+            push_bits(&mut bits, 1, 2);
+            let mut code = |value: u32, len: usize| {
+                bits.extend((0..len).rev().map(|i| (value >> i) & 1 == 1));
+            };
+            if let Some(c) = lit {
+                code(0x30 + u32::from(c), 8);
+            }
+            // 長さ 3 の符号 257 と距離符号 dist
+            code(1, 7);
+            code(dist, 5);
+            code(0, 7);
+            let input = bits
+                .chunks(8)
+                .map(|c| c.iter().rev().fold(0, |a, &b| (a << 1) | b as u8))
+                .collect::<Vec<u8>>();
+            let decoded = input
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::with_dict(dict))
+                .collect::<Result<Vec<_>, _>>();
+            let sliced = Deflater::with_dict(dict)
+                .decode_slice(&input)
+                .map(|(d, _)| d);
+            assert_eq!(decoded, sliced);
+            decoded
+        };
+        assert_eq!(block(b"", None, 0), Err(CompressionError::DataError));
+        assert_eq!(block(b"", Some(b'a'), 0), Ok(b"aaaa".to_vec()));
+        // 距離 2 は 1 バイトの出力より前を指す
+        assert_eq!(block(b"", Some(b'a'), 1), Err(CompressionError::DataError));
+        assert_eq!(block(b"b", Some(b'a'), 1), Ok(b"abab".to_vec()));
+        assert_eq!(block(b"b", None, 0), Ok(b"bbb".to_vec()));
+    }
+
+    #[test]
+    fn test_distance_too_far_back() {
+        // 固定ハフマンの最終ブロックの最初の符号が長さ 3, 距離 1 の参照。
+        // zlib は "invalid distance too far back" とする
+        let input = [0x03, 0x02, 0x00];
+        assert_eq!(
+            input
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>(),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(
+            Deflater::new().decode_slice(&input).map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+        // 与えられた窓に残っている値は履歴ではない
+        let mut window = vec![b'x'; 0x8000];
+        let mut output = [0; 3];
+        assert_eq!(
+            Deflater::with_buffer(&mut window[..])
+                .and_then(|mut d| d.decode_slice_into(&input, &mut output))
+                .map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+        assert_eq!(
+            Deflater::with_dict(b"x").decode_slice(&input),
+            Ok((b"xxx".to_vec(), input.len()))
+        );
+    }
+
+    #[test]
+    fn test_code_round_trip() {
+        let input = include_bytes!("../../data/sample1.ref");
+        let encoded = input
+            .to_vec()
+            .encode(&mut Inflater::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let codes = encoded
+            .iter()
+            .cloned()
+            .decode(&mut DeflateCodeDecoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // 同じ符号からは同じブロックが作られる
+        let reencoded = codes
+            .iter()
+            .cloned()
+            .encode(&mut DeflateCodeEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(reencoded, Ok(encoded));
+    }
+
+    #[test]
+    fn test_code_encoder_limits() {
+        let encode = |dict: &[u8], codes: &[LzssCode]| {
+            codes
+                .iter()
+                .cloned()
+                .encode(
+                    &mut DeflateCodeEncoder::with_dict(dict),
+                    Action::Finish,
+                )
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|e| {
+                    e.iter()
+                        .cloned()
+                        .decode(&mut Deflater::with_dict(dict))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+        };
+        let sym = LzssCode::Symbol(b'a');
+        let reference = |len, pos| LzssCode::Reference { len, pos };
+        assert_eq!(
+            encode(b"", &[reference(3, 0)]),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(encode(b"b", &[reference(3, 0)]), Ok(b"bbb".to_vec()));
+        assert_eq!(
+            encode(b"", &[sym, reference(2, 0)]),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(encode(b"", &[sym, reference(258, 0)]), Ok(vec![b'a'; 259]));
+        assert_eq!(
+            encode(b"", &[sym, reference(259, 0)]),
+            Err(CompressionError::DataError)
+        );
+        let dict = vec![b'b'; 0x9000];
+        assert_eq!(encode(&dict, &[reference(3, 0x7FFF)]), Ok(b"bbb".to_vec()));
+        assert_eq!(
+            encode(&dict, &[reference(3, 0x8000)]),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    #[test]
+    fn test_long_runs() {
+        let mut input = vec![b'a'; 100_000];
+        input.extend(b"ab".iter().cycle().take(50_000));
+        input.extend(vec![b'z'; 1000]);
+        for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast] {
+            let mut encoder = Inflater::new();
+            encoder.set_parse_mode(mode);
+            let encoded = input
+                .iter()
+                .cloned()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let codes = encoded
+                .iter()
+                .cloned()
+                .decode(&mut DeflateCodeDecoder::new())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let longest = codes
+                .iter()
+                .map(|c| match *c {
+                    LzssCode::Reference { len, .. } => len,
+                    LzssCode::Symbol(_) => 0,
+                })
+                .max();
+            assert_eq!(longest, Some(258));
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut Deflater::new())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(input.clone()));
+        }
+    }
+
+    #[test]
+    fn test_zlib_streams() {
+        let text = (90..100)
+            .rev()
+            .map(|i| {
+                format!(
+                    "{0} bottles of beer on the wall, {0} bottles of beer.\n",
+                    i
+                )
+            })
+            .collect::<String>();
+        // Python の zlib.compressobj(level, DEFLATED, -15, 9, strategy) で
+        // 作った、ヘッダのない deflate
+        let dynamic = [
+            0x85, 0xCB, 0xCB, 0x09, 0x80, 0x30, 0x10, 0x05, 0xC0, 0xBB, 0x55,
+            0x6C, 0x01, 0x22, 0x89, 0xF9, 0x97, 0x63, 0x60, 0xC5, 0x43, 0x30,
+            0xA0, 0x01, 0xDB, 0xB7, 0x80, 0x3C, 0x78, 0xE7, 0x61, 0x4A, 0x91,
+            0xDA, 0xC7, 0x68, 0xFA, 0x4A, 0x3F, 0xA5, 0xAA, 0x3E, 0xD2, 0x6F,
+            0x19, 0x97, 0xCA, 0x77, 0xB4, 0xB6, 0x4A, 0x99, 0x7C, 0x5B, 0x4A,
+            0x26, 0x27, 0x83, 0x93, 0xC8, 0x49, 0xE0, 0x44, 0x72, 0x22, 0x38,
+            0x81, 0x9C, 0x00, 0x8E, 0x27, 0xC7, 0x83, 0xE3, 0xC8, 0x71, 0xE0,
+            0xEC, 0xE4, 0xEC, 0xE0, 0x58, 0x72, 0x2C, 0x38, 0x86, 0x1C, 0x33,
+            0x9F, 0x1F,
+        ];
+        let fixed = [
+            0xB3, 0xB4, 0x54, 0x48, 0xCA, 0x2F, 0x29, 0xC9, 0x49, 0x2D, 0x56,
+            0xC8, 0x4F, 0x53, 0x48, 0x4A, 0x4D, 0x2D, 0x52, 0xC8, 0xCF, 0x53,
+            0x28, 0xC9, 0x48, 0x55, 0x28, 0x4F, 0xCC, 0xC9, 0xD1, 0x51, 0xB0,
+            0xC4, 0x90, 0xD7, 0xE3, 0xB2, 0xB4, 0x20, 0xA0, 0x07, 0x43, 0x1E,
+            0xA8, 0xC7, 0x9C, 0x80, 0x1E, 0x0C, 0x79, 0xA0, 0x1E, 0x33, 0x02,
+            0x7A, 0x30, 0xE4, 0x81, 0x7A, 0x4C, 0x09, 0xE8, 0xC1, 0x90, 0x07,
+            0xEA, 0x31, 0x21, 0xA0, 0x07, 0x43, 0x1E, 0xA8, 0xC7, 0x98, 0x80,
+            0x1E, 0x0C, 0x79, 0xA0, 0x1E, 0x23, 0x02, 0x7A, 0x30, 0xE4, 0x81,
+            0x7A, 0x0C, 0x09, 0xE8, 0xC1, 0x90, 0x07, 0xEA, 0x31, 0x20, 0xA0,
+            0x07, 0x43, 0x5E, 0x8F, 0x0B, 0x00,
+        ];
+        let mut stored = vec![0x01, 0x08, 0x02, 0xF7, 0xFD];
+        stored.extend_from_slice(text.as_bytes());
+        for encoded in &[&dynamic[..], &fixed, &stored] {
+            assert_eq!(
+                Deflater::new().decode_slice(encoded),
+                Ok((text.as_bytes().to_vec(), encoded.len()))
+            );
+            // zlib と同じく、途中で切れたものは不完全なストリームとする。
+            // 切れる前までの符号は、元のストリームの符号の先頭と同じ
+            let codes = encoded
+                .iter()
+                .cloned()
+                .decode(&mut DeflateCodeDecoder::new())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            for len in 0..encoded.len() {
+                let truncated = &encoded[..len];
+                assert_eq!(
+                    Deflater::new()
+                        .decode_slice(truncated)
+                        .map_err(|e| e.kind()),
+                    Err(ErrorKind::UnexpectedEof),
+                    "{} of {} bytes",
+                    len,
+                    encoded.len()
+                );
+                // エラーの後も Err を返し続けるので、最初のエラーで止める
+                let mut decoder = DeflateCodeDecoder::new();
+                let mut iter = truncated.iter().cloned().decode(&mut decoder);
+                let mut decoded = Vec::new();
+                let err = loop {
+                    match iter.next() {
+                        Some(Ok(code)) => decoded.push(code),
+                        Some(Err(e)) => break Some(e.kind()),
+                        None => break None,
+                    }
+                };
+                assert_eq!(
+                    err,
+                    Some(ErrorKind::UnexpectedEof),
+                    "{} of {} bytes",
+                    len,
+                    encoded.len()
+                );
+                assert_eq!(decoded, codes[..decoded.len()]);
+            }
+        }
+        // 符号化したものは Deflater だけで元に戻り、ヘッダも検査値もない
+        let encoded =
+            Inflater::new().compress_to_vec(text.as_bytes(), Action::Finish);
+        let encoded = encoded.unwrap();
+        assert!(encoded.len() <= dynamic.len() + dynamic.len() / 10);
+        assert_eq!(
+            Deflater::new().decode_slice(&encoded),
+            Ok((text.into_bytes(), encoded.len()))
+        );
+    }
+
+    #[test]
+    fn test_set_dictionary() {
+        let dict = b"The quick brown fox jumps over the lazy dog. ";
+        let data =
+            b"The quick brown fox jumps over the lazy dog again and again.";
+        let mut encoder = Inflater::new();
+        encoder.set_dictionary(dict).unwrap();
+        let encoded = encoder.compress_to_vec(data, Action::Finish).unwrap();
+        // 最初の符号から辞書を指す
+        let codes = encoded
+            .iter()
+            .cloned()
+            .decode(&mut DeflateCodeDecoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(codes[0], LzssCode::Reference { len: 43, pos: 44 });
+        let mut decoder = Deflater::new();
+        decoder.set_dictionary(dict).unwrap();
+        assert_eq!(
+            decoder.decode_slice(&encoded),
+            Ok((data.to_vec(), encoded.len()))
+        );
+        assert_eq!(
+            Deflater::with_dict(dict).decode_slice(&encoded),
+            Ok((data.to_vec(), encoded.len()))
+        );
+        // Python の zlib.compressobj(zdict=dict, wbits=-15) の出力
+        let zlib = [
+            0x0B, 0x21, 0x5E, 0xB1, 0x42, 0x62, 0x7A, 0x62, 0x66, 0x9E, 0x42,
+            0x62, 0x5E, 0x0A, 0x84, 0xA5, 0x07, 0x00,
+        ];
+        let mut decoder = Deflater::new();
+        decoder.set_dictionary(dict).unwrap();
+        assert_eq!(
+            decoder.decode_slice(&zlib),
+            Ok((data.to_vec(), zlib.len()))
+        );
+    }
+
+    #[test]
+    fn test_long_dictionary() {
+        let dict = rng()
+            .sample_iter(StandardUniform)
+            .take(100_000)
+            .collect::<Vec<u8>>();
+        // 辞書の末尾 32 KiB の中だけを指せる
+        let data =
+            [&dict[dict.len() - 0x8000..][..1000], &dict[99_000..]].concat();
+        let mut encoder = Inflater::new();
+        encoder.set_dictionary(&dict).unwrap();
+        let encoded = encoder.compress_to_vec(&data, Action::Finish).unwrap();
+        assert!(encoded.len() < 100, "{} bytes", encoded.len());
+        let mut decoder = Deflater::new();
+        decoder.set_dictionary(&dict).unwrap();
+        assert_eq!(
+            decoder.decode_slice(&encoded),
+            Ok((data.to_vec(), encoded.len()))
+        );
+        let mut encoder = DeflateCodeEncoder::new();
+        encoder.set_dictionary(&dict).unwrap();
+        let encoded = iter::once(LzssCode::Reference {
+            len: 258,
+            pos: 0x7FFF,
+        })
+        .encode(&mut encoder, Action::Finish)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(
+            Deflater::with_dict(&dict).decode_slice(&encoded),
+            Ok((dict[dict.len() - 0x8000..][..258].to_vec(), encoded.len()))
+        );
+    }
+
+    #[test]
+    fn test_dictionary_after_start() {
+        let mut encoder = Inflater::new();
+        encoder.compress_to_vec(b"abc", Action::Run).unwrap();
+        assert_eq!(
+            encoder.set_dictionary(b"abc"),
+            Err(CompressionError::Unsupported)
+        );
+        let mut encoder = DeflateCodeEncoder::new();
+        let _ = iter::empty().encode(&mut encoder, Action::Run).count();
+        assert_eq!(
+            encoder.set_dictionary(b"abc"),
+            Err(CompressionError::Unsupported)
+        );
+        let encoded = Inflater::new().compress_to_vec(b"abc", Action::Finish);
+        let mut decoder = Deflater::new();
+        decoder.decode_slice(&encoded.unwrap()).unwrap();
+        assert_eq!(
+            decoder.set_dictionary(b"abc"),
+            Err(CompressionError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn test_deflate64() {
+        // 無圧縮のブロックのあとに、32 KiB より遠い参照の固定ハフマンの
+        // ブロック
+        let data = rng()
+            .sample_iter(StandardUniform)
+            .take(60_000)
+            .collect::<Vec<u8>>();
+        let mut input = vec![0x00];
+        input.extend(&(data.len() as u16).to_le_bytes());
+        input.extend(&(!data.len() as u16).to_le_bytes());
+        input.extend(&data);
+        let mut bits = Vec::new();
+        push_bits(&mut bits, 1, 1);
+        push_bits(&mut bits, 1, 2);
+        // 符号は上位ビットから書く
+        let code = |bits: &mut Vec<bool>, value: u32, len: usize| {
+            bits.extend((0..len).rev().map(|i| (value >> i) & 1 == 1));
+        };
+        // 長さ符号 285 と距離符号 31, 30
+        code(&mut bits, 0xC5, 8);
+        push_bits(&mut bits, 1000, 16);
+        code(&mut bits, 31, 5);
+        push_bits(&mut bits, 5000, 14);
+        code(&mut bits, 0xC5, 8);
+        push_bits(&mut bits, 0xFFFF, 16);
+        code(&mut bits, 30, 5);
+        push_bits(&mut bits, 0, 14);
+        code(&mut bits, 0, 7);
+        input.extend(
+            bits.chunks(8)
+                .map(|c| c.iter().rev().fold(0, |a, &b| (a << 1) | b as u8)),
+        );
+
+        let mut expected = data.clone();
+        for &(len, dist) in &[(1003, 54_153), (65_538, 32_769)] {
+            for _ in 0..len {
+                expected.push(expected[expected.len() - dist]);
+            }
+        }
+        let decoded = input
+            .iter()
+            .cloned()
+            .decode(&mut Deflater::deflate64())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(expected.clone()));
+        assert_eq!(
+            Deflater::deflate64().decode_slice(&input),
+            Ok((expected, input.len()))
+        );
+        let codes = input
+            .iter()
+            .cloned()
+            .decode(&mut DeflateCodeDecoder::deflate64())
+            .skip(data.len())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(
+            codes,
+            Ok(vec![
+                LzssCode::Reference {
+                    len: 1003,
+                    pos: 54_152,
+                },
+                LzssCode::Reference {
+                    len: 65_538,
+                    pos: 32_768,
+                },
+            ])
+        );
+        // 'a' のあとの長さ符号 285 と距離 1 は、deflate では 258 バイト
+        // の繰り返し
+        let mut bits = Vec::new();
+        push_bits(&mut bits, 1, 1);
+        push_bits(&mut bits, 1, 2);
+        code(&mut bits, 0x30 + u32::from(b'a'), 8);
+        code(&mut bits, 0xC5, 8);
+        push_bits(&mut bits, 0, 16);
+        code(&mut bits, 0, 5);
+        code(&mut bits, 0, 7);
+        let input = bits
+            .chunks(8)
+            .map(|c| c.iter().rev().fold(0, |a, &b| (a << 1) | b as u8))
+            .collect::<Vec<u8>>();
+        assert_eq!(
+            Deflater::deflate64().decode_slice(&input).unwrap().0,
+            b"aaaa"
+        );
+        // deflate は 16 ビットの 0 を距離符号 0 と終端として読む
+        let decoded = Deflater::new().decode_slice(&input).unwrap().0;
+        assert_eq!(decoded, vec![b'a'; 259]);
+    }
 }
@@ -8,15 +8,145 @@
 use crate::core::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CompressionError {
+pub enum ErrorKind {
     DataError,
     UnexpectedEof,
     Unexpected,
+    BufferTooSmall,
+    ChecksumMismatch,
+    HeaderChecksumMismatch,
+    Unsupported,
+}
+
+/// Error of the encoders and decoders.
+///
+/// Decoders record the input position they had reached when the error
+/// occurred; `Display` shows it as "at byte N, bit B". A checksum that
+/// does not match may come with the one stored and the one computed, as
+/// may the NLEN of a stored deflate block that is not the complement of
+/// its LEN. Some errors also tell what is wrong with the input.
+/// Equality only compares the kind, so `err == CompressionError::DataError`
+/// holds wherever the error happened.
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionError {
+    kind: ErrorKind,
+    bit_offset: Option<usize>,
+    checksums: Option<(u32, u32)>,
+    reason: Option<&'static str>,
+}
+
+#[allow(non_upper_case_globals)]
+impl CompressionError {
+    pub const DataError: Self = Self::new(ErrorKind::DataError);
+    pub const UnexpectedEof: Self = Self::new(ErrorKind::UnexpectedEof);
+    pub const Unexpected: Self = Self::new(ErrorKind::Unexpected);
+    pub const BufferTooSmall: Self = Self::new(ErrorKind::BufferTooSmall);
+    pub const ChecksumMismatch: Self = Self::new(ErrorKind::ChecksumMismatch);
+    pub const HeaderChecksumMismatch: Self =
+        Self::new(ErrorKind::HeaderChecksumMismatch);
+    pub const Unsupported: Self = Self::new(ErrorKind::Unsupported);
+}
+
+impl CompressionError {
+    #[inline]
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            bit_offset: None,
+            checksums: None,
+            reason: None,
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the input position of the error in bits, if known.
+    #[inline]
+    pub fn bit_offset(&self) -> Option<usize> {
+        self.bit_offset
+    }
+
+    /// Returns the checksum stored in the input and the one computed, if
+    /// the error is a checksum that does not match and they are known. For
+    /// a stored deflate block they are its NLEN and the complement of its
+    /// LEN.
+    #[inline]
+    pub fn checksums(&self) -> Option<(u32, u32)> {
+        self.checksums
+    }
+
+    /// Returns what is wrong with the input, if the decoder tells. The
+    /// deflate decoder uses the messages of zlib, such as "invalid
+    /// distances set".
+    #[inline]
+    pub fn reason(&self) -> Option<&'static str> {
+        self.reason
+    }
+
+    #[cfg(feature = "lzss")]
+    pub(crate) fn with_reason(mut self, reason: &'static str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    #[cfg(any(feature = "deflate", feature = "lzhuf"))]
+    pub(crate) fn with_checksums(
+        mut self,
+        expected: u32,
+        computed: u32,
+    ) -> Self {
+        self.checksums = Some((expected, computed));
+        self
+    }
+
+    fn description_in(&self) -> &str {
+        match self.kind {
+            ErrorKind::DataError => "data integrity error in data",
+            ErrorKind::UnexpectedEof => "file ends unexpectedly",
+            ErrorKind::Unexpected => "unexpected error",
+            ErrorKind::BufferTooSmall => "buffer too small",
+            ErrorKind::ChecksumMismatch => {
+                "checksum of the data does not match"
+            }
+            ErrorKind::HeaderChecksumMismatch => {
+                "checksum of the header does not match"
+            }
+            ErrorKind::Unsupported => "unsupported format or method",
+        }
+    }
+}
+
+impl PartialEq for CompressionError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for CompressionError {}
+
+impl From<ErrorKind> for CompressionError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
 }
 
 impl fmt::Display for CompressionError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.description_in())
+        write!(f, "{}", self.description_in())?;
+        if let Some(reason) = self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        if let Some((expected, computed)) = self.checksums {
+            write!(
+                f,
+                " (expected {:#06x}, computed {:#06x})",
+                expected, computed
+            )?;
+        }
+        fmt_offset(f, self.bit_offset)
     }
 }
 
@@ -26,17 +156,33 @@ impl ::std::error::Error for CompressionError {
         self.description_in()
     }
 
-    fn cause(&self) -> Option<&dyn (::std::error::Error)> {
+    fn cause(&self) -> Option<&dyn ::std::error::Error> {
         None
     }
 }
 
-impl CompressionError {
-    fn description_in(&self) -> &str {
-        match *self {
-            CompressionError::DataError => "data integrity error in data",
-            CompressionError::UnexpectedEof => "file ends unexpectedly",
-            CompressionError::Unexpected => "unexpected error",
-        }
+#[cfg(any(feature = "bzip2", feature = "lzss"))]
+/// Errors that can be stamped with the input position.
+pub(crate) trait WithOffset {
+    /// Sets the position in bits unless one is already set.
+    fn with_offset(self, bit_offset: usize) -> Self;
+}
+
+#[cfg(any(feature = "bzip2", feature = "lzss"))]
+impl WithOffset for CompressionError {
+    #[inline]
+    fn with_offset(mut self, bit_offset: usize) -> Self {
+        self.bit_offset = self.bit_offset.or(Some(bit_offset));
+        self
+    }
+}
+
+pub(crate) fn fmt_offset(
+    f: &mut fmt::Formatter<'_>,
+    bit_offset: Option<usize>,
+) -> fmt::Result {
+    match bit_offset {
+        Some(o) => write!(f, " at byte {}, bit {}", o >> 3, o & 0x07),
+        None => Ok(()),
     }
 }
@@ -6,7 +6,7 @@
 //! <http://mozilla.org/MPL/2.0/>.
 
 use crate::bitio::direction::right::Right;
-use crate::bitio::reader::{BitRead, BitReader};
+use crate::bitio::reader::BitRead;
 use crate::core::hash::{BuildHasher, Hasher};
 use crate::crc32::{BuiltinDigest, IEEE_REVERSE};
 use crate::deflate::decoder::DeflaterBase;
@@ -50,21 +50,11 @@ impl GZipDecoderBase {
         reader: &mut R,
         iter: &mut I,
     ) -> Result<u32, CompressionError> {
-        (0..4)
-            .map(|i| {
-                Ok(reader
-                    .read_bits::<u32, _>(8, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data()
-                    << (i << 3))
-            })
-            .fold(
-                Ok(0_u32),
-                |s: Result<_, CompressionError>,
-                 x: Result<_, CompressionError>| {
-                    Ok(x? | s?)
-                },
-            )
+        // Right 方向なのでリトルエンディアンのまま読める
+        match reader.read_bits::<u32, _>(32, iter) {
+            Ok(ref b) if b.len() == 32 => Ok(b.data()),
+            _ => Err(CompressionError::UnexpectedEof),
+        }
     }
 }
 
@@ -73,11 +63,15 @@ impl BitDecodeService for GZipDecoderBase {
     type Error = CompressionError;
     type Output = u8;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<u8>, Self::Error> {
+    ) -> Result<Option<u8>, Self::Error>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
         loop {
             if !self.header_checked {
                 if self.header.len() < self.header_needlen {
@@ -136,12 +130,12 @@ impl BitDecodeService for GZipDecoderBase {
                     // FNAME
                     let fname_len = if (flg & 0b1000) != 0 {
                         // NAME
-                        if let Some(l) = (&self.header)
+                        if let Some(l) = self
+                            .header
                             .iter()
                             .enumerate()
                             .skip(10 + xlen)
-                            .skip_while(|x| *x.1 != 0)
-                            .next()
+                            .find(|x| *x.1 == 0)
                         {
                             l.0 - 10 - xlen
                         } else {
@@ -155,12 +149,12 @@ impl BitDecodeService for GZipDecoderBase {
                     // FCOMMENT
                     let fcomment_len = if (flg & 0b1_0000) != 0 {
                         // COMMENT
-                        if let Some(l) = (&self.header)
+                        if let Some(l) = self
+                            .header
                             .iter()
                             .enumerate()
                             .skip(10 + xlen + fname_len)
-                            .skip_while(|x| *x.1 != 0)
-                            .next()
+                            .find(|x| *x.1 == 0)
                         {
                             l.0 - 10 - xlen - fname_len
                         } else {
@@ -232,6 +226,26 @@ impl GZipDecoder {
             inner: BitDecoderImpl::<GZipDecoderBase>::new(),
         }
     }
+
+    /// Returns the number of input bytes consumed so far.
+    ///
+    /// Once the end of the stream has been returned, this is the length of
+    /// the stream; the input from this offset on belongs to the caller.
+    pub fn bytes_consumed(&self) -> usize {
+        self.inner.reader().bytes_consumed()
+    }
+
+    /// Decodes a stream held in memory, reading the slice directly instead
+    /// of through a byte iterator. Returns the decoded data and the number
+    /// of input bytes the stream occupies.
+    ///
+    /// The decoder must not have been given any input through `next`.
+    pub fn decode_slice(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(Vec<u8>, usize), CompressionError> {
+        self.inner.decode_slice(input)
+    }
 }
 
 impl Default for GZipDecoder {
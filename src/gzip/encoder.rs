@@ -8,20 +8,27 @@
 use crate::action::Action;
 use crate::core::borrow::BorrowMut;
 use crate::core::hash::{BuildHasher, Hasher};
+use crate::core::iter;
 use crate::core::marker::PhantomData;
 use crate::core::mem;
 use crate::crc32::{BuiltinDigest, IEEE_REVERSE};
-use crate::deflate::encoder::Inflater;
+use crate::deflate::encoder::{BlockMode, DeflateCodeEncoder, Inflater};
 use crate::error::CompressionError;
-use crate::traits::encoder::Encoder;
+use crate::lzss::decoder::LzssDecoder;
+use crate::lzss::encoder::ParseMode;
+use crate::lzss::LzssCode;
+use crate::traits::decoder::Decoder;
+use crate::traits::encoder::{EncodeExt, Encoder};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
-struct ScanIterator<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()> {
+struct ScanIterator<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item)> {
     phantom: PhantomData<I>,
     inner: BI,
     closure: F,
 }
 
-impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()> Iterator
+impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item)> Iterator
     for ScanIterator<I, BI, F>
 {
     type Item = I::Item;
@@ -34,9 +41,7 @@ impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()> Iterator
     }
 }
 
-impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()>
-    ScanIterator<I, BI, F>
-{
+impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item)> ScanIterator<I, BI, F> {
     pub(crate) fn new(inner: BI, closure: F) -> Self {
         Self {
             inner,
@@ -46,9 +51,10 @@ impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()>
     }
 }
 
+/// The header and trailer of a member around the deflate stream, with the
+/// checksum and size of the data the stream holds.
 #[derive(Debug)]
-pub struct GZipEncoder {
-    inflater: Inflater,
+struct GZipMember {
     crc32: Option<BuiltinDigest>,
     header_len: u8,
     header: [u8; 10],
@@ -58,16 +64,9 @@ pub struct GZipEncoder {
     i_size: u32,
 }
 
-impl Default for GZipEncoder {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl GZipEncoder {
-    pub fn new() -> Self {
+impl GZipMember {
+    fn new() -> Self {
         Self {
-            inflater: Inflater::new(),
             crc32: Some(IEEE_REVERSE.build_hasher()),
             header: [
                 0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF,
@@ -79,17 +78,21 @@ impl GZipEncoder {
             i_size_len: 4,
         }
     }
-}
 
-impl Encoder for GZipEncoder {
-    type Error = CompressionError;
-    type In = u8;
-    type Out = u8;
-    fn next<I: Iterator<Item = u8>>(
+    /// Outputs the header, then the deflate stream `body` returns, then
+    /// the trailer. `body` adds the data it encodes to the digest and the
+    /// size it is given.
+    fn next<F>(
         &mut self,
-        iter: &mut I,
+        body: F,
         action: Action,
-    ) -> Option<Result<u8, CompressionError>> {
+    ) -> Option<Result<u8, CompressionError>>
+    where
+        F: FnOnce(
+            &mut BuiltinDigest,
+            &mut u32,
+        ) -> Option<Result<u8, CompressionError>>,
+    {
         let hlen = self.header_len;
         if hlen > 0 {
             let hlen_all = self.header.len();
@@ -111,18 +114,12 @@ impl Encoder for GZipEncoder {
                 Some(Ok(hash as u8))
             }
         } else {
-            let mut crc32 = mem::replace(&mut self.crc32, None);
+            let mut crc32 = self.crc32.take();
             let mut i_size = self.i_size;
-            let ret = self.inflater.next(
-                &mut ScanIterator::<I, _, _>::new(iter, |x: &u8| {
-                    crc32.as_mut().unwrap().write_u8(*x);
-                    i_size += 1;
-                }),
-                action,
-            );
+            let ret = body(crc32.as_mut().unwrap(), &mut i_size);
             self.i_size = i_size;
             let _ = mem::replace(&mut self.crc32, crc32);
-            if ret.is_none() {
+            if ret.is_none() && action == Action::Finish {
                 let hash = self.crc32.as_mut().unwrap().finish() as u32;
                 let ret = hash as u8;
                 self.hash = Some(hash >> 8);
@@ -134,6 +131,173 @@ impl Encoder for GZipEncoder {
     }
 }
 
+#[derive(Debug)]
+pub struct GZipEncoder {
+    inflater: Inflater,
+    member: GZipMember,
+}
+
+impl Default for GZipEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GZipEncoder {
+    pub fn new() -> Self {
+        Self {
+            inflater: Inflater::new(),
+            member: GZipMember::new(),
+        }
+    }
+
+    /// Creates an encoder with `set_level(level)`. `new` is the same as
+    /// level 6.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is more than 9.
+    pub fn with_level(level: u32) -> Self {
+        let mut ret = Self::new();
+        ret.set_level(level);
+        ret
+    }
+
+    /// Sets how the data is compressed by a level from 0 to 9, as
+    /// `Inflater::set_level` does. The XFL byte of the header is 2 at
+    /// level 9 and 4 at level 1, as gzip writes it, and 0 otherwise. Call
+    /// it before encoding, as the header is written first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is more than 9.
+    pub fn set_level(&mut self, level: u32) {
+        self.inflater.set_level(level);
+        self.member.header[8] = match level {
+            1 => 4,
+            9 => 2,
+            _ => 0,
+        };
+    }
+
+    /// Sets how the input is split into codes, as
+    /// `Inflater::set_parse_mode` does.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.inflater.set_parse_mode(mode);
+    }
+
+    /// Sets the blocks written, from the next block on, as
+    /// `Inflater::set_block_mode` does.
+    pub fn set_block_mode(&mut self, mode: BlockMode) {
+        self.inflater.set_block_mode(mode);
+    }
+
+    /// Cuts the output where rsync can find it again, as
+    /// `Inflater::set_rsyncable` does.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.inflater.set_rsyncable(rsyncable);
+    }
+
+    /// Ends the output as `Action::Flush` does and drops the window, as
+    /// `Inflater::full_flush` does. Returns the bytes up to that point that
+    /// are not output yet.
+    pub fn full_flush(&mut self) -> Result<Vec<u8>, CompressionError> {
+        let out = iter::empty()
+            .encode(self, Action::Flush)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.inflater.forget_window();
+        Ok(out)
+    }
+}
+
+impl Encoder for GZipEncoder {
+    type Error = CompressionError;
+    type In = u8;
+    type Out = u8;
+    fn next<I: Iterator<Item = u8>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        let inflater = &mut self.inflater;
+        self.member.next(
+            |crc32, i_size| {
+                inflater.next(
+                    &mut ScanIterator::<I, _, _>::new(iter, |x: &u8| {
+                        crc32.write_u8(*x);
+                        *i_size += 1;
+                    }),
+                    action,
+                )
+            },
+            action,
+        )
+    }
+}
+
+/// GZip encoder of a stream of `LzssCode`s, which it encodes with a
+/// `DeflateCodeEncoder` and so accepts the same codes.
+///
+/// The trailer needs the checksum of the data, so the encoder also
+/// decodes the codes into a window of its own.
+#[derive(Debug)]
+pub struct GZipCodeEncoder {
+    codes: DeflateCodeEncoder,
+    window: LzssDecoder,
+    member: GZipMember,
+}
+
+impl Default for GZipCodeEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GZipCodeEncoder {
+    const WINDOW_SIZE: usize = 0x8000;
+
+    pub fn new() -> Self {
+        Self {
+            codes: DeflateCodeEncoder::new(),
+            window: LzssDecoder::new(Self::WINDOW_SIZE),
+            member: GZipMember::new(),
+        }
+    }
+}
+
+impl Encoder for GZipCodeEncoder {
+    type Error = CompressionError;
+    type In = LzssCode;
+    type Out = u8;
+    fn next<I: Iterator<Item = LzssCode>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        let codes = &mut self.codes;
+        let window = &mut self.window;
+        self.member.next(
+            |crc32, i_size| {
+                codes.next(
+                    &mut ScanIterator::<I, _, _>::new(iter, |x: &LzssCode| {
+                        // 不正な符号は DeflateCodeEncoder が弾くので、
+                        // ここでは復号できた分だけを数える
+                        let mut code = Some(*x);
+                        while let Some(Ok(d)) =
+                            window.next(&mut code.take().into_iter())
+                        {
+                            crc32.write_u8(d);
+                            *i_size += 1;
+                        }
+                    }),
+                    action,
+                )
+            },
+            action,
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,4 +326,124 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_levels() {
+        use crate::gzip::decoder::GZipDecoder;
+
+        let data = include_bytes!("../../data/sample3.ref");
+        for level in 0..=9 {
+            let out = data
+                .iter()
+                .cloned()
+                .encode(&mut GZipEncoder::with_level(level), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let xfl = match level {
+                1 => 4,
+                9 => 2,
+                _ => 0,
+            };
+            assert_eq!(out[8], xfl);
+            assert_eq!(
+                GZipDecoder::new().decode_slice(&out),
+                Ok((data.to_vec(), out.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_sync_flush() {
+        use crate::core::iter;
+        use crate::gzip::decoder::GZipDecoder;
+        use crate::traits::decoder::DecodeExt;
+
+        let mut encoder = GZipEncoder::new();
+        let mut stream = Vec::new();
+        for m in &[&b"Hello, world!"[..], b"Hello again, world!"] {
+            stream.extend(
+                m.iter()
+                    .cloned()
+                    .encode(&mut encoder, Action::Flush)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+            );
+            assert!(stream.ends_with(&[0, 0, 0xFF, 0xFF]));
+        }
+        let decoded = stream
+            .iter()
+            .cloned()
+            .decode(&mut GZipDecoder::new())
+            .take_while(Result::is_ok)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(b"Hello, world!Hello again, world!".to_vec()));
+        stream.extend(
+            iter::empty()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+        );
+        assert_eq!(
+            GZipDecoder::new().decode_slice(&stream).map(|(d, _)| d),
+            Ok(b"Hello, world!Hello again, world!".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_full_flush() {
+        use crate::deflate::decoder::Deflater;
+        use crate::gzip::decoder::GZipDecoder;
+
+        let data = &include_bytes!("../../data/sample3.ref")[..];
+        let mut encoder = GZipEncoder::new();
+        let mut stream = data
+            .iter()
+            .cloned()
+            .encode(&mut encoder, Action::Run)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        stream.extend(encoder.full_flush().unwrap());
+        let cut = stream.len();
+        stream.extend(
+            data.iter()
+                .cloned()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+        );
+        // 区切りの後ろは、それだけで deflate として戻る
+        let (decoded, _) =
+            Deflater::new().decode_slice(&stream[cut..]).unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(
+            GZipDecoder::new().decode_slice(&stream).map(|(d, _)| d),
+            Ok([data, data].concat())
+        );
+    }
+
+    #[test]
+    fn test_rsyncable() {
+        use crate::gzip::decoder::GZipDecoder;
+
+        let data = &include_bytes!("../../data/sample2.ref")[..];
+        let mut encoder = GZipEncoder::new();
+        encoder.set_rsyncable(true);
+        let stream = data
+            .iter()
+            .cloned()
+            .encode(&mut encoder, Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            GZipDecoder::new().decode_slice(&stream).map(|(d, _)| d),
+            Ok(data.to_vec())
+        );
+        // 本体は Inflater と同じ所で区切られる
+        let mut inflater = Inflater::new();
+        inflater.set_rsyncable(true);
+        let body = inflater.compress_to_vec(data, Action::Finish).unwrap();
+        assert_eq!(&stream[10..stream.len() - 8], &body[..]);
+        let cuts = body.windows(4).filter(|w| w == &[0, 0, 0xFF, 0xFF]);
+        assert!(cuts.count() >= data.len() / 0x8000);
+    }
 }
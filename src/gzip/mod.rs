@@ -12,27 +12,41 @@ pub(crate) mod encoder;
 #[cfg(test)]
 mod tests {
     use crate::action::Action;
+    use crate::crc32::crc32;
+    use crate::deflate::decoder::DeflateCodeDecoder;
+    use crate::deflate::encoder::Inflater;
+    use crate::error::CompressionError;
     use crate::gzip::decoder::GZipDecoder;
-    use crate::gzip::encoder::GZipEncoder;
-    use crate::traits::decoder::DecodeExt;
+    use crate::gzip::encoder::{GZipCodeEncoder, GZipEncoder};
+    use crate::lzss::encoder::ParseMode;
+    use crate::traits::decoder::{DecodeExt, TryDecodeExt};
     use crate::traits::encoder::EncodeExt;
     #[cfg(not(feature = "std"))]
+    #[allow(unused_imports)]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
-    use rand::distributions::Standard;
-    use rand::{thread_rng, Rng};
+    use rand::distr::StandardUniform;
+    use rand::{rng, RngExt};
 
     fn check(testarray: &[u8]) {
         let encoded = testarray
             .to_vec()
             .encode(&mut GZipEncoder::new(), Action::Finish)
-            .collect::<Result<Vec<_>, _>>();
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
         let decoded = encoded
-            .unwrap()
+            .iter()
+            .cloned()
             .decode(&mut GZipDecoder::new())
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
 
         assert_eq!(testarray.to_vec(), decoded);
+        assert_eq!(
+            GZipDecoder::new().decode_slice(&encoded),
+            Ok((decoded, encoded.len()))
+        );
     }
 
     #[test]
@@ -62,34 +76,49 @@ mod tests {
 
     #[test]
     fn test_multiblocks() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks2() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks3() {
-        let rng = thread_rng();
+        let rng = rng();
 
         check(
             &(rng
-                .sample_iter(&Standard)
+                .sample_iter(StandardUniform)
                 .take(0xF_FFFF)
                 .collect::<Vec<_>>()),
         );
     }
 
     fn test_rand_with_len(len: usize) {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(len).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(len)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
@@ -106,4 +135,255 @@ mod tests {
     fn test_multiblocks5() {
         test_rand_with_len(0x10_0001);
     }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let mut input = b"aabbaabbaaabbbaaabbbaabbaabb"
+            .to_vec()
+            .encode(&mut GZipEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let len = input.len();
+        input.extend_from_slice(b"garbage after the stream");
+
+        let mut decoder = GZipDecoder::new();
+        let decoded = input
+            .iter()
+            .cloned()
+            .decode(&mut decoder)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(decoded, b"aabbaabbaaabbbaaabbbaabbaabb".to_vec());
+        assert_eq!(decoder.bytes_consumed(), len);
+        assert_eq!(&input[len..], b"garbage after the stream");
+        assert_eq!(GZipDecoder::new().decode_slice(&input), Ok((decoded, len)));
+    }
+
+    #[test]
+    fn test_concatenated() {
+        let mut input = Vec::new();
+        for s in [&b"first"[..], b"second", b""].iter() {
+            input.extend(
+                s.to_vec()
+                    .encode(&mut GZipEncoder::new(), Action::Finish)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+            );
+        }
+
+        let mut rest = &input[..];
+        let mut decoded = Vec::new();
+        while !rest.is_empty() {
+            let mut decoder = GZipDecoder::new();
+            decoded.push(
+                rest.iter()
+                    .cloned()
+                    .decode(&mut decoder)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+            );
+            rest = &rest[decoder.bytes_consumed()..];
+        }
+        assert_eq!(
+            decoded,
+            vec![b"first".to_vec(), b"second".to_vec(), Vec::new()]
+        );
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum SourceError {
+        Transport,
+        Decode(CompressionError),
+    }
+
+    impl From<CompressionError> for SourceError {
+        fn from(error: CompressionError) -> Self {
+            SourceError::Decode(error)
+        }
+    }
+
+    #[test]
+    fn test_source_error() {
+        let input = (0..0x1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let encoded = input
+            .encode(&mut GZipEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let half = encoded.len() / 2;
+
+        let ret = encoded
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| {
+                if i == half {
+                    Err(SourceError::Transport)
+                } else {
+                    Ok(s)
+                }
+            })
+            .try_decode(&mut GZipDecoder::new())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(ret, Err(SourceError::Transport));
+
+        let ret = encoded[..half]
+            .iter()
+            .map(|&s| Ok(s))
+            .try_decode(&mut GZipDecoder::new())
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(
+            ret,
+            Err(SourceError::Decode(CompressionError::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_source_error_after_stream() {
+        // 読み込みの先読みがエラーに届くかどうかに関わらず成功する
+        for len in 0..16 {
+            let input = vec![b'a'; len];
+            let encoded = input
+                .encode(&mut GZipEncoder::new(), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            let decoded = encoded
+                .iter()
+                .map(|&s| Ok(s))
+                .chain(Some(Err(SourceError::Transport)))
+                .try_decode(&mut GZipDecoder::new())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(vec![b'a'; len]));
+        }
+    }
+
+    #[test]
+    fn test_golden_digests() {
+        // 符号表の構築が決定的で、出力が環境によらず同じであることを確かめる
+        let cases: [(&[u8], usize, u32); 4] = [
+            (include_bytes!("../../data/sample1.ref"), 38185, 0xa393_a87c),
+            (include_bytes!("../../data/sample2.ref"), 87087, 0xf403_439c),
+            (include_bytes!("../../data/sample3.ref"), 348, 0x4776_cacb),
+            (include_bytes!("../../data/sample4.ref"), 74855, 0x14d7_9f1e),
+        ];
+        for &(input, len, digest) in &cases {
+            let encoded = input
+                .to_vec()
+                .encode(&mut GZipEncoder::new(), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!((encoded.len(), crc32(&encoded)), (len, digest));
+        }
+    }
+
+    #[test]
+    fn test_optimal_parse() {
+        let cases: [(&[u8], usize); 4] = [
+            (include_bytes!("../../data/sample1.ref"), 38185),
+            (include_bytes!("../../data/sample2.ref"), 87087),
+            (include_bytes!("../../data/sample3.ref"), 348),
+            (include_bytes!("../../data/sample4.ref"), 74855),
+        ];
+        for &(input, lazy_len) in &cases {
+            let mut encoder = GZipEncoder::new();
+            encoder.set_parse_mode(ParseMode::Optimal);
+            let encoded = input
+                .to_vec()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            // 既定の遅延評価より大きくならない
+            assert!(encoded.len() <= lazy_len);
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut GZipDecoder::new())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(input.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_fast_parse() {
+        // `gzip -1` の出力の大きさ
+        let cases: [(&[u8], usize); 4] = [
+            (include_bytes!("../../data/sample1.ref"), 44709),
+            (include_bytes!("../../data/sample2.ref"), 99446),
+            (include_bytes!("../../data/sample3.ref"), 817),
+            (include_bytes!("../../data/sample4.ref"), 88693),
+        ];
+        for &(input, zlib_len) in &cases {
+            let mut encoder = GZipEncoder::new();
+            encoder.set_parse_mode(ParseMode::Fast);
+            let encoded = input
+                .to_vec()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            // zlib の圧縮レベル 1 より 5% 以上は大きくならない
+            assert!(encoded.len() * 100 <= zlib_len * 105);
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut GZipDecoder::new())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(input.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_code_encoder() {
+        let input = include_bytes!("../../data/sample2.ref");
+        let gzip = input
+            .to_vec()
+            .encode(&mut GZipEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let codes = input
+            .to_vec()
+            .encode(&mut Inflater::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+            .into_iter()
+            .decode(&mut DeflateCodeDecoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // 符号から作ったメンバーも CRC と長さを含めて同じになる
+        let encoded = codes
+            .into_iter()
+            .encode(&mut GZipCodeEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(encoded, Ok(gzip));
+    }
+
+    #[cfg(feature = "lzhuf")]
+    #[test]
+    fn test_transcode_lzhuf() {
+        use crate::lzhuf::decoder::LzhufCodeDecoder;
+        use crate::lzhuf::encoder::LzhufEncoder;
+        use crate::lzhuf::LzhufMethod;
+
+        let input = include_bytes!("../../data/sample4.ref");
+        for method in &[LzhufMethod::Lh5, LzhufMethod::Lh6] {
+            let codes = input
+                .to_vec()
+                .encode(&mut LzhufEncoder::new(method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .into_iter()
+                .decode(&mut LzhufCodeDecoder::new(method))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let encoded = codes
+                .into_iter()
+                .encode(&mut GZipCodeEncoder::new(), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut GZipDecoder::new())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(input.to_vec()));
+        }
+    }
 }
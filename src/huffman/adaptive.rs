@@ -0,0 +1,540 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+use crate::bitio::direction::Direction;
+use crate::bitio::reader::BitRead;
+use crate::core::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+const ROOT: usize = 0;
+
+/// Adaptive Huffman decoder of LHA's -lh1- and -lh2- methods.
+///
+/// Every symbol starts with weight 1 and the code tree is updated after
+/// each symbol (the FGK algorithm). Nodes are numbered from the root in
+/// order of non-increasing weight, and each run of equal weights is kept as
+/// a block led by its first node, as in the LHA implementation. When the
+/// total weight reaches `0x8000` the weights are halved and the tree is
+/// rebuilt.
+///
+/// The offsets of -lh2- use a tree built by `growing`, which starts with
+/// one symbol and has the others added one at a time by `add_symbol`.
+#[derive(Clone, Debug)]
+pub struct AdaptiveHuffmanDecoder<D: Direction> {
+    // 内部節点 i の子は child[i] と child[i] - 1 で、負の値は !記号 の葉
+    child: Vec<i32>,
+    parent: Vec<usize>,
+    freq: Vec<u16>,
+    // 節点の属するブロックと、ブロックの先頭の節点。末尾の block は番兵
+    block: Vec<usize>,
+    edge: Vec<usize>,
+    // stock[avail..] が未使用のブロック番号
+    stock: Vec<usize>,
+    avail: usize,
+    leaf: Vec<usize>,
+    // 使っている最後の節点
+    last: usize,
+    // 記号を足していく木では根の重みを最大に保ち、重みの合計は別に数える
+    total: Option<u16>,
+    phantom: PhantomData<fn() -> D>,
+}
+
+impl<D: Direction> AdaptiveHuffmanDecoder<D> {
+    const MAX_FREQ: u16 = 0x8000;
+    const PEEK_BITS: usize = 16;
+
+    /// Builds a decoder for `symbols` symbols.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symbols` is less than 2 or not less than `0x8000`.
+    pub fn new(symbols: usize) -> Self {
+        assert!(
+            (2..Self::MAX_FREQ as usize).contains(&symbols),
+            "invalid number of symbols"
+        );
+        let nodes = 2 * symbols - 1;
+        let mut ret = Self {
+            child: vec![0; nodes],
+            parent: vec![0; nodes],
+            freq: vec![0; nodes],
+            block: vec![0; nodes + 1],
+            edge: vec![0; nodes + 1],
+            stock: (0..=nodes).collect(),
+            // ブロック 0 は番兵, 1 は葉
+            avail: 2,
+            leaf: vec![0; symbols],
+            last: nodes - 1,
+            total: None,
+            phantom: PhantomData,
+        };
+
+        // 重み 1 の葉を末尾に並べ、後ろから 2 つずつまとめる
+        for s in 0..symbols {
+            let j = nodes - 1 - s;
+            ret.freq[j] = 1;
+            ret.child[j] = !(s as i32);
+            ret.leaf[s] = j;
+            ret.block[j] = 1;
+        }
+        ret.edge[1] = symbols - 1;
+        let mut i = nodes - 1;
+        for j in (0..symbols - 1).rev() {
+            let f = ret.freq[i] + ret.freq[i - 1];
+            ret.freq[j] = f;
+            ret.child[j] = i as i32;
+            ret.parent[i] = j;
+            ret.parent[i - 1] = j;
+            let b = if f == ret.freq[j + 1] {
+                ret.block[j + 1]
+            } else {
+                ret.new_block()
+            };
+            ret.block[j] = b;
+            ret.edge[b] = j;
+            i -= 2;
+        }
+        ret
+    }
+
+    /// Builds a decoder for up to `symbols` symbols that starts with only
+    /// symbol 0, whose code has no bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symbols` is less than 2 or not less than `0x8000`.
+    pub fn growing(symbols: usize) -> Self {
+        assert!(
+            (2..Self::MAX_FREQ as usize).contains(&symbols),
+            "invalid number of symbols"
+        );
+        let nodes = 2 * symbols - 1;
+        let mut ret = Self {
+            child: vec![0; nodes],
+            parent: vec![0; nodes],
+            freq: vec![0; nodes],
+            block: vec![0; nodes + 1],
+            edge: vec![0; nodes + 1],
+            stock: (0..=nodes).collect(),
+            // ブロック 0 は番兵
+            avail: 1,
+            leaf: vec![0; symbols],
+            last: ROOT,
+            total: Some(0),
+            phantom: PhantomData,
+        };
+        ret.freq[ROOT] = 1;
+        ret.child[ROOT] = !0;
+        let b = ret.new_block();
+        ret.block[ROOT] = b;
+        ret.edge[b] = ROOT;
+        ret
+    }
+
+    /// Adds the next symbol to a tree built by `growing`, as a sibling of
+    /// the leaf of least weight, and counts it once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree has all its symbols or was built by `new`.
+    pub fn add_symbol(&mut self) {
+        assert!(self.total.is_some(), "tree of fixed symbols");
+        let p = self.last;
+        let (r, q) = (p + 1, p + 2);
+        assert!(q < self.child.len(), "all symbols added");
+        let symbol = p / 2 + 1;
+        // 最も軽い葉を内部節点にして、その葉と新しい葉を子にする
+        self.child[r] = self.child[p];
+        self.set_parent(self.child[r], r);
+        self.child[q] = !(symbol as i32);
+        self.child[p] = q as i32;
+        self.freq[r] = self.freq[p];
+        self.freq[q] = 0;
+        self.block[r] = self.block[p];
+        if p == ROOT {
+            self.freq[ROOT] = u16::MAX;
+            self.edge[self.block[ROOT]] += 1;
+        }
+        self.parent[r] = p;
+        self.parent[q] = p;
+        let b = self.new_block();
+        self.block[q] = b;
+        self.edge[b] = q;
+        self.leaf[symbol] = q;
+        self.last = q;
+        self.update(symbol);
+    }
+
+    /// Decodes a symbol and updates the tree. Returns `None` at the end of
+    /// the input, including when the input ends inside a code, as the
+    /// padding of the last byte does.
+    pub fn dec<R: BitRead, I: Iterator<Item = u8>>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<u16>, String> {
+        let mut c = self.child[ROOT];
+        loop {
+            let b = reader.peek_bits::<u32, _>(Self::PEEK_BITS, iter)?;
+            let (data, len) = (*b.data_ref(), b.len());
+            let mut k = 0;
+            while c >= 0 && k < len {
+                let bit = if D::is_reverse() {
+                    data >> k
+                } else {
+                    data >> (len - 1 - k)
+                } & 1;
+                c = self.child[c as usize - bit as usize];
+                k += 1;
+            }
+            if c < 0 {
+                let _ = reader.skip_bits(k, iter)?;
+                break;
+            }
+            if len < Self::PEEK_BITS {
+                return Ok(None);
+            }
+            let _ = reader.skip_bits(len, iter)?;
+        }
+        let symbol = !c as usize;
+        self.update(symbol);
+        Ok(Some(symbol as u16))
+    }
+
+    fn update(&mut self, symbol: usize) {
+        match self.total {
+            None => {
+                if self.freq[ROOT] == Self::MAX_FREQ {
+                    self.reconstruct();
+                }
+                self.freq[ROOT] += 1;
+            }
+            Some(Self::MAX_FREQ) => {
+                self.reconstruct();
+                self.total = Some(self.freq[ROOT]);
+                self.freq[ROOT] = u16::MAX;
+            }
+            Some(_) => {}
+        }
+        let mut q = self.leaf[symbol];
+        while q != ROOT {
+            q = self.increment(q);
+        }
+        if let Some(total) = self.total.as_mut() {
+            *total += 1;
+        }
+    }
+
+    /// Increments the weight of node `p`, after swapping it with the leader
+    /// of its block, and returns the parent.
+    fn increment(&mut self, mut p: usize) -> usize {
+        let b = self.block[p];
+        let q = self.edge[b];
+        if q != p || b == self.block[p + 1] {
+            if q != p {
+                let r = self.child[p];
+                let s = self.child[q];
+                self.child[p] = s;
+                self.child[q] = r;
+                self.set_parent(r, q);
+                self.set_parent(s, p);
+                p = q;
+            }
+            // 先頭が抜けて、ブロックには残りの節点が残る
+            self.edge[b] += 1;
+            self.freq[p] += 1;
+            if self.freq[p] == self.freq[p - 1] {
+                self.block[p] = self.block[p - 1];
+            } else {
+                let nb = self.new_block();
+                self.block[p] = nb;
+                self.edge[nb] = p;
+            }
+        } else {
+            // ブロックに 1 つだけの節点
+            self.freq[p] += 1;
+            if self.freq[p] == self.freq[p - 1] {
+                self.free_block(b);
+                self.block[p] = self.block[p - 1];
+            }
+        }
+        self.parent[p]
+    }
+
+    /// Halves the weights and rebuilds the tree from the leaves.
+    fn reconstruct(&mut self) {
+        let nodes = self.last + 1;
+        // 葉を重みを半分にして前に詰め、ブロックはすべて返す
+        let mut j = 0;
+        for i in 0..nodes {
+            let k = self.child[i];
+            if k < 0 {
+                self.freq[j] = self.freq[i].div_ceil(2);
+                self.child[j] = k;
+                j += 1;
+            }
+            let b = self.block[i];
+            if self.edge[b] == i {
+                self.free_block(b);
+            }
+        }
+
+        // 後ろから葉と、2 つずつまとめた節点を重みの順に並べる
+        let mut j = j as isize - 1;
+        let mut i = nodes as isize - 1;
+        let mut l = nodes as isize - 2;
+        while i >= 0 {
+            while i >= l {
+                self.freq[i as usize] = self.freq[j as usize];
+                self.child[i as usize] = self.child[j as usize];
+                i -= 1;
+                j -= 1;
+            }
+            let f = self.freq[l as usize] + self.freq[l as usize + 1];
+            let mut k = 0;
+            while f < self.freq[k] {
+                k += 1;
+            }
+            while j >= k as isize {
+                self.freq[i as usize] = self.freq[j as usize];
+                self.child[i as usize] = self.child[j as usize];
+                i -= 1;
+                j -= 1;
+            }
+            self.freq[i as usize] = f;
+            self.child[i as usize] = (l + 1) as i32;
+            i -= 1;
+            l -= 2;
+        }
+
+        let mut f = 0;
+        let mut b = 0;
+        for i in 0..nodes {
+            self.set_parent(self.child[i], i);
+            if self.freq[i] != f {
+                f = self.freq[i];
+                b = self.new_block();
+                self.edge[b] = i;
+            }
+            self.block[i] = b;
+        }
+    }
+
+    #[inline]
+    fn set_parent(&mut self, child: i32, p: usize) {
+        if child < 0 {
+            self.leaf[!child as usize] = p;
+        } else {
+            self.parent[child as usize] = p;
+            self.parent[child as usize - 1] = p;
+        }
+    }
+
+    #[inline]
+    fn new_block(&mut self) -> usize {
+        self.avail += 1;
+        self.stock[self.avail - 1]
+    }
+
+    #[inline]
+    fn free_block(&mut self, b: usize) {
+        self.avail -= 1;
+        self.stock[self.avail] = b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitio::direction::left::Left;
+    use crate::bitio::direction::right::Right;
+    use crate::bitio::slice_reader::SliceBitReader;
+    use crate::core::iter;
+    use rand::{rng, RngExt};
+
+    /// Appends the code of `symbol` to `bits` and updates the tree as the
+    /// decoder does.
+    fn enc<D: Direction>(
+        tree: &mut AdaptiveHuffmanDecoder<D>,
+        symbol: usize,
+        bits: &mut Vec<bool>,
+    ) {
+        let start = bits.len();
+        let mut p = tree.leaf[symbol];
+        while p != ROOT {
+            // 奇数番目の節点が 1
+            bits.push(p & 1 == 1);
+            p = tree.parent[p];
+        }
+        bits[start..].reverse();
+        tree.update(symbol);
+    }
+
+    fn pack<D: Direction>(bits: &[bool]) -> Vec<u8> {
+        bits.chunks(8)
+            .map(|c| {
+                c.iter().enumerate().fold(0, |a, (i, &b)| {
+                    let shift = if D::is_reverse() { i } else { 7 - i };
+                    a | (b as u8) << shift
+                })
+            })
+            .collect()
+    }
+
+    fn check_tree<D: Direction>(tree: &AdaptiveHuffmanDecoder<D>) {
+        // 記号を足していく木の根の重みは合計ではなく、ブロックも見ない
+        let fixed_root = |i| i == ROOT && tree.total.is_some();
+        for (i, &c) in tree.child[..=tree.last].iter().enumerate() {
+            if i > 0 {
+                assert!(tree.freq[i - 1] >= tree.freq[i]);
+                let b = tree.block[i];
+                if !fixed_root(i - 1) {
+                    assert_eq!(
+                        b == tree.block[i - 1],
+                        tree.freq[i - 1] == tree.freq[i]
+                    );
+                }
+            }
+            if !fixed_root(i) {
+                assert!(tree.edge[tree.block[i]] <= i);
+                assert_eq!(tree.freq[tree.edge[tree.block[i]]], tree.freq[i]);
+            }
+            if c < 0 {
+                assert_eq!(tree.leaf[!c as usize], i);
+            } else {
+                let c = c as usize;
+                if !fixed_root(i) {
+                    assert_eq!(tree.freq[i], tree.freq[c] + tree.freq[c - 1]);
+                }
+                assert_eq!((tree.parent[c], tree.parent[c - 1]), (i, i));
+            }
+        }
+    }
+
+    fn adaptive_checker<D: Direction>() {
+        const SYMBOLS: usize = 314;
+        let mut rng = rng();
+        let input = (0..100_000)
+            .map(|_| match rng.random_range(0..4) {
+                0 => rng.random_range(0..SYMBOLS),
+                _ => rng.random_range(0..16) * rng.random_range(0..4),
+            })
+            .collect::<Vec<_>>();
+
+        let mut tree = AdaptiveHuffmanDecoder::<D>::new(SYMBOLS);
+        let mut bits = Vec::new();
+        let mut rebuilt = false;
+        for (i, &s) in input.iter().enumerate() {
+            let total = tree.freq[ROOT];
+            enc(&mut tree, s, &mut bits);
+            rebuilt |= tree.freq[ROOT] <= total;
+            if i % 97 == 0 {
+                check_tree(&tree);
+            }
+        }
+        // 最大の重みに達して木を作り直している
+        assert!(rebuilt);
+        check_tree(&tree);
+
+        let data = pack::<D>(&bits);
+        let mut reader = SliceBitReader::<D>::new(&data);
+        let mut decoder = AdaptiveHuffmanDecoder::<D>::new(SYMBOLS);
+        for &s in &input {
+            assert_eq!(
+                decoder.dec(&mut reader, &mut iter::empty()),
+                Ok(Some(s as u16))
+            );
+        }
+        assert_eq!(decoder.freq, tree.freq);
+        assert_eq!(decoder.child, tree.child);
+    }
+
+    #[test]
+    fn adaptive_huffman_roundtrip() {
+        adaptive_checker::<Left>();
+        adaptive_checker::<Right>();
+    }
+
+    #[test]
+    fn adaptive_huffman_growing() {
+        const SYMBOLS: usize = 128;
+        let mut rng = rng();
+        let mut tree = AdaptiveHuffmanDecoder::<Left>::growing(SYMBOLS);
+        // None は記号を足したところ
+        let mut input = Vec::new();
+        let mut bits = Vec::new();
+        let mut rebuilt = false;
+        for i in 0..100_000 {
+            let symbols = tree.last / 2 + 1;
+            if i % 500 == 499 && symbols < SYMBOLS {
+                tree.add_symbol();
+                input.push(None);
+                continue;
+            }
+            // 小さい記号ほど多く出る
+            let s = rng
+                .random_range(0..symbols)
+                .min(rng.random_range(0..symbols));
+            let total = tree.total;
+            enc(&mut tree, s, &mut bits);
+            input.push(Some(s));
+            rebuilt |= tree.total <= total;
+            if i % 97 == 0 {
+                check_tree(&tree);
+            }
+        }
+        assert!(rebuilt);
+        assert_eq!(tree.last, 2 * SYMBOLS - 2);
+        check_tree(&tree);
+
+        let data = pack::<Left>(&bits);
+        let mut reader = SliceBitReader::<Left>::new(&data);
+        let mut decoder = AdaptiveHuffmanDecoder::<Left>::growing(SYMBOLS);
+        for &s in &input {
+            match s {
+                Some(s) => assert_eq!(
+                    decoder.dec(&mut reader, &mut iter::empty()),
+                    Ok(Some(s as u16))
+                ),
+                None => decoder.add_symbol(),
+            }
+        }
+        assert_eq!(decoder.freq, tree.freq);
+        assert_eq!(decoder.child, tree.child);
+    }
+
+    #[test]
+    #[should_panic]
+    fn adaptive_huffman_growing_full() {
+        let mut tree = AdaptiveHuffmanDecoder::<Left>::growing(2);
+        tree.add_symbol();
+        tree.add_symbol();
+    }
+
+    #[test]
+    fn adaptive_huffman_initial_codes() {
+        // 初期の符号長はすべて 2 で、記号 0 は 00
+        let mut tree = AdaptiveHuffmanDecoder::<Left>::new(4);
+        let data = [0b0000_0000];
+        let mut reader = SliceBitReader::<Left>::new(&data);
+        assert_eq!(tree.dec(&mut reader, &mut iter::empty()), Ok(Some(0)));
+        check_tree(&tree);
+        assert_eq!(tree.freq[..3], [5, 3, 2]);
+        // 記号 0 は 11 になり、途中で終わった符号は記号にならない
+        let data = [0b1100_0000];
+        let mut reader = SliceBitReader::<Left>::new(&data[..0]);
+        assert_eq!(tree.dec(&mut reader, &mut iter::empty()), Ok(None));
+        let mut reader = SliceBitReader::<Left>::new(&data);
+        assert_eq!(tree.dec(&mut reader, &mut iter::empty()), Ok(Some(0)));
+    }
+}
@@ -5,12 +5,16 @@
 //! version 2.0 (the "License"). You can obtain a copy of the License at
 //! <http://mozilla.org/MPL/2.0/>.
 
+#[cfg(any(feature = "bzip2", test))]
+use crate::core::cmp;
+use crate::huffman::canonical::length_limited_lengths;
 #[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
+#[cfg(any(feature = "bzip2", test))]
 fn down_heap(buf: &mut [usize], mut n: usize, len: usize) {
     let tmp = buf[n];
     let mut leaf = (n << 1) + 1;
@@ -30,6 +34,7 @@ fn down_heap(buf: &mut [usize], mut n: usize, len: usize) {
     buf[n] = tmp;
 }
 
+#[cfg(any(feature = "bzip2", test))]
 fn create_heap(buf: &mut [usize]) {
     let s = buf.len() >> 1;
     for i in (0..(s >> 1)).rev() {
@@ -37,6 +42,7 @@ fn create_heap(buf: &mut [usize]) {
     }
 }
 
+#[cfg(any(feature = "bzip2", test))]
 fn take_package(
     ty: &mut [Vec<usize>],
     len: &mut [usize],
@@ -55,6 +61,7 @@ fn take_package(
 }
 
 /// Reverse package merge
+#[cfg(any(feature = "bzip2", test))]
 fn gen_code_lm<F: Fn(usize, usize) -> usize>(
     freq: &[usize],
     lim: usize,
@@ -66,7 +73,8 @@ fn gen_code_lm<F: Fn(usize, usize) -> usize>(
         .enumerate()
         .map(|(i, &f)| (i, f))
         .collect::<Vec<_>>();
-    freqmap.sort_by(|x, y| y.1.cmp(&x.1));
+    // 同じ頻度は記号の順。出力が環境によらないように順序を一意にする
+    freqmap.sort_unstable_by_key(|&(i, f)| (cmp::Reverse(f), i));
     let (map, sfreq): (Vec<_>, Vec<_>) = freqmap.into_iter().unzip();
 
     let mut max_elem = vec![0; lim];
@@ -150,6 +158,7 @@ fn gen_code_lm<F: Fn(usize, usize) -> usize>(
     r.into_iter().map(move |v| v.0).collect::<Vec<_>>()
 }
 
+#[cfg(any(feature = "bzip2", test))]
 fn gen_code<F: Fn(usize, usize) -> usize>(
     freq: &[usize],
     lim: usize,
@@ -195,6 +204,10 @@ fn gen_code<F: Fn(usize, usize) -> usize>(
     }
 }
 
+/// Returns the code lengths for `freq` limited to `lim` bits, merging
+/// weights with `weight_add_fn`. The result depends only on the arguments:
+/// the heap and the sorts break ties by symbol index.
+#[cfg(any(feature = "bzip2", test))]
 pub(crate) fn make_tab_with_fn<F: Fn(usize, usize) -> usize>(
     freq: &[usize],
     lim: usize,
@@ -224,9 +237,18 @@ pub(crate) fn make_tab_with_fn<F: Fn(usize, usize) -> usize>(
     }
 }
 
-#[cfg(any(feature = "deflate", feature = "lzhuf", test))]
+/// Returns the code lengths for `freq` limited to `lim` bits, without the
+/// unused symbols at the end.
+///
+/// Package-merge finds the best lengths within the limit directly, so
+/// skewed frequencies that would give an unlimited Huffman code longer
+/// codes still give a complete code the decoders accept.
 pub(crate) fn make_table(freq: &[usize], lim: usize) -> Vec<u8> {
-    make_tab_with_fn(freq, lim, |x, y| x + y)
+    let mut tab = length_limited_lengths(freq, lim);
+    debug_assert!(tab.iter().all(|&l| usize::from(l) <= lim));
+    let used = tab.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1);
+    tab.truncate(used);
+    tab
 }
 
 #[cfg(test)]
@@ -0,0 +1,212 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+use crate::core::cmp;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// Returns optimal code lengths for `freqs` with no length above
+/// `max_len`, using package-merge.
+///
+/// Symbols of frequency 0 get length 0. A single used symbol gets length
+/// 1; otherwise the lengths form a complete prefix code, so the sum of
+/// `2^-len` over the used symbols is exactly 1.
+///
+/// Equal frequencies are ordered by symbol index, so the lengths depend
+/// only on `freqs` and `max_len` and are the same on every platform.
+///
+/// # Panics
+///
+/// Panics if more than `2^max_len` symbols are used.
+pub fn length_limited_lengths(freqs: &[usize], max_len: usize) -> Vec<u8> {
+    let mut ret = vec![0; freqs.len()];
+    let mut leaves = freqs
+        .iter()
+        .enumerate()
+        .filter(|&(_, &f)| f != 0)
+        .map(|(i, &f)| (f, i))
+        .collect::<Vec<_>>();
+    let n = leaves.len();
+    if n <= 1 {
+        if let Some(&(_, i)) = leaves.first() {
+            ret[i] = 1;
+        }
+        return ret;
+    }
+    // ハフマン符号は n - 1 ビットより深くならないので、それより長い制限は不要
+    let max_len = cmp::min(max_len, cmp::min(n - 1, u8::MAX as usize));
+    assert!(
+        max_len > 0
+            && (n - 1).checked_shr(max_len as u32 - 1).unwrap_or(0) <= 1,
+        "too many symbols for the length limit"
+    );
+    // (頻度, 記号) は重複しないので、不安定ソートでも順序は一意に決まる
+    leaves.sort_unstable();
+
+    // levels[j] は深さ j + 1 のリストで、各要素がパッケージかどうかを持つ。
+    // 最深のリストは葉だけなので省略する
+    let mut levels = Vec::with_capacity(max_len - 1);
+    let mut weights = leaves.iter().map(|l| l.0).collect::<Vec<_>>();
+    for _ in 1..max_len {
+        let mut merged = Vec::with_capacity(n + (weights.len() >> 1));
+        let mut is_package = Vec::with_capacity(merged.capacity());
+        let mut leaf = leaves.iter().map(|l| l.0).peekable();
+        let mut packages =
+            weights.chunks_exact(2).map(|p| p[0] + p[1]).peekable();
+        loop {
+            let take_leaf = match (leaf.peek(), packages.peek()) {
+                (Some(l), Some(p)) => l <= p,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let w = if take_leaf {
+                leaf.next()
+            } else {
+                packages.next()
+            };
+            merged.push(w.unwrap());
+            is_package.push(!take_leaf);
+        }
+        levels.push(is_package);
+        weights = merged;
+    }
+
+    // 最上位のリストの先頭 2n - 2 個を選び、パッケージを下位へ展開する。
+    // 選ばれた葉 1 つにつき、その記号の符号長が 1 増える
+    let mut count = 2 * n - 2;
+    for is_package in levels.iter().rev() {
+        let selected = &is_package[..count];
+        let packages = selected.iter().filter(|&&p| p).count();
+        for &(_, i) in &leaves[..count - packages] {
+            ret[i] += 1;
+        }
+        count = packages << 1;
+    }
+    for &(_, i) in &leaves[..count] {
+        ret[i] += 1;
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rng, RngExt};
+
+    fn cost(freqs: &[usize], lens: &[u8]) -> usize {
+        freqs.iter().zip(lens).map(|(&f, &l)| f * l as usize).sum()
+    }
+
+    /// Kraft sum scaled by `2^max`.
+    fn kraft(lens: &[u8], max: u8) -> u128 {
+        lens.iter()
+            .filter(|&&l| l != 0)
+            .map(|&l| 1_u128 << (max - l))
+            .sum()
+    }
+
+    /// Cost of an unconstrained Huffman code: the sum of the merged weights.
+    fn huffman_cost(freqs: &[usize]) -> usize {
+        let mut w = freqs
+            .iter()
+            .cloned()
+            .filter(|&f| f != 0)
+            .collect::<Vec<_>>();
+        let mut cost = 0;
+        while w.len() > 1 {
+            w.sort_unstable_by(|a, b| b.cmp(a));
+            let s = w.pop().unwrap() + w.pop().unwrap();
+            cost += s;
+            w.push(s);
+        }
+        cost
+    }
+
+    #[test]
+    fn length_limited_edge_cases() {
+        assert_eq!(length_limited_lengths(&[], 15), vec![]);
+        assert_eq!(length_limited_lengths(&[0, 0, 0], 15), vec![0, 0, 0]);
+        assert_eq!(length_limited_lengths(&[0, 7, 0], 15), vec![0, 1, 0]);
+        assert_eq!(length_limited_lengths(&[3, 0, 5], 15), vec![1, 0, 1]);
+        assert_eq!(
+            length_limited_lengths(&[0, 1, 1, 2, 2, 4, 4, 8, 8], 15),
+            vec![0, 4, 4, 4, 4, 3, 3, 2, 2]
+        );
+        // 8 記号を 3 ビットに収める
+        assert_eq!(
+            length_limited_lengths(&[1, 1, 2, 4, 8, 16, 32, 64], 3),
+            vec![3; 8]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn length_limited_too_many_symbols() {
+        let _ = length_limited_lengths(&[1; 9], 3);
+    }
+
+    #[test]
+    fn length_limited_fibonacci() {
+        // フィボナッチ頻度はハフマン木が最も深くなる
+        let mut freqs = vec![1_usize, 1];
+        while freqs.len() < 30 {
+            let l = freqs.len();
+            freqs.push(freqs[l - 1] + freqs[l - 2]);
+        }
+        let lens = length_limited_lengths(&freqs, 64);
+        assert_eq!(*lens.iter().max().unwrap(), 29);
+        assert_eq!(cost(&freqs, &lens), huffman_cost(&freqs));
+        for lim in 5..29 {
+            let lens = length_limited_lengths(&freqs, lim);
+            assert_eq!(*lens.iter().max().unwrap() as usize, lim);
+            assert_eq!(kraft(&lens, lim as u8), 1 << lim);
+        }
+    }
+
+    #[test]
+    fn length_limited_random() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let len = rng.random_range(2..300);
+            let freqs = (0..len)
+                .map(|_| match rng.random_range(0..4) {
+                    0 => 0,
+                    1 => 1 << rng.random_range(0..20),
+                    _ => rng.random_range(1..1000),
+                })
+                .collect::<Vec<usize>>();
+            let used = freqs.iter().filter(|&&f| f != 0).count();
+            if used < 2 {
+                continue;
+            }
+            let optimal = huffman_cost(&freqs);
+            let mut prev = 0;
+            for lim in (9..=20).rev() {
+                let lens = length_limited_lengths(&freqs, lim);
+                assert!(lens.iter().all(|&l| l as usize <= lim));
+                assert!(freqs
+                    .iter()
+                    .zip(&lens)
+                    .all(|(&f, &l)| (f == 0) == (l == 0)));
+                assert_eq!(kraft(&lens, 20), 1 << 20);
+                let c = cost(&freqs, &lens);
+                assert!(c >= optimal);
+                // 制限を厳しくするほどコストは下がらない
+                assert!(c >= prev);
+                prev = c;
+            }
+            assert_eq!(
+                cost(&freqs, &length_limited_lengths(&freqs, 64)),
+                optimal
+            );
+        }
+    }
+}
@@ -10,224 +10,1079 @@ use crate::bitio::reader::BitRead;
 use crate::bitio::small_bit_vec::{SmallBitVec, SmallBitVecReverse};
 use crate::core::cmp;
 use crate::core::marker::PhantomData;
-use crate::core::ops::{Add, BitAnd, Shl, Shr, Sub};
-use crate::huffman::create_huffman_table;
+use crate::error::CompressionError;
+use crate::huffman::table::{read_coded, TableDecoder, TABLE_COUNT_BITS};
+use crate::huffman::{first_codes, lengths_from_pairs, symbols_used};
 #[cfg(not(feature = "std"))]
 use alloc::borrow::ToOwned;
 #[cfg(not(feature = "std"))]
-use alloc::boxed::Box;
-#[cfg(not(feature = "std"))]
 use alloc::string::String;
 #[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
+use num_traits::cast;
 
+/// Table driven Huffman decoder.
+///
+/// The root table is indexed by the next `stab_bits` bits. Codes longer
+/// than that share a root entry per prefix, which points to a subtable
+/// indexed by the rest of the bits, so every code is resolved with at most
+/// two lookups.
+///
+/// A decoder built by `new_compact` drops the table when only a few
+/// symbols are used and it would be large, and instead keeps the number of
+/// codes of each length and the symbols in code order. Walking the lengths
+/// of the canonical code is slower, but takes a few bytes per symbol.
+///
+/// `S` is the symbol type; it must be able to hold every index of the code
+/// lengths the decoder is built from.
 #[derive(Debug)]
-pub(crate) struct HuffmanDecoder<D: Direction> {
+pub struct HuffmanDecoder<D: Direction, S = u16> {
+    symb_len: Vec<u8>,
+    root_bits: usize,
+    allow_incomplete: bool,
+    allow_compact: bool,
     stab_bits: usize,
-    stab: Vec<SymbolTableItem>,
+    max_len: usize,
+    stab: Vec<TableEntry<S>>,
+    // 疎な符号の表現。counts[l] は長さ l の符号の数
+    compact: bool,
+    counts: Vec<u8>,
+    sorted: Vec<S>,
     phantom: PhantomData<fn() -> D>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-enum HuffmanLeaf {
-    Leaf(u16),
-    Branch(Box<HuffmanLeaf>, Box<HuffmanLeaf>),
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TableEntry<S> {
+    /// Symbol and the number of bits it takes in this table.
+    Symbol(S, u8),
+    /// Subtable at the offset, indexed by the given number of bits.
+    Sub(u32, u8),
     None,
 }
 
-impl HuffmanLeaf {
-    #[inline]
-    pub(crate) fn new() -> Self {
-        HuffmanLeaf::None
+impl<D: Direction, S: Copy + num_traits::NumCast> HuffmanDecoder<D, S> {
+    /// Bits peeked at a time by `dec_many`.
+    const WINDOW_BITS: usize = 56;
+    /// The compact form is used for at most this many symbols...
+    const COMPACT_MAX_SYMBOLS: usize = 32;
+    /// ...when the root table would have more entries than this.
+    const COMPACT_MIN_TABLE: usize = 64;
+    /// Largest root table `auto_stab_bits` picks.
+    const AUTO_MAX_STAB_BITS: usize = 12;
+
+    /// Builds a decoder for the code lengths `symb_len`, with a root table
+    /// of `stab_bits` bits. Lengths that do not form a complete prefix code
+    /// are rejected, except a single code of length 1.
+    pub fn new(symb_len: &[u8], stab_bits: usize) -> Result<Self, String> {
+        Self::with_mode(symb_len, stab_bits, false, false)
     }
 
-    pub(crate) fn add<T>(
-        &mut self,
-        code: &SmallBitVec<T>,
-        value: u16,
-    ) -> Result<(), String>
-    where
-        T: BitAnd<Output = T>
-            + Clone
-            + Shr<usize, Output = T>
-            + From<u8>
-            + PartialEq<T>,
-    {
-        if code.is_empty() {
-            *self = HuffmanLeaf::Leaf(value);
-        } else {
-            if let HuffmanLeaf::None = *self {
-                *self = HuffmanLeaf::Branch(
-                    Box::new(Self::new()),
-                    Box::new(Self::new()),
-                );
-            } else if let HuffmanLeaf::Leaf(_) = *self {
-                return Err("ignore huffman table".to_owned());
-            }
+    /// Like `new`, but also accepts an incomplete code. Reading one of the
+    /// unused codes is then an error.
+    pub fn new_allow_incomplete(
+        symb_len: &[u8],
+        stab_bits: usize,
+    ) -> Result<Self, String> {
+        Self::with_mode(symb_len, stab_bits, true, false)
+    }
 
-            if let HuffmanLeaf::Branch(ref mut lft, ref mut rgt) = *self {
-                let next = SmallBitVec::<T>::new(
-                    code.data_ref().clone() >> 1,
-                    code.len() - 1,
-                );
-                if (code.data_ref().clone() & T::from(1)) == T::from(0) {
-                    lft
-                } else {
-                    rgt
-                }
-                .add(&next, value)?;
-            } else {
-                unreachable!();
-            }
-        }
-        Ok(())
+    /// Like `new`, but uses the compact form for codes of at most 32
+    /// symbols whose root table would have more than 64 entries. The
+    /// choice is made again on each `rebuild`.
+    pub fn new_compact(
+        symb_len: &[u8],
+        stab_bits: usize,
+    ) -> Result<Self, String> {
+        Self::with_mode(symb_len, stab_bits, false, true)
     }
-}
 
-#[derive(Clone, Debug)]
-enum SymbolTableItem {
-    Short(u16, u8),
-    Long(HuffmanLeaf),
-    None,
-}
+    /// Like `new`, but takes the lengths of the used symbols as
+    /// `(symbol, length)` pairs in any order. Pairs of length 0 are allowed
+    /// and a symbol may appear only once. The codes are those `new` assigns
+    /// to the same lengths.
+    pub fn from_pairs(
+        pairs: &[(u32, u8)],
+        stab_bits: usize,
+    ) -> Result<Self, String> {
+        Self::new(&lengths_from_pairs(pairs)?, stab_bits)
+    }
 
-impl<D: Direction> HuffmanDecoder<D> {
-    pub(crate) fn new(
+    /// Same as `new`; the inverse of `to_canonical_lengths`.
+    pub fn from_canonical_lengths(
         symb_len: &[u8],
-        mut stab_bits: usize,
+        stab_bits: usize,
     ) -> Result<Self, String> {
-        let max_len =
-            symb_len.iter().cloned().max().unwrap_or_else(|| 0) as usize;
-        stab_bits = cmp::min(max_len, stab_bits);
-
-        if max_len < 16 {
-            Self::new_t::<u16, _>(symb_len, stab_bits, |d| d as usize)
-        } else if max_len < 32 {
-            Self::new_t::<u32, _>(symb_len, stab_bits, |d| d as usize)
-        } else {
-            Err("length error".to_owned())
+        Self::new(symb_len, stab_bits)
+    }
+
+    /// Reads code lengths written by `HuffmanEncoder::write_table`, in the
+    /// bit order they were written, and builds a decoder as `new` does.
+    /// A table of one symbol gives that symbol a code of length 1.
+    pub fn read_table<R: BitRead, I: Iterator<Item = u8>>(
+        reader: &mut R,
+        iter: &mut I,
+        stab_bits: usize,
+    ) -> Result<Self, CompressionError> {
+        let mut len_decoder = TableDecoder::<D>::default();
+        let mut symb_len = Vec::new();
+        let single = read_coded(
+            reader,
+            iter,
+            TABLE_COUNT_BITS,
+            &mut len_decoder,
+            &mut symb_len,
+        )?;
+        if let Some(s) = single {
+            symb_len.resize(usize::from(s) + 1, 0);
+            symb_len[usize::from(s)] = 1;
         }
+        Self::new(&symb_len, stab_bits).map_err(|_| CompressionError::DataError)
     }
 
-    fn new_t<T, F>(
+    fn with_mode(
         symb_len: &[u8],
         stab_bits: usize,
-        cast_to_usize: F,
-    ) -> Result<Self, String>
-    where
-        T: Add<Output = T>
-            + BitAnd<Output = T>
-            + Clone
-            + PartialOrd<T>
-            + Shl<u8, Output = T>
-            + Shl<usize, Output = T>
-            + Shr<usize, Output = T>
-            + Sub<Output = T>
-            + From<u8>,
-        F: Fn(T) -> usize,
-        SmallBitVec<T>: SmallBitVecReverse,
-    {
-        let huff_tab = create_huffman_table::<T>(symb_len, false);
-        let mut stab = vec![SymbolTableItem::None; 1 << stab_bits];
-        for (i, h) in huff_tab.into_iter().enumerate() {
-            if let Some(b) = h {
-                if stab_bits >= b.len() {
-                    let ld = stab_bits - b.len();
-                    let head = cast_to_usize(if !D::is_reverse() {
-                        b.data_ref().clone() << ld
-                    } else {
-                        b.reverse().data_ref().clone()
-                    });
-                    for j in 0..(1 << ld) {
-                        if !D::is_reverse() {
-                            stab[head | j] =
-                                SymbolTableItem::Short(i as u16, b.len() as u8);
-                        } else {
-                            stab[head | (j << b.len())] =
-                                SymbolTableItem::Short(i as u16, b.len() as u8);
-                        }
-                    }
-                } else {
-                    let ld = b.len() - stab_bits;
-                    let head = if !D::is_reverse() {
-                        b.data_ref().clone() >> ld
-                    } else {
-                        b.reverse().data_ref().clone()
-                            & ((T::from(1) << stab_bits) - T::from(1))
-                    };
-                    let body = SmallBitVec::new(
-                        b.reverse().data_ref().clone() >> stab_bits,
-                        ld,
-                    );
-                    match &mut stab[cast_to_usize(head)] {
-                        &mut SymbolTableItem::Short(_, _) => unreachable!(),
-                        &mut SymbolTableItem::Long(ref mut store) => {
-                            store.add(&body, i as u16)?;
-                        }
-                        d => {
-                            let mut l = HuffmanLeaf::new();
-                            l.add(&body, i as u16)?;
-                            *d = SymbolTableItem::Long(l);
-                        }
-                    }
+        allow_incomplete: bool,
+        allow_compact: bool,
+    ) -> Result<Self, String> {
+        let mut ret = Self {
+            symb_len: Vec::new(),
+            root_bits: stab_bits,
+            allow_incomplete,
+            allow_compact,
+            stab_bits: 0,
+            max_len: 0,
+            stab: Vec::new(),
+            compact: false,
+            counts: Vec::new(),
+            sorted: Vec::new(),
+            phantom: PhantomData,
+        };
+        ret.rebuild(symb_len)?;
+        Ok(ret)
+    }
+
+    /// Rebuilds the decoder for new code lengths, reusing the table
+    /// storage. The lengths are checked as by the constructor the decoder
+    /// was built with; on error the decoder is left unchanged.
+    pub fn rebuild(&mut self, symb_len: &[u8]) -> Result<(), String> {
+        let stab_bits = self.root_bits;
+        self.rebuild_with_stab_bits(symb_len, stab_bits)
+    }
+
+    /// Like `rebuild`, but also changes the root table size to
+    /// `stab_bits`, for codes that call for a different size than the
+    /// last one.
+    pub fn rebuild_with_stab_bits(
+        &mut self,
+        symb_len: &[u8],
+        stab_bits: usize,
+    ) -> Result<(), String> {
+        Self::check_lengths(symb_len, self.allow_incomplete)?;
+        if symb_len.len() > 1 && cast::<_, S>(symb_len.len() - 1).is_none() {
+            return Err("too many symbols".to_owned());
+        }
+        self.root_bits = stab_bits;
+        self.fill(symb_len);
+        Ok(())
+    }
+
+    /// Returns a root table size for `symb_len`: one bit more than it takes
+    /// to give each used symbol an entry, but at most 12 bits and no more
+    /// than the longest code. Filling the table takes time in proportion
+    /// to its size, so a code of few symbols gets a small table.
+    pub fn auto_stab_bits(symb_len: &[u8]) -> usize {
+        let used = symb_len.iter().filter(|&&l| l != 0).count();
+        let max_len = symb_len.iter().cloned().max().unwrap_or(0) as usize;
+        let bits = (usize::BITS - used.saturating_sub(1).leading_zeros()) + 1;
+        cmp::min(cmp::min(bits as usize, Self::AUTO_MAX_STAB_BITS), max_len)
+    }
+
+    /// Checks the Kraft inequality for `symb_len`.
+    fn check_lengths(
+        symb_len: &[u8],
+        allow_incomplete: bool,
+    ) -> Result<(), String> {
+        let max_len = symb_len.iter().cloned().max().unwrap_or(0) as usize;
+        if max_len >= 32 {
+            return Err("length error".to_owned());
+        }
+        // 最長の符号長を基準にした Kraft 和
+        let used = symb_len
+            .iter()
+            .filter(|&&l| l != 0)
+            .map(|&l| 1_u64 << (max_len - l as usize))
+            .sum::<u64>();
+        let full = 1_u64 << max_len;
+        // 記号が 1 つだけのときは長さ 1 の符号を許す
+        let single = max_len == 1 && used == 1;
+        if used > full {
+            Err("oversubscribed huffman table".to_owned())
+        } else if used < full && !allow_incomplete && !single {
+            Err("incomplete huffman table".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn fill(&mut self, symb_len: &[u8]) {
+        let max_len = symb_len.iter().cloned().max().unwrap_or(0) as usize;
+        let stab_bits = cmp::min(max_len, self.root_bits);
+        let first_code = first_codes(symb_len);
+        self.symb_len.clear();
+        self.symb_len.extend_from_slice(symb_len);
+        self.stab_bits = stab_bits;
+        self.max_len = max_len;
+        let used = symb_len.iter().filter(|&&l| l != 0).count();
+        self.compact = self.allow_compact
+            && used <= Self::COMPACT_MAX_SYMBOLS
+            && 1 << stab_bits > Self::COMPACT_MIN_TABLE;
+        self.counts.clear();
+        self.sorted.clear();
+        self.stab.clear();
+        if self.compact {
+            self.fill_compact(symb_len);
+            return;
+        }
+        let stab = &mut self.stab;
+        stab.resize(1 << stab_bits, TableEntry::None);
+
+        // 各接頭辞の副テーブルは、その接頭辞を持つ最長の符号に合わせる
+        let mut next_code = first_code;
+        for &l in symb_len.iter().filter(|&&l| l != 0) {
+            let len = usize::from(l);
+            let code = next_code[len] as u32;
+            next_code[len] += 1;
+            if len > stab_bits {
+                let bits = (len - stab_bits) as u8;
+                let i = Self::index(code >> bits, stab_bits, 0, stab_bits);
+                match stab[i] {
+                    TableEntry::Sub(_, b) if b >= bits => {}
+                    _ => stab[i] = TableEntry::Sub(0, bits),
                 }
             }
         }
-        Ok(Self {
-            stab_bits,
-            stab,
-            phantom: PhantomData,
+        for i in 0..1 << stab_bits {
+            if let TableEntry::Sub(_, bits) = stab[i] {
+                stab[i] = TableEntry::Sub(stab.len() as u32, bits);
+                stab.resize(stab.len() + (1 << bits), TableEntry::None);
+            }
+        }
+
+        let mut next_code = first_code;
+        for (symb, &l) in symb_len.iter().enumerate().filter(|x| *x.1 != 0) {
+            let len = usize::from(l);
+            let code = next_code[len] as u32;
+            next_code[len] += 1;
+            let (offset, code, len, bits) = if len <= stab_bits {
+                (0, code, len, stab_bits)
+            } else {
+                let rest = len - stab_bits;
+                let i = Self::index(code >> rest, stab_bits, 0, stab_bits);
+                match stab[i] {
+                    TableEntry::Sub(offset, bits) => (
+                        offset as usize,
+                        code & ((1 << rest) - 1),
+                        rest,
+                        usize::from(bits),
+                    ),
+                    _ => unreachable!(),
+                }
+            };
+            let symb = cast(symb).unwrap();
+            for j in 0..1 << (bits - len) {
+                stab[offset + Self::index(code, len, j, bits)] =
+                    TableEntry::Symbol(symb, len as u8);
+            }
+        }
+    }
+
+    fn fill_compact(&mut self, symb_len: &[u8]) {
+        self.counts.resize(self.max_len + 1, 0);
+        for &l in symb_len.iter().filter(|&&l| l != 0) {
+            self.counts[usize::from(l)] += 1;
+        }
+        // 正準符号の順、つまり (符号長, 記号) の順に並べる
+        for len in 1..=self.max_len as u8 {
+            self.sorted.extend(
+                symb_len
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &l)| l == len)
+                    .map(|(symb, _)| cast::<_, S>(symb).unwrap()),
+            );
+        }
+    }
+
+    /// Returns the table index of the `len` bit `code` followed by the
+    /// `bits - len` bit filler `j`.
+    #[inline]
+    fn index(code: u32, len: usize, j: usize, bits: usize) -> usize {
+        if !D::is_reverse() {
+            ((code as usize) << (bits - len)) | j
+        } else if len == 0 {
+            j
+        } else {
+            (code.reverse_bits() >> (32 - len)) as usize | (j << len)
+        }
+    }
+
+    /// Returns the code lengths the decoder was built from.
+    #[inline]
+    pub fn code_lengths(&self) -> &[u8] {
+        &self.symb_len
+    }
+
+    /// Returns the code lengths without the unused symbols at the end, as
+    /// `HuffmanEncoder::to_canonical_lengths` does.
+    pub fn to_canonical_lengths(&self) -> Vec<u8> {
+        let used = self
+            .symb_len
+            .iter()
+            .rposition(|&l| l != 0)
+            .map_or(0, |i| i + 1);
+        self.symb_len[..used].to_vec()
+    }
+
+    /// Returns the code of `symbol` in the order it is read, or `None` if
+    /// the symbol is not used.
+    pub fn code(&self, symbol: usize) -> Option<SmallBitVec<u32>> {
+        let len = usize::from(*self.symb_len.get(symbol).filter(|&&l| l != 0)?);
+        // 正準符号は (符号長, 記号) の順に前にある符号の数で決まる
+        let code = self
+            .symb_len
+            .iter()
+            .enumerate()
+            .filter(|&(i, &l)| l != 0 && (usize::from(l), i) < (len, symbol))
+            .map(|(_, &l)| 1_u32 << (len - usize::from(l)))
+            .sum::<u32>();
+        let code = SmallBitVec::new(code, len);
+        Some(if D::is_reverse() {
+            code.reverse()
+        } else {
+            code
         })
     }
 
-    pub(crate) fn dec<R: BitRead, I: Iterator<Item = u8>>(
+    /// Returns the symbols with a nonzero code length, in order.
+    pub fn symbols_used(&self) -> impl Iterator<Item = usize> + '_ {
+        symbols_used(&self.symb_len)
+    }
+
+    /// Reads one symbol. Returns `None` at the end of the input, including
+    /// when it ends inside a code.
+    #[inline]
+    pub fn dec<R: BitRead, I: Iterator<Item = u8>>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<S>, String> {
+        self.dec_with_len(reader, iter).map(|r| r.map(|(v, _)| v))
+    }
+
+    /// Like `dec`, but also returns the length of the code read, which is
+    /// the number of bits the reader advanced. Returns `None` without
+    /// advancing if the input ends inside a code.
+    pub fn dec_with_len<R: BitRead, I: Iterator<Item = u8>>(
         &mut self,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<u16>, String> {
+    ) -> Result<Option<(S, usize)>, String> {
+        if self.compact {
+            let c = reader.peek_bits::<u64, _>(self.max_len, iter)?;
+            if c.is_empty() {
+                return Ok(None);
+            }
+            let avail = c.len();
+            let c = if !D::is_reverse() {
+                *c.data_ref() << (self.max_len - c.len())
+            } else {
+                *c.data_ref()
+            };
+            return match self.compact_lookup(c, self.max_len, 0) {
+                Some((v, l)) => {
+                    Ok(Self::skip_code(reader, iter, l, avail)?
+                        .then_some((v, l)))
+                }
+                None if avail < self.max_len => Ok(None),
+                None => Err("huffman table error".to_owned()),
+            };
+        }
         let c = reader.peek_bits::<usize, _>(self.stab_bits, iter)?;
         if c.is_empty() {
             return Ok(None);
         }
+        let avail = c.len();
         let c = if !D::is_reverse() {
             *c.data_ref() << (self.stab_bits - c.len())
         } else {
             *c.data_ref()
         };
-        if let SymbolTableItem::Short(ref v, ref l) = self.stab[c] {
-            let _ = reader.skip_bits(*l as usize, iter)?;
-            Ok(Some(*v))
-        } else if let SymbolTableItem::Long(ref leaf) = self.stab[c] {
-            let _ = reader.skip_bits(self.stab_bits, iter)?;
-            let mut lleaf = leaf;
-
-            // 32ビット以上はエラーとするコードもあるが、
-            // そもそもハフマンテーブル自体そこまで長く作成できない。
-            loop {
-                match *lleaf {
-                    HuffmanLeaf::Leaf(v) => return Ok(Some(v)),
-                    HuffmanLeaf::Branch(ref lft, ref rgt) => {
-                        lleaf =
-                            if let Ok(b) = reader.read_bits::<u8, _>(1, iter) {
-                                if *b.data_ref() == 0 {
-                                    lft
-                                } else {
-                                    rgt
-                                }
-                            } else {
-                                return Err("reader error".to_owned());
-                            };
+        match self.stab[c] {
+            TableEntry::Symbol(v, l) => {
+                let l = l as usize;
+                Ok(Self::skip_code(reader, iter, l, avail)?.then_some((v, l)))
+            }
+            TableEntry::Sub(offset, bits) => {
+                // 根と副テーブルの分を一度に読む
+                let len = self.stab_bits + bits as usize;
+                let c = reader.peek_bits::<usize, _>(len, iter)?;
+                let avail = c.len();
+                let c = if !D::is_reverse() {
+                    (*c.data_ref() << (len - c.len())) & ((1 << bits) - 1)
+                } else {
+                    *c.data_ref() >> self.stab_bits
+                };
+                match self.stab[offset as usize + c] {
+                    TableEntry::Symbol(v, l) => {
+                        let l = self.stab_bits + l as usize;
+                        Ok(Self::skip_code(reader, iter, l, avail)?
+                            .then_some((v, l)))
+                    }
+                    _ if avail < len => Ok(None),
+                    _ => Err("huffman table error".to_owned()),
+                }
+            }
+            // 入力が尽きて 0 で埋めた所が未使用の符号に当たった
+            TableEntry::None if avail < self.stab_bits => Ok(None),
+            // 不完全なテーブルの未使用の符号
+            TableEntry::None => Err("huffman table error".to_owned()),
+        }
+    }
+
+    /// Consumes a code of `len` bits found in the `avail` bits peeked, and
+    /// returns false if the input ends inside it. The bits beyond the end
+    /// were looked up as zeros, so the code must not reach them.
+    #[inline]
+    fn skip_code<R: BitRead, I: Iterator<Item = u8>>(
+        reader: &mut R,
+        iter: &mut I,
+        len: usize,
+        avail: usize,
+    ) -> Result<bool, String> {
+        Ok(len <= avail && reader.skip_bits(len, iter)? == len)
+    }
+
+    /// Reads up to `out.len()` symbols into `out` and returns how many were
+    /// read. Fewer are returned only at the end of the input or on an error.
+    ///
+    /// An error after at least one symbol is not reported; the symbols
+    /// before it are returned and the reader is left at the bad code, so
+    /// the next call returns the error.
+    pub fn dec_many<R: BitRead, I: Iterator<Item = u8>>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+        out: &mut [S],
+    ) -> Result<usize, String> {
+        self.dec_many_until(reader, iter, out, |_| false)
+    }
+
+    /// Like `dec_many`, but also stops after a symbol for which `stop`
+    /// returns true, so that the caller can read what follows it.
+    pub(crate) fn dec_many_until<R, I, F>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+        out: &mut [S],
+        mut stop: F,
+    ) -> Result<usize, String>
+    where
+        R: BitRead,
+        I: Iterator<Item = u8>,
+        F: FnMut(S) -> bool,
+    {
+        let mut n = 0;
+        // 最長の符号が収まる間は、まとめて覗いたビットから続けて読む
+        while n < out.len() && self.max_len != 0 {
+            let w = match reader.peek_bits::<u64, _>(Self::WINDOW_BITS, iter) {
+                Ok(w) => w,
+                Err(e) if n == 0 => return Err(e.to_owned()),
+                Err(_) => return Ok(n),
+            };
+            if w.len() < self.max_len {
+                break;
+            }
+            let (data, len) = (*w.data_ref(), w.len());
+            let mut used = 0;
+            while n < out.len() && len - used >= self.max_len {
+                match self.lookup(data, len, used) {
+                    Some((v, l)) => {
+                        out[n] = v;
+                        n += 1;
+                        used += l;
+                        if stop(v) {
+                            let _ = reader.skip_bits(used, iter)?;
+                            return Ok(n);
+                        }
+                    }
+                    None => {
+                        let _ = reader.skip_bits(used, iter)?;
+                        if n == 0 {
+                            return Err("huffman table error".to_owned());
+                        }
+                        return Ok(n);
+                    }
+                }
+            }
+            let _ = reader.skip_bits(used, iter)?;
+        }
+
+        // 入力の終端付近は 1 記号ずつ読む
+        while n < out.len() {
+            match self.dec(reader, iter) {
+                Ok(Some(v)) => {
+                    out[n] = v;
+                    n += 1;
+                    if stop(v) {
+                        break;
                     }
-                    HuffmanLeaf::None => {
-                        return Err("huffman table error".to_owned())
+                }
+                Ok(None) => break,
+                Err(e) if n == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(n)
+    }
+
+    /// Resolves the code that starts after the first `used` of the `len`
+    /// bits in `data` and returns the symbol and its code length. At least
+    /// `max_len` bits must follow.
+    #[inline]
+    fn lookup(&self, data: u64, len: usize, used: usize) -> Option<(S, usize)> {
+        if self.compact {
+            return self.compact_lookup(data, len, used);
+        }
+        let c = Self::window_bits(data, len, used, self.stab_bits);
+        match self.stab[c] {
+            TableEntry::Symbol(v, l) => Some((v, usize::from(l))),
+            TableEntry::Sub(offset, bits) => {
+                let c = Self::window_bits(
+                    data,
+                    len,
+                    used + self.stab_bits,
+                    usize::from(bits),
+                );
+                match self.stab[offset as usize + c] {
+                    TableEntry::Symbol(v, l) => {
+                        Some((v, self.stab_bits + usize::from(l)))
                     }
+                    _ => None,
                 }
             }
+            TableEntry::None => None,
+        }
+    }
+
+    /// `lookup` for the compact form: tries each length in turn, as the
+    /// codes of one length are consecutive and follow those of the shorter
+    /// lengths.
+    fn compact_lookup(
+        &self,
+        data: u64,
+        len: usize,
+        used: usize,
+    ) -> Option<(S, usize)> {
+        let mut code = Self::window_bits(data, len, used, self.max_len) as u32;
+        if D::is_reverse() {
+            code = code.reverse_bits() >> (32 - self.max_len);
+        }
+        let (mut first, mut index) = (0_u32, 0_usize);
+        for l in 1..=self.max_len {
+            let c = code >> (self.max_len - l);
+            let count = u32::from(self.counts[l]);
+            if c < first + count {
+                return Some((self.sorted[index + (c - first) as usize], l));
+            }
+            index += count as usize;
+            first = (first + count) << 1;
+        }
+        None
+    }
+
+    /// Returns the `bits` bits that follow the first `used` of the `len`
+    /// bits in `data`.
+    #[inline]
+    fn window_bits(data: u64, len: usize, used: usize, bits: usize) -> usize {
+        let v = if D::is_reverse() {
+            data >> used
         } else {
-            unreachable!();
+            data >> (len - used - bits)
+        };
+        (v & ((1 << bits) - 1)) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Action;
+    use crate::bitio::direction::left::Left;
+    use crate::bitio::direction::right::Right;
+    use crate::bitio::slice_reader::SliceBitReader;
+    use crate::bitio::writer::{BitWriteExt, BitWriter};
+    use crate::core::iter;
+    use crate::core::mem::size_of;
+    use crate::huffman::canonical::length_limited_lengths;
+    use crate::huffman::encoder::HuffmanEncoder;
+    #[cfg(not(feature = "std"))]
+    use alloc::string::ToString;
+    use rand::{rng, RngExt};
+
+    #[test]
+    fn two_level_table_size() {
+        // 1, 2, ..., 15, 16, 16 の長さ。長い符号は根の 1 項目にまとまる
+        let symb_len = (1..16).chain(vec![16, 16]).collect::<Vec<u8>>();
+        let left = HuffmanDecoder::<Left>::new(&symb_len, 8).unwrap();
+        assert_eq!(left.stab.len(), (1 << 8) + (1 << 8));
+        let right = HuffmanDecoder::<Right>::new(&symb_len, 12).unwrap();
+        assert_eq!(right.stab.len(), (1 << 12) + (1 << 4));
+    }
+
+    #[test]
+    fn rebuild_reuses_table() {
+        let long = (1..16).chain(vec![16, 16]).collect::<Vec<u8>>();
+        let mut decoder = HuffmanDecoder::<Left>::new(&long, 8).unwrap();
+        let ptr = decoder.stab.as_ptr();
+        let capacity = decoder.stab.capacity();
+
+        let short = [2, 2, 2, 3, 3];
+        decoder.rebuild(&short).unwrap();
+        assert_eq!(decoder.stab.as_ptr(), ptr);
+        assert_eq!(decoder.stab.capacity(), capacity);
+        assert_eq!(decoder.code_lengths(), &short);
+
+        // 不正な長さでは元の表がそのまま残る
+        assert_eq!(
+            decoder.rebuild(&[1, 1, 1]),
+            Err("oversubscribed huffman table".to_string())
+        );
+        assert_eq!(decoder.code_lengths(), &short);
+        let fresh = HuffmanDecoder::<Left>::new(&short, 8).unwrap();
+        assert_eq!(decoder.stab, fresh.stab);
+
+        let mut encoder = HuffmanEncoder::<Left, u16>::new(&long);
+        encoder.rebuild(&short);
+        let fresh = HuffmanEncoder::<Left, u16>::new(&short);
+        for s in 0..short.len() as u16 {
+            assert_eq!(encoder.enc(s), fresh.enc(s));
+        }
+        assert!(encoder.enc(5).is_err());
+    }
+
+    #[test]
+    fn huffman_auto_stab_bits() {
+        let auto = HuffmanDecoder::<Left>::auto_stab_bits;
+        assert_eq!(auto(&[]), 0);
+        assert_eq!(auto(&[0, 1, 0, 1]), 1);
+        assert_eq!(auto(&[1, 2, 3, 3]), 3);
+        assert_eq!(auto(&[2; 4]), 2);
+        let mut few = vec![0; 510];
+        few[..5].copy_from_slice(&[1, 2, 3, 4, 4]);
+        assert_eq!(auto(&few), 4);
+        let mut few = vec![0; 510];
+        few[..6].copy_from_slice(&[1, 2, 3, 4, 5, 5]);
+        assert_eq!(auto(&few), 4);
+        assert_eq!(auto(&[9; 510]), 9);
+        assert_eq!(auto(&length_limited_lengths(&[1; 5000], 16)), 12);
+    }
+
+    fn stab_bits_checker<D: Direction>() {
+        let mut rng = rng();
+        let freqs = (0..510)
+            .map(|_| 1 << rng.random_range(0..14))
+            .collect::<Vec<usize>>();
+        let symb_len = length_limited_lengths(&freqs, 16);
+        let encoder = HuffmanEncoder::<D, u16>::new(&symb_len);
+        let input = (0..3000)
+            .map(|_| rng.random_range(0..510_u16))
+            .filter(|&s| symb_len[usize::from(s)] != 0)
+            .collect::<Vec<_>>();
+        let mut writer = BitWriter::<D>::new();
+        let data = input
+            .iter()
+            .map(|&s| encoder.enc(s).unwrap())
+            .to_bytes(&mut writer, Action::Flush)
+            .collect::<Vec<u8>>();
+
+        // 根の表の大きさに関係なく同じ結果になる
+        let mut decoder = HuffmanDecoder::<D>::new(&[1, 1], 12).unwrap();
+        for stab_bits in 1..=16 {
+            decoder
+                .rebuild_with_stab_bits(&symb_len, stab_bits)
+                .unwrap();
+            let mut reader = SliceBitReader::<D>::new(&data);
+            for &s in &input {
+                assert_eq!(
+                    decoder.dec(&mut reader, &mut iter::empty()),
+                    Ok(Some(s))
+                );
+            }
+        }
+
+        // 失敗しても前の表の大きさのまま
+        assert!(decoder.rebuild_with_stab_bits(&[1, 1, 1], 4).is_err());
+        decoder.rebuild(&symb_len).unwrap();
+        assert_eq!(
+            decoder.stab,
+            HuffmanDecoder::<D>::new(&symb_len, 16).unwrap().stab
+        );
+    }
+
+    #[test]
+    fn huffman_rebuild_with_stab_bits() {
+        stab_bits_checker::<Left>();
+        stab_bits_checker::<Right>();
+    }
+
+    fn large_alphabet_checker<D: Direction>() {
+        const SYMBOLS: usize = 100_000;
+        let mut rng = rng();
+        let freqs = (0..SYMBOLS)
+            .map(|_| 1 << rng.random_range(0..8))
+            .collect::<Vec<usize>>();
+        let symb_len = length_limited_lengths(&freqs, 20);
+        let encoder = HuffmanEncoder::<D, u32>::new(&symb_len);
+        let mut decoder = HuffmanDecoder::<D, u32>::new(&symb_len, 12).unwrap();
+        let test_array = (0..20_000)
+            .map(|_| rng.random_range(0..SYMBOLS as u32))
+            .chain(vec![0, 65_535, 65_536, SYMBOLS as u32 - 1])
+            .collect::<Vec<_>>();
+
+        let mut writer = BitWriter::<D>::new();
+        let data = test_array
+            .iter()
+            .map(|&s| encoder.enc(s).unwrap())
+            .to_bytes(&mut writer, Action::Flush)
+            .collect::<Vec<u8>>();
+        let mut reader = SliceBitReader::<D>::new(&data);
+        for &s in &test_array {
+            assert_eq!(
+                decoder.dec(&mut reader, &mut iter::empty()),
+                Ok(Some(s))
+            );
+        }
+
+        // 記号が u32 でも項目の大きさは変わらず、表は平坦な 20 ビットの表より
+        // ずっと小さい
+        assert_eq!(size_of::<TableEntry<u32>>(), size_of::<TableEntry<u16>>());
+        assert!(decoder.stab.len() < 2 * SYMBOLS);
+        assert!(decoder.stab.len() < (1 << 20) / 2);
+    }
+
+    #[test]
+    fn huffman_decode_large_alphabet() {
+        large_alphabet_checker::<Left>();
+        large_alphabet_checker::<Right>();
+        assert_eq!(
+            HuffmanDecoder::<Left, u8>::new(&[9; 512], 4).unwrap_err(),
+            "too many symbols"
+        );
+        assert!(HuffmanDecoder::<Left, u8>::new(&[8; 256], 4).is_ok());
+    }
+
+    fn dec_many_checker<D: Direction>() {
+        let mut rng = rng();
+        let freqs = (0..300)
+            .map(|_| 1 << rng.random_range(0..12))
+            .collect::<Vec<usize>>();
+        let symb_len = length_limited_lengths(&freqs, 16);
+        let encoder = HuffmanEncoder::<D, u16>::new(&symb_len);
+        let mut decoder = HuffmanDecoder::<D>::new(&symb_len, 8).unwrap();
+        let input = (0..5000)
+            .map(|_| rng.random_range(0..300_u16))
+            .collect::<Vec<_>>();
+        let mut writer = BitWriter::<D>::new();
+        let data = input
+            .iter()
+            .map(|&s| encoder.enc(s).unwrap())
+            .to_bytes(&mut writer, Action::Flush)
+            .collect::<Vec<u8>>();
+
+        let mut reader = SliceBitReader::<D>::new(&data);
+        let mut decoded = Vec::new();
+        let mut out = [0; 37];
+        while decoded.len() < input.len() {
+            let len = cmp::min(out.len(), input.len() - decoded.len());
+            let n = decoder
+                .dec_many(&mut reader, &mut iter::empty(), &mut out[..len])
+                .unwrap();
+            assert_eq!(n, len);
+            decoded.extend_from_slice(&out[..n]);
+        }
+        assert_eq!(decoded, input);
+
+        // 終端では埋め草の 0 ビットを 1 つずつ読んだのと同じだけ返す
+        let mut tail = 0;
+        while let Ok(Some(_)) = decoder.dec(&mut reader, &mut iter::empty()) {
+            tail += 1;
+        }
+        let mut reader = SliceBitReader::<D>::new(&data);
+        let mut out = vec![0; input.len() + 100];
+        assert_eq!(
+            decoder.dec_many(&mut reader, &mut iter::empty(), &mut out),
+            Ok(input.len() + tail)
+        );
+        assert_eq!(&out[..input.len()], &input[..]);
+        assert_eq!(
+            decoder.dec_many(&mut reader, &mut iter::empty(), &mut out),
+            Ok(0)
+        );
+
+        // 停止条件の記号の直後で止まる
+        let mut reader = SliceBitReader::<D>::new(&data);
+        let stop = input.iter().position(|&s| s >= 256).unwrap();
+        assert_eq!(
+            decoder.dec_many_until(
+                &mut reader,
+                &mut iter::empty(),
+                &mut out,
+                |s| s >= 256
+            ),
+            Ok(stop + 1)
+        );
+        assert_eq!(&out[..=stop], &input[..=stop]);
+        assert_eq!(
+            decoder.dec(&mut reader, &mut iter::empty()),
+            Ok(Some(input[stop + 1]))
+        );
+    }
+
+    #[test]
+    fn huffman_dec_many() {
+        dec_many_checker::<Left>();
+        dec_many_checker::<Right>();
+    }
+
+    #[test]
+    fn huffman_dec_many_error() {
+        // 符号 111 は未使用
+        let symb_len = [1, 2, 3];
+        let mut decoder =
+            HuffmanDecoder::<Left>::new_allow_incomplete(&symb_len, 2).unwrap();
+        let data = [0b0101_1011, 0b1000_0000, 0, 0, 0, 0, 0, 0];
+        let mut reader = SliceBitReader::<Left>::new(&data);
+        let mut out = [0; 8];
+        assert_eq!(
+            decoder.dec_many(&mut reader, &mut iter::empty(), &mut out),
+            Ok(3)
+        );
+        assert_eq!(out[..3], [0, 1, 2]);
+        assert_eq!(reader.bit_position(), 6);
+        assert_eq!(
+            decoder.dec_many(&mut reader, &mut iter::empty(), &mut out),
+            Err("huffman table error".to_owned())
+        );
+        assert_eq!(reader.bit_position(), 6);
+    }
+
+    fn compact_checker<D: Direction>(symb_len: &[u8]) {
+        let mut rng = rng();
+        let mut table = HuffmanDecoder::<D>::new(symb_len, 12).unwrap();
+        let mut compact =
+            HuffmanDecoder::<D>::new_compact(symb_len, 12).unwrap();
+        assert!(!table.compact);
+        assert!(compact.compact);
+        let used = compact.symbols_used().collect::<Vec<_>>();
+        for s in 0..symb_len.len() {
+            assert_eq!(compact.code(s), table.code(s));
         }
+
+        let encoder = HuffmanEncoder::<D, u16>::new(symb_len);
+        let input = (0..2000)
+            .map(|_| used[rng.random_range(0..used.len())] as u16)
+            .collect::<Vec<_>>();
+        let mut writer = BitWriter::<D>::new();
+        let encoded = input
+            .iter()
+            .map(|&s| encoder.enc(s).unwrap())
+            .to_bytes(&mut writer, Action::Flush)
+            .collect::<Vec<u8>>();
+        let random = (0..500).map(|_| rng.random()).collect::<Vec<u8>>();
+        for data in [encoded, random].iter() {
+            let mut expected = Vec::new();
+            let mut reader = SliceBitReader::<D>::new(data);
+            while let Some(s) =
+                table.dec(&mut reader, &mut iter::empty()).unwrap()
+            {
+                expected.push(s);
+            }
+            let mut reader = SliceBitReader::<D>::new(data);
+            for &s in &expected {
+                assert_eq!(
+                    compact.dec(&mut reader, &mut iter::empty()),
+                    Ok(Some(s))
+                );
+            }
+            assert_eq!(compact.dec(&mut reader, &mut iter::empty()), Ok(None));
+            let mut reader = SliceBitReader::<D>::new(data);
+            let mut out = vec![0; expected.len() + 1];
+            assert_eq!(
+                compact.dec_many(&mut reader, &mut iter::empty(), &mut out),
+                Ok(expected.len())
+            );
+            assert_eq!(&out[..expected.len()], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn huffman_decoder_compact() {
+        // lh7 の典型的な位置の木
+        let offset = [0, 0, 0, 8, 0, 0, 0, 0, 8, 7, 7, 7, 5, 4, 3, 2, 1];
+        compact_checker::<Left>(&offset);
+        compact_checker::<Right>(&offset);
+        let long = (1..16).chain(vec![16, 16]).collect::<Vec<u8>>();
+        compact_checker::<Left>(&long);
+        compact_checker::<Right>(&long);
+        // 大きなアルファベットの一部だけを使う
+        let mut sparse = vec![0; 600];
+        for (i, l) in (1..8).chain(Some(7)).enumerate() {
+            sparse[i * 83 + 5] = l;
+        }
+        compact_checker::<Left>(&sparse);
+        compact_checker::<Right>(&sparse);
+
+        let table = HuffmanDecoder::<Left>::new(&offset, 12).unwrap();
+        let mut compact =
+            HuffmanDecoder::<Left>::new_compact(&offset, 12).unwrap();
+        assert_eq!(table.stab.len() * size_of::<TableEntry<u16>>(), 2048);
+        assert_eq!(compact.stab.capacity(), 0);
+        assert_eq!(compact.counts.len() + compact.sorted.len() * 2, 29);
+
+        // 表が小さく済む符号では表に戻る
+        compact.rebuild(&[2, 2, 2, 3, 3]).unwrap();
+        assert!(!compact.compact);
+        compact
+            .rebuild(
+                &[0; 40].iter().chain(&offset).cloned().collect::<Vec<_>>(),
+            )
+            .unwrap();
+        assert!(compact.compact);
+        assert_eq!(compact.sorted[0], 56);
+    }
+
+    fn dec_with_len_checker<D: Direction>(
+        mut decoder: HuffmanDecoder<D>,
+        symb_len: &[u8],
+    ) {
+        // 符号と生のビット列を交互に並べる
+        let mut rng = rng();
+        let encoder = HuffmanEncoder::<D, u16>::new(symb_len);
+        let used = decoder.symbols_used().collect::<Vec<_>>();
+        let input = (0..3000)
+            .map(|_| {
+                let raw = rng.random_range(0..10);
+                let raw = SmallBitVec::new(rng.random_range(0..1 << raw), raw);
+                (used[rng.random_range(0..used.len())] as u16, raw)
+            })
+            .collect::<Vec<_>>();
+        let mut writer = BitWriter::<D>::new();
+        let data = input
+            .iter()
+            .flat_map(|(s, raw)| vec![encoder.enc(*s).unwrap(), raw.clone()])
+            .to_bytes(&mut writer, Action::Flush)
+            .collect::<Vec<u8>>();
+
+        let mut reader = SliceBitReader::<D>::new(&data);
+        let mut code_bits = 0;
+        let mut raw_bits = 0;
+        for &(s, ref raw) in &input {
+            let pos = reader.bit_position();
+            let (v, l) = decoder
+                .dec_with_len(&mut reader, &mut iter::empty())
+                .unwrap()
+                .unwrap();
+            assert_eq!((v, l), (s, usize::from(symb_len[usize::from(s)])));
+            assert_eq!(reader.bit_position() - pos, l);
+            code_bits += l;
+            let r = reader
+                .read_bits::<u16, _>(raw.len(), &mut iter::empty())
+                .unwrap();
+            assert_eq!(&r, raw);
+            raw_bits += raw.len();
+        }
+        assert_eq!(reader.bit_position(), code_bits + raw_bits);
+
+        // 入力が符号の途中で尽きると、進まずに None を返す
+        for &s in &used {
+            let code = encoder.enc(s as u16).unwrap();
+            let l = code.len();
+            for k in 0..l {
+                let code = u32::from(code.data());
+                let head = if D::is_reverse() {
+                    code & ((1 << k) - 1)
+                } else {
+                    code >> (l - k)
+                };
+                // 符号の頭がちょうど入力の末尾で終わるように前を埋める
+                let pad = (8 - k % 8) % 8;
+                let mut writer = BitWriter::<D>::new();
+                let data =
+                    vec![SmallBitVec::new(0, pad), SmallBitVec::new(head, k)]
+                        .into_iter()
+                        .to_bytes(&mut writer, Action::Flush)
+                        .collect::<Vec<u8>>();
+                assert_eq!(data.len() * 8, pad + k);
+                let mut reader = SliceBitReader::<D>::new(&data);
+                reader.skip_bits(pad, &mut iter::empty()).unwrap();
+                assert_eq!(
+                    decoder.dec_with_len(&mut reader, &mut iter::empty()),
+                    Ok(None)
+                );
+                assert_eq!(reader.bit_position(), pad);
+            }
+        }
+    }
+
+    #[test]
+    fn huffman_dec_with_len() {
+        // 根のテーブル、副テーブル、コンパクト形式のそれぞれで数える
+        let long = (1..16).chain(vec![16, 16]).collect::<Vec<u8>>();
+        let offset = [0, 0, 0, 8, 0, 0, 0, 0, 8, 7, 7, 7, 5, 4, 3, 2, 1];
+        for &stab_bits in &[4, 12] {
+            dec_with_len_checker(
+                HuffmanDecoder::<Left>::new(&long, stab_bits).unwrap(),
+                &long,
+            );
+            dec_with_len_checker(
+                HuffmanDecoder::<Right>::new(&long, stab_bits).unwrap(),
+                &long,
+            );
+        }
+        let compact = HuffmanDecoder::<Left>::new_compact(&offset, 12).unwrap();
+        assert!(compact.compact);
+        dec_with_len_checker(compact, &offset);
+        let compact =
+            HuffmanDecoder::<Right>::new_compact(&offset, 12).unwrap();
+        dec_with_len_checker(compact, &offset);
+    }
+
+    fn accessor_checker<D: Direction>() {
+        let symb_len = [0_u8, 4, 4, 4, 4, 3, 3, 2, 2, 0];
+        let decoder = HuffmanDecoder::<D>::new(&symb_len, 3).unwrap();
+        let encoder = HuffmanEncoder::<D, u32>::new(&symb_len);
+        assert_eq!(decoder.code_lengths(), &symb_len[..]);
+        assert_eq!(
+            decoder.symbols_used().collect::<Vec<_>>(),
+            (1..9).collect::<Vec<_>>()
+        );
+        for s in 0..11 {
+            assert_eq!(decoder.code(s), encoder.code(s));
+        }
+    }
+
+    #[test]
+    fn huffman_decoder_accessors() {
+        accessor_checker::<Left>();
+        accessor_checker::<Right>();
+        let decoder = HuffmanDecoder::<Right>::new(&[2, 1, 0, 2], 4).unwrap();
+        assert_eq!(decoder.code(0), Some(SmallBitVec::new(0b01, 2)));
+        assert_eq!(decoder.code(1), Some(SmallBitVec::new(0b0, 1)));
+        assert_eq!(decoder.code(2), None);
+        assert_eq!(decoder.code(3), Some(SmallBitVec::new(0b11, 2)));
     }
 }
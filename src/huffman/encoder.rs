@@ -7,9 +7,12 @@
 
 use crate::bitio::direction::Direction;
 use crate::bitio::small_bit_vec::{SmallBitVec, SmallBitVecReverse};
+use crate::bitio::writer::BitWrite;
 use crate::core::marker::PhantomData;
-use crate::core::ops::{Add, Shl};
-use crate::huffman::create_huffman_table;
+use crate::error::CompressionError;
+use crate::huffman::cano_huff_table::make_table;
+use crate::huffman::table::{write_coded, TABLE_COUNT_BITS};
+use crate::huffman::{first_codes, lengths_from_pairs, symbols_used};
 #[cfg(not(feature = "std"))]
 use alloc::borrow::ToOwned;
 #[cfg(not(feature = "std"))]
@@ -21,7 +24,12 @@ use alloc::vec;
 use alloc::vec::Vec;
 use num_traits::{cast, NumCast};
 
-pub(crate) struct HuffmanEncoder<D: Direction, T> {
+/// Canonical Huffman encoder.
+///
+/// The codes of each length are assigned in symbol order, starting from
+/// the shortest length, as in deflate.
+pub struct HuffmanEncoder<D: Direction, T> {
+    symb_len: Vec<u8>,
     bit_vec_tab: Vec<Option<SmallBitVec<T>>>,
     phantom: PhantomData<fn() -> D>,
 }
@@ -29,17 +37,108 @@ pub(crate) struct HuffmanEncoder<D: Direction, T> {
 impl<D, T> HuffmanEncoder<D, T>
 where
     D: Direction,
-    T: Clone + PartialOrd<T> + Shl<u8, Output = T> + Add<Output = T> + From<u8>,
+    T: Clone + NumCast,
     SmallBitVec<T>: SmallBitVecReverse,
 {
-    pub(crate) fn new(symb_len: &[u8]) -> Self {
-        Self {
-            bit_vec_tab: create_huffman_table(symb_len, D::is_reverse()),
+    /// Builds an encoder for the code lengths `symb_len`. Symbols of
+    /// length 0 are not encodable.
+    pub fn new(symb_len: &[u8]) -> Self {
+        let mut ret = Self {
+            symb_len: Vec::new(),
+            bit_vec_tab: Vec::new(),
             phantom: PhantomData,
+        };
+        ret.rebuild(symb_len);
+        ret
+    }
+
+    /// Rebuilds the encoder for new code lengths, reusing its storage.
+    pub fn rebuild(&mut self, symb_len: &[u8]) {
+        let mut next_code = first_codes(symb_len);
+        self.symb_len.clear();
+        self.symb_len.extend_from_slice(symb_len);
+        let used = symb_len.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1);
+        self.bit_vec_tab.clear();
+        self.bit_vec_tab.resize(used, None);
+        for (i, &l) in symb_len[..used].iter().enumerate() {
+            if l != 0 {
+                let code = next_code[l as usize];
+                next_code[l as usize] += 1;
+                let code = SmallBitVec::new(cast(code).unwrap(), l as usize);
+                self.bit_vec_tab[i] = Some(if D::is_reverse() {
+                    code.reverse()
+                } else {
+                    code
+                });
+            }
         }
     }
 
-    pub(crate) fn enc<U: NumCast + Clone>(
+    /// Like `new`, but takes `(symbol, length)` pairs in any order, as
+    /// `HuffmanDecoder::from_pairs` does. A symbol may appear only once.
+    pub fn from_pairs(pairs: &[(u32, u8)]) -> Result<Self, String> {
+        Ok(Self::new(&lengths_from_pairs(pairs)?))
+    }
+
+    /// Same as `new`; the inverse of `to_canonical_lengths`.
+    pub fn from_canonical_lengths(symb_len: &[u8]) -> Self {
+        Self::new(symb_len)
+    }
+
+    /// Builds an encoder from symbol frequencies, with no code longer than
+    /// `max_len`, and returns it with the code lengths.
+    ///
+    /// The lengths are those of `length_limited_lengths` without the unused
+    /// symbols at the end. Ties are broken by symbol index, so the result
+    /// depends only on `freqs` and `max_len`, on every platform.
+    pub fn from_frequencies(
+        freqs: &[usize],
+        max_len: usize,
+    ) -> (Self, Vec<u8>) {
+        let symb_len = make_table(freqs, max_len);
+        (Self::new(&symb_len), symb_len)
+    }
+
+    /// Returns the code lengths the encoder was built from.
+    #[inline]
+    pub fn code_lengths(&self) -> &[u8] {
+        &self.symb_len
+    }
+
+    /// Returns the code lengths without the unused symbols at the end.
+    /// With the canonical assignment they describe the code completely, so
+    /// equal codes give equal lengths.
+    pub fn to_canonical_lengths(&self) -> Vec<u8> {
+        self.symb_len[..self.bit_vec_tab.len()].to_vec()
+    }
+
+    /// Writes the code lengths in the run-length coded form of the -lh5-
+    /// symbol table, with a 16-bit symbol count, for
+    /// `HuffmanDecoder::read_table`.
+    ///
+    /// The unused symbols at the end are not written. A code of one symbol
+    /// is written as that symbol alone, and an empty code as symbol 0.
+    /// Lengths over 16 and more than 65535 symbols are a `DataError`.
+    pub fn write_table<W: BitWrite<u16>>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), CompressionError> {
+        write_coded::<D, _>(writer, &self.symb_len, TABLE_COUNT_BITS)
+    }
+
+    /// Returns the code of `symbol` in the order it is written, or `None`
+    /// if the symbol is not used.
+    pub fn code(&self, symbol: usize) -> Option<SmallBitVec<T>> {
+        self.bit_vec_tab.get(symbol).cloned().flatten()
+    }
+
+    /// Returns the symbols with a nonzero code length, in order.
+    pub fn symbols_used(&self) -> impl Iterator<Item = usize> + '_ {
+        symbols_used(&self.symb_len)
+    }
+
+    /// Returns the code of `data`.
+    pub fn enc<U: NumCast + Clone>(
         &self,
         data: U,
     ) -> Result<SmallBitVec<T>, String> {
@@ -59,6 +158,7 @@ mod tests {
     use super::*;
     use crate::bitio::direction::left::Left;
     use crate::bitio::direction::right::Right;
+    use crate::huffman::decoder::HuffmanDecoder;
 
     #[test]
     fn lefthuffman_encode_new() {
@@ -137,6 +237,73 @@ mod tests {
         assert_eq!(tab.len(), 256);
     }
 
+    #[test]
+    fn lefthuffman_from_frequencies() {
+        let (hencoder, symb_len) =
+            HuffmanEncoder::<Left, u16>::from_frequencies(
+                &[0, 1, 1, 2, 2, 4, 4, 8, 8, 0, 0],
+                15,
+            );
+        assert_eq!(symb_len, vec![0, 4, 4, 4, 4, 3, 3, 2, 2]);
+        assert_eq!(hencoder.enc(1).ok(), Some(SmallBitVec::new(0b1100, 4)));
+        assert_eq!(hencoder.enc(8).ok(), Some(SmallBitVec::new(0b01, 2)));
+        assert!(hencoder.enc(0).is_err());
+        assert!(hencoder.enc(9).is_err());
+    }
+
+    #[test]
+    fn huffman_encoder_accessors() {
+        let symb_len = [0_u8, 4, 4, 4, 4, 3, 3, 2, 2, 0, 0];
+        let hencoder = HuffmanEncoder::<Left, u16>::new(&symb_len);
+        assert_eq!(hencoder.code_lengths(), &symb_len[..]);
+        assert_eq!(
+            hencoder.symbols_used().collect::<Vec<_>>(),
+            (1..9).collect::<Vec<_>>()
+        );
+        assert_eq!(hencoder.code(0), None);
+        assert_eq!(hencoder.code(5), Some(SmallBitVec::new(0b100, 3)));
+        assert_eq!(hencoder.code(10), None);
+        assert_eq!(hencoder.code(11), None);
+    }
+
+    #[test]
+    fn huffman_from_frequencies_ties() {
+        // 同じ頻度では記号の小さい方が長い符号になる
+        let (_, symb_len) =
+            HuffmanEncoder::<Right, u16>::from_frequencies(&[1, 1, 1], 15);
+        assert_eq!(symb_len, vec![2, 2, 1]);
+        let (_, symb_len) = HuffmanEncoder::<Right, u16>::from_frequencies(
+            &[1, 1, 1, 1, 1],
+            15,
+        );
+        assert_eq!(symb_len, vec![3, 3, 2, 2, 2]);
+    }
+
+    #[test]
+    fn huffman_from_frequencies_overlong() {
+        // 制限なしでは長すぎる符号になる頻度でも、制限内の完全な符号を作る
+        let mut skewed = vec![1; 286];
+        skewed[0] = 1 << 30;
+        let mut fib = vec![1_usize, 1];
+        while fib.len() < 40 {
+            let l = fib.len();
+            fib.push(fib[l - 1] + fib[l - 2]);
+        }
+        let cases: [(&[usize], usize); 5] = [
+            (&skewed, 15),
+            (&skewed, 16),
+            (&fib, 15),
+            (&fib, 16),
+            (&fib[..19], 7),
+        ];
+        for &(freqs, max_len) in &cases {
+            let (_, symb_len) =
+                HuffmanEncoder::<Right, u16>::from_frequencies(freqs, max_len);
+            assert!(symb_len.iter().all(|&l| l as usize <= max_len));
+            assert!(HuffmanDecoder::<Right>::new(&symb_len, 9).is_ok());
+        }
+    }
+
     #[test]
     fn righthuffman_encode_new() {
         let hencoder =
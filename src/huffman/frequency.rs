@@ -0,0 +1,136 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// Symbol and offset histograms of a code stream, for building the Huffman
+/// tables of a block.
+///
+/// `map` turns each code, such as an `LzssCode`, into its literal/length
+/// symbol and, for a reference, its offset symbol. The format decides the
+/// mapping, so one counter serves formats with different length and
+/// offset codes.
+#[derive(Clone, Debug)]
+pub struct FrequencyCounter<F> {
+    symbols: Vec<usize>,
+    offsets: Vec<usize>,
+    map: F,
+}
+
+impl<F> FrequencyCounter<F> {
+    /// Creates a counter for `symbols` literal/length symbols and
+    /// `offsets` offset symbols.
+    pub fn new(symbols: usize, offsets: usize, map: F) -> Self {
+        Self {
+            symbols: vec![0; symbols],
+            offsets: vec![0; offsets],
+            map,
+        }
+    }
+
+    /// Counts `code`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `map` returns a symbol out of range.
+    pub fn add<C>(&mut self, code: &C)
+    where
+        F: FnMut(&C) -> (usize, Option<usize>),
+    {
+        let (symbol, offset) = (self.map)(code);
+        self.symbols[symbol] += 1;
+        if let Some(o) = offset {
+            self.offsets[o] += 1;
+        }
+    }
+
+    /// Counts a literal/length symbol that does not come from a code, such
+    /// as an end of block marker.
+    pub fn add_symbol(&mut self, symbol: usize) {
+        self.symbols[symbol] += 1;
+    }
+
+    /// Returns the counts of the literal/length symbols.
+    #[inline]
+    pub fn symbol_freqs(&self) -> &[usize] {
+        &self.symbols
+    }
+
+    /// Returns the counts of the offset symbols.
+    #[inline]
+    pub fn offset_freqs(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// Resets all counts to zero.
+    pub fn clear(&mut self) {
+        self.symbols.iter_mut().for_each(|f| *f = 0);
+        self.offsets.iter_mut().for_each(|f| *f = 0);
+    }
+}
+
+impl<C, F> Extend<C> for FrequencyCounter<F>
+where
+    F: FnMut(&C) -> (usize, Option<usize>),
+{
+    fn extend<I: IntoIterator<Item = C>>(&mut self, iter: I) {
+        for c in iter {
+            self.add(&c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency_counter_raw_symbols() {
+        let mut counter =
+            FrequencyCounter::new(4, 0, |&s: &u8| (s.into(), None));
+        counter.extend(vec![0_u8, 3, 3, 1, 3]);
+        counter.add_symbol(2);
+        assert_eq!(counter.symbol_freqs(), &[1, 1, 1, 3]);
+        assert_eq!(counter.offset_freqs(), &[]);
+        counter.clear();
+        assert_eq!(counter.symbol_freqs(), &[0; 4]);
+    }
+
+    #[cfg(feature = "lzss")]
+    #[test]
+    fn frequency_counter_lzss() {
+        use crate::lzss::LzssCode;
+
+        // 長さ 3 以降を 256 から、距離をビット数で数える
+        let map = |c: &LzssCode| match *c {
+            LzssCode::Symbol(s) => (usize::from(s), None),
+            LzssCode::Reference { len, pos } => (
+                len + 253,
+                Some((usize::BITS - pos.leading_zeros()) as usize),
+            ),
+        };
+        let mut counter = FrequencyCounter::new(260, 5, map);
+        let codes = [
+            LzssCode::Symbol(b'a'),
+            LzssCode::Reference { len: 3, pos: 0 },
+            LzssCode::Symbol(b'a'),
+            LzssCode::Reference { len: 5, pos: 9 },
+            LzssCode::Reference { len: 3, pos: 2 },
+        ];
+        counter.extend(codes.iter().cloned());
+        let mut symbols = [0; 260];
+        symbols[usize::from(b'a')] = 2;
+        symbols[256] = 2;
+        symbols[258] = 1;
+        assert_eq!(counter.symbol_freqs(), &symbols[..]);
+        assert_eq!(counter.offset_freqs(), &[1, 0, 1, 0, 1]);
+    }
+}
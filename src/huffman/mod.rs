@@ -6,64 +6,72 @@
 //! <http://mozilla.org/MPL/2.0/>.
 #![cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
 
+pub mod adaptive;
 pub(crate) mod cano_huff_table;
-pub(crate) mod decoder;
-pub(crate) mod encoder;
+pub mod canonical;
+pub mod decoder;
+pub mod encoder;
+mod frequency;
+pub(crate) mod table;
 
-use crate::bitio::small_bit_vec::{SmallBitVec, SmallBitVecReverse};
-use crate::bucket_sort::BucketSort;
-use crate::core::ops::{Add, Shl};
+pub use self::frequency::FrequencyCounter;
+
+use crate::core::mem;
+#[cfg(not(feature = "std"))]
+use alloc::borrow::ToOwned;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
 #[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
-fn create_huffman_table<
-    T: PartialOrd<T> + Shl<u8, Output = T> + Clone + From<u8> + Add<Output = T>,
->(
-    symb_len: &[u8],
-    is_reverse: bool,
-) -> Vec<Option<SmallBitVec<T>>>
-where
-    SmallBitVec<T>: SmallBitVecReverse,
-{
-    let symbs = symb_len
+/// Expands `(symbol, length)` pairs into lengths indexed by symbol.
+fn lengths_from_pairs(pairs: &[(u32, u8)]) -> Result<Vec<u8>, String> {
+    let len = pairs
+        .iter()
+        .map(|&(s, _)| s as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let mut symb_len = vec![0; len];
+    let mut seen = vec![false; len];
+    for &(s, l) in pairs {
+        if mem::replace(&mut seen[s as usize], true) {
+            return Err("duplicate symbol".to_owned());
+        }
+        symb_len[s as usize] = l;
+    }
+    Ok(symb_len)
+}
+
+fn symbols_used(symb_len: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    symb_len
         .iter()
         .enumerate()
-        .filter(|&(_, &t)| t != 0)
-        .collect::<Vec<_>>();
-    if !symbs.is_empty() {
-        let min_symb = symbs[0].0;
-        let max_symb = symbs.last().unwrap().0;
-        symbs
-            .bucket_sort_all_by_key(|x| *x.1)
-            .into_iter()
-            .scan((0, T::from(0)), move |c, (s, &l)| {
-                let code = c.1.clone() << if c.0 < l { l - c.0 } else { 0 };
-                *c = (l, code.clone() + T::from(1));
-                Some((
-                    s,
-                    if is_reverse {
-                        SmallBitVec::<T>::new(code, l as usize).reverse()
-                    } else {
-                        SmallBitVec::<T>::new(code, l as usize)
-                    },
-                ))
-            })
-            .collect::<Vec<_>>()
-            .bucket_sort_by_key(|x| x.0, min_symb, max_symb)
-            .into_iter()
-            .scan(0, move |c, (s, v)| {
-                let r = vec![None; s - *c].into_iter().chain(vec![Some(v)]);
-                *c = s + 1;
-                Some(r)
-            })
-            .flatten()
-            .collect::<Vec<_>>()
-    } else {
-        Vec::new()
+        .filter(|&(_, &l)| l != 0)
+        .map(|(i, _)| i)
+}
+
+/// Returns the first canonical code of each length, as in RFC 1951
+/// 3.2.2.
+///
+/// # Panics
+///
+/// Panics if a length is over 64.
+fn first_codes(symb_len: &[u8]) -> [u64; 65] {
+    let mut count = [0_u64; 65];
+    // 0 を数えないのは、同じ要素への加算が続くと遅いため
+    for &l in symb_len.iter().filter(|&&l| l != 0) {
+        count[usize::from(l)] += 1;
     }
+    let mut next_code = [0_u64; 65];
+    let mut code = 0_u64;
+    for len in 1..65 {
+        code = code.wrapping_add(count[len - 1]) << 1;
+        next_code[len] = code;
+    }
+    next_code
 }
 
 #[cfg(test)]
@@ -73,21 +81,52 @@ mod tests {
     use crate::bitio::direction::right::Right;
     use crate::bitio::direction::Direction;
     use crate::bitio::reader::BitReader;
+    use crate::bitio::small_bit_vec::{SmallBitVec, SmallBitVecReverse};
     use crate::bitio::writer::{BitWriteExt, BitWriter};
+    use crate::core::ops::{Add, BitOr, Shl, Shr};
+    use crate::huffman::canonical::length_limited_lengths;
     use crate::huffman::decoder::HuffmanDecoder;
     use crate::huffman::encoder::HuffmanEncoder;
     #[cfg(not(feature = "std"))]
+    use alloc::string::ToString;
+    #[cfg(not(feature = "std"))]
     #[allow(unused_imports)]
     use alloc::vec;
     #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
+    use num_traits::sign::Unsigned;
+    use rand::{rng, RngExt};
 
     fn enc_and_dec_checker<D: Direction>(
         symb_len: &[u8],
         testarray: &[u16],
         stab_bits: usize,
     ) {
-        let hencoder = HuffmanEncoder::<D, u16>::new(symb_len);
+        assert_eq!(
+            enc_and_dec::<D, u16>(symb_len, testarray, stab_bits),
+            testarray.to_vec()
+        );
+    }
+
+    fn enc_and_dec<D: Direction, T>(
+        symb_len: &[u8],
+        testarray: &[u16],
+        stab_bits: usize,
+    ) -> Vec<u16>
+    where
+        T: Copy
+            + BitOr<Output = T>
+            + From<u8>
+            + Shl<u8, Output = T>
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + Add<Output = T>
+            + PartialOrd<T>
+            + Unsigned
+            + num_traits::NumCast,
+        SmallBitVec<T>: SmallBitVecReverse,
+    {
+        let hencoder = HuffmanEncoder::<D, T>::new(symb_len);
         let mut hdecoder =
             HuffmanDecoder::<D>::new(symb_len, stab_bits).unwrap();
 
@@ -103,7 +142,7 @@ mod tests {
         while let Ok(Some(c)) = hdecoder.dec(&mut reader, &mut vec) {
             ac.push(c);
         }
-        assert_eq!(ac, testarray.to_vec());
+        ac
     }
 
     #[test]
@@ -149,4 +188,187 @@ mod tests {
 
         enc_and_dec_checker::<Right>(&symb_len, &test_array, 2);
     }
+
+    /// 1, 2, ..., `max_len - 1`, `max_len`, `max_len` の長さを持つ完全な符号
+    fn deep_symb_len(max_len: u8) -> Vec<u8> {
+        (1..max_len).chain(vec![max_len, max_len]).collect()
+    }
+
+    fn deep_test_array(max_len: u8) -> Vec<u16> {
+        (0..=u16::from(max_len))
+            .chain((0..=u16::from(max_len)).rev())
+            .chain(vec![0, 0, 1, 0, u16::from(max_len)])
+            .collect()
+    }
+
+    fn deep_checker<D: Direction, T>(max_len: u8, stab_bits: usize)
+    where
+        T: Copy
+            + BitOr<Output = T>
+            + From<u8>
+            + Shl<u8, Output = T>
+            + Shl<usize, Output = T>
+            + Shr<usize, Output = T>
+            + Add<Output = T>
+            + PartialOrd<T>
+            + Unsigned
+            + num_traits::NumCast,
+        SmallBitVec<T>: SmallBitVecReverse,
+    {
+        let symb_len = deep_symb_len(max_len);
+        let test_array = deep_test_array(max_len);
+        let ret = enc_and_dec::<D, T>(&symb_len, &test_array, stab_bits);
+        // 末尾のパディングは 1 ビットの符号として読まれる
+        assert_eq!(ret[..test_array.len()], test_array[..]);
+        assert!(ret[test_array.len()..].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn lefthuffman_decode_20bit() {
+        deep_checker::<Left, u32>(20, 8);
+        deep_checker::<Left, u32>(20, 20);
+    }
+
+    #[test]
+    fn righthuffman_decode_20bit() {
+        deep_checker::<Right, u32>(20, 8);
+        deep_checker::<Right, u32>(20, 20);
+    }
+
+    fn root_size_checker<D: Direction>() {
+        let mut rng = rng();
+        for _ in 0..20 {
+            let mut freqs = (0..rng.random_range(2..300))
+                .map(|_| match rng.random_range(0..3) {
+                    0 => 0,
+                    1 => 1,
+                    _ => 1 << rng.random_range(0..16),
+                })
+                .collect::<Vec<usize>>();
+            // 記号が 1 つだけの符号は不完全な表になるので 2 つは使う
+            freqs[0] += 1;
+            freqs[1] += 1;
+            let symb_len = length_limited_lengths(&freqs, 16);
+            let test_array = (0..2000)
+                .map(|_| rng.random_range(0..freqs.len()) as u16)
+                .filter(|&s| symb_len[s as usize] != 0)
+                .collect::<Vec<_>>();
+            for &stab_bits in &[1, 4, 9, 12, 16] {
+                let ret =
+                    enc_and_dec::<D, u32>(&symb_len, &test_array, stab_bits);
+                assert_eq!(ret[..test_array.len()], test_array[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn huffman_decode_root_sizes() {
+        root_size_checker::<Left>();
+        root_size_checker::<Right>();
+    }
+
+    #[test]
+    fn huffman_table_validation() {
+        let err = |l: &[u8]| HuffmanDecoder::<Left>::new(l, 4).unwrap_err();
+        assert_eq!(err(&[1, 1, 1]), "oversubscribed huffman table");
+        assert_eq!(err(&[1, 2, 0, 3]), "incomplete huffman table");
+        assert_eq!(err(&[0, 0]), "incomplete huffman table");
+        assert_eq!(err(&[2]), "incomplete huffman table");
+        assert!(HuffmanDecoder::<Left>::new(&[0, 1, 0], 4).is_ok());
+        assert_eq!(
+            HuffmanDecoder::<Left>::new_allow_incomplete(&[2, 1, 2, 2], 4)
+                .unwrap_err(),
+            "oversubscribed huffman table"
+        );
+
+        // 未使用の符号 11 を読むとエラー
+        let mut hdecoder =
+            HuffmanDecoder::<Left>::new_allow_incomplete(&[1, 2], 2).unwrap();
+        let mut reader = BitReader::<Left>::new();
+        let mut iter = vec![0b0101_1100].into_iter();
+        assert_eq!(hdecoder.dec(&mut reader, &mut iter), Ok(Some(0)));
+        assert_eq!(hdecoder.dec(&mut reader, &mut iter), Ok(Some(1)));
+        assert!(hdecoder.dec(&mut reader, &mut iter).is_err());
+    }
+
+    #[test]
+    fn huffman_decode_24bit() {
+        deep_checker::<Left, u32>(24, 12);
+        deep_checker::<Right, u32>(24, 12);
+        deep_checker::<Left, u64>(24, 12);
+        deep_checker::<Right, u64>(24, 12);
+    }
+
+    fn from_pairs_checker<D: Direction>() {
+        let mut rng = rng();
+        let freqs = (0..101)
+            .map(|_| 1 << rng.random_range(0..10))
+            .collect::<Vec<usize>>();
+        let lens = length_limited_lengths(&freqs, 15);
+        let mut pairs = lens
+            .iter()
+            .enumerate()
+            .map(|(i, &l)| (4000 + i as u32, l))
+            .collect::<Vec<_>>();
+        pairs.push((17, 0));
+        // 順序に依らない
+        for i in (1..pairs.len()).rev() {
+            pairs.swap(i, rng.random_range(0..=i));
+        }
+        let mut dense = vec![0; 4000];
+        dense.extend_from_slice(&lens);
+
+        let encoder = HuffmanEncoder::<D, u16>::from_pairs(&pairs).unwrap();
+        let dense_encoder = HuffmanEncoder::<D, u16>::new(&dense);
+        let mut decoder = HuffmanDecoder::<D>::from_pairs(&pairs, 10).unwrap();
+        let dense_decoder = HuffmanDecoder::<D>::new(&dense, 10).unwrap();
+        assert_eq!(encoder.code_lengths(), &dense[..]);
+        assert_eq!(decoder.code_lengths(), &dense[..]);
+        for s in 0..dense.len() + 1 {
+            assert_eq!(encoder.code(s), dense_encoder.code(s));
+            assert_eq!(decoder.code(s), dense_decoder.code(s));
+        }
+
+        let test_array = (0..1000)
+            .map(|_| 4000 + rng.random_range(0..101))
+            .collect::<Vec<u16>>();
+        let mut writer = BitWriter::<D>::new();
+        let mut data = test_array
+            .iter()
+            .map(|&c| encoder.enc(c).unwrap())
+            .to_bytes(&mut writer, Action::Flush);
+        let mut reader = BitReader::<D>::new();
+        for &c in &test_array {
+            assert_eq!(decoder.dec(&mut reader, &mut data), Ok(Some(c)));
+        }
+    }
+
+    #[test]
+    fn huffman_from_pairs() {
+        from_pairs_checker::<Left>();
+        from_pairs_checker::<Right>();
+
+        let err = "duplicate symbol".to_string();
+        let pairs = [(3, 1), (7, 2), (3, 2)];
+        assert_eq!(
+            HuffmanDecoder::<Left>::from_pairs(&pairs, 4).unwrap_err(),
+            err
+        );
+        assert_eq!(
+            HuffmanEncoder::<Left, u16>::from_pairs(&pairs).err(),
+            Some(err)
+        );
+        assert_eq!(
+            HuffmanDecoder::<Left>::from_pairs(&[(3, 1), (7, 2)], 4)
+                .unwrap_err(),
+            "incomplete huffman table"
+        );
+        assert_eq!(
+            HuffmanDecoder::<Left, u8>::from_pairs(&[(0, 1), (256, 1)], 4)
+                .unwrap_err(),
+            "too many symbols"
+        );
+        let single = HuffmanDecoder::<Left>::from_pairs(&[(9, 1)], 4).unwrap();
+        assert_eq!(single.symbols_used().collect::<Vec<_>>(), vec![9]);
+    }
 }
@@ -0,0 +1,604 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+//! Code length tables as the -lh5- to -lh7- formats of LHA write them.
+//!
+//! The plain form is a count of lengths followed by each length in 3 bits,
+//! with lengths of 7 and over continued in unary. The coded form writes a
+//! length tree in the plain form first, then codes each length with it,
+//! using its symbols 0, 1 and 2 for runs of 1, 3 to 18, and 20 to 531
+//! zeros. Either form writes a table with at most one used symbol as a
+//! count of 0 and the symbol.
+
+use crate::bitio::direction::Direction;
+use crate::bitio::reader::BitRead;
+use crate::bitio::small_bit_vec::SmallBitVec;
+use crate::bitio::writer::BitWrite;
+use crate::core::cmp;
+use crate::error::CompressionError;
+use crate::huffman::decoder::HuffmanDecoder;
+use crate::huffman::encoder::HuffmanEncoder;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// Longest code length a table can hold.
+pub(crate) const MAX_CODE_LEN: u8 = 16;
+/// Bits of the symbol count of `HuffmanEncoder::write_table`.
+pub(crate) const TABLE_COUNT_BITS: usize = 16;
+/// Bits of the count of the length tree.
+const LEN_TREE_COUNT_BITS: usize = 5;
+/// Symbols of the length tree: the runs of zeros and lengths 1 to 16.
+const LEN_TREE_SYMBOLS: usize = 3 + MAX_CODE_LEN as usize;
+/// Bits of the search table of the length tree.
+const LEN_TREE_STAB_BITS: usize = 5;
+/// Longest run of zeros of a single length tree symbol.
+const MAX_ZERO_RUN: usize = 20 + 511;
+
+/// Returns the number of lengths to write and the number of used symbols,
+/// or an error if the table cannot be written.
+fn table_size(
+    symb_len: &[u8],
+    count_bits: usize,
+) -> Result<(usize, usize), CompressionError> {
+    let count = symb_len.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1);
+    if count >> count_bits != 0 || symb_len.iter().any(|&l| l > MAX_CODE_LEN) {
+        return Err(CompressionError::DataError);
+    }
+    Ok((count, symb_len.iter().filter(|&&l| l != 0).count()))
+}
+
+fn write_single<W: BitWrite<u16>>(
+    writer: &mut W,
+    count: usize,
+    count_bits: usize,
+) {
+    writer.write_bits(&SmallBitVec::new(0, count_bits));
+    writer.write_bits(&SmallBitVec::new(
+        count.saturating_sub(1) as u16,
+        count_bits,
+    ));
+}
+
+fn write_len<W: BitWrite<u16>>(writer: &mut W, len: u8) {
+    if len >= 7 {
+        writer.write_bits(&SmallBitVec::new(7, 3));
+        writer.write_unary(usize::from(len - 7), false);
+    } else {
+        writer.write_bits(&SmallBitVec::new(u16::from(len), 3));
+    }
+}
+
+/// Writes `symb_len` in the plain form with a count of `count_bits` bits.
+/// With `zero_run`, a 2-bit count of zeros follows the third length, as
+/// in the length tree.
+pub(crate) fn write_plain<W: BitWrite<u16>>(
+    writer: &mut W,
+    symb_len: &[u8],
+    count_bits: usize,
+    zero_run: bool,
+) -> Result<(), CompressionError> {
+    let (count, used) = table_size(symb_len, count_bits)?;
+    if used <= 1 {
+        write_single(writer, count, count_bits);
+        return Ok(());
+    }
+    writer.write_bits(&SmallBitVec::new(count as u16, count_bits));
+    let mut i = 0;
+    for (symb, &len) in symb_len.iter().enumerate().filter(|x| *x.1 != 0) {
+        while symb >= i {
+            if zero_run && i == 3 {
+                let skip = if symb > 6 { 3 } else { symb - 3 };
+                writer.write_bits(&SmallBitVec::new(skip as u16, 2));
+                i += skip;
+            }
+            write_len(writer, if symb == i { len } else { 0 });
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `symb_len` in the coded form with a count of `count_bits` bits.
+pub(crate) fn write_coded<D: Direction, W: BitWrite<u16>>(
+    writer: &mut W,
+    symb_len: &[u8],
+    count_bits: usize,
+) -> Result<(), CompressionError> {
+    let (count, used) = table_size(symb_len, count_bits)?;
+    if used <= 1 {
+        write_single(writer, 0, LEN_TREE_COUNT_BITS);
+        write_single(writer, count, count_bits);
+        return Ok(());
+    }
+
+    // 長さを長さ表の記号と、ゼロの連続の追加ビットに直す
+    let mut list = Vec::new();
+    let mut freq = [0; LEN_TREE_SYMBOLS];
+    let mut i = 0;
+    for (symb, &len) in symb_len.iter().enumerate().filter(|x| *x.1 != 0) {
+        let mut gap = symb - i;
+        i = symb + 1;
+        while gap > MAX_ZERO_RUN {
+            list.push((2, MAX_ZERO_RUN - 20));
+            gap -= MAX_ZERO_RUN;
+        }
+        if gap > 19 {
+            list.push((2, gap - 20));
+        } else if gap == 19 {
+            list.push((1, 15));
+            list.push((0, 0));
+        } else if gap > 2 {
+            list.push((1, gap - 3));
+        } else {
+            list.extend((0..gap).map(|_| (0, 0)));
+        }
+        list.push((usize::from(len) + 2, 0));
+    }
+    for &(s, _) in &list {
+        freq[s] += 1;
+    }
+
+    let (len_enc, len_lens) = HuffmanEncoder::<D, u16>::from_frequencies(
+        &freq,
+        usize::from(MAX_CODE_LEN),
+    );
+    write_plain(writer, &len_lens, LEN_TREE_COUNT_BITS, true)?;
+    // 長さ表の記号が 1 種類なら符号は書かない
+    let single = len_lens.iter().filter(|&&l| l != 0).count() <= 1;
+    writer.write_bits(&SmallBitVec::new(count as u16, count_bits));
+    for (s, extra) in list {
+        if !single {
+            let code =
+                len_enc.enc(s).map_err(|_| CompressionError::Unexpected)?;
+            writer.write_bits(&code);
+        }
+        match s {
+            1 => writer.write_bits(&SmallBitVec::new(extra as u16, 4)),
+            2 => writer.write_bits(&SmallBitVec::new(extra as u16, 9)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_bits<R: BitRead, I: Iterator<Item = u8>>(
+    reader: &mut R,
+    iter: &mut I,
+    len: usize,
+) -> Result<u16, CompressionError> {
+    reader
+        .read_bits::<u16, _>(len, iter)
+        .map(|x| x.data())
+        .map_err(|_| CompressionError::UnexpectedEof)
+}
+
+fn read_len<R: BitRead, I: Iterator<Item = u8>>(
+    reader: &mut R,
+    iter: &mut I,
+) -> Result<u8, CompressionError> {
+    let mut c = read_bits(reader, iter, 3)? as u8;
+    if c == 7 {
+        // LHA は 3 + 13 ビットまで読むが、16 より長い符号は使えない
+        c += reader.read_unary(usize::from(MAX_CODE_LEN - 7), iter)? as u8;
+    }
+    Ok(c)
+}
+
+/// Reads a table written by `write_plain` into `out`. Returns the symbol
+/// of a table written without codes, leaving `out` empty.
+pub(crate) fn read_plain<R: BitRead, I: Iterator<Item = u8>>(
+    reader: &mut R,
+    iter: &mut I,
+    count_bits: usize,
+    zero_run: bool,
+    out: &mut Vec<u8>,
+) -> Result<Option<u16>, CompressionError> {
+    out.clear();
+    let len = usize::from(read_bits(reader, iter, count_bits)?);
+    if len == 0 {
+        return read_bits(reader, iter, count_bits).map(Some);
+    }
+    while out.len() < len {
+        if zero_run && out.len() == 3 {
+            let count = usize::from(read_bits(reader, iter, 2)?);
+            if count > len - out.len() {
+                return Err(CompressionError::DataError);
+            }
+            out.resize(out.len() + count, 0);
+            if out.len() == len {
+                break;
+            }
+        }
+        out.push(read_len(reader, iter)?);
+    }
+    Ok(None)
+}
+
+/// Reads a table written by `write_coded` into `out`, as `read_plain`
+/// does. The length tree is read into `len_decoder`.
+pub(crate) fn read_coded<D, R, I>(
+    reader: &mut R,
+    iter: &mut I,
+    count_bits: usize,
+    len_decoder: &mut TableDecoder<D>,
+    out: &mut Vec<u8>,
+) -> Result<Option<u16>, CompressionError>
+where
+    D: Direction,
+    R: BitRead,
+    I: Iterator<Item = u8>,
+{
+    let single = read_plain(reader, iter, LEN_TREE_COUNT_BITS, true, out)?;
+    len_decoder.set(single, out, LEN_TREE_STAB_BITS)?;
+    out.clear();
+    let len = usize::from(read_bits(reader, iter, count_bits)?);
+    if len == 0 {
+        return read_bits(reader, iter, count_bits).map(Some);
+    }
+    while out.len() < len {
+        let count = match len_decoder.dec(reader, iter)? {
+            None => return Err(CompressionError::UnexpectedEof),
+            Some(0) => 1,
+            Some(1) => 3 + usize::from(read_bits(reader, iter, 4)?),
+            Some(2) => 20 + usize::from(read_bits(reader, iter, 9)?),
+            Some(n) => {
+                out.push((n - 2) as u8);
+                continue;
+            }
+        };
+        // 0 の連続が表の記号数を越えるなら、広げる前に誤りとする
+        if count > len - out.len() {
+            return Err(CompressionError::DataError);
+        }
+        out.resize(out.len() + count, 0);
+    }
+    Ok(None)
+}
+
+/// Huffman decoder of a table read by `read_plain` or `read_coded`, or the
+/// only symbol of a table without codes, which takes no bits. The Huffman
+/// table is kept and rebuilt in place; sparse codes, such as most lzhuf
+/// offset trees, use the compact form.
+#[derive(Debug)]
+pub(crate) struct TableDecoder<D: Direction> {
+    decoder: Option<HuffmanDecoder<D>>,
+    single: Option<u16>,
+}
+
+impl<D: Direction> Default for TableDecoder<D> {
+    fn default() -> Self {
+        Self {
+            decoder: None,
+            single: None,
+        }
+    }
+}
+
+impl<D: Direction> TableDecoder<D> {
+    /// Sets the table from the result of `read_plain` or `read_coded` and
+    /// the lengths it read. The root table has at most `stab_bits` bits,
+    /// fewer if `HuffmanDecoder::auto_stab_bits` picks fewer.
+    pub(crate) fn set(
+        &mut self,
+        single: Option<u16>,
+        symb_len: &[u8],
+        stab_bits: usize,
+    ) -> Result<(), CompressionError> {
+        self.single = single;
+        if single.is_some() {
+            return Ok(());
+        }
+        let stab_bits =
+            cmp::min(stab_bits, HuffmanDecoder::<D>::auto_stab_bits(symb_len));
+        match self.decoder {
+            Some(ref mut hd) => hd.rebuild_with_stab_bits(symb_len, stab_bits),
+            None => HuffmanDecoder::new_compact(symb_len, stab_bits)
+                .map(|hd| self.decoder = Some(hd)),
+        }
+        .map_err(|_| CompressionError::DataError)
+    }
+
+    pub(crate) fn dec<R: BitRead, I: Iterator<Item = u8>>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<u16>, CompressionError> {
+        match (self.single, self.decoder.as_mut()) {
+            (Some(s), _) => Ok(Some(s)),
+            (None, Some(hd)) => hd
+                .dec(reader, iter)
+                .map_err(|_| CompressionError::DataError),
+            (None, None) => Err(CompressionError::DataError),
+        }
+    }
+
+    /// Reads symbols into `out` as `HuffmanDecoder::dec_many_until` does.
+    #[cfg(feature = "lzhuf")]
+    pub(crate) fn dec_many_until<R, I, F>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+        out: &mut [u16],
+        mut stop: F,
+    ) -> Result<usize, CompressionError>
+    where
+        R: BitRead,
+        I: Iterator<Item = u8>,
+        F: FnMut(u16) -> bool,
+    {
+        match (self.single, self.decoder.as_mut()) {
+            (Some(s), _) => {
+                let n = if stop(s) { out.len().min(1) } else { out.len() };
+                out[..n].iter_mut().for_each(|x| *x = s);
+                Ok(n)
+            }
+            (None, Some(hd)) => hd
+                .dec_many_until(reader, iter, out, stop)
+                .map_err(|_| CompressionError::DataError),
+            (None, None) => Err(CompressionError::DataError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitio::direction::left::Left;
+    use crate::bitio::direction::right::Right;
+    use crate::bitio::reader::BitReader;
+    use crate::bitio::writer::BitWriter;
+    use crate::huffman::canonical::length_limited_lengths;
+    #[cfg(not(feature = "std"))]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
+    use alloc::vec::IntoIter;
+    use rand::{rng, RngExt};
+    #[cfg(feature = "std")]
+    use std::vec::IntoIter;
+
+    /// Writes the table of `symb_len` and `symbols` coded with it, then reads
+    /// them back and returns the lengths read.
+    fn round_trip<D: Direction>(symb_len: &[u8], symbols: &[usize]) -> Vec<u8> {
+        let enc = HuffmanEncoder::<D, u16>::new(symb_len);
+        let mut writer = BitWriter::<D>::new();
+        enc.write_table(&mut writer).unwrap();
+        for &s in symbols {
+            writer.write_bits(&enc.enc(s).unwrap());
+        }
+        writer.pad_to_byte(false);
+        let bytes = crate::core::iter::from_fn(|| writer.pop_byte())
+            .collect::<Vec<_>>();
+
+        let mut reader = BitReader::<D>::new();
+        let mut iter = bytes.into_iter();
+        let mut dec =
+            HuffmanDecoder::<D>::read_table(&mut reader, &mut iter, 12)
+                .unwrap();
+        for &s in symbols {
+            assert_eq!(
+                dec.dec(&mut reader, &mut iter).unwrap(),
+                Some(s as u16)
+            );
+        }
+        dec.to_canonical_lengths()
+    }
+
+    fn random_lengths(len: usize) -> Vec<u8> {
+        let mut rng = rng();
+        let sparse = rng.random_range(0..4) == 0;
+        let freqs = (0..len)
+            .map(|_| match rng.random_range(0..8) {
+                0..=5 if sparse => 0,
+                0 => 0,
+                1 => 1 << rng.random_range(0..24),
+                _ => rng.random_range(1..1000),
+            })
+            .collect::<Vec<usize>>();
+        length_limited_lengths(&freqs, usize::from(MAX_CODE_LEN))
+    }
+
+    fn table_round_trip_checker<D: Direction>() {
+        let mut rng = rng();
+        for _ in 0..300 {
+            let len = match rng.random_range(0..3) {
+                0 => rng.random_range(2..40),
+                1 => rng.random_range(40..600),
+                _ => rng.random_range(600..5000),
+            };
+            let symb_len = random_lengths(len);
+            let used = symb_len
+                .iter()
+                .enumerate()
+                .filter(|&(_, &l)| l != 0)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            if used.len() < 2 {
+                continue;
+            }
+            let symbols = (0..20)
+                .map(|_| used[rng.random_range(0..used.len())])
+                .collect::<Vec<_>>();
+            let expected =
+                HuffmanEncoder::<D, u16>::new(&symb_len).to_canonical_lengths();
+            assert_eq!(round_trip::<D>(&symb_len, &symbols), expected);
+        }
+    }
+
+    #[test]
+    fn table_round_trip() {
+        table_round_trip_checker::<Left>();
+        table_round_trip_checker::<Right>();
+    }
+
+    #[test]
+    fn table_zero_runs() {
+        // 各長さのゼロの連続を、1 記号での上限をまたいでも書ける
+        for &gap in &[1, 2, 3, 18, 19, 20, 531, 532, 550, 1062, 1063, 3000] {
+            let mut symb_len = vec![2, 0, 2];
+            symb_len.resize(symb_len.len() + gap, 0);
+            symb_len.push(1);
+            assert_eq!(
+                round_trip::<Left>(&symb_len, &[0, 2, gap + 3]),
+                symb_len
+            );
+        }
+        // 長さ 16 の符号
+        let mut symb_len = (1..=16).collect::<Vec<u8>>();
+        symb_len.push(16);
+        assert_eq!(round_trip::<Right>(&symb_len, &[0, 15, 16]), symb_len);
+    }
+
+    /// Reads a table with `read` from the bit fields `fields`, written from
+    /// the upper bit.
+    fn read_fields<F>(
+        fields: &[(u16, usize)],
+        read: F,
+    ) -> Result<Vec<u8>, CompressionError>
+    where
+        F: FnOnce(
+            &mut BitReader<Left>,
+            &mut IntoIter<u8>,
+            &mut Vec<u8>,
+        ) -> Result<Option<u16>, CompressionError>,
+    {
+        let mut writer = BitWriter::<Left>::new();
+        for &(v, len) in fields {
+            writer.write_bits(&SmallBitVec::new(v, len));
+        }
+        writer.pad_to_byte(false);
+        let bytes = crate::core::iter::from_fn(|| writer.pop_byte())
+            .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        read(&mut BitReader::new(), &mut bytes.into_iter(), &mut out)
+            .map(|_| out)
+    }
+
+    #[test]
+    fn table_long_lengths() {
+        // 7 に続く 1 の数が符号長の延長で、16 までしか読まない
+        let plain = |ones| {
+            read_fields(
+                &[(1, 5), (7, 3), ((1_u16 << ones) - 1, ones), (0, 1)],
+                |r, i, o| read_plain(r, i, 5, false, o),
+            )
+        };
+        assert_eq!(plain(9), Ok(vec![16]));
+        assert_eq!(plain(10), Err(CompressionError::DataError));
+        assert_eq!(plain(13), Err(CompressionError::DataError));
+    }
+
+    #[test]
+    fn table_zero_run_overrun() {
+        // 長さ表は 0, 1, 2 の符号長 1, 2, 2 (符号 0, 10, 11)
+        let coded = |run| {
+            let mut len_decoder = TableDecoder::<Left>::default();
+            read_fields(
+                &[
+                    (3, 5),
+                    (1, 3),
+                    (2, 3),
+                    (2, 3),
+                    (5, 9),
+                    (2, 2),
+                    (run, 4),
+                    (0, 1),
+                    (0, 1),
+                ],
+                |r, i, o| read_coded(r, i, 9, &mut len_decoder, o),
+            )
+        };
+        // 5 個の表に 0 の連続 3 + run 個と、符号長 0 の記号
+        assert_eq!(coded(0), Ok(vec![0; 5]));
+        assert_eq!(coded(2), Ok(vec![0; 5]));
+        assert_eq!(coded(3), Err(CompressionError::DataError));
+        assert_eq!(coded(15), Err(CompressionError::DataError));
+
+        // 9 ビットで書ける最大の 511 個の表に、0 の連続 20 + 490 個と `last`
+        let long = |last: &[(u16, usize)]| {
+            let mut len_decoder = TableDecoder::<Left>::default();
+            let mut fields = vec![
+                (3, 5),
+                (1, 3),
+                (2, 3),
+                (2, 3),
+                (511, 9),
+                (3, 2),
+                (490, 9),
+            ];
+            fields.extend_from_slice(last);
+            let mut len = 0;
+            let ret = read_fields(&fields, |r, i, o| {
+                let ret = read_coded(r, i, 9, &mut len_decoder, o);
+                len = o.len();
+                ret
+            });
+            (ret, len)
+        };
+        assert_eq!(long(&[(0, 1)]), (Ok(vec![0; 511]), 511));
+        // 越える連続は、表を広げずに止める
+        for last in &[[(2, 2), (0, 4)], [(3, 2), (0, 9)], [(3, 2), (511, 9)]] {
+            assert_eq!(long(last), (Err(CompressionError::DataError), 510));
+        }
+    }
+
+    #[test]
+    fn table_single_symbol() {
+        let mut symb_len = vec![0; 700];
+        symb_len[650] = 1;
+        let mut expected = vec![0; 651];
+        expected[650] = 1;
+        assert_eq!(round_trip::<Left>(&symb_len, &[]), expected);
+        // 空の表は記号 0 だけの表として読む
+        assert_eq!(round_trip::<Left>(&[0, 0], &[]), vec![1]);
+    }
+
+    #[test]
+    fn table_write_errors() {
+        let mut writer = Vec::new();
+        let enc = HuffmanEncoder::<Left, u32>::new(&[1, 2, 3, 17, 17]);
+        assert_eq!(
+            enc.write_table(&mut writer),
+            Err(CompressionError::DataError)
+        );
+        let mut symb_len = vec![0; 65536];
+        symb_len[0] = 1;
+        symb_len[65535] = 1;
+        let enc = HuffmanEncoder::<Left, u16>::new(&symb_len);
+        assert_eq!(
+            enc.write_table(&mut writer),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    #[test]
+    fn canonical_lengths_round_trip() {
+        for _ in 0..50 {
+            let symb_len = random_lengths(300);
+            let mut padded = symb_len.clone();
+            padded.extend_from_slice(&[0; 7]);
+            let enc = HuffmanEncoder::<Left, u16>::new(&padded);
+            let lengths = enc.to_canonical_lengths();
+            assert_ne!(lengths.last(), Some(&0));
+            let enc2 =
+                HuffmanEncoder::<Left, u16>::from_canonical_lengths(&lengths);
+            assert_eq!(enc2.to_canonical_lengths(), lengths);
+            if lengths.iter().filter(|&&l| l != 0).count() < 2 {
+                continue;
+            }
+            let dec =
+                HuffmanDecoder::<Left>::from_canonical_lengths(&padded, 10)
+                    .unwrap();
+            assert_eq!(dec.to_canonical_lengths(), lengths);
+            for s in enc.symbols_used() {
+                assert_eq!(
+                    enc.code(s).map(|c| c.data() as u32),
+                    dec.code(s).map(|c| c.data())
+                );
+            }
+        }
+    }
+}
@@ -39,25 +39,36 @@
 pub(crate) use std as core;
 
 #[cfg(not(feature = "std"))]
+#[allow(clippy::single_component_path_imports)]
 pub(crate) use core;
 
-#[cfg(not(feature = "std"))]
+#[cfg(all(feature = "alloc", not(feature = "std")))]
 // #[macro_use(vec)]
 extern crate alloc;
 
 mod action;
 mod adler32;
 mod bitset;
-mod bucket_sort;
 mod cbuffer;
+
+/// Checksums of the compressed formats.
+pub mod checksum;
 mod crc32;
 mod error;
 
-mod bitio;
+/// Bit-level readers and writers.
+///
+/// Without the `alloc` feature, `BitWriter`, `DynBitRead` and the rewind
+/// window of `BitReader` are left out; `SliceBitWriter` writes into a
+/// caller-provided buffer instead.
+pub mod bitio;
 mod suffix_array;
 
-mod huffman;
-mod lzss;
+/// Huffman code construction.
+pub mod huffman;
+
+/// LZSS coding with configurable window and match lengths.
+pub mod lzss;
 mod traits;
 
 mod bzip2;
@@ -75,27 +86,38 @@ pub mod prelude {
         if #[cfg(feature = "bzip2")] {
             pub use crate::bzip2::decoder::BZip2Decoder;
             pub use crate::bzip2::encoder::BZip2Encoder;
-            pub use crate::bzip2::error::BZip2Error;
+            pub use crate::bzip2::error::{BZip2Error, BZip2ErrorKind};
         }
     }
 
     cfg_if! {
         if #[cfg(feature = "deflate")] {
-            pub use crate::deflate::decoder::Deflater;
-            pub use crate::deflate::encoder::Inflater;
+            pub use crate::deflate::decoder::{DeflateCodeDecoder, Deflater};
+            pub use crate::deflate::encoder::{
+                BlockMode, DeflateCodeEncoder, Inflater,
+            };
         }
     }
     cfg_if! {
         if #[cfg(feature = "gzip")] {
             pub use crate::gzip::decoder::GZipDecoder;
-            pub use crate::gzip::encoder::GZipEncoder;
+            pub use crate::gzip::encoder::{GZipCodeEncoder, GZipEncoder};
         }
     }
     cfg_if! {
         if #[cfg(feature = "lzhuf")] {
-            pub use crate::lzhuf::LzhufMethod;
-            pub use crate::lzhuf::decoder::LzhufDecoder;
-            pub use crate::lzhuf::encoder::LzhufEncoder;
+            pub use crate::lzhuf::{LzhufMethod, UnsupportedMethod};
+            pub use crate::lzhuf::archive::{LhaEntry, LhaReader};
+            #[cfg(feature = "std")]
+            pub use crate::lzhuf::archive::ExtractInfo;
+            pub use crate::lzhuf::decoder::{LzhufCodeDecoder, LzhufDecoder};
+            pub use crate::lzhuf::encoder::{
+                LzhufCodeEncoder, LzhufEncodeInfo, LzhufEncoder,
+            };
+            pub use crate::lzhuf::header::{LhaHeader, LhaTimestamp};
+            pub use crate::lzhuf::path::LhaPath;
+            #[cfg(feature = "std")]
+            pub use crate::lzhuf::writer::LhaWriter;
         }
     }
     cfg_if! {
@@ -108,10 +130,15 @@ pub mod prelude {
         if #[cfg(feature = "lzss")] {
             pub use crate::lzss::decoder::LzssDecoder;
             pub use crate::lzss::encoder::LzssEncoder;
+            pub use crate::lzss::format::{OkumuraDecoder, OkumuraEncoder};
             pub use crate::lzss::LzssCode;
         }
     }
-    pub use crate::error::CompressionError;
-    pub use crate::traits::decoder::{DecodeExt, DecodeIterator, Decoder};
+    pub use crate::error::{CompressionError, ErrorKind};
+    pub use crate::traits::decoder::{
+        DecodeExt, DecodeIterator, Decoder, TryDecodeExt, TryDecodeIterator,
+    };
     pub use crate::traits::encoder::{EncodeExt, EncodeIterator, Encoder};
+    #[cfg(feature = "std")]
+    pub use crate::traits::io::{DecodeReader, EncodeWriter};
 }
@@ -0,0 +1,585 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+#[cfg(feature = "std")]
+use crate::checksum::Crc16;
+use crate::core::cmp;
+use crate::error::CompressionError;
+use crate::lzhuf::decoder::LzhufDecoder;
+use crate::lzhuf::header::LhaHeader;
+use crate::lzhuf::path::LhaPath;
+use crate::lzhuf::LzhufMethod;
+#[cfg(feature = "std")]
+use crate::traits::decoder::DecodeExt;
+#[cfg(not(feature = "std"))]
+use alloc::borrow::Cow;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+/// A member of an LHA archive, its header and compressed data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LhaEntry<'a> {
+    pub header: LhaHeader,
+    data: Cow<'a, [u8]>,
+}
+
+impl LhaEntry<'_> {
+    /// Returns the compressed data.
+    pub fn compressed(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the path of the entry. It may be absolute or have `..`
+    /// components; see `sanitized_path`.
+    pub fn path(&self) -> LhaPath {
+        LhaPath::new(&self.header.name)
+    }
+
+    /// Returns the path without its root and its `.` and `..` components,
+    /// safe to extract under a directory.
+    pub fn sanitized_path(&self) -> LhaPath {
+        self.path().sanitized()
+    }
+
+    /// Returns whether the entry is a directory, of method `-lhd-`.
+    pub fn is_dir(&self) -> bool {
+        &self.header.method == b"-lhd-"
+    }
+
+    /// Decodes the data with the method of the header and checks it
+    /// against the original size and CRC-16. A directory decodes to no
+    /// data.
+    ///
+    /// A method the decoder does not have is `Unsupported`, and a CRC
+    /// that does not match `ChecksumMismatch`.
+    pub fn decompress(&self) -> Result<Vec<u8>, CompressionError> {
+        self.decompress_with(&mut LzhufDecoder::new(&LzhufMethod::Lh0))
+    }
+
+    /// Decodes as `decompress` does, with `decoder` reset for the member,
+    /// so the members of an archive can share one decoder and its window.
+    pub fn decompress_with(
+        &self,
+        decoder: &mut LzhufDecoder,
+    ) -> Result<Vec<u8>, CompressionError> {
+        if self.is_dir() {
+            return Ok(Vec::new());
+        }
+        let method =
+            self.header.method().ok_or(CompressionError::Unsupported)?;
+        decoder.reset_with_original_size(
+            &method,
+            self.header.original_size as usize,
+        );
+        let (data, _) = decoder.decode_slice(&self.data)?;
+        self.header.verify(&data)?;
+        Ok(data)
+    }
+}
+
+/// What `LhaEntry::extract_to` wrote.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtractInfo {
+    /// The number of bytes written, the original size of the header.
+    pub written: u64,
+    /// Whether the CRC-16 of the bytes written matches the header.
+    pub crc_matched: bool,
+}
+
+#[cfg(feature = "std")]
+impl LhaEntry<'_> {
+    const EXTRACT_BUF_LEN: usize = 0x8000;
+
+    /// Decodes the data into `sink` in chunks of 32 KiB, up to the original
+    /// size of the header, and computes the CRC-16 on the way; memory use
+    /// does not grow with the size of the member. A directory writes
+    /// nothing.
+    ///
+    /// The data is written before its CRC is known, so a CRC that does not
+    /// match is `crc_matched: false` rather than an error. Errors of the
+    /// data, such as `Unsupported` or `UnexpectedEof`, are
+    /// `io::ErrorKind::InvalidData` with the `CompressionError` inside.
+    pub fn extract_to<W: Write + ?Sized>(
+        &self,
+        sink: &mut W,
+    ) -> io::Result<ExtractInfo> {
+        self.extract_to_with(sink, &mut LzhufDecoder::new(&LzhufMethod::Lh0))
+    }
+
+    /// Decodes into `sink` as `extract_to` does, with `decoder` reset for
+    /// the member.
+    pub fn extract_to_with<W: Write + ?Sized>(
+        &self,
+        sink: &mut W,
+        decoder: &mut LzhufDecoder,
+    ) -> io::Result<ExtractInfo> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        if self.is_dir() {
+            return Ok(ExtractInfo {
+                written: 0,
+                crc_matched: true,
+            });
+        }
+        let method = self
+            .header
+            .method()
+            .ok_or_else(|| invalid(CompressionError::Unsupported))?;
+        decoder.reset_with_original_size(
+            &method,
+            self.header.original_size as usize,
+        );
+        let mut crc = Crc16::new();
+        let mut written = 0;
+        let mut buf = Vec::with_capacity(Self::EXTRACT_BUF_LEN);
+        let mut bytes = self.data.iter().cloned().decode(decoder);
+        loop {
+            for b in (&mut bytes).take(Self::EXTRACT_BUF_LEN) {
+                buf.push(b.map_err(invalid)?);
+            }
+            if buf.is_empty() {
+                break;
+            }
+            crc.update(&buf);
+            sink.write_all(&buf)?;
+            written += buf.len() as u64;
+            buf.clear();
+        }
+        Ok(ExtractInfo {
+            written,
+            crc_matched: crc.finish() == self.header.crc,
+        })
+    }
+}
+
+/// Iterator over the members of an LHA archive.
+///
+/// It stops after the end of the archive, a zero byte or the end of the
+/// input, or after the first error in a header. Members are not decoded
+/// until `LhaEntry::decompress`, so one of an unknown method does not stop
+/// the ones after it. `skip_stub` finds the archive after the program of
+/// a self-extracting archive.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// fn extract(
+///     archive: &[u8],
+/// ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, CompressionError> {
+///     let mut ret = Vec::new();
+///     for entry in LhaReader::new(archive) {
+///         let entry = entry?;
+///         match entry.decompress() {
+///             Ok(data) => ret.push((entry.header.name.clone(), data)),
+///             Err(e) if e.kind() == ErrorKind::Unsupported => {
+///                 eprintln!("skipped {:?}", entry.header.name);
+///             }
+///             Err(e) => return Err(e),
+///         }
+///     }
+///     Ok(ret)
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LhaReader<'a> {
+    archive: Cow<'a, [u8]>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> LhaReader<'a> {
+    /// Creates a reader of the archive in `archive`. The entries borrow
+    /// their data from it.
+    pub fn new(archive: &'a [u8]) -> Self {
+        Self {
+            archive: Cow::Borrowed(archive),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl LhaReader<'static> {
+    /// Reads the whole archive from `reader` and creates a reader of it.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut archive = Vec::new();
+        reader.read_to_end(&mut archive)?;
+        Ok(Self {
+            archive: Cow::Owned(archive),
+            pos: 0,
+            done: false,
+        })
+    }
+}
+
+impl<'a> LhaReader<'a> {
+    /// Skips a stub of up to `limit` bytes before the archive, such as the
+    /// program of a self-extracting archive, and returns its length, 0 if
+    /// the archive starts where the reader is. Call it before reading the
+    /// entries.
+    ///
+    /// The archive starts at the first header with a method of `-lh?-` or
+    /// `-lz?-`, a checksum that matches, or a header CRC for level 2, and
+    /// data that ends within the input; a program may hold method names or
+    /// bytes that pass one of these. No such header is `DataError`.
+    pub fn skip_stub(
+        &mut self,
+        limit: usize,
+    ) -> Result<usize, CompressionError> {
+        let rest = &self.archive[self.pos..];
+        let stub = (0..=cmp::min(limit, rest.len()))
+            .find(|&i| is_archive_start(&rest[i..]))
+            .ok_or(CompressionError::DataError)?;
+        self.pos += stub;
+        Ok(stub)
+    }
+
+    fn next_entry(&mut self) -> Result<Option<LhaEntry<'a>>, CompressionError> {
+        let header = match LhaHeader::parse(&self.archive[self.pos..])? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+        let payload = header.payload();
+        let range = self.pos + payload.start..self.pos + payload.end;
+        let data = match self.archive {
+            Cow::Borrowed(a) => a.get(range).map(Cow::Borrowed),
+            Cow::Owned(ref a) => a.get(range).map(|d| Cow::Owned(d.to_vec())),
+        }
+        .ok_or(CompressionError::UnexpectedEof)?;
+        self.pos += payload.end;
+        Ok(Some(LhaEntry { header, data }))
+    }
+}
+
+/// Returns whether `input` starts with a header that `skip_stub` takes
+/// for the start of an archive.
+fn is_archive_start(input: &[u8]) -> bool {
+    match input.get(2..7) {
+        Some([b'-', b'l', b'h' | b'z', _, b'-']) => {}
+        _ => return false,
+    }
+    match LhaHeader::parse(input) {
+        // レベル 2 の CRC は共通ヘッダにしかない
+        Ok(Some(h)) => {
+            (h.level != 2 || h.extended.iter().any(|e| e.0 == 0))
+                && h.payload().end <= input.len()
+        }
+        _ => false,
+    }
+}
+
+impl<'a> Iterator for LhaReader<'a> {
+    type Item = Result<LhaEntry<'a>, CompressionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let ret = self.next_entry().transpose();
+        if !matches!(ret, Some(Ok(_))) {
+            self.done = true;
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::lzhuf::decoder::LzhufCodeDecoder;
+    use crate::lzss::decoder::LzssDecoder;
+    use crate::traits::decoder::DecodeExt;
+    #[cfg(not(feature = "std"))]
+    use alloc::{vec, vec::Vec};
+
+    const MIXED: &[u8] = include_bytes!("../../data/mixed.lzh");
+    const SAMPLE3: &[u8] = include_bytes!("../../data/sample3.ref");
+    const HELLO: &[u8] = b"Hello, LHA!\r\nHello, LHA!\r\nHello, LHA!\r\n";
+
+    #[test]
+    fn test_extract() {
+        let mut names = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in LhaReader::new(MIXED) {
+            let entry = entry.unwrap();
+            match entry.decompress() {
+                Ok(data) => names.push((entry.header.name.clone(), data)),
+                Err(e) => {
+                    assert_eq!(e.kind(), ErrorKind::Unsupported);
+                    skipped.push(entry.header.name.clone());
+                }
+            }
+        }
+        assert_eq!(
+            names,
+            vec![
+                (b"docs/".to_vec(), Vec::new()),
+                (b"docs/lh5.txt".to_vec(), SAMPLE3.to_vec()),
+                (b"lh7.txt".to_vec(), SAMPLE3.to_vec()),
+                (b"HELLO.TXT".to_vec(), HELLO.to_vec()),
+            ]
+        );
+        assert_eq!(skipped, vec![b"OLD.PMA".to_vec()]);
+    }
+
+    #[test]
+    fn test_stub() {
+        // 4 KiB のスタブに、方式の表と条件を 1 つずつ欠くヘッダを置いた
+        const SFX: &[u8] = include_bytes!("../../data/sfx.exe");
+        let members = |reader: LhaReader<'_>| {
+            reader
+                .map(|e| {
+                    let e = e.unwrap();
+                    (e.header.name.clone(), e.decompress().ok())
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut reader = LhaReader::new(SFX);
+        assert_eq!(reader.skip_stub(8 << 10), Ok(4096));
+        assert_eq!(members(reader), members(LhaReader::new(MIXED)));
+
+        assert!(matches!(LhaReader::new(SFX).next(), Some(Err(_))));
+        assert_eq!(
+            LhaReader::new(SFX).skip_stub(4095),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(LhaReader::new(MIXED).skip_stub(0), Ok(0));
+    }
+
+    #[test]
+    fn test_larc() {
+        // LArc の符号化を写したもので圧縮したメンバー
+        const LARC: &[u8] = include_bytes!("../../data/larc.lzh");
+        let entries =
+            LhaReader::new(LARC).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (&e.header.name[..], e.header.method(), e.header.crc))
+                .collect::<Vec<_>>(),
+            vec![
+                (&b"SAMPLE3.TXT"[..], Some(LzhufMethod::Lz5), 0x3221),
+                (b"SAMPLE3.LZS", Some(LzhufMethod::Lzs), 0x3221),
+                (b"FILL.BIN", Some(LzhufMethod::Lz5), 0xB6C9),
+                (b"HELLO.TXT", Some(LzhufMethod::Lzs), 0xE31C),
+            ]
+        );
+        for entry in &entries {
+            let data = entry.decompress().unwrap();
+            assert_eq!(data.len(), entry.header.original_size as usize);
+            // 符号列は、窓の初期値を辞書にして同じデータに戻る
+            let method = entry.header.method().unwrap();
+            let decoded = entry
+                .compressed()
+                .iter()
+                .cloned()
+                .decode(&mut LzhufCodeDecoder::with_original_size(
+                    &method,
+                    data.len(),
+                ))
+                .map(Result::unwrap)
+                .decode(&mut LzssDecoder::with_dict(
+                    1 << method.dictionary_bits(),
+                    &method.initial_window(),
+                ))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(data));
+        }
+        assert_eq!(entries[0].decompress().unwrap(), SAMPLE3);
+        assert_eq!(entries[3].decompress().unwrap(), HELLO);
+    }
+
+    #[test]
+    fn test_methods() {
+        let entries = LhaReader::new(MIXED)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| e.header.method())
+                .collect::<Vec<_>>(),
+            vec![
+                None,
+                Some(LzhufMethod::Lh5),
+                Some(LzhufMethod::Lh7),
+                None,
+                Some(LzhufMethod::Lh0),
+            ]
+        );
+        assert!(entries[0].is_dir());
+        assert_eq!(entries[3].compressed(), &(0..16).collect::<Vec<u8>>()[..]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_reader() {
+        let borrowed = LhaReader::new(MIXED)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let owned = LhaReader::from_reader(MIXED)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(owned, borrowed);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_paths() {
+        use crate::lzhuf::header::LhaTimestamp;
+        use crate::lzhuf::writer::LhaWriter;
+
+        // 拡張ヘッダ 0x02 の 0xFF 区切りで .. を書く
+        let mut writer = LhaWriter::new(Vec::new());
+        let names: [&[u8]; 3] =
+            [b"../../evil", b"docs/readme.txt", b"\x93\xFA\x96\x7B.txt"];
+        for name in &names {
+            writer.add(name, HELLO, LhaTimestamp::Unix(0)).unwrap();
+        }
+        let archive = writer.finish().unwrap();
+        assert!(archive.windows(3).any(|w| w == b"..\xFF"));
+        let paths = LhaReader::new(&archive)
+            .map(|e| {
+                let e = e.unwrap();
+                (e.path(), e.sanitized_path())
+            })
+            .collect::<Vec<_>>();
+        assert!(!paths[0].0.is_safe());
+        assert_eq!(paths[0].1.to_string_lossy(), "evil");
+        assert!(paths[1].0.is_safe());
+        assert_eq!(paths[1].1.to_string_lossy(), "docs/readme.txt");
+        // 「日本.txt」の Shift_JIS のバイトはそのまま
+        assert_eq!(paths[2].1.components(), &[names[2].to_vec()]);
+    }
+
+    #[test]
+    fn test_member_boundary() {
+        // 各項目の直後に次のヘッダが続く入力から、元の大きさだけ取り出す
+        let mut rest = MIXED;
+        while let Some(header) = LhaHeader::parse(rest).unwrap() {
+            let next = &rest[header.payload().end..];
+            let size = header.original_size as usize;
+            rest = &rest[header.header_len..];
+            let method = match header.method() {
+                Some(m) => m,
+                None => {
+                    rest = next;
+                    continue;
+                }
+            };
+            let (data, used) = LzhufDecoder::with_original_size(&method, size)
+                .decode_slice(rest)
+                .unwrap();
+            assert_eq!(used, header.compressed_size as usize);
+            header.verify(&data).unwrap();
+
+            let mut iter = rest.iter().cloned();
+            let decoded = (&mut iter)
+                .take(used)
+                .decode(&mut LzhufDecoder::with_original_size(&method, size))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(decoded, data);
+            assert_eq!(iter.collect::<Vec<_>>(), next);
+            rest = next;
+        }
+        assert_eq!(rest, [0]);
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        // 最後の -lh0- のデータを 1 バイト変える
+        let mut input = MIXED.to_vec();
+        let len = input.len();
+        input[len - 2] ^= 1;
+        let entry = LhaReader::new(&input).last().unwrap().unwrap();
+        assert_eq!(
+            entry.decompress().map_err(|e| e.kind()),
+            Err(ErrorKind::ChecksumMismatch)
+        );
+        #[cfg(feature = "std")]
+        {
+            let mut sink = Vec::new();
+            let info = entry.extract_to(&mut sink).unwrap();
+            assert_eq!((info.written, info.crc_matched), (39, false));
+            assert_eq!(sink, [&HELLO[..38], &[HELLO[38] ^ 1]].concat());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_extract_to() {
+        for entry in LhaReader::new(MIXED) {
+            let entry = entry.unwrap();
+            let mut sink = Vec::new();
+            match (entry.decompress(), entry.extract_to(&mut sink)) {
+                (Ok(data), Ok(info)) => {
+                    assert_eq!(sink, data);
+                    assert_eq!(info.written, data.len() as u64);
+                    assert!(info.crc_matched);
+                }
+                (Err(e), Err(io)) => {
+                    assert_eq!(e.kind(), ErrorKind::Unsupported);
+                    assert_eq!(io.kind(), io::ErrorKind::InvalidData);
+                    let inner = io.get_ref().unwrap();
+                    assert_eq!(
+                        inner.downcast_ref::<CompressionError>(),
+                        Some(&e)
+                    );
+                }
+                r => panic!("{:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_decoder() {
+        let entries = LhaReader::new(MIXED)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut decoder = LzhufDecoder::new(&LzhufMethod::Lh0);
+        // -lh7- の次の -lh5- は大きい窓をそのまま使う
+        for entry in entries.iter().chain(entries.iter().rev()) {
+            assert_eq!(entry.decompress_with(&mut decoder), entry.decompress());
+        }
+        // 途中で誤った項目のあとも、次の項目から復号し直せる
+        let mut broken = entries[2].data.to_vec();
+        broken.truncate(broken.len() / 2);
+        let entry = LhaEntry {
+            header: entries[2].header.clone(),
+            data: Cow::Owned(broken),
+        };
+        assert!(entry.decompress_with(&mut decoder).is_err());
+        assert_eq!(entries[1].decompress_with(&mut decoder).unwrap(), SAMPLE3);
+    }
+
+    #[test]
+    fn test_truncated() {
+        // 途中で切れたアーカイブは、そこまでの項目と誤りを返して止まる
+        let mut reader = LhaReader::new(&MIXED[..MIXED.len() - 8]);
+        for _ in 0..4 {
+            assert!(reader.next().unwrap().is_ok());
+        }
+        assert_eq!(
+            reader.next().map(|r| r.map_err(|e| e.kind())),
+            Some(Err(ErrorKind::UnexpectedEof))
+        );
+        assert!(reader.next().is_none());
+        // 終わりの 0 がなくてもよい
+        let len = MIXED.len();
+        assert_eq!(LhaReader::new(&MIXED[..len - 1]).count(), 5);
+    }
+}
@@ -7,9 +7,12 @@
 
 use crate::bitio::direction::left::Left;
 use crate::bitio::reader::{BitRead, BitReader};
+use crate::core::{cmp, iter};
 use crate::error::CompressionError;
+use crate::huffman::adaptive::AdaptiveHuffmanDecoder;
 use crate::huffman::decoder::HuffmanDecoder;
-use crate::lzhuf::{LzhufMethod, LZSS_MIN_MATCH};
+use crate::huffman::table::{read_coded, read_plain, TableDecoder};
+use crate::lzhuf::LzhufMethod;
 use crate::lzss::decoder::LzssDecoder;
 use crate::lzss::LzssCode;
 use crate::traits::decoder::{
@@ -21,282 +24,796 @@ use alloc::vec;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
-#[derive(Debug)]
-enum LzhufHuffmanDecoder {
-    HuffmanDecoder(HuffmanDecoder<Left>),
-    Default(u16),
-}
-
-impl LzhufHuffmanDecoder {
-    pub(crate) fn dec<R: BitRead, I: Iterator<Item = u8>>(
-        &mut self,
-        reader: &mut R,
-        iter: &mut I,
-    ) -> Result<Option<u16>, CompressionError> {
-        match *self {
-            LzhufHuffmanDecoder::HuffmanDecoder(ref mut hd) => hd
-                .dec(reader, iter)
-                .map_err(|_| CompressionError::DataError),
-            LzhufHuffmanDecoder::Default(s) => Ok(Some(s)),
-        }
-    }
-}
-
 #[derive(Debug)]
 pub(crate) struct LzhufDecoderInner {
+    method: LzhufMethod,
     offset_len: usize,
     min_match: usize,
     block_len: usize,
-    symbol_decoder: Option<LzhufHuffmanDecoder>,
-    offset_decoder: Option<LzhufHuffmanDecoder>,
+    len_decoder: TableDecoder<Left>,
+    symbol_decoder: TableDecoder<Left>,
+    offset_decoder: TableDecoder<Left>,
+    // 符号長を読み込む作業領域
+    lengths: Vec<u8>,
+    // 読み込み済みで未出力の記号 symbols[head..tail]
+    symbols: Vec<u16>,
+    head: usize,
+    tail: usize,
 }
 
 impl LzhufDecoderInner {
     const SEARCH_TAB_LEN: usize = 12;
+    const SYMBOL_BUF_LEN: usize = 64;
 
     pub(crate) fn new(method: LzhufMethod) -> Self {
         Self {
+            method,
             offset_len: method.offset_bits(),
-            min_match: LZSS_MIN_MATCH,
+            min_match: method.min_match(),
             block_len: 0,
 
-            symbol_decoder: None,
-            offset_decoder: None,
+            len_decoder: TableDecoder::default(),
+            symbol_decoder: TableDecoder::default(),
+            offset_decoder: TableDecoder::default(),
+            lengths: Vec::new(),
+            symbols: vec![0; Self::SYMBOL_BUF_LEN],
+            head: 0,
+            tail: 0,
         }
     }
 
-    fn dec_len<R: BitRead, I: Iterator<Item = u8>>(
+    /// Starts over for `method`, keeping the tables and buffers, which the
+    /// first block sets again.
+    fn reset(&mut self, method: LzhufMethod) {
+        self.method = method;
+        self.offset_len = method.offset_bits();
+        self.min_match = method.min_match();
+        self.block_len = 0;
+        self.head = 0;
+        self.tail = 0;
+    }
+
+    fn init_block<R: BitRead, I: Iterator<Item = u8>>(
         &mut self,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<u8, CompressionError> {
-        let mut c = reader
-            .read_bits::<u8, _>(3, iter)
+    ) -> Result<bool, CompressionError> {
+        match reader
+            .read_bits::<u16, _>(16, iter)
+            .map(|x| (x.data(), x.len()))
             .map_err(|_| CompressionError::UnexpectedEof)?
-            .data();
-        if c == 7 {
-            while reader
-                .read_bits::<u8, _>(1, iter)
-                .map_err(|_| CompressionError::UnexpectedEof)?
-                .data()
-                == 1
-            {
-                c += 1;
+        {
+            (s, 16) if s != 0 => {
+                self.block_len = s as usize;
+                // 記号の少ないブロックでは表を作る手間の方が大きいので、
+                // 表をブロックの記号数程度に抑える
+                let stab_bits = cmp::min(
+                    Self::SEARCH_TAB_LEN,
+                    (usize::BITS - self.block_len.leading_zeros()) as usize,
+                );
+                let ll = &mut self.lengths;
+                let single =
+                    read_coded(reader, iter, 9, &mut self.len_decoder, ll)?;
+                // 最長の一致より長い記号は表を作る前に弾く
+                let m = self.method;
+                check_alphabet(
+                    single,
+                    ll,
+                    256 + m.max_match() - m.min_match() + 1,
+                )?;
+                self.symbol_decoder.set(single, ll, stab_bits)?;
+                let single =
+                    read_plain(reader, iter, self.offset_len, false, ll)?;
+                // 窓より遠い距離の記号は、追加ビットの読み込みより前に弾く
+                check_alphabet(single, ll, m.dictionary_bits() + 1)?;
+                self.offset_decoder.set(single, ll, stab_bits)?;
+                Ok(true)
             }
+            _ => Ok(false),
         }
-        Ok(c)
     }
+}
+
+/// Returns `DataError` if a table read by `read_coded` or `read_plain`
+/// has codes for symbols from `count` on.
+fn check_alphabet(
+    single: Option<u16>,
+    lengths: &[u8],
+    count: usize,
+) -> Result<(), CompressionError> {
+    let outside = match single {
+        Some(s) => usize::from(s) >= count,
+        None => lengths.iter().skip(count).any(|&l| l != 0),
+    };
+    if outside {
+        return Err(CompressionError::DataError);
+    }
+    Ok(())
+}
 
-    fn dec_len_tree<R: BitRead, I: Iterator<Item = u8>>(
+impl BitDecodeService for LzhufDecoderInner {
+    type Direction = Left;
+    type Error = CompressionError;
+    type Output = LzssCode;
+
+    fn next<R, I>(
         &mut self,
-        tbit_len: usize,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<LzhufHuffmanDecoder, CompressionError> {
-        let len = reader
-            .read_bits::<u16, _>(tbit_len, iter)
-            .map_err(|_| CompressionError::UnexpectedEof)?
-            .data() as usize;
-        if len == 0 {
-            Ok(LzhufHuffmanDecoder::Default(
-                reader
-                    .read_bits::<u16, _>(tbit_len, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data(),
-            ))
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        if self.head == self.tail {
+            if self.block_len == 0 && !self.init_block(reader, iter)? {
+                return Ok(None);
+            }
+            // ブロックの残りの記号数を超えないようにまとめて読む
+            let len = self.block_len.min(Self::SYMBOL_BUF_LEN);
+            let n = self.symbol_decoder.dec_many_until(
+                reader,
+                iter,
+                &mut self.symbols[..len],
+                |s| s > 255,
+            )?;
+            if n == 0 {
+                return Err(CompressionError::UnexpectedEof);
+            }
+            self.block_len -= n;
+            self.head = 0;
+            self.tail = n;
+        }
+        let sym = usize::from(self.symbols[self.head]);
+        self.head += 1;
+        if sym <= 255 {
+            Ok(Some(LzssCode::Symbol(sym as u8)))
         } else {
-            let mut ll = Vec::new();
-            while ll.len() < len {
-                if ll.len() == 3 {
-                    for _ in 0..reader
-                        .read_bits::<u8, _>(2, iter)
+            let len = sym - 256 + self.min_match;
+            let mut pos = self
+                .offset_decoder
+                .dec(reader, iter)?
+                .ok_or(CompressionError::UnexpectedEof)?
+                as usize;
+            if pos > 1 {
+                pos = (1 << (pos - 1))
+                    | reader
+                        .read_bits::<u16, _>(pos - 1, iter)
                         .map_err(|_| CompressionError::UnexpectedEof)?
-                        .data()
-                    {
-                        ll.push(0);
-                    }
-                    if ll.len() > len {
-                        return Err(CompressionError::DataError);
-                    }
-                    if ll.len() == len {
-                        break;
-                    }
-                }
-                ll.push(self.dec_len(reader, iter)?);
+                        .data() as usize;
             }
-            Ok(LzhufHuffmanDecoder::HuffmanDecoder(
-                HuffmanDecoder::new(&ll, 5)
-                    .map_err(|_| CompressionError::DataError)?,
-            ))
+            Ok(Some(LzssCode::Reference { len, pos }))
         }
     }
+}
+
+/// Decoder of the -lh1- codes: an adaptive Huffman code of the symbols
+/// and a fixed code of the upper 6 bits of the offsets.
+#[derive(Debug)]
+pub(crate) struct Lh1DecoderInner {
+    symbol_decoder: AdaptiveHuffmanDecoder<Left>,
+    offset_decoder: HuffmanDecoder<Left>,
+}
 
-    fn dec_symb_tree<R: BitRead, I: Iterator<Item = u8>>(
+impl Lh1DecoderInner {
+    // LHA の ready_made と同じ固定表 (符号長, 個数)
+    const OFFSET_LENGTHS: [(u8, usize); 6] =
+        [(3, 1), (4, 3), (5, 8), (6, 12), (7, 24), (8, 16)];
+
+    pub(crate) fn new() -> Self {
+        let lengths = Self::OFFSET_LENGTHS
+            .iter()
+            .flat_map(|&(l, n)| iter::repeat_n(l, n))
+            .collect::<Vec<_>>();
+        Self {
+            symbol_decoder: AdaptiveHuffmanDecoder::new(
+                256 + LzhufMethod::Lh1.max_match()
+                    - LzhufMethod::Lh1.min_match()
+                    + 1,
+            ),
+            offset_decoder: HuffmanDecoder::new(&lengths, 8).unwrap(),
+        }
+    }
+}
+
+impl BitDecodeService for Lh1DecoderInner {
+    type Direction = Left;
+    type Error = CompressionError;
+    type Output = LzssCode;
+
+    fn next<R, I>(
         &mut self,
-        len_decoder: &mut LzhufHuffmanDecoder,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<LzhufHuffmanDecoder, CompressionError> {
-        let len = reader
-            .read_bits::<u16, _>(9, iter)
-            .map_err(|_| CompressionError::UnexpectedEof)?
-            .data() as usize;
-        if len == 0 {
-            Ok(LzhufHuffmanDecoder::Default(
-                reader
-                    .read_bits::<u16, _>(9, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data(),
-            ))
-        } else {
-            let mut ll = Vec::new();
-            while ll.len() < len {
-                match len_decoder.dec(reader, iter)? {
-                    None => return Err(CompressionError::UnexpectedEof),
-                    Some(0) => ll.push(0),
-                    Some(1) => {
-                        for _ in 0..(3 + reader
-                            .read_bits::<u8, _>(4, iter)
-                            .map_err(|_| CompressionError::UnexpectedEof)?
-                            .data())
-                        {
-                            ll.push(0);
-                        }
-                    }
-                    Some(2) => {
-                        for _ in 0..(20
-                            + reader
-                                .read_bits::<u16, _>(9, iter)
-                                .map_err(|_| CompressionError::UnexpectedEof)?
-                                .data())
-                        {
-                            ll.push(0);
-                        }
-                    }
-                    Some(n) => ll.push((n - 2) as u8),
-                }
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        // 終端の印はなく、入力の終わりで止まる
+        let sym = match self
+            .symbol_decoder
+            .dec(reader, iter)
+            .map_err(|_| CompressionError::DataError)?
+        {
+            Some(s) => usize::from(s),
+            None => return Ok(None),
+        };
+        if sym <= 255 {
+            return Ok(Some(LzssCode::Symbol(sym as u8)));
+        }
+        let len = sym - 256 + LzhufMethod::Lh1.min_match();
+        let upper = self
+            .offset_decoder
+            .dec(reader, iter)
+            .map_err(|_| CompressionError::DataError)?
+            .ok_or(CompressionError::UnexpectedEof)?;
+        let lower = reader
+            .read_bits::<u16, _>(6, iter)
+            .map_err(|_| CompressionError::UnexpectedEof)?;
+        if lower.len() < 6 {
+            return Err(CompressionError::UnexpectedEof);
+        }
+        let pos = usize::from(upper) << 6 | usize::from(lower.data());
+        Ok(Some(LzssCode::Reference { len, pos }))
+    }
+}
+
+/// Reads `n` bits, which the input must have all of.
+fn read_exact<R, I>(
+    reader: &mut R,
+    iter: &mut I,
+    n: usize,
+) -> Result<usize, CompressionError>
+where
+    R: BitRead,
+    I: Iterator<Item = u8>,
+{
+    let bits = reader
+        .read_bits::<u16, _>(n, iter)
+        .map_err(|_| CompressionError::UnexpectedEof)?;
+    if bits.len() < n {
+        return Err(CompressionError::UnexpectedEof);
+    }
+    Ok(usize::from(bits.data()))
+}
+
+// -lh2- と -lh3- の記号の数で、最後の記号は 8 ビットで長さを足す
+const LH23_SYMBOLS: usize = 286;
+
+/// Returns the length of the reference of symbol `sym` of -lh2- or -lh3-,
+/// reading the extra bits of the last symbol. Lengths over 256, which
+/// LHA does not write, are a `DataError`.
+fn lh23_len<R, I>(
+    sym: usize,
+    reader: &mut R,
+    iter: &mut I,
+) -> Result<usize, CompressionError>
+where
+    R: BitRead,
+    I: Iterator<Item = u8>,
+{
+    let mut len = sym - 256 + LzhufMethod::Lh2.min_match();
+    if sym == LH23_SYMBOLS - 1 {
+        len += read_exact(reader, iter, 8)?;
+    }
+    if len > LzhufMethod::Lh2.max_match() {
+        return Err(CompressionError::DataError);
+    }
+    Ok(len)
+}
+
+/// Decoder of the -lh2- codes: adaptive Huffman codes of the symbols and
+/// of the upper 7 bits of the offsets.
+#[derive(Debug)]
+pub(crate) struct Lh2DecoderInner {
+    symbol_decoder: AdaptiveHuffmanDecoder<Left>,
+    offset_decoder: AdaptiveHuffmanDecoder<Left>,
+    // 出力した長さと、次に位置の記号を足す長さ
+    count: usize,
+    next_count: usize,
+}
+
+impl Lh2DecoderInner {
+    // この長さごとに位置の記号を 1 つ足す
+    const COUNT_STEP: usize = 64;
+
+    pub(crate) fn new() -> Self {
+        let dic_len = 1 << LzhufMethod::Lh2.dictionary_bits();
+        Self {
+            symbol_decoder: AdaptiveHuffmanDecoder::new(LH23_SYMBOLS),
+            offset_decoder: AdaptiveHuffmanDecoder::growing(
+                dic_len / Self::COUNT_STEP,
+            ),
+            count: 0,
+            next_count: Self::COUNT_STEP,
+        }
+    }
+}
+
+impl BitDecodeService for Lh2DecoderInner {
+    type Direction = Left;
+    type Error = CompressionError;
+    type Output = LzssCode;
+
+    fn next<R, I>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        // 終端の印はなく、入力の終わりで止まる
+        let sym = match self
+            .symbol_decoder
+            .dec(reader, iter)
+            .map_err(|_| CompressionError::DataError)?
+        {
+            Some(s) => usize::from(s),
+            None => return Ok(None),
+        };
+        if sym <= 255 {
+            self.count += 1;
+            return Ok(Some(LzssCode::Symbol(sym as u8)));
+        }
+        let len = lh23_len(sym, reader, iter)?;
+        // 出力が窓を埋めるまで、届く位置の記号を足していく
+        while self.count > self.next_count {
+            self.offset_decoder.add_symbol();
+            self.next_count += Self::COUNT_STEP;
+            if self.next_count >= 1 << LzhufMethod::Lh2.dictionary_bits() {
+                self.next_count = usize::MAX;
             }
-            Ok(LzhufHuffmanDecoder::HuffmanDecoder(
-                HuffmanDecoder::new(&ll, Self::SEARCH_TAB_LEN)
-                    .map_err(|_| CompressionError::DataError)?,
-            ))
         }
+        let upper = self
+            .offset_decoder
+            .dec(reader, iter)
+            .map_err(|_| CompressionError::DataError)?
+            .ok_or(CompressionError::UnexpectedEof)?;
+        let pos = usize::from(upper) << 6 | read_exact(reader, iter, 6)?;
+        self.count += len;
+        Ok(Some(LzssCode::Reference { len, pos }))
     }
+}
 
-    fn dec_offs_tree<R: BitRead, I: Iterator<Item = u8>>(
+/// A code of a block of -lh3-.
+#[derive(Debug)]
+enum Lh3Code {
+    // 符号のない 1 つだけの記号
+    Single(usize),
+    Huffman(HuffmanDecoder<Left>),
+    // どの記号にも符号がなく、使うと誤り
+    Empty,
+}
+
+impl Lh3Code {
+    fn new(lengths: &[u8], stab_bits: usize) -> Result<Self, CompressionError> {
+        if lengths.iter().all(|&l| l == 0) {
+            return Ok(Lh3Code::Empty);
+        }
+        HuffmanDecoder::new(lengths, stab_bits)
+            .map(Lh3Code::Huffman)
+            .map_err(|_| CompressionError::DataError)
+    }
+
+    fn dec<R, I>(
         &mut self,
-        pbit_len: usize,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<LzhufHuffmanDecoder, CompressionError> {
-        let len = reader
-            .read_bits::<u16, _>(pbit_len, iter)
-            .map_err(|_| CompressionError::UnexpectedEof)?
-            .data() as usize;
-        if len == 0 {
-            Ok(LzhufHuffmanDecoder::Default(
-                reader
-                    .read_bits::<u16, _>(pbit_len, iter)
-                    .map_err(|_| CompressionError::UnexpectedEof)?
-                    .data(),
-            ))
-        } else {
-            Ok(LzhufHuffmanDecoder::HuffmanDecoder(
-                HuffmanDecoder::new(
-                    &(0..len)
-                        .map(|_| self.dec_len(reader, iter))
-                        .collect::<Result<Vec<u8>, CompressionError>>()?,
-                    Self::SEARCH_TAB_LEN,
-                )
-                .map_err(|_| CompressionError::DataError)?,
-            ))
+    ) -> Result<usize, CompressionError>
+    where
+        R: BitRead<Direction = Left>,
+        I: Iterator<Item = u8>,
+    {
+        match self {
+            Lh3Code::Single(s) => Ok(*s),
+            Lh3Code::Huffman(decoder) => decoder
+                .dec(reader, iter)
+                .map_err(|_| CompressionError::DataError)?
+                .map(usize::from)
+                .ok_or(CompressionError::UnexpectedEof),
+            Lh3Code::Empty => Err(CompressionError::DataError),
         }
     }
+}
 
-    fn init_block<R: BitRead, I: Iterator<Item = u8>>(
+/// Decoder of the -lh3- codes: blocks with a static Huffman code of the
+/// symbols, and one of the upper 7 bits of the offsets that is either in
+/// the block or the fixed table of LHA.
+#[derive(Debug)]
+pub(crate) struct Lh3DecoderInner {
+    block_len: usize,
+    symbol_decoder: Lh3Code,
+    offset_decoder: Lh3Code,
+    // 符号長を読み込む作業領域
+    lengths: Vec<u8>,
+}
+
+impl Lh3DecoderInner {
+    const OFFSET_SYMBOLS: usize = 128;
+    // LHA の ready_made(1) と同じ固定表 (符号長, 個数)
+    const OFFSET_LENGTHS: [(u8, usize); 7] =
+        [(2, 1), (4, 2), (5, 3), (6, 7), (7, 18), (8, 47), (9, 50)];
+
+    pub(crate) fn new() -> Self {
+        Self {
+            block_len: 0,
+            symbol_decoder: Lh3Code::Empty,
+            offset_decoder: Lh3Code::Empty,
+            lengths: Vec::new(),
+        }
+    }
+
+    /// Reads the code lengths of `count` symbols, each read by
+    /// `read_len`. Lengths of 1 for the first three symbols instead mean
+    /// a single symbol of `single_bits` bits, which has no code.
+    fn read_code<R, I, F>(
         &mut self,
         reader: &mut R,
         iter: &mut I,
-    ) -> Result<bool, CompressionError> {
-        match reader
-            .read_bits::<u16, _>(16, iter)
-            .map(|x| (x.data(), x.len()))
-            .map_err(|_| CompressionError::UnexpectedEof)?
-        {
-            (s, 16) if s != 0 => {
-                self.block_len = s as usize;
-                let mut lt = self.dec_len_tree(5, reader, iter)?;
-                self.symbol_decoder =
-                    Some(self.dec_symb_tree(&mut lt, reader, iter)?);
-                let offlen = self.offset_len;
-                self.offset_decoder =
-                    Some(self.dec_offs_tree(offlen, reader, iter)?);
-                Ok(true)
+        count: usize,
+        single_bits: usize,
+        stab_bits: usize,
+        mut read_len: F,
+    ) -> Result<Lh3Code, CompressionError>
+    where
+        R: BitRead<Direction = Left>,
+        I: Iterator<Item = u8>,
+        F: FnMut(&mut R, &mut I) -> Result<u8, CompressionError>,
+    {
+        self.lengths.clear();
+        while self.lengths.len() < count {
+            let len = read_len(reader, iter)?;
+            self.lengths.push(len);
+            if self.lengths[..] == [1, 1, 1] {
+                let s = read_exact(reader, iter, single_bits)?;
+                if s >= count {
+                    return Err(CompressionError::DataError);
+                }
+                return Ok(Lh3Code::Single(s));
             }
-            _ => Ok(false),
         }
+        Lh3Code::new(&self.lengths, stab_bits)
+    }
+
+    fn init_block<R, I>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<bool, CompressionError>
+    where
+        R: BitRead<Direction = Left>,
+        I: Iterator<Item = u8>,
+    {
+        let len = reader
+            .read_bits::<u16, _>(16, iter)
+            .map_err(|_| CompressionError::UnexpectedEof)?;
+        if len.len() < 16 {
+            return Ok(false);
+        }
+        // 0 は 0x10000 個の記号のブロック
+        self.block_len = match len.data() {
+            0 => 0x10000,
+            n => usize::from(n),
+        };
+        self.symbol_decoder =
+            self.read_code(reader, iter, LH23_SYMBOLS, 9, 12, |r, i| {
+                Ok(match read_exact(r, i, 1)? {
+                    1 => read_exact(r, i, 4)? as u8 + 1,
+                    _ => 0,
+                })
+            })?;
+        self.offset_decoder = if read_exact(reader, iter, 1)? == 1 {
+            self.read_code(reader, iter, Self::OFFSET_SYMBOLS, 7, 8, |r, i| {
+                Ok(read_exact(r, i, 4)? as u8)
+            })?
+        } else {
+            self.lengths.clear();
+            self.lengths.extend(
+                Self::OFFSET_LENGTHS
+                    .iter()
+                    .flat_map(|&(l, n)| iter::repeat_n(l, n)),
+            );
+            Lh3Code::new(&self.lengths, 8)?
+        };
+        Ok(true)
     }
 }
 
-impl BitDecodeService for LzhufDecoderInner {
+impl BitDecodeService for Lh3DecoderInner {
     type Direction = Left;
     type Error = CompressionError;
     type Output = LzssCode;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<LzssCode>, CompressionError> {
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        // 終端の印はなく、ブロックの頭が読めなければ終わる
         if self.block_len == 0 && !self.init_block(reader, iter)? {
             return Ok(None);
         }
         self.block_len -= 1;
-        let sym = self
-            .symbol_decoder
-            .as_mut()
-            .unwrap()
-            .dec(reader, iter)?
-            .ok_or_else(|| CompressionError::UnexpectedEof)?
-            as usize;
+        let sym = self.symbol_decoder.dec(reader, iter)?;
         if sym <= 255 {
-            Ok(Some(LzssCode::Symbol(sym as u8)))
-        } else {
-            let len = sym - 256 + self.min_match;
-            let mut pos = self
-                .offset_decoder
-                .as_mut()
-                .unwrap()
-                .dec(reader, iter)?
-                .ok_or_else(|| CompressionError::UnexpectedEof)?
-                as usize;
-            if pos > 1 {
-                pos = (1 << (pos - 1))
-                    | reader
-                        .read_bits::<u16, _>(pos - 1, iter)
-                        .map_err(|_| CompressionError::UnexpectedEof)?
-                        .data() as usize;
+            return Ok(Some(LzssCode::Symbol(sym as u8)));
+        }
+        let len = lh23_len(sym, reader, iter)?;
+        let upper = self.offset_decoder.dec(reader, iter)?;
+        let pos = upper << 6 | read_exact(reader, iter, 6)?;
+        Ok(Some(LzssCode::Reference { len, pos }))
+    }
+}
+
+/// A code of the LArc methods: a literal, or the index into the ring
+/// buffer and the length less the shortest of a reference.
+type LarcCode = Result<u8, (usize, usize)>;
+
+/// Decoder of the LArc codes, which have no Huffman stage: -lzs- has a
+/// flag bit before each code, and -lz5- a flag byte before each eight
+/// codes. References hold an index into a ring buffer whose writing
+/// starts the longest match before its end.
+#[derive(Debug)]
+pub(crate) struct LarcDecoderInner {
+    method: LzhufMethod,
+    // -lz5- の旗で、上位バイトが残りの項目数を数える
+    flags: u16,
+    ring_pos: usize,
+}
+
+impl LarcDecoderInner {
+    pub(crate) fn new(method: LzhufMethod) -> Self {
+        let ring_len = 1 << method.dictionary_bits();
+        Self {
+            method,
+            flags: 0,
+            ring_pos: ring_len - method.max_match(),
+        }
+    }
+
+    /// Reads a code of -lzs-, or returns `None` at the end of the input,
+    /// which may be in the padding of the last byte.
+    fn next_lzs<R, I>(
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<LarcCode>, CompressionError>
+    where
+        R: BitRead<Direction = Left>,
+        I: Iterator<Item = u8>,
+    {
+        let mut bits = |n| {
+            reader
+                .read_bits::<u16, _>(n, iter)
+                .map(|b| (b.len() == n).then(|| usize::from(b.data())))
+                .map_err(|_| CompressionError::UnexpectedEof)
+        };
+        Ok(match bits(1)? {
+            Some(1) => bits(8)?.map(|c| Ok(c as u8)),
+            Some(_) => bits(11)?.zip(bits(4)?).map(Err),
+            None => None,
+        })
+    }
+
+    /// Reads a code of -lz5- as `next_lzs` does. The input may end after
+    /// any code, but not inside one.
+    fn next_lz5<R, I>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<LarcCode>, CompressionError>
+    where
+        R: BitRead<Direction = Left>,
+        I: Iterator<Item = u8>,
+    {
+        let mut byte = || {
+            let mut buf = [0; 1];
+            match reader.read_bytes(&mut buf, iter) {
+                Ok(1) => Ok(Some(buf[0])),
+                Ok(_) => Ok(None),
+                Err(_) => Err(CompressionError::UnexpectedEof),
             }
-            Ok(Some(LzssCode::Reference { len, pos }))
+        };
+        self.flags >>= 1;
+        if self.flags & 0x100 == 0 {
+            match byte()? {
+                Some(c) => self.flags = u16::from(c) | 0xFF00,
+                None => return Ok(None),
+            }
+        }
+        let first = match byte()? {
+            Some(c) => usize::from(c),
+            None => return Ok(None),
+        };
+        if self.flags & 1 != 0 {
+            return Ok(Some(Ok(first as u8)));
+        }
+        let second =
+            usize::from(byte()?.ok_or(CompressionError::UnexpectedEof)?);
+        Ok(Some(Err((first | ((second & 0xF0) << 4), second & 0x0F))))
+    }
+}
+
+impl BitDecodeService for LarcDecoderInner {
+    type Direction = Left;
+    type Error = CompressionError;
+    type Output = LzssCode;
+
+    fn next<R, I>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        // 終端の印はなく、入力の終わりで止まる
+        let code = match self.method {
+            LzhufMethod::Lzs => Self::next_lzs(reader, iter)?,
+            _ => self.next_lz5(reader, iter)?,
+        };
+        let mask = (1 << self.method.dictionary_bits()) - 1;
+        let code = match code {
+            Some(Ok(s)) => LzssCode::Symbol(s),
+            Some(Err((index, len))) => LzssCode::Reference {
+                len: len + self.method.min_match(),
+                pos: self.ring_pos.wrapping_sub(index + 1) & mask,
+            },
+            None => return Ok(None),
+        };
+        self.ring_pos += match code {
+            LzssCode::Symbol(_) => 1,
+            LzssCode::Reference { len, .. } => len,
+        };
+        self.ring_pos &= mask;
+        Ok(Some(code))
+    }
+}
+
+#[derive(Debug)]
+enum LzhufCodes {
+    // -lh0- の各バイトをそのまま記号にする
+    Stored,
+    Static(LzhufDecoderInner),
+    Adaptive(Lh1DecoderInner),
+    Dynamic(Lh2DecoderInner),
+    HalfStatic(Lh3DecoderInner),
+    Larc(LarcDecoderInner),
+}
+
+impl LzhufCodes {
+    fn new(method: LzhufMethod) -> Self {
+        match method {
+            LzhufMethod::Lh0 => LzhufCodes::Stored,
+            LzhufMethod::Lh1 => LzhufCodes::Adaptive(Lh1DecoderInner::new()),
+            LzhufMethod::Lh2 => LzhufCodes::Dynamic(Lh2DecoderInner::new()),
+            LzhufMethod::Lh3 => LzhufCodes::HalfStatic(Lh3DecoderInner::new()),
+            LzhufMethod::Lzs | LzhufMethod::Lz5 => {
+                LzhufCodes::Larc(LarcDecoderInner::new(method))
+            }
+            _ => LzhufCodes::Static(LzhufDecoderInner::new(method)),
+        }
+    }
+
+    fn reset(&mut self, method: LzhufMethod) {
+        match (self, method) {
+            (
+                LzhufCodes::Static(inner),
+                LzhufMethod::Lh4
+                | LzhufMethod::Lh5
+                | LzhufMethod::Lh6
+                | LzhufMethod::Lh7,
+            ) => inner.reset(method),
+            (codes, _) => *codes = Self::new(method),
+        }
+    }
+}
+
+impl BitDecodeService for LzhufCodes {
+    type Direction = Left;
+    type Error = CompressionError;
+    type Output = LzssCode;
+
+    fn next<R, I>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        match self {
+            LzhufCodes::Stored => {
+                let mut buf = [0; 1];
+                match reader.read_bytes(&mut buf, iter) {
+                    Ok(1) => Ok(Some(LzssCode::Symbol(buf[0]))),
+                    Ok(_) => Ok(None),
+                    Err(_) => Err(CompressionError::UnexpectedEof),
+                }
+            }
+            LzhufCodes::Static(inner) => inner.next(reader, iter),
+            LzhufCodes::Adaptive(inner) => inner.next(reader, iter),
+            LzhufCodes::Dynamic(inner) => inner.next(reader, iter),
+            LzhufCodes::HalfStatic(inner) => inner.next(reader, iter),
+            LzhufCodes::Larc(inner) => inner.next(reader, iter),
         }
     }
 }
 
+#[derive(Debug)]
+struct LzhufCodeBase {
+    inner: LzhufCodes,
+    // 元の大きさが分かっているとき、残りの符号が表す長さ
+    remaining: Option<usize>,
+}
+
+impl BitDecodeService for LzhufCodeBase {
+    type Direction = Left;
+    type Error = CompressionError;
+    type Output = LzssCode;
+
+    fn next<R, I>(
+        &mut self,
+        reader: &mut R,
+        iter: &mut I,
+    ) -> Result<Option<LzssCode>, CompressionError>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        let ret = self.inner.next(reader, iter)?;
+        if let Some(n) = self.remaining.as_mut() {
+            let len = match ret {
+                Some(LzssCode::Symbol(_)) => 1,
+                Some(LzssCode::Reference { len, .. }) => len,
+                // 元の大きさに届く前に終わった
+                None => return Err(CompressionError::UnexpectedEof),
+            };
+            // 元の大きさを越える参照
+            *n = n.checked_sub(len).ok_or(CompressionError::DataError)?;
+        }
+        Ok(ret)
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct LzhufDecoderBase {
     lzss_decoder: LzssDecoder,
-    inner: LzhufDecoderInner,
+    inner: LzhufCodes,
+    // 元の大きさが分かっているときの残りの出力
+    remaining: Option<usize>,
 }
 
 impl LzhufDecoderBase {
-    const MAX_BLOCK_SIZE: usize = 0x1_0000;
-
-    pub(crate) fn new(method: LzhufMethod) -> Self {
+    pub(crate) fn new(method: LzhufMethod, size: Option<usize>) -> Self {
+        let dic_len = 1 << method.dictionary_bits();
         Self {
-            lzss_decoder: LzssDecoder::new(Self::MAX_BLOCK_SIZE),
-            inner: LzhufDecoderInner::new(method),
+            lzss_decoder: LzssDecoder::with_dict(
+                dic_len,
+                &method.initial_window(),
+            ),
+            inner: LzhufCodes::new(method),
+            remaining: size,
         }
     }
+
+    fn reset(&mut self, method: LzhufMethod, size: Option<usize>) {
+        self.lzss_decoder
+            .reset(1 << method.dictionary_bits(), &method.initial_window());
+        self.inner.reset(method);
+        self.remaining = size;
+    }
 }
 
 impl BitDecodeService for LzhufDecoderBase {
@@ -304,18 +821,38 @@ impl BitDecodeService for LzhufDecoderBase {
     type Error = CompressionError;
     type Output = u8;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<u8>, Self::Error> {
-        let mut bd = BitDecoder::<LzhufDecoderInner, _, _>::with_service(
+    ) -> Result<Option<u8>, Self::Error>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        let mut bd = BitDecoder::<LzhufCodes, _, _>::with_service(
             &mut self.inner,
             reader,
         );
-        self.lzss_decoder
-            .next(&mut DecodeIterator::<I, _, _>::new(iter, &mut bd).flatten())
-            .transpose()
+        let mut err = None;
+        let ret = self.lzss_decoder.next(
+            &mut DecodeIterator::<I, _, _>::new(iter, &mut bd)
+                .scan(&mut err, |err, x| x.map_err(|e| **err = Some(e)).ok()),
+        );
+        let ret = match err {
+            Some(e) => return Err(e),
+            None => ret.transpose()?,
+        };
+        match (ret, self.remaining.as_mut()) {
+            (Some(_), Some(n)) => *n -= 1,
+            // 元の大きさに届く前に終わった
+            (None, Some(_)) => return Err(CompressionError::UnexpectedEof),
+            _ => {}
+        }
+        Ok(ret)
     }
 }
 
@@ -325,14 +862,123 @@ pub struct LzhufDecoder {
 }
 
 impl LzhufDecoder {
+    /// Creates a decoder that keeps only the window of `method`, from
+    /// 4 KiB for -lh4- to 64 KiB for -lh7-. A reference beyond it is a
+    /// `DataError`.
     pub fn new(method: &LzhufMethod) -> Self {
         Self {
             inner: BitDecoderImpl::<LzhufDecoderBase>::with_service(
-                LzhufDecoderBase::new(*method),
+                LzhufDecoderBase::new(*method, None),
                 BitReader::new(),
             ),
         }
     }
+
+    /// Creates a decoder that stops after `size` bytes, the original size
+    /// of an archive member, without reading further input. The end of
+    /// the input before that is `UnexpectedEof`.
+    ///
+    /// -lh0- to -lh3-, -lzs- and -lz5- data have no end marker and need it
+    /// to stop in the right place. Data of the other methods may have
+    /// padding or the next member after them.
+    ///
+    /// `decode_slice` returns the input up to the last bit read, so the
+    /// rest of the slice is where the next member starts. Through `next`,
+    /// the decoder reads up to 8 bytes ahead; bound the input with
+    /// `take(compressed_size)` to leave the next member in the iterator.
+    pub fn with_original_size(method: &LzhufMethod, size: usize) -> Self {
+        Self {
+            inner: BitDecoderImpl::<LzhufDecoderBase>::with_service(
+                LzhufDecoderBase::new(*method, Some(size)),
+                BitReader::new(),
+            ),
+        }
+    }
+
+    /// Starts over to decode a stream of `method`, as a decoder from `new`,
+    /// but keeps the window and the tables for the next stream where they
+    /// are large enough, such as for the next member of an archive. A
+    /// window kept from a method of a larger one stays that large.
+    pub fn reset(&mut self, method: &LzhufMethod) {
+        self.inner.reset_with(|base| base.reset(*method, None));
+    }
+
+    /// Starts over as `reset` does, to stop after `size` bytes as a decoder
+    /// from `with_original_size`.
+    pub fn reset_with_original_size(
+        &mut self,
+        method: &LzhufMethod,
+        size: usize,
+    ) {
+        self.inner
+            .reset_with(|base| base.reset(*method, Some(size)));
+    }
+
+    /// Decodes a stream held in memory, reading the slice directly instead
+    /// of through a byte iterator. Returns the decoded data and the number
+    /// of input bytes the stream occupies.
+    ///
+    /// The decoder must not have been given any input through `next`.
+    pub fn decode_slice(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(Vec<u8>, usize), CompressionError> {
+        self.inner.decode_slice(input)
+    }
+}
+
+/// Decoder of any `LzhufMethod` that outputs the `LzssCode`s of the
+/// stream instead of the bytes they stand for. -lh0- data are all
+/// `Symbol`s.
+///
+/// A `Reference` has `len` between 3 and 256 (60 for -lh1-, 2 to 17 for
+/// -lzs- and 18 for -lz5-) and `pos` below the window size of the method:
+/// 2 KiB for -lzs-, 4 KiB for -lh1-, -lh4- and -lz5-, 8 KiB for -lh2-,
+/// -lh3- and -lh5-, 32 KiB for -lh6- and 64 KiB for -lh7-. The codes are
+/// not checked against the output; an `LzssDecoder` of the window size
+/// rejects references before its start. References of -lh1- to -lh3-,
+/// -lzs- and -lz5- may reach into the window these methods start with, so
+/// their codes decode with
+/// `LzssDecoder::with_dict(size, &method.initial_window())`.
+#[derive(Debug)]
+pub struct LzhufCodeDecoder {
+    inner: BitDecoderImpl<LzhufCodeBase>,
+}
+
+impl LzhufCodeDecoder {
+    pub fn new(method: &LzhufMethod) -> Self {
+        Self::with_size(method, None)
+    }
+
+    /// Creates a decoder that stops after the codes of `size` bytes, the
+    /// original size of an archive member, as
+    /// `LzhufDecoder::with_original_size` does. A reference past `size`
+    /// is a `DataError`.
+    pub fn with_original_size(method: &LzhufMethod, size: usize) -> Self {
+        Self::with_size(method, Some(size))
+    }
+
+    fn with_size(method: &LzhufMethod, size: Option<usize>) -> Self {
+        Self {
+            inner: BitDecoderImpl::<LzhufCodeBase>::from(LzhufCodeBase {
+                inner: LzhufCodes::new(*method),
+                remaining: size,
+            }),
+        }
+    }
+}
+
+impl Decoder for LzhufCodeDecoder {
+    type Input = u8;
+    type Output = LzssCode;
+    type Error = CompressionError;
+
+    fn next<I: Iterator<Item = Self::Input>>(
+        &mut self,
+        iter: &mut I,
+    ) -> Option<Result<Self::Output, Self::Error>> {
+        self.inner.next(iter)
+    }
 }
 
 impl Decoder for LzhufDecoder {
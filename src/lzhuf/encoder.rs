@@ -7,26 +7,23 @@
 
 use crate::action::Action;
 use crate::bitio::direction::left::Left;
-use crate::bitio::direction::Direction;
 use crate::bitio::small_bit_vec::SmallBitVec;
-use crate::bitio::writer::BitWriter;
+use crate::bitio::writer::{BitWrite, BitWriter};
+use crate::checksum::Crc16;
 use crate::core::cmp::{self, Ordering};
 use crate::error::CompressionError;
-use crate::huffman::cano_huff_table::make_table;
 use crate::huffman::encoder::HuffmanEncoder;
-use crate::lzhuf::{LzhufMethod, LZSS_MIN_MATCH};
-use crate::lzss::encoder::LzssEncoder;
+use crate::huffman::table::{write_coded, write_plain};
+use crate::huffman::FrequencyCounter;
+use crate::lzhuf::LzhufMethod;
+use crate::lzss::encoder::{CodePrices, LzssEncoder, MatchParams, ParseMode};
 use crate::lzss::LzssCode;
 use crate::traits::encoder::Encoder;
 #[cfg(not(feature = "std"))]
-use alloc::collections::vec_deque::VecDeque;
-#[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
-#[cfg(feature = "std")]
-use std::collections::vec_deque::VecDeque;
 
 fn lzss_comparison(lhs: LzssCode, rhs: LzssCode) -> Ordering {
     match (lhs, rhs) {
@@ -52,16 +49,17 @@ enum LzhufHuffmanEncoder {
 }
 
 impl LzhufHuffmanEncoder {
-    pub(crate) fn new(symb_len: &[u8]) -> Self {
-        let symbs = symb_len
-            .iter()
-            .enumerate()
-            .filter(|&(_, &t)| t != 0)
-            .collect::<Vec<_>>();
-        if symbs.len() <= 1 {
-            LzhufHuffmanEncoder::Default
+    /// Returns the encoder and the code lengths for `freq`. A table with
+    /// at most one symbol is written without codes.
+    pub(crate) fn from_frequencies(
+        freq: &[usize],
+        max_len: usize,
+    ) -> (Self, Vec<u8>) {
+        let (enc, symb_len) = HuffmanEncoder::from_frequencies(freq, max_len);
+        if symb_len.iter().filter(|&&t| t != 0).count() <= 1 {
+            (LzhufHuffmanEncoder::Default, symb_len)
         } else {
-            LzhufHuffmanEncoder::HuffmanEncoder(HuffmanEncoder::new(symb_len))
+            (LzhufHuffmanEncoder::HuffmanEncoder(enc), symb_len)
         }
     }
 
@@ -79,8 +77,6 @@ impl LzhufHuffmanEncoder {
     }
 }
 
-const MIN_MATCH: u16 = 3;
-
 #[derive(Debug, Eq, PartialEq)]
 enum LzhufLzssCode {
     Symbol(u8),
@@ -91,151 +87,512 @@ enum LzhufLzssCode {
     },
 }
 
-impl From<&LzssCode> for LzhufLzssCode {
-    fn from(data: &LzssCode) -> Self {
+impl LzhufLzssCode {
+    /// Maps `data` to its symbols, for references of at least `min_match`
+    /// bytes.
+    fn new(data: &LzssCode, min_match: usize) -> Self {
         match *data {
             LzssCode::Symbol(s) => LzhufLzssCode::Symbol(s),
             LzssCode::Reference { len, pos } => {
                 let po = (pos + 1).next_power_of_two();
                 LzhufLzssCode::Reference {
-                    len: len as u16 + 256 - MIN_MATCH,
+                    len: (len + 256 - min_match) as u16,
                     pos_offset: po.trailing_zeros() as u16,
                     pos_sublen: (pos - (po >> 1)) as u16,
                 }
             }
         }
     }
+
+    /// Returns the symbol and the offset symbol.
+    fn symbols(&self) -> (usize, Option<usize>) {
+        match *self {
+            LzhufLzssCode::Symbol(s) => (usize::from(s), None),
+            LzhufLzssCode::Reference {
+                len, pos_offset, ..
+            } => (usize::from(len), Some(usize::from(pos_offset))),
+        }
+    }
 }
 
+type LzhufCounter =
+    FrequencyCounter<fn(&LzhufLzssCode) -> (usize, Option<usize>)>;
+
 #[derive(Debug)]
 pub struct LzhufEncoder {
-    inner: LzhufEncoderInner,
+    // -lh0- では持たず、入力をそのまま出力する
+    coder: Option<LzhufCoder>,
+    crc: Crc16,
+    original_size: u64,
+    compressed_size: u64,
+}
+
+/// The sizes and the CRC of a stream, which `LzhufEncoder::finish`
+/// returns for the header of an archive member.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LzhufEncodeInfo {
+    /// The number of bytes of input.
+    pub original_size: u64,
+    /// The number of bytes of output.
+    pub compressed_size: u64,
+    /// The CRC-16 of the input, as `Crc16` computes it.
+    pub crc: u16,
+}
+
+#[derive(Debug)]
+struct LzhufCoder {
+    codes: LzhufCodeEncoder,
     lzss: LzssEncoder<fn(LzssCode, LzssCode) -> Ordering>,
-    writer: BitWriter<Left>,
-    queue: VecDeque<SmallBitVec<u16>>,
-    finished: bool,
-    bitbuf: u16,
-    bitbuflen: usize,
-    bit_finished: bool,
 }
 
 impl LzhufEncoder {
     const LZSS_MAX_MATCH: usize = 256;
-    const LZSS_LAZY_LEVEL: usize = 3;
     const LZHUF_MAX_BLOCK_LENGTH: usize = 0xFFFF;
+    // 最適解析が直前のブロックの符号長を使えるように、一度に渡す量を
+    // 解析の単位までにする
+    const SLICE_CHUNK: usize = 0x1000;
+    // zlib の圧縮レベル 9 と同じ探索の深さ
+    const OPTIMAL_PARAMS: MatchParams = MatchParams {
+        good_length: 32,
+        nice_length: 256,
+        max_lazy: 256,
+        max_chain: 4096,
+    };
+
+    /// # Panics
+    ///
+    /// Panics if `method` is `LzhufMethod::Lh1` to `LzhufMethod::Lh3`,
+    /// `LzhufMethod::Lzs` or `LzhufMethod::Lz5`, which are not supported
+    /// for encoding.
+    pub fn new(method: &LzhufMethod) -> Self {
+        let coder = match method {
+            LzhufMethod::Lh0 => None,
+            _ => {
+                let codes = LzhufCodeEncoder::new(method);
+                Some(LzhufCoder {
+                    lzss: LzssEncoder::with_params(
+                        codes.window_size(),
+                        codes.inner.min_match,
+                        Self::LZSS_MAX_MATCH,
+                        lzss_comparison,
+                    ),
+                    codes,
+                })
+            }
+        };
+        Self {
+            coder,
+            crc: Crc16::new(),
+            original_size: 0,
+            compressed_size: 0,
+        }
+    }
+
+    /// Sets how the input is split into codes. `ParseMode::Optimal` is the
+    /// slowest and smallest setting: it searches matches as deeply as zlib
+    /// level 9, and prices the codes by the Huffman code lengths of the
+    /// previous block. `ParseMode::Fast` is the fastest that searches the
+    /// window, in the place of zlib level 1, and `ParseMode::Rle` is zlib's
+    /// strategy `Z_RLE`. -lh0- has no codes and ignores it.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        let coder = match self.coder {
+            Some(ref mut coder) => coder,
+            None => return,
+        };
+        coder.lzss.set_parse_mode(mode);
+        match mode {
+            ParseMode::Lazy | ParseMode::Fast | ParseMode::Rle => {
+                coder.lzss.set_match_params(MatchParams::default());
+                coder.codes.inner.optimal = false;
+            }
+            ParseMode::Optimal => {
+                coder.lzss.set_match_params(Self::OPTIMAL_PARAMS);
+                coder.codes.inner.optimal = true;
+            }
+        }
+    }
+
+    /// Sets the parse mode and the match search by a level from 1 to 9,
+    /// as zlib does; the method stays as it is. The levels are:
+    ///
+    /// - 1: `ParseMode::Fast`, several times faster than the default.
+    /// - 2, 3: `ParseMode::Lazy` without lazy matching, comparing up to 8
+    ///   and 32 candidates.
+    /// - 4 to 8: `ParseMode::Lazy` with the `MatchParams` of zlib's levels,
+    ///   but 32 candidates at 4 so it does not fall behind 3. 6 is the
+    ///   default of the encoder, `MatchParams::default()`.
+    /// - 9: `ParseMode::Optimal`.
+    ///
+    /// Higher levels are slower and, on most input, smaller.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is 0 or more than 9.
+    pub fn set_level(&mut self, level: u32) {
+        // zlib の configuration_table の値で、nice_length は最長の一致まで
+        let (good_length, max_lazy, nice_length, max_chain) = match level {
+            1 => return self.set_parse_mode(ParseMode::Fast),
+            2 => (4, 0, 16, 8),
+            3 => (4, 0, 32, 32),
+            4 => (4, 4, 32, 32),
+            5 => (8, 16, 32, 32),
+            6 => (8, 16, 128, 128),
+            7 => (8, 32, 128, 256),
+            8 => (32, 128, 256, 1024),
+            9 => return self.set_parse_mode(ParseMode::Optimal),
+            _ => panic!("level out of range"),
+        };
+        self.set_parse_mode(ParseMode::Lazy);
+        if let Some(ref mut coder) = self.coder {
+            coder.lzss.set_match_params(MatchParams {
+                good_length,
+                nice_length,
+                max_lazy,
+                max_chain,
+            });
+        }
+    }
+
+    /// Sets the largest number of codes in a block, 0xFFFF by default.
+    /// Smaller blocks follow changes in the input sooner, but write the
+    /// tables more often. -lh0- has no blocks and ignores it.
+    ///
+    /// Besides the window, the encoder holds the codes of one block and,
+    /// once they are written, their bytes until they are read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0 or more than 0xFFFF.
+    pub fn set_block_size(&mut self, size: usize) {
+        assert!(
+            (1..=Self::LZHUF_MAX_BLOCK_LENGTH).contains(&size),
+            "block size out of range"
+        );
+        if let Some(ref mut coder) = self.coder {
+            coder.codes.inner.max_block_len = size;
+        }
+    }
+
+    /// Encodes `input` with `action` and returns the bytes, the same as
+    /// collecting `input.iter().cloned().encode(self, action)`, but reading
+    /// the input straight from the slice.
+    pub fn compress_to_vec(
+        &mut self,
+        input: &[u8],
+        action: Action,
+    ) -> Result<Vec<u8>, CompressionError> {
+        let out = match self.coder {
+            Some(ref mut coder) => {
+                let mut out = Vec::new();
+                coder.encode_slice(input, action != Action::Run, &mut out)?;
+                coder.codes.end(action, &mut out)?;
+                out
+            }
+            None => input.to_vec(),
+        };
+        self.crc.update(input);
+        self.original_size += input.len() as u64;
+        self.compressed_size += out.len() as u64;
+        Ok(out)
+    }
+
+    /// Ends the block at the input given so far and returns the bytes
+    /// completed. Unlike `Action::Flush`, it does not pad the output to a
+    /// byte, and the stream goes on with the next block; the bits of the
+    /// block left in the last byte come out with the next bytes.
+    ///
+    /// The output up to here and the byte after it decode to the input
+    /// given so far, as the decoder ends at a block length of less than
+    /// 16 bits.
+    pub fn flush_block(&mut self) -> Result<Vec<u8>, CompressionError> {
+        let coder = match self.coder {
+            Some(ref mut coder) => coder,
+            None => return Ok(Vec::new()),
+        };
+        let mut out = Vec::new();
+        coder.encode_slice(&[], true, &mut out)?;
+        coder.codes.inner.flush(&mut coder.codes.writer)?;
+        coder.codes.pop_bytes(&mut out);
+        self.compressed_size += out.len() as u64;
+        Ok(out)
+    }
+
+    /// Returns the sizes and the CRC-16 of the input and output since
+    /// `new` or the last `finish`, and starts them over for the next
+    /// stream. They are counted as the input is read and the output
+    /// returned, so call it after the stream ends with `Action::Finish`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use compression::checksum::Crc16;
+    /// use compression::prelude::*;
+    ///
+    /// let data = b"aabbaabbaabbaabb";
+    /// let mut encoder = LzhufEncoder::new(&LzhufMethod::Lh5);
+    /// let compressed = data
+    ///     .iter()
+    ///     .cloned()
+    ///     .encode(&mut encoder, Action::Finish)
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// let info = encoder.finish();
+    /// assert_eq!(info.original_size, data.len() as u64);
+    /// assert_eq!(info.compressed_size, compressed.len() as u64);
+    /// assert_eq!(info.crc, Crc16::checksum(data));
+    /// ```
+    pub fn finish(&mut self) -> LzhufEncodeInfo {
+        let info = LzhufEncodeInfo {
+            original_size: self.original_size,
+            compressed_size: self.compressed_size,
+            crc: self.crc.finish(),
+        };
+        self.crc = Crc16::new();
+        self.original_size = 0;
+        self.compressed_size = 0;
+        info
+    }
+}
+
+impl LzhufCoder {
+    /// Encodes `input`, and all of the input held by the parser if
+    /// `flush`, and appends the bytes completed to `out`.
+    fn encode_slice(
+        &mut self,
+        input: &[u8],
+        flush: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        let (codes, lzss) = (&mut self.codes, &mut self.lzss);
+        let mut err = None;
+        let mut chunks = input.chunks(LzhufEncoder::SLICE_CHUNK);
+        let mut flushed = false;
+        loop {
+            if let Some(p) = codes.inner.prices.take() {
+                lzss.set_prices(p);
+            }
+            let chunk = match chunks.next() {
+                Some(chunk) => chunk,
+                None if flush && !flushed => {
+                    lzss.flush();
+                    flushed = true;
+                    &[]
+                }
+                None => break,
+            };
+            lzss.encode_slice(chunk, &mut |code| {
+                if err.is_none() {
+                    err = codes.push_code(&code, out).err();
+                }
+            });
+            if let Some(e) = err {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encoder for LzhufEncoder {
+    type Error = CompressionError;
+    type In = u8;
+    type Out = u8;
+    fn next<I: Iterator<Item = u8>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        let (crc, original_size) = (&mut self.crc, &mut self.original_size);
+        let mut iter = iter.inspect(|&b| {
+            crc.update(&[b]);
+            *original_size += 1;
+        });
+        let ret = match self.coder {
+            Some(ref mut coder) => {
+                let lzss = &mut coder.lzss;
+                coder.codes.next_with(
+                    |inner| {
+                        if let Some(p) = inner.prices.take() {
+                            lzss.set_prices(p);
+                        }
+                        lzss.next(&mut iter, action)
+                    },
+                    action,
+                )
+            }
+            None => iter.next().map(Ok),
+        };
+        if let Some(Ok(_)) = ret {
+            self.compressed_size += 1;
+        }
+        ret
+    }
+}
 
+/// -lh4- to -lh7- encoder of a stream of `LzssCode`s, for codes that come
+/// from somewhere other than this crate's match finder, such as another
+/// format's code decoder.
+///
+/// It accepts the codes the method can hold: a `Reference` needs `len`
+/// between 3 and 256 and `pos` below the window size of the method, and
+/// may not reach before the start of the output. Other codes stop the
+/// encoding with `CompressionError::DataError`.
+#[derive(Debug)]
+pub struct LzhufCodeEncoder {
+    inner: LzhufEncoderInner,
+    // ブロックは表と符号を書き出した時点でここに入る
+    writer: BitWriter<Left>,
+    // Flush か Finish で入力の終わりを符号化し、バイトを出し切るところ
+    finished: bool,
+    // 参照できる出力の長さ
+    history: usize,
+}
+
+impl LzhufCodeEncoder {
+    /// # Panics
+    ///
+    /// Panics if `method` is `LzhufMethod::Lh1` to `LzhufMethod::Lh3`,
+    /// `LzhufMethod::Lzs` or `LzhufMethod::Lz5`, which are not supported
+    /// for encoding, or
+    /// `LzhufMethod::Lh0`, which has no codes.
     pub fn new(method: &LzhufMethod) -> Self {
-        let dic_len = 1 << method.dictionary_bits();
+        match method {
+            LzhufMethod::Lh0 => panic!("-lh0- has no codes"),
+            LzhufMethod::Lh1
+            | LzhufMethod::Lh2
+            | LzhufMethod::Lh3
+            | LzhufMethod::Lzs
+            | LzhufMethod::Lz5 => {
+                panic!("{} encoding is not supported", method)
+            }
+            _ => {}
+        }
         Self {
             inner: LzhufEncoderInner::new(
-                Self::LZHUF_MAX_BLOCK_LENGTH,
+                LzhufEncoder::LZHUF_MAX_BLOCK_LENGTH,
                 method.offset_bits(),
-                Self::LZSS_MAX_MATCH,
-            ),
-            lzss: LzssEncoder::new(
-                lzss_comparison,
-                dic_len,
-                Self::LZSS_MAX_MATCH,
-                LZSS_MIN_MATCH,
-                Self::LZSS_LAZY_LEVEL,
+                method.min_match(),
+                LzhufEncoder::LZSS_MAX_MATCH,
+                1 << method.dictionary_bits(),
             ),
             writer: BitWriter::new(),
-            queue: VecDeque::new(),
             finished: false,
-            bitbuf: 0,
-            bitbuflen: 0,
-            bit_finished: false,
+            history: 0,
         }
     }
 
-    fn next_bits<I: Iterator<Item = u8>>(
+    #[inline]
+    fn window_size(&self) -> usize {
+        self.inner.window_size
+    }
+
+    /// Encodes `code` and appends the bytes completed so far to `out`.
+    fn push_code(
         &mut self,
-        iter: &mut I,
+        code: &LzssCode,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        self.inner.next(code, &mut self.writer)?;
+        self.pop_bytes(out);
+        Ok(())
+    }
+
+    /// Ends the input given to `push_code` as `action` does, and appends
+    /// the rest of the bytes to `out`.
+    fn end(
+        &mut self,
+        action: Action,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        if action == Action::Run {
+            return Ok(());
+        }
+        self.inner.flush(&mut self.writer)?;
+        let _ = self.writer.pad_to_byte(false);
+        self.pop_bytes(out);
+        Ok(())
+    }
+
+    fn pop_bytes(&mut self, out: &mut Vec<u8>) {
+        while let Some(b) = self.writer.pop_byte() {
+            out.push(b);
+        }
+    }
+
+    /// Encodes the codes `code` returns, which gets to look at the block
+    /// state first so `LzhufEncoder` can pass the prices on to its parser.
+    fn next_with<F>(
+        &mut self,
+        mut code: F,
         action: Action,
-    ) -> Option<Result<SmallBitVec<u16>, CompressionError>> {
-        while self.queue.is_empty() {
-            match self.lzss.next(iter, action) {
+    ) -> Option<Result<u8, CompressionError>>
+    where
+        F: FnMut(
+            &mut LzhufEncoderInner,
+        ) -> Option<Result<LzssCode, CompressionError>>,
+    {
+        loop {
+            if let Some(b) = self.writer.pop_byte() {
+                return Some(Ok(b));
+            }
+            if self.finished {
+                self.finished = false;
+                return None;
+            }
+            match code(&mut self.inner) {
                 Some(Ok(ref s)) => {
-                    if let Err(e) = self.inner.next(s, &mut self.queue) {
+                    if let Err(e) = self.inner.next(s, &mut self.writer) {
                         return Some(Err(e));
                     }
                 }
-                Some(Err(e)) => {
-                    return Some(Err(e));
-                }
+                Some(Err(e)) => return Some(Err(e)),
+                None if action == Action::Run => return None,
                 None => {
-                    if self.finished {
-                        self.finished = false;
-                        return None;
-                    } else {
-                        match action {
-                            Action::Flush => {
-                                if let Err(e) =
-                                    self.inner.flush(&mut self.queue)
-                                {
-                                    return Some(Err(e));
-                                }
-                            }
-                            Action::Finish => {
-                                if let Err(e) =
-                                    self.inner.finish(&mut self.queue)
-                                {
-                                    return Some(Err(e));
-                                }
-                            }
-                            _ => {}
-                        }
-                        self.finished = true;
+                    if let Err(e) = self.inner.flush(&mut self.writer) {
+                        return Some(Err(e));
                     }
+                    let _ = self.writer.pad_to_byte(false);
+                    self.finished = true;
                 }
             }
         }
-        self.queue.pop_front().map(Ok)
     }
 }
 
-impl Encoder for LzhufEncoder {
+impl Encoder for LzhufCodeEncoder {
     type Error = CompressionError;
-    type In = u8;
+    type In = LzssCode;
     type Out = u8;
-    fn next<I: Iterator<Item = u8>>(
+    fn next<I: Iterator<Item = LzssCode>>(
         &mut self,
         iter: &mut I,
         action: Action,
     ) -> Option<Result<u8, CompressionError>> {
-        while self.bitbuflen == 0 {
-            let s = match self.next_bits(iter, action) {
-                Some(Err(e)) => return Some(Err(e)),
-                Some(Ok(ref s)) => self.writer.write_bits(s),
-                None => {
-                    if self.bit_finished {
-                        self.bit_finished = false;
-                        return None;
-                    } else {
-                        match action {
-                            Action::Finish | Action::Flush => {
-                                self.bit_finished = true;
-                                match self.writer.flush::<u16>() {
-                                    Some((x, y)) if y != 0 => (x, y),
-                                    _ => return None,
-                                }
-                            }
-                            _ => {
-                                return None;
-                            }
+        let mut history = self.history;
+        let ret = self.next_with(
+            |inner| {
+                iter.next().map(|s| {
+                    let len = match s {
+                        LzssCode::Symbol(_) => 1,
+                        LzssCode::Reference { len, pos }
+                            if (inner.min_match..=inner.max_match)
+                                .contains(&len)
+                                && pos < history =>
+                        {
+                            len
                         }
-                    }
-                }
-            };
-            self.bitbuf = s.0;
-            self.bitbuflen = s.1;
-        }
-
-        let ret = Left::convert(self.bitbuf, 16, 8) as u8;
-
-        self.bitbuf = Left::forward(self.bitbuf, 8);
-        self.bitbuflen -= 1;
-        Some(Ok(ret))
+                        LzssCode::Reference { .. } => {
+                            return Err(CompressionError::DataError)
+                        }
+                    };
+                    history = cmp::min(history + len, inner.window_size);
+                    Ok(s)
+                })
+            },
+            action,
+        );
+        self.history = history;
+        ret
     }
 }
 
@@ -244,254 +601,125 @@ struct LzhufEncoderInner {
     max_block_len: usize,
     offset_tab_len: usize,
     block_buf: Vec<LzhufLzssCode>,
-    symbol_freq: Vec<usize>,
-    offset_freq: Vec<usize>,
-    size_of_symbol_freq_buf: usize,
-    size_of_offset_freq_buf: usize,
+    freq: LzhufCounter,
+    min_match: usize,
+    max_match: usize,
+    window_size: usize,
+    // 最適解析のとき、直前のブロックの符号長から求めた符号の長さ
+    optimal: bool,
+    prices: Option<CodePrices>,
 }
 
 impl LzhufEncoderInner {
-    // Length Table
-    const TBIT_SIZE: usize = 5;
     // Symbol Table
     const CBIT_SIZE: usize = 9;
 
     fn init_block(&mut self) {
-        self.block_buf = Vec::with_capacity(self.max_block_len);
-        self.symbol_freq = vec![0; self.size_of_symbol_freq_buf];
-        self.offset_freq = vec![0; self.size_of_offset_freq_buf];
+        // 次のブロックも同じ領域に貯める
+        self.block_buf.clear();
+        self.freq.clear();
     }
 
     pub(crate) fn new(
         max_block_len: usize,
         offset_tab_len: usize,
+        min_match: usize,
         max_match: usize,
+        window_size: usize,
     ) -> Self {
-        let mbl_npot = max_block_len.next_power_of_two() >> 1;
+        // 距離の記号は窓の大きさのビット数まで、-lh7- では 17 個
         let size_of_offset_freq_buf =
-            cmp::max(max_block_len - mbl_npot, mbl_npot - 1);
-        let size_of_symbol_freq_buf = max_match + 256 - MIN_MATCH as usize + 1;
+            (usize::BITS - (window_size - 1).leading_zeros()) as usize + 1;
+        let size_of_symbol_freq_buf = max_match + 256 - min_match + 1;
         Self {
             max_block_len,
             offset_tab_len,
-            size_of_symbol_freq_buf,
-            size_of_offset_freq_buf,
             block_buf: Vec::with_capacity(max_block_len),
-            symbol_freq: vec![0; size_of_symbol_freq_buf],
-            offset_freq: vec![0; size_of_offset_freq_buf],
-        }
-    }
-
-    fn enc_len(&mut self, len: u16) -> Vec<SmallBitVec<u16>> {
-        if len >= 7 {
-            let mut ret = vec![SmallBitVec::new(7, 3)];
-            for _ in 7..len {
-                ret.push(SmallBitVec::new(1, 1));
-            }
-            ret.push(SmallBitVec::new(0, 1));
-            ret
-        } else {
-            vec![SmallBitVec::new(len, 3)]
-        }
-    }
-
-    fn write_symb_tab(
-        &mut self,
-        symb_enc_tab: &[u8],
-    ) -> Result<Vec<SmallBitVec<u16>>, CompressionError> {
-        // write symbol table
-        let symb_enc_tab_map = symb_enc_tab
-            .iter()
-            .enumerate()
-            .filter(|&(_, &t)| t != 0)
-            .collect::<Vec<_>>();
-        let mut ret = Vec::new();
-        if symb_enc_tab.is_empty() {
-            ret.push(SmallBitVec::new(0, Self::TBIT_SIZE));
-            ret.push(SmallBitVec::new(0, Self::TBIT_SIZE));
-            ret.push(SmallBitVec::new(0, Self::CBIT_SIZE));
-            ret.push(SmallBitVec::new(0, Self::CBIT_SIZE));
-        } else if symb_enc_tab_map.len() == 1 {
-            ret.push(SmallBitVec::new(0, Self::TBIT_SIZE));
-            ret.push(SmallBitVec::new(0, Self::TBIT_SIZE));
-            ret.push(SmallBitVec::new(0, Self::CBIT_SIZE));
-            ret.push(SmallBitVec::new(
-                symb_enc_tab_map[0].0 as u16,
-                Self::CBIT_SIZE,
-            ));
-        } else {
-            // generate length encoder
-            let mut sym_list = Vec::new();
-            let mut freq = vec![0; 19];
-
-            if !symb_enc_tab.is_empty() {
-                let mut i = 0;
-                for (sym_ind, &sym_len) in
-                    symb_enc_tab.iter().enumerate().filter(|&(_, &t)| t != 0)
-                {
-                    let gap = sym_ind - i;
-                    i = sym_ind + 1;
-                    if gap > 19 {
-                        sym_list.push((2, gap - 20));
-                        freq[2] += 1;
-                    } else if gap == 19 {
-                        sym_list.push((1, 15));
-                        sym_list.push((0, 0));
-                        freq[1] += 1;
-                        freq[0] += 1;
-                    } else if gap > 2 {
-                        sym_list.push((1, gap - 3));
-                        freq[1] += 1;
-                    } else if gap > 0 {
-                        sym_list.push((0, 0));
-                        if gap == 2 {
-                            sym_list.push((0, 0));
-                        }
-                        freq[0] += gap;
-                    }
-                    sym_list.push((3, sym_len as usize + 2));
-                    freq[sym_len as usize + 2] += 1;
-                }
-            }
-
-            // write length and symbol table
-            let len_enc_tab = make_table(&freq, 16);
-            let len_enc_tab_map = len_enc_tab
-                .iter()
-                .enumerate()
-                .filter(|&(_, &t)| t != 0)
-                .collect::<Vec<_>>();
-
-            let mut len_enc = if len_enc_tab_map.is_empty() {
-                unreachable!();
-            } else if len_enc_tab_map.len() == 1 {
-                ret.push(SmallBitVec::new(0, Self::TBIT_SIZE));
-                ret.push(SmallBitVec::new(
-                    len_enc_tab_map[0].0 as u16,
-                    Self::TBIT_SIZE,
-                ));
-                LzhufHuffmanEncoder::Default
-            } else {
-                let mut i = 0;
-                ret.push(SmallBitVec::new(
-                    len_enc_tab_map.last().unwrap().0 as u16 + 1,
-                    Self::TBIT_SIZE,
-                ));
-
-                for (len_ind, &len_len) in len_enc_tab_map {
-                    while len_ind >= i {
-                        if i == 3 {
-                            let skip =
-                                if len_ind > 6 { 3 } else { len_ind - 3 };
-                            ret.push(SmallBitVec::new(skip as u16, 2));
-                            i += skip;
-                        }
-
-                        if len_ind != i {
-                            ret.push(SmallBitVec::new(0, 3));
-                        } else {
-                            ret.append(&mut self.enc_len(u16::from(len_len)));
-                        }
-                        i += 1;
-                    }
-                }
-                LzhufHuffmanEncoder::new(&len_enc_tab)
-            };
-
-            ret.push(SmallBitVec::new(
-                symb_enc_tab_map.last().unwrap().0 as u16 + 1,
-                Self::CBIT_SIZE,
-            ));
-            for (s, l) in sym_list {
-                match s {
-                    0 => {
-                        if let Some(e) = len_enc.enc(0)? {
-                            ret.push(e)
-                        }
-                    }
-                    1 => {
-                        if let Some(e) = len_enc.enc(1)? {
-                            ret.push(e)
-                        };
-                        ret.push(SmallBitVec::new(l as u16, 4));
-                    }
-                    2 => {
-                        if let Some(e) = len_enc.enc(2)? {
-                            ret.push(e)
-                        };
-                        ret.push(SmallBitVec::new(l as u16, 9));
-                    }
-                    _ => {
-                        if let Some(e) = len_enc.enc(l as u16)? {
-                            ret.push(e)
-                        }
-                    }
-                }
-            }
+            freq: FrequencyCounter::new(
+                size_of_symbol_freq_buf,
+                size_of_offset_freq_buf,
+                LzhufLzssCode::symbols as fn(&_) -> _,
+            ),
+            min_match,
+            max_match,
+            window_size,
+            optimal: false,
+            prices: None,
         }
-        Ok(ret)
     }
 
-    fn write_offset_tab(
-        &mut self,
+    /// Returns the cost of the codes under the code lengths `sym_enc_tab`
+    /// and `off_enc_tab`. Symbols without a length cost one bit more than
+    /// the longest code, or as much as in a code of equal lengths if that
+    /// is more.
+    fn code_prices(
+        &self,
+        sym_enc_tab: &[u8],
         off_enc_tab: &[u8],
-        pbit_len: usize,
-    ) -> Vec<SmallBitVec<u16>> {
-        // write length and symbol table
-        let off_enc_tab_map = off_enc_tab
-            .iter()
-            .enumerate()
-            .filter(|&(_, &t)| t != 0)
-            .collect::<Vec<_>>();
-
-        let mut ret = Vec::new();
-        if off_enc_tab_map.is_empty() {
-            ret.push(SmallBitVec::new(0, pbit_len));
-            ret.push(SmallBitVec::new(0, pbit_len));
-        } else if off_enc_tab_map.len() == 1 {
-            ret.push(SmallBitVec::new(0, pbit_len));
-            ret.push(SmallBitVec::new(off_enc_tab_map[0].0 as u16, pbit_len));
-        } else {
-            let mut i = 0;
-            ret.push(SmallBitVec::new(
-                off_enc_tab_map.last().unwrap().0 as u16 + 1,
-                pbit_len,
-            ));
-            for (symb, &len) in off_enc_tab_map {
-                while symb >= i {
-                    if symb != i {
-                        ret.push(SmallBitVec::new(0, 3));
-                    } else {
-                        ret.append(&mut self.enc_len(u16::from(len)));
-                    }
-                    i += 1;
+    ) -> CodePrices {
+        fn lens(tab: &[u8], n: usize) -> Vec<u32> {
+            let longest = tab.iter().max().map_or(0, |&l| cmp::min(l + 1, 16));
+            let uniform = usize::BITS - (n - 1).leading_zeros();
+            let unused = cmp::max(u32::from(longest), uniform);
+            (0..n)
+                .map(|s| match tab.get(s) {
+                    Some(&l) if l != 0 => u32::from(l),
+                    _ => unused,
+                })
+                .collect()
+        }
+        let n = self.max_match + 256 - self.min_match + 1;
+        let sym = lens(sym_enc_tab, n);
+        let n = (usize::BITS - (self.window_size - 1).leading_zeros()) as usize;
+        let off = lens(off_enc_tab, n + 1);
+        let lengths = (0..=self.max_match)
+            .map(|len| {
+                if len < self.min_match {
+                    0
+                } else {
+                    sym[len + 256 - self.min_match]
                 }
-            }
+            })
+            .collect();
+        let positions = (0..self.window_size)
+            .map(|pos| {
+                let bits = (usize::BITS - pos.leading_zeros()) as usize;
+                off[bits] + bits.saturating_sub(1) as u32
+            })
+            .collect();
+        CodePrices {
+            literals: sym[..256].to_vec(),
+            lengths,
+            positions,
         }
-        ret
     }
 
+    /// Writes the block held, its tables and then its codes, and starts
+    /// the next one.
     fn write_block(
         &mut self,
-        queue: &mut VecDeque<SmallBitVec<u16>>,
+        writer: &mut BitWriter<Left>,
     ) -> Result<(), CompressionError> {
-        let sym_enc_tab = make_table(&self.symbol_freq, 16);
-        let off_enc_tab = make_table(&self.offset_freq, 16);
-        let mut sym_enc = LzhufHuffmanEncoder::new(&sym_enc_tab);
-        let mut off_enc = LzhufHuffmanEncoder::new(&off_enc_tab);
+        let (mut sym_enc, sym_enc_tab) =
+            LzhufHuffmanEncoder::from_frequencies(self.freq.symbol_freqs(), 16);
+        let (mut off_enc, off_enc_tab) =
+            LzhufHuffmanEncoder::from_frequencies(self.freq.offset_freqs(), 16);
+        if self.optimal {
+            // ブロックの表を作り直すたびに次の解析の値段も更新する
+            self.prices = Some(self.code_prices(&sym_enc_tab, &off_enc_tab));
+        }
 
         // write block length
-        queue.push_back(SmallBitVec::new(self.block_buf.len() as u16, 16));
+        writer.write_bits(&SmallBitVec::new(self.block_buf.len() as u16, 16));
 
-        queue.extend(self.write_symb_tab(&sym_enc_tab)?);
-        let l = self.offset_tab_len;
-        queue.extend(self.write_offset_tab(&off_enc_tab, l));
+        write_coded::<Left, _>(writer, &sym_enc_tab, Self::CBIT_SIZE)?;
+        write_plain(writer, &off_enc_tab, self.offset_tab_len, false)?;
 
         for d in &self.block_buf {
             match *d {
                 LzhufLzssCode::Symbol(s) => {
                     if let Some(e) = sym_enc.enc(u16::from(s))? {
-                        queue.push_back(e)
+                        writer.write_bits(&e)
                     }
                 }
                 LzhufLzssCode::Reference {
@@ -500,13 +728,13 @@ impl LzhufEncoderInner {
                     pos_sublen,
                 } => {
                     if let Some(e) = sym_enc.enc(len)? {
-                        queue.push_back(e)
+                        writer.write_bits(&e)
                     }
                     if let Some(e) = off_enc.enc(pos_offset)? {
-                        queue.push_back(e)
+                        writer.write_bits(&e)
                     }
                     if pos_offset > 1 {
-                        queue.push_back(SmallBitVec::new(
+                        writer.write_bits(&SmallBitVec::new(
                             pos_sublen,
                             pos_offset as usize - 1,
                         ));
@@ -522,47 +750,34 @@ impl LzhufEncoderInner {
     fn next(
         &mut self,
         buf: &LzssCode,
-        queue: &mut VecDeque<SmallBitVec<u16>>,
+        writer: &mut BitWriter<Left>,
     ) -> Result<(), CompressionError> {
-        let code = LzhufLzssCode::from(buf);
-        match code {
-            LzhufLzssCode::Symbol(s) => {
-                self.symbol_freq[s as usize] += 1;
-            }
-            LzhufLzssCode::Reference {
-                len, pos_offset, ..
-            } => {
-                self.symbol_freq[len as usize] += 1;
-                self.offset_freq[pos_offset as usize] += 1;
+        // 復号器が受け付けない距離や長さの符号は出力しない
+        if let LzssCode::Reference { len, pos } = *buf {
+            if pos >= self.window_size
+                || !(self.min_match..=self.max_match).contains(&len)
+            {
+                return Err(CompressionError::DataError);
             }
         }
-
+        let code = LzhufLzssCode::new(buf, self.min_match);
+        self.freq.add(&code);
         self.block_buf.push(code);
 
-        if self.block_buf.len() == self.max_block_len {
-            self.write_block(queue)?;
+        if self.block_buf.len() >= self.max_block_len {
+            self.write_block(writer)?;
         }
 
         Ok(())
     }
 
+    /// Writes the codes held as a block, if there are any.
     fn flush(
         &mut self,
-        queue: &mut VecDeque<SmallBitVec<u16>>,
+        writer: &mut BitWriter<Left>,
     ) -> Result<(), CompressionError> {
         if !self.block_buf.is_empty() {
-            self.write_block(queue)
-        } else {
-            Ok(())
-        }
-    }
-
-    fn finish(
-        &mut self,
-        queue: &mut VecDeque<SmallBitVec<u16>>,
-    ) -> Result<(), CompressionError> {
-        if !self.block_buf.is_empty() {
-            self.write_block(queue)
+            self.write_block(writer)
         } else {
             Ok(())
         }
@@ -574,6 +789,10 @@ mod tests {
     use super::*;
     use crate::action::Action;
     use crate::bitio::writer::BitWriteExt;
+    use crate::core::iter;
+    use crate::huffman::canonical::length_limited_lengths;
+    use crate::lzhuf::decoder::{LzhufCodeDecoder, LzhufDecoder};
+    use crate::traits::decoder::DecodeExt;
     use crate::traits::encoder::EncodeExt;
 
     #[test]
@@ -712,7 +931,7 @@ mod tests {
     #[test]
     fn test_lzhuflzsscode_offset() {
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 0 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 0 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 0,
@@ -720,7 +939,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 1 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 1 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 1,
@@ -728,7 +947,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 2 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 2 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 2,
@@ -736,7 +955,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 3 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 3 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 2,
@@ -744,7 +963,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 4 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 4 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 3,
@@ -752,7 +971,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 5 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 5 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 3,
@@ -760,7 +979,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 6 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 6 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 3,
@@ -768,7 +987,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 7 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 7 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 3,
@@ -776,7 +995,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 8 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 8 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 4,
@@ -784,7 +1003,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 15 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 15 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 4,
@@ -792,7 +1011,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 16 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 16 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 5,
@@ -800,7 +1019,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 31 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 31 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 5,
@@ -808,7 +1027,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 32 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 32 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 6,
@@ -816,7 +1035,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 64 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 64 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 7,
@@ -824,7 +1043,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 128 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 128 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 8,
@@ -832,7 +1051,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 256 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 256 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 9,
@@ -840,7 +1059,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 512 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 512 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 10,
@@ -848,7 +1067,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 1023 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 1023 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 10,
@@ -856,7 +1075,7 @@ mod tests {
             }
         );
         assert_eq!(
-            LzhufLzssCode::from(&LzssCode::Reference { len: 3, pos: 1024 }),
+            LzhufLzssCode::new(&LzssCode::Reference { len: 3, pos: 1024 }, 3),
             LzhufLzssCode::Reference {
                 len: 256,
                 pos_offset: 11,
@@ -864,4 +1083,305 @@ mod tests {
             }
         );
     }
+
+    /// Returns the Fibonacci numbers from 1, 1 up to `n` of them.
+    fn fibonacci(n: usize) -> Vec<usize> {
+        let mut ret = vec![1, 1];
+        while ret.len() < n {
+            let l = ret.len();
+            ret.push(ret[l - 1] + ret[l - 2]);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_overlong_frequencies() {
+        // フィボナッチ頻度の記号は、制限がなければ 16 ビットを超える
+        let method = LzhufMethod::Lh7;
+        let mut codes = Vec::new();
+        for (i, &f) in fibonacci(22).iter().enumerate() {
+            codes.extend((0..f).map(|_| LzssCode::Symbol(i as u8 * 11)));
+        }
+        let offsets = method.dictionary_bits() + 1;
+        for (c, &f) in fibonacci(offsets).iter().enumerate() {
+            // 距離符号 c の最小の距離
+            let pos = if c == 0 { 0 } else { 1 << (c - 1) };
+            codes.extend((0..f).map(|_| LzssCode::Reference { len: 3, pos }));
+        }
+        let mut expected = Vec::new();
+        for c in &codes {
+            match *c {
+                LzssCode::Symbol(s) => expected.push(s),
+                LzssCode::Reference { len, pos } => {
+                    for _ in 0..len {
+                        expected.push(expected[expected.len() - pos - 1]);
+                    }
+                }
+            }
+        }
+
+        let mut inner = LzhufEncoderInner::new(
+            LzhufEncoder::LZHUF_MAX_BLOCK_LENGTH,
+            method.offset_bits(),
+            method.min_match(),
+            LzhufEncoder::LZSS_MAX_MATCH,
+            1 << method.dictionary_bits(),
+        );
+        let mut writer = BitWriter::<Left>::new();
+        for c in &codes {
+            inner.next(c, &mut writer).unwrap();
+        }
+        let lens = length_limited_lengths(inner.freq.symbol_freqs(), 64);
+        assert!(*lens.iter().max().unwrap() > 16);
+        inner.flush(&mut writer).unwrap();
+
+        let _ = writer.pad_to_byte(false);
+        let encoded = iter::from_fn(|| writer.pop_byte()).collect::<Vec<_>>();
+        assert_eq!(
+            LzhufDecoder::new(&method).decode_slice(&encoded),
+            Ok((expected, encoded.len()))
+        );
+    }
+
+    #[test]
+    fn test_reference_before_start() {
+        let method = LzhufMethod::Lh5;
+        let encode = |codes: &[LzssCode]| {
+            let mut inner = LzhufEncoderInner::new(
+                LzhufEncoder::LZHUF_MAX_BLOCK_LENGTH,
+                method.offset_bits(),
+                method.min_match(),
+                LzhufEncoder::LZSS_MAX_MATCH,
+                1 << method.dictionary_bits(),
+            );
+            let mut writer = BitWriter::<Left>::new();
+            for c in codes {
+                inner.next(c, &mut writer).unwrap();
+            }
+            inner.flush(&mut writer).unwrap();
+            let _ = writer.pad_to_byte(false);
+            iter::from_fn(|| writer.pop_byte()).collect::<Vec<_>>()
+        };
+
+        // 最初の符号が参照
+        let encoded = encode(&[LzssCode::Reference { len: 3, pos: 0 }]);
+        let err = encoded
+            .iter()
+            .cloned()
+            .decode(&mut LzhufDecoder::new(&method))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert_eq!(err, CompressionError::DataError);
+        assert!(err.bit_offset().is_some());
+        assert_eq!(
+            LzhufDecoder::new(&method).decode_slice(&encoded),
+            Err(CompressionError::DataError)
+        );
+
+        let encoded = encode(&[
+            LzssCode::Symbol(b'a'),
+            LzssCode::Reference { len: 3, pos: 1 },
+        ]);
+        assert_eq!(
+            LzhufDecoder::new(&method).decode_slice(&encoded),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    #[test]
+    fn test_compress_to_vec() {
+        let mut seed = 0x2545_F491_u32;
+        let source = (0..3000)
+            .flat_map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                ["the ", "quick ", "brown ", "fox ", "jumps\n", "over "]
+                    [(seed % 6) as usize]
+                    .bytes()
+            })
+            .collect::<Vec<u8>>();
+        for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast] {
+            for &action in &[Action::Run, Action::Flush, Action::Finish] {
+                let mut encoder = LzhufEncoder::new(&LzhufMethod::Lh5);
+                encoder.set_parse_mode(mode);
+                let expected = source
+                    .iter()
+                    .cloned()
+                    .encode(&mut encoder, action)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let mut encoder = LzhufEncoder::new(&LzhufMethod::Lh5);
+                encoder.set_parse_mode(mode);
+                assert_eq!(
+                    encoder.compress_to_vec(&source, action),
+                    Ok(expected)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_flush_block() {
+        let source = include_bytes!("../../data/sample4.ref");
+        for &method in &[LzhufMethod::Lh5, LzhufMethod::Lh7] {
+            let mut encoder = LzhufEncoder::new(&method);
+            let mut out = Vec::new();
+            let mut flushed = Vec::new();
+            for chunk in source.chunks(0x1000) {
+                out.extend(
+                    encoder.compress_to_vec(chunk, Action::Run).unwrap(),
+                );
+                out.extend(encoder.flush_block().unwrap());
+                flushed.push((flushed.len() * 0x1000 + chunk.len(), out.len()));
+            }
+            out.extend(encoder.compress_to_vec(&[], Action::Finish).unwrap());
+            let decode = |input: &[u8]| {
+                LzhufDecoder::new(&method).decode_slice(input).unwrap().0
+            };
+            assert_eq!(decode(&out), &source[..]);
+            // 区切りまでの出力と、その次の 1 バイトで区切りまでの入力になる
+            for &(read, written) in &flushed {
+                let end = cmp::min(written + 1, out.len());
+                assert_eq!(decode(&out[..end]), &source[..read]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_size() {
+        let source = include_bytes!("../../data/sample4.ref");
+        let mut lens = Vec::new();
+        for &size in &[1, 100, 0x1000, 0xFFFF] {
+            // 1 符号ずつのブロックは短い入力で試す
+            let source = if size == 1 { &source[..0x800] } else { source };
+            let mut encoder = LzhufEncoder::new(&LzhufMethod::Lh5);
+            encoder.set_block_size(size);
+            let out = encoder.compress_to_vec(source, Action::Finish).unwrap();
+            let (decoded, used) = LzhufDecoder::new(&LzhufMethod::Lh5)
+                .decode_slice(&out)
+                .unwrap();
+            assert_eq!(decoded, source);
+            assert_eq!(used, out.len());
+            lens.push(out.len());
+        }
+        // 表を何度も書くほど大きくなる
+        assert!(lens[0] > 0x800);
+        assert!(lens[1] > lens[2] && lens[2] > lens[3]);
+    }
+
+    #[test]
+    fn test_long_offsets() {
+        // 窓 64 KiB の -lh7- だけが、60000 バイト離れた繰り返しを参照できる
+        let mut seed = 0x2545_F491_u32;
+        let mut source = (0..60_000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect::<Vec<_>>();
+        source.extend_from_within(..4000);
+        let mut lens = Vec::new();
+        for &method in &[LzhufMethod::Lh6, LzhufMethod::Lh7] {
+            let out = LzhufEncoder::new(&method)
+                .compress_to_vec(&source, Action::Finish)
+                .unwrap();
+            let longest = out
+                .iter()
+                .cloned()
+                .decode(&mut LzhufCodeDecoder::new(&method))
+                .map(|c| match c.unwrap() {
+                    LzssCode::Reference { pos, .. } => pos,
+                    LzssCode::Symbol(_) => 0,
+                })
+                .max()
+                .unwrap();
+            assert!(longest < 1 << method.dictionary_bits());
+            assert_eq!(
+                LzhufDecoder::new(&method).decode_slice(&out),
+                Ok((source.clone(), out.len()))
+            );
+            lens.push((longest, out.len()));
+        }
+        assert!(lens[0].1 > source.len());
+        assert_eq!(lens[1].0, 59_999);
+        assert!(lens[1].1 < 60_500);
+    }
+
+    #[test]
+    fn test_encode_info() {
+        let inputs: [&[u8]; 4] = [
+            b"",
+            b"a",
+            include_bytes!("../../data/sample3.ref"),
+            include_bytes!("../../data/sample4.ref"),
+        ];
+        for &method in &[LzhufMethod::Lh0, LzhufMethod::Lh5, LzhufMethod::Lh7] {
+            let mut encoder = LzhufEncoder::new(&method);
+            // 続けて符号化し、finish ごとに数え直す
+            for data in &inputs {
+                let out = data
+                    .iter()
+                    .cloned()
+                    .encode(&mut encoder, Action::Finish)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let expected = LzhufEncodeInfo {
+                    original_size: data.len() as u64,
+                    compressed_size: out.len() as u64,
+                    crc: Crc16::checksum(data),
+                };
+                assert_eq!(encoder.finish(), expected);
+                // 区切って渡しても、数えるのは入力と出力の全体
+                let (head, tail) = data.split_at(data.len() / 3);
+                let mut out =
+                    encoder.compress_to_vec(head, Action::Run).unwrap();
+                out.extend(encoder.flush_block().unwrap());
+                out.extend(
+                    encoder.compress_to_vec(tail, Action::Finish).unwrap(),
+                );
+                let info = encoder.finish();
+                assert_eq!(info.original_size, data.len() as u64);
+                assert_eq!(info.compressed_size, out.len() as u64);
+                assert_eq!(info.crc, Crc16::checksum(data));
+            }
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        // 圧縮できない入力では少ない一致の方が小さくなるので 0.5% まで許す
+        let corpus: [&[u8]; 3] = [
+            include_bytes!("../../data/sample1.ref"),
+            include_bytes!("../../data/sample3.ref"),
+            include_bytes!("../../data/sample7.ref"),
+        ];
+        for &method in &[LzhufMethod::Lh5, LzhufMethod::Lh7] {
+            for data in &corpus {
+                let mut sizes = Vec::new();
+                for level in 1..=9 {
+                    let mut encoder = LzhufEncoder::new(&method);
+                    encoder.set_level(level);
+                    let out =
+                        encoder.compress_to_vec(data, Action::Finish).unwrap();
+                    assert_eq!(
+                        LzhufDecoder::new(&method).decode_slice(&out),
+                        Ok((data.to_vec(), out.len()))
+                    );
+                    sizes.push(out.len());
+                }
+                for (i, &lower) in sizes.iter().enumerate() {
+                    for &higher in &sizes[i + 1..] {
+                        assert!(higher <= lower + lower / 200, "{:?}", sizes);
+                    }
+                }
+                let default = LzhufEncoder::new(&method)
+                    .compress_to_vec(data, Action::Finish)
+                    .unwrap();
+                assert_eq!(default.len(), sizes[5]);
+            }
+        }
+    }
 }
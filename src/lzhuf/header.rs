@@ -0,0 +1,793 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! Member headers of LHA archives (`.lzh`), levels 0 to 2.
+//!
+//! All levels start with the same fields after two bytes of size and
+//! checksum: the method, the two sizes, the timestamp, an attribute byte
+//! and the level at offset 20. Level 0 and 1 headers are covered by an
+//! 8-bit sum. Level 1 and 2 headers are followed by a chain of extended
+//! headers, each a type byte, its contents and the size of the next one;
+//! in level 2, a common header of type 0 holds a CRC-16 of the whole
+//! header.
+
+use crate::checksum::Crc16;
+use crate::core::cmp;
+use crate::core::convert::TryFrom;
+use crate::core::ops::Range;
+use crate::core::str;
+use crate::error::CompressionError;
+use crate::lzhuf::LzhufMethod;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Modification time of an archive member.
+///
+/// The header has no time zone, so MS-DOS times convert as if they were
+/// UTC.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// // 1996-03-01 12:30:04
+/// let dos = LhaTimestamp::Dos(0x2061_63C2);
+/// assert_eq!(dos.to_unix(), 825_683_404);
+/// assert_eq!(LhaTimestamp::Unix(825_683_405).to_dos(), 0x2061_63C2);
+/// // 1980 より前は MS-DOS の形式で表せない
+/// assert_eq!(LhaTimestamp::Unix(0).to_dos(), 0x0021_0000);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LhaTimestamp {
+    /// MS-DOS date and time in local time, the date in the upper 16 bits,
+    /// of level 0 and 1 headers.
+    Dos(u32),
+    /// Seconds since the Unix epoch, of level 2 headers and the extended
+    /// header of type 0x54.
+    Unix(u32),
+}
+
+impl LhaTimestamp {
+    const SECS_PER_DAY: u32 = 86_400;
+    // 1980-01-01 00:00:00, MS-DOS の時刻の始まり
+    const DOS_EPOCH: u32 = 315_532_800;
+    const DOS_MIN: u32 = 0x0021_0000;
+
+    /// Returns the time in seconds since the Unix epoch. A month, hour,
+    /// minute or second out of range in an MS-DOS time is clamped into
+    /// it, and times after 2106 are `u32::MAX`.
+    pub fn to_unix(self) -> u32 {
+        let dos = match self {
+            LhaTimestamp::Unix(t) => return t,
+            LhaTimestamp::Dos(d) => d,
+        };
+        let year = 1980 + (dos >> 25);
+        let month = ((dos >> 21) & 0xF).clamp(1, 12);
+        let day = cmp::max((dos >> 16) & 0x1F, 1);
+        let hours = cmp::min((dos >> 11) & 0x1F, 23);
+        let minutes = cmp::min((dos >> 5) & 0x3F, 59);
+        let seconds = cmp::min(dos & 0x1F, 29) * 2;
+        let t = u64::from(days_from_civil(year, month, day))
+            * u64::from(Self::SECS_PER_DAY)
+            + u64::from(hours * 3600 + minutes * 60 + seconds);
+        u32::try_from(t).unwrap_or(u32::MAX)
+    }
+
+    /// Returns the time in the MS-DOS format, rounded down to its 2-second
+    /// steps. Times before 1980 are clamped to 1980-01-01 00:00:00, the
+    /// earliest it holds.
+    pub fn to_dos(self) -> u32 {
+        let t = match self {
+            LhaTimestamp::Dos(d) => return d,
+            LhaTimestamp::Unix(t) if t < Self::DOS_EPOCH => {
+                return Self::DOS_MIN
+            }
+            LhaTimestamp::Unix(t) => t,
+        };
+        let (year, month, day) = civil_from_days(t / Self::SECS_PER_DAY);
+        let secs = t % Self::SECS_PER_DAY;
+        ((year - 1980) << 25)
+            | (month << 21)
+            | (day << 16)
+            | ((secs / 3600) << 11)
+            | ((secs / 60 % 60) << 5)
+            | (secs % 60 / 2)
+    }
+}
+
+/// Returns the days from 1970-01-01 to a date from 1970 on.
+fn days_from_civil(year: u32, month: u32, day: u32) -> u32 {
+    // 3 月始まりの年で数え、閏日を年の終わりに置く
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year % 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the year, month and day `days` after 1970-01-01.
+fn civil_from_days(days: u32) -> (u32, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(feature = "std")]
+impl From<SystemTime> for LhaTimestamp {
+    /// Converts to a Unix time, clamped to the range of `u32`.
+    fn from(time: SystemTime) -> Self {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => u32::try_from(d.as_secs()).unwrap_or(u32::MAX),
+            Err(_) => 0,
+        };
+        LhaTimestamp::Unix(secs)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<LhaTimestamp> for SystemTime {
+    fn from(time: LhaTimestamp) -> Self {
+        UNIX_EPOCH + Duration::from_secs(u64::from(time.to_unix()))
+    }
+}
+
+/// Header of a member of an LHA archive.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// fn members(archive: &[u8]) -> Result<Vec<Vec<u8>>, CompressionError> {
+///     let mut ret = Vec::new();
+///     let mut rest = archive;
+///     while let Some(header) = LhaHeader::parse(rest)? {
+///         let payload = rest
+///             .get(header.payload())
+///             .ok_or(CompressionError::UnexpectedEof)?;
+///         let method = header.method().ok_or(CompressionError::DataError)?;
+///         let (data, _) = LzhufDecoder::with_original_size(
+///             &method,
+///             header.original_size as usize,
+///         )
+///         .decode_slice(payload)?;
+///         header.verify(&data)?;
+///         ret.push(data);
+///         rest = &rest[header.payload().end..];
+///     }
+///     Ok(ret)
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LhaHeader {
+    pub level: u8,
+    /// The method string, such as `*b"-lh5-"`.
+    pub method: [u8; 5],
+    pub compressed_size: u32,
+    pub original_size: u32,
+    /// The time of the header, or of the extended header of type 0x54 if
+    /// there is one.
+    pub timestamp: LhaTimestamp,
+    /// The MS-DOS attribute, from the extended header of type 0x40 if
+    /// there is one.
+    pub attribute: u8,
+    /// The Unix permission bits, of the extended header of type 0x50.
+    pub unix_mode: Option<u16>,
+    /// The comment, of the extended header of type 0x3F.
+    pub comment: Option<Vec<u8>>,
+    /// The path as stored. The directory of an extended header of type
+    /// 0x02 comes before the name, its separators changed to `/`.
+    pub name: Vec<u8>,
+    /// CRC-16 of the original data.
+    pub crc: u16,
+    /// The system the archive was made on, such as `b'U'` or `b'M'`.
+    /// Level 0 headers only have it with an extension.
+    pub os_id: Option<u8>,
+    /// The type and contents of each extended header, in order, including
+    /// the ones read into the fields above. `LhaWriter::add_with_extended`
+    /// writes them back.
+    pub extended: Vec<(u8, Vec<u8>)>,
+    /// The length of the header, where the compressed data starts.
+    pub header_len: usize,
+}
+
+fn bytes(input: &[u8], range: Range<usize>) -> Result<&[u8], CompressionError> {
+    input.get(range).ok_or(CompressionError::UnexpectedEof)
+}
+
+fn u16_at(input: &[u8], pos: usize) -> Result<u16, CompressionError> {
+    let b = bytes(input, pos..pos + 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(input: &[u8], pos: usize) -> Result<u32, CompressionError> {
+    let b = bytes(input, pos..pos + 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// An extended header: the offset of its contents, its type and contents.
+type Extended<'a> = (usize, u8, &'a [u8]);
+
+/// Reads the chain of extended headers in `input` from `pos`, the first
+/// of size `next`. Returns the headers and the end of the chain. A chain
+/// that runs past `input` is `overrun`.
+fn read_chain(
+    input: &[u8],
+    mut pos: usize,
+    mut next: usize,
+    overrun: CompressionError,
+) -> Result<(Vec<Extended<'_>>, usize), CompressionError> {
+    let mut ret = Vec::new();
+    while next != 0 {
+        // 種別と次の大きさの 3 バイトは必ずある
+        if next < 3 {
+            return Err(CompressionError::DataError);
+        }
+        let ext = input.get(pos..pos + next).ok_or(overrun)?;
+        ret.push((pos + 1, ext[0], &ext[1..next - 2]));
+        pos += next;
+        next = usize::from(u16_at(ext, next - 2)?);
+    }
+    Ok((ret, pos))
+}
+
+impl LhaHeader {
+    /// Parses the header at the start of `input`. Returns `None` at the
+    /// end of the archive, a zero byte, or if `input` is empty.
+    ///
+    /// A wrong checksum of a level 0 or 1 header or CRC of a level 2 one is
+    /// `HeaderChecksumMismatch`, with the stored and computed values in
+    /// `CompressionError::checksums`. Inconsistent sizes and levels other
+    /// than 0 to 2 are `DataError`; a header cut short is `UnexpectedEof`.
+    pub fn parse(input: &[u8]) -> Result<Option<Self>, CompressionError> {
+        match input.first() {
+            None | Some(0) => return Ok(None),
+            Some(_) => {}
+        }
+        let level = *input.get(20).ok_or(CompressionError::UnexpectedEof)?;
+        let mut method = [0; 5];
+        method.copy_from_slice(bytes(input, 2..7)?);
+        let mut header = Self {
+            level,
+            method,
+            compressed_size: u32_at(input, 7)?,
+            original_size: u32_at(input, 11)?,
+            timestamp: LhaTimestamp::Dos(u32_at(input, 15)?),
+            attribute: input[19],
+            unix_mode: None,
+            comment: None,
+            name: Vec::new(),
+            crc: 0,
+            os_id: None,
+            extended: Vec::new(),
+            header_len: 0,
+        };
+        match level {
+            0 | 1 => header.parse_level01(input)?,
+            2 => header.parse_level2(input)?,
+            _ => return Err(CompressionError::DataError),
+        }
+        Ok(Some(header))
+    }
+
+    fn parse_level01(&mut self, input: &[u8]) -> Result<(), CompressionError> {
+        let base = bytes(input, 0..2 + usize::from(input[0]))?;
+        let sum = base[2..].iter().fold(0_u8, |a, &b| a.wrapping_add(b));
+        if sum != base[1] {
+            return Err(CompressionError::HeaderChecksumMismatch
+                .with_checksums(u32::from(base[1]), u32::from(sum)));
+        }
+        let name_len =
+            usize::from(*base.get(21).ok_or(CompressionError::DataError)?);
+        // 名前, CRC と、レベル 1 では OS と次の拡張ヘッダの大きさ
+        let fixed = 24 + name_len + if self.level == 1 { 3 } else { 0 };
+        if base.len() < fixed {
+            return Err(CompressionError::DataError);
+        }
+        self.name = base[22..22 + name_len].to_vec();
+        self.crc = u16_at(base, 22 + name_len)?;
+        self.os_id = base.get(24 + name_len).cloned();
+        self.header_len = base.len();
+        if self.level == 0 {
+            return Ok(());
+        }
+
+        let next = usize::from(u16_at(base, base.len() - 2)?);
+        let (ext, end) = read_chain(
+            input,
+            base.len(),
+            next,
+            CompressionError::UnexpectedEof,
+        )?;
+        // レベル 1 の圧縮サイズは拡張ヘッダを含む
+        let ext_len = end - base.len();
+        if ext_len as u64 > u64::from(self.compressed_size) {
+            return Err(CompressionError::DataError);
+        }
+        self.compressed_size -= ext_len as u32;
+        self.header_len = end;
+        self.read_extended(&ext);
+        Ok(())
+    }
+
+    fn parse_level2(&mut self, input: &[u8]) -> Result<(), CompressionError> {
+        let header = bytes(input, 0..usize::from(u16_at(input, 0)?))?;
+        if header.len() < 26 {
+            return Err(CompressionError::DataError);
+        }
+        self.timestamp = LhaTimestamp::Unix(u32_at(header, 15)?);
+        self.crc = u16_at(header, 21)?;
+        self.os_id = Some(header[23]);
+        self.header_len = header.len();
+        let next = usize::from(u16_at(header, 24)?);
+        let (ext, end) =
+            read_chain(header, 26, next, CompressionError::DataError)?;
+        // 連鎖の終わりの後には、大きさの下位バイトを 0 にしない詰め物だけ
+        if header.len() - end > 1 {
+            return Err(CompressionError::DataError);
+        }
+        // 共通ヘッダの CRC は、その欄を 0 としたヘッダ全体のもの
+        if let Some(&(pos, _, c)) = ext.iter().find(|e| e.1 == 0) {
+            let stored =
+                u16_at(c, 0).map_err(|_| CompressionError::DataError)?;
+            let mut crc = Crc16::new();
+            crc.update(&header[..pos]);
+            crc.update(&[0, 0]);
+            crc.update(&header[pos + 2..]);
+            if crc.finish() != stored {
+                return Err(CompressionError::HeaderChecksumMismatch
+                    .with_checksums(
+                        u32::from(stored),
+                        u32::from(crc.finish()),
+                    ));
+            }
+        }
+        self.read_extended(&ext);
+        Ok(())
+    }
+
+    fn read_extended(&mut self, ext: &[Extended<'_>]) {
+        let mut dir = Vec::new();
+        for &(_, kind, contents) in ext {
+            match kind {
+                0x01 => self.name = contents.to_vec(),
+                0x02 => {
+                    dir = contents
+                        .iter()
+                        .map(|&b| if b == 0xFF { b'/' } else { b })
+                        .collect();
+                    if !dir.is_empty() && !dir.ends_with(b"/") {
+                        dir.push(b'/');
+                    }
+                }
+                0x3F => self.comment = Some(contents.to_vec()),
+                0x40 if !contents.is_empty() => self.attribute = contents[0],
+                0x50 if contents.len() >= 2 => {
+                    self.unix_mode =
+                        Some(u16::from_le_bytes([contents[0], contents[1]]))
+                }
+                0x54 if contents.len() >= 4 => {
+                    self.timestamp = LhaTimestamp::Unix(u32::from_le_bytes([
+                        contents[0],
+                        contents[1],
+                        contents[2],
+                        contents[3],
+                    ]))
+                }
+                _ => {}
+            }
+            self.extended.push((kind, contents.to_vec()));
+        }
+        if !dir.is_empty() {
+            dir.extend_from_slice(&self.name);
+            self.name = dir;
+        }
+    }
+
+    /// Returns the method to decode the member with, or `None` for other
+    /// methods, such as the `-lhd-` of directories.
+    pub fn method(&self) -> Option<LzhufMethod> {
+        str::from_utf8(&self.method).ok()?.parse().ok()
+    }
+
+    /// Returns where the compressed data is, relative to the start of the
+    /// header. The next header follows it.
+    pub fn payload(&self) -> Range<usize> {
+        self.header_len..self.header_len + self.compressed_size as usize
+    }
+
+    /// Checks the decoded data against the original size and the CRC-16
+    /// of the header. A different size is `DataError`, a different CRC
+    /// `ChecksumMismatch` with the two CRCs in `checksums`.
+    pub fn verify(&self, data: &[u8]) -> Result<(), CompressionError> {
+        if data.len() as u64 != u64::from(self.original_size) {
+            return Err(CompressionError::DataError);
+        }
+        let crc = Crc16::checksum(data);
+        if crc != self.crc {
+            return Err(CompressionError::ChecksumMismatch
+                .with_checksums(u32::from(self.crc), u32::from(crc)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::lzhuf::decoder::LzhufDecoder;
+    #[cfg(not(feature = "std"))]
+    use alloc::{vec, vec::Vec};
+
+    const LEVEL0: &[u8] = include_bytes!("../../data/level0.lzh");
+    const LEVEL1: &[u8] = include_bytes!("../../data/level1.lzh");
+    const LEVEL2: &[u8] = include_bytes!("../../data/level2.lzh");
+    const WINDOWS: &[u8] = include_bytes!("../../data/windows.lzh");
+    const SAMPLE3: &[u8] = include_bytes!("../../data/sample3.ref");
+    const HELLO: &[u8] = b"Hello, LHA!\r\nHello, LHA!\r\nHello, LHA!\r\n";
+    // 1996-03-01 12:30:04
+    const DOS_TIME: u32 = 0x2061_63C2;
+
+    /// Returns the headers of `archive` and the decoded members.
+    fn members(archive: &[u8]) -> Vec<(LhaHeader, Vec<u8>)> {
+        let mut ret = Vec::new();
+        let mut rest = archive;
+        while let Some(header) = LhaHeader::parse(rest).unwrap() {
+            let (data, used) = LzhufDecoder::with_original_size(
+                &header.method().unwrap(),
+                header.original_size as usize,
+            )
+            .decode_slice(&rest[header.payload()])
+            .unwrap();
+            assert!(used <= header.compressed_size as usize);
+            header.verify(&data).unwrap();
+            rest = &rest[header.payload().end..];
+            ret.push((header, data));
+        }
+        assert_eq!(rest, [0]);
+        ret
+    }
+
+    #[test]
+    fn test_level0() {
+        let m = members(LEVEL0);
+        assert_eq!(m.len(), 2);
+        let (ref h, ref data) = m[0];
+        assert_eq!((h.level, &h.method), (0, b"-lh0-"));
+        assert_eq!(h.name, b"HELLO.TXT");
+        assert_eq!(h.timestamp, LhaTimestamp::Dos(DOS_TIME));
+        assert_eq!((h.attribute, h.os_id), (0x20, None));
+        assert_eq!(h.header_len, 2 + 22 + 9);
+        assert_eq!(data, HELLO);
+        let (ref h, ref data) = m[1];
+        assert_eq!(h.method(), Some(LzhufMethod::Lh5));
+        assert_eq!(
+            (&h.name[..], h.attribute),
+            (&b"DOCS\\SAMPLE3.TXT"[..], 0x21)
+        );
+        assert_eq!(data, SAMPLE3);
+    }
+
+    #[test]
+    fn test_level1() {
+        let m = members(LEVEL1);
+        assert_eq!(m.len(), 2);
+        let (ref h, ref data) = m[0];
+        assert_eq!(
+            (h.level, h.method(), h.os_id),
+            (1, Some(LzhufMethod::Lh5), Some(b'U'))
+        );
+        assert_eq!(h.name, b"sample3.txt");
+        // 拡張ヘッダ 0x54 の Unix 時間が基本ヘッダの時刻に優先する
+        assert_eq!(h.timestamp, LhaTimestamp::Unix(820_000_000));
+        // 拡張ヘッダの分を除いた圧縮サイズ
+        assert_eq!(h.compressed_size, 373);
+        assert_eq!((h.unix_mode, &h.comment), (Some(0o100644), &None));
+        assert_eq!(
+            h.extended,
+            vec![
+                (0x50, 0o100644_u16.to_le_bytes().to_vec()),
+                (0x54, 820_000_000_u32.to_le_bytes().to_vec()),
+            ]
+        );
+        assert_eq!(data, SAMPLE3);
+        let (ref h, ref data) = m[1];
+        // 長い名前は拡張ヘッダにある
+        assert_eq!(h.name, b"a long file name.txt");
+        assert_eq!(data, HELLO);
+    }
+
+    #[test]
+    fn test_level2() {
+        let m = members(LEVEL2);
+        assert_eq!(m.len(), 2);
+        let (ref h, ref data) = m[0];
+        assert_eq!(
+            (h.level, h.method(), h.os_id),
+            (2, Some(LzhufMethod::Lh5), Some(b'U'))
+        );
+        assert_eq!(h.name, b"docs/ref/sample3.txt");
+        assert_eq!(h.timestamp, LhaTimestamp::Unix(820_000_000));
+        assert_eq!(h.extended.len(), 4);
+        assert_eq!(h.unix_mode, Some(0o100644));
+        assert_eq!(data, SAMPLE3);
+        let (ref h, ref data) = m[1];
+        assert_eq!((&h.name[..], h.attribute), (&b"hello.txt"[..], 0x21));
+        assert_eq!(data, HELLO);
+    }
+
+    #[test]
+    fn test_windows() {
+        let m = members(WINDOWS);
+        assert_eq!(m.len(), 2);
+        let (ref h, ref data) = m[0];
+        assert_eq!((h.level, h.os_id), (2, Some(b'W')));
+        // 0xFF で終わらないディレクトリ名
+        assert_eq!(h.name, b"Docs/SAMPLE3.TXT");
+        assert_eq!((h.attribute, h.unix_mode), (0x20, None));
+        assert_eq!(h.comment.as_deref(), Some(&b"sample text"[..]));
+        // FILETIME と 64 ビットの大きさは、そのまま extended に残る
+        assert_eq!(
+            h.extended.iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec![0x00, 0x01, 0x02, 0x40, 0x41, 0x42, 0x3F]
+        );
+        assert_eq!(h.extended[4].1.len(), 24);
+        assert_eq!(data, SAMPLE3);
+        let (ref h, ref data) = m[1];
+        assert_eq!((h.level, h.os_id), (1, Some(b'M')));
+        assert_eq!((&h.name[..], h.attribute), (&b"HELLO.TXT"[..], 0x21));
+        assert_eq!(h.comment.as_deref(), Some(&b"greeting"[..]));
+        assert_eq!(h.timestamp, LhaTimestamp::Dos(DOS_TIME));
+        assert_eq!(data, HELLO);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        for &(dos, unix) in &[
+            (0x0021_0000, 315_532_800),
+            (DOS_TIME, 825_683_404),
+            // 2000-02-29 23:59:58, 2038-01-19 03:14:06, 2106-02-07 06:28:14
+            (0x285D_BF7D, 951_868_798),
+            (0x7433_19C3, 2_147_483_646),
+            (0xFC47_3387, 4_294_967_294),
+        ] {
+            assert_eq!(LhaTimestamp::Dos(dos).to_unix(), unix);
+            assert_eq!(LhaTimestamp::Unix(unix).to_dos(), dos);
+            // 2 秒単位に切り捨てる
+            assert_eq!(LhaTimestamp::Unix(unix + 1).to_dos(), dos);
+        }
+        let mut t = 315_532_800_u32;
+        while let Some(next) = t.checked_add(999_983) {
+            let dos = LhaTimestamp::Unix(t).to_dos();
+            assert_eq!(LhaTimestamp::Dos(dos).to_unix(), t & !1);
+            t = next;
+        }
+        // 1980 より前は MS-DOS の最初の時刻にする
+        for &unix in &[0, 315_532_799] {
+            assert_eq!(LhaTimestamp::Unix(unix).to_dos(), 0x0021_0000);
+        }
+        // 範囲外の欄は範囲に収め、2106 年より後は u32 の最大値
+        assert_eq!(LhaTimestamp::Dos(0).to_unix(), 315_532_800);
+        assert_eq!(LhaTimestamp::Dos(0xFFFF_FFFF).to_unix(), u32::MAX);
+        // 1996-03-01 の 31:63:62 は 23:59:58
+        assert_eq!(
+            LhaTimestamp::Dos(0x2061_FFFF).to_unix(),
+            825_638_400 + 86_398
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_system_time() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let time = UNIX_EPOCH + Duration::from_secs(825_683_404);
+        assert_eq!(LhaTimestamp::from(time), LhaTimestamp::Unix(825_683_404));
+        assert_eq!(SystemTime::from(LhaTimestamp::Dos(DOS_TIME)), time);
+        let before = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(LhaTimestamp::from(before), LhaTimestamp::Unix(0));
+        let after = UNIX_EPOCH + Duration::from_secs(1 << 40);
+        assert_eq!(LhaTimestamp::from(after), LhaTimestamp::Unix(u32::MAX));
+    }
+
+    #[test]
+    fn test_end_of_archive() {
+        assert_eq!(LhaHeader::parse(&[]), Ok(None));
+        assert_eq!(LhaHeader::parse(&[0, 1, 2]), Ok(None));
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        // レベル 0, 1 の合計とレベル 2 のヘッダの CRC
+        for (archive, pos) in
+            &[(LEVEL0, 1), (LEVEL0, 30), (LEVEL1, 25), (LEVEL2, 40)]
+        {
+            let mut input = archive.to_vec();
+            input[*pos] ^= 0x10;
+            let e = LhaHeader::parse(&input).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::HeaderChecksumMismatch);
+            let (expected, computed) = e.checksums().unwrap();
+            assert_ne!(expected, computed);
+        }
+        let mut input = LEVEL0.to_vec();
+        input[1] = input[1].wrapping_add(1);
+        let e = LhaHeader::parse(&input).unwrap_err();
+        let sum = u32::from(LEVEL0[1]);
+        assert_eq!(e.checksums(), Some((sum + 1, sum)));
+    }
+
+    /// Returns a level 2 header with the extended headers of `ext`, where
+    /// the one of type 0x00 holds the CRC of the header.
+    fn level2(ext: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut header = vec![0, 0];
+        header.extend_from_slice(b"-lh0-");
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&820_000_000_u32.to_le_bytes());
+        header.extend_from_slice(&[0x20, 2, 0, 0, b'U']);
+        let mut crc_at = None;
+        for &(kind, contents) in ext {
+            header
+                .extend_from_slice(&(contents.len() as u16 + 3).to_le_bytes());
+            header.push(kind);
+            if kind == 0x00 {
+                crc_at = Some(header.len());
+            }
+            header.extend_from_slice(contents);
+        }
+        header.extend_from_slice(&[0, 0]);
+        let len = header.len() as u16;
+        header[..2].copy_from_slice(&len.to_le_bytes());
+        if let Some(pos) = crc_at {
+            let crc = Crc16::checksum(&header);
+            header[pos..pos + 2].copy_from_slice(&crc.to_le_bytes());
+        }
+        header
+    }
+
+    #[test]
+    fn test_header_crc() {
+        let several: &[(u8, &[u8])] = &[
+            (0x01, b"a.txt"),
+            (0x3F, b"comment"),
+            (0x00, &[0, 0]),
+            (0x50, &[0xA4, 0x81]),
+        ];
+        for ext in &[&[][..], &[(0x00, &[0, 0][..])], several] {
+            let header = level2(ext);
+            let parsed = LhaHeader::parse(&header).unwrap().unwrap();
+            assert_eq!(parsed.header_len, header.len());
+            assert_eq!(parsed.extended.len(), ext.len());
+            // 基本ヘッダのレベル以外の欄と、拡張ヘッダの中身
+            let mut fields = (2..20).chain(21..24).collect::<Vec<_>>();
+            let mut crc_at = None;
+            let mut pos = 27;
+            for &(kind, contents) in ext.iter() {
+                if kind == 0x00 {
+                    crc_at = Some(pos);
+                }
+                fields.extend(pos..pos + contents.len());
+                pos += contents.len() + 3;
+            }
+            let crc_at = match crc_at {
+                Some(pos) => pos,
+                // CRC の拡張ヘッダがなければ確かめない
+                None => {
+                    let mut input = header.clone();
+                    input[15] ^= 1;
+                    assert!(LhaHeader::parse(&input).is_ok());
+                    continue;
+                }
+            };
+            for i in fields {
+                let mut input = header.clone();
+                input[i] ^= 0x01;
+                let e = LhaHeader::parse(&input).unwrap_err();
+                assert_eq!(e.kind(), ErrorKind::HeaderChecksumMismatch);
+                let mut zeroed = input.clone();
+                zeroed[crc_at..crc_at + 2].fill(0);
+                let computed = Crc16::checksum(&zeroed);
+                let expected = u16_at(&input, crc_at).unwrap();
+                assert_eq!(
+                    e.checksums(),
+                    Some((u32::from(expected), u32::from(computed)))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        for archive in &[LEVEL0, LEVEL1, LEVEL2] {
+            let len = LhaHeader::parse(archive).unwrap().unwrap().header_len;
+            for l in 1..len {
+                assert_eq!(
+                    LhaHeader::parse(&archive[..l]).map_err(|e| e.kind()),
+                    Err(ErrorKind::UnexpectedEof)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_chain() {
+        // レベル 2 のヘッダを短くし、拡張ヘッダがはみ出す
+        let mut input = LEVEL2.to_vec();
+        input[0] -= 4;
+        assert_eq!(
+            LhaHeader::parse(&input).map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+        // 大きさ 1 の拡張ヘッダ
+        let mut input = LEVEL2.to_vec();
+        input[24..26].copy_from_slice(&[1, 0]);
+        assert_eq!(
+            LhaHeader::parse(&input).map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+        // 最初の拡張ヘッダの後で大きさ 0 が連鎖を終え、残りが読まれない
+        let mut input = LEVEL2.to_vec();
+        input[29..31].copy_from_slice(&[0, 0]);
+        input[27..29].copy_from_slice(&[0, 0]);
+        let len = usize::from(u16_at(&input, 0).unwrap());
+        let crc = Crc16::checksum(&input[..len]);
+        input[27..29].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            LhaHeader::parse(&input).map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+        // 拡張ヘッダが圧縮サイズより大きいレベル 1 のヘッダ
+        let mut input = LEVEL1.to_vec();
+        input[7..11].copy_from_slice(&[4, 0, 0, 0]);
+        input[1] = input[2..2 + usize::from(input[0])]
+            .iter()
+            .fold(0, |a, &b| a.wrapping_add(b));
+        assert_eq!(
+            LhaHeader::parse(&input).map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+        // 知らないレベル
+        let mut input = LEVEL0.to_vec();
+        input[20] = 3;
+        assert_eq!(
+            LhaHeader::parse(&input).map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+    }
+
+    #[test]
+    fn test_garbage() {
+        let mut seed = 0x9E37_79B9_u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        };
+        for archive in &[LEVEL0, LEVEL1, LEVEL2] {
+            for _ in 0..2000 {
+                let mut input = archive[..64].to_vec();
+                for _ in 0..4 {
+                    let i = usize::from(next()) % input.len();
+                    input[i] = next();
+                }
+                let _ = LhaHeader::parse(&input);
+            }
+        }
+    }
+}
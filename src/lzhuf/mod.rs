@@ -6,63 +6,313 @@
 //! <http://mozilla.org/MPL/2.0/>.
 #![cfg(feature = "lzhuf")]
 
+pub(crate) mod archive;
 pub(crate) mod decoder;
 pub(crate) mod encoder;
+pub(crate) mod header;
+pub(crate) mod path;
+pub(crate) mod writer;
 
-const LZSS_MIN_MATCH: usize = 3;
+use crate::core::str::FromStr;
+use crate::core::{fmt, iter};
+use crate::error::CompressionError;
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
-#[derive(Clone, Copy, Debug)]
+/// Compression method of an LHA archive member.
+///
+/// `-lh0-` to `-lh7-` are supported, and the LArc `-lzs-` and `-lz5-`;
+/// `-lh1-` to `-lh3-`, `-lzs-` and `-lz5-` only for decoding. Other
+/// methods, such as the PMarc `-pm0-` to `-pm2-`, are not; neither is
+/// `-lhd-`, which marks a directory rather than a method.
+///
+/// It parses from and displays as the identifier of the archive headers,
+/// such as `-lh5-`; parsing also takes `lh5` and ignores case.
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// assert_eq!("-lh5-".parse(), Ok(LzhufMethod::Lh5));
+/// assert_eq!("LH7".parse(), Ok(LzhufMethod::Lh7));
+/// assert_eq!(LzhufMethod::Lh6.to_string(), "-lh6-");
+///
+/// let err = "-pm2-".parse::<LzhufMethod>().unwrap_err();
+/// assert_eq!(err.to_string(), "method -pm2- not supported");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LzhufMethod {
+    /// Stored data without compression, which the encoder and the decoder
+    /// copy as is. The data has no end marker; decode with
+    /// `LzhufDecoder::with_original_size` to stop after the original size
+    /// given in the archive header.
+    Lh0,
+    /// LHarc 1.x method with an adaptive Huffman code and a 4 KiB window.
+    ///
+    /// It can only be decoded. The stream has no end marker, so the
+    /// padding of the last byte may decode to extra bytes; decode with
+    /// `LzhufDecoder::with_original_size`.
+    Lh1,
+    /// LHarc method between -lh1- and -lh4- with an 8 KiB window and
+    /// adaptive Huffman codes of both the symbols and the upper 7 bits of
+    /// the offsets. The offset code starts with one symbol and gains one
+    /// for each 64 bytes of output, until it covers the window.
+    ///
+    /// It can only be decoded. As with `Lh1`, decode with
+    /// `LzhufDecoder::with_original_size`.
+    Lh2,
+    /// LHarc method between -lh1- and -lh4- with an 8 KiB window and
+    /// blocks of static Huffman codes, each with its code of the symbols
+    /// and either its own code of the upper 7 bits of the offsets or a
+    /// fixed one.
+    ///
+    /// It can only be decoded. As with `Lh1`, decode with
+    /// `LzhufDecoder::with_original_size`.
+    Lh3,
     Lh4,
     Lh5,
     Lh6,
     Lh7,
+    /// LArc method with a 2 KiB window and no Huffman stage: a flag bit
+    /// before each byte or reference of 11 bits of position and 4 of
+    /// length, from 2 to 17.
+    ///
+    /// It can only be decoded. As with `Lh1`, decode with
+    /// `LzhufDecoder::with_original_size`.
+    Lzs,
+    /// LArc method with a 4 KiB window and no Huffman stage, in the byte
+    /// format of LZSS.C, with references of 3 to 18 bytes. The window
+    /// starts out filled with the pattern of `initial_window`.
+    ///
+    /// It can only be decoded. As with `Lh1`, decode with
+    /// `LzhufDecoder::with_original_size`.
+    Lz5,
 }
 
 impl LzhufMethod {
+    const ALL: [Self; 10] = [
+        LzhufMethod::Lh0,
+        LzhufMethod::Lh1,
+        LzhufMethod::Lh2,
+        LzhufMethod::Lh3,
+        LzhufMethod::Lh4,
+        LzhufMethod::Lh5,
+        LzhufMethod::Lh6,
+        LzhufMethod::Lh7,
+        LzhufMethod::Lzs,
+        LzhufMethod::Lz5,
+    ];
+
+    /// Returns the identifier of the archive headers, such as `-lh5-`.
+    pub fn id(self) -> &'static str {
+        match self {
+            LzhufMethod::Lh0 => "-lh0-",
+            LzhufMethod::Lh1 => "-lh1-",
+            LzhufMethod::Lh2 => "-lh2-",
+            LzhufMethod::Lh3 => "-lh3-",
+            LzhufMethod::Lh4 => "-lh4-",
+            LzhufMethod::Lh5 => "-lh5-",
+            LzhufMethod::Lh6 => "-lh6-",
+            LzhufMethod::Lh7 => "-lh7-",
+            LzhufMethod::Lzs => "-lzs-",
+            LzhufMethod::Lz5 => "-lz5-",
+        }
+    }
+
+    /// Returns the bytes the window of the decoder starts with, as
+    /// `LzssDecoder::with_dict` takes them, which references may reach
+    /// into from the start of the data: spaces of the window size for
+    /// -lh1- to -lh3- and -lzs-, and for -lz5- the ring buffer of LArc, with 13
+    /// copies of each byte value, the byte values up and down, 128 zeros
+    /// and spaces. The other methods start with an empty window.
+    pub fn initial_window(self) -> Vec<u8> {
+        let len = 1 << self.dictionary_bits();
+        match self {
+            LzhufMethod::Lh1
+            | LzhufMethod::Lh2
+            | LzhufMethod::Lh3
+            | LzhufMethod::Lzs => vec![b' '; len],
+            LzhufMethod::Lz5 => {
+                // LArc の窓の初期値で、書き込みの前の 18 バイトも空白
+                let mut ret = (0..=255_u8)
+                    .flat_map(|b| iter::repeat_n(b, 13))
+                    .chain(0..=255)
+                    .chain((0..=255).rev())
+                    .chain(iter::repeat_n(0, 128))
+                    .collect::<Vec<_>>();
+                ret.resize(len, b' ');
+                // 書き込みを始める位置から見た古い順に並べる
+                ret.rotate_left(len - self.max_match());
+                ret
+            }
+            _ => Vec::new(),
+        }
+    }
+
     fn dictionary_bits(self) -> usize {
         match self {
-            LzhufMethod::Lh4 => 12,
-            LzhufMethod::Lh5 => 13,
+            LzhufMethod::Lh0 => 0,
+            LzhufMethod::Lzs => 11,
+            LzhufMethod::Lh1 | LzhufMethod::Lh4 | LzhufMethod::Lz5 => 12,
+            LzhufMethod::Lh2 | LzhufMethod::Lh3 | LzhufMethod::Lh5 => 13,
             LzhufMethod::Lh6 => 15,
             LzhufMethod::Lh7 => 16,
         }
     }
 
+    /// Returns the length of the shortest reference, which the length
+    /// symbols count from.
+    fn min_match(self) -> usize {
+        match self {
+            LzhufMethod::Lzs => 2,
+            _ => 3,
+        }
+    }
+
+    /// Returns the length of the longest reference.
+    fn max_match(self) -> usize {
+        match self {
+            LzhufMethod::Lh1 => 60,
+            LzhufMethod::Lzs => 17,
+            LzhufMethod::Lz5 => 18,
+            _ => 256,
+        }
+    }
+
     fn offset_bits(self) -> usize {
         match self {
+            LzhufMethod::Lh0 => unreachable!("-lh0- has no offsets"),
+            LzhufMethod::Lh1 => unreachable!("-lh1- has a fixed offset table"),
+            LzhufMethod::Lh2 | LzhufMethod::Lh3 => {
+                unreachable!("{} has 7-bit offset codes", self)
+            }
+            LzhufMethod::Lzs | LzhufMethod::Lz5 => {
+                unreachable!("{} has no offset codes", self)
+            }
             LzhufMethod::Lh4 | LzhufMethod::Lh5 => 4,
             LzhufMethod::Lh6 | LzhufMethod::Lh7 => 5,
         }
     }
 }
 
+impl fmt::Display for LzhufMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl FromStr for LzhufMethod {
+    type Err = UnsupportedMethod;
+
+    fn from_str(s: &str) -> Result<Self, UnsupportedMethod> {
+        let id = match s.len() {
+            5 if s.starts_with('-') && s.ends_with('-') => &s[1..4],
+            _ => s,
+        };
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|m| m.id()[1..4].eq_ignore_ascii_case(id))
+            .ok_or_else(|| UnsupportedMethod {
+                method: s.to_string(),
+            })
+    }
+}
+
+/// Error of parsing an `LzhufMethod`, with the method given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsupportedMethod {
+    method: String,
+}
+
+impl UnsupportedMethod {
+    /// Returns the method that was not recognised, as given.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+}
+
+impl fmt::Display for UnsupportedMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "method {} not supported", self.method)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for UnsupportedMethod {}
+
+impl From<UnsupportedMethod> for CompressionError {
+    fn from(_: UnsupportedMethod) -> Self {
+        CompressionError::Unsupported
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::action::Action;
-    use crate::lzhuf::decoder::LzhufDecoder;
-    use crate::lzhuf::encoder::LzhufEncoder;
+    use crate::checksum::Crc16;
+    use crate::crc32::crc32;
+    use crate::error::{CompressionError, ErrorKind};
+    use crate::lzhuf::decoder::{LzhufCodeDecoder, LzhufDecoder};
+    use crate::lzhuf::encoder::{LzhufCodeEncoder, LzhufEncoder};
+    use crate::lzss::encoder::ParseMode;
+    use crate::lzss::LzssCode;
     use crate::traits::decoder::DecodeExt;
     use crate::traits::encoder::EncodeExt;
     #[cfg(not(feature = "std"))]
+    #[allow(unused_imports)]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
-    use rand::distributions::Standard;
-    use rand::{thread_rng, Rng};
+    use rand::distr::StandardUniform;
+    use rand::{rng, RngExt};
+
+    #[test]
+    fn test_method_id() {
+        for &m in &LzhufMethod::ALL {
+            let id = m.to_string();
+            assert_eq!(id.parse(), Ok(m));
+            assert_eq!(id.to_uppercase().parse(), Ok(m));
+            assert_eq!(id.trim_matches('-').parse(), Ok(m));
+        }
+        for &id in &["-lh9-", "-lhd-", "-pm2-", "-lz4-", "lh", "-lh5", "", "--"]
+        {
+            let err = id.parse::<LzhufMethod>().unwrap_err();
+            assert_eq!(err.method(), id);
+            assert_eq!(
+                CompressionError::from(err),
+                CompressionError::Unsupported
+            );
+        }
+        assert_eq!(
+            "-pm2-".parse::<LzhufMethod>().unwrap_err().to_string(),
+            "method -pm2- not supported"
+        );
+    }
 
     fn check(testarray: &[u8]) {
         let method = LzhufMethod::Lh7;
         let encoded = testarray
             .to_vec()
             .encode(&mut LzhufEncoder::new(&method), Action::Finish)
-            .collect::<Result<Vec<_>, _>>();
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
         let decoded = encoded
-            .unwrap()
+            .iter()
+            .cloned()
             .decode(&mut LzhufDecoder::new(&method))
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
 
         assert_eq!(testarray.to_vec(), decoded);
+        assert_eq!(
+            LzhufDecoder::new(&method).decode_slice(&encoded),
+            Ok((decoded, encoded.len()))
+        );
     }
 
     #[test]
@@ -92,34 +342,49 @@ mod tests {
 
     #[test]
     fn test_multiblocks1() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks2() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks3() {
-        let rng = thread_rng();
+        let rng = rng();
 
         check(
             &(rng
-                .sample_iter(&Standard)
+                .sample_iter(StandardUniform)
                 .take(0xF_FFFF)
                 .collect::<Vec<_>>()),
         );
     }
 
     fn test_rand_with_len(len: usize) {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(len).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(len)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
@@ -161,4 +426,805 @@ mod tests {
     fn test_multiblocks11() {
         check(include_bytes!("../../data/sample7.ref"));
     }
+
+    fn decode_lh1(input: &[u8], len: usize) -> Vec<u8> {
+        input
+            .iter()
+            .cloned()
+            .decode(&mut LzhufDecoder::with_original_size(
+                &LzhufMethod::Lh1,
+                len,
+            ))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lh1() {
+        // LHa for UNIX の -lh1- 符号化処理を写したもので圧縮した本体
+        let expected = include_bytes!("../../data/sample3.ref");
+        let encoded = include_bytes!("../../data/sample3.lh1");
+        let decoded = decode_lh1(encoded, expected.len());
+        assert_eq!(decoded, &expected[..]);
+        // 書庫のヘッダに記録されるはずの値と照合する
+        assert_eq!(
+            (decoded.len(), Crc16::checksum(&decoded)),
+            (120_244, 0x3221)
+        );
+    }
+
+    #[test]
+    fn test_lh1_initial_dictionary() {
+        // 先頭の 16 個の空白は初期の辞書を参照する
+        let encoded = [
+            0x99, 0x00, 0x7d, 0x3e, 0x3f, 0x8c, 0x2f, 0xde, 0xe2, 0x48, 0x12,
+        ];
+        let expected = b"                hello,    hello";
+        assert_eq!(decode_lh1(&encoded, expected.len()), &expected[..]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_lh1_encoder() {
+        let _ = LzhufEncoder::new(&LzhufMethod::Lh1);
+    }
+
+    fn decode_with_size(
+        method: LzhufMethod,
+        input: &[u8],
+        len: usize,
+    ) -> Vec<u8> {
+        input
+            .iter()
+            .cloned()
+            .decode(&mut LzhufDecoder::with_original_size(&method, len))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    // data/words.lh2 と data/words.lh3 の元のデータで、単語の列の所々に
+    // 少し前の 300 バイトの繰り返しを挟む
+    fn words(n: usize) -> Vec<u8> {
+        const WORDS: [&[u8]; 16] = [
+            b"rust",
+            b"lha",
+            b"huffman",
+            b"window",
+            b"archive",
+            b"node",
+            b"tree",
+            b"code",
+            b"offset",
+            b"length",
+            b"block",
+            b"symbol",
+            b"adaptive",
+            b"static",
+            b"bit",
+            b"byte",
+        ];
+        let mut seed = 0x2545_F491_u32;
+        let mut ret = Vec::new();
+        for _ in 0..n {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let r = seed as usize;
+            if r.is_multiple_of(97) && ret.len() > 1000 {
+                let start = ret.len() - 1000 + (r >> 8) % 700;
+                ret.extend_from_within(start..start + 300);
+            } else {
+                ret.extend_from_slice(WORDS[(r >> 8) % 16]);
+                ret.push(b' ');
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn test_lh2() {
+        // LHa for UNIX の dhuf.c を写したもので圧縮した本体で、位置の木は
+        // 窓を埋めるまで記号が増え、重みが 0x8000 に達して作り直される
+        let expected = words(80_000);
+        let encoded = include_bytes!("../../data/words.lh2");
+        let decoded =
+            decode_with_size(LzhufMethod::Lh2, encoded, expected.len());
+        assert_eq!(decoded, expected);
+        assert_eq!(
+            (decoded.len(), Crc16::checksum(&decoded)),
+            (741_715, 0x025C)
+        );
+    }
+
+    #[test]
+    fn test_lh2_initial_window() {
+        // 初期の窓の空白への参照と、追加ビットで長さを足す 256 バイトの参照
+        let encoded = [0xC6, 0xA0, 0xB0, 0xB7, 0x1B, 0x80, 0x10];
+        let mut expected = b"     ".to_vec();
+        for _ in 0..129 {
+            expected.extend_from_slice(b"hi");
+        }
+        let decoded =
+            decode_with_size(LzhufMethod::Lh2, &encoded, expected.len());
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_lh3() {
+        // LHa for UNIX の shuf.c を写したもので圧縮した本体で、ブロックごと
+        // に位置の符号を書くものと固定の表を使うものが交互に並ぶ
+        let expected = words(80_000);
+        let encoded = include_bytes!("../../data/words.lh3");
+        let decoded =
+            decode_with_size(LzhufMethod::Lh3, encoded, expected.len());
+        assert_eq!(decoded, expected);
+        assert_eq!(Crc16::checksum(&decoded), 0x025C);
+    }
+
+    #[test]
+    fn test_lh3_single_codes() {
+        // 固定の位置の表でリテラルだけのブロックと、記号も位置も 1 つだけで
+        // 符号のないブロック
+        let encoded = [
+            0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x46, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x16, 0x00, 0x0a, 0x84, 0x21,
+            0x00, 0x88, 0x88, 0x00, 0x82, 0x08, 0x20, 0x82, 0x08, 0x20, 0x82,
+        ];
+        let expected = b"abc".repeat(11);
+        let method = LzhufMethod::Lh3;
+        assert_eq!(decode_with_size(method, &encoded, 33), expected);
+        // ブロックの後の詰め物はブロックの頭に足りないので、終わりと読む
+        assert_eq!(
+            encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>(),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn test_lh3_empty_offset_code() {
+        // 位置の符号長がすべて 0 のブロックの、長さ 3 の参照
+        let mut fields = vec![(1, 16)];
+        fields.extend(iter::repeat_n((0, 1), 256));
+        fields.extend_from_slice(&[(1, 1), (0, 4)]);
+        fields.extend(iter::repeat_n((0, 1), 29));
+        fields.push((1, 1));
+        fields.extend(iter::repeat_n((0, 4), 128));
+        fields.push((0, 1));
+        let encoded = pack_bits(&fields);
+        assert_eq!(
+            encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&LzhufMethod::Lh3))
+                .collect::<Result<Vec<_>, _>>(),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_lh2_encoder() {
+        let _ = LzhufEncoder::new(&LzhufMethod::Lh2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_lh3_encoder() {
+        let _ = LzhufCodeEncoder::new(&LzhufMethod::Lh3);
+    }
+
+    #[test]
+    fn test_lz5_initial_window() {
+        // LArc の窓の初期値の各部分を、リングの位置で参照する
+        let refs = [
+            (13 * 0x41, 13),
+            (13 * 256 + 0x30, 10),
+            (13 * 256 + 256, 3),
+            (13 * 256 + 512, 4),
+            (13 * 256 + 512 + 128, 3),
+            (13 * 256 + 0xFE, 3),
+            (0, 5),
+            (4095, 3),
+        ];
+        let mut encoded = vec![0];
+        for &(index, len) in &refs {
+            encoded.push(index as u8);
+            encoded.push(((index >> 4) & 0xF0) as u8 | (len - 3) as u8);
+        }
+        let mut expected = vec![b'A'; 13];
+        expected.extend_from_slice(b"0123456789");
+        expected.extend_from_slice(&[0xFF, 0xFE, 0xFD, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"   \xFE\xFF\xFF");
+        // 18 バイト書くとリングの先頭に戻り、初期値を書き換えている
+        expected.extend_from_slice(b"56789456");
+        let method = LzhufMethod::Lz5;
+        assert_eq!(
+            decode_with_size(method, &encoded, expected.len()),
+            expected
+        );
+        let window = method.initial_window();
+        assert_eq!(window.len(), 4096);
+        assert_eq!(
+            (&window[..18], &window[18..31]),
+            (&[b' '; 18][..], &[0; 13][..])
+        );
+    }
+
+    #[test]
+    fn test_lzs() {
+        // 窓の初めの 17 個の空白への参照と、リテラルの x
+        let encoded = [0x00, 0x0F, 0xBC, 0x00];
+        let expected = b"                 x";
+        let method = LzhufMethod::Lzs;
+        assert_eq!(decode_with_size(method, &encoded, 18), &expected[..]);
+        // 最後のバイトの詰め物は符号に足りないので、終わりと読む
+        assert_eq!(
+            encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>(),
+            Ok(expected.to_vec())
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_larc_encoder() {
+        let _ = LzhufEncoder::new(&LzhufMethod::Lz5);
+    }
+
+    #[test]
+    fn test_lh0() {
+        let method = LzhufMethod::Lh0;
+        for data in &[&b""[..], b"a", include_bytes!("../../data/sample3.ref")]
+        {
+            let encoded = data
+                .iter()
+                .cloned()
+                .encode(&mut LzhufEncoder::new(&method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(encoded.as_deref(), Ok(*data));
+            assert_eq!(
+                LzhufEncoder::new(&method)
+                    .compress_to_vec(data, Action::Finish),
+                Ok(data.to_vec())
+            );
+            let decoded = data
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded.as_deref(), Ok(*data));
+
+            // 後ろに続く次の書庫の要素は読まない
+            let mut input = data.to_vec();
+            input.extend(b"next");
+            assert_eq!(
+                LzhufDecoder::with_original_size(&method, data.len())
+                    .decode_slice(&input),
+                Ok((data.to_vec(), data.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_original_size() {
+        let data = include_bytes!("../../data/sample3.ref");
+        let encoded = data
+            .iter()
+            .cloned()
+            .encode(&mut LzhufEncoder::new(&LzhufMethod::Lh5), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let decode = |method, input: &[u8], size| {
+            input
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::with_original_size(method, size))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let lh5 = &LzhufMethod::Lh5;
+        assert_eq!(decode(lh5, &encoded, data.len()), Ok(data.to_vec()));
+        assert_eq!(decode(lh5, &encoded, 100), Ok(data[..100].to_vec()));
+        assert_eq!(decode(lh5, &encoded, 0), Ok(Vec::new()));
+        assert_eq!(
+            decode(lh5, &encoded, data.len() + 1),
+            Err(CompressionError::UnexpectedEof)
+        );
+        let lh0 = &LzhufMethod::Lh0;
+        assert_eq!(decode(lh0, b"", 0), Ok(Vec::new()));
+        assert_eq!(decode(lh0, b"abc", 0), Ok(Vec::new()));
+        assert_eq!(
+            decode(lh0, b"abc", 4).map_err(|e| e.kind()),
+            Err(ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "-lh0- has no codes")]
+    fn test_lh0_code_encoder() {
+        let _ = LzhufCodeEncoder::new(&LzhufMethod::Lh0);
+    }
+
+    fn decode_raw(input: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        input
+            .iter()
+            .cloned()
+            .decode(&mut LzhufDecoder::new(&LzhufMethod::Lh7))
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    #[test]
+    fn test_len_run_too_long() {
+        // ブロック長 1, 長さ表 3 個, 最初の長さが 7 + 1 の連続
+        let mut input = vec![0x00, 0x01, 0b0001_1111];
+        input.extend(vec![0xFF; 64]);
+        let err = decode_raw(&input).unwrap_err();
+        assert_eq!(err, CompressionError::DataError);
+        assert_eq!(err.bit_offset(), Some(24));
+    }
+
+    fn pack_bits(fields: &[(u32, usize)]) -> Vec<u8> {
+        let bits = fields
+            .iter()
+            .flat_map(|&(v, n)| (0..n).rev().map(move |i| (v >> i) & 1 == 1))
+            .collect::<Vec<_>>();
+        let mut ret = bits
+            .chunks(8)
+            .map(|c| {
+                c.iter()
+                    .chain(vec![false; 8 - c.len()].iter())
+                    .fold(0, |a, &b| (a << 1) | b as u8)
+            })
+            .collect::<Vec<u8>>();
+        ret.extend(vec![0; 8]);
+        ret
+    }
+
+    #[test]
+    fn test_lh6_fixture() {
+        // 表がどれも 1 記号だけのブロックを並べ、'x', 'a', 長さ 256 で
+        // 距離 1 の参照を 80 個、最後に 20482 バイト前への長さ 3 の参照
+        let block = |count, symbol, offset| {
+            vec![(count, 16), (0, 5), (0, 5), (0, 9), (symbol, 9), (0, 5)]
+                .into_iter()
+                .chain(Some((offset, 5)))
+        };
+        let input = pack_bits(
+            &block(1, u32::from(b'x'), 0)
+                .chain(block(1, u32::from(b'a'), 0))
+                .chain(block(80, 256 + 253, 0))
+                .chain(block(1, 256, 15))
+                .chain(Some((20481 - (1 << 14), 14)))
+                .collect::<Vec<_>>(),
+        );
+        let mut expected = b"x".to_vec();
+        expected.extend(vec![b'a'; 2 + 80 * 256 - 1]);
+        expected.extend(b"xaa");
+        let decode = |method| {
+            input
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        assert_eq!(decode(LzhufMethod::Lh6), Ok(expected.clone()));
+        assert_eq!(decode(LzhufMethod::Lh7), Ok(expected));
+    }
+
+    #[test]
+    fn test_offset_out_of_window() {
+        // 'x' のブロックに続いて、距離の表が窓より遠い記号を持つブロック
+        let decode = |method, bits, offsets: &[(u32, usize)]| {
+            let input = pack_bits(
+                &[
+                    (1, 16),
+                    (0, 5),
+                    (0, 5),
+                    (0, 9),
+                    (u32::from(b'x'), 9),
+                    (0, bits),
+                    (0, bits),
+                    (1, 16),
+                    (0, 5),
+                    (0, 5),
+                    (0, 9),
+                    (256, 9),
+                ]
+                .iter()
+                .chain(offsets)
+                .chain(&[(0, 16), (0, 16)])
+                .cloned()
+                .collect::<Vec<_>>(),
+            );
+            input
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.kind())
+        };
+        assert_eq!(
+            decode(LzhufMethod::Lh5, 4, &[(0, 4), (0, 4)]),
+            Ok(b"xxxx".to_vec())
+        );
+        for &(method, bits, max) in
+            &[(LzhufMethod::Lh5, 4, 13), (LzhufMethod::Lh7, 5, 16)]
+        {
+            for offset in max + 1..1 << bits {
+                assert_eq!(
+                    decode(method, bits, &[(0, bits), (offset, bits)]),
+                    Err(ErrorKind::DataError)
+                );
+            }
+        }
+        // 読み込んだ表の 15 番目 (-lh5- の窓の外) に符号がある
+        let mut table = vec![(15, 4), (1, 3)];
+        table.extend(vec![(0, 3); 13]);
+        table.push((1, 3));
+        assert_eq!(
+            decode(LzhufMethod::Lh5, 4, &table),
+            Err(ErrorKind::DataError)
+        );
+    }
+
+    #[test]
+    fn test_symbol_out_of_range() {
+        let decode = |fields: &[(u32, usize)]| {
+            pack_bits(fields)
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&LzhufMethod::Lh5))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.kind())
+        };
+        // 'x' に続いて、表が 1 記号だけで距離 1 の参照のブロック
+        let single = |symbol| {
+            decode(&[
+                (1, 16),
+                (0, 5),
+                (0, 5),
+                (0, 9),
+                (u32::from(b'x'), 9),
+                (0, 4),
+                (0, 4),
+                (1, 16),
+                (0, 5),
+                (0, 5),
+                (0, 9),
+                (symbol, 9),
+                (0, 4),
+                (0, 4),
+                (0, 16),
+            ])
+        };
+        assert_eq!(single(509), Ok(vec![b'x'; 257]));
+        assert_eq!(single(510), Err(ErrorKind::DataError));
+        assert_eq!(single(511), Err(ErrorKind::DataError));
+        // 長さ表は符号長 2 の 4 記号 (0 の連続 2 種と符号長 1)、
+        // 511 記号の表で 'x' と 510 に符号長 1
+        assert_eq!(
+            decode(&[
+                (1, 16),
+                (4, 5),
+                (2, 3),
+                (2, 3),
+                (2, 3),
+                (0, 2),
+                (2, 3),
+                (511, 9),
+                (0b10, 2),
+                (100, 9),
+                (0b11, 2),
+                (0b10, 2),
+                (369, 9),
+                (0b11, 2),
+                (0, 4),
+                (0, 4),
+                (0, 1),
+                (0, 16),
+            ]),
+            Err(ErrorKind::DataError)
+        );
+    }
+
+    #[test]
+    fn test_lh6_window() {
+        let input = include_bytes!("../../data/sample1.ref");
+        // 8 KiB より遠く 32 KiB より近い繰り返し
+        let mut data = input[..0x5000].to_vec();
+        data.extend_from_slice(&input[..0x5000]);
+        for method in &[LzhufMethod::Lh5, LzhufMethod::Lh6] {
+            let encoded = data
+                .iter()
+                .cloned()
+                .encode(&mut LzhufEncoder::new(method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let codes = encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufCodeDecoder::new(method))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let far = codes.iter().any(|c| match *c {
+                LzssCode::Reference { pos, .. } => pos >= 0x2000,
+                LzssCode::Symbol(_) => false,
+            });
+            assert_eq!(far, matches!(method, LzhufMethod::Lh6));
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(method))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(data.clone()));
+        }
+    }
+
+    #[test]
+    fn test_oversubscribed_len_tree() {
+        // ブロック長 1, 長さ表 3 個がすべて長さ 1
+        let input = pack_bits(&[(1, 16), (3, 5), (1, 3), (1, 3), (1, 3)]);
+        let err = decode_raw(&input).unwrap_err();
+        assert_eq!(err, CompressionError::DataError);
+        assert_eq!(err.bit_offset(), Some(30));
+    }
+
+    #[test]
+    fn test_incomplete_symbol_tree() {
+        // 長さ表は記号 3, 4 が長さ 1, 記号表は長さ 1 と 2 の 2 個
+        let input = pack_bits(&[
+            (1, 16),
+            (5, 5),
+            (0, 9),
+            (0, 2),
+            (1, 3),
+            (1, 3),
+            (2, 9),
+            (0, 1),
+            (1, 1),
+        ]);
+        let err = decode_raw(&input).unwrap_err();
+        assert_eq!(err, CompressionError::DataError);
+        assert_eq!(err.bit_offset(), Some(49));
+    }
+
+    #[test]
+    fn test_symbol_zero_run_overrun() {
+        // 長さ表は 0, 1, 2 の符号長 1, 2, 2 で、記号表 510 個に 0 を 509 個
+        let table = |last: &[(u32, usize)]| {
+            let mut fields =
+                vec![(1, 16), (3, 5), (1, 3), (2, 3), (2, 3), (510, 9)];
+            fields.extend_from_slice(&[(3, 2), (489, 9)]);
+            fields.extend_from_slice(last);
+            decode_raw(&pack_bits(&fields)).unwrap_err()
+        };
+        // 0 の連続 3 個と 20 + 511 個が、残りの 1 個を越える
+        for &(last, offset) in &[
+            ([(2, 2), (0, 4)], 56),
+            ([(3, 2), (0, 9)], 61),
+            ([(3, 2), (511, 9)], 61),
+        ] {
+            let err = table(&last);
+            assert_eq!(err, CompressionError::DataError);
+            assert_eq!(err.bit_offset(), Some(offset));
+        }
+    }
+
+    #[test]
+    fn test_len_run_truncated() {
+        let input = vec![0x00, 0x01, 0b0001_1111, 0xFF];
+        let err = decode_raw(&input).unwrap_err();
+        assert_eq!(err, CompressionError::UnexpectedEof);
+        assert_eq!(err.bit_offset(), Some(32));
+    }
+
+    #[test]
+    fn test_golden_digests() {
+        // 符号表の構築が決定的で、出力が環境によらず同じであることを確かめる
+        const SAMPLE1: &[u8] = include_bytes!("../../data/sample1.ref");
+        const SAMPLE2: &[u8] = include_bytes!("../../data/sample2.ref");
+        const SAMPLE3: &[u8] = include_bytes!("../../data/sample3.ref");
+        const SAMPLE4: &[u8] = include_bytes!("../../data/sample4.ref");
+        let cases: [(&[u8], LzhufMethod, usize, u32); 11] = [
+            (SAMPLE1, LzhufMethod::Lh5, 40984, 0x4825_a37b),
+            (SAMPLE1, LzhufMethod::Lh7, 37819, 0x2d76_3151),
+            (SAMPLE1, LzhufMethod::Lh6, 38154, 0x7849_a950),
+            (SAMPLE2, LzhufMethod::Lh5, 92947, 0x4786_9b14),
+            (SAMPLE2, LzhufMethod::Lh7, 85317, 0x8253_b8ca),
+            (SAMPLE2, LzhufMethod::Lh6, 87059, 0xabfe_5592),
+            (SAMPLE3, LzhufMethod::Lh5, 373, 0xfa0f_3100),
+            (SAMPLE3, LzhufMethod::Lh7, 374, 0x8b02_260d),
+            (SAMPLE4, LzhufMethod::Lh5, 81436, 0xcb05_b2f4),
+            (SAMPLE4, LzhufMethod::Lh7, 73281, 0x971f_3ed2),
+            (SAMPLE4, LzhufMethod::Lh6, 74755, 0x006e_a17d),
+        ];
+        for &(input, method, len, digest) in &cases {
+            let encoded = input
+                .to_vec()
+                .encode(&mut LzhufEncoder::new(&method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!((encoded.len(), crc32(&encoded)), (len, digest));
+        }
+    }
+
+    #[test]
+    fn test_optimal_parse() {
+        const SAMPLE1: &[u8] = include_bytes!("../../data/sample1.ref");
+        const SAMPLE2: &[u8] = include_bytes!("../../data/sample2.ref");
+        const SAMPLE3: &[u8] = include_bytes!("../../data/sample3.ref");
+        let cases: [(&[u8], LzhufMethod, usize); 4] = [
+            (SAMPLE1, LzhufMethod::Lh5, 40984),
+            (SAMPLE2, LzhufMethod::Lh7, 85317),
+            (SAMPLE3, LzhufMethod::Lh5, 373),
+            (SAMPLE3, LzhufMethod::Lh7, 374),
+        ];
+        for &(input, method, lazy_len) in &cases {
+            let mut encoder = LzhufEncoder::new(&method);
+            encoder.set_parse_mode(ParseMode::Optimal);
+            let encoded = input
+                .to_vec()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            // 既定の遅延評価より大きくならない
+            assert!(encoded.len() <= lazy_len);
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(input.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_code_round_trip() {
+        let input = include_bytes!("../../data/sample1.ref");
+        for method in &[
+            LzhufMethod::Lh4,
+            LzhufMethod::Lh5,
+            LzhufMethod::Lh6,
+            LzhufMethod::Lh7,
+        ] {
+            let encoded = input
+                .to_vec()
+                .encode(&mut LzhufEncoder::new(method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let codes = encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufCodeDecoder::new(method))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let reencoded = codes
+                .iter()
+                .cloned()
+                .encode(&mut LzhufCodeEncoder::new(method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(reencoded, Ok(encoded));
+        }
+    }
+
+    #[test]
+    fn test_code_original_size() {
+        // -lh1- は終わりの印がないので、元の大きさで符号列を止める
+        let expected = include_bytes!("../../data/sample3.ref");
+        let encoded = include_bytes!("../../data/sample3.lh1");
+        let method = LzhufMethod::Lh1;
+        let codes = |size| {
+            encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufCodeDecoder::with_original_size(
+                    &method, size,
+                ))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let len = |c: &LzssCode| match *c {
+            LzssCode::Symbol(_) => 1,
+            LzssCode::Reference { len, .. } => len,
+        };
+        let all = codes(expected.len()).unwrap();
+        assert_eq!(all.iter().map(len).sum::<usize>(), expected.len());
+        assert_eq!(
+            codes(expected.len() + 1),
+            Err(CompressionError::UnexpectedEof)
+        );
+        // 参照の途中で終わる大きさは符号と合わない
+        let first = all
+            .iter()
+            .position(|c| matches!(c, LzssCode::Reference { .. }))
+            .unwrap();
+        let before = all[..first].iter().map(len).sum::<usize>();
+        assert_eq!(codes(before), Ok(all[..first].to_vec()));
+        assert_eq!(codes(before + 1), Err(CompressionError::DataError));
+    }
+
+    #[test]
+    fn test_code_encoder_limits() {
+        let method = LzhufMethod::Lh5;
+        let encode = |codes: &[LzssCode]| {
+            codes
+                .iter()
+                .cloned()
+                .encode(&mut LzhufCodeEncoder::new(&method), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|e| {
+                    e.iter()
+                        .cloned()
+                        .decode(&mut LzhufDecoder::new(&method))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+        };
+        let sym = LzssCode::Symbol(b'a');
+        let reference = |len, pos| LzssCode::Reference { len, pos };
+        assert_eq!(
+            encode(&[reference(3, 0)]),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(
+            encode(&[sym, reference(2, 0)]),
+            Err(CompressionError::DataError)
+        );
+        assert_eq!(encode(&[sym, reference(256, 0)]), Ok(vec![b'a'; 257]));
+        assert_eq!(
+            encode(&[sym, reference(257, 0)]),
+            Err(CompressionError::DataError)
+        );
+        // -lh5- の窓は 8 KiB
+        let mut codes = vec![sym; 0x2001];
+        codes.push(reference(3, 0x1FFF));
+        assert_eq!(encode(&codes), Ok(vec![b'a'; 0x2004]));
+        codes.pop();
+        codes.push(reference(3, 0x2000));
+        assert_eq!(encode(&codes), Err(CompressionError::DataError));
+    }
+
+    #[test]
+    fn test_long_runs() {
+        let method = LzhufMethod::Lh7;
+        let mut input = vec![b'a'; 100_000];
+        input.extend(b"ab".iter().cycle().take(50_000));
+        input.extend(vec![b'z'; 1000]);
+        for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast] {
+            let mut encoder = LzhufEncoder::new(&method);
+            encoder.set_parse_mode(mode);
+            let encoded = input
+                .iter()
+                .cloned()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let codes = encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufCodeDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let longest = codes
+                .iter()
+                .map(|c| match *c {
+                    LzssCode::Reference { len, .. } => len,
+                    LzssCode::Symbol(_) => 0,
+                })
+                .max();
+            assert_eq!(longest, Some(256));
+            let decoded = encoded
+                .iter()
+                .cloned()
+                .decode(&mut LzhufDecoder::new(&method))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(input.clone()));
+        }
+    }
 }
@@ -0,0 +1,236 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+
+use crate::core::str;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+/// The path of an archive member, split into its components.
+///
+/// Paths are split at `/`, which the reader puts for the 0xFF of extended
+/// headers, and at `\`, the separator of MS-DOS archives. Names that are
+/// not UTF-8 are taken as Shift_JIS, whose second bytes may be 0x5C, so a
+/// `\` right after a first byte of Shift_JIS does not split them. The
+/// components are the bytes as stored.
+///
+/// A path from an archive may be absolute or climb out of the directory it
+/// is extracted to; `sanitized` drops the parts that do.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// let path = LhaPath::new(b"..\\..\\evil.txt");
+/// assert_eq!(path.components(), &[&b".."[..], b"..", b"evil.txt"]);
+/// assert!(!path.is_safe());
+/// assert_eq!(path.sanitized().to_string_lossy(), "evil.txt");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LhaPath {
+    absolute: bool,
+    components: Vec<Vec<u8>>,
+}
+
+impl LhaPath {
+    /// Splits the path `name` as stored in an archive header.
+    pub fn new(name: &[u8]) -> Self {
+        let sjis = str::from_utf8(name).is_err();
+        let mut absolute = false;
+        let mut components = Vec::new();
+        let mut current = Vec::new();
+        let mut bytes = name.iter().cloned();
+        while let Some(b) = bytes.next() {
+            match b {
+                b'/' | b'\\' => {
+                    if components.is_empty() && current.is_empty() {
+                        absolute = true;
+                    }
+                    components.push(current);
+                    current = Vec::new();
+                }
+                // 2 バイト文字はまとめて読み、2 バイト目を区切りとしない
+                0x81..=0x9F | 0xE0..=0xFC if sjis => {
+                    current.push(b);
+                    current.extend(bytes.next());
+                }
+                _ => current.push(b),
+            }
+        }
+        components.push(current);
+        // C:\ のようなドライブ名
+        if let [drive, b':', ..] = components[0][..] {
+            if drive.is_ascii_alphabetic() {
+                absolute = true;
+                components[0].drain(..2);
+            }
+        }
+        components.retain(|c| !c.is_empty());
+        Self {
+            absolute,
+            components,
+        }
+    }
+
+    /// Returns the components, without empty ones.
+    pub fn components(&self) -> &[Vec<u8>] {
+        &self.components
+    }
+
+    /// Returns whether the path starts at a root, with a separator or a
+    /// drive such as `C:`.
+    pub fn is_absolute(&self) -> bool {
+        self.absolute
+    }
+
+    /// Returns whether the path stays under the directory it is extracted
+    /// to: it is relative and has no `.` or `..` components.
+    pub fn is_safe(&self) -> bool {
+        !self.absolute && self.components.iter().all(|c| !is_dot(c))
+    }
+
+    /// Returns the path without its root and its `.` and `..` components,
+    /// which is under the directory it is extracted to. It may have no
+    /// components left.
+    pub fn sanitized(&self) -> Self {
+        Self {
+            absolute: false,
+            components: self
+                .components
+                .iter()
+                .filter(|c| !is_dot(c))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Returns the path with its components joined by `/`, and bytes that
+    /// are not UTF-8 replaced with U+FFFD.
+    pub fn to_string_lossy(&self) -> String {
+        let mut ret = String::new();
+        if self.absolute {
+            ret.push('/');
+        }
+        for (i, c) in self.components.iter().enumerate() {
+            if i != 0 {
+                ret.push('/');
+            }
+            ret.push_str(&String::from_utf8_lossy(c));
+        }
+        ret
+    }
+
+    /// Returns the path of the sanitized components, relative to the
+    /// directory the member is extracted to. Components that are not UTF-8
+    /// are converted as in `to_string_lossy`.
+    #[cfg(feature = "std")]
+    pub fn to_path_buf(&self) -> PathBuf {
+        self.sanitized()
+            .components
+            .iter()
+            .map(|c| String::from_utf8_lossy(c))
+            .map(Cow::into_owned)
+            .collect()
+    }
+}
+
+fn is_dot(component: &[u8]) -> bool {
+    component == b"." || component == b".."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(feature = "std"))]
+    use alloc::vec;
+
+    fn components(name: &[u8]) -> Vec<Vec<u8>> {
+        LhaPath::new(name).components().to_vec()
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(components(b"a/b\\c.txt"), vec![&b"a"[..], b"b", b"c.txt"]);
+        assert_eq!(components(b"dir//x/"), vec![&b"dir"[..], b"x"]);
+        assert!(components(b"").is_empty());
+        assert!(LhaPath::new(b"DOCS\\SAMPLE3.TXT").is_safe());
+    }
+
+    #[test]
+    fn test_traversal() {
+        let path = LhaPath::new(b"..\\..\\evil");
+        assert!(!path.is_absolute());
+        assert!(!path.is_safe());
+        assert_eq!(path.sanitized().components(), &[b"evil".to_vec()]);
+        let path = LhaPath::new(b"a/./b/../../c");
+        assert_eq!(path.sanitized().to_string_lossy(), "a/b/c");
+        assert!(LhaPath::new(b"..").sanitized().components().is_empty());
+        // .. を含む名前は親のディレクトリではない
+        assert!(LhaPath::new(b"..evil/x..").is_safe());
+    }
+
+    #[test]
+    fn test_absolute() {
+        for &name in &[
+            &b"C:\\Windows\\System32\\evil.dll"[..],
+            b"c:Windows\\System32\\evil.dll",
+            b"\\Windows\\System32\\evil.dll",
+            b"/Windows/System32/evil.dll",
+            b"\\\\server\\Windows\\System32\\evil.dll",
+        ] {
+            let path = LhaPath::new(name);
+            assert!(path.is_absolute() && !path.is_safe(), "{:?}", name);
+            let sanitized = path.sanitized();
+            assert!(sanitized.is_safe());
+            assert!(sanitized
+                .to_string_lossy()
+                .ends_with("Windows/System32/evil.dll"));
+        }
+        assert_eq!(
+            LhaPath::new(b"/etc/passwd").to_string_lossy(),
+            "/etc/passwd"
+        );
+    }
+
+    #[test]
+    fn test_shift_jis() {
+        // 「表示\ソフト.txt」の 表 と ソ の 2 バイト目は 0x5C
+        let name = b"\x95\x5C\x8E\xA6\\\x83\x5C\x83\x74\x83\x67.txt";
+        let path = LhaPath::new(name);
+        assert_eq!(
+            path.components(),
+            &[
+                b"\x95\x5C\x8E\xA6".to_vec(),
+                b"\x83\x5C\x83\x74\x83\x67.txt".to_vec()
+            ]
+        );
+        assert!(path.is_safe());
+        assert_eq!(
+            path.to_string_lossy(),
+            "\u{FFFD}\\\u{FFFD}\u{FFFD}/\u{FFFD}\\\u{FFFD}t\u{FFFD}g.txt"
+        );
+        // UTF-8 の名前は Shift_JIS として読まない
+        let path = LhaPath::new("ソフト\\表.txt".as_bytes());
+        assert_eq!(path.to_string_lossy(), "ソフト/表.txt");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_path_buf() {
+        let path = LhaPath::new(b"C:\\docs\\..\\..\\readme.txt");
+        assert_eq!(
+            path.to_path_buf(),
+            PathBuf::from("docs").join("readme.txt")
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+#![cfg(feature = "std")]
+
+use crate::action::Action;
+use crate::checksum::Crc16;
+use crate::lzhuf::encoder::{LzhufEncodeInfo, LzhufEncoder};
+use crate::lzhuf::header::LhaTimestamp;
+use crate::lzhuf::LzhufMethod;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Writer of LHA archives with level 2 headers.
+///
+/// Each member is compressed with -lh5- in memory, or stored as -lh0- if
+/// that is not smaller, and written with its header at once; only one
+/// member is held at a time.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// let mtime = LhaTimestamp::Unix(820_000_000);
+/// let mut writer = LhaWriter::new(Vec::new());
+/// writer.add(b"docs/hello.txt", b"Hello, LHA!", mtime).unwrap();
+/// let archive = writer.finish().unwrap();
+///
+/// let entry = LhaReader::new(&archive).next().unwrap().unwrap();
+/// assert_eq!(entry.header.name, b"docs/hello.txt");
+/// assert_eq!(entry.decompress().unwrap(), b"Hello, LHA!");
+/// ```
+#[derive(Debug)]
+pub struct LhaWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> LhaWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Compresses `data` and writes it as the member `name`, modified at
+    /// `mtime`. The directories of `name` are separated with `/`. The
+    /// header holds the time as a Unix time, converted by
+    /// `LhaTimestamp::to_unix`.
+    ///
+    /// Data of 4 GiB or more and names that do not fit in the header are
+    /// `io::ErrorKind::InvalidInput`.
+    pub fn add(
+        &mut self,
+        name: &[u8],
+        data: &[u8],
+        mtime: LhaTimestamp,
+    ) -> io::Result<()> {
+        self.add_with_extended(name, data, mtime, &[])
+    }
+
+    /// Same as `add`, and writes the extended headers of `extended` after
+    /// the ones of the name, such as the `LhaHeader::extended` of a member
+    /// read from another archive. Those of the types 0x00 to 0x02, which
+    /// `add` writes, are skipped.
+    pub fn add_with_extended(
+        &mut self,
+        name: &[u8],
+        data: &[u8],
+        mtime: LhaTimestamp,
+        extended: &[(u8, Vec<u8>)],
+    ) -> io::Result<()> {
+        let mut encoder = LzhufEncoder::new(&LzhufMethod::Lh5);
+        let compressed = encoder
+            .compress_to_vec(data, Action::Finish)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let info = encoder.finish();
+        let (method, payload, info) = if compressed.len() < data.len() {
+            (b"-lh5-", &compressed[..], info)
+        } else {
+            let stored = LzhufEncodeInfo {
+                compressed_size: info.original_size,
+                ..info
+            };
+            (b"-lh0-", data, stored)
+        };
+        let header =
+            level2_header(method, &info, mtime.to_unix(), name, extended)?;
+        self.inner.write_all(&header)?;
+        self.inner.write_all(payload)
+    }
+
+    /// Writes the end of the archive and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Returns the level 2 header of a member of the sizes and CRC of `info`,
+/// with the common header, the name and, if `name` has one, the directory
+/// as extended headers, followed by `extra` but for its types 0x00 to
+/// 0x02.
+fn level2_header(
+    method: &[u8; 5],
+    info: &LzhufEncodeInfo,
+    mtime: u32,
+    name: &[u8],
+    extra: &[(u8, Vec<u8>)],
+) -> io::Result<Vec<u8>> {
+    let size = |n: u64| {
+        u32::try_from(n).map_err(|_| invalid_input("member too large"))
+    };
+    let (dir, file) = match name.iter().rposition(|&b| b == b'/') {
+        Some(i) => name.split_at(i + 1),
+        None => (&name[..0], name),
+    };
+    let dir = dir
+        .iter()
+        .map(|&b| if b == b'/' { 0xFF } else { b })
+        .collect::<Vec<_>>();
+    let mut ext = vec![(0x00, vec![0, 0]), (0x01, file.to_vec())];
+    if !dir.is_empty() {
+        ext.push((0x02, dir));
+    }
+    ext.extend(extra.iter().filter(|e| e.0 > 0x02).cloned());
+
+    let mut header = vec![0, 0];
+    header.extend_from_slice(method);
+    header.extend_from_slice(&size(info.compressed_size)?.to_le_bytes());
+    header.extend_from_slice(&size(info.original_size)?.to_le_bytes());
+    header.extend_from_slice(&mtime.to_le_bytes());
+    header.extend_from_slice(&[0x20, 2]);
+    header.extend_from_slice(&info.crc.to_le_bytes());
+    header.push(b'U');
+    for (kind, contents) in &ext {
+        let len = u16::try_from(contents.len() + 3)
+            .map_err(|_| invalid_input("name too long"))?;
+        header.extend_from_slice(&len.to_le_bytes());
+        header.push(*kind);
+        header.extend_from_slice(contents);
+    }
+    header.extend_from_slice(&[0, 0]);
+    // 下位バイトが 0 の大きさはアーカイブの終わりと読まれるので 1 足す
+    if header.len() & 0xFF == 0 {
+        header.push(0);
+    }
+    let len = u16::try_from(header.len())
+        .map_err(|_| invalid_input("name too long"))?;
+    header[..2].copy_from_slice(&len.to_le_bytes());
+    // 共通ヘッダの CRC の欄は、その種別の直後
+    let crc = Crc16::checksum(&header);
+    header[27..29].copy_from_slice(&crc.to_le_bytes());
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzhuf::archive::LhaReader;
+    use crate::lzhuf::header::LhaHeader;
+
+    // LHA の仕様から書いた別のスクリプトで作り、libarchive 3.8.2 の
+    // bsdtar -x でも CRC の検査を通って元のとおりに展開できる
+    const WRITTEN: &[u8] = include_bytes!("../../data/written.lzh");
+    const SAMPLE3: &[u8] = include_bytes!("../../data/sample3.ref");
+    const HELLO: &[u8] = b"Hello, LHA!\r\nHello, LHA!\r\nHello, LHA!\r\n";
+    const MTIME: LhaTimestamp = LhaTimestamp::Unix(820_000_000);
+
+    fn write(members: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut writer = LhaWriter::new(Vec::new());
+        for &(name, data) in members {
+            writer.add(name, data, MTIME).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_fixture() {
+        let archive = write(&[
+            (b"docs/ref/sample3.txt", SAMPLE3),
+            (b"hello.txt", HELLO),
+            (b"empty", b""),
+        ]);
+        assert_eq!(archive, WRITTEN);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let noise = (0..200_u32)
+            .map(|i| (i.wrapping_mul(0x9E37_79B9) >> 24) as u8)
+            .collect::<Vec<_>>();
+        let members: &[(&[u8], &[u8])] = &[
+            (b"a/b/c/sample3.txt", SAMPLE3),
+            (b"noise.bin", &noise),
+            (b"empty/", b""),
+        ];
+        let archive = write(members);
+        let entries = LhaReader::new(&archive)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), members.len());
+        for (entry, &(name, data)) in entries.iter().zip(members) {
+            assert_eq!(entry.header.name, name);
+            assert_eq!(entry.header.timestamp, MTIME);
+            assert_eq!(entry.decompress().unwrap(), data);
+        }
+        assert_eq!(
+            entries.iter().map(|e| &e.header.method).collect::<Vec<_>>(),
+            vec![b"-lh5-", b"-lh0-", b"-lh0-"]
+        );
+    }
+
+    #[test]
+    fn test_header_size() {
+        // 大きさの下位バイトが 0 になる長さの名前を詰め物で避ける
+        for len in 200..300 {
+            let name = vec![b'x'; len];
+            let archive = write(&[(&name, HELLO)]);
+            assert_ne!(archive[0], 0);
+            let entry = LhaReader::new(&archive).next().unwrap().unwrap();
+            assert_eq!(entry.header.name, name);
+        }
+        let mut writer = LhaWriter::new(Vec::new());
+        assert_eq!(
+            writer
+                .add(&vec![b'x'; 0x10000], HELLO, MTIME)
+                .map_err(|e| e.kind()),
+            Err(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_extended() {
+        const WINDOWS: &[u8] = include_bytes!("../../data/windows.lzh");
+        let entry = LhaReader::new(WINDOWS).next().unwrap().unwrap();
+        let data = entry.decompress().unwrap();
+        let mut writer = LhaWriter::new(Vec::new());
+        writer
+            .add_with_extended(
+                &entry.header.name,
+                &data,
+                entry.header.timestamp,
+                &entry.header.extended,
+            )
+            .unwrap();
+        let archive = writer.finish().unwrap();
+        let copy = LhaReader::new(&archive).next().unwrap().unwrap();
+        assert_eq!(copy.header.name, entry.header.name);
+        assert_eq!(copy.header.attribute, 0x20);
+        assert_eq!(copy.header.comment, entry.header.comment);
+        // 名前の拡張ヘッダは書き直され、他の種別はそのまま写る
+        let kinds = |h: &LhaHeader| {
+            h.extended
+                .iter()
+                .filter(|e| e.0 > 0x02)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds(&copy.header), kinds(&entry.header));
+        assert_eq!(copy.header.extended[1], (0x01, b"SAMPLE3.TXT".to_vec()));
+        assert_eq!(copy.decompress().unwrap(), SAMPLE3);
+    }
+
+    #[test]
+    fn test_header_crc() {
+        let extra = [
+            (0x3F, b"comment".to_vec()),
+            (0x50, vec![0xA4, 0x81]),
+            (0x54, vec![0; 4]),
+        ];
+        for n in 0..=extra.len() {
+            let mut writer = LhaWriter::new(Vec::new());
+            writer
+                .add_with_extended(b"docs/hello.txt", HELLO, MTIME, &extra[..n])
+                .unwrap();
+            let archive = writer.finish().unwrap();
+            let header = LhaHeader::parse(&archive).unwrap().unwrap();
+            assert_eq!(header.extended.len(), 3 + n);
+            // 欄を 0 にしたヘッダから計算し直す
+            let stored = u16::from_le_bytes([archive[27], archive[28]]);
+            let mut zeroed = archive[..header.header_len].to_vec();
+            zeroed[27..29].fill(0);
+            assert_eq!(stored, Crc16::checksum(&zeroed));
+        }
+    }
+
+    #[test]
+    fn test_timestamp() {
+        // MS-DOS の時刻は Unix 時間にして書き、読むと同じ時刻に戻る
+        // 1996-03-01 12:30:04
+        let dos = LhaTimestamp::Dos(0x2061_63C2);
+        for &(mtime, unix) in &[
+            (dos, 825_683_404),
+            (LhaTimestamp::Unix(0), 0),
+            (LhaTimestamp::Unix(u32::MAX), u32::MAX),
+        ] {
+            let mut writer = LhaWriter::new(Vec::new());
+            writer.add(b"hello.txt", HELLO, mtime).unwrap();
+            let archive = writer.finish().unwrap();
+            let entry = LhaReader::new(&archive).next().unwrap().unwrap();
+            assert_eq!(entry.header.timestamp, LhaTimestamp::Unix(unix));
+            assert_eq!(entry.header.timestamp.to_dos(), mtime.to_dos());
+        }
+    }
+}
@@ -6,9 +6,12 @@
 //! <http://mozilla.org/MPL/2.0/>.
 
 use crate::cbuffer::CircularBuffer;
+use crate::core::cmp;
 use crate::error::CompressionError;
 use crate::lzss::LzssCode;
 use crate::traits::decoder::Decoder;
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
 
 /// # Examples
 ///
@@ -52,27 +55,123 @@ use crate::traits::decoder::Decoder;
 /// }
 /// ```
 #[derive(Debug)]
-pub struct LzssDecoder {
-    buf: CircularBuffer<u8>,
+pub struct LzssDecoder<B = Box<[u8]>> {
+    buf: CircularBuffer<u8, B>,
     offset: usize,
+    // 窓より長い参照の残り
+    rest: (usize, usize),
 }
 
 impl LzssDecoder {
+    /// Creates a decoder that keeps the last `size_of_window` bytes of
+    /// output, so references reach back that far. Any size works, not only
+    /// the 64 KiB and below of `LzssEncoder`, so streams of formats with
+    /// larger windows decode too. The window is allocated up front.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of_window` is 0.
     pub fn new(size_of_window: usize) -> Self {
+        assert!(size_of_window > 0, "window size out of range");
         Self {
             buf: CircularBuffer::new(size_of_window),
             offset: 0,
+            rest: (0, 0),
         }
     }
 
     pub fn with_dict(size_of_window: usize, dict: &[u8]) -> Self {
-        let mut buf = CircularBuffer::new(size_of_window);
-        buf.append(dict);
-        Self { buf, offset: 0 }
+        let mut ret = Self::new(size_of_window);
+        ret.set_dictionary(dict);
+        ret
+    }
+
+    /// Starts over as `with_dict` does, keeping the window if it holds
+    /// `size_of_window` bytes. A larger window stays as it is, so
+    /// references are only checked against the output and `dict`.
+    #[cfg(feature = "lzhuf")]
+    pub(crate) fn reset(&mut self, size_of_window: usize, dict: &[u8]) {
+        if self.buf.cap() < size_of_window {
+            self.buf = CircularBuffer::new(size_of_window);
+        } else {
+            self.buf.clear();
+        }
+        self.offset = 0;
+        self.rest = (0, 0);
+        self.set_dictionary(dict);
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> LzssDecoder<B> {
+    /// Creates a decoder that keeps its window in `buf`, such as a
+    /// `&mut [u8]` or an array, instead of allocating it. All of `buf` is
+    /// used, so references reach back its length; an error is returned if
+    /// that is less than `size_of_window`, the window the format needs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of_window` is 0.
+    pub fn with_buffer(
+        buf: B,
+        size_of_window: usize,
+    ) -> Result<Self, CompressionError> {
+        assert!(size_of_window > 0, "window size out of range");
+        if buf.as_ref().len() < size_of_window {
+            return Err(CompressionError::BufferTooSmall);
+        }
+        Ok(Self {
+            buf: CircularBuffer::with_storage(buf),
+            offset: 0,
+            rest: (0, 0),
+        })
+    }
+
+    /// Puts the last window size bytes of `dict` in the window as if they
+    /// had been decoded, without outputting them, as
+    /// `LzssEncoder::set_dictionary` does on the encoding side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if part of a reference is still to be output.
+    pub fn set_dictionary(&mut self, dict: &[u8]) {
+        assert!(
+            self.offset == 0 && self.rest.1 == 0,
+            "dictionary set with output pending"
+        );
+        let dictstart = dict.len() - cmp::min(self.buf.cap(), dict.len());
+        self.buf.append(&dict[dictstart..]);
+    }
+
+    /// Returns whether part of the last code is still to be output.
+    #[cfg(feature = "deflate")]
+    pub(crate) fn is_pending(&self) -> bool {
+        self.offset > 0 || self.rest.1 > 0
+    }
+
+    /// Puts `data` in the window and queues it for output, as that many
+    /// `Symbol`s would be, with one copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if output is pending or `data` is longer than the window.
+    #[cfg(feature = "deflate")]
+    pub(crate) fn push_literals(&mut self, data: &[u8]) {
+        assert!(!self.is_pending(), "literals pushed with output pending");
+        assert!(data.len() <= self.buf.cap(), "literals exceed the window");
+        self.buf.append(data);
+        self.offset = data.len();
+    }
+
+    fn copy_back(&mut self, pos: usize, len: usize) {
+        // 出力は窓から読み出すので、一度に窓の大きさまで複写する
+        let n = cmp::min(len, self.buf.cap());
+        self.buf.copy_back(pos, n);
+        self.offset = n;
+        self.rest = (pos, len - n);
     }
 }
 
-impl Decoder for LzssDecoder {
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Decoder for LzssDecoder<B> {
     type Input = LzssCode;
     type Error = CompressionError;
     type Output = u8;
@@ -82,6 +181,11 @@ impl Decoder for LzssDecoder {
         s: &mut I,
     ) -> Option<Result<Self::Output, Self::Error>> {
         while self.offset == 0 {
+            if self.rest.1 > 0 {
+                let (pos, len) = self.rest;
+                self.copy_back(pos, len);
+                continue;
+            }
             match s.next() {
                 Some(s) => match s {
                     LzssCode::Symbol(s) => {
@@ -89,11 +193,14 @@ impl Decoder for LzssDecoder {
                         self.offset += 1;
                     }
                     LzssCode::Reference { len, pos } => {
-                        self.offset += len;
-                        for _ in 0..len {
-                            let d = self.buf[pos];
-                            self.buf.push(d);
+                        // 出力と辞書より前は参照できない
+                        if pos >= self.buf.len() {
+                            return Some(Err(CompressionError::DataError
+                                .with_reason(
+                                    "invalid distance too far back",
+                                )));
                         }
+                        self.copy_back(pos, len);
                     }
                 },
                 None => return None,
@@ -112,6 +219,9 @@ mod tests {
     use crate::lzss::tests::comparison;
     use crate::traits::encoder::Encoder;
     #[cfg(not(feature = "std"))]
+    #[allow(unused_imports)]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
 
     #[test]
@@ -133,4 +243,216 @@ mod tests {
 
         assert_eq!(testvec.to_vec(), ret);
     }
+
+    fn check_with_dict(
+        dict: &[u8],
+        data: &[u8],
+        window: usize,
+    ) -> Vec<LzssCode> {
+        let mut encoder = LzssEncoder::with_params(window, 3, 18, comparison);
+        encoder.set_dictionary(dict);
+        let mut iter = data.iter().cloned();
+        let codes = (0..)
+            .scan((), |_, _| encoder.next(&mut iter, Action::Flush))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert!(codes.iter().all(|c| match *c {
+            LzssCode::Reference { pos, .. } => pos < window,
+            LzssCode::Symbol(_) => true,
+        }));
+
+        let mut decoder = LzssDecoder::new(window);
+        decoder.set_dictionary(dict);
+        let mut dec_iter = codes.iter().cloned();
+        let ret = (0..)
+            .scan((), |_, _| decoder.next(&mut dec_iter))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(ret, data);
+        codes
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dict = b"{\"id\": 1, \"name\": \"sensor\", \"value\": 0.5}";
+        let data = b"{\"id\": 2, \"name\": \"sensor\", \"value\": 0.7}";
+        let codes = check_with_dict(dict, data, 0x100);
+        // 最初の記号から辞書を参照する
+        assert_eq!(
+            codes[0],
+            LzssCode::Reference {
+                len: 7,
+                pos: dict.len() - 1
+            }
+        );
+        assert!(codes.len() < 10);
+    }
+
+    #[test]
+    fn test_dictionary_longer_than_window() {
+        let mut seed = 0x9E37_79B9_u32;
+        let dict = (0..0x300)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect::<Vec<_>>();
+        // 窓に残る末尾は参照でき、窓から外れた先頭は参照できない
+        let codes = check_with_dict(&dict, &dict[0x200..0x210], 0x100);
+        assert_eq!(codes, vec![LzssCode::Reference { len: 16, pos: 0xFF }]);
+        let codes = check_with_dict(&dict, &dict[..0x10], 0x100);
+        assert!(codes.iter().all(|c| matches!(*c, LzssCode::Symbol(_))));
+    }
+
+    #[test]
+    fn test_dictionary_shorter_than_min_match() {
+        let codes = check_with_dict(b"ab", b"abababab", 0x100);
+        assert_eq!(codes, vec![LzssCode::Reference { len: 8, pos: 1 }]);
+        check_with_dict(b"", b"abababab", 0x100);
+        check_with_dict(b"a", b"a", 0x100);
+    }
+
+    #[test]
+    fn test_reference_before_start() {
+        let decode = |dict: &[u8], codes: &[LzssCode]| {
+            let mut decoder = LzssDecoder::with_dict(0x100, dict);
+            let mut iter = codes.iter().cloned();
+            (0..)
+                .scan((), |_, _| decoder.next(&mut iter))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let first = LzssCode::Reference { len: 3, pos: 0 };
+        assert_eq!(decode(b"", &[first]), Err(CompressionError::DataError));
+        assert_eq!(decode(b"a", &[first]), Ok(b"aaa".to_vec()));
+
+        let codes = [
+            LzssCode::Symbol(b'a'),
+            LzssCode::Symbol(b'b'),
+            LzssCode::Reference { len: 3, pos: 1 },
+            LzssCode::Reference { len: 3, pos: 5 },
+        ];
+        assert_eq!(decode(b"", &codes), Err(CompressionError::DataError));
+        assert_eq!(decode(b"x", &codes), Ok(b"ababaxab".to_vec()));
+        // 窓より遠い参照も誤り
+        let far = LzssCode::Reference { len: 3, pos: 0x100 };
+        assert_eq!(
+            decode(&[0; 0x200], &[far]),
+            Err(CompressionError::DataError)
+        );
+    }
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut seed = 0x2545_F491_u32;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (seed >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_large_windows() {
+        for &window in &[0x2_0000, 0x10_0000] {
+            let data = random_bytes(window + 0x1000);
+            let mut codes = data
+                .iter()
+                .cloned()
+                .map(LzssCode::Symbol)
+                .collect::<Vec<_>>();
+            // 窓のいちばん遠いバイトと、その少し手前からの参照
+            codes.push(LzssCode::Reference {
+                len: 258,
+                pos: window - 1,
+            });
+            codes.push(LzssCode::Reference {
+                len: 3,
+                pos: window - 2,
+            });
+            let mut expected = data.clone();
+            for &(len, dist) in &[(258, window), (3, window - 1)] {
+                for _ in 0..len {
+                    expected.push(expected[expected.len() - dist]);
+                }
+            }
+            let mut decoder = LzssDecoder::new(window);
+            let mut iter = codes.iter().cloned();
+            let ret = (0..)
+                .scan((), |_, _| decoder.next(&mut iter))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(ret, Ok(expected));
+
+            let mut decoder = LzssDecoder::with_dict(window, &data);
+            let far = LzssCode::Reference {
+                len: 3,
+                pos: window,
+            };
+            assert_eq!(
+                decoder.next(&mut Some(far).into_iter()),
+                Some(Err(CompressionError::DataError))
+            );
+        }
+    }
+
+    #[test]
+    fn test_reference_longer_than_window() {
+        let mut decoder = LzssDecoder::with_dict(0x10, b"0123456789abcdef");
+        let mut iter = [
+            LzssCode::Reference { len: 40, pos: 2 },
+            LzssCode::Symbol(b'x'),
+        ]
+        .iter()
+        .cloned();
+        let ret = (0..)
+            .scan((), |_, _| decoder.next(&mut iter))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut expected = b"def".repeat(14)[..40].to_vec();
+        expected.push(b'x');
+        assert_eq!(ret, expected);
+    }
+
+    fn decode_all<B: AsRef<[u8]> + AsMut<[u8]>>(
+        decoder: &mut LzssDecoder<B>,
+        codes: &[LzssCode],
+    ) -> Result<Vec<u8>, CompressionError> {
+        let mut iter = codes.iter().cloned();
+        (0..)
+            .scan((), |_, _| decoder.next(&mut iter))
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    #[test]
+    fn test_with_buffer() {
+        let codes = [
+            LzssCode::Symbol(b'a'),
+            LzssCode::Symbol(b'b'),
+            LzssCode::Reference { len: 5, pos: 1 },
+            LzssCode::Reference { len: 20, pos: 6 },
+        ];
+        let expected = decode_all(&mut LzssDecoder::new(0x10), &codes);
+        let mut buf = [0_u8; 0x10];
+        let mut decoder = LzssDecoder::with_buffer(&mut buf[..], 0x10).unwrap();
+        assert_eq!(decode_all(&mut decoder, &codes), expected);
+        let mut decoder = LzssDecoder::with_buffer([0_u8; 0x20], 0x10).unwrap();
+        assert_eq!(decode_all(&mut decoder, &codes), expected);
+
+        assert_eq!(
+            LzssDecoder::with_buffer(&mut buf[..], 0x11).err(),
+            Some(CompressionError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "dictionary set with input pending")]
+    fn test_dictionary_pending() {
+        let mut encoder = LzssEncoder::with_params(0x100, 3, 18, comparison);
+        let mut iter = b"abc".iter().cloned();
+        assert!(encoder.next(&mut iter, Action::Run).is_none());
+        encoder.set_dictionary(b"abc");
+    }
 }
@@ -7,16 +7,127 @@
 
 use crate::action::Action;
 use crate::core::cmp::{self, Ordering};
+use crate::core::marker::PhantomData;
 use crate::error::CompressionError;
-use crate::lzss::compare_match_info;
+use crate::lzss::finder::{HashChainFinder, MatchFinder, Window};
 use crate::lzss::slidedict::SlideDict;
 use crate::lzss::LzssCode;
 use crate::traits::encoder::Encoder;
 #[cfg(not(feature = "std"))]
 use alloc::collections::vec_deque::VecDeque;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 #[cfg(feature = "std")]
 use std::collections::vec_deque::VecDeque;
 
+/// Limits on the match search, named after the zlib tuning parameters.
+///
+/// The default is zlib's compression level 6. `max_lazy` is applied by
+/// the encoder, the others by `HashChainFinder`; a finder given to
+/// `LzssEncoder::with_match_finder` is passed them all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchParams {
+    /// Once a match this long is found, the searches for a longer match
+    /// at the following positions compare only a quarter of `max_chain`
+    /// candidates.
+    pub good_length: usize,
+    /// A search stops at the first match this long.
+    pub nice_length: usize,
+    /// A match this long is emitted without searching the following
+    /// positions for a longer one. 0 disables lazy matching.
+    pub max_lazy: usize,
+    /// Candidates compared per search, at least 1. Candidates are earlier
+    /// positions whose next `min_match` bytes hash the same.
+    pub max_chain: usize,
+}
+
+impl Default for MatchParams {
+    fn default() -> Self {
+        Self {
+            good_length: 8,
+            nice_length: 128,
+            max_lazy: 16,
+            max_chain: 128,
+        }
+    }
+}
+
+/// How the input is split into literals and references.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Emits the best match at each position, deferring it when a longer
+    /// one follows as `MatchParams` allows.
+    #[default]
+    Lazy,
+    /// Chooses, over blocks of a few KiB, the codes of the least total
+    /// cost under the encoder's `CodePrices`. Only `max_chain` and
+    /// `nice_length` of `MatchParams` apply, and the comparison function
+    /// is not used.
+    Optimal,
+    /// Emits the first match found, looking up a single candidate by a
+    /// hash of the next 4 bytes, and searches only every few bytes over
+    /// data that keeps failing to match. It runs several times faster than
+    /// `Lazy`. Deflate output comes out about as large as with zlib level
+    /// 1, mostly 15 to 30% larger than `Lazy`, and more on very repetitive
+    /// data. `MatchParams` and the comparison function are not used.
+    Fast,
+    /// Emits only runs, matches at distance 1, without searching the
+    /// window, as zlib's `Z_RLE`. It runs several times faster than
+    /// `Lazy`, and suits data that is mostly runs and small values, such
+    /// as PNG scanlines after filtering, where deflate output comes out
+    /// about as large. `MatchParams` and the comparison function are not
+    /// used.
+    Rle,
+}
+
+/// Costs in bits of the codes, used by `ParseMode::Optimal`.
+///
+/// A literal `b` costs `literals[b]` and a reference costs
+/// `lengths[len] + positions[pos]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodePrices {
+    /// Cost of each literal byte, 256 entries.
+    pub literals: Vec<u32>,
+    /// Cost of each match length, indexed up to the maximum match length.
+    pub lengths: Vec<u32>,
+    /// Cost of each `pos`, indexed up to the window size.
+    pub positions: Vec<u32>,
+}
+
+impl CodePrices {
+    /// Creates prices for a window of `window_size` bytes and matches up to
+    /// `max_match` bytes, estimated from an Elias gamma code: 9 bits a
+    /// literal, and about twice the bit lengths of `len` and `pos` a
+    /// reference.
+    pub fn new(window_size: usize, max_match: usize) -> Self {
+        fn gamma(n: usize) -> u32 {
+            2 * (usize::BITS - n.leading_zeros()) - 1
+        }
+        Self {
+            literals: vec![9; 256],
+            lengths: (0..=max_match).map(|l| 1 + gamma(l + 1)).collect(),
+            positions: (0..window_size).map(|p| gamma(p + 1)).collect(),
+        }
+    }
+}
+
+/// Counts of the codes of a parse, from `LzssEncoder::analyze`, laid out
+/// as the tables of `CodePrices`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LzssStats {
+    /// Literals of each byte value, 256 entries.
+    pub literals: Vec<usize>,
+    /// References of each length, indexed up to the maximum match length.
+    pub lengths: Vec<usize>,
+    /// References of each `pos`, indexed up to the window size.
+    pub positions: Vec<usize>,
+    /// Number of codes, literals and references together.
+    pub codes: usize,
+}
+
 /// # Examples
 ///
 /// ```rust
@@ -58,17 +169,31 @@ use std::collections::vec_deque::VecDeque;
 ///         .unwrap();
 /// }
 /// ```
-#[derive(Debug)]
-pub struct LzssEncoder<F>
+#[derive(Clone, Debug)]
+pub struct LzssEncoder<F, M = HashChainFinder<F>>
 where
     F: Fn(LzssCode, LzssCode) -> Ordering + Copy,
 {
-    slide: SlideDict<F>,
+    slide: SlideDict,
+    finder: M,
+    // 次に探索に入れる位置
+    indexed: usize,
+    comparison: PhantomData<F>,
     min_match: usize,
     max_match: usize,
     lazy_level: usize,
+    params: MatchParams,
+    max_insert: usize,
+    mode: ParseMode,
+    prices: Option<CodePrices>,
+    // 最適解析の作業領域
+    costs: Vec<u32>,
+    steps: Vec<LzssCode>,
+    matches: Vec<(usize, usize)>,
     offset: usize,
-    comp: F,
+    // 高速モードの 4 バイトのハッシュごとの最新の位置
+    fast_tab: Vec<u32>,
+    misses: usize,
     lzss_queue: VecDeque<LzssCode>,
     finished: bool,
 }
@@ -77,6 +202,36 @@ impl<F> LzssEncoder<F>
 where
     F: Fn(LzssCode, LzssCode) -> Ordering + Copy,
 {
+    /// Creates an encoder with a `window_size` byte window whose references
+    /// copy `min_match` to `max_match` bytes, as documented on `LzssCode`.
+    /// A repeat longer than `max_match` becomes several references of at
+    /// most `max_match` bytes, so every code the encoder emits fits a
+    /// format with the same limits.
+    ///
+    /// `comp` picks between candidate matches: it returns
+    /// `Ordering::Less` when `lhs` is the better one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_size` is 0 or over `MAX_WINDOW_SIZE`, or if
+    /// `min_match` is less than 2 or greater than `max_match`.
+    pub fn with_params(
+        window_size: usize,
+        min_match: usize,
+        max_match: usize,
+        comp: F,
+    ) -> Self {
+        Self::new(
+            comp,
+            window_size,
+            max_match,
+            min_match,
+            Self::DEFAULT_LAZY_LEVEL,
+        )
+    }
+
+    /// Like `with_params`, but also sets how many following positions are
+    /// searched for a longer match before a match is emitted.
     pub fn new(
         comp: F,
         size_of_window: usize,
@@ -84,23 +239,17 @@ where
         min_match: usize,
         lazy_level: usize,
     ) -> Self {
-        Self {
-            slide: SlideDict::new(
-                size_of_window + max_match + lazy_level + 1,
-                size_of_window,
-                min_match,
-                comp,
-            ),
+        Self::with_finder(
+            HashChainFinder::new(min_match, comp),
+            size_of_window,
             max_match,
             min_match,
             lazy_level,
-            offset: 0,
-            comp,
-            lzss_queue: VecDeque::new(),
-            finished: false,
-        }
+        )
     }
 
+    /// Like `new`, but starts with the last `size_of_window` bytes of
+    /// `dict` in the window, so the first references can point into it.
     pub fn with_dict(
         comp: F,
         size_of_window: usize,
@@ -109,51 +258,447 @@ where
         lazy_level: usize,
         dict: &[u8],
     ) -> Self {
-        let mut slide = SlideDict::new(
-            size_of_window + max_match + lazy_level + 1,
-            size_of_window,
+        let mut ret =
+            Self::new(comp, size_of_window, max_match, min_match, lazy_level);
+        ret.set_dictionary(dict);
+        ret
+    }
+}
+
+impl<M: MatchFinder> LzssEncoder<fn(LzssCode, LzssCode) -> Ordering, M> {
+    /// Like `with_params`, but searches matches with `finder` in place of
+    /// the built-in `HashChainFinder`. The parse modes work as with the
+    /// built-in search, except that `ParseMode::Fast` keeps to its own
+    /// table and `ParseMode::Rle` looks only for runs, and of
+    /// `MatchParams` only `max_lazy` applies unless `finder` takes up the
+    /// rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics as `with_params` does.
+    pub fn with_match_finder(
+        finder: M,
+        window_size: usize,
+        min_match: usize,
+        max_match: usize,
+    ) -> Self {
+        Self::with_finder(
+            finder,
+            window_size,
+            max_match,
             min_match,
-            comp,
+            Self::DEFAULT_LAZY_LEVEL,
+        )
+    }
+}
+
+impl<F, M> LzssEncoder<F, M>
+where
+    F: Fn(LzssCode, LzssCode) -> Ordering + Copy,
+    M: MatchFinder,
+{
+    /// Lazy matching level used by `with_params`.
+    pub const DEFAULT_LAZY_LEVEL: usize = 3;
+    /// Initial `set_max_insert_length` setting.
+    pub const DEFAULT_MAX_INSERT_LENGTH: usize = 128;
+    /// Largest supported `window_size`.
+    pub const MAX_WINDOW_SIZE: usize = 0x1_0000;
+    // 最適解析で一度に符号を決めるバイト数
+    const OPTIMAL_BLOCK: usize = 0x1000;
+    const FAST_HASH_BITS: u32 = 14;
+    // これだけ続けて一致しないと、探す間隔を 1 バイトずつ広げる
+    const FAST_SKIP_SHIFT: usize = 5;
+    const FAST_MAX_SKIP: usize = 31;
+
+    fn with_finder(
+        mut finder: M,
+        size_of_window: usize,
+        max_match: usize,
+        min_match: usize,
+        lazy_level: usize,
+    ) -> Self {
+        assert!(
+            (1..=Self::MAX_WINDOW_SIZE).contains(&size_of_window),
+            "window size out of range"
         );
-        let dictstart = dict.len() - cmp::min(size_of_window, dict.len());
-        slide.append(&dict[dictstart..]);
+        assert!(
+            (2..=max_match).contains(&min_match),
+            "match lengths out of range"
+        );
+        let lookahead = max_match + cmp::max(lazy_level, Self::OPTIMAL_BLOCK);
+        finder.set_match_params(MatchParams::default());
         Self {
-            slide,
+            slide: SlideDict::new(
+                size_of_window + lookahead + 1,
+                size_of_window,
+            ),
+            finder,
+            indexed: 0,
+            comparison: PhantomData,
             max_match,
             min_match,
             lazy_level,
+            params: MatchParams::default(),
+            max_insert: Self::DEFAULT_MAX_INSERT_LENGTH,
+            mode: ParseMode::default(),
+            prices: None,
+            costs: Vec::new(),
+            steps: Vec::new(),
+            matches: Vec::new(),
             offset: 0,
-            comp,
+            fast_tab: Vec::new(),
+            misses: 0,
             lzss_queue: VecDeque::new(),
             finished: false,
         }
     }
 
-    fn encode(&mut self) {
-        let info = self.slide.search_dic(self.offset, self.max_match);
+    /// Puts the last window size bytes of `dict` in the window as if they
+    /// had been encoded, so the following references can point into them.
+    /// No codes are emitted for `dict`. The decoder is given the same bytes
+    /// at the same point with `LzssDecoder::set_dictionary`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if input is buffered, as it is after `Action::Run` until the
+    /// next flush.
+    pub fn set_dictionary(&mut self, dict: &[u8]) {
+        assert!(self.offset == 0, "dictionary set with input pending");
+        let window = self.slide.max_pos();
+        let dictstart = dict.len() - cmp::min(window, dict.len());
+        self.slide.append(&dict[dictstart..]);
+        self.index(0, false);
+    }
 
-        if let Some(info) = info.and_then(|x| {
-            if x.len >= self.min_match {
-                Some(x)
+    /// Drops the window, so the codes after it do not refer to the bytes
+    /// before, as after a zlib `Z_FULL_FLUSH`. The match search keeps its
+    /// settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if input is buffered, as `set_dictionary` does.
+    #[cfg(feature = "deflate")]
+    pub(crate) fn forget_window(&mut self) {
+        assert!(self.offset == 0, "window dropped with input pending");
+        self.slide.forget();
+    }
+
+    /// Enters the positions up to the one `offset` bytes before the end,
+    /// as far as their first `min_match` bytes have come. With `skip` they
+    /// are only counted, for positions that are not going to be searched.
+    fn index(&mut self, offset: usize, skip: bool) {
+        let window = Window::new(&self.slide);
+        let last = cmp::max(offset, self.min_match);
+        while window.end().wrapping_sub(self.indexed) >= last {
+            if skip {
+                self.finder.skip(&window, self.indexed);
             } else {
-                None
+                self.finder.insert(&window, self.indexed);
             }
-        }) {
-            let lazy_level = cmp::min(info.len, self.lazy_level);
-            let mut out_info = info.clone();
+            self.indexed = self.indexed.wrapping_add(1);
+        }
+    }
+
+    /// Returns the match search limits.
+    #[inline]
+    pub fn match_params(&self) -> MatchParams {
+        self.params
+    }
+
+    /// Sets the match search limits. Longer searches encode slower and
+    /// may find longer matches.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `params.max_chain` is 0.
+    pub fn set_match_params(&mut self, params: MatchParams) {
+        assert!(params.max_chain > 0, "chain length out of range");
+        self.params = params;
+        self.finder.set_match_params(params);
+    }
+
+    /// Sets only `max_chain` of the match search limits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_chain` is 0.
+    pub fn set_max_chain(&mut self, max_chain: usize) {
+        self.set_match_params(MatchParams {
+            max_chain,
+            ..self.params
+        });
+    }
+
+    /// Sets the longest match whose inside is still entered into the match
+    /// search under `ParseMode::Lazy`, as zlib's `max_insert_length`. Of a
+    /// longer match that overlaps itself, as in a run of zeros, only the
+    /// last `dist` positions, at least `min_match`, are entered: the ones
+    /// before repeat them. This saves most of the time on long runs, and
+    /// changes the output a little. `usize::MAX` enters every position.
+    pub fn set_max_insert_length(&mut self, len: usize) {
+        self.max_insert = len;
+    }
+
+    /// Returns how the input is split into codes.
+    #[inline]
+    pub fn parse_mode(&self) -> ParseMode {
+        self.mode
+    }
+
+    /// Sets how the input is split into codes. Input already buffered is
+    /// parsed the new way.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.mode = mode;
+    }
+
+    /// Sets the prices `ParseMode::Optimal` minimizes, from the next block
+    /// it parses. Until this is called, it uses `CodePrices::new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a table of `prices` is shorter than documented on
+    /// `CodePrices`.
+    pub fn set_prices(&mut self, prices: CodePrices) {
+        assert!(
+            prices.literals.len() >= 256
+                && prices.lengths.len() > self.max_match
+                && prices.positions.len() >= self.slide.max_pos(),
+            "price table too short"
+        );
+        self.prices = Some(prices);
+    }
+
+    /// Returns the counts of the codes that encoding `data` with
+    /// `Action::Flush` would return, without changing the encoder. That
+    /// encoding, done next, returns exactly the codes counted, so a format
+    /// can write tables built from the counts before the codes and needs
+    /// to keep only `data`, not the codes.
+    pub fn analyze(&self, data: &[u8]) -> LzssStats
+    where
+        M: Clone,
+    {
+        let mut stats = LzssStats {
+            literals: vec![0; 256],
+            lengths: vec![0; self.max_match + 1],
+            positions: vec![0; self.slide.max_pos()],
+            codes: 0,
+        };
+        let mut encoder = self.clone();
+        let mut iter = data.iter().cloned();
+        while let Some(Ok(code)) = encoder.next(&mut iter, Action::Flush) {
+            match code {
+                LzssCode::Symbol(c) => stats.literals[usize::from(c)] += 1,
+                LzssCode::Reference { len, pos } => {
+                    stats.lengths[len] += 1;
+                    stats.positions[pos] += 1;
+                }
+            }
+            stats.codes += 1;
+        }
+        stats
+    }
+
+    fn lookahead(&self) -> usize {
+        match self.mode {
+            ParseMode::Lazy => self.max_match + self.lazy_level,
+            ParseMode::Optimal => self.max_match + Self::OPTIMAL_BLOCK,
+            ParseMode::Fast | ParseMode::Rle => self.max_match,
+        }
+    }
+
+    /// Returns the distance to the last position entered whose next 4
+    /// bytes hash the same as those `n` bytes before the end, and enters
+    /// that position in its place.
+    fn fast_lookup(&mut self, n: usize) -> usize {
+        // 位置は入れたバイト数で数え、32 ビットで折り返す
+        let cur = self.slide.fed().wrapping_sub(n) as u32;
+        let key = self.slide.read_u32(n);
+        let hash = (key.wrapping_mul(0x9E37_79B1)
+            >> (32 - Self::FAST_HASH_BITS)) as usize;
+        let dist = cur.wrapping_sub(self.fast_tab[hash]) as usize;
+        self.fast_tab[hash] = cur;
+        dist
+    }
+
+    fn encode_fast(&mut self) {
+        if self.fast_tab.is_empty() {
+            self.fast_tab = vec![0; 1 << Self::FAST_HASH_BITS];
+        }
+        let n = self.offset;
+        let mut len = 0;
+        let mut dist = 0;
+        if n >= 4 {
+            dist = self.fast_lookup(n);
+            len = self.slide.match_len(n, dist, self.max_match);
+        }
+        if len >= self.min_match {
+            self.lzss_queue
+                .push_back(LzssCode::Reference { len, pos: dist - 1 });
+            // 一致の終わり近くの位置だけは入れておく
+            if len > 2 && n - len + 2 >= 4 {
+                self.fast_lookup(n - len + 2);
+            }
+            self.offset -= len;
+            self.misses = 0;
+        } else {
+            let step = cmp::min(
+                1 + cmp::min(
+                    self.misses >> Self::FAST_SKIP_SHIFT,
+                    Self::FAST_MAX_SKIP,
+                ),
+                n,
+            );
+            for i in 0..step {
+                let c = self.slide[n - 1 - i];
+                self.lzss_queue.push_back(LzssCode::Symbol(c));
+            }
+            self.offset -= step;
+            self.misses += 1;
+        }
+        self.index(self.offset + 1, true);
+    }
+
+    fn encode_rle(&mut self) {
+        let n = self.offset;
+        let len = self.slide.match_len(n, 1, self.max_match);
+        if len >= self.min_match {
+            self.lzss_queue
+                .push_back(LzssCode::Reference { len, pos: 0 });
+            self.offset -= len;
+        } else {
+            self.lzss_queue
+                .push_back(LzssCode::Symbol(self.slide[n - 1]));
+            self.offset -= 1;
+        }
+        self.index(self.offset + 1, true);
+    }
+
+    /// Finds the cheapest codes for all buffered bytes, and emits them
+    /// until at most `rest` bytes are left.
+    fn encode_optimal(&mut self, rest: usize) {
+        let n = self.offset;
+        let params = self.params;
+        let prices = match self.prices.take() {
+            Some(p) => p,
+            None => CodePrices::new(self.slide.max_pos(), self.max_match),
+        };
+
+        // costs[i] は先頭 i バイトを符号化する最小のビット数、
+        // steps[i] はそこへ至る最後の符号
+        self.costs.clear();
+        self.costs.resize(n + 1, u32::MAX);
+        self.costs[0] = 0;
+        self.steps.clear();
+        self.steps.resize(n + 1, LzssCode::default());
+        // 十分長い一致の内側では一致を探さない
+        let mut skip_to = 0;
+        for i in 0..n {
+            let cost = self.costs[i];
+            let c = self.slide[n - 1 - i];
+            let lit = cost.saturating_add(prices.literals[usize::from(c)]);
+            if lit < self.costs[i + 1] {
+                self.costs[i + 1] = lit;
+                self.steps[i + 1] = LzssCode::Symbol(c);
+            }
+            if i < skip_to {
+                continue;
+            }
+
+            self.matches.clear();
+            self.index(n - i, false);
+            let window = Window::new(&self.slide);
+            let at = window.end().wrapping_sub(n - i);
+            let max_len = cmp::min(self.max_match, n - i);
+            self.finder
+                .find_all(&window, at, max_len, &mut self.matches);
+            let mut len = self.min_match;
+            for &m in &self.matches {
+                let (mlen, dist) = match checked(
+                    &window,
+                    at,
+                    Some(m),
+                    max_len,
+                    self.min_match,
+                ) {
+                    Some(m) => m,
+                    None => continue,
+                };
+                let pos = dist - 1;
+                let base = cost.saturating_add(prices.positions[pos]);
+                while len <= mlen {
+                    let c = base.saturating_add(prices.lengths[len]);
+                    if c < self.costs[i + len] {
+                        self.costs[i + len] = c;
+                        self.steps[i + len] = LzssCode::Reference { len, pos };
+                    }
+                    len += 1;
+                }
+            }
+            if !self.matches.is_empty() && len > params.nice_length {
+                skip_to = i + len - 1;
+            }
+        }
+        self.prices = Some(prices);
+
+        // 末尾から辿った符号を逆順に並べ直す
+        let mut i = n;
+        let first = self.lzss_queue.len();
+        while i > 0 {
+            let code = self.steps[i];
+            i -= match code {
+                LzssCode::Symbol(_) => 1,
+                LzssCode::Reference { len, .. } => len,
+            };
+            self.lzss_queue.push_back(code);
+        }
+        let path = self.lzss_queue.len() - first;
+        for k in 0..path / 2 {
+            self.lzss_queue.swap(first + k, first + path - 1 - k);
+        }
+        // 残す分は次の解析でまた選び直す
+        let mut covered = 0;
+        let mut emitted = 0;
+        while emitted < path && n - covered > rest {
+            covered += match self.lzss_queue[first + emitted] {
+                LzssCode::Symbol(_) => 1,
+                LzssCode::Reference { len, .. } => len,
+            };
+            emitted += 1;
+        }
+        self.lzss_queue.truncate(first + emitted);
+        self.offset -= covered;
+    }
+
+    fn encode(&mut self) {
+        let params = self.params;
+        self.index(self.offset, false);
+        let window = Window::new(&self.slide);
+        let pos = window.end().wrapping_sub(self.offset);
+        let max_len = cmp::min(self.max_match, self.offset);
+        let found = self.finder.find(&window, pos, max_len);
+
+        if let Some((len, dist)) =
+            checked(&window, pos, found, max_len, self.min_match)
+        {
+            let lazy_level = cmp::min(len, self.lazy_level);
+            let mut out = (len, dist);
             let mut lazy_index = 0;
             for i in 1..lazy_level {
-                if out_info.len >= self.max_match {
+                if out.0 >= cmp::min(self.max_match, params.max_lazy) {
                     break;
                 }
+                // zlib と同じく、より長い一致があるときだけ後ろにずらす
+                self.index(self.offset - i, false);
+                let window = Window::new(&self.slide);
+                let max_len = cmp::min(self.max_match, self.offset - i);
+                let at = pos.wrapping_add(i);
+                let found =
+                    self.finder.find_longer(&window, at, max_len, out.0);
                 if let Some(item) =
-                    self.slide.search_dic(self.offset - i, self.max_match)
+                    checked(&window, at, found, max_len, self.min_match)
                 {
-                    if item.len > self.min_match
-                        && compare_match_info(&self.comp, &item, &out_info)
-                            == Ordering::Less
-                    {
-                        out_info = item;
+                    if item.0 > out.0 {
+                        out = item;
                         lazy_index = i;
                     }
                 }
@@ -168,14 +713,20 @@ where
                 }
                 _ => self.lzss_queue.push_back(LzssCode::Reference {
                     len: lazy_index,
-                    pos: info.pos as usize,
+                    pos: dist - 1,
                 }),
             }
             self.lzss_queue.push_back(LzssCode::Reference {
-                len: out_info.len,
-                pos: out_info.pos as usize,
+                len: out.0,
+                pos: out.1 - 1,
             });
-            self.offset -= out_info.len + lazy_index;
+            self.offset -= out.0 + lazy_index;
+            // 自身と重なる長い一致の内側は周期の繰り返しなので、最後の
+            // 一周期分だけを入れておけば同じ位置が見つかる
+            if out.0 > self.max_insert && out.1 < out.0 {
+                let keep = cmp::max(out.1, self.min_match);
+                self.index(self.offset + 1 + keep, true);
+            }
         } else {
             let c = self.slide[self.offset - 1];
             self.lzss_queue.push_back(LzssCode::Symbol(c));
@@ -183,26 +734,108 @@ where
         }
     }
 
-    fn next_in(&mut self, data: u8) {
-        if self.max_match + self.lazy_level > self.offset {
-            self.slide.append(&[data]);
-            self.offset += 1;
+    /// Parses the buffered input down to less than `lookahead` bytes.
+    fn parse(&mut self, lookahead: usize) {
+        while self.offset >= lookahead {
+            match self.mode {
+                ParseMode::Lazy => self.encode(),
+                ParseMode::Optimal => self.encode_optimal(self.max_match),
+                ParseMode::Fast => self.encode_fast(),
+                ParseMode::Rle => self.encode_rle(),
+            }
         }
-        while self.offset >= self.max_match + self.lazy_level {
-            self.encode();
+    }
+
+    fn next_in(&mut self, data: u8) {
+        let lookahead = self.lookahead();
+        self.slide.push(data);
+        self.offset += 1;
+        self.parse(lookahead);
+    }
+
+    /// Encodes `input` as `next` does with `Action::Run`, and passes the
+    /// codes to `sink`, after any that `next` has not returned yet. The
+    /// input is copied into the window up to the lookahead at a time, and
+    /// the codes are the same as through `next`.
+    ///
+    /// The codes of the last bytes wait for more input, as they do with
+    /// `Action::Run`. After `flush`, an empty `input` passes them on.
+    pub fn encode_slice<G: FnMut(LzssCode)>(
+        &mut self,
+        input: &[u8],
+        sink: &mut G,
+    ) {
+        let mut rest = input;
+        loop {
+            for code in self.lzss_queue.drain(..) {
+                sink(code);
+            }
+            if rest.is_empty() {
+                break;
+            }
+            let lookahead = self.lookahead();
+            let n = cmp::min(
+                cmp::max(lookahead.saturating_sub(self.offset), 1),
+                rest.len(),
+            );
+            self.slide.append(&rest[..n]);
+            self.offset += n;
+            rest = &rest[n..];
+            self.parse(lookahead);
         }
     }
 
-    fn flush(&mut self) {
-        while self.offset > 0 {
-            self.encode();
+    /// Encodes all input taken so far, so that the codes `next` returns
+    /// from here on cover every byte of it: a match deferred for lazy
+    /// matching is emitted and the lookahead is drained. The window is
+    /// kept, so the codes of later input may still refer to earlier bytes.
+    ///
+    /// `next` flushes by itself when the input runs out under
+    /// `Action::Flush` or `Action::Finish`.
+    pub fn flush(&mut self) {
+        match self.mode {
+            ParseMode::Lazy => {
+                while self.offset > 0 {
+                    self.encode();
+                }
+            }
+            ParseMode::Optimal => self.encode_optimal(0),
+            ParseMode::Fast => {
+                while self.offset > 0 {
+                    self.encode_fast();
+                }
+            }
+            ParseMode::Rle => {
+                while self.offset > 0 {
+                    self.encode_rle();
+                }
+            }
         }
     }
 }
 
-impl<F> Encoder for LzssEncoder<F>
+/// Shortens the match `m` at `pos` to the bytes that really match, and
+/// drops it if that leaves fewer than `min_match`.
+fn checked(
+    window: &Window<'_>,
+    pos: usize,
+    m: Option<(usize, usize)>,
+    max_len: usize,
+    min_match: usize,
+) -> Option<(usize, usize)> {
+    let (len, dist) = m?;
+    let len = window.match_len(pos, dist, cmp::min(len, max_len));
+    if len >= min_match {
+        Some((len, dist))
+    } else {
+        None
+    }
+}
+
+impl<F, M> Encoder for LzssEncoder<F, M>
 where
     F: Fn(LzssCode, LzssCode) -> Ordering + Copy,
+    M: MatchFinder,
 {
     type Error = CompressionError;
     type In = u8;
@@ -229,7 +862,15 @@ where
                 }
             }
         }
-        self.lzss_queue.pop_front().map(Ok)
+        let ret = self.lzss_queue.pop_front();
+        debug_assert!(match ret {
+            Some(LzssCode::Reference { len, pos }) => {
+                (self.min_match..=self.max_match).contains(&len)
+                    && pos < self.slide.max_pos()
+            }
+            _ => true,
+        });
+        ret.map(Ok)
     }
 }
 
@@ -596,4 +1237,736 @@ mod tests {
 
         assert_eq!(ret, result);
     }
+
+    #[test]
+    fn test_with_params() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::Decoder;
+
+        let mut source = b"abab".to_vec();
+        for i in 0..3000_u32 {
+            source.extend_from_slice(&(i % 97 * 31).to_le_bytes()[..2]);
+            source.extend_from_slice(b"ab");
+        }
+        let mut encoder = LzssEncoder::with_params(0x1000, 2, 18, comparison);
+        let mut iter = source.iter().cloned();
+        let ret = (0..)
+            .scan((), |_, _| encoder.next(&mut iter, Action::Finish))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ret[2], LzssCode::Reference { len: 2, pos: 1 });
+        assert!(ret.iter().all(|c| match *c {
+            LzssCode::Reference { len, pos } => {
+                (2..=18).contains(&len) && pos < 0x1000
+            }
+            LzssCode::Symbol(_) => true,
+        }));
+
+        let mut decoder = LzssDecoder::new(0x1000);
+        let mut iter = ret.into_iter();
+        let decoded = (0..)
+            .scan((), |_, _| decoder.next(&mut iter))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, source);
+    }
+
+    #[test]
+    #[should_panic(expected = "match lengths out of range")]
+    fn test_with_params_min_match() {
+        LzssEncoder::with_params(0x1000, 1, 18, comparison);
+    }
+
+    #[test]
+    fn test_min_match_2() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::DecodeExt;
+
+        // 3 バイトの並びはどれも一度しか現れず、一致は 2 バイトに限られる
+        let k = 16;
+        let mut seen = vec![false; k * k * k];
+        let mut source = vec![0_u8, 0];
+        loop {
+            let n = source.len();
+            let prefix = (usize::from(source[n - 2]) * k
+                + usize::from(source[n - 1]))
+                * k;
+            match (0..k).rev().find(|&c| !seen[prefix + c]) {
+                Some(c) => {
+                    seen[prefix + c] = true;
+                    source.push(c as u8);
+                }
+                None => break,
+            }
+        }
+        assert_eq!(source.len(), k * k * k + 2);
+
+        for &mode in &[
+            ParseMode::Lazy,
+            ParseMode::Optimal,
+            ParseMode::Fast,
+            ParseMode::Rle,
+        ] {
+            for &min_match in &[2, 3] {
+                let mut encoder =
+                    LzssEncoder::with_params(0x1000, min_match, 18, comparison);
+                encoder.set_parse_mode(mode);
+                let codes = encode_chunks(&mut encoder, &source, 0x100)
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                let references = codes
+                    .iter()
+                    .filter(|c| match c {
+                        LzssCode::Reference { len, .. } => {
+                            assert_eq!(*len, 2);
+                            true
+                        }
+                        LzssCode::Symbol(_) => false,
+                    })
+                    .count();
+                if min_match == 3 {
+                    assert_eq!(references, 0);
+                } else if mode == ParseMode::Lazy {
+                    assert!(references > source.len() / 4);
+                }
+                let decoded = codes
+                    .into_iter()
+                    .decode(&mut LzssDecoder::new(0x1000))
+                    .collect::<Result<Vec<_>, _>>();
+                assert_eq!(decoded, Ok(source.clone()));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "window size out of range")]
+    fn test_with_params_window() {
+        LzssEncoder::with_params(0x1_0001, 3, 18, comparison);
+    }
+
+    #[test]
+    fn test_max_chain() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::Decoder;
+
+        // 窓を何度も回り、同じハッシュ値の位置が長く連なる入力
+        let mut seed = 0x2545_F491_u32;
+        let source = (0..100_000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                b"abcab"[(seed % 5) as usize]
+            })
+            .collect::<Vec<u8>>();
+        let mut codes = Vec::new();
+        for &max_chain in &[1, 4, 256, 10_000] {
+            let mut encoder =
+                LzssEncoder::with_params(0x1000, 3, 258, comparison);
+            encoder.set_max_chain(max_chain);
+            let mut iter = source.iter().cloned();
+            let ret = (0..)
+                .scan((), |_, _| encoder.next(&mut iter, Action::Finish))
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+
+            let mut decoder = LzssDecoder::new(0x1000);
+            let mut dec_iter = ret.iter().cloned();
+            let decoded = (0..)
+                .scan((), |_, _| decoder.next(&mut dec_iter))
+                .map(Result::unwrap)
+                .collect::<Vec<_>>();
+            assert_eq!(decoded, source);
+            codes.push(ret.len());
+        }
+        // 長く探すほど一致が長くなる
+        assert!(codes[0] > codes[1] && codes[1] > codes[2]);
+        assert!(codes[2] >= codes[3]);
+    }
+
+    fn encode_with(
+        source: &[u8],
+        window: usize,
+        params: MatchParams,
+        chunk: usize,
+    ) -> Vec<Vec<LzssCode>> {
+        let mut encoder = LzssEncoder::with_params(window, 3, 258, comparison);
+        encoder.set_match_params(params);
+        encode_chunks(&mut encoder, source, chunk)
+    }
+
+    fn encode_chunks<F, M>(
+        encoder: &mut LzssEncoder<F, M>,
+        source: &[u8],
+        chunk: usize,
+    ) -> Vec<Vec<LzssCode>>
+    where
+        F: Fn(LzssCode, LzssCode) -> Ordering + Copy,
+        M: MatchFinder,
+    {
+        source
+            .chunks(chunk)
+            .map(|c| {
+                let mut iter = c.iter().cloned();
+                (0..)
+                    .scan((), |_, _| encoder.next(&mut iter, Action::Flush))
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lazy_matching() {
+        let source = b"bcdefgh_abcd-abcdefgh";
+        let greedy = MatchParams {
+            max_lazy: 0,
+            ..MatchParams::default()
+        };
+        let lens = |codes: &[LzssCode]| {
+            codes[codes.len() - 2..]
+                .iter()
+                .map(|c| match *c {
+                    LzssCode::Symbol(_) => 0,
+                    LzssCode::Reference { len, .. } => len,
+                })
+                .collect::<Vec<_>>()
+        };
+        // 貪欲法では "abcd" と "efgh"、遅延評価では 'a' と "bcdefgh"
+        let codes = encode_with(source, 0x1000, greedy, source.len());
+        assert_eq!(lens(&codes[0]), vec![4, 4]);
+        let codes =
+            encode_with(source, 0x1000, MatchParams::default(), source.len());
+        assert_eq!(lens(&codes[0]), vec![0, 7]);
+        assert_eq!(codes[0][codes[0].len() - 2], LzssCode::Symbol(b'a'));
+    }
+
+    // 窓の大きさちょうどと、それを 1 超える周期で繰り返す入力
+    fn periodic_source() -> Vec<u8> {
+        let mut seed = 0x9E37_79B9_u32;
+        let mut source = (0..0x100)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                b"abcd"[(seed % 4) as usize]
+            })
+            .collect::<Vec<u8>>();
+        for i in 0..5000 {
+            let c = source[source.len() - 0x100 - i % 2];
+            source.push(if i % 61 == 0 { b'x' } else { c });
+        }
+        source
+    }
+
+    #[test]
+    fn test_lazy_boundaries() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::Decoder;
+
+        let source = periodic_source();
+        // zlib のレベル 4 から 9 の設定
+        let levels = [
+            (4, 4, 16, 16),
+            (8, 16, 32, 32),
+            (8, 16, 128, 128),
+            (8, 32, 128, 256),
+            (32, 128, 258, 1024),
+            (32, 258, 258, 4096),
+        ];
+        for &(good_length, max_lazy, nice_length, max_chain) in &levels {
+            let params = MatchParams {
+                good_length,
+                nice_length,
+                max_lazy,
+                max_chain,
+            };
+            // 遅延評価の途中で区切っても、区切りまでを出し切る
+            for &chunk in &[1, 7, 0x100, source.len()] {
+                let mut decoder = LzssDecoder::new(0x100);
+                let codes = encode_with(&source, 0x100, params, chunk);
+                for (c, s) in codes.iter().zip(source.chunks(chunk)) {
+                    assert!(c.iter().all(|c| match *c {
+                        LzssCode::Reference { pos, .. } => pos < 0x100,
+                        LzssCode::Symbol(_) => true,
+                    }));
+                    let mut iter = c.iter().cloned();
+                    let decoded = (0..)
+                        .scan((), |_, _| decoder.next(&mut iter))
+                        .map(Result::unwrap)
+                        .collect::<Vec<_>>();
+                    assert_eq!(decoded, s);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "chain length out of range")]
+    fn test_match_params_chain() {
+        let mut encoder = LzssEncoder::with_params(0x1000, 3, 18, comparison);
+        encoder.set_match_params(MatchParams {
+            max_chain: 0,
+            ..MatchParams::default()
+        });
+    }
+
+    fn total_price(codes: &[LzssCode], prices: &CodePrices) -> u32 {
+        codes
+            .iter()
+            .map(|c| match *c {
+                LzssCode::Symbol(s) => prices.literals[usize::from(s)],
+                LzssCode::Reference { len, pos } => {
+                    prices.lengths[len] + prices.positions[pos]
+                }
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_optimal_parse() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::Decoder;
+
+        let source = periodic_source();
+        let prices = CodePrices::new(0x100, 258);
+        let greedy = MatchParams {
+            max_lazy: 0,
+            ..MatchParams::default()
+        };
+        let lazy = encode_with(&source, 0x100, MatchParams::default(), 0x2000);
+        let greedy = encode_with(&source, 0x100, greedy, 0x2000);
+
+        for &chunk in &[1, 7, 0x100, 0x1001, source.len()] {
+            let mut encoder =
+                LzssEncoder::with_params(0x100, 3, 258, comparison);
+            encoder.set_parse_mode(ParseMode::Optimal);
+            let codes = encode_chunks(&mut encoder, &source, chunk);
+            let mut decoder = LzssDecoder::new(0x100);
+            for (c, s) in codes.iter().zip(source.chunks(chunk)) {
+                assert!(c.iter().all(|c| match *c {
+                    LzssCode::Reference { len, pos } => {
+                        (3..=258).contains(&len) && pos < 0x100
+                    }
+                    LzssCode::Symbol(_) => true,
+                }));
+                let mut iter = c.iter().cloned();
+                let decoded = (0..)
+                    .scan((), |_, _| decoder.next(&mut iter))
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>();
+                assert_eq!(decoded, s);
+            }
+            if chunk == source.len() {
+                let price = total_price(&codes[0], &prices);
+                assert!(price <= total_price(&lazy[0], &prices));
+                assert!(price <= total_price(&greedy[0], &prices));
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimal_prices() {
+        let source = b"abcabcabcabcabcabc";
+        let mut encoder = LzssEncoder::with_params(0x100, 3, 258, comparison);
+        encoder.set_parse_mode(ParseMode::Optimal);
+        let mut prices = CodePrices::new(0x100, 258);
+        let codes = encode_chunks(&mut encoder, source, source.len());
+        assert_eq!(codes[0].len(), 4);
+
+        // 参照が 1 バイトずつの字句より高ければ使わない
+        for p in &mut prices.positions {
+            *p = 1000;
+        }
+        encoder.set_prices(prices);
+        let codes = encode_chunks(&mut encoder, source, source.len());
+        assert_eq!(
+            codes[0],
+            source
+                .iter()
+                .map(|&c| LzssCode::Symbol(c))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_mode_switch() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::DecodeExt;
+
+        let source = periodic_source();
+        let mut encoder = LzssEncoder::with_params(0x100, 3, 258, comparison);
+        encoder.set_parse_mode(ParseMode::Optimal);
+        let mut codes = Vec::new();
+        let mut iter = source[..3000].iter().cloned();
+        while let Some(c) = encoder.next(&mut iter, Action::Run) {
+            codes.push(c.unwrap());
+        }
+        // 解析途中の先読みが残ったまま切り替える
+        encoder.set_parse_mode(ParseMode::Lazy);
+        let mut iter = source[3000..].iter().cloned();
+        while let Some(c) = encoder.next(&mut iter, Action::Finish) {
+            codes.push(c.unwrap());
+        }
+        let decoded = codes
+            .into_iter()
+            .decode(&mut LzssDecoder::new(0x100))
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(source));
+    }
+
+    #[test]
+    fn test_fast_parse() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::DecodeExt;
+
+        // 一致しない長い区間の後でも、また一致を探す
+        let mut seed = 0x2545_F491_u32;
+        let mut source = (0..0x4000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (seed >> 24) as u8
+            })
+            .collect::<Vec<_>>();
+        let runs = long_runs();
+        source.extend_from_slice(&runs);
+        for &chunk in &[0x101, 0x1000, source.len()] {
+            let mut encoder =
+                LzssEncoder::with_params(0x8000, 3, 258, comparison);
+            encoder.set_parse_mode(ParseMode::Fast);
+            let codes = encode_chunks(&mut encoder, &source, chunk)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let references = codes
+                .iter()
+                .filter(|c| matches!(c, LzssCode::Reference { .. }))
+                .count();
+            assert!(codes.len() < 0x4000 + runs.len() / 10);
+            assert!(references > 10);
+            let decoded = codes
+                .into_iter()
+                .decode(&mut LzssDecoder::new(0x8000))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(source.clone()));
+        }
+
+        // 高速モードで入れた位置は、切り替えた後の探索で見つからなくてよい
+        let text = periodic_source();
+        let mut encoder = LzssEncoder::with_params(0x100, 3, 258, comparison);
+        encoder.set_parse_mode(ParseMode::Fast);
+        let mut codes = encode_chunks(&mut encoder, &text[..3000], 3000);
+        encoder.set_parse_mode(ParseMode::Lazy);
+        codes.extend(encode_chunks(&mut encoder, &text[3000..], 0x1000));
+        let decoded = codes
+            .into_iter()
+            .flatten()
+            .decode(&mut LzssDecoder::new(0x100))
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(text));
+    }
+
+    #[test]
+    #[should_panic(expected = "price table too short")]
+    fn test_set_prices_short() {
+        let mut encoder = LzssEncoder::with_params(0x1000, 3, 18, comparison);
+        encoder.set_prices(CodePrices::new(0x1000, 17));
+    }
+
+    // 上限よりずっと長い連続と、周期の短い繰り返し
+    fn long_runs() -> Vec<u8> {
+        let mut source = vec![b'a'; 5000];
+        source.extend(b"ab".iter().cycle().take(6000));
+        source.extend(b"abcdefg".iter().cycle().take(5000));
+        source.extend((0..=255).map(|x: u8| x.wrapping_mul(151)));
+        source.extend(vec![b'z'; 1500]);
+        source
+    }
+
+    #[test]
+    fn test_long_runs() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::Decoder;
+
+        let source = long_runs();
+        for &(window, min_match, max_match) in &[
+            (0x8000, 3, 258),
+            (0x2000, 3, 256),
+            (0x1000, 2, 1024),
+            (0x1000, 3, 18),
+        ] {
+            for &mode in &[
+                ParseMode::Lazy,
+                ParseMode::Optimal,
+                ParseMode::Fast,
+                ParseMode::Rle,
+            ] {
+                let mut encoder = LzssEncoder::with_params(
+                    window, min_match, max_match, comparison,
+                );
+                encoder.set_parse_mode(mode);
+                let codes = encode_chunks(&mut encoder, &source, source.len())
+                    .remove(0);
+                let lens = codes
+                    .iter()
+                    .filter_map(|c| match *c {
+                        LzssCode::Reference { len, pos } => {
+                            assert!(pos < window);
+                            assert!(mode != ParseMode::Rle || pos == 0);
+                            Some(len)
+                        }
+                        LzssCode::Symbol(_) => None,
+                    })
+                    .collect::<Vec<_>>();
+                assert!(lens
+                    .iter()
+                    .all(|l| (min_match..=max_match).contains(l)));
+                // 長い連続は上限いっぱいの参照に分かれる
+                assert!(
+                    lens.iter().filter(|&&l| l == max_match).count()
+                        >= 5000 / max_match - 1
+                );
+
+                let mut decoder = LzssDecoder::new(window);
+                let mut iter = codes.iter().cloned();
+                let decoded = (0..)
+                    .scan((), |_, _| decoder.next(&mut iter))
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>();
+                assert_eq!(decoded, source);
+            }
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::Decoder;
+
+        let mut source = periodic_source();
+        source.extend(long_runs());
+        // 一致の途中や、最小一致長に満たない先読みの中で区切る
+        let steps = [1, 2, 1, 5, 3, 17, 259, 2, 1, 300, 7, 4097, 1, 2, 64];
+        for &mode in &[
+            ParseMode::Lazy,
+            ParseMode::Optimal,
+            ParseMode::Fast,
+            ParseMode::Rle,
+        ] {
+            let mut encoder =
+                LzssEncoder::with_params(0x1000, 3, 258, comparison);
+            encoder.set_parse_mode(mode);
+            let mut decoder = LzssDecoder::new(0x1000);
+            let mut decoded = Vec::new();
+            let mut cut = 0;
+            for &step in steps.iter().cycle() {
+                if cut == source.len() {
+                    break;
+                }
+                let next = cmp::min(cut + step, source.len());
+                let mut iter = source[cut..next].iter().cloned();
+                let mut codes = (0..)
+                    .scan((), |_, _| encoder.next(&mut iter, Action::Run))
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>();
+                encoder.flush();
+                codes.extend(
+                    (0..)
+                        .scan((), |_, _| {
+                            encoder.next(&mut None.into_iter(), Action::Run)
+                        })
+                        .map(Result::unwrap),
+                );
+                let mut iter = codes.into_iter();
+                decoded.extend(
+                    (0..)
+                        .scan((), |_, _| decoder.next(&mut iter))
+                        .map(Result::unwrap),
+                );
+                assert_eq!(decoded, &source[..next]);
+                cut = next;
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_slice() {
+        use crate::traits::encoder::EncodeExt;
+
+        let mut source = periodic_source();
+        source.extend(long_runs());
+        let steps = [1, 2, 1, 5, 3, 17, 259, 2, 1, 300, 7, 4097, 1, 2, 64];
+        for &mode in &[
+            ParseMode::Lazy,
+            ParseMode::Optimal,
+            ParseMode::Fast,
+            ParseMode::Rle,
+        ] {
+            let mut encoder =
+                LzssEncoder::with_params(0x1000, 3, 258, comparison);
+            encoder.set_parse_mode(mode);
+            let expected = source
+                .iter()
+                .cloned()
+                .encode(&mut encoder.clone(), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            let mut codes = Vec::new();
+            let mut cut = 0;
+            for &step in steps.iter().cycle() {
+                if cut == source.len() {
+                    break;
+                }
+                let next = cmp::min(cut + step, source.len());
+                encoder
+                    .encode_slice(&source[cut..next], &mut |c| codes.push(c));
+                cut = next;
+            }
+            encoder.flush();
+            encoder.encode_slice(&[], &mut |c| codes.push(c));
+            assert_eq!(codes, expected);
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut source = periodic_source();
+        source.extend(long_runs());
+        for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast] {
+            let mut encoder =
+                LzssEncoder::with_params(0x1000, 3, 258, comparison);
+            encoder.set_parse_mode(mode);
+            // 前の符号化で窓と先読みが埋まった後でも同じ符号になる
+            for chunk in source.chunks(0x1800) {
+                let stats = encoder.analyze(chunk);
+                assert_eq!(encoder.analyze(chunk), stats);
+                let codes =
+                    encode_chunks(&mut encoder, chunk, chunk.len()).remove(0);
+                let mut counted = LzssStats {
+                    literals: vec![0; 256],
+                    lengths: vec![0; 259],
+                    positions: vec![0; 0x1000],
+                    codes: codes.len(),
+                };
+                for code in codes {
+                    match code {
+                        LzssCode::Symbol(c) => {
+                            counted.literals[usize::from(c)] += 1
+                        }
+                        LzssCode::Reference { len, pos } => {
+                            counted.lengths[len] += 1;
+                            counted.positions[pos] += 1;
+                        }
+                    }
+                }
+                assert_eq!(stats, counted);
+                if mode == ParseMode::Optimal {
+                    encoder.set_prices(CodePrices::new(0x1000, 258));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_insert_length() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::DecodeExt;
+
+        // ゼロのページと、周期が一致の上限より長い繰り返しを交互に置く
+        let text = periodic_source();
+        let mut source = Vec::new();
+        for i in 0..8 {
+            source.extend(vec![0; 0x1000]);
+            source.extend_from_slice(&text[i * 0x100..i * 0x100 + 0x800]);
+        }
+        source.extend(long_runs());
+        let sizes = [usize::MAX, 128, 16, 3]
+            .iter()
+            .map(|&max_insert| {
+                let mut encoder =
+                    LzssEncoder::with_params(0x8000, 3, 258, comparison);
+                encoder.set_max_insert_length(max_insert);
+                let codes = encode_chunks(&mut encoder, &source, 0x1000)
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+                let len = codes.len();
+                let decoded = codes
+                    .into_iter()
+                    .decode(&mut LzssDecoder::new(0x8000))
+                    .collect::<Result<Vec<_>, _>>();
+                assert_eq!(decoded, Ok(source.clone()));
+                len
+            })
+            .collect::<Vec<_>>();
+        for &len in &sizes[1..] {
+            assert!(len <= sizes[0] + sizes[0] / 200, "{:?}", sizes);
+        }
+    }
+
+    // 常に一つ前のバイトからの最長の一致があると答える
+    struct ClaimFinder;
+
+    impl MatchFinder for ClaimFinder {
+        fn insert(&mut self, _window: &Window<'_>, _pos: usize) {}
+
+        fn find(
+            &mut self,
+            _window: &Window<'_>,
+            _pos: usize,
+            max_len: usize,
+        ) -> Option<(usize, usize)> {
+            Some((max_len, 1))
+        }
+    }
+
+    #[test]
+    fn test_match_finder() {
+        use crate::lzss::decoder::LzssDecoder;
+        use crate::traits::decoder::DecodeExt;
+
+        let mut source = periodic_source();
+        source.extend(long_runs());
+        for &mode in &[ParseMode::Lazy, ParseMode::Optimal, ParseMode::Fast] {
+            let mut builtin =
+                LzssEncoder::with_params(0x1000, 3, 258, comparison);
+            builtin.set_parse_mode(mode);
+            let mut given = LzssEncoder::with_match_finder(
+                HashChainFinder::new(3, comparison),
+                0x1000,
+                3,
+                258,
+            );
+            given.set_parse_mode(mode);
+            assert_eq!(
+                encode_chunks(&mut given, &source, 0x1000),
+                encode_chunks(&mut builtin, &source, 0x1000)
+            );
+
+            // 一致しない候補は捨て、一致する分だけに縮める
+            let mut encoder =
+                LzssEncoder::with_match_finder(ClaimFinder, 0x1000, 3, 258);
+            encoder.set_parse_mode(mode);
+            let codes = encode_chunks(&mut encoder, &source, 0x1000)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            if mode != ParseMode::Fast {
+                assert!(codes.iter().any(|c| matches!(
+                    c,
+                    LzssCode::Reference { len: 258, pos: 0 }
+                )));
+            }
+            let decoded = codes
+                .into_iter()
+                .decode(&mut LzssDecoder::new(0x1000))
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(decoded, Ok(source.clone()));
+        }
+    }
 }
@@ -0,0 +1,498 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! The match search of `LzssEncoder`, which
+//! `LzssEncoder::with_match_finder` replaces.
+//!
+//! A match is `(len, dist)`: `len` bytes copied from `dist` bytes back,
+//! which the encoder emits as `LzssCode::Reference { len, pos: dist - 1 }`.
+//! Positions count the bytes the encoder has taken, from 0, including a
+//! dictionary, and wrap around at `usize::MAX`.
+
+use crate::cbuffer::CircularBuffer;
+use crate::core::cmp::{self, Ordering};
+use crate::lzss::encoder::MatchParams;
+use crate::lzss::slidedict::SlideDict;
+use crate::lzss::{compare_match_info, LzssCode, MatchInfo};
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// The bytes a `MatchFinder` searches: the window before the position
+/// being encoded, and the input buffered after it.
+#[derive(Debug)]
+pub struct Window<'a> {
+    pub(crate) dict: &'a SlideDict,
+}
+
+impl<'a> Window<'a> {
+    pub(crate) fn new(dict: &'a SlideDict) -> Self {
+        Self { dict }
+    }
+
+    /// Returns the position after the last byte held.
+    #[inline]
+    pub fn end(&self) -> usize {
+        self.dict.fed()
+    }
+
+    /// Returns the position of the first byte held.
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.end().wrapping_sub(self.dict.len())
+    }
+
+    /// Returns the window size, the longest `dist` a match may have.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.dict.max_pos()
+    }
+
+    /// Returns the byte at `pos`, or `None` if it is not held.
+    #[inline]
+    pub fn get(&self, pos: usize) -> Option<u8> {
+        let offset = self.end().wrapping_sub(pos);
+        if offset == 0 || offset > self.dict.len() {
+            None
+        } else {
+            Some(self.dict[offset - 1])
+        }
+    }
+
+    /// Returns how many bytes from `pos` on, up to `max_len` and to the
+    /// end, equal those from `pos - dist` on. It is 0 if `dist` is 0, over
+    /// the window size or reaches before the first byte held.
+    pub fn match_len(&self, pos: usize, dist: usize, max_len: usize) -> usize {
+        let offset = self.end().wrapping_sub(pos);
+        if offset > self.dict.len() {
+            return 0;
+        }
+        self.dict.match_len(offset, dist, max_len)
+    }
+}
+
+/// A match search for `LzssEncoder`.
+///
+/// The encoder checks the matches it is given against the window and
+/// shortens them to the bytes that really match, so a finder may return
+/// candidates without comparing them; ones that end up shorter than the
+/// minimum match length are dropped.
+pub trait MatchFinder {
+    /// Enters `pos` into the search. The encoder calls this or `skip` for
+    /// every position in turn, once the first `min_match` bytes from `pos`
+    /// are in `window`, and by the time it searches at `pos`. Positions
+    /// after the one searched may have been entered already, as
+    /// `ParseMode::Optimal` searches the end of a block again, so a search
+    /// has to tell the positions before `pos` from those after.
+    fn insert(&mut self, window: &Window<'_>, pos: usize);
+
+    /// Returns a match for the bytes at `pos` of at most `max_len` bytes,
+    /// or `None`. `max_len` never reaches past the end of `window`.
+    fn find(
+        &mut self,
+        window: &Window<'_>,
+        pos: usize,
+        max_len: usize,
+    ) -> Option<(usize, usize)>;
+
+    /// Like `insert`, for positions the encoder is not going to search:
+    /// those `ParseMode::Fast` passes, as it searches with a table of its
+    /// own, those `ParseMode::Rle` passes, and most of the inside of a
+    /// long run, as set by `LzssEncoder::set_max_insert_length`. By
+    /// default it calls `insert`.
+    fn skip(&mut self, window: &Window<'_>, pos: usize) {
+        self.insert(window, pos);
+    }
+
+    /// Returns a match longer than `len` bytes, for lazy matching, which
+    /// looks at the next positions after finding a match of `len` bytes.
+    /// By default it returns the match `find` returns if that is longer.
+    fn find_longer(
+        &mut self,
+        window: &Window<'_>,
+        pos: usize,
+        max_len: usize,
+        len: usize,
+    ) -> Option<(usize, usize)> {
+        self.find(window, pos, max_len).filter(|m| m.0 > len)
+    }
+
+    /// Pushes to `matches` the matches `ParseMode::Optimal` chooses from,
+    /// each longer than the one before. By default it pushes the match
+    /// `find` returns.
+    fn find_all(
+        &mut self,
+        window: &Window<'_>,
+        pos: usize,
+        max_len: usize,
+        matches: &mut Vec<(usize, usize)>,
+    ) {
+        matches.extend(self.find(window, pos, max_len));
+    }
+
+    /// Takes the limits given to `LzssEncoder::set_match_params`. By
+    /// default they are ignored.
+    fn set_match_params(&mut self, params: MatchParams) {
+        let _ = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HashTab {
+    search_tab: Vec<u16>,
+    flag_tab: Vec<u8>,
+    len: usize,
+}
+
+impl HashTab {
+    const HASH_SIZE: usize = 16;
+    const TAB_LEN: usize = 1 << Self::HASH_SIZE;
+    #[cfg(target_pointer_width = "32")]
+    const HASH_FRAC: usize = 0x7A7C_4F9F;
+    #[cfg(target_pointer_width = "64")]
+    const HASH_FRAC: usize = 0x7A7C_4F9F_7A7C_4F9F;
+    #[cfg(target_pointer_width = "32")]
+    const USIZE_WIDTH: usize = 32;
+    #[cfg(target_pointer_width = "64")]
+    const USIZE_WIDTH: usize = 64;
+
+    #[cfg(all(
+        not(target_pointer_width = "64"),
+        not(target_pointer_width = "32")
+    ))]
+    fn usize_width() -> usize {
+        usize::count_zeros(0_usize)
+    }
+
+    #[cfg(any(target_pointer_width = "64", target_pointer_width = "32"))]
+    #[inline]
+    fn usize_width() -> usize {
+        Self::USIZE_WIDTH
+    }
+
+    #[cfg(all(
+        not(target_pointer_width = "64"),
+        not(target_pointer_width = "32")
+    ))]
+    const HASH_FRAC: usize = 0x7A7C_4F9F_7A7C_4F9F;
+
+    #[inline]
+    fn new() -> Self {
+        Self {
+            search_tab: vec![0_u16; Self::TAB_LEN],
+            flag_tab: vec![0_u8; Self::TAB_LEN >> 2],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn gen_change(&mut self) {
+        for i in 0..self.flag_tab.len() {
+            self.flag_tab[i] = (self.flag_tab[i] & 0b0101_0101) << 1;
+        }
+        self.len = 0;
+    }
+
+    #[inline]
+    fn get_hash<I: Iterator<Item = u8>>(data: I) -> usize {
+        let mut hash = 0_usize;
+        for d in data {
+            hash = (hash << 8) | (hash >> 24) ^ usize::from(d);
+        }
+        hash.overflowing_mul(Self::HASH_FRAC).0
+            >> (Self::usize_width() - Self::HASH_SIZE)
+    }
+
+    #[inline]
+    fn push_tab(&mut self, hash: usize) {
+        self.search_tab[hash] = self.len as u16;
+        self.flag_tab[hash >> 2] |= 1 << ((hash & 0b11) << 1);
+        self.len += 1;
+        if self.len >= Self::TAB_LEN {
+            self.gen_change();
+        }
+    }
+
+    /// Counts a position without entering it in the table.
+    #[inline]
+    fn skip(&mut self) {
+        self.len += 1;
+        if self.len >= Self::TAB_LEN {
+            self.gen_change();
+        }
+    }
+
+    fn push(&mut self, hash: usize) -> Option<usize> {
+        let f = (self.flag_tab[hash >> 2] >> ((hash & 0b11) << 1)) & 0b11;
+        let ret = if f != 0 {
+            let p = self.search_tab[hash] as usize;
+            if f & 1 == 1 {
+                Some(self.len - p)
+            } else {
+                Some(Self::TAB_LEN + self.len - p)
+            }
+        } else {
+            None
+        };
+        self.push_tab(hash);
+        ret
+    }
+}
+
+/// The match search `LzssEncoder` uses unless given another: chains of
+/// the earlier positions whose first `min_match` bytes hash the same,
+/// searched within the limits of `MatchParams`, with the encoder's
+/// comparison function picking between matches.
+///
+/// The chains hold the distance to the previous position, so the finder
+/// relies on the encoder's order of calls and serves a single encoder.
+#[derive(Clone, Debug)]
+pub struct HashChainFinder<F: Fn(LzssCode, LzssCode) -> Ordering> {
+    comparison: F,
+    min_match: usize,
+    params: MatchParams,
+    hash_tab: HashTab,
+    // 同じハッシュ値を持つ一つ前の位置までの距離で、最初の呼び出しで窓に合わせて確保する
+    pos: CircularBuffer<u32>,
+    max_pos: usize,
+    // 次に入る位置
+    next: usize,
+}
+
+impl<F: Fn(LzssCode, LzssCode) -> Ordering> HashChainFinder<F> {
+    /// Creates a finder for matches of at least `min_match` bytes, with
+    /// the default `MatchParams`.
+    pub fn new(min_match: usize, comparison: F) -> Self {
+        Self {
+            comparison,
+            min_match,
+            params: MatchParams::default(),
+            hash_tab: HashTab::new(),
+            pos: CircularBuffer::new(0),
+            max_pos: 0,
+            next: 0,
+        }
+    }
+
+    /// Returns where the chain of `pos` is in `self.pos`, or `None` if
+    /// `pos` has not been entered.
+    #[inline]
+    fn chain_index(&self, pos: usize) -> Option<usize> {
+        let back = self.next.wrapping_sub(pos);
+        if back == 0 || back > self.pos.len() {
+            None
+        } else {
+            Some(back - 1)
+        }
+    }
+
+    #[inline]
+    fn push_chain(&mut self, window: &Window<'_>, pos: Option<usize>) {
+        if self.pos.cap() == 0 {
+            self.pos = CircularBuffer::new(window.dict.cap());
+            self.max_pos = window.size();
+        }
+        match pos {
+            Some(pos) => self.pos.push(pos as u32),
+            _ => self.pos.push(self.max_pos as u32 + 1),
+        }
+    }
+
+    /// Searches up to `max_chain` earlier positions with the same hash for
+    /// the longest match, stopping early at one of `nice_match` bytes.
+    fn search_dic(
+        &self,
+        dict: &SlideDict,
+        offset: usize,
+        pos_offset: usize,
+        mut max_match: usize,
+        max_chain: usize,
+        nice_match: usize,
+    ) -> Option<MatchInfo> {
+        if offset < self.min_match {
+            return None;
+        }
+
+        let mut pos = self.pos[pos_offset] as usize;
+        max_match = cmp::min(max_match, offset);
+
+        let mut info: Option<MatchInfo> = None;
+        // 最長の一致の長さと、その直後のバイト。
+        // そこが一致しない候補は最長より長くなれないので比べずに飛ばす
+        let mut best_len = 0;
+        let mut next_byte = None;
+
+        let mut pos_count = max_chain;
+
+        while pos <= self.max_pos && pos_count > 0 {
+            if let Some(b) = next_byte {
+                if dict[offset + pos - 1 - best_len] != b {
+                    pos_count -= 1;
+                    pos += self.pos[pos_offset + pos] as usize;
+                    continue;
+                }
+            }
+            let nlen = dict.check_match(offset, offset + pos, max_match);
+            let new_info = MatchInfo {
+                len: nlen,
+                pos: (pos - 1) as u16,
+            };
+
+            info = info
+                .and_then(|iinfo: MatchInfo| {
+                    if iinfo.len >= nlen
+                        || compare_match_info(
+                            &self.comparison,
+                            &iinfo,
+                            &new_info,
+                        ) == Ordering::Less
+                    {
+                        Some(iinfo)
+                    } else {
+                        None
+                    }
+                })
+                .or(Some(new_info));
+
+            if nlen >= cmp::min(max_match, nice_match) {
+                pos_count = 0;
+            } else {
+                pos_count -= 1;
+                best_len = info.as_ref().map_or(0, |i| i.len);
+                next_byte = Some(dict[offset - 1 - best_len]);
+            }
+
+            pos += self.pos[pos_offset + pos] as usize;
+        }
+        info
+    }
+
+    /// Like `search_dic`, but pushes to `matches` every match longer than
+    /// the ones before it, from the nearest, as `(len, dist)`. Matches
+    /// shorter than `min_match` are left out.
+    fn search_matches(
+        &self,
+        dict: &SlideDict,
+        offset: usize,
+        pos_offset: usize,
+        mut max_match: usize,
+        matches: &mut Vec<(usize, usize)>,
+    ) {
+        if offset < self.min_match {
+            return;
+        }
+
+        let mut pos = self.pos[pos_offset] as usize;
+        max_match = cmp::min(max_match, offset);
+        let nice_match = cmp::min(max_match, self.params.nice_length);
+
+        let mut best_len = self.min_match - 1;
+        let mut pos_count = self.params.max_chain;
+
+        while pos <= self.max_pos && pos_count > 0 {
+            pos_count -= 1;
+            if dict[offset + pos - 1 - best_len] == dict[offset - 1 - best_len]
+            {
+                let nlen = dict.check_match(offset, offset + pos, max_match);
+                if nlen > best_len {
+                    best_len = nlen;
+                    matches.push((nlen, pos));
+                    if nlen >= nice_match {
+                        break;
+                    }
+                }
+            }
+            pos += self.pos[pos_offset + pos] as usize;
+        }
+    }
+}
+
+impl<F: Fn(LzssCode, LzssCode) -> Ordering> MatchFinder for HashChainFinder<F> {
+    fn insert(&mut self, window: &Window<'_>, pos: usize) {
+        let offset = window.end().wrapping_sub(pos);
+        debug_assert!(offset >= self.min_match);
+        let dict = window.dict;
+        let hash = HashTab::get_hash(
+            (0..self.min_match).map(|i| dict[offset - 1 - i]),
+        );
+        let prev = self.hash_tab.push(hash);
+        self.push_chain(window, prev);
+        self.next = pos.wrapping_add(1);
+    }
+
+    fn skip(&mut self, window: &Window<'_>, pos: usize) {
+        self.hash_tab.skip();
+        self.push_chain(window, None);
+        self.next = pos.wrapping_add(1);
+    }
+
+    fn find(
+        &mut self,
+        window: &Window<'_>,
+        pos: usize,
+        max_len: usize,
+    ) -> Option<(usize, usize)> {
+        let offset = window.end().wrapping_sub(pos);
+        self.search_dic(
+            window.dict,
+            offset,
+            self.chain_index(pos)?,
+            max_len,
+            self.params.max_chain,
+            self.params.nice_length,
+        )
+        .filter(|m| m.len >= self.min_match)
+        .map(|m| (m.len, usize::from(m.pos) + 1))
+    }
+
+    fn find_longer(
+        &mut self,
+        window: &Window<'_>,
+        pos: usize,
+        max_len: usize,
+        len: usize,
+    ) -> Option<(usize, usize)> {
+        let offset = window.end().wrapping_sub(pos);
+        // 十分長い一致が見つかった後は浅く探す
+        let max_chain = if len >= self.params.good_length {
+            cmp::max(self.params.max_chain / 4, 1)
+        } else {
+            self.params.max_chain
+        };
+        self.search_dic(
+            window.dict,
+            offset,
+            self.chain_index(pos)?,
+            max_len,
+            max_chain,
+            self.params.nice_length,
+        )
+        .filter(|m| m.len > len)
+        .map(|m| (m.len, usize::from(m.pos) + 1))
+    }
+
+    fn find_all(
+        &mut self,
+        window: &Window<'_>,
+        pos: usize,
+        max_len: usize,
+        matches: &mut Vec<(usize, usize)>,
+    ) {
+        let offset = window.end().wrapping_sub(pos);
+        let pos_offset = match self.chain_index(pos) {
+            Some(i) => i,
+            None => return,
+        };
+        self.search_matches(window.dict, offset, pos_offset, max_len, matches);
+    }
+
+    fn set_match_params(&mut self, params: MatchParams) {
+        self.params = params;
+    }
+}
@@ -0,0 +1,375 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+//!
+//! The byte format of Haruhiko Okumura's LZSS.C (1989).
+//!
+//! The stream is a sequence of groups: a flag byte followed by up to eight
+//! items, the first item belonging to the lowest bit. A set bit is a
+//! literal byte. A clear bit is a two-byte reference: the low 8 bits of a
+//! 12-bit position in the 4 KiB ring buffer, then its high 4 bits above
+//! the length less 3, so references copy 3 to 18 bytes. The position is
+//! an index into the ring, not a distance. Output starts at index 4078,
+//! with the 4078 bytes before it filled with spaces and the last 18 bytes
+//! of the ring zero, as in the static buffer of LZSS.C. There is no end
+//! marker; the stream ends where the input does.
+
+use crate::action::Action;
+use crate::core::cmp::Ordering;
+use crate::core::iter;
+use crate::error::{CompressionError, WithOffset};
+use crate::lzss::decoder::LzssDecoder;
+use crate::lzss::encoder::LzssEncoder;
+use crate::lzss::LzssCode;
+use crate::traits::decoder::Decoder;
+use crate::traits::encoder::Encoder;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::vec;
+
+const RING_SIZE: usize = 0x1000;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 18;
+// 出力を書き始める位置で、その前は空白で埋まっている
+const RING_START: usize = RING_SIZE - MAX_MATCH;
+const FILL: u8 = b' ';
+
+fn lzss_comparison(lhs: LzssCode, rhs: LzssCode) -> Ordering {
+    match (lhs, rhs) {
+        (
+            LzssCode::Reference {
+                len: llen,
+                pos: lpos,
+            },
+            LzssCode::Reference {
+                len: rlen,
+                pos: rpos,
+            },
+        ) => ((llen << 3) + lpos).cmp(&((rlen << 3) + rpos)).reverse(),
+        (LzssCode::Symbol(_), LzssCode::Symbol(_)) => Ordering::Equal,
+        (_, LzssCode::Symbol(_)) => Ordering::Greater,
+        (LzssCode::Symbol(_), _) => Ordering::Less,
+    }
+}
+
+/// Encoder of the LZSS.C format. The output decodes with the original
+/// `lzss d`.
+///
+/// The flag byte of a group comes before its items, so `Action::Flush`
+/// only writes out complete groups; the rest waits for more input or for
+/// `Action::Finish`.
+#[derive(Debug)]
+pub struct OkumuraEncoder {
+    lzss: LzssEncoder<fn(LzssCode, LzssCode) -> Ordering>,
+    // 旗のバイトと 8 個までの項目
+    group: [u8; 17],
+    group_len: usize,
+    items: usize,
+    // 書き出し中のグループの次のバイト
+    out: usize,
+    ring_pos: usize,
+}
+
+impl Default for OkumuraEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OkumuraEncoder {
+    pub fn new() -> Self {
+        Self {
+            lzss: LzssEncoder::with_dict(
+                lzss_comparison,
+                RING_SIZE,
+                MAX_MATCH,
+                MIN_MATCH,
+                LzssEncoder::<fn(LzssCode, LzssCode) -> Ordering>::DEFAULT_LAZY_LEVEL,
+                &[FILL; RING_START],
+            ),
+            group: [0; 17],
+            group_len: 1,
+            items: 0,
+            out: 0,
+            ring_pos: RING_START,
+        }
+    }
+
+    fn push(&mut self, code: LzssCode) {
+        match code {
+            LzssCode::Symbol(s) => {
+                self.group[0] |= 1 << self.items;
+                self.group[self.group_len] = s;
+                self.group_len += 1;
+                self.ring_pos += 1;
+            }
+            LzssCode::Reference { len, pos } => {
+                let index =
+                    self.ring_pos.wrapping_sub(pos + 1) & (RING_SIZE - 1);
+                self.group[self.group_len] = index as u8;
+                self.group[self.group_len + 1] =
+                    ((index >> 4) & 0xF0) as u8 | (len - MIN_MATCH) as u8;
+                self.group_len += 2;
+                self.ring_pos += len;
+            }
+        }
+        self.ring_pos &= RING_SIZE - 1;
+        self.items += 1;
+    }
+}
+
+impl Encoder for OkumuraEncoder {
+    type Error = CompressionError;
+    type In = u8;
+    type Out = u8;
+    fn next<I: Iterator<Item = u8>>(
+        &mut self,
+        iter: &mut I,
+        action: Action,
+    ) -> Option<Result<u8, CompressionError>> {
+        loop {
+            if self.out > 0 {
+                let b = self.group[self.out - 1];
+                self.out += 1;
+                if self.out > self.group_len {
+                    self.group[0] = 0;
+                    self.group_len = 1;
+                    self.items = 0;
+                    self.out = 0;
+                }
+                return Some(Ok(b));
+            }
+            match self.lzss.next(iter, action) {
+                Some(Ok(code)) => {
+                    self.push(code);
+                    if self.items == 8 {
+                        self.out = 1;
+                    }
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    if action == Action::Finish && self.items > 0 {
+                        self.out = 1;
+                    } else {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OkumuraCodes {
+    // 上位バイトが残りの項目数を数える
+    flags: u16,
+    ring_pos: usize,
+    bytes: usize,
+}
+
+impl OkumuraCodes {
+    fn new() -> Self {
+        Self {
+            flags: 0,
+            ring_pos: RING_START,
+            bytes: 0,
+        }
+    }
+
+    fn next<I: Iterator<Item = u8>>(
+        &mut self,
+        iter: &mut I,
+    ) -> Result<Option<LzssCode>, CompressionError> {
+        self.flags >>= 1;
+        if self.flags & 0x100 == 0 {
+            match iter.next() {
+                Some(c) => self.flags = u16::from(c) | 0xFF00,
+                None => return Ok(None),
+            }
+            self.bytes += 1;
+        }
+        // 項目の途中でなければ入力の終わりがストリームの終わり
+        let first = match iter.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        self.bytes += 1;
+        if self.flags & 1 != 0 {
+            self.ring_pos = (self.ring_pos + 1) & (RING_SIZE - 1);
+            return Ok(Some(LzssCode::Symbol(first)));
+        }
+        let second = iter.next().ok_or_else(|| {
+            CompressionError::UnexpectedEof.with_offset(self.bytes << 3)
+        })?;
+        self.bytes += 1;
+        let index = usize::from(first) | (usize::from(second & 0xF0) << 4);
+        let len = usize::from(second & 0x0F) + MIN_MATCH;
+        let pos = self.ring_pos.wrapping_sub(index + 1) & (RING_SIZE - 1);
+        self.ring_pos = (self.ring_pos + len) & (RING_SIZE - 1);
+        Ok(Some(LzssCode::Reference { len, pos }))
+    }
+}
+
+/// Decoder of the LZSS.C format.
+///
+/// As in `lzss d`, input that ends at the start of an item ends the
+/// stream, so the decoder never reports a truncated stream there. Input
+/// that ends inside a reference is `CompressionError::UnexpectedEof`.
+#[derive(Debug)]
+pub struct OkumuraDecoder {
+    lzss: LzssDecoder,
+    codes: OkumuraCodes,
+}
+
+impl Default for OkumuraDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OkumuraDecoder {
+    pub fn new() -> Self {
+        // 辞書の先頭がリングの末尾の 0 の 18 バイト
+        let mut dict = vec![0; MAX_MATCH];
+        dict.extend_from_slice(&[FILL; RING_START]);
+        Self {
+            lzss: LzssDecoder::with_dict(RING_SIZE, &dict),
+            codes: OkumuraCodes::new(),
+        }
+    }
+}
+
+impl Decoder for OkumuraDecoder {
+    type Input = u8;
+    type Output = u8;
+    type Error = CompressionError;
+
+    fn next<I: Iterator<Item = Self::Input>>(
+        &mut self,
+        iter: &mut I,
+    ) -> Option<Result<Self::Output, Self::Error>> {
+        let codes = &mut self.codes;
+        let mut err = None;
+        let ret = self.lzss.next(&mut iter::from_fn(|| {
+            codes.next(iter).unwrap_or_else(|e| {
+                err = Some(e);
+                None
+            })
+        }));
+        match err {
+            Some(e) => Some(Err(e)),
+            None => ret,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::decoder::DecodeExt;
+    use crate::traits::encoder::EncodeExt;
+    #[cfg(not(feature = "std"))]
+    use alloc::vec::Vec;
+
+    fn decode(input: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        input
+            .iter()
+            .cloned()
+            .decode(&mut OkumuraDecoder::new())
+            .collect()
+    }
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        input
+            .iter()
+            .cloned()
+            .encode(&mut OkumuraEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reference_files() {
+        // LZSS.C の `lzss e` で圧縮したファイル
+        let cases: [(&[u8], &[u8]); 2] = [
+            (
+                include_bytes!("../../data/sample1.lzss"),
+                include_bytes!("../../data/sample1.ref"),
+            ),
+            (
+                include_bytes!("../../data/sample3.lzss"),
+                include_bytes!("../../data/sample3.ref"),
+            ),
+        ];
+        for &(encoded, expected) in &cases {
+            assert_eq!(decode(encoded), Ok(expected.to_vec()));
+            assert_eq!(decode(&encode(expected)), Ok(expected.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(encode(b""), b"");
+        assert_eq!(decode(b""), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_layout() {
+        // 7 個の 'a' は 'a' と位置 4078、長さ 6 の参照になる
+        assert_eq!(encode(b"aaaaaaa"), [0x01, b'a', 0xEE, 0xF3]);
+        // 8 個の項目ごとに旗のバイトが入る
+        let encoded = encode(b"abcdefghij");
+        assert_eq!(encoded[0], 0xFF);
+        assert_eq!(encoded[9], 0x03);
+        assert_eq!(&encoded[10..], b"ij");
+        assert_eq!(decode(&encoded), Ok(b"abcdefghij".to_vec()));
+    }
+
+    #[test]
+    fn test_initial_ring() {
+        // 先頭の空白とリングの末尾の 0 を参照する
+        assert_eq!(decode(&[0x00, 0x00, 0x00]), Ok(b"   ".to_vec()));
+        assert_eq!(decode(&[0x00, 0xEE, 0xF2]), Ok(vec![0; 5]));
+        let encoded = encode(b"     x     ");
+        assert_eq!(encoded.len(), 6);
+        assert_eq!(decode(&encoded), Ok(b"     x     ".to_vec()));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let encoded = encode(b"abcabcabc");
+        assert_eq!(decode(&encoded[..encoded.len() - 2]), Ok(b"abc".to_vec()));
+        let err = decode(&encoded[..encoded.len() - 1]).unwrap_err();
+        assert_eq!(err, CompressionError::UnexpectedEof);
+        assert_eq!(err.bit_offset(), Some(40));
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut encoder = OkumuraEncoder::new();
+        let mut data = b"abcde".iter().cloned();
+        let flushed = (0..)
+            .scan((), |_, _| encoder.next(&mut data, Action::Flush))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // 8 個に満たないグループは書き出さない
+        assert!(flushed.is_empty());
+        let mut data = b"fghij".iter().cloned();
+        let mut encoded = (0..)
+            .scan((), |_, _| encoder.next(&mut data, Action::Flush))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(encoded.len(), 9);
+        let mut data = iter::empty();
+        encoded.extend(
+            (0..)
+                .scan((), |_, _| encoder.next(&mut data, Action::Finish))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+        );
+        assert_eq!(decode(&encoded), Ok(b"abcdefghij".to_vec()));
+    }
+}
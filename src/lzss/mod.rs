@@ -48,15 +48,47 @@
 //! ```
 #![cfg(feature = "lzss")]
 
-pub(crate) mod decoder;
-pub(crate) mod encoder;
+pub mod decoder;
+pub mod encoder;
+pub mod finder;
+pub mod format;
 mod slidedict;
 
 use crate::core::cmp::Ordering;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// One LZSS code: a literal byte or a copy from earlier output.
+///
+/// `LzssEncoder` emits references with `len` between its minimum and
+/// maximum match lengths and `pos` below its window size. `LzssDecoder`
+/// accepts any reference whose source lies within its window.
+///
+/// The codes are also the common form of the formats built on LZSS, so a
+/// stream can change format without being decoded to bytes: the code
+/// decoders (`DeflateCodeDecoder`, `LzhufCodeDecoder`) output the codes
+/// of a stream and the code encoders (`DeflateCodeEncoder`,
+/// `GZipCodeEncoder`, `LzhufCodeEncoder`) encode them. An encoder takes
+/// the codes of a decoder whenever its window is at least as large and
+/// its lengths cover the decoder's:
+///
+/// | format         | `len`    | `pos` below |
+/// |----------------|----------|-------------|
+/// | deflate        | 3 to 258 | 32768       |
+/// | -lh4-          | 3 to 256 | 4096        |
+/// | -lh5-          | 3 to 256 | 8192        |
+/// | -lh6-          | 3 to 256 | 32768       |
+/// | -lh7-          | 3 to 256 | 65536       |
+///
+/// So -lh4- to -lh6- streams always re-encode as deflate, while -lh7-
+/// and deflate streams convert only if their references happen to fit.
+/// An encoder given a code it cannot hold returns
+/// `CompressionError::DataError`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum LzssCode {
+    /// A literal byte.
     Symbol(u8),
+    /// Copies `len` bytes starting `pos + 1` bytes back, so `pos` 0 is the
+    /// last byte written. The copy may overlap the bytes it produces, as
+    /// in `len: 4, pos: 0`, which repeats the last byte four times.
     Reference { len: usize, pos: usize },
 }
 
@@ -6,156 +6,63 @@
 //! <http://mozilla.org/MPL/2.0/>.
 
 use crate::cbuffer::CircularBuffer;
-use crate::core::cmp::{self, Ordering};
+use crate::core::cmp;
 use crate::core::mem;
 use crate::core::ops::Index;
-use crate::core::slice;
-use crate::core::u16;
-use crate::lzss::compare_match_info;
-use crate::lzss::LzssCode;
-use crate::lzss::MatchInfo;
-#[cfg(not(feature = "std"))]
-#[allow(unused_imports)]
-use alloc::vec;
-#[cfg(not(feature = "std"))]
-use alloc::vec::Vec;
+use crate::core::ptr;
 
-#[derive(Debug)]
-struct HashTab {
-    search_tab: Vec<u16>,
-    flag_tab: Vec<u8>,
-    len: usize,
+/// The bytes an `LzssEncoder` can refer to, with the ones not yet encoded.
+/// Index 0 is the last byte appended.
+#[derive(Clone, Debug)]
+pub(crate) struct SlideDict {
+    buf: CircularBuffer<u8>,
+    max_pos: usize,
+    // これまでに入れたバイト数で、32 ビットでは折り返す
+    fed: usize,
+    // forget のあとに入れたバイト数。それより前は窓にないものとする
+    since_forget: usize,
 }
 
-impl HashTab {
-    const HASH_SIZE: usize = 16;
-    const TAB_LEN: usize = 1 << Self::HASH_SIZE;
-    #[cfg(target_pointer_width = "32")]
-    const HASH_FRAC: usize = 0x7A7C_4F9F;
-    #[cfg(target_pointer_width = "64")]
-    const HASH_FRAC: usize = 0x7A7C_4F9F_7A7C_4F9F;
-    #[cfg(target_pointer_width = "32")]
-    const USIZE_WIDTH: usize = 32;
-    #[cfg(target_pointer_width = "64")]
-    const USIZE_WIDTH: usize = 64;
-
-    #[cfg(all(
-        not(target_pointer_width = "64"),
-        not(target_pointer_width = "32")
-    ))]
-    fn usize_width() -> usize {
-        usize::count_zeros(0_usize)
-    }
-
-    #[cfg(any(target_pointer_width = "64", target_pointer_width = "32"))]
-    #[inline]
-    fn usize_width() -> usize {
-        Self::USIZE_WIDTH
-    }
-
-    #[cfg(all(
-        not(target_pointer_width = "64"),
-        not(target_pointer_width = "32")
-    ))]
-    const HASH_FRAC: usize = 0x7A7C_4F9F_7A7C_4F9F;
-
-    #[inline]
-    pub(crate) fn new() -> Self {
+impl SlideDict {
+    pub(crate) fn new(size_of_buf: usize, max_pos: usize) -> Self {
         Self {
-            search_tab: vec![0_u16; Self::TAB_LEN],
-            flag_tab: vec![0_u8; Self::TAB_LEN >> 2],
-            len: 0,
+            max_pos,
+            buf: CircularBuffer::new(size_of_buf),
+            fed: 0,
+            since_forget: usize::MAX,
         }
     }
 
     #[inline]
-    fn gen_change(&mut self) {
-        for i in 0..self.flag_tab.len() {
-            self.flag_tab[i] = (self.flag_tab[i] & 0b0101_0101) << 1;
-        }
-        self.len = 0;
+    pub(crate) fn max_pos(&self) -> usize {
+        self.max_pos
     }
 
+    /// Returns the number of bytes appended so far.
     #[inline]
-    fn get_hash(data: &[u8]) -> usize {
-        let mut hash = 0_usize;
-        for d in data {
-            hash = (hash << 8) | (hash >> 24) ^ usize::from(*d);
-        }
-        hash.overflowing_mul(Self::HASH_FRAC).0
-            >> (Self::usize_width() - Self::HASH_SIZE)
+    pub(crate) fn fed(&self) -> usize {
+        self.fed
     }
 
+    /// Returns the number of bytes held, at most the buffer size.
     #[inline]
-    fn push_tab(&mut self, hash: usize) {
-        self.search_tab[hash] = self.len as u16;
-        self.flag_tab[hash >> 2] |= 1 << ((hash & 0b11) << 1);
-        self.len += 1;
-        if self.len >= Self::TAB_LEN {
-            self.gen_change();
-        }
+    pub(crate) fn len(&self) -> usize {
+        cmp::min(self.buf.len(), self.since_forget)
     }
 
-    pub(crate) fn push(&mut self, data: &[u8]) -> Option<usize> {
-        let hash = Self::get_hash(data);
-        let f = (self.flag_tab[hash >> 2] >> ((hash & 0b11) << 1)) & 0b11;
-        let ret = if f != 0 {
-            let p = self.search_tab[hash] as usize;
-            if f & 1 == 1 {
-                Some(self.len - p)
-            } else {
-                Some(Self::TAB_LEN + self.len - p)
-            }
-        } else {
-            None
-        };
-        self.push_tab(hash);
-        ret
-    }
-}
-
-#[derive(Debug)]
-pub(crate) struct SlideDict<F: Fn(LzssCode, LzssCode) -> Ordering> {
-    comparison: F,
-    buf: CircularBuffer<u8>,
-    pos: CircularBuffer<usize>,
-    max_pos: usize,
-    min_match: usize,
-    hash_tab: HashTab,
-    append_buf: Vec<u8>,
-}
-
-impl<F: Fn(LzssCode, LzssCode) -> Ordering> SlideDict<F> {
-    const MATCH_SEARCH_COUNT: usize = 256;
-
-    pub(crate) fn new(
-        size_of_buf: usize,
-        max_pos: usize,
-        min_match: usize,
-        comparison: F,
-    ) -> Self {
-        Self {
-            comparison,
-            min_match,
-            max_pos,
-            buf: CircularBuffer::new(size_of_buf),
-            pos: CircularBuffer::new(size_of_buf),
-            append_buf: Vec::with_capacity(
-                size_of_buf - max_pos + min_match - 1,
-            ),
-            hash_tab: HashTab::new(),
-        }
+    /// Drops the bytes held from the window, so no match reaches before
+    /// the next byte appended. The positions go on from `fed`.
+    #[cfg(feature = "deflate")]
+    pub(crate) fn forget(&mut self) {
+        self.since_forget = 0;
     }
 
     #[inline]
-    fn push_pos(&mut self, data: &[u8]) {
-        match self.hash_tab.push(data) {
-            Some(pos) => self.pos.push(pos),
-            _ => self.pos.push(self.max_pos + 1),
-        }
+    pub(crate) fn cap(&self) -> usize {
+        self.buf.cap()
     }
 
-    fn check_match(
+    pub(crate) fn check_match(
         &self,
         mut pos1: usize,
         mut pos2: usize,
@@ -173,6 +80,20 @@ impl<F: Fn(LzssCode, LzssCode) -> Ordering> SlideDict<F> {
         }
 
         let mut l = 0;
+        // 折り返しに掛からない間は 8 バイトずつ比べる
+        while l + 8 <= max_match && pos2 + 8 < icap {
+            let x = unsafe {
+                let a = ptr::read_unaligned(rawbuf.as_ptr().add(pos1).cast());
+                let b = ptr::read_unaligned(rawbuf.as_ptr().add(pos2).cast());
+                u64::from_le(a) ^ u64::from_le(b)
+            };
+            if x != 0 {
+                return l + (x.trailing_zeros() / 8) as usize;
+            }
+            l += 8;
+            pos1 += 8;
+            pos2 += 8;
+        }
         while unsafe {
             *rawbuf.get_unchecked(pos1) == *rawbuf.get_unchecked(pos2)
         } && l < max_match
@@ -189,85 +110,61 @@ impl<F: Fn(LzssCode, LzssCode) -> Ordering> SlideDict<F> {
         l
     }
 
-    pub(crate) fn append(&mut self, data: &[u8]) {
-        self.buf.append(data);
-        let mm = self.min_match;
-        self.append_buf.append(&mut data.to_vec());
-        if self.buf.len() >= self.min_match {
-            for i in 0..=(self.append_buf.len() - mm) {
-                let v = unsafe {
-                    slice::from_raw_parts(self.append_buf.as_ptr().add(i), mm)
-                };
-                self.push_pos(v);
-            }
-        }
-        if self.append_buf.len() >= self.min_match {
-            let bl = self.min_match - 1;
-            for i in 0..bl {
-                let j = self.append_buf.len() - self.min_match + i + 1;
-                self.append_buf[i] = self.append_buf[j];
-            }
-            unsafe {
-                self.append_buf.set_len(bl);
-            }
+    /// Returns the length of the match `dist` bytes back from the byte
+    /// `offset` bytes before the end, or 0 if that lies outside the window.
+    pub(crate) fn match_len(
+        &self,
+        offset: usize,
+        dist: usize,
+        max_match: usize,
+    ) -> usize {
+        if dist == 0
+            || dist > self.max_pos
+            || offset == 0
+            || offset + dist > self.len()
+        {
+            return 0;
         }
+        self.check_match(offset, offset + dist, cmp::min(max_match, offset))
     }
 
-    pub(crate) fn search_dic(
-        &mut self,
-        offset: usize,
-        mut max_match: usize,
-    ) -> Option<MatchInfo> {
-        if offset < self.min_match {
-            return None;
+    /// Returns the 4 bytes from `offset` bytes before the end, the first
+    /// in the low byte.
+    pub(crate) fn read_u32(&self, offset: usize) -> u32 {
+        debug_assert!(offset >= 4);
+        let rawbuf = self.buf.get_raw_ref();
+        let p = self.buf.get_raw_pos();
+        let i = if p < offset {
+            rawbuf.len() + p - offset
+        } else {
+            p - offset
+        };
+        match rawbuf.get(i..i + 4) {
+            Some(b) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            None => u32::from_le_bytes([
+                self[offset - 1],
+                self[offset - 2],
+                self[offset - 3],
+                self[offset - 4],
+            ]),
         }
+    }
 
-        let pos_offset = offset - self.min_match;
-
-        let mut pos = self.pos[pos_offset];
-        max_match = cmp::min(max_match, offset);
-
-        let mut info = None;
-
-        let mut pos_count = Self::MATCH_SEARCH_COUNT - 1;
-
-        while pos <= self.max_pos && pos_count > 0 {
-            let nlen = self.check_match(offset, offset + pos, max_match);
-            // debug_assert!(pos > 65536, "position overflow");
-            let new_info = MatchInfo {
-                len: nlen,
-                pos: (pos - 1) as u16,
-            };
-
-            info = info
-                .and_then(|iinfo: MatchInfo| {
-                    if iinfo.len >= nlen
-                        || compare_match_info(
-                            &self.comparison,
-                            &iinfo,
-                            &new_info,
-                        ) == Ordering::Less
-                    {
-                        Some(iinfo)
-                    } else {
-                        None
-                    }
-                })
-                .or_else(|| Some(new_info));
-
-            if nlen == max_match {
-                pos_count = 0;
-            } else {
-                pos_count -= 1;
-            }
+    #[inline]
+    pub(crate) fn push(&mut self, data: u8) {
+        self.buf.push(data);
+        self.fed = self.fed.wrapping_add(1);
+        self.since_forget = self.since_forget.saturating_add(1);
+    }
 
-            pos += self.pos[pos_offset + pos];
-        }
-        info
+    pub(crate) fn append(&mut self, data: &[u8]) {
+        self.buf.append(data);
+        self.fed = self.fed.wrapping_add(data.len());
+        self.since_forget = self.since_forget.saturating_add(data.len());
     }
 }
 
-impl<F: Fn(LzssCode, LzssCode) -> Ordering> Index<usize> for SlideDict<F> {
+impl Index<usize> for SlideDict {
     type Output = u8;
 
     #[inline]
@@ -1,6 +1,5 @@
 use crate::core::mem;
 use crate::core::slice;
-use crate::core::usize;
 use crate::suffix_array::bucket::BucketBuilder;
 use crate::suffix_array::ls_type::LSTypeArray;
 #[cfg(not(feature = "std"))]
@@ -67,7 +66,7 @@ fn array_rotate_for_non_sentinel_bwt(
     sarray[0]
 }
 
-fn fill<T>(array: &mut [usize], offset: usize, count: usize, value: usize) {
+fn fill(array: &mut [usize], offset: usize, count: usize, value: usize) {
     for a in array.iter_mut().skip(offset).take(count) {
         *a = value
     }
@@ -93,7 +92,7 @@ fn induce_sa<T: Copy>(
 
         for i in 0..type_array.len() {
             let mut j = suffix_array[i];
-            if j < usize::max_value() && j != shift {
+            if j < usize::MAX && j != shift {
                 j = if j == 0 { type_array.len() } else { j } - 1;
                 if !type_array.get(j) {
                     let bj = bucket[j];
@@ -108,7 +107,7 @@ fn induce_sa<T: Copy>(
         let mut bucket = bucket_builder.build(true);
         for i in (0..type_array.len()).rev() {
             let mut j = suffix_array[i];
-            if j < usize::max_value() && j != shift {
+            if j < usize::MAX && j != shift {
                 j = if j == 0 { type_array.len() } else { j } - 1;
                 if type_array.get(j) {
                     let bj = bucket[j] - 1;
@@ -143,7 +142,7 @@ fn sa_is<T: Copy + PartialEq<T> + PartialOrd<T>>(
     let mut bucket = bucket_builder.build(true);
 
     // find ends of buckets
-    fill::<usize>(suffix_array, 0, count, usize::max_value());
+    fill(suffix_array, 0, count, usize::MAX);
 
     for i in ((shift + 1)..count).chain(0..shift) {
         if type_array.is_lms(i) {
@@ -167,9 +166,9 @@ fn sa_is<T: Copy + PartialEq<T> + PartialOrd<T>>(
 
     // find the lexicographic names of substrings
     // init the name array buffer
-    fill::<usize>(suffix_array, n1, count - n1, usize::max_value());
+    fill(suffix_array, n1, count - n1, usize::MAX);
     let mut name = 0;
-    let mut prev_store = usize::max_value();
+    let mut prev_store = usize::MAX;
 
     for i in 0..n1 {
         let mut prev = prev_store;
@@ -177,7 +176,7 @@ fn sa_is<T: Copy + PartialEq<T> + PartialOrd<T>>(
         let mut now = pos;
         let mut diff = false;
         loop {
-            if prev == usize::max_value()
+            if prev == usize::MAX
                 || now == shift
                 || prev == shift
                 || array[now] != array[prev]
@@ -208,7 +207,7 @@ fn sa_is<T: Copy + PartialEq<T> + PartialOrd<T>>(
     {
         let mut j = count - 1;
         for i in (n1..=j).rev() {
-            if suffix_array[i] < usize::max_value() {
+            if suffix_array[i] < usize::MAX {
                 suffix_array[j] = suffix_array[i];
                 j -= 1;
             }
@@ -251,10 +250,10 @@ fn sa_is<T: Copy + PartialEq<T> + PartialOrd<T>>(
     }
 
     // init SA[n1..n-1]
-    fill::<usize>(suffix_array, n1, count - n1, usize::max_value());
+    fill(suffix_array, n1, count - n1, usize::MAX);
 
     for i in (0..n1).rev() {
-        let j = mem::replace(&mut suffix_array[i], usize::max_value());
+        let j = mem::replace(&mut suffix_array[i], usize::MAX);
         let b2j = bucket2[j] - 1;
         bucket2[j] = b2j;
         suffix_array[b2j] = j;
@@ -274,10 +273,9 @@ pub(crate) fn bwt(array: &[u8], max_value: usize) -> Vec<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::u8;
 
     fn test_bwt(src: &[u8], bwtstr: &[u8]) {
-        let ret = bwt(src, u8::max_value() as usize);
+        let ret = bwt(src, u8::MAX as usize);
         let mut bwt_ret = vec![0_u8; src.len()];
         for i in 0..bwt_ret.len() {
             let j = if ret[i] == 0 { bwt_ret.len() } else { ret[i] } - 1;
@@ -287,7 +285,7 @@ mod tests {
     }
 
     fn test_bwtpos(src: &[u8], bwtpos: &[usize]) {
-        let ret = bwt(src, u8::max_value() as usize);
+        let ret = bwt(src, u8::MAX as usize);
         assert_eq!(ret, bwtpos);
     }
 
@@ -5,11 +5,19 @@
 //! version 2.0 (the "License"). You can obtain a copy of the License at
 //! <http://mozilla.org/MPL/2.0/>.
 
-use crate::bitio::direction::Direction;
-use crate::bitio::reader::BitReader;
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
+use crate::bitio::{BitRead, BitReader, Direction, SliceBitReader};
 use crate::core::borrow::BorrowMut;
+use crate::core::iter;
 use crate::core::marker::PhantomData;
 use crate::error::CompressionError;
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
+use crate::error::WithOffset;
+#[cfg(all(
+    any(feature = "bzip2", feature = "deflate", feature = "lzhuf"),
+    not(feature = "std")
+))]
+use alloc::vec::Vec;
 use cfg_if::cfg_if;
 
 pub trait DecodeExt<I>
@@ -85,6 +93,147 @@ where
     }
 }
 
+pub trait TryDecodeExt<I, T, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+{
+    fn try_decode<D: Decoder<Input = T>>(
+        self,
+        decoder: &mut D,
+    ) -> TryDecodeIterator<'_, I, D, E>
+    where
+        CompressionError: From<D::Error>,
+        E: From<D::Error>;
+}
+
+impl<I, T, E> TryDecodeExt<I::IntoIter, T, E> for I
+where
+    I: IntoIterator<Item = Result<T, E>>,
+{
+    fn try_decode<D: Decoder<Input = T>>(
+        self,
+        decoder: &mut D,
+    ) -> TryDecodeIterator<'_, I::IntoIter, D, E>
+    where
+        CompressionError: From<D::Error>,
+        E: From<D::Error>,
+    {
+        TryDecodeIterator::new(self.into_iter(), decoder)
+    }
+}
+
+/// Decodes the input of a fallible source such as
+/// `Iterator<Item = Result<u8, E>>`.
+///
+/// The input ends at the first source error. If the decoder then fails,
+/// the source error is returned in place of the decoder's error. If the
+/// stream ends without needing the failed input, iteration ends normally
+/// and the source error is left for `take_error`.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+///
+/// fn main() {
+///     # #[cfg(feature = "gzip")]
+///     let compressed = b"aabbaabbaabbaabb\n"
+///         .into_iter()
+///         .cloned()
+///         .encode(&mut GZipEncoder::new(), Action::Finish)
+///         .collect::<Result<Vec<_>, _>>()
+///         .unwrap();
+///
+///     # #[cfg(feature = "gzip")]
+///     let decompressed = compressed
+///         .into_iter()
+///         .map(Ok::<_, CompressionError>)
+///         .try_decode(&mut GZipDecoder::new())
+///         .collect::<Result<Vec<_>, _>>()
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TryDecodeIterator<'a, I, D, E>
+where
+    I: Iterator<Item = Result<D::Input, E>>,
+    D: Decoder,
+    CompressionError: From<D::Error>,
+    E: From<D::Error>,
+{
+    decoder: &'a mut D,
+    inner: I,
+    error: Option<E>,
+    failed: bool,
+    finished: bool,
+}
+
+impl<'a, I, D, E> TryDecodeIterator<'a, I, D, E>
+where
+    I: Iterator<Item = Result<D::Input, E>>,
+    D: Decoder,
+    CompressionError: From<D::Error>,
+    E: From<D::Error>,
+{
+    pub(crate) fn new(inner: I, decoder: &'a mut D) -> Self {
+        Self {
+            decoder,
+            inner,
+            error: None,
+            failed: false,
+            finished: false,
+        }
+    }
+
+    /// Takes the source error that was not returned by the iterator.
+    pub fn take_error(&mut self) -> Option<E> {
+        self.error.take()
+    }
+}
+
+impl<I, D, E> Iterator for TryDecodeIterator<'_, I, D, E>
+where
+    I: Iterator<Item = Result<D::Input, E>>,
+    D: Decoder,
+    CompressionError: From<D::Error>,
+    E: From<D::Error>,
+{
+    type Item = Result<D::Output, E>;
+
+    fn next(&mut self) -> Option<Result<D::Output, E>> {
+        if self.finished {
+            return None;
+        }
+        let (inner, error, failed) =
+            (&mut self.inner, &mut self.error, &mut self.failed);
+        // ソースのエラー以降は入力の終端として扱う
+        let mut bytes = iter::from_fn(|| {
+            if *failed {
+                return None;
+            }
+            match inner.next()? {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    *error = Some(e);
+                    *failed = true;
+                    None
+                }
+            }
+        });
+        match self.decoder.next(&mut bytes) {
+            Some(Ok(s)) => Some(Ok(s)),
+            Some(Err(e)) => {
+                self.finished = true;
+                Some(Err(self.error.take().unwrap_or_else(|| E::from(e))))
+            }
+            None => {
+                self.finished = true;
+                None
+            }
+        }
+    }
+}
+
 pub trait Decoder
 where
     CompressionError: From<Self::Error>,
@@ -105,7 +254,7 @@ cfg_if! {
         where
             T: BitDecodeService,
             CompressionError: From<T::Error>,
-            R: BorrowMut<BitReader<T::Direction>>,
+            R: BitRead<Direction = T::Direction>,
             B: BorrowMut<T>,
         {
             reader: R,
@@ -135,7 +284,7 @@ cfg_if! {
         where
             T: BitDecodeService,
             CompressionError: From<T::Error>,
-            R: BorrowMut<BitReader<T::Direction>>,
+            R: BitRead<Direction = T::Direction>,
             B: BorrowMut<T>,
         {
             pub(crate) fn with_service(service: B, reader: R) -> Self {
@@ -146,6 +295,100 @@ cfg_if! {
                 }
             }
         }
+        #[cfg(any(feature = "gzip", feature = "zlib"))]
+        impl<T, R, B> BitDecoder<T, R, B>
+        where
+            T: BitDecodeService,
+            CompressionError: From<T::Error>,
+            R: BitRead<Direction = T::Direction>,
+            B: BorrowMut<T>,
+        {
+            pub(crate) fn reader(&self) -> &R {
+                &self.reader
+            }
+        }
+
+        #[cfg(feature = "deflate")]
+        impl<T, R, B> BitDecoder<T, R, B>
+        where
+            T: BitDecodeService,
+            CompressionError: From<T::Error>,
+            R: BitRead<Direction = T::Direction>,
+            B: BorrowMut<T>,
+        {
+            pub(crate) fn service_mut(&mut self) -> &mut T {
+                self.service.borrow_mut()
+            }
+        }
+
+        impl<T, R, B> BitDecoder<T, R, B>
+        where
+            T: BitDecodeService,
+            CompressionError: From<T::Error>,
+            R: BitRead<Direction = T::Direction>,
+            B: BorrowMut<T>,
+        {
+            /// Runs the service over `input` through a `SliceBitReader`
+            /// until the end of the stream. Returns the output and the
+            /// number of input bytes the stream occupies.
+            pub(crate) fn decode_slice(
+                &mut self,
+                input: &[u8],
+            ) -> Result<(Vec<T::Output>, usize), T::Error> {
+                let mut reader = SliceBitReader::<T::Direction>::new(input);
+                let mut ret = Vec::new();
+                while let Some(s) = self
+                    .service
+                    .borrow_mut()
+                    .next(&mut reader, &mut iter::empty())
+                    .map_err(|e| e.with_offset(reader.bit_position()))?
+                {
+                    ret.push(s);
+                }
+                Ok((ret, reader.bytes_consumed()))
+            }
+
+            /// Like `decode_slice`, but writes the output into `output`.
+            /// Returns the number of items written and of input bytes the
+            /// stream occupies, or `BufferTooSmall` if the output does not
+            /// fit.
+            #[cfg(feature = "deflate")]
+            pub(crate) fn decode_slice_into(
+                &mut self,
+                input: &[u8],
+                output: &mut [T::Output],
+            ) -> Result<(usize, usize), CompressionError> {
+                let mut reader = SliceBitReader::<T::Direction>::new(input);
+                let mut written = 0;
+                while let Some(s) = self
+                    .service
+                    .borrow_mut()
+                    .next(&mut reader, &mut iter::empty())
+                    .map_err(|e| e.with_offset(reader.bit_position()))?
+                {
+                    *output
+                        .get_mut(written)
+                        .ok_or(CompressionError::BufferTooSmall)? = s;
+                    written += 1;
+                }
+                Ok((written, reader.bytes_consumed()))
+            }
+        }
+
+        #[cfg(feature = "lzhuf")]
+        impl<T> BitDecoder<T, BitReader<T::Direction>, T>
+        where
+            T: BitDecodeService,
+            CompressionError: From<T::Error>,
+        {
+            /// Drops the input read so far and lets `f` start the service
+            /// over, for the next stream.
+            pub(crate) fn reset_with<F: FnOnce(&mut T)>(&mut self, f: F) {
+                self.reader = BitReader::new();
+                f(&mut self.service);
+            }
+        }
+
         impl<T> Default for BitDecoder<T, BitReader<T::Direction>, T>
         where
             T: BitDecodeService + Default,
@@ -182,7 +425,7 @@ cfg_if! {
         where
             T: BitDecodeService,
             CompressionError: From<T::Error>,
-            R: BorrowMut<BitReader<T::Direction>>,
+            R: BitRead<Direction = T::Direction>,
             B: BorrowMut<T>,
         {
             type Error = T::Error;
@@ -192,9 +435,11 @@ cfg_if! {
                 &mut self,
                 iter: &mut I,
             ) -> Option<Result<Self::Output, Self::Error>> {
+                let reader = &mut self.reader;
                 self.service
                     .borrow_mut()
-                    .next(self.reader.borrow_mut(), iter)
+                    .next(reader, iter)
+                    .map_err(|e| e.with_offset(reader.bit_position()))
                     .transpose()
             }
         }
@@ -204,17 +449,22 @@ cfg_if! {
     }
 }
 
+#[cfg(any(feature = "bzip2", feature = "deflate", feature = "lzhuf"))]
 pub(crate) trait BitDecodeService
 where
     Self::Direction: Direction,
+    Self::Error: WithOffset,
     CompressionError: From<Self::Error>,
 {
     type Direction;
     type Error;
     type Output;
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<Self::Output>, Self::Error>;
+    ) -> Result<Option<Self::Output>, Self::Error>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>;
 }
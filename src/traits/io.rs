@@ -0,0 +1,490 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+#![cfg(feature = "std")]
+
+use crate::action::Action;
+use crate::error::CompressionError;
+use crate::traits::decoder::Decoder;
+use crate::traits::encoder::Encoder;
+use std::io::{self, Read, Write};
+
+const DEFAULT_BUF_SIZE: usize = 0x2000;
+
+/// Buffered byte iterator over a `std::io::Read`.
+///
+/// Iteration stops at the first IO error, which is kept until it is taken
+/// with `take_error`.
+#[derive(Debug)]
+pub(crate) struct ReadBytes<R: Read> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ReadBytes<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    pub(crate) fn with_capacity(cap: usize, inner: R) -> Self {
+        Self {
+            inner,
+            buf: vec![0; cap].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            error: None,
+        }
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for ReadBytes<R> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.len {
+            if self.error.is_some() {
+                return None;
+            }
+            loop {
+                match self.inner.read(&mut self.buf) {
+                    Ok(0) => return None,
+                    Ok(len) => {
+                        self.pos = 0;
+                        self.len = len;
+                        break;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        self.error = Some(e);
+                        return None;
+                    }
+                }
+            }
+        }
+        let ret = self.buf[self.pos];
+        self.pos += 1;
+        Some(ret)
+    }
+}
+
+/// `std::io::Read` adapter that decodes the bytes pulled from `inner`.
+///
+/// Errors reported by `inner` are returned unchanged; decode failures are
+/// returned as `io::ErrorKind::InvalidData` wrapping the `CompressionError`.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+/// use std::io::Read;
+///
+/// fn main() {
+///     # #[cfg(feature = "gzip")]
+///     let compressed = b"aabbaabbaabbaabb\n"
+///         .into_iter()
+///         .cloned()
+///         .encode(&mut GZipEncoder::new(), Action::Finish)
+///         .collect::<Result<Vec<_>, _>>()
+///         .unwrap();
+///
+///     # #[cfg(feature = "gzip")]
+///     let mut decompressed = Vec::new();
+///     # #[cfg(feature = "gzip")]
+///     DecodeReader::new(&compressed[..], &mut GZipDecoder::new())
+///         .read_to_end(&mut decompressed)
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DecodeReader<'a, R, D>
+where
+    R: Read,
+    D: Decoder<Input = u8, Output = u8>,
+    CompressionError: From<D::Error>,
+{
+    decoder: &'a mut D,
+    inner: ReadBytes<R>,
+    pending: Option<io::Error>,
+    finished: bool,
+}
+
+impl<'a, R, D> DecodeReader<'a, R, D>
+where
+    R: Read,
+    D: Decoder<Input = u8, Output = u8>,
+    CompressionError: From<D::Error>,
+{
+    pub fn new(inner: R, decoder: &'a mut D) -> Self {
+        Self {
+            decoder,
+            inner: ReadBytes::new(inner),
+            pending: None,
+            finished: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+impl<R, D> Read for DecodeReader<'_, R, D>
+where
+    R: Read,
+    D: Decoder<Input = u8, Output = u8>,
+    CompressionError: From<D::Error>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(e) = self.pending.take() {
+            return Err(e);
+        }
+        let mut len = 0;
+        while len < buf.len() && !self.finished {
+            let err = match self.decoder.next(&mut self.inner) {
+                Some(Ok(s)) => {
+                    buf[len] = s;
+                    len += 1;
+                    continue;
+                }
+                Some(Err(e)) => {
+                    self.finished = true;
+                    Some(self.inner.take_error().unwrap_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            CompressionError::from(e),
+                        )
+                    }))
+                }
+                None => {
+                    self.finished = true;
+                    self.inner.take_error()
+                }
+            };
+            if let Some(e) = err {
+                if len == 0 {
+                    return Err(e);
+                }
+                self.pending = Some(e);
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// `std::io::Write` adapter that encodes the written bytes into `inner`.
+///
+/// Encoded bytes are passed to `inner` as soon as the encoder produces them.
+/// `flush` runs the encoder with `Action::Flush`, and `finish` terminates
+/// the stream and returns `inner`. Encode failures are returned as
+/// `io::ErrorKind::InvalidData` wrapping the `CompressionError`; once an
+/// error is returned the writer refuses further input.
+///
+/// # Examples
+///
+/// ```rust
+/// use compression::prelude::*;
+/// use std::io::Write;
+///
+/// fn main() {
+///     # #[cfg(feature = "gzip")]
+///     let mut encoder = GZipEncoder::new();
+///     # #[cfg(feature = "gzip")]
+///     let mut writer = EncodeWriter::new(Vec::new(), &mut encoder);
+///     # #[cfg(feature = "gzip")]
+///     writer.write_all(b"aabbaabbaabbaabb\n").unwrap();
+///     # #[cfg(feature = "gzip")]
+///     let compressed = writer.finish().unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EncodeWriter<'a, W, E>
+where
+    W: Write,
+    E: Encoder<In = u8, Out = u8>,
+    CompressionError: From<E::Error>,
+{
+    encoder: &'a mut E,
+    inner: W,
+    buf: Vec<u8>,
+    poisoned: bool,
+}
+
+impl<'a, W, E> EncodeWriter<'a, W, E>
+where
+    W: Write,
+    E: Encoder<In = u8, Out = u8>,
+    CompressionError: From<E::Error>,
+{
+    pub fn new(inner: W, encoder: &'a mut E) -> Self {
+        Self {
+            encoder,
+            inner,
+            buf: Vec::with_capacity(DEFAULT_BUF_SIZE),
+            poisoned: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Terminates the encoded stream and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.run(&[], Action::Finish)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn run(&mut self, data: &[u8], action: Action) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::other("encoder aborted by previous error"));
+        }
+        let mut iter = data.iter().cloned();
+        while let Some(r) = self.encoder.next(&mut iter, action) {
+            match r {
+                Ok(s) => {
+                    self.buf.push(s);
+                    if self.buf.len() >= DEFAULT_BUF_SIZE {
+                        self.write_buf()?;
+                    }
+                }
+                Err(e) => {
+                    self.poisoned = true;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        CompressionError::from(e),
+                    ));
+                }
+            }
+        }
+        self.write_buf()
+    }
+
+    fn write_buf(&mut self) -> io::Result<()> {
+        let ret = self.inner.write_all(&self.buf);
+        self.buf.clear();
+        if ret.is_err() {
+            self.poisoned = true;
+        }
+        ret
+    }
+}
+
+impl<W, E> Write for EncodeWriter<'_, W, E>
+where
+    W: Write,
+    E: Encoder<In = u8, Out = u8>,
+    CompressionError: From<E::Error>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.run(buf, Action::Run)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.run(&[], Action::Flush)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+    use crate::action::Action;
+    use crate::gzip::decoder::GZipDecoder;
+    use crate::gzip::encoder::GZipEncoder;
+    use crate::traits::decoder::DecodeExt;
+    use crate::traits::encoder::EncodeExt;
+    use rand::distr::StandardUniform;
+    use rand::{rng, RngExt};
+
+    struct FailingRead<'a> {
+        data: &'a [u8],
+        fail_at: usize,
+    }
+
+    impl Read for FailingRead<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_at == 0 {
+                return Err(io::Error::other("broken"));
+            }
+            let len = buf.len().min(self.data.len()).min(self.fail_at).min(7);
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            self.fail_at -= len;
+            Ok(len)
+        }
+    }
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        data.iter()
+            .cloned()
+            .encode(&mut GZipEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_gzip() {
+        let data = rng()
+            .sample_iter(StandardUniform)
+            .take(0x3_0000)
+            .collect::<Vec<u8>>();
+        let encoded = encode(&data);
+
+        let mut decoder = GZipDecoder::new();
+        let mut decoded = Vec::new();
+        DecodeReader::new(&encoded[..], &mut decoder)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_read_io_error() {
+        let data = b"aabbaabbaaabbbaaabbbaabbaabb".repeat(100);
+        let encoded = encode(&data);
+
+        let mut decoder = GZipDecoder::new();
+        let mut decoded = Vec::new();
+        let err = DecodeReader::new(
+            FailingRead {
+                data: &encoded,
+                fail_at: encoded.len() / 2,
+            },
+            &mut decoder,
+        )
+        .read_to_end(&mut decoded)
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "broken");
+    }
+
+    #[test]
+    fn test_read_data_error() {
+        let mut encoded = encode(b"aabbaabbaaabbbaaabbbaabbaabb");
+        let len = encoded.len();
+        encoded[len - 8] ^= 0xFF;
+
+        let mut decoder = GZipDecoder::new();
+        let mut decoded = Vec::new();
+        let err = DecodeReader::new(&encoded[..], &mut decoder)
+            .read_to_end(&mut decoded)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[derive(Debug)]
+    struct ShortWrite {
+        data: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for ShortWrite {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.limit - self.data.len());
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_gzip() {
+        let data = rng()
+            .sample_iter(StandardUniform)
+            .take(0x3_0000)
+            .map(|x: u8| x & 0x0F)
+            .collect::<Vec<u8>>();
+
+        let mut encoder = GZipEncoder::new();
+        let mut writer = EncodeWriter::new(Vec::new(), &mut encoder);
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let encoded = writer.finish().unwrap();
+        assert_eq!(encoded, encode(&data));
+
+        let decoded = encoded
+            .iter()
+            .cloned()
+            .decode(&mut GZipDecoder::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[test]
+    fn test_write_short() {
+        let data = b"aabbaabbaaabbbaaabbbaabbaabb".repeat(100);
+
+        let mut encoder = GZipEncoder::new();
+        let mut writer = EncodeWriter::new(
+            ShortWrite {
+                data: Vec::new(),
+                limit: 16,
+            },
+            &mut encoder,
+        );
+        writer.write_all(&data).unwrap();
+        let err = writer.finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[cfg(any(feature = "zlib", feature = "bzip2"))]
+    fn check_chunked<E, D>(encoder: &mut E, decoder: &mut D)
+    where
+        E: Encoder<In = u8, Out = u8>,
+        D: Decoder<Input = u8, Output = u8>,
+        CompressionError: From<E::Error> + From<D::Error>,
+        E::Error: core::fmt::Debug,
+        D::Error: core::fmt::Debug,
+    {
+        let data = b"aabbaabbaaabbbaaabbbaabbaabb".repeat(1000);
+        let mut writer = EncodeWriter::new(Vec::new(), encoder);
+        for chunk in data.chunks(777) {
+            writer.write_all(chunk).unwrap();
+        }
+        let encoded = writer.finish().unwrap();
+        let decoded = encoded
+            .iter()
+            .cloned()
+            .decode(decoder)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(data, decoded);
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_write_zlib() {
+        use crate::zlib::decoder::ZlibDecoder;
+        use crate::zlib::encoder::ZlibEncoder;
+        check_chunked(&mut ZlibEncoder::new(), &mut ZlibDecoder::new());
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_write_bzip2() {
+        use crate::bzip2::decoder::BZip2Decoder;
+        use crate::bzip2::encoder::BZip2Encoder;
+        check_chunked(&mut BZip2Encoder::new(9), &mut BZip2Decoder::new());
+    }
+}
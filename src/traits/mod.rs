@@ -7,6 +7,7 @@
 
 pub(crate) mod decoder;
 pub(crate) mod encoder;
+pub(crate) mod io;
 
 // pub use decoder::{DecodeExt, DecodeIterator, Decoder};
 // pub use encoder::{EncodeAction, EncodeExt, EncodeIterator, Encoder};
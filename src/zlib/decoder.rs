@@ -48,11 +48,15 @@ impl BitDecodeService for ZlibDecoderBase {
     type Error = CompressionError;
     type Output = u8;
 
-    fn next<I: Iterator<Item = u8>>(
+    fn next<R, I>(
         &mut self,
-        reader: &mut BitReader<Self::Direction>,
+        reader: &mut R,
         iter: &mut I,
-    ) -> Result<Option<u8>, Self::Error> {
+    ) -> Result<Option<u8>, Self::Error>
+    where
+        R: BitRead<Direction = Self::Direction>,
+        I: Iterator<Item = u8>,
+    {
         loop {
             if !self.header_checked {
                 let s = reader
@@ -110,20 +114,11 @@ impl BitDecodeService for ZlibDecoderBase {
                     }
                     Ok(None) => {
                         let _ = reader.skip_to_next_byte();
-                        let c = (0..4)
-                            .map(|_| reader.read_bits::<u32, _>(8, iter))
-                            .fold(
-                                Ok(0_u32),
-                                |s: Result<_, CompressionError>, x| {
-                                    Ok(x.map_err(|_| {
-                                        CompressionError::UnexpectedEof
-                                    })?
-                                    .data()
-                                        | (s.map_err(|_| {
-                                            CompressionError::UnexpectedEof
-                                        })? << 8))
-                                },
-                            )?;
+                        // ADLER32 はビッグエンディアン
+                        let c = match reader.read_bits::<u32, _>(32, iter) {
+                            Ok(ref b) if b.len() == 32 => b.data().swap_bytes(),
+                            _ => return Err(CompressionError::UnexpectedEof),
+                        };
                         if u64::from(c) != self.adler32.finish() {
                             return Err(CompressionError::DataError);
                         } else {
@@ -157,6 +152,26 @@ impl ZlibDecoder {
             ),
         }
     }
+
+    /// Returns the number of input bytes consumed so far.
+    ///
+    /// Once the end of the stream has been returned, this is the length of
+    /// the stream; the input from this offset on belongs to the caller.
+    pub fn bytes_consumed(&self) -> usize {
+        self.inner.reader().bytes_consumed()
+    }
+
+    /// Decodes a stream held in memory, reading the slice directly instead
+    /// of through a byte iterator. Returns the decoded data and the number
+    /// of input bytes the stream occupies.
+    ///
+    /// The decoder must not have been given any input through `next`.
+    pub fn decode_slice(
+        &mut self,
+        input: &[u8],
+    ) -> Result<(Vec<u8>, usize), CompressionError> {
+        self.inner.decode_slice(input)
+    }
 }
 
 impl Default for ZlibDecoder {
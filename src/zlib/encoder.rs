@@ -9,24 +9,26 @@ use crate::action::Action;
 use crate::adler32::Adler32;
 use crate::core::borrow::BorrowMut;
 use crate::core::hash::Hasher;
+use crate::core::iter;
 use crate::core::marker::PhantomData;
 use crate::core::mem;
-use crate::deflate::encoder::Inflater;
+use crate::deflate::encoder::{BlockMode, Inflater};
 use crate::error::CompressionError;
-use crate::traits::encoder::Encoder;
+use crate::lzss::encoder::ParseMode;
+use crate::traits::encoder::{EncodeExt, Encoder};
 #[cfg(not(feature = "std"))]
 #[allow(unused_imports)]
 use alloc::vec;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
-struct ScanIterator<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()> {
+struct ScanIterator<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item)> {
     phantom: PhantomData<I>,
     inner: BI,
     closure: F,
 }
 
-impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()> Iterator
+impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item)> Iterator
     for ScanIterator<I, BI, F>
 {
     type Item = I::Item;
@@ -39,9 +41,7 @@ impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()> Iterator
     }
 }
 
-impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item) -> ()>
-    ScanIterator<I, BI, F>
-{
+impl<I: Iterator, BI: BorrowMut<I>, F: FnMut(&I::Item)> ScanIterator<I, BI, F> {
     pub(crate) fn new(inner: BI, closure: F) -> Self {
         Self {
             inner,
@@ -109,6 +109,94 @@ impl ZlibEncoder {
             hashlen: 3,
         }
     }
+
+    /// Creates an encoder whose references reach back at most
+    /// `1 << window_bits` bytes, as `Inflater::with_window_bits` does, and
+    /// whose header has the CINFO of that window, `window_bits - 8`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_bits` is not between 9 and 15.
+    pub fn with_window_bits(window_bits: u32) -> Self {
+        let mut ret = Self {
+            inflater: Inflater::with_window_bits(window_bits),
+            ..Self::new()
+        };
+        ret.header[0] = ((window_bits - 8) << 4) as u8 | 8;
+        ret.set_check();
+        ret
+    }
+
+    /// Creates an encoder with `set_level(level)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is more than 9.
+    pub fn with_level(level: u32) -> Self {
+        let mut ret = Self::new();
+        ret.set_level(level);
+        ret
+    }
+
+    /// Sets how the data is compressed by a level from 0 to 9, as
+    /// `Inflater::set_level` does, and the FLEVEL of the header as zlib
+    /// does: 0 for levels 0 and 1, 1 for 2 to 5, 2 for 6 and 3 for 7 to 9.
+    /// `new` compresses as level 6 but writes FLEVEL 3. Call it before
+    /// encoding, as the header is written first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is more than 9.
+    pub fn set_level(&mut self, level: u32) {
+        self.inflater.set_level(level);
+        let flevel = match level {
+            0 | 1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        };
+        // FDICT を残す
+        self.header[1] = (flevel << 6) | (self.header[1] & 0x20);
+        self.set_check();
+    }
+
+    /// Sets the FCHECK of the header, so its first 2 bytes are a multiple
+    /// of 31.
+    fn set_check(&mut self) {
+        let flg = self.header[1] & 0xE0;
+        let check =
+            31 - ((u16::from(self.header[0]) << 8) | u16::from(flg)) % 31;
+        self.header[1] = flg | (check % 31) as u8;
+    }
+
+    /// Sets how the input is split into codes, as
+    /// `Inflater::set_parse_mode` does.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.inflater.set_parse_mode(mode);
+    }
+
+    /// Sets the blocks written, from the next block on, as
+    /// `Inflater::set_block_mode` does.
+    pub fn set_block_mode(&mut self, mode: BlockMode) {
+        self.inflater.set_block_mode(mode);
+    }
+
+    /// Cuts the output where rsync can find it again, as
+    /// `Inflater::set_rsyncable` does.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        self.inflater.set_rsyncable(rsyncable);
+    }
+
+    /// Ends the output as `Action::Flush` does and drops the window, as
+    /// `Inflater::full_flush` does. Returns the bytes up to that point that
+    /// are not output yet.
+    pub fn full_flush(&mut self) -> Result<Vec<u8>, CompressionError> {
+        let out = iter::empty()
+            .encode(self, Action::Flush)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.inflater.forget_window();
+        Ok(out)
+    }
 }
 
 impl Encoder for ZlibEncoder {
@@ -133,7 +221,7 @@ impl Encoder for ZlibEncoder {
                 Some(Ok((hash >> (self.hashlen << 3)) as u8))
             }
         } else {
-            let mut adler32 = mem::replace(&mut self.adler32, None);
+            let mut adler32 = self.adler32.take();
             let ret = self.inflater.next(
                 &mut ScanIterator::<I, _, _>::new(iter, |x: &u8| {
                     adler32.as_mut().unwrap().write_u8(*x)
@@ -141,7 +229,7 @@ impl Encoder for ZlibEncoder {
                 action,
             );
             let _ = mem::replace(&mut self.adler32, adler32);
-            if ret.is_none() {
+            if ret.is_none() && action == Action::Finish {
                 let hash = self.adler32.as_mut().unwrap().finish() as u32;
                 let ret = (hash >> 24) as u8;
                 self.hash = Some(hash);
@@ -190,4 +278,134 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_levels() {
+        use crate::zlib::decoder::ZlibDecoder;
+
+        let data = include_bytes!("../../data/sample3.ref");
+        for level in 0..=9 {
+            let out = data
+                .iter()
+                .cloned()
+                .encode(&mut ZlibEncoder::with_level(level), Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let flevel = [0, 0, 1, 1, 1, 1, 2, 3, 3, 3][level as usize];
+            assert_eq!((out[0], out[1] >> 5), (0x78, flevel << 1));
+            assert_eq!(u16::from_be_bytes([out[0], out[1]]) % 31, 0);
+            assert_eq!(
+                ZlibDecoder::new().decode_slice(&out),
+                Ok((data.to_vec(), out.len()))
+            );
+        }
+        // 辞書を使う印は残す
+        let mut encoder = ZlibEncoder::with_dict(b"a");
+        encoder.set_level(1);
+        assert_eq!(encoder.header[..2], [0x78, 0x20]);
+    }
+
+    #[test]
+    fn test_window_bits() {
+        use crate::zlib::decoder::ZlibDecoder;
+
+        // Python の zlib.decompress(data, 9) で復号できる
+        let ret = b"a"
+            .iter()
+            .cloned()
+            .encode(&mut ZlibEncoder::with_window_bits(9), Action::Finish)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(
+            ret,
+            Ok(vec![0x18, 0xD3, 0x4B, 0x04, 0x00, 0x00, 0x62, 0x00, 0x62])
+        );
+        let data = include_bytes!("../../data/sample3.ref");
+        for bits in 9..=15 {
+            let mut encoder = ZlibEncoder::with_window_bits(bits);
+            encoder.set_level(2);
+            let out = data
+                .iter()
+                .cloned()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(u32::from(out[0]), ((bits - 8) << 4) | 8);
+            assert_eq!(out[1] >> 6, 1);
+            assert_eq!(u16::from_be_bytes([out[0], out[1]]) % 31, 0);
+            assert_eq!(
+                ZlibDecoder::new().decode_slice(&out),
+                Ok((data.to_vec(), out.len()))
+            );
+        }
+        assert_eq!(ZlibEncoder::with_window_bits(15).header, vec![0x78, 0xDA]);
+    }
+
+    #[test]
+    fn test_sync_flush() {
+        use crate::core::iter;
+        use crate::traits::decoder::DecodeExt;
+        use crate::zlib::decoder::ZlibDecoder;
+
+        let mut encoder = ZlibEncoder::new();
+        let mut stream = Vec::new();
+        for m in &[&b"Hello, world!"[..], b"Hello again, world!"] {
+            stream.extend(
+                m.iter()
+                    .cloned()
+                    .encode(&mut encoder, Action::Flush)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+            );
+            assert!(stream.ends_with(&[0, 0, 0xFF, 0xFF]));
+        }
+        let decoded = stream
+            .iter()
+            .cloned()
+            .decode(&mut ZlibDecoder::new())
+            .take_while(Result::is_ok)
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded, Ok(b"Hello, world!Hello again, world!".to_vec()));
+        stream.extend(
+            iter::empty()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+        );
+        assert_eq!(
+            ZlibDecoder::new().decode_slice(&stream).map(|(d, _)| d),
+            Ok(b"Hello, world!Hello again, world!".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_full_flush() {
+        use crate::deflate::decoder::Deflater;
+        use crate::zlib::decoder::ZlibDecoder;
+
+        let data = &include_bytes!("../../data/sample3.ref")[..];
+        let mut encoder = ZlibEncoder::new();
+        let mut stream = data
+            .iter()
+            .cloned()
+            .encode(&mut encoder, Action::Run)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        stream.extend(encoder.full_flush().unwrap());
+        let cut = stream.len();
+        stream.extend(
+            data.iter()
+                .cloned()
+                .encode(&mut encoder, Action::Finish)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+        );
+        // 区切りの後ろは、それだけで deflate として戻る
+        let (decoded, _) =
+            Deflater::new().decode_slice(&stream[cut..]).unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(
+            ZlibDecoder::new().decode_slice(&stream).map(|(d, _)| d),
+            Ok([data, data].concat())
+        );
+    }
 }
@@ -12,27 +12,37 @@ pub(crate) mod encoder;
 #[cfg(test)]
 mod tests {
     use crate::action::Action;
+    use crate::error::ErrorKind;
     use crate::traits::decoder::DecodeExt;
     use crate::traits::encoder::EncodeExt;
     use crate::zlib::decoder::ZlibDecoder;
     use crate::zlib::encoder::ZlibEncoder;
     #[cfg(not(feature = "std"))]
+    #[allow(unused_imports)]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
     use alloc::vec::Vec;
-    use rand::distributions::Standard;
-    use rand::{thread_rng, Rng};
+    use rand::distr::StandardUniform;
+    use rand::{rng, RngExt};
 
     fn check(testarray: &[u8]) {
         let encoded = testarray
             .to_vec()
             .encode(&mut ZlibEncoder::new(), Action::Finish)
-            .collect::<Result<Vec<_>, _>>();
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
         let decoded = encoded
-            .unwrap()
+            .iter()
+            .cloned()
             .decode(&mut ZlibDecoder::new())
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
 
         assert_eq!(testarray.to_vec(), decoded);
+        assert_eq!(
+            ZlibDecoder::new().decode_slice(&encoded),
+            Ok((decoded, encoded.len()))
+        );
     }
 
     #[test]
@@ -62,34 +72,49 @@ mod tests {
 
     #[test]
     fn test_multiblocks() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks2() {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(323_742).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(323_742)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
     fn test_multiblocks3() {
-        let rng = thread_rng();
+        let rng = rng();
 
         check(
             &(rng
-                .sample_iter(&Standard)
+                .sample_iter(StandardUniform)
                 .take(0xF_FFFF)
                 .collect::<Vec<_>>()),
         );
     }
 
     fn test_rand_with_len(len: usize) {
-        let rng = thread_rng();
+        let rng = rng();
 
-        check(&(rng.sample_iter(&Standard).take(len).collect::<Vec<_>>()));
+        check(
+            &(rng
+                .sample_iter(StandardUniform)
+                .take(len)
+                .collect::<Vec<_>>()),
+        );
     }
 
     #[test]
@@ -106,4 +131,70 @@ mod tests {
     fn test_multiblocks5() {
         test_rand_with_len(0x10_0001);
     }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let mut input = b"aabbaabbaaabbbaaabbbaabbaabb"
+            .to_vec()
+            .encode(&mut ZlibEncoder::new(), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let len = input.len();
+        input.extend_from_slice(b"garbage after the stream");
+
+        let mut decoder = ZlibDecoder::new();
+        let decoded = input
+            .iter()
+            .cloned()
+            .decode(&mut decoder)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(decoded, b"aabbaabbaaabbbaaabbbaabbaabb".to_vec());
+        assert_eq!(decoder.bytes_consumed(), len);
+        assert_eq!(&input[len..], b"garbage after the stream");
+        assert_eq!(ZlibDecoder::new().decode_slice(&input), Ok((decoded, len)));
+    }
+
+    #[test]
+    fn test_distance_too_far_back() {
+        // 最初の符号が出力より前を指す。zlib は Z_DATA_ERROR とする
+        let input = [0x78, 0x9C, 0x03, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+        assert_eq!(
+            ZlibDecoder::new()
+                .decode_slice(&input)
+                .map_err(|e| e.kind()),
+            Err(ErrorKind::DataError)
+        );
+    }
+
+    #[test]
+    fn test_concatenated() {
+        let mut input = Vec::new();
+        for s in [&b"first"[..], b"second", b""].iter() {
+            input.extend(
+                s.to_vec()
+                    .encode(&mut ZlibEncoder::new(), Action::Finish)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+            );
+        }
+
+        let mut rest = &input[..];
+        let mut decoded = Vec::new();
+        while !rest.is_empty() {
+            let mut decoder = ZlibDecoder::new();
+            decoded.push(
+                rest.iter()
+                    .cloned()
+                    .decode(&mut decoder)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap(),
+            );
+            rest = &rest[decoder.bytes_consumed()..];
+        }
+        assert_eq!(
+            decoded,
+            vec![b"first".to_vec(), b"second".to_vec(), Vec::new()]
+        );
+    }
 }
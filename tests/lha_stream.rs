@@ -0,0 +1,175 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+#![cfg(all(feature = "lzhuf", feature = "std"))]
+
+use compression::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{self, Write};
+
+struct PeakAlloc;
+
+thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+    static COUNT: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = CURRENT.try_with(|c| {
+            c.set(c.get() + layout.size());
+            let _ = PEAK.try_with(|p| p.set(p.get().max(c.get())));
+        });
+        let _ = COUNT.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ =
+            CURRENT.try_with(|c| c.set(c.get().saturating_sub(layout.size())));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+/// Returns the bytes allocated by this thread now, and resets the peak
+/// to it.
+fn reset_peak() -> usize {
+    let current = CURRENT.with(Cell::get);
+    PEAK.with(|p| p.set(current));
+    current
+}
+
+fn peak() -> usize {
+    PEAK.with(Cell::get)
+}
+
+/// Returns the number of allocations made by this thread so far.
+fn count() -> usize {
+    COUNT.with(Cell::get)
+}
+
+const SIZE: usize = 32 << 20;
+
+fn pattern(i: usize) -> u8 {
+    (i % 251) as u8 ^ (i >> 16) as u8
+}
+
+/// Sink that checks the bytes against `pattern` without keeping them.
+#[derive(Default)]
+struct CheckingSink {
+    len: usize,
+    largest_write: usize,
+}
+
+impl Write for CheckingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (i, &b) in buf.iter().enumerate() {
+            assert_eq!(b, pattern(self.len + i));
+        }
+        self.len += buf.len();
+        self.largest_write = self.largest_write.max(buf.len());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_extract_large_member() {
+    let archive = {
+        let data = (0..SIZE).map(pattern).collect::<Vec<_>>();
+        let mut writer = LhaWriter::new(Vec::new());
+        writer
+            .add(b"large.bin", &data, LhaTimestamp::Unix(0))
+            .unwrap();
+        writer.finish().unwrap()
+    };
+    let entry = LhaReader::new(&archive).next().unwrap().unwrap();
+    assert_eq!(&entry.header.method, b"-lh5-");
+
+    let mut sink = CheckingSink::default();
+    let before = reset_peak();
+    let info = entry.extract_to(&mut sink).unwrap();
+    let used = peak() - before;
+    assert_eq!(
+        info,
+        ExtractInfo {
+            written: SIZE as u64,
+            crc_matched: true,
+        }
+    );
+    assert_eq!(sink.len, SIZE);
+    assert!(sink.largest_write <= 0x8000);
+    // 窓と作業領域だけで、メンバーの大きさによらない
+    assert!(used < 1 << 20, "{} bytes", used);
+}
+
+#[test]
+fn test_encode_large_input() {
+    let mut encoder = LzhufEncoder::new(&LzhufMethod::Lh7);
+    let mut decoder = LzhufDecoder::with_original_size(&LzhufMethod::Lh7, SIZE);
+    let before = reset_peak();
+    let mut len = 0;
+    // 符号化した端から復号し、どちらも出力を貯めない
+    for (i, b) in (0..SIZE)
+        .map(pattern)
+        .encode(&mut encoder, Action::Finish)
+        .map(Result::unwrap)
+        .decode(&mut decoder)
+        .enumerate()
+    {
+        assert_eq!(b.unwrap(), pattern(i));
+        len += 1;
+    }
+    let used = peak() - before;
+    assert_eq!(len, SIZE);
+    // 窓と 1 ブロックの符号だけで、入力の大きさによらない
+    assert!(used < 1 << 20, "{} bytes", used);
+}
+
+#[test]
+fn test_reuse_decoder() {
+    let archive = include_bytes!("../data/mixed.lzh");
+    let entries = LhaReader::new(archive)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let (lh5, lh7) = (&entries[1], &entries[2]);
+    let counted = |f: &mut dyn FnMut() -> Vec<u8>| {
+        let before = count();
+        let data = f();
+        (data, count() - before)
+    };
+
+    // -lh7- の 64 KiB の窓を作ったあとは、-lh5- の項目にもそれを使う
+    let mut decoder = LzhufDecoder::new(&LzhufMethod::Lh0);
+    let (fresh, fresh_count) = counted(&mut || lh7.decompress().unwrap());
+    let (reused, grown_count) =
+        counted(&mut || lh7.decompress_with(&mut decoder).unwrap());
+    assert_eq!(reused, fresh);
+    assert!(grown_count <= fresh_count);
+    let (fresh, fresh_count) = counted(&mut || lh5.decompress().unwrap());
+    let (reused, reused_count) =
+        counted(&mut || lh5.decompress_with(&mut decoder).unwrap());
+    assert_eq!(reused, fresh);
+    assert!(
+        reused_count < fresh_count,
+        "{} allocations, {} with a fresh decoder",
+        reused_count,
+        fresh_count
+    );
+
+    // 窓も表も作り直さない
+    let before = count();
+    decoder.reset(&LzhufMethod::Lh5);
+    assert_eq!(count(), before);
+}
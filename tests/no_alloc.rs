@@ -0,0 +1,93 @@
+//! rust-compression
+//!
+//! # Licensing
+//! This Source Code is subject to the terms of the Mozilla Public License
+//! version 2.0 (the "License"). You can obtain a copy of the License at
+//! <http://mozilla.org/MPL/2.0/>.
+#![cfg(feature = "deflate")]
+
+use compression::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|c| c.set(c.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocs() -> usize {
+    ALLOCS.with(Cell::get)
+}
+
+// zlib の Z_FIXED で圧縮した固定ハフマンのブロック
+const FIXED: [u8; 69] = [
+    51, 52, 80, 72, 47, 74, 77, 205, 83, 72, 202, 47, 41, 201, 73, 45, 86, 200,
+    72, 204, 75, 207, 204, 75, 87, 200, 207, 83, 40, 201, 72, 85, 40, 79, 204,
+    201, 225, 178, 36, 66, 141, 5, 17, 106, 204, 137, 80, 99, 70, 132, 26, 83,
+    34, 212, 152, 16, 161, 198, 152, 8, 53, 70, 68, 168, 49, 36, 66, 13, 0,
+];
+
+// 無圧縮のブロック
+const STORED: [u8; 11] = [1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100];
+
+fn bottles() -> Vec<u8> {
+    (0..10)
+        .map(|i| format!("{} green bottles hanging on the wall\n", 10 - i))
+        .flat_map(String::into_bytes)
+        .collect()
+}
+
+#[test]
+fn test_decode_without_allocation() {
+    let expected = bottles();
+    let mut window = [0_u8; 0x8000];
+    let mut output = [0_u8; 512];
+
+    let before = allocs();
+    let ret = Deflater::with_buffer(&mut window[..])
+        .and_then(|mut d| d.decode_slice_into(&FIXED, &mut output));
+    assert_eq!(allocs(), before);
+    assert_eq!(ret, Ok((expected.len(), FIXED.len())));
+    assert_eq!(&output[..expected.len()], &expected[..]);
+
+    let before = allocs();
+    let ret = Deflater::with_buffer(&mut window[..])
+        .and_then(|mut d| d.decode_slice_into(&STORED, &mut output));
+    assert_eq!(allocs(), before);
+    assert_eq!(ret, Ok((6, STORED.len())));
+    assert_eq!(&output[..6], b"stored");
+}
+
+#[test]
+fn test_buffer_too_small() {
+    let mut window = [0_u8; 0x8000];
+    assert_eq!(
+        Deflater::with_buffer(&mut window[..0x7FFF]).err(),
+        Some(CompressionError::BufferTooSmall)
+    );
+
+    let len = bottles().len();
+    let mut output = [0_u8; 512];
+    let mut decoder = Deflater::with_buffer(&mut window[..]).unwrap();
+    assert_eq!(
+        decoder
+            .decode_slice_into(&FIXED, &mut output[..len - 1])
+            .map_err(|e| e.kind()),
+        Err(ErrorKind::BufferTooSmall)
+    );
+}